@@ -0,0 +1,116 @@
+//! With the "spirv" feature the shaders are compiled to SPIR-V here, so that their
+//! errors stop the build instead of the game, see `opengl::spirv`.
+
+fn main() {
+    #[cfg(feature = "spirv")]
+    spirv::compile_shaders();
+}
+
+#[cfg(feature = "spirv")]
+mod spirv {
+    use std::env;
+    use std::fmt::Write;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use shaderc::{
+        CompileOptions, Compiler, EnvVersion, IncludeType, ResolvedInclude, ShaderKind, TargetEnv,
+    };
+
+    const SHADERS_DIR: &str = "src/shaders";
+
+    pub fn compile_shaders() {
+        println!("cargo:rerun-if-changed={}", SHADERS_DIR);
+        let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+
+        let compiler = Compiler::new().expect("Couldn't create the shader compiler");
+        let mut options = CompileOptions::new().unwrap();
+        options.set_target_env(TargetEnv::OpenGL, EnvVersion::OpenGL4_5 as u32);
+        // Programs look their uniforms up by name
+        options.set_generate_debug_info();
+        options.set_auto_map_locations(true);
+        options.set_auto_bind_uniforms(true);
+        options.set_include_callback(|name, _: IncludeType, _, _| {
+            let path = Path::new(SHADERS_DIR).join(name);
+            let content = fs::read_to_string(&path)
+                .map_err(|err| format!("Failed to read {}: {}", path.display(), err))?;
+            Ok(ResolvedInclude {
+                resolved_name: path.to_string_lossy().into_owned(),
+                content,
+            })
+        });
+
+        let mut paths = vec![];
+        find_files(Path::new(SHADERS_DIR), &mut paths);
+        paths.sort();
+
+        let mut errors = vec![];
+        let mut lookup = String::new();
+        for path in paths {
+            let kind = match shader_kind(&path) {
+                Some(kind) => kind,
+                None => continue, // Included by the others
+            };
+            let code = fs::read_to_string(&path).unwrap();
+            let binary =
+                match compiler.compile_into_spirv(&code, kind, &path, "main", Some(&options)) {
+                    Ok(binary) => binary,
+                    Err(err) => {
+                        errors.push(err.to_string());
+                        continue;
+                    }
+                };
+            let spv_path = out_dir.join(format!("{}.spv", path));
+            fs::create_dir_all(spv_path.parent().unwrap()).unwrap();
+            fs::write(&spv_path, binary.as_binary_u8()).unwrap();
+            writeln!(
+                lookup,
+                "        {:?} => Some(include_bytes!({:?})),",
+                path,
+                spv_path.to_string_lossy()
+            )
+            .unwrap();
+        }
+        if !errors.is_empty() {
+            panic!("Failed to compile shaders:\n{}", errors.join("\n"));
+        }
+
+        let code = format!(
+            "/// SPIR-V of the shader, relative to the crate root\n\
+             pub fn binary(path: &str) -> Option<&'static [u8]> {{\n    \
+                 match path {{\n{}        _ => None,\n    }}\n}}\n",
+            lookup
+        );
+        fs::write(out_dir.join("spirv.rs"), code).unwrap();
+    }
+
+    /// Paths of all the files under the directory, with '/' separators
+    fn find_files(dir: &Path, paths: &mut Vec<String>) {
+        for path in fs::read_dir(dir)
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+        {
+            if path.is_dir() {
+                find_files(&path, paths);
+            } else {
+                paths.push(path.to_string_lossy().replace('\\', "/"));
+            }
+        }
+    }
+
+    /// From the extension, e.g. "terrain.te.glsl" or "post.frag"
+    fn shader_kind(path: &str) -> Option<ShaderKind> {
+        let name = path.strip_suffix(".glsl").unwrap_or(path);
+        let extension = Path::new(name).extension()?.to_str()?;
+        match extension {
+            "vert" => Some(ShaderKind::Vertex),
+            "frag" => Some(ShaderKind::Fragment),
+            "tc" => Some(ShaderKind::TessControl),
+            "te" => Some(ShaderKind::TessEvaluation),
+            "geometry" => Some(ShaderKind::Geometry),
+            "comp" => Some(ShaderKind::Compute),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,429 @@
+//! The states the app can be in: the main menu, the editor and play mode. Each is a
+//! type with its own update, draw and input handling, and `Game` hands the frame and
+//! the input to the one that's active, see `Game::update_and_render` and
+//! `Game::change_state`.
+
+use glam::{Vec2, Vec3Swizzles};
+use glutin::event::VirtualKeyCode;
+
+use crate::bricks::Placement;
+use crate::debug_view::DebugView;
+use crate::editor::undo::Undo;
+use crate::editor::Tool;
+use crate::input::{InputAxis, PointerButton};
+use crate::keybindings::KeyAction;
+use crate::profiler;
+use crate::scene::Transform;
+use crate::timestep;
+use crate::{Game, Result};
+
+/// Eye height above the terrain while walking, in world units
+pub const EYE_HEIGHT: f32 = 1.7;
+/// World units per second while walking
+pub const WALK_SPEED: f32 = 4.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AppState {
+    /// Where it starts, and where Escape goes back to
+    Menu,
+    /// Shaping the terrain and building with all the tools
+    Editor,
+    /// Walking around on the terrain and placing bricks
+    Play,
+}
+
+/// Picked in the main menu, see `Gui::menu`
+pub enum MenuChoice {
+    Open(AppState),
+    Quit,
+}
+
+/// What each of the `AppState`s does. The state is lent the rest of the game for each
+/// call, see `Game::with_state`.
+pub trait State {
+    fn kind(&self) -> AppState;
+
+    /// The GUI, the input and the simulation of the frame. Returns the state to be in
+    /// for the next one.
+    fn update(&mut self, game: &mut Game, delta_time: f32, steps: u32) -> Result<AppState>;
+
+    /// Renders the frame `update` has left and presents it
+    fn draw(&mut self, game: &mut Game) -> Result<()>;
+
+    /// A bound action pressed since the last frame, see `keybindings`
+    fn key_action(&mut self, _game: &mut Game, _action: KeyAction) {}
+
+    /// A key pressed with Ctrl, returns false if it does nothing here
+    fn ctrl_shortcut(&mut self, _game: &mut Game, _key: VirtualKeyCode) -> bool {
+        false
+    }
+
+    /// A pressed key that isn't bound to an action
+    fn key_pressed(&mut self, _game: &mut Game, _key: VirtualKeyCode) {}
+}
+
+pub fn new_state(kind: AppState) -> Box<dyn State> {
+    match kind {
+        AppState::Menu => Box::new(Menu),
+        AppState::Editor => Box::new(Editor::default()),
+        AppState::Play => Box::new(Play::default()),
+    }
+}
+
+/// The scene behind the main menu
+pub struct Menu;
+
+impl State for Menu {
+    fn kind(&self) -> AppState {
+        AppState::Menu
+    }
+
+    fn update(&mut self, game: &mut Game, delta_time: f32, steps: u32) -> Result<AppState> {
+        let gui_scope = profiler::cpu_scope("GUI");
+        let choice = game
+            .gui
+            .menu(&mut game.gui_state, game.windowed_context.window());
+        let next_state = match choice {
+            Some(MenuChoice::Open(state)) => state,
+            Some(MenuChoice::Quit) => {
+                game.input.should_exit = true;
+                AppState::Menu
+            }
+            None => AppState::Menu,
+        };
+        drop(gui_scope);
+
+        let update_scope = profiler::cpu_scope("Update");
+        game.update_world(delta_time, steps, false)?;
+        drop(update_scope);
+
+        Ok(next_state)
+    }
+
+    fn draw(&mut self, game: &mut Game) -> Result<()> {
+        let render_scope = profiler::cpu_scope("Render");
+        game.begin_world()?;
+        game.end_world()?;
+        game.draw_gui();
+        drop(render_scope);
+
+        game.end_frame()?;
+        Ok(())
+    }
+}
+
+/// The camera and the particles move by the frame time, the rest of the simulation by
+/// the fixed steps
+#[derive(Default)]
+pub struct Editor {
+    /// Where the brick tool would put bricks, drawn see-through
+    brick_ghosts: Vec<Placement>,
+}
+
+impl State for Editor {
+    fn kind(&self) -> AppState {
+        AppState::Editor
+    }
+
+    fn update(&mut self, game: &mut Game, delta_time: f32, steps: u32) -> Result<AppState> {
+        let render_targets = game.render_targets();
+        let active_object = game.editor_state.selected_objects.first().copied();
+        let mut model_matrix = active_object
+            .and_then(|entity| game.scene.transform(entity))
+            .map(|transform| transform.matrix());
+        let scene_items = game.scene_items();
+        let mut emitters = game.emitter_items();
+        let selected_materials = active_object
+            .and_then(|entity| game.scene.model_mut(entity))
+            .map(|model| &mut model.materials[..]);
+        let brick_names: Vec<&str> = game.bricks.types.iter().map(|t| t.name.as_str()).collect();
+        let selection_center = game
+            .bricks
+            .selection_bounds()
+            .filter(|_| game.editor_state.tool == Tool::Bricks)
+            .map(|bounds| (bounds.min + bounds.max) * 0.5);
+        let mut brick_gizmo = selection_center.map(|center| center + game.brick_gizmo_offset);
+
+        let gui_scope = profiler::cpu_scope("GUI");
+        let actions = if game.editor_state.show_gui {
+            game.gui.layout_and_interact(
+                &mut game.gui_state,
+                game.windowed_context.window(),
+                &game.frame_uniforms.view,
+                &game.frame_uniforms.proj,
+                model_matrix.as_mut(),
+                brick_gizmo.as_mut(),
+                &scene_items,
+                selected_materials,
+                &mut game.editor_state,
+                &mut game.atmosphere,
+                &mut game.terrain,
+                &game.brush_textures,
+                &mut game.post_settings,
+                &game.render_settings,
+                &mut game.display_settings,
+                &mut game.water,
+                &mut emitters,
+                &render_targets,
+                &game.target_viewer,
+                &game.profiler,
+                &brick_names,
+                game.bricks.selection_count(),
+                game.bricks.loose_count(),
+                &game.prefabs,
+                &game.config.keybindings,
+                &mut game.mouse_settings,
+                &mut game.gamepads.settings,
+                game.image_loader.progress(),
+                game.config.project_path.as_deref(),
+                &game.bookmarks,
+            )
+        } else {
+            game.gui
+                .skip_frame(&mut game.gui_state, game.windowed_context.window());
+            vec![]
+        };
+        if let (Some(entity), Some(model_matrix)) = (active_object, model_matrix) {
+            game.scene
+                .set_transform(entity, Transform::from_matrix(&model_matrix));
+        }
+        game.apply_emitter_items(emitters);
+        if let (Some(center), Some(gizmo)) = (selection_center, brick_gizmo) {
+            game.move_bricks_with_gizmo(gizmo - center);
+        }
+        game.process_gui_actions(actions)?;
+        drop(gui_scope);
+
+        let input_scope = profiler::cpu_scope("Input");
+        let mut sculpting = false;
+        // Sculpting or painting, the stroke can be undone once it ends
+        let mut stroking = false;
+        let mut brick_ghosts = vec![];
+        if game.gui.wants_input() {
+            // Pointer over UI or currently interacting with it
+            game.terrain.hide_cursor();
+            game.grab_cursor(false);
+            game.set_cursor_visible(true); // we always want cursor with UI
+        } else {
+            // Process input
+            game.camera.speed_boost = game.input.modifiers.shift;
+            game.grab_cursor(game.input.mouse_buttons.secondary);
+
+            // Move camera. The keys only fly with the secondary button held, the
+            // sticks always do.
+            let move_axis = |axis| {
+                if game.input.mouse_buttons.secondary {
+                    game.input.axis(axis)
+                } else {
+                    game.input.analog(axis)
+                }
+            };
+            let movement = Vec2::new(
+                move_axis(InputAxis::MoveRight),
+                move_axis(InputAxis::MoveForward),
+            );
+            game.move_camera(movement, delta_time);
+            game.look_around(game.input.mouse_buttons.secondary, delta_time);
+
+            let tool = game.editor_state.tool;
+            if !tool.uses_brush() {
+                game.terrain.hide_cursor();
+                game.set_cursor_visible(true);
+            } else if game.input.pointer_moved || game.input.camera_moved {
+                let ray = game.pointer_ray();
+                let cursor_active = game.terrain.move_cursor(&ray);
+                game.set_cursor_visible(!cursor_active);
+            }
+
+            if game.input.scrolled && tool.uses_brush() {
+                // The cursor ring is drawn with the new size this frame
+                let y = game.input.scroll_delta.y;
+                let brush = &mut game.terrain.brush.settings;
+                brush.scroll(y, game.input.modifiers.ctrl);
+            }
+
+            let stroke = game
+                .input
+                .brush_stroke()
+                .filter(|_| game.terrain.cursor.is_finite());
+            match (tool, stroke) {
+                // Holding Alt samples the height to flatten to instead
+                (Tool::Sculpt, _) if game.input.modifiers.alt => {
+                    game.terrain.sample_flatten_height();
+                }
+                (Tool::Sculpt, Some(stroke)) => {
+                    if game.stroke_undo.is_none() {
+                        game.stroke_undo = Some(Undo::Heights(game.terrain.copy_heights()));
+                    }
+                    for _ in 0..steps {
+                        game.terrain.shape_terrain(timestep::STEP, stroke);
+                    }
+                    sculpting = true;
+                    stroking = true;
+                }
+                (Tool::Paint, Some(stroke)) => {
+                    if game.stroke_undo.is_none() {
+                        let copy = game.terrain.material.copy_splatmap();
+                        game.stroke_undo = Some(Undo::Splat(copy));
+                    }
+                    let layer = game.editor_state.paint_layer;
+                    for _ in 0..steps {
+                        game.terrain.paint_layer(layer, timestep::STEP, stroke);
+                    }
+                    stroking = true;
+                }
+                (Tool::Bricks, _) => brick_ghosts = game.use_brick_tool(),
+                // On release, so that dragging from an object doesn't select it
+                (Tool::Select, _) if game.input.gestures.primary.clicked => game.pick_object(),
+                _ => {}
+            }
+        }
+        if !stroking {
+            if let Some(edit) = game.stroke_undo.take() {
+                game.undo.push(edit);
+            }
+        }
+        game.record_brick_edits();
+        drop(input_scope);
+
+        let update_scope = profiler::cpu_scope("Update");
+        game.update_world(delta_time, steps, sculpting)?;
+        drop(update_scope);
+
+        self.brick_ghosts = brick_ghosts;
+        Ok(AppState::Editor)
+    }
+
+    fn draw(&mut self, game: &mut Game) -> Result<()> {
+        let render_targets = game.render_targets();
+        let render_scope = profiler::cpu_scope("Render");
+        if game.editor_state.debug_view == DebugView::Lit {
+            game.begin_world()?;
+            game.draw_markers()?;
+            for placement in &self.brick_ghosts {
+                game.bricks.draw_ghost(placement)?;
+            }
+            game.bricks.draw_selection();
+            if game.editor_state.show_debug_shapes {
+                game.queue_debug_shapes();
+            }
+            game.end_world()?;
+            if game.editor_state.show_labels {
+                game.draw_labels()?;
+            }
+        } else {
+            game.draw_debug_view(game.editor_state.debug_view)?;
+        }
+        if game.editor_state.show_render_targets {
+            if let Some(target) = render_targets.get(game.editor_state.render_target) {
+                game.target_viewer.update(target)?;
+            }
+        }
+        game.draw_gui();
+        drop(render_scope);
+
+        game.end_frame()?;
+        Ok(())
+    }
+
+    fn key_action(&mut self, game: &mut Game, action: KeyAction) {
+        game.key_action(action);
+    }
+
+    fn ctrl_shortcut(&mut self, game: &mut Game, key: VirtualKeyCode) -> bool {
+        game.ctrl_shortcut(key)
+    }
+
+    fn key_pressed(&mut self, game: &mut Game, key: VirtualKeyCode) {
+        if !game.input.modifiers.ctrl && game.editor_state.tool == Tool::Bricks {
+            game.brick_hotkey(key);
+        }
+    }
+}
+
+/// Walking on the terrain and placing the brick picked in the editor where the
+/// crosshair is
+#[derive(Default)]
+pub struct Play {
+    brick_ghosts: Vec<Placement>,
+}
+
+impl State for Play {
+    fn kind(&self) -> AppState {
+        AppState::Play
+    }
+
+    fn update(&mut self, game: &mut Game, delta_time: f32, steps: u32) -> Result<AppState> {
+        let gui_scope = profiler::cpu_scope("GUI");
+        let brick_name = game
+            .bricks
+            .types
+            .get(game.editor_state.brick_type)
+            .map_or("", |brick_type| brick_type.name.as_str());
+        game.gui.play_hud(
+            &mut game.gui_state,
+            game.windowed_context.window(),
+            brick_name,
+        );
+        drop(gui_scope);
+
+        let input_scope = profiler::cpu_scope("Input");
+        // The mouse always looks around, the crosshair stands in for the cursor
+        game.grab_cursor(game.in_focus);
+        let movement = Vec2::new(
+            game.input.axis(InputAxis::MoveRight),
+            game.input.axis(InputAxis::MoveForward),
+        );
+        game.move_camera(movement, delta_time);
+        game.look_around(true, delta_time);
+        // Past the edges of the terrain the height stays as it was
+        if let Some(ground) = game.terrain.height_at(game.camera.position.xz()) {
+            let eye = ground + EYE_HEIGHT;
+            if eye != game.camera.position.y {
+                game.camera.position.y = eye;
+                game.input.camera_moved = true;
+            }
+        }
+
+        let ray = game.camera.get_ray_forward();
+        let clicked = game.input.button_just_pressed(PointerButton::Primary);
+        let brick_ghosts = game.place_brick(&ray, clicked);
+        if game.input.button_just_pressed(PointerButton::Secondary) {
+            game.bricks.remove_at(&ray, &mut game.instances);
+        }
+        drop(input_scope);
+
+        let update_scope = profiler::cpu_scope("Update");
+        game.update_world(delta_time, steps, false)?;
+        drop(update_scope);
+
+        self.brick_ghosts = brick_ghosts;
+        Ok(AppState::Play)
+    }
+
+    fn draw(&mut self, game: &mut Game) -> Result<()> {
+        let render_scope = profiler::cpu_scope("Render");
+        game.begin_world()?;
+        for placement in &self.brick_ghosts {
+            game.bricks.draw_ghost(placement)?;
+        }
+        game.end_world()?;
+        game.draw_gui();
+        drop(render_scope);
+
+        game.end_frame()?;
+        Ok(())
+    }
+
+    /// Play mode only walks and turns the brick, the rest is for the editor
+    fn key_action(&mut self, game: &mut Game, action: KeyAction) {
+        if action == KeyAction::RotateBrick {
+            let rotation = &mut game.editor_state.brick_rotation;
+            *rotation = (*rotation + 1) % 4;
+        }
+    }
+
+    fn key_pressed(&mut self, game: &mut Game, key: VirtualKeyCode) {
+        game.select_brick_kind(key);
+    }
+}
@@ -0,0 +1,288 @@
+use std::f32::consts::TAU;
+
+use glam::{Mat4, Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::opengl::shader::{Program, Result};
+
+// How much the sun's path is tilted towards +Z (roughly the latitude)
+const SUN_PATH_TILT: f32 = 0.6;
+const SUN_DISTANCE: f32 = 600.0;
+
+const DAY_LIGHT: Vec3 = glam::const_vec3!([1.0, 0.97, 0.9]);
+const SUNSET_LIGHT: Vec3 = glam::const_vec3!([1.0, 0.55, 0.3]);
+const NIGHT_LIGHT: Vec3 = glam::const_vec3!([0.08, 0.1, 0.2]);
+
+const DAY_SKY: Vec3 = glam::const_vec3!([1.0, 1.0, 1.0]);
+const SUNSET_SKY: Vec3 = glam::const_vec3!([1.0, 0.6, 0.45]);
+const NIGHT_SKY: Vec3 = glam::const_vec3!([0.04, 0.05, 0.1]);
+
+pub struct DirectionalLight {
+    pub color: Vec3,
+    /// Points towards the light
+    pub direction: Vec3,
+}
+
+/// Exponential height fog, applied in the terrain and skybox fragment shaders
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Fog {
+    pub enabled: bool,
+    pub color: Vec3,
+    /// Fog density at `base_height`
+    pub density: f32,
+    /// How quickly the density decreases with altitude
+    pub height_falloff: f32,
+    pub base_height: f32,
+}
+
+impl Default for Fog {
+    fn default() -> Self {
+        Fog {
+            enabled: true,
+            color: Vec3::new(0.62, 0.69, 0.78),
+            density: 0.0015,
+            height_falloff: 0.012,
+            base_height: 0.0,
+        }
+    }
+}
+
+/// Preetham analytic sky used instead of the skybox cubemap when `procedural` is set
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Sky {
+    pub procedural: bool,
+    /// Haziness of the air, 2 is a very clear day, 10 is a hazy one
+    pub turbidity: f32,
+    /// Scale applied to the sky luminance before tone mapping
+    pub exposure: f32,
+}
+
+impl Default for Sky {
+    fn default() -> Self {
+        Sky {
+            procedural: true,
+            turbidity: 2.5,
+            exposure: 0.12,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CloudQuality {
+    Low,
+    Medium,
+    High,
+}
+
+impl CloudQuality {
+    pub const ALL: [CloudQuality; 3] =
+        [CloudQuality::Low, CloudQuality::Medium, CloudQuality::High];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CloudQuality::Low => "Low",
+            CloudQuality::Medium => "Medium",
+            CloudQuality::High => "High",
+        }
+    }
+
+    /// Raymarching steps along the view ray and towards the sun
+    pub fn steps(&self) -> (i32, i32) {
+        match self {
+            CloudQuality::Low => (24, 3),
+            CloudQuality::Medium => (48, 5),
+            CloudQuality::High => (96, 8),
+        }
+    }
+}
+
+/// Raymarched cloud layer, drawn over the sky
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Clouds {
+    pub enabled: bool,
+    pub quality: CloudQuality,
+    /// Fraction of the sky covered, [0, 1]
+    pub coverage: f32,
+    pub density: f32,
+    pub bottom: f32,
+    pub top: f32,
+    /// World units per second, in XZ
+    pub wind: Vec2,
+}
+
+impl Default for Clouds {
+    fn default() -> Self {
+        Clouds {
+            enabled: true,
+            quality: CloudQuality::Medium,
+            coverage: 0.45,
+            density: 0.03,
+            bottom: 450.0,
+            top: 800.0,
+            wind: Vec2::new(8.0, 3.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimeOfDay {
+    /// Hours since midnight, [0, 24)
+    pub hour: f32,
+    /// How many real seconds a full day takes
+    pub day_length: f32,
+    pub paused: bool,
+}
+
+impl Default for TimeOfDay {
+    fn default() -> Self {
+        TimeOfDay {
+            hour: 15.0,
+            day_length: 600.0,
+            paused: true,
+        }
+    }
+}
+
+impl TimeOfDay {
+    pub fn advance(&mut self, delta_time: f32) {
+        if !self.paused {
+            self.hour = (self.hour + delta_time * 24.0 / self.day_length).rem_euclid(24.0);
+        }
+    }
+
+    /// Rises at 6:00 in -X, sets at 18:00 in +X
+    pub fn sun_direction(&self) -> Vec3 {
+        let angle = (self.hour - 6.0) / 24.0 * TAU;
+        Vec3::new(
+            -angle.cos(),
+            angle.sin() * SUN_PATH_TILT.cos(),
+            angle.sin() * SUN_PATH_TILT.sin(),
+        )
+        .normalize()
+    }
+}
+
+pub struct Atmosphere {
+    pub fog: Fog,
+    pub sky: Sky,
+    pub clouds: Clouds,
+    pub time_of_day: TimeOfDay,
+    /// Center of the area covered by the shadow map, moves with the terrain
+    pub shadow_center: Vec3,
+    /// Turned off, the scene is only lit by the sky
+    pub sun_enabled: bool,
+}
+
+impl Default for Atmosphere {
+    fn default() -> Self {
+        Atmosphere {
+            fog: Fog::default(),
+            sky: Sky::default(),
+            clouds: Clouds::default(),
+            time_of_day: TimeOfDay::default(),
+            shadow_center: Vec3::ZERO,
+            sun_enabled: true,
+        }
+    }
+}
+
+impl Atmosphere {
+    pub fn update(&mut self, delta_time: f32) {
+        self.time_of_day.advance(delta_time);
+    }
+
+    /// 0 at night, 1 during the day
+    pub fn daylight(&self) -> f32 {
+        smoothstep(-0.1, 0.25, self.time_of_day.sun_direction().y)
+    }
+
+    /// 1 when the sun is at the horizon, fading to 0 away from it
+    fn sunset(&self) -> f32 {
+        1.0 - smoothstep(0.0, 0.3, self.time_of_day.sun_direction().y.abs())
+    }
+
+    /// The sun during the day and the moon at night
+    pub fn light(&self) -> DirectionalLight {
+        let sun_direction = self.time_of_day.sun_direction();
+        let direction = if sun_direction.y >= 0.0 {
+            sun_direction
+        } else {
+            -sun_direction
+        };
+        let color = if self.sun_enabled {
+            NIGHT_LIGHT
+                .lerp(DAY_LIGHT, self.daylight())
+                .lerp(SUNSET_LIGHT, self.sunset() * 0.7)
+        } else {
+            Vec3::ZERO
+        };
+
+        DirectionalLight { color, direction }
+    }
+
+    pub fn sky_tint(&self) -> Vec3 {
+        NIGHT_SKY
+            .lerp(DAY_SKY, self.daylight())
+            .lerp(SUNSET_SKY, self.sunset() * 0.5)
+    }
+
+    /// Shadows are faint under the moon and fade out when the light is grazing
+    pub fn shadow_strength(&self) -> f32 {
+        if !self.sun_enabled {
+            return 0.0;
+        }
+        let grazing = smoothstep(0.0, 0.1, self.light().direction.y);
+        (0.3 + 0.7 * self.daylight()) * grazing
+    }
+
+    /// Orthographic projection from the light covering the whole terrain
+    pub fn light_view_projection(&self) -> Mat4 {
+        let direction = self.light().direction;
+        let up = if direction.y.abs() > 0.99 {
+            Vec3::Z
+        } else {
+            Vec3::Y
+        };
+        let projection = Mat4::orthographic_rh_gl(-600.0, 600.0, -600.0, 600.0, 1.0, 1200.0);
+        let view = Mat4::look_at_rh(
+            self.shadow_center + direction * SUN_DISTANCE,
+            self.shadow_center,
+            up,
+        );
+        projection * view
+    }
+
+    /// Sets the `sun` and `shadow_strength` uniforms, and the ones in
+    /// common/environment.glsl. The procedural sky isn't in the skybox cubemap, so
+    /// its light is uniform.
+    pub fn set_lighting_uniforms(&self, shader: &Program) -> Result<()> {
+        let light = self.light();
+        shader.set_vec3("sun.direction", &light.direction)?;
+        shader.set_vec3("sun.color", &light.color)?;
+        shader.set_vec3("ambient_color", &(0.35 * self.sky_tint()))?;
+        shader.set_vec3("sky_tint", &self.sky_tint())?;
+        shader.set_i32("image_based_lighting", !self.sky.procedural as i32)?;
+        shader.set_f32("shadow_strength", self.shadow_strength())?;
+        Ok(())
+    }
+
+    /// Sets the `fog` uniform struct declared in the shader
+    pub fn set_fog_uniforms(&self, shader: &Program) -> Result<()> {
+        let fog = &self.fog;
+        let density = if fog.enabled { fog.density } else { 0.0 };
+        shader.set_vec3("fog.color", &(fog.color * self.sky_tint()))?;
+        shader.set_f32("fog.density", density)?;
+        shader.set_f32("fog.height_falloff", fog.height_falloff)?;
+        shader.set_f32("fog.base_height", fog.base_height)?;
+        Ok(())
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
@@ -0,0 +1,102 @@
+//! Maps baked from a heightmap on the GPU for the `bake` command, which makes a context
+//! for them without a window, see `headless`.
+
+use gl::types::*;
+
+use crate::heightfield::HeightField;
+use crate::opengl::objects::Texture2D;
+use crate::opengl::readback::TextureReadback;
+use crate::opengl::shader::Program;
+use crate::Result;
+
+/// Must match the local size in the bake shaders
+const GROUP_SIZE: u32 = 8;
+
+pub struct Baker {
+    /// R32F
+    heights: Texture2D,
+    size: usize,
+    /// Takes the heights in [0, 1] to texels
+    height_scale: f32,
+}
+
+impl Baker {
+    /// Uploads the heights
+    pub fn new(field: &HeightField, height_scale: f32) -> Self {
+        let heights = Texture2D::new();
+        let size = field.size as i32;
+        unsafe {
+            gl::TextureStorage2D(heights.id(), 1, gl::R32F, size, size);
+            gl::TextureSubImage2D(
+                heights.id(),
+                0,
+                0,
+                0,
+                size,
+                size,
+                gl::RED,
+                gl::FLOAT,
+                field.heights.as_ptr() as *const _,
+            );
+        }
+        Baker {
+            heights,
+            size: field.size,
+            height_scale,
+        }
+    }
+
+    /// World space normals with Y up as RGBA8, the same size as the heightmap
+    pub fn normal_map(&self) -> Result<Vec<u8>> {
+        let shader = Program::new()
+            .compute_shader(shader_file!("bake/normals.comp"))?
+            .link()?;
+        shader.set_used();
+        shader.set_f32("height_scale", self.height_scale)?;
+        Ok(self.run(&shader, gl::RGBA8, gl::RGBA, 4))
+    }
+
+    /// How much of the sky each texel sees as R8, 1 in the open. `radius` is how far
+    /// away in texels the terrain still hides the sky.
+    pub fn ambient_occlusion(&self, radius: f32) -> Result<Vec<u8>> {
+        let shader = Program::new()
+            .compute_shader(shader_file!("bake/ao.comp"))?
+            .link()?;
+        shader.set_used();
+        shader.set_f32("height_scale", self.height_scale)?;
+        shader.set_f32("radius", radius)?;
+        Ok(self.run(&shader, gl::R8, gl::RED, 1))
+    }
+
+    /// Runs the shader, which is in use, over a texture of the format and reads it back
+    fn run(
+        &self,
+        shader: &Program,
+        format: GLenum,
+        pixel_format: GLenum,
+        texel_bytes: usize,
+    ) -> Vec<u8> {
+        let output = Texture2D::new();
+        let size = self.size as i32;
+        unsafe {
+            gl::TextureStorage2D(output.id(), 1, format, size, size);
+            gl::BindImageTexture(
+                0,
+                self.heights.id(),
+                0,
+                gl::FALSE,
+                0,
+                gl::READ_ONLY,
+                gl::R32F,
+            );
+            gl::BindImageTexture(1, output.id(), 0, gl::FALSE, 0, gl::WRITE_ONLY, format);
+        }
+        let groups = (self.size as u32 + GROUP_SIZE - 1) / GROUP_SIZE;
+        shader.dispatch(groups, groups, 1);
+        unsafe {
+            gl::MemoryBarrier(gl::TEXTURE_UPDATE_BARRIER_BIT);
+        }
+        let bytes = self.size * self.size * texel_bytes;
+        TextureReadback::start(output.id(), pixel_format, gl::UNSIGNED_BYTE, bytes).wait()
+    }
+}
@@ -0,0 +1,162 @@
+//! The `--benchmark` run: the camera flies a fixed path over a heightmap from the
+//! repository with the default settings, and the timings of every frame are written to
+//! a CSV file with a summary next to it. The frames are the same on every run, so the
+//! numbers can be compared between commits.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use glam::Vec3;
+
+use crate::profiler::{PassTiming, Profiler};
+use crate::terrain::{MAX_HEIGHT, TERRAIN_SIZE};
+use crate::Result;
+
+/// Committed with the code, so that every run draws the same terrain
+pub const HEIGHTMAP: &str = "textures/heightmaps/ruapehu.png";
+pub const DEFAULT_OUTPUT: &str = "benchmark.csv";
+/// Of the window, in physical pixels
+pub const RESOLUTION: [u32; 2] = [1920, 1080];
+/// While the shaders compile and the images load, not recorded
+const WARM_UP_FRAMES: u32 = 120;
+/// One lap around the terrain
+const FRAMES: u32 = 1800;
+/// From the center, in world units
+const ORBIT_RADIUS: f32 = TERRAIN_SIZE * 0.35;
+
+#[derive(Debug, Clone, Copy)]
+struct FrameRecord {
+    /// Between the starts of the frame and the next one, in milliseconds
+    frame: f32,
+    /// Of the top-level CPU scopes
+    cpu: f32,
+    /// Of the top-level GPU passes. Their results come a few frames late, so this is
+    /// of a frame slightly before.
+    gpu: f32,
+    draw_calls: u32,
+}
+
+pub struct Benchmark {
+    output: PathBuf,
+    /// Of the frame being drawn, counting the warm-up
+    frame: u32,
+    records: Vec<FrameRecord>,
+}
+
+impl Benchmark {
+    pub fn new(output: PathBuf) -> Self {
+        Benchmark {
+            output,
+            frame: 0,
+            records: Vec::with_capacity(FRAMES as usize),
+        }
+    }
+
+    /// Where the camera is for the current frame and where it looks. It circles the
+    /// terrain rising and falling, looking a little ahead and towards the middle.
+    pub fn camera(&self) -> (Vec3, Vec3) {
+        let t = self.frame.saturating_sub(WARM_UP_FRAMES) as f32 / FRAMES as f32;
+        let angle = t * std::f32::consts::TAU;
+        let height = MAX_HEIGHT * (1.0 + 0.4 * (angle * 3.0).sin());
+        let position = Vec3::new(
+            angle.cos() * ORBIT_RADIUS,
+            height,
+            angle.sin() * ORBIT_RADIUS,
+        );
+        let ahead = angle + 0.6;
+        let target = Vec3::new(
+            ahead.cos() * ORBIT_RADIUS * 0.4,
+            MAX_HEIGHT * 0.2,
+            ahead.sin() * ORBIT_RADIUS * 0.4,
+        );
+        (position, (target - position).normalize())
+    }
+
+    /// Call once the profiler has the previous frame, before drawing the next one
+    pub fn record(&mut self, profiler: &Profiler) {
+        if self.frame > WARM_UP_FRAMES {
+            let top_level = |timings: &[PassTiming]| -> f32 {
+                timings
+                    .iter()
+                    .filter(|timing| timing.depth == 0)
+                    .map(|timing| timing.milliseconds)
+                    .sum()
+            };
+            self.records.push(FrameRecord {
+                frame: profiler.frame_times.back().copied().unwrap_or(0.0),
+                cpu: top_level(&profiler.cpu_timings),
+                gpu: top_level(&profiler.passes),
+                draw_calls: profiler.draw_calls,
+            });
+        }
+        self.frame += 1;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.records.len() >= FRAMES as usize
+    }
+
+    /// Writes the CSV and the summary, and returns the summary. `about` goes at the
+    /// top of it, e.g. the driver and the resolution.
+    pub fn save(&self, about: &str) -> Result<String> {
+        let mut csv = String::from("frame,frame_ms,cpu_ms,gpu_ms,draw_calls\n");
+        for (index, record) in self.records.iter().enumerate() {
+            writeln!(
+                csv,
+                "{},{:.3},{:.3},{:.3},{}",
+                index, record.frame, record.cpu, record.gpu, record.draw_calls
+            )?;
+        }
+        fs::write(&self.output, csv)?;
+
+        let summary = format!("{}\n{}", about, self.summary());
+        let summary_path = summary_path(&self.output);
+        fs::write(&summary_path, &summary)?;
+        Ok(format!(
+            "{}\nSaved {} and {}",
+            summary,
+            self.output.display(),
+            summary_path.display()
+        ))
+    }
+
+    fn summary(&self) -> String {
+        let mut frame_times: Vec<f32> = self.records.iter().map(|record| record.frame).collect();
+        frame_times.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let percentile = |p: f32| {
+            let index = ((frame_times.len() - 1) as f32 * p).round() as usize;
+            frame_times.get(index).copied().unwrap_or(0.0)
+        };
+        let count = self.records.len().max(1) as f32;
+        let mean =
+            |value: fn(&FrameRecord) -> f32| self.records.iter().map(value).sum::<f32>() / count;
+        let mean_frame = mean(|record| record.frame);
+        format!(
+            "Frames: {}\n\
+             Frame time: mean {:.2} ms ({:.1} fps), median {:.2} ms, 95% {:.2} ms, 99% {:.2} ms, max {:.2} ms\n\
+             CPU: mean {:.2} ms\n\
+             GPU: mean {:.2} ms\n\
+             Draw calls: mean {:.0}\n",
+            self.records.len(),
+            mean_frame,
+            1000.0 / mean_frame.max(0.001),
+            percentile(0.5),
+            percentile(0.95),
+            percentile(0.99),
+            percentile(1.0),
+            mean(|record| record.cpu),
+            mean(|record| record.gpu),
+            mean(|record| record.draw_calls as f32),
+        )
+    }
+}
+
+/// benchmark.csv goes with benchmark-summary.txt
+fn summary_path(csv: &Path) -> PathBuf {
+    let stem = csv.file_stem().map_or_else(
+        || String::from("benchmark"),
+        |stem| stem.to_string_lossy().into_owned(),
+    );
+    csv.with_file_name(format!("{}-summary.txt", stem))
+}
@@ -0,0 +1,322 @@
+//! Camera-facing quads: plain billboards for markers and sprites, and impostors,
+//! which stand in for distant meshes with pictures of them baked from around.
+
+use std::f32::consts::TAU;
+use std::mem::size_of;
+
+use gl::types::*;
+use glam::{Mat4, Vec2, Vec3, Vec4};
+use thiserror::Error;
+
+use crate::model::Model;
+use crate::opengl::bindings::{self, TextureUnit};
+use crate::opengl::shader::Program;
+use crate::opengl::{check_framebuffer, empty_vertex_array, SavedTarget};
+use crate::profiler;
+use crate::texture::calculate_mip_levels;
+use crate::utils::size_of_slice;
+use crate::Result;
+
+/// Must match the buffer binding in billboard.vert
+const BILLBOARDS_SSBO_BINDING: u32 = 0;
+
+/// Number of directions around the vertical axis an impostor is baked from
+const IMPOSTOR_VIEWS: i32 = 8;
+/// Size of one view in the impostor atlas, in pixels
+const IMPOSTOR_CELL_SIZE: i32 = 256;
+
+#[derive(Debug, Error)]
+pub enum BillboardError {
+    #[error("Can't bake an impostor of a model with no geometry")]
+    EmptyModel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BillboardMode {
+    /// Always parallel to the screen
+    Spherical = 0,
+    /// Only turns around the vertical axis, e.g. for trees
+    Cylindrical = 1,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Billboard {
+    /// Center of the quad
+    pub position: Vec3,
+    /// World units
+    pub size: Vec2,
+    /// Linear, multiplied with the sprite
+    pub color: Vec4,
+    pub mode: BillboardMode,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct BillboardBlock {
+    position: Vec3,
+    mode: i32,
+    size: Vec2,
+    _padding: Vec2,
+    color: Vec4,
+}
+
+impl From<&Billboard> for BillboardBlock {
+    fn from(billboard: &Billboard) -> Self {
+        BillboardBlock {
+            position: billboard.position,
+            mode: billboard.mode as i32,
+            size: billboard.size,
+            _padding: Vec2::ZERO,
+            color: billboard.color,
+        }
+    }
+}
+
+/// Draws batches of alpha-blended billboards on top of the scene
+pub struct BillboardRenderer {
+    shader: Program,
+    vao: GLuint,
+    buffer: GLuint,
+    /// In billboards
+    capacity: usize,
+}
+
+impl BillboardRenderer {
+    pub fn new() -> Result<Self> {
+        let shader = Program::new()
+            .vertex_shader(shader_file!("billboard/billboard.vert"))?
+            .fragment_shader(shader_file!("billboard/billboard.frag"))?
+            .link()?;
+
+        let vao = empty_vertex_array();
+
+        Ok(BillboardRenderer {
+            shader,
+            vao,
+            buffer: 0,
+            capacity: 0,
+        })
+    }
+
+    /// Without a sprite the billboards are round dots
+    pub fn draw(&mut self, billboards: &[Billboard], sprite: Option<GLuint>) -> Result<()> {
+        if billboards.is_empty() {
+            return Ok(());
+        }
+
+        let blocks: Vec<BillboardBlock> = billboards.iter().map(BillboardBlock::from).collect();
+        if blocks.len() > self.capacity {
+            self.capacity = blocks.len().next_power_of_two();
+            unsafe {
+                if self.buffer != 0 {
+                    gl::DeleteBuffers(1, &self.buffer);
+                }
+                gl::CreateBuffers(1, &mut self.buffer);
+                gl::NamedBufferStorage(
+                    self.buffer,
+                    (self.capacity * size_of::<BillboardBlock>()) as isize,
+                    std::ptr::null(),
+                    gl::DYNAMIC_STORAGE_BIT,
+                );
+            }
+        }
+
+        self.shader.set_used();
+        self.shader.set_i32("has_sprite", sprite.is_some() as i32)?;
+        unsafe {
+            gl::NamedBufferSubData(
+                self.buffer,
+                0,
+                size_of_slice(&blocks) as isize,
+                blocks.as_ptr() as *const _,
+            );
+            if let Some(sprite) = sprite {
+                bindings::bind_texture(TextureUnit::ALBEDO, sprite);
+            }
+
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::ONE, gl::ONE_MINUS_SRC_ALPHA);
+            gl::DepthMask(gl::FALSE);
+
+            gl::BindBufferBase(
+                gl::SHADER_STORAGE_BUFFER,
+                BILLBOARDS_SSBO_BINDING,
+                self.buffer,
+            );
+            gl::BindVertexArray(self.vao);
+            gl::DrawArraysInstanced(gl::TRIANGLE_STRIP, 0, 4, blocks.len() as i32);
+            profiler::count_draw_call();
+
+            gl::DepthMask(gl::TRUE);
+            gl::Disable(gl::BLEND);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for BillboardRenderer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.buffer);
+            gl::DeleteVertexArrays(1, &self.vao);
+        }
+    }
+}
+
+/// Pictures of a model taken from `IMPOSTOR_VIEWS` directions around it, drawn as an
+/// upright billboard showing the picture closest to the current view direction
+#[derive(Debug)]
+pub struct Impostor {
+    /// The views side by side, alpha is coverage
+    albedo_atlas: GLuint,
+    /// Model space normals packed into [0, 1]
+    normal_atlas: GLuint,
+    /// Center of the model bounds
+    center: Vec3,
+    /// Half the width and height of the quad
+    half_size: Vec2,
+}
+
+impl Impostor {
+    /// Renders the model with orthographic cameras looking at it horizontally,
+    /// using the program built by `Impostor::bake_shader`
+    pub fn bake(shader: &Program, model: &mut Model) -> Result<Self> {
+        let bounds = model.bounds;
+        if bounds.min.x > bounds.max.x {
+            return Err(BillboardError::EmptyModel.into());
+        }
+        let center = (bounds.min + bounds.max) * 0.5;
+        let half_extents = (bounds.max - bounds.min) * 0.5;
+        // Wide enough for any rotation around the vertical axis
+        let half_size = Vec2::new(
+            Vec2::new(half_extents.x, half_extents.z).length(),
+            half_extents.y,
+        )
+        .max(Vec2::splat(0.001));
+
+        let width = IMPOSTOR_CELL_SIZE * IMPOSTOR_VIEWS;
+        let height = IMPOSTOR_CELL_SIZE;
+        let mut textures: [GLuint; 2] = [0; 2];
+        let mut depth: GLuint = 0;
+        let mut fbo: GLuint = 0;
+        unsafe {
+            gl::CreateTextures(gl::TEXTURE_2D, 2, textures.as_mut_ptr());
+            let levels = calculate_mip_levels(width as usize, height as usize);
+            for (texture, format) in textures.iter().zip([gl::SRGB8_ALPHA8, gl::RGBA8]) {
+                gl::TextureParameteri(
+                    *texture,
+                    gl::TEXTURE_MIN_FILTER,
+                    gl::LINEAR_MIPMAP_LINEAR as GLint,
+                );
+                gl::TextureParameteri(*texture, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+                gl::TextureParameteri(*texture, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+                gl::TextureParameteri(*texture, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+                gl::TextureStorage2D(*texture, levels, format, width, height);
+            }
+            gl::CreateRenderbuffers(1, &mut depth);
+            gl::NamedRenderbufferStorage(depth, gl::DEPTH_COMPONENT24, width, height);
+
+            gl::CreateFramebuffers(1, &mut fbo);
+            gl::NamedFramebufferTexture(fbo, gl::COLOR_ATTACHMENT0, textures[0], 0);
+            gl::NamedFramebufferTexture(fbo, gl::COLOR_ATTACHMENT1, textures[1], 0);
+            gl::NamedFramebufferRenderbuffer(fbo, gl::DEPTH_ATTACHMENT, gl::RENDERBUFFER, depth);
+            let draw_buffers = [gl::COLOR_ATTACHMENT0, gl::COLOR_ATTACHMENT1];
+            gl::NamedFramebufferDrawBuffers(fbo, 2, draw_buffers.as_ptr());
+        }
+        if let Err(err) = check_framebuffer(fbo, "Impostor") {
+            unsafe {
+                gl::DeleteFramebuffers(1, &fbo);
+                gl::DeleteRenderbuffers(1, &depth);
+            }
+            bindings::delete_textures(&textures);
+            return Err(err.into());
+        }
+
+        let baked = unsafe {
+            let saved_target = SavedTarget::current();
+            gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+            gl::ClearColor(0.0, 0.0, 0.0, 0.0);
+            gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+            let baked = draw_views(shader, model, center, half_size);
+
+            saved_target.restore();
+            gl::DeleteFramebuffers(1, &fbo);
+            gl::DeleteRenderbuffers(1, &depth);
+            baked
+        };
+        if let Err(err) = baked {
+            bindings::delete_textures(&textures);
+            return Err(err);
+        }
+        unsafe {
+            gl::GenerateTextureMipmap(textures[0]);
+            gl::GenerateTextureMipmap(textures[1]);
+        }
+
+        Ok(Impostor {
+            albedo_atlas: textures[0],
+            normal_atlas: textures[1],
+            center,
+            half_size,
+        })
+    }
+
+    /// The program `bake` draws the views with
+    pub fn bake_shader() -> Result<Program> {
+        let shader = Program::new()
+            .vertex_shader(shader_file!("billboard/impostor_bake.vert"))?
+            .fragment_shader(shader_file!("billboard/impostor_bake.frag"))?
+            .link()?;
+        Ok(shader)
+    }
+
+    /// Sets the uniforms of impostor.vert and binds the atlases
+    pub fn bind(&self, shader: &Program) -> Result<()> {
+        shader.set_vec3("center", &self.center)?;
+        shader.set_vec2("half_size", &self.half_size)?;
+        shader.set_i32("views", IMPOSTOR_VIEWS)?;
+        // Mipmapped and clamped, with their own filtering
+        bindings::bind_texture(TextureUnit::ALBEDO, self.albedo_atlas);
+        bindings::bind_texture(TextureUnit::NORMAL_MAP, self.normal_atlas);
+        Ok(())
+    }
+}
+
+/// Draws the model once for each view into its cell of the bound atlas
+fn draw_views(shader: &Program, model: &mut Model, center: Vec3, half_size: Vec2) -> Result<()> {
+    shader.set_used();
+    let projection = Mat4::orthographic_rh_gl(
+        -half_size.x,
+        half_size.x,
+        -half_size.y,
+        half_size.y,
+        0.0,
+        4.0 * half_size.x,
+    );
+    for view in 0..IMPOSTOR_VIEWS {
+        // Must match the view selection in impostor.vert
+        let angle = view as f32 * TAU / IMPOSTOR_VIEWS as f32;
+        let direction = Vec3::new(angle.sin(), 0.0, angle.cos());
+        let eye = center + direction * 2.0 * half_size.x;
+        shader.set_mat4(
+            "view_proj",
+            &(projection * Mat4::look_at_rh(eye, center, Vec3::Y)),
+        )?;
+        unsafe {
+            gl::Viewport(
+                view * IMPOSTOR_CELL_SIZE,
+                0,
+                IMPOSTOR_CELL_SIZE,
+                IMPOSTOR_CELL_SIZE,
+            );
+        }
+        model.draw(shader, &Mat4::IDENTITY)?;
+    }
+    Ok(())
+}
+
+impl Drop for Impostor {
+    fn drop(&mut self) {
+        bindings::delete_textures(&[self.albedo_atlas, self.normal_atlas]);
+    }
+}
@@ -0,0 +1,158 @@
+//! The kinds of bricks there are, loaded from a JSON file. Each kind has a size in
+//! grid cells, a shape, and the cells where it connects to other bricks: studs on
+//! top and anti-studs underneath. Both default to the whole footprint.
+
+use std::fs;
+
+use glam::{IVec2, IVec3, Mat4, Vec3};
+use glutin::event::VirtualKeyCode;
+use serde::Deserialize;
+
+use super::{PLATE_HEIGHT, STUD_SIZE};
+use crate::Result;
+
+pub const CATALOG_PATH: &str = "models/bricks/catalog.json";
+
+/// The number keys select the first ten kinds, in order
+const HOTKEYS: [VirtualKeyCode; 10] = [
+    VirtualKeyCode::Key1,
+    VirtualKeyCode::Key2,
+    VirtualKeyCode::Key3,
+    VirtualKeyCode::Key4,
+    VirtualKeyCode::Key5,
+    VirtualKeyCode::Key6,
+    VirtualKeyCode::Key7,
+    VirtualKeyCode::Key8,
+    VirtualKeyCode::Key9,
+    VirtualKeyCode::Key0,
+];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum BrickShape {
+    #[default]
+    Box,
+    /// Full height at the back row, sloping down to one plate at the front (min z)
+    Slope,
+}
+
+#[derive(Debug, Deserialize)]
+struct BrickTypeDesc {
+    name: String,
+    /// Studs wide, plates high, studs deep
+    size: [i32; 3],
+    #[serde(default)]
+    shape: BrickShape,
+    /// Cells of the footprint, x and z
+    studs: Option<Vec<[i32; 2]>>,
+    anti_studs: Option<Vec<[i32; 2]>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CatalogDesc {
+    types: Vec<BrickTypeDesc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BrickType {
+    pub name: String,
+    pub size: IVec3,
+    pub shape: BrickShape,
+    /// Footprint cells with a stud on top
+    pub studs: Vec<IVec2>,
+    /// Footprint cells that take a stud from below
+    pub anti_studs: Vec<IVec2>,
+}
+
+impl BrickType {
+    /// Size in cells after turning by `rotation` quarter turns around Y
+    pub fn size(&self, rotation: u8) -> IVec3 {
+        if rotation.is_multiple_of(2) {
+            self.size
+        } else {
+            IVec3::new(self.size.z, self.size.y, self.size.x)
+        }
+    }
+
+    pub fn studs(&self, rotation: u8) -> Vec<IVec2> {
+        self.rotate_cells(&self.studs, rotation)
+    }
+
+    pub fn anti_studs(&self, rotation: u8) -> Vec<IVec2> {
+        self.rotate_cells(&self.anti_studs, rotation)
+    }
+
+    /// Turns the mesh counter-clockwise seen from above, keeping the min corner of its
+    /// cells at the origin
+    pub fn rotation_transform(&self, rotation: u8) -> Mat4 {
+        let turn = Mat4::from_rotation_y(rotation as f32 * std::f32::consts::FRAC_PI_2);
+        let extent = self.size.as_vec3() * Vec3::new(STUD_SIZE, PLATE_HEIGHT, STUD_SIZE);
+        let corner = turn.transform_vector3(extent);
+        let offset = Vec3::new(-corner.x.min(0.0), 0.0, -corner.z.min(0.0));
+        Mat4::from_translation(offset) * turn
+    }
+
+    /// Footprint cells after the same turn as `rotation_transform`
+    fn rotate_cells(&self, cells: &[IVec2], rotation: u8) -> Vec<IVec2> {
+        cells
+            .iter()
+            .map(|&cell| {
+                (0..rotation % 4).fold(cell, |cell, turn| {
+                    let width = self.size(turn).x;
+                    IVec2::new(cell.y, width - 1 - cell.x)
+                })
+            })
+            .collect()
+    }
+}
+
+pub fn load(path: &str) -> Result<Vec<BrickType>> {
+    let catalog: CatalogDesc = serde_json::from_str(&fs::read_to_string(path)?)?;
+    if catalog.types.is_empty() {
+        return Err(format!("{} has no brick types", path).into());
+    }
+    catalog
+        .types
+        .into_iter()
+        .map(|desc| {
+            let BrickTypeDesc {
+                name,
+                size,
+                shape,
+                studs,
+                anti_studs,
+            } = desc;
+            let size = IVec3::from(size);
+            if size.min_element() < 1 {
+                return Err(format!("{} has an empty size", name).into());
+            }
+            let footprint: Vec<[i32; 2]> = (0..size.x)
+                .flat_map(|x| (0..size.z).map(move |z| [x, z]))
+                .collect();
+            let cells = |cells: Option<Vec<[i32; 2]>>| -> Result<Vec<IVec2>> {
+                let cells = cells.unwrap_or_else(|| footprint.clone());
+                if let Some(cell) = cells.iter().find(|cell| !footprint.contains(cell)) {
+                    return Err(format!("{:?} is outside of {}", cell, name).into());
+                }
+                Ok(cells.into_iter().map(IVec2::from).collect())
+            };
+            Ok(BrickType {
+                studs: cells(studs)?,
+                anti_studs: cells(anti_studs)?,
+                name,
+                size,
+                shape,
+            })
+        })
+        .collect()
+}
+
+/// Index of the kind the key selects
+pub fn hotkey_index(key: VirtualKeyCode) -> Option<usize> {
+    HOTKEYS.iter().position(|&hotkey| hotkey == key)
+}
+
+pub fn hotkey_name(index: usize) -> Option<String> {
+    HOTKEYS
+        .get(index)
+        .map(|key| format!("{:?}", key).replace("Key", ""))
+}
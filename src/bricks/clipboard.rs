@@ -0,0 +1,131 @@
+//! Bricks copied from the world, kept relative to the min corner of the group so that
+//! they can be pasted anywhere, turned and mirrored.
+//!
+//! Mirroring turns each brick instead of flipping its mesh, which is exact for bricks
+//! that are symmetric along their own X axis, like all of the catalog ones.
+
+use glam::{IVec3, Vec3};
+
+use super::{Brick, GridBox};
+
+/// Ways to change the clipboard before pasting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClipboardOp {
+    Rotate,
+    MirrorX,
+    MirrorZ,
+}
+
+impl ClipboardOp {
+    pub const ALL: [ClipboardOp; 3] = [
+        ClipboardOp::Rotate,
+        ClipboardOp::MirrorX,
+        ClipboardOp::MirrorZ,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ClipboardOp::Rotate => "Rotate (R)",
+            ClipboardOp::MirrorX => "Mirror X (M)",
+            ClipboardOp::MirrorZ => "Mirror Z (N)",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ClipboardBrick {
+    pub kind: usize,
+    pub rotation: u8,
+    /// Relative to the min corner of the group
+    pub cells: GridBox,
+    pub color: Vec3,
+}
+
+#[derive(Debug, Clone)]
+pub struct Clipboard {
+    pub bricks: Vec<ClipboardBrick>,
+    /// Of the box around all bricks
+    pub size: IVec3,
+}
+
+impl Clipboard {
+    /// None if there are no bricks to copy
+    pub fn copy<'a>(bricks: impl Iterator<Item = &'a Brick>) -> Option<Self> {
+        Self::new(
+            bricks
+                .map(|brick| ClipboardBrick {
+                    kind: brick.kind,
+                    rotation: brick.rotation,
+                    cells: brick.cells,
+                    color: brick.color,
+                })
+                .collect(),
+        )
+    }
+
+    /// Takes bricks anywhere on the grid and moves them to the origin, None if there
+    /// are none
+    pub fn new(mut bricks: Vec<ClipboardBrick>) -> Option<Self> {
+        let min = bricks
+            .iter()
+            .map(|brick| brick.cells.min)
+            .reduce(IVec3::min)?;
+        let max = bricks
+            .iter()
+            .map(|brick| brick.cells.max)
+            .reduce(IVec3::max)?;
+        for brick in &mut bricks {
+            brick.cells = brick.cells.moved(-min);
+        }
+        Some(Clipboard {
+            bricks,
+            size: max - min,
+        })
+    }
+
+    pub fn apply(&mut self, op: ClipboardOp) {
+        match op {
+            ClipboardOp::Rotate => self.rotate(),
+            ClipboardOp::MirrorX => self.mirror_x(),
+            ClipboardOp::MirrorZ => self.mirror_z(),
+        }
+    }
+
+    /// A quarter turn counter-clockwise seen from above, same as for single bricks
+    fn rotate(&mut self) {
+        let width = self.size.x;
+        for brick in &mut self.bricks {
+            let size = brick.cells.max - brick.cells.min;
+            let min = IVec3::new(
+                brick.cells.min.z,
+                brick.cells.min.y,
+                width - brick.cells.min.x - size.x,
+            );
+            brick.cells = GridBox::new(min, IVec3::new(size.z, size.y, size.x));
+            brick.rotation = (brick.rotation + 1) % 4;
+        }
+        self.size = IVec3::new(self.size.z, self.size.y, self.size.x);
+    }
+
+    /// Flips the group along the X axis
+    fn mirror_x(&mut self) {
+        for brick in &mut self.bricks {
+            let size = brick.cells.max - brick.cells.min;
+            brick.cells.min.x = self.size.x - brick.cells.max.x;
+            brick.cells.max.x = brick.cells.min.x + size.x;
+            // Facing -X instead of +X and the other way round
+            brick.rotation = (4 - brick.rotation) % 4;
+        }
+    }
+
+    /// Flips the group along the Z axis
+    fn mirror_z(&mut self) {
+        for brick in &mut self.bricks {
+            let size = brick.cells.max - brick.cells.min;
+            brick.cells.min.z = self.size.z - brick.cells.max.z;
+            brick.cells.max.z = brick.cells.min.z + size.z;
+            // Facing -Z instead of +Z and the other way round
+            brick.rotation = (6 - brick.rotation) % 4;
+        }
+    }
+}
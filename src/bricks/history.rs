@@ -0,0 +1,96 @@
+//! Undo and redo for brick edits. Every edit keeps enough to be turned around, so
+//! undoing one is applying its inverse. Bricks are referred to by id because their
+//! order and instances change as they come and go.
+
+use std::collections::VecDeque;
+
+use glam::{IVec3, Vec3};
+
+use super::GridBox;
+
+/// Everything needed to put a brick back
+#[derive(Debug, Clone, Copy)]
+pub struct BrickRecord {
+    pub id: u32,
+    pub kind: usize,
+    pub rotation: u8,
+    pub cells: GridBox,
+    pub color: Vec3,
+}
+
+#[derive(Debug, Clone)]
+pub enum Edit {
+    Add(Vec<BrickRecord>),
+    Remove(Vec<BrickRecord>),
+    Move {
+        ids: Vec<u32>,
+        offset: IVec3,
+    },
+    /// Brick ids with their colors before and after
+    Recolor(Vec<(u32, Vec3, Vec3)>),
+}
+
+impl Edit {
+    pub fn inverse(&self) -> Edit {
+        match self {
+            Edit::Add(records) => Edit::Remove(records.clone()),
+            Edit::Remove(records) => Edit::Add(records.clone()),
+            Edit::Move { ids, offset } => Edit::Move {
+                ids: ids.clone(),
+                offset: -*offset,
+            },
+            Edit::Recolor(changes) => Edit::Recolor(
+                changes
+                    .iter()
+                    .map(|&(id, from, to)| (id, to, from))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+pub struct History {
+    done: VecDeque<Edit>,
+    undone: Vec<Edit>,
+    /// The oldest edits are forgotten past this many
+    depth: usize,
+}
+
+impl History {
+    pub fn new(depth: usize) -> Self {
+        History {
+            done: VecDeque::new(),
+            undone: vec![],
+            depth,
+        }
+    }
+
+    /// Forgets the undone edits, they can't be redone after a new one
+    pub fn push(&mut self, edit: Edit) {
+        self.undone.clear();
+        self.done.push_back(edit);
+        while self.done.len() > self.depth {
+            self.done.pop_front();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.done.clear();
+        self.undone.clear();
+    }
+
+    /// The edit that undoes the last one
+    pub fn undo(&mut self) -> Option<Edit> {
+        let edit = self.done.pop_back()?;
+        let inverse = edit.inverse();
+        self.undone.push(edit);
+        Some(inverse)
+    }
+
+    /// The last undone edit, to be applied again
+    pub fn redo(&mut self) -> Option<Edit> {
+        let edit = self.undone.pop()?;
+        self.done.push_back(edit.clone());
+        Some(edit)
+    }
+}
@@ -0,0 +1,84 @@
+//! Bricks bucketed by the chunks of the grid they're in, so that finding the ones
+//! around a box or under the ray goes through a few chunks instead of every brick.
+
+use std::collections::{HashMap, HashSet};
+
+use glam::IVec3;
+
+use super::GridBox;
+
+/// In cells, a chunk is about as tall as it is wide
+const CHUNK_SIZE: [i32; 3] = [16, 40, 16];
+
+#[derive(Debug, Default)]
+pub struct BrickIndex {
+    /// Ids of the bricks overlapping each chunk, a brick can be in several
+    chunks: HashMap<IVec3, Vec<u32>>,
+}
+
+impl BrickIndex {
+    pub fn insert(&mut self, id: u32, cells: &GridBox) {
+        for chunk in chunk_range(cells) {
+            self.chunks.entry(chunk).or_default().push(id);
+        }
+    }
+
+    pub fn remove(&mut self, id: u32, cells: &GridBox) {
+        for chunk in chunk_range(cells) {
+            if let Some(ids) = self.chunks.get_mut(&chunk) {
+                ids.retain(|&other| other != id);
+                if ids.is_empty() {
+                    self.chunks.remove(&chunk);
+                }
+            }
+        }
+    }
+
+    /// Ids of the bricks that may overlap the area, each once
+    pub fn near(&self, area: &GridBox) -> HashSet<u32> {
+        let (min, max) = (chunk_of(area.min), chunk_of(area.max - IVec3::ONE));
+        let span = max - min + IVec3::ONE;
+        let chunk_count = span.x as i64 * span.y as i64 * span.z as i64;
+        let mut ids = HashSet::new();
+        if chunk_count > self.chunks.len() as i64 {
+            // Huge areas like whole columns, it's quicker to go through what's there
+            for (chunk, chunk_ids) in &self.chunks {
+                if chunk.cmpge(min).all() && chunk.cmple(max).all() {
+                    ids.extend(chunk_ids);
+                }
+            }
+        } else {
+            for chunk in chunk_range(area) {
+                if let Some(chunk_ids) = self.chunks.get(&chunk) {
+                    ids.extend(chunk_ids);
+                }
+            }
+        }
+        ids
+    }
+
+    /// Cells of every chunk with bricks, with the ids of those bricks
+    pub fn chunks(&self) -> impl Iterator<Item = (GridBox, &[u32])> {
+        let size = IVec3::from(CHUNK_SIZE);
+        self.chunks
+            .iter()
+            .map(move |(chunk, ids)| (GridBox::new(*chunk * size, size), ids.as_slice()))
+    }
+}
+
+fn chunk_of(cell: IVec3) -> IVec3 {
+    let size = IVec3::from(CHUNK_SIZE);
+    IVec3::new(
+        cell.x.div_euclid(size.x),
+        cell.y.div_euclid(size.y),
+        cell.z.div_euclid(size.z),
+    )
+}
+
+/// Chunks the cells overlap
+fn chunk_range(cells: &GridBox) -> impl Iterator<Item = IVec3> {
+    let (min, max) = (chunk_of(cells.min), chunk_of(cells.max - IVec3::ONE));
+    (min.x..=max.x).flat_map(move |x| {
+        (min.y..=max.y).flat_map(move |y| (min.z..=max.z).map(move |z| IVec3::new(x, y, z)))
+    })
+}
@@ -0,0 +1,276 @@
+//! Importer for LDraw models, .ldr files and .mpd files with several models in one.
+//! Parts are looked up by number in a table of the ones that match catalog types.
+//! Submodels are looked up in the .mpd itself and then next to the model file.
+//!
+//! Only parts that stand upright and are turned by quarter turns fit on the grid, the
+//! others are skipped along with parts that aren't in the table.
+
+use std::collections::HashMap;
+use std::f32::consts::FRAC_PI_2;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use glam::{Mat4, Vec3, Vec4};
+use serde::Deserialize;
+
+use super::catalog::BrickType;
+use super::clipboard::{Clipboard, ClipboardBrick};
+use super::{palette, GridBox};
+use crate::Result;
+
+pub const PARTS_PATH: &str = "models/bricks/ldraw.json";
+
+/// LDraw units
+const LDU_PER_STUD: f32 = 20.0;
+const LDU_PER_PLATE: f32 = 8.0;
+/// Color code that takes the color of the parent model
+const MAIN_COLOR: u32 = 16;
+/// Models referring to themselves shouldn't hang the editor
+const MAX_DEPTH: usize = 32;
+
+/// Solid colors from LDConfig.ldr, the official color table
+const COLORS: [(u32, u32); 27] = [
+    (0, 0x1B2A34),
+    (1, 0x0055BF),
+    (2, 0x237841),
+    (3, 0x008F9B),
+    (4, 0xC91A09),
+    (5, 0xC870A0),
+    (6, 0x583927),
+    (7, 0x9BA19D),
+    (8, 0x6D6E5C),
+    (9, 0xB4D2E3),
+    (10, 0x4B9F4A),
+    (11, 0x55A5AF),
+    (12, 0xF2705E),
+    (13, 0xFC97AC),
+    (14, 0xF2CD37),
+    (15, 0xFFFFFF),
+    (19, 0xE4CD9E),
+    (25, 0xFE8A18),
+    (27, 0xBBE90B),
+    (28, 0x958A73),
+    (70, 0x582A12),
+    (71, 0xA0A5A9),
+    (72, 0x6C6E68),
+    (272, 0x0A3463),
+    (320, 0x720E0F),
+    (322, 0x36AEBF),
+    (484, 0xA95500),
+];
+
+#[derive(Debug, Deserialize)]
+struct PartDesc {
+    #[serde(rename = "type")]
+    brick_type: String,
+    /// Quarter turns from the LDraw part to the catalog type
+    #[serde(default)]
+    rotation: u8,
+}
+
+#[derive(Debug, Deserialize)]
+struct PartsDesc {
+    /// By part number, which is the file name without .dat
+    parts: HashMap<String, PartDesc>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Part {
+    kind: usize,
+    rotation: u8,
+}
+
+pub struct Import {
+    pub clipboard: Clipboard,
+    /// Parts that aren't in the table or don't fit on the grid
+    pub skipped: usize,
+}
+
+struct Importer<'a> {
+    types: &'a [BrickType],
+    parts: HashMap<String, Part>,
+    /// Lines of each model by lowercase name, loaded as they're referred to
+    models: HashMap<String, Vec<String>>,
+    dir: PathBuf,
+    bricks: Vec<ClipboardBrick>,
+    skipped: usize,
+}
+
+/// Loads the model into a clipboard, ready to be pasted
+pub fn import(path: impl AsRef<Path>, types: &[BrickType]) -> Result<Import> {
+    let path = path.as_ref();
+    let table: PartsDesc = serde_json::from_str(&fs::read_to_string(PARTS_PATH)?)?;
+    let mut parts = HashMap::new();
+    for (number, desc) in table.parts {
+        let kind = types
+            .iter()
+            .position(|brick_type| brick_type.name == desc.brick_type)
+            .ok_or_else(|| format!("{} refers to unknown type {}", number, desc.brick_type))?;
+        let part = Part {
+            kind,
+            rotation: desc.rotation % 4,
+        };
+        parts.insert(number.to_lowercase(), part);
+    }
+
+    let mut importer = Importer {
+        types,
+        parts,
+        models: HashMap::new(),
+        dir: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+        bricks: vec![],
+        skipped: 0,
+    };
+    let name = name_key(&path.file_name().unwrap_or_default().to_string_lossy());
+    let main = importer.load_file(path, name)?;
+    importer.walk(&main, Mat4::IDENTITY, MAIN_COLOR, 0)?;
+
+    let Importer {
+        bricks, skipped, ..
+    } = importer;
+    let mut kept: Vec<ClipboardBrick> = Vec::with_capacity(bricks.len());
+    for brick in bricks {
+        // The same part twice, or parts that only fit together off the grid
+        if kept.iter().any(|other| other.cells.overlaps(&brick.cells)) {
+            continue;
+        }
+        kept.push(brick);
+    }
+    let clipboard =
+        Clipboard::new(kept).ok_or_else(|| format!("{} has no known bricks", path.display()))?;
+    Ok(Import { clipboard, skipped })
+}
+
+impl Importer<'_> {
+    /// Splits the file into its models and returns the name of the first one, which is
+    /// `name` unless the file starts with another
+    fn load_file(&mut self, path: &Path, name: String) -> Result<String> {
+        let text = fs::read_to_string(path)?;
+        let mut main = None;
+        let mut current = name;
+        for line in text.lines() {
+            let line = line.trim();
+            if let Some(name) = line.strip_prefix("0 FILE ") {
+                current = name_key(name);
+            } else {
+                main.get_or_insert_with(|| current.clone());
+                self.models
+                    .entry(current.clone())
+                    .or_default()
+                    .push(line.to_string());
+            }
+        }
+        main.ok_or_else(|| format!("{} is empty", path.display()).into())
+    }
+
+    /// Adds the bricks of the model and its submodels
+    fn walk(&mut self, model: &str, transform: Mat4, color: u32, depth: usize) -> Result<()> {
+        if depth > MAX_DEPTH {
+            return Err(format!("{} is nested too deep", model).into());
+        }
+        let lines = self.models.get(model).cloned().unwrap_or_default();
+        for line in lines {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            // Only type 1 lines refer to other files, the rest is geometry and meta
+            if fields.first() != Some(&"1") {
+                continue;
+            }
+            if fields.len() < 15 {
+                return Err(format!("Bad line in {}: {}", model, line).into());
+            }
+            let code = parse_color(fields[1])
+                .ok_or_else(|| format!("Bad color in {}: {}", model, line))?;
+            let code = if code == MAIN_COLOR { color } else { code };
+            let numbers = fields[2..14]
+                .iter()
+                .map(|field| field.parse::<f32>())
+                .collect::<std::result::Result<Vec<f32>, _>>()
+                .map_err(|_| format!("Bad number in {}: {}", model, line))?;
+            // Position, then the rotation matrix row by row
+            let n = &numbers;
+            let local = Mat4::from_cols(
+                Vec4::new(n[3], n[6], n[9], 0.0),
+                Vec4::new(n[4], n[7], n[10], 0.0),
+                Vec4::new(n[5], n[8], n[11], 0.0),
+                Vec4::new(n[0], n[1], n[2], 1.0),
+            );
+            let transform = transform * local;
+
+            let name = name_key(&fields[14..].join(" "));
+            let number = name.strip_suffix(".dat").unwrap_or(&name);
+            if let Some(&part) = self.parts.get(number) {
+                match self.brick(part, transform, code) {
+                    Some(brick) => self.bricks.push(brick),
+                    None => self.skipped += 1,
+                }
+            } else if self.models.contains_key(&name) {
+                self.walk(&name, transform, code, depth + 1)?;
+            } else if self.dir.join(&name).is_file() {
+                let path = self.dir.join(&name);
+                let submodel = self.load_file(&path, name)?;
+                self.walk(&submodel, transform, code, depth + 1)?;
+            } else {
+                self.skipped += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// None if the part doesn't fit on the grid
+    fn brick(&self, part: Part, transform: Mat4, code: u32) -> Option<ClipboardBrick> {
+        // LDraw has -Y up, turning it half around X makes it Y up like here
+        let flip = Mat4::from_scale(Vec3::new(1.0, -1.0, -1.0));
+        let transform = flip * transform * flip;
+        if transform.y_axis.y < 0.99 {
+            return None;
+        }
+        let x_axis = transform.x_axis;
+        let turns = (-x_axis.z).atan2(x_axis.x) / FRAC_PI_2;
+        if (turns - turns.round()).abs() > 0.01 {
+            return None;
+        }
+        let rotation = (turns.round() as i32 + part.rotation as i32).rem_euclid(4) as u8;
+
+        // Parts have their origin in the middle of the top
+        let size = self.types[part.kind].size(rotation);
+        let top =
+            transform.w_axis.truncate() / Vec3::new(LDU_PER_STUD, LDU_PER_PLATE, LDU_PER_STUD);
+        let min = top - size.as_vec3() * Vec3::new(0.5, 1.0, 0.5);
+        Some(ClipboardBrick {
+            kind: part.kind,
+            rotation,
+            cells: GridBox::new(min.round().as_ivec3(), size),
+            color: color(code),
+        })
+    }
+}
+
+/// LDraw file names are case insensitive and may use backslashes
+fn name_key(name: &str) -> String {
+    name.trim().to_lowercase().replace('\\', "/")
+}
+
+/// Color codes are decimal, or hex for direct colors
+fn parse_color(field: &str) -> Option<u32> {
+    match field
+        .strip_prefix("0x")
+        .or_else(|| field.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => field.parse().ok(),
+    }
+}
+
+/// sRGB of the color code, unknown ones get the default brick color
+fn color(code: u32) -> Vec3 {
+    // Direct colors are 0x2RRGGBB
+    if code >> 24 == 2 {
+        return palette::srgb(code & 0xFF_FFFF);
+    }
+    let hex = COLORS
+        .iter()
+        .find(|&&(known, _)| known == code)
+        .map(|&(_, hex)| hex)
+        .unwrap_or(palette::COLORS[palette::DEFAULT_COLOR].1);
+    palette::srgb(hex)
+}
@@ -0,0 +1,136 @@
+//! Brick geometry made in code: the body is a profile in the YZ plane extruded along
+//! X, with studs on top wherever the brick type has them.
+
+use std::f32::consts::TAU;
+
+use glam::{Vec2, Vec3};
+
+use super::catalog::{BrickShape, BrickType};
+use super::{PLATE_HEIGHT, STUD_SIZE};
+use crate::model::Vertex;
+
+const STUD_RADIUS: f32 = 0.3 * STUD_SIZE;
+const STUD_HEIGHT: f32 = 0.2 * STUD_SIZE;
+const STUD_SEGMENTS: usize = 16;
+/// Bricks are a bit smaller than their cells so that the seams between them show
+const GAP: f32 = 0.01 * STUD_SIZE;
+
+#[derive(Debug, Default)]
+pub struct Geometry {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+}
+
+impl Geometry {
+    /// Counter-clockwise when looking at the front
+    fn quad(&mut self, corners: [Vec3; 4], normals: [Vec3; 4]) {
+        let first = self.vertices.len() as u32;
+        for (pos, normal) in corners.iter().zip(normals) {
+            self.vertices.push(Vertex {
+                pos: *pos,
+                normal,
+                uv: Vec2::ZERO,
+            });
+        }
+        self.indices
+            .extend([first, first + 1, first + 2, first, first + 2, first + 3]);
+    }
+
+    /// A prism from x0 to x1 with a convex profile, counter-clockwise as (z, y) points
+    fn extrude(&mut self, profile: &[Vec2], x0: f32, x1: f32) {
+        let point = |x: f32, p: Vec2| Vec3::new(x, p.y, p.x);
+        for (i, &a) in profile.iter().enumerate() {
+            let b = profile[(i + 1) % profile.len()];
+            let edge = b - a;
+            let normal = Vec3::new(0.0, -edge.x, edge.y).normalize();
+            self.quad(
+                [point(x0, a), point(x1, a), point(x1, b), point(x0, b)],
+                [normal; 4],
+            );
+        }
+
+        // The caps, fanned out from the first point
+        for (x, normal) in [(x0, -Vec3::X), (x1, Vec3::X)] {
+            let first = self.vertices.len() as u32;
+            for &p in profile {
+                self.vertices.push(Vertex {
+                    pos: point(x, p),
+                    normal,
+                    uv: Vec2::ZERO,
+                });
+            }
+            for i in 1..profile.len() as u32 - 1 {
+                if normal.x < 0.0 {
+                    self.indices.extend([first, first + i, first + i + 1]);
+                } else {
+                    self.indices.extend([first, first + i + 1, first + i]);
+                }
+            }
+        }
+    }
+
+    /// A cylinder standing on `base`, with no bottom
+    fn stud(&mut self, base: Vec3) {
+        let ring = |i: usize| {
+            let angle = i as f32 * TAU / STUD_SEGMENTS as f32;
+            Vec3::new(angle.cos(), 0.0, angle.sin())
+        };
+        let top = Vec3::new(0.0, STUD_HEIGHT, 0.0);
+        for i in 0..STUD_SEGMENTS {
+            let (a, b) = (ring(i), ring(i + 1));
+            let (bottom_a, bottom_b) = (base + a * STUD_RADIUS, base + b * STUD_RADIUS);
+            self.quad(
+                [bottom_a, bottom_a + top, bottom_b + top, bottom_b],
+                [a, a, b, b],
+            );
+        }
+
+        let center = self.vertices.len() as u32;
+        self.vertices.push(Vertex {
+            pos: base + top,
+            normal: Vec3::Y,
+            uv: Vec2::ZERO,
+        });
+        for i in 0..=STUD_SEGMENTS {
+            self.vertices.push(Vertex {
+                pos: base + top + ring(i) * STUD_RADIUS,
+                normal: Vec3::Y,
+                uv: Vec2::ZERO,
+            });
+        }
+        for i in 0..STUD_SEGMENTS as u32 {
+            self.indices
+                .extend([center, center + i + 2, center + i + 1]);
+        }
+    }
+}
+
+/// A brick with the min corner of its cells at the origin
+pub fn brick(brick_type: &BrickType) -> Geometry {
+    let size = brick_type.size;
+    let extent = size.as_vec3() * Vec3::new(STUD_SIZE, PLATE_HEIGHT, STUD_SIZE);
+    let (front, back) = (GAP, extent.z - GAP);
+    let profile = match brick_type.shape {
+        BrickShape::Box => vec![
+            Vec2::new(front, 0.0),
+            Vec2::new(back, 0.0),
+            Vec2::new(back, extent.y),
+            Vec2::new(front, extent.y),
+        ],
+        BrickShape::Slope => vec![
+            Vec2::new(front, 0.0),
+            Vec2::new(back, 0.0),
+            Vec2::new(back, extent.y),
+            Vec2::new((back - STUD_SIZE).max(front), extent.y),
+            Vec2::new(front, PLATE_HEIGHT),
+        ],
+    };
+    let mut geometry = Geometry::default();
+    geometry.extrude(&profile, GAP, extent.x - GAP);
+    for stud in &brick_type.studs {
+        // x and z of the cell
+        let center = (stud.as_vec2() + 0.5) * STUD_SIZE;
+        geometry.stud(Vec3::new(center.x, extent.y, center.y));
+    }
+    geometry
+}
@@ -0,0 +1,1288 @@
+//! Bricks placed on a stud grid. The grid is fixed to the terrain corner, so studs
+//! line up with the heightmap however the local origin moves. Horizontal grid
+//! coordinates are in studs and vertical ones in plates, a brick is three plates high.
+//!
+//! A brick can only go where it doesn't overlap another one, and it has to either
+//! stand on the terrain or have a stud of one brick in an anti-stud of the other,
+//! with a brick above or below it. Bricks can't go into the terrain.
+
+pub mod catalog;
+pub mod clipboard;
+pub mod history;
+pub mod index;
+pub mod ldraw;
+pub mod mesh;
+pub mod palette;
+pub mod physics;
+pub mod prefab;
+pub mod save;
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use glam::{IVec2, IVec3, Mat4, Vec2, Vec3, Vec3Swizzles, Vec4};
+
+use crate::debug_draw;
+use crate::instancing::{InstanceId, InstancedRenderer, MeshId};
+use crate::material::Material;
+use crate::model::Model;
+use crate::opengl::shader::Program;
+use crate::ray::{Ray, AABB};
+use crate::terrain::{HeightSnapshot, Terrain};
+use crate::Result;
+use catalog::BrickType;
+use clipboard::Clipboard;
+use history::{BrickRecord, Edit, History};
+use index::BrickIndex;
+use physics::BrickPhysics;
+
+/// Distance between studs
+pub const STUD_SIZE: f32 = 1.0;
+pub const PLATE_HEIGHT: f32 = 0.4;
+/// Studs shown around the placement preview
+const GRID_PREVIEW_RADIUS: i32 = 6;
+const SELECTION_COLOR: [f32; 4] = [1.0, 0.8, 0.1, 1.0];
+/// Valid, floating and invalid
+const PLACEMENT_COLORS: [[f32; 4]; 3] = [
+    [0.3, 1.0, 0.3, 1.0],
+    [1.0, 0.5, 0.1, 1.0],
+    [1.0, 0.2, 0.2, 1.0],
+];
+
+/// Cells from `min` up to but not including `max`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GridBox {
+    pub min: IVec3,
+    pub max: IVec3,
+}
+
+impl GridBox {
+    pub fn new(min: IVec3, size: IVec3) -> Self {
+        GridBox {
+            min,
+            max: min + size,
+        }
+    }
+
+    pub fn moved(&self, offset: IVec3) -> Self {
+        GridBox {
+            min: self.min + offset,
+            max: self.max + offset,
+        }
+    }
+
+    pub fn overlaps(&self, other: &GridBox) -> bool {
+        self.min.cmplt(other.max).all() && other.min.cmplt(self.max).all()
+    }
+}
+
+/// Converts between grid cells and local positions
+#[derive(Debug, Clone, Copy)]
+pub struct StudGrid {
+    /// Local position of the min corner of cell (0, 0, 0)
+    origin: Vec3,
+}
+
+impl StudGrid {
+    pub fn new(origin: Vec2) -> Self {
+        StudGrid {
+            origin: Vec3::new(origin.x, 0.0, origin.y),
+        }
+    }
+
+    pub fn to_local(self, cell: IVec3) -> Vec3 {
+        self.origin + self.distance(cell)
+    }
+
+    /// The cell containing the point
+    pub fn cell_at(&self, pos: Vec3) -> IVec3 {
+        ((pos - self.origin) / cell_size()).floor().as_ivec3()
+    }
+
+    /// How far apart cells `offset` away from each other are
+    pub fn distance(self, offset: IVec3) -> Vec3 {
+        offset.as_vec3() * cell_size()
+    }
+
+    /// The whole number of cells closest to the distance
+    pub fn cells_in(self, distance: Vec3) -> IVec3 {
+        (distance / cell_size()).round().as_ivec3()
+    }
+
+    pub fn bounds(&self, cells: &GridBox) -> AABB {
+        AABB {
+            min: self.to_local(cells.min),
+            max: self.to_local(cells.max),
+        }
+    }
+
+    pub fn shift_origin(&mut self, shift: Vec3) {
+        self.origin -= shift;
+    }
+}
+
+fn cell_size() -> Vec3 {
+    Vec3::new(STUD_SIZE, PLATE_HEIGHT, STUD_SIZE)
+}
+
+#[derive(Debug)]
+pub struct Brick {
+    /// Stays the same while the brick is around, see `history`
+    pub id: u32,
+    /// Index into the catalog
+    pub kind: usize,
+    /// Quarter turns around Y
+    pub rotation: u8,
+    pub cells: GridBox,
+    /// sRGB, see `palette`
+    pub color: Vec3,
+    pub selected: bool,
+    instance: InstanceId,
+}
+
+/// Where a brick would go, and whether the stacking rules allow it there
+#[derive(Debug, Clone, Copy)]
+pub struct Placement {
+    pub kind: usize,
+    pub rotation: u8,
+    pub cells: GridBox,
+    pub valid: bool,
+    /// Stands on the terrain with the base further above it than allowed, see
+    /// `Foundation`
+    pub floating: bool,
+}
+
+/// How a brick would stand on the terrain. Its base goes on the highest terrain under
+/// it, so it's never in the terrain but it may float over slopes.
+#[derive(Debug, Clone, Copy)]
+pub struct Foundation {
+    pub level: i32,
+    /// Between the base and the lowest terrain under it, in world units
+    pub gap: f32,
+}
+
+pub struct BrickWorld {
+    pub grid: StudGrid,
+    pub types: Vec<BrickType>,
+    /// One shared by all bricks of each type
+    meshes: Vec<MeshId>,
+    /// The same meshes again for the placement preview
+    ghosts: Vec<Model>,
+    ghost_shader: Program,
+    /// In no particular order, removing one moves the last one into its place
+    bricks: Vec<Brick>,
+    /// Index into `bricks` by brick id
+    slots: HashMap<u32, usize>,
+    index: BrickIndex,
+    /// Copied with Ctrl+C, see `paste`
+    pub clipboard: Option<Clipboard>,
+    history: History,
+    /// Edits committed so far, see `edits`
+    edits: u64,
+    next_id: u32,
+    /// Bricks that fell off, see `update_physics`
+    physics: BrickPhysics,
+    /// Where bricks came or went, or the terrain under them changed, since the last
+    /// check for loose ones
+    unchecked: Vec<GridBox>,
+}
+
+impl BrickWorld {
+    /// Up to `undo_depth` edits can be undone
+    pub fn new(
+        terrain: &Terrain,
+        instances: &mut InstancedRenderer,
+        undo_depth: usize,
+    ) -> Result<Self> {
+        let types = catalog::load(catalog::CATALOG_PATH)?;
+        let mut meshes = vec![];
+        let mut ghosts = vec![];
+        for brick_type in &types {
+            let geometry = mesh::brick(brick_type);
+            let mut material = Material::new(&brick_type.name);
+            // Tinted with the color of each brick
+            material.params.albedo = Vec4::ONE;
+            material.params.roughness = 0.3;
+            material.params.metallic = 0.0;
+            let model = Model::from_geometry(&geometry.vertices, &geometry.indices, material);
+            meshes.push(instances.add_mesh(model));
+            // The ghost shader doesn't use the material
+            let ghost = Material::new(&brick_type.name);
+            ghosts.push(Model::from_geometry(
+                &geometry.vertices,
+                &geometry.indices,
+                ghost,
+            ));
+        }
+        let ghost_shader = Program::new()
+            .vertex_shader(shader_file!("bricks/ghost.vert"))?
+            .fragment_shader(shader_file!("bricks/ghost.frag"))?
+            .link()?;
+
+        Ok(BrickWorld {
+            grid: StudGrid::new(terrain.aabb.min.xz()),
+            types,
+            meshes,
+            ghosts,
+            ghost_shader,
+            bricks: vec![],
+            slots: HashMap::new(),
+            index: BrickIndex::default(),
+            clipboard: None,
+            history: History::new(undo_depth),
+            edits: 0,
+            next_id: 0,
+            physics: BrickPhysics::new(),
+            unchecked: vec![],
+        })
+    }
+
+    /// Where a brick of the type under the ray would go: on top of the brick the ray
+    /// hits, next to it if it hits a side, or on the terrain under the cursor.
+    /// It's floating if it's on the terrain with more than `max_gap` plates under it.
+    pub fn placement(
+        &self,
+        ray: &Ray,
+        terrain: &Terrain,
+        kind: usize,
+        rotation: u8,
+        max_gap: f32,
+    ) -> Option<Placement> {
+        let size = self.types[kind].size(rotation);
+        let cells = self.cells_under(ray, terrain, size)?;
+        Some(Placement {
+            kind,
+            rotation,
+            cells,
+            valid: self.can_place(kind, rotation, &cells, terrain),
+            floating: self.floats(&cells, terrain, max_gap),
+        })
+    }
+
+    /// Where a box of that size would go, by the same rules as `placement`
+    fn cells_under(&self, ray: &Ray, terrain: &Terrain, size: IVec3) -> Option<GridBox> {
+        // Centered on the cell under the pointer
+        let centered = |cell: IVec3| {
+            let min = cell - IVec3::new((size.x - 1) / 2, 0, (size.z - 1) / 2);
+            GridBox::new(min, size)
+        };
+
+        let terrain_hit = terrain.height_at(terrain.cursor).map(|height| {
+            let point = Vec3::new(terrain.cursor.x, height, terrain.cursor.y);
+            (point, ray.origin().distance(point))
+        });
+        // Whichever is closer
+        let brick_hit = self
+            .pick(ray)
+            .filter(|&(_, t)| terrain_hit.is_none_or(|(_, distance)| t < distance));
+        let cells = match (brick_hit, terrain_hit) {
+            (Some((index, t)), _) => {
+                let hit = self.bricks[index].cells;
+                let point = ray.get_point_at(t);
+                let normal = self.face_normal(&hit, point);
+                let cell = self.grid.cell_at(point + normal * 0.5 * STUD_SIZE);
+                if normal.y > 0.0 {
+                    centered(IVec3::new(cell.x, hit.max.y, cell.z))
+                } else if normal.y < 0.0 {
+                    centered(IVec3::new(cell.x, hit.min.y - size.y, cell.z))
+                } else {
+                    // Sideways from the face, at the same height
+                    let cells = centered(IVec3::new(cell.x, hit.min.y, cell.z));
+                    let offset = if normal.x > 0.0 {
+                        IVec3::new(cell.x - cells.min.x, 0, 0)
+                    } else if normal.x < 0.0 {
+                        IVec3::new(cell.x + 1 - cells.max.x, 0, 0)
+                    } else if normal.z > 0.0 {
+                        IVec3::new(0, 0, cell.z - cells.min.z)
+                    } else {
+                        IVec3::new(0, 0, cell.z + 1 - cells.max.z)
+                    };
+                    cells.moved(offset)
+                }
+            }
+            (_, Some((point, _))) => {
+                let cell = self.grid.cell_at(point);
+                let mut cells = centered(cell);
+                cells.min.y = self.terrain_level(&cells, terrain)?;
+                cells.max.y = cells.min.y + size.y;
+                cells
+            }
+            (None, None) => return None,
+        };
+        Some(cells)
+    }
+
+    /// Returns false if the stacking rules don't allow the brick there
+    pub fn place(
+        &mut self,
+        placement: &Placement,
+        color: Vec3,
+        terrain: &Terrain,
+        instances: &mut InstancedRenderer,
+    ) -> bool {
+        let Placement {
+            kind,
+            rotation,
+            cells,
+            ..
+        } = *placement;
+        if !self.can_place(kind, rotation, &cells, terrain) {
+            return false;
+        }
+        let record = self.new_record(placement, color);
+        self.commit(Edit::Add(vec![record]), instances);
+        true
+    }
+
+    /// Where the clipboard would be pasted, one placement per brick. They're only valid
+    /// if all of them fit and at least one is connected or on the terrain.
+    pub fn paste_placements(&self, ray: &Ray, terrain: &Terrain, max_gap: f32) -> Vec<Placement> {
+        let clipboard = match &self.clipboard {
+            Some(clipboard) => clipboard,
+            None => return vec![],
+        };
+        let group = match self.cells_under(ray, terrain, clipboard.size) {
+            Some(group) => group,
+            None => return vec![],
+        };
+        let mut placements: Vec<Placement> = clipboard
+            .bricks
+            .iter()
+            .map(|brick| Placement {
+                kind: brick.kind,
+                rotation: brick.rotation,
+                cells: brick.cells.moved(group.min),
+                valid: false,
+                floating: false,
+            })
+            .collect();
+        let valid = placements
+            .iter()
+            .all(|placement| self.fits(&placement.cells, terrain, false))
+            && placements.iter().any(|placement| {
+                self.can_place(
+                    placement.kind,
+                    placement.rotation,
+                    &placement.cells,
+                    terrain,
+                )
+            });
+        for placement in &mut placements {
+            placement.valid = valid;
+            placement.floating = self.floats(&placement.cells, terrain, max_gap);
+        }
+        placements
+    }
+
+    /// Adds the bricks from `paste_placements` with their copied colors and selects them
+    pub fn paste(&mut self, placements: &[Placement], instances: &mut InstancedRenderer) -> bool {
+        let colors: Vec<Vec3> = match &self.clipboard {
+            Some(clipboard) => clipboard.bricks.iter().map(|brick| brick.color).collect(),
+            None => return false,
+        };
+        if placements.is_empty() || !placements.iter().all(|placement| placement.valid) {
+            return false;
+        }
+        let records: Vec<BrickRecord> = placements
+            .iter()
+            .zip(colors)
+            .map(|(placement, color)| self.new_record(placement, color))
+            .collect();
+        self.commit(Edit::Add(records.clone()), instances);
+        self.select_records(&records);
+        true
+    }
+
+    /// Puts the selected bricks into the clipboard, returns false if there are none
+    pub fn copy_selection(&mut self) -> bool {
+        let copied = Clipboard::copy(self.bricks.iter().filter(|brick| brick.selected));
+        let copied_any = copied.is_some();
+        if copied_any {
+            self.clipboard = copied;
+        }
+        copied_any
+    }
+
+    /// Saves the selected bricks as a prefab, returns false if there are none
+    pub fn save_prefab(&self, dir: impl AsRef<Path>, name: &str) -> Result<bool> {
+        match Clipboard::copy(self.bricks.iter().filter(|brick| brick.selected)) {
+            Some(clipboard) => {
+                prefab::save(dir, name, &clipboard, &self.types)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Removes the closest brick under the ray, returns false if there isn't one
+    pub fn remove_at(&mut self, ray: &Ray, instances: &mut InstancedRenderer) -> bool {
+        match self.pick(ray) {
+            Some((index, _)) => {
+                let record = self.bricks[index].record();
+                self.commit(Edit::Remove(vec![record]), instances);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes every brick that overlaps the area, returns how many there were
+    pub fn demolish(&mut self, area: &GridBox, instances: &mut InstancedRenderer) -> usize {
+        let records: Vec<BrickRecord> = self
+            .near(area)
+            .filter(|brick| brick.cells.overlaps(area))
+            .map(Brick::record)
+            .collect();
+        let count = records.len();
+        self.commit(Edit::Remove(records), instances);
+        count
+    }
+
+    /// The cell of the brick or the terrain under the ray
+    pub fn cell_under(&self, ray: &Ray, terrain: &Terrain) -> Option<IVec3> {
+        let terrain_hit = terrain
+            .height_at(terrain.cursor)
+            .map(|height| Vec3::new(terrain.cursor.x, height, terrain.cursor.y));
+        match self.pick(ray) {
+            Some((index, t))
+                if terrain_hit.is_none_or(|point| t < ray.origin().distance(point)) =>
+            {
+                let point = ray.get_point_at(t);
+                let normal = self.face_normal(&self.bricks[index].cells, point);
+                // Just inside the face that was hit
+                Some(self.grid.cell_at(point - normal * 0.1 * PLATE_HEIGHT))
+            }
+            _ => terrain_hit.map(|point| self.grid.cell_at(point)),
+        }
+    }
+
+    /// The cells between two corners seen from above, from the lower corner up to the
+    /// top of the tallest brick standing there
+    pub fn area_between(&self, a: IVec3, b: IVec3) -> GridBox {
+        let mut area = GridBox {
+            min: a.min(b),
+            max: a.max(b) + IVec3::ONE,
+        };
+        let column = GridBox {
+            min: area.min,
+            max: IVec3::new(area.max.x, i32::MAX, area.max.z),
+        };
+        for brick in self.near(&column) {
+            if brick.cells.overlaps(&column) {
+                area.max.y = area.max.y.max(brick.cells.max.y);
+            }
+        }
+        area
+    }
+
+    /// Selects the closest brick under the ray, or nothing if there isn't one.
+    /// With `toggle` the other bricks stay selected and the picked one is flipped.
+    pub fn select_at(&mut self, ray: &Ray, toggle: bool) {
+        let picked = self.pick(ray).map(|(index, _)| index);
+        if !toggle {
+            self.clear_selection();
+        }
+        if let Some(index) = picked {
+            let brick = &mut self.bricks[index];
+            brick.selected = !toggle || !brick.selected;
+        }
+    }
+
+    /// Selects the bricks overlapping the area, adding to the selection if `add`
+    pub fn select_area(&mut self, area: &GridBox, add: bool) {
+        if !add {
+            self.clear_selection();
+        }
+        for id in self.index.near(area) {
+            let brick = &mut self.bricks[self.slots[&id]];
+            brick.selected |= brick.cells.overlaps(area);
+        }
+    }
+
+    pub fn clear_selection(&mut self) {
+        for brick in &mut self.bricks {
+            brick.selected = false;
+        }
+    }
+
+    pub fn selection_count(&self) -> usize {
+        self.bricks.iter().filter(|brick| brick.selected).count()
+    }
+
+    /// Selects every brick of the type, adding to the selection if `add`
+    /// Around all the selected bricks
+    pub fn selection_bounds(&self) -> Option<AABB> {
+        let mut selected = self.bricks.iter().filter(|brick| brick.selected).peekable();
+        selected.peek()?;
+        Some(selected.fold(AABB::empty(), |bounds, brick| {
+            let brick_bounds = self.grid.bounds(&brick.cells);
+            AABB {
+                min: bounds.min.min(brick_bounds.min),
+                max: bounds.max.max(brick_bounds.max),
+            }
+        }))
+    }
+
+    pub fn select_kind(&mut self, kind: usize, add: bool) {
+        for brick in &mut self.bricks {
+            brick.selected = brick.kind == kind || (add && brick.selected);
+        }
+    }
+
+    /// How many bricks of each type there are, and how many of them are selected
+    pub fn kind_counts(&self) -> Vec<(usize, usize)> {
+        let mut counts = vec![(0, 0); self.types.len()];
+        for brick in &self.bricks {
+            let (count, selected) = &mut counts[brick.kind];
+            *count += 1;
+            *selected += brick.selected as usize;
+        }
+        counts
+    }
+
+    /// Shows or hides all bricks of the type, they can still be picked and built on
+    pub fn set_kind_visible(&self, kind: usize, visible: bool, instances: &mut InstancedRenderer) {
+        instances.set_visible(self.meshes[kind], visible);
+    }
+
+    pub fn is_kind_visible(&self, kind: usize, instances: &InstancedRenderer) -> bool {
+        instances.is_visible(self.meshes[kind])
+    }
+
+    /// Moves the selected bricks by whole cells, returns false if they don't fit there
+    pub fn move_selection(
+        &mut self,
+        offset: IVec3,
+        terrain: &Terrain,
+        instances: &mut InstancedRenderer,
+    ) -> bool {
+        let fits = self
+            .bricks
+            .iter()
+            .filter(|brick| brick.selected)
+            .all(|brick| {
+                let cells = brick.cells.moved(offset);
+                self.fits(&cells, terrain, true)
+            });
+        if !fits {
+            return false;
+        }
+        let ids = self
+            .selected_records()
+            .iter()
+            .map(|record| record.id)
+            .collect();
+        self.commit(Edit::Move { ids, offset }, instances);
+        true
+    }
+
+    /// Copies the selected bricks on top of themselves and selects the copies instead.
+    /// Returns false if the copies don't fit there.
+    pub fn duplicate_selection(
+        &mut self,
+        terrain: &Terrain,
+        instances: &mut InstancedRenderer,
+    ) -> bool {
+        let selected = self.selected_records();
+        if selected.is_empty() {
+            return false;
+        }
+        let (min_y, max_y) = selected
+            .iter()
+            .fold((i32::MAX, i32::MIN), |(min, max), record| {
+                (min.min(record.cells.min.y), max.max(record.cells.max.y))
+            });
+        let offset = IVec3::new(0, max_y - min_y, 0);
+        if !selected
+            .iter()
+            .all(|record| self.fits(&record.cells.moved(offset), terrain, false))
+        {
+            return false;
+        }
+
+        let copies: Vec<BrickRecord> = selected
+            .iter()
+            .map(|record| {
+                let copy = Placement {
+                    kind: record.kind,
+                    rotation: record.rotation,
+                    cells: record.cells.moved(offset),
+                    valid: true,
+                    floating: false,
+                };
+                self.new_record(&copy, record.color)
+            })
+            .collect();
+        self.commit(Edit::Add(copies.clone()), instances);
+        self.select_records(&copies);
+        true
+    }
+
+    /// Returns how many bricks were removed
+    pub fn delete_selection(&mut self, instances: &mut InstancedRenderer) -> usize {
+        let records = self.selected_records();
+        let count = records.len();
+        self.commit(Edit::Remove(records), instances);
+        count
+    }
+
+    /// Gives the selected bricks a new color, sRGB
+    pub fn paint_selection(&mut self, color: Vec3, instances: &mut InstancedRenderer) {
+        let changes = self
+            .bricks
+            .iter()
+            .filter(|brick| brick.selected && brick.color != color)
+            .map(|brick| (brick.id, brick.color, color))
+            .collect();
+        self.commit(Edit::Recolor(changes), instances);
+    }
+
+    /// Returns false if there's nothing to undo
+    pub fn undo(&mut self, instances: &mut InstancedRenderer) -> bool {
+        match self.history.undo() {
+            Some(edit) => {
+                self.apply(&edit, instances);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns false if there's nothing to redo
+    pub fn redo(&mut self, instances: &mut InstancedRenderer) -> bool {
+        match self.history.redo() {
+            Some(edit) => {
+                self.apply(&edit, instances);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Goes up with every edit that can be undone, for the editor's undo stack to
+    /// notice new ones
+    pub fn edits(&self) -> u64 {
+        self.edits
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        save::save(path, &self.bricks, &self.types)
+    }
+
+    /// Same as `save` on a worker, see `save::save_job`
+    pub fn save_job(
+        &self,
+        path: String,
+    ) -> impl FnOnce() -> std::result::Result<(), String> + Send + 'static {
+        save::save_job(path, &self.bricks, &self.types)
+    }
+
+    /// Replaces all bricks with the saved ones, which can't be undone
+    pub fn load(
+        &mut self,
+        path: impl AsRef<Path>,
+        instances: &mut InstancedRenderer,
+    ) -> Result<()> {
+        let saved = save::load(path, &self.types)?;
+        let removed = self.bricks.iter().map(Brick::record).collect();
+        self.apply(&Edit::Remove(removed), instances);
+        let added = saved
+            .iter()
+            .map(|(placement, color)| self.new_record(placement, *color))
+            .collect();
+        self.apply(&Edit::Add(added), instances);
+        self.history.clear();
+        Ok(())
+    }
+
+    pub fn draw_selection(&self) {
+        for brick in self.bricks.iter().filter(|brick| brick.selected) {
+            debug_draw::aabb(&self.grid.bounds(&brick.cells), Vec4::from(SELECTION_COLOR));
+        }
+    }
+
+    /// Outlines the brick `remove_at` would remove
+    pub fn draw_removal(&self, ray: &Ray) {
+        if let Some((index, _)) = self.pick(ray) {
+            let bounds = self.grid.bounds(&self.bricks[index].cells);
+            debug_draw::aabb(&bounds, Vec4::new(1.0, 0.2, 0.2, 1.0));
+        }
+    }
+
+    /// Outlines the area and the bricks in it, in red if they're about to be removed
+    pub fn draw_area(&self, area: &GridBox, delete: bool) {
+        let color = if delete {
+            Vec4::new(1.0, 0.2, 0.2, 1.0)
+        } else {
+            Vec4::from(SELECTION_COLOR)
+        };
+        debug_draw::aabb(&self.grid.bounds(area), color);
+        for brick in self
+            .bricks
+            .iter()
+            .filter(|brick| brick.cells.overlaps(area))
+        {
+            debug_draw::aabb(&self.grid.bounds(&brick.cells), color);
+        }
+    }
+
+    /// Free, not in the terrain, and standing on it or connected to a brick above or below
+    pub fn can_place(&self, kind: usize, rotation: u8, cells: &GridBox, terrain: &Terrain) -> bool {
+        if self.near(cells).any(|brick| brick.cells.overlaps(cells)) {
+            return false;
+        }
+        let level = self.terrain_level(cells, terrain);
+        if level.is_some_and(|level| cells.min.y < level) {
+            return false;
+        }
+        // Including the layers right above and below
+        let around = GridBox {
+            min: cells.min - IVec3::Y,
+            max: cells.max + IVec3::Y,
+        };
+        let connected = self
+            .near(&around)
+            .any(|other| self.joined(kind, rotation, cells, other));
+        connected || level == Some(cells.min.y)
+    }
+
+    /// For the bricks over the area to be checked for ground under them, see
+    /// `Terrain::take_changed_area`
+    pub fn terrain_changed(&mut self, min: Vec2, max: Vec2) {
+        let min = self.grid.cell_at(Vec3::new(min.x, 0.0, min.y));
+        let max = self.grid.cell_at(Vec3::new(max.x, 0.0, max.y));
+        // All the way up and down, whatever stands there is checked
+        self.unchecked.push(GridBox {
+            min: IVec3::new(min.x, i32::MIN / 2, min.z),
+            max: IVec3::new(max.x + 1, i32::MAX / 2, max.z + 1),
+        });
+    }
+
+    /// Lets the bricks that aren't connected to anything standing on the terrain fall,
+    /// and moves the ones falling already by the fixed steps, drawing them `alpha` of
+    /// the way past the last one. Nothing changes while `frozen`. Only the bricks around
+    /// the changes are checked, once the terrain's mirror has caught up with them.
+    pub fn update_physics(
+        &mut self,
+        terrain: &Terrain,
+        instances: &mut InstancedRenderer,
+        steps: u32,
+        alpha: f32,
+        frozen: bool,
+    ) {
+        if frozen {
+            return;
+        }
+        if !self.unchecked.is_empty() {
+            if let Some(heights) = terrain.mirrored_heights() {
+                let areas = std::mem::take(&mut self.unchecked);
+                let groups = self.loose_groups(&areas, &heights);
+                if !groups.is_empty() {
+                    self.physics.set_terrain(&heights);
+                }
+                for group in groups {
+                    let bricks = group.iter().filter_map(|&id| self.take(id)).collect();
+                    self.physics.drop_group(bricks, self.grid, &self.types);
+                }
+            }
+        }
+        if !self.physics.is_active() {
+            return;
+        }
+
+        let mut anchors = HashMap::new();
+        for bounds in self.physics.active_bounds() {
+            // A cell further out in case they move fast
+            let area = GridBox {
+                min: self.grid.cell_at(bounds.min) - IVec3::ONE,
+                max: self.grid.cell_at(bounds.max) + IVec3::splat(2),
+            };
+            anchors.extend(self.near(&area).map(|brick| (brick.id, brick.cells)));
+        }
+        self.physics.set_anchors(anchors, self.grid);
+        for _ in 0..steps {
+            self.physics.step(instances);
+        }
+        self.physics.interpolate(alpha, instances);
+    }
+
+    /// How many bricks fell off the grid and are lying around
+    pub fn loose_count(&self) -> usize {
+        self.physics.loose_count()
+    }
+
+    pub fn clear_loose(&mut self, instances: &mut InstancedRenderer) {
+        self.physics.clear(instances);
+    }
+
+    /// Draws the brick the placement would add see-through, red if it can't go there.
+    /// Depth tested against the scene in the current framebuffer.
+    pub fn draw_ghost(&mut self, placement: &Placement) -> Result<()> {
+        let color = placement_color(placement) * Vec4::new(1.0, 1.0, 1.0, 0.4);
+        let transform = self.transform(placement.kind, placement.rotation, &placement.cells);
+        self.ghost_shader.set_used();
+        self.ghost_shader.set_vec4("color", &color)?;
+        unsafe {
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::ONE, gl::ONE_MINUS_SRC_ALPHA);
+            gl::DepthMask(gl::FALSE);
+        }
+        self.ghosts[placement.kind].draw(&self.ghost_shader, &transform)?;
+        unsafe {
+            gl::DepthMask(gl::TRUE);
+            gl::Disable(gl::BLEND);
+        }
+        Ok(())
+    }
+
+    /// Outlines the placement and the studs around it
+    pub fn draw_placement(&self, placement: &Placement) {
+        debug_draw::aabb(
+            &self.grid.bounds(&placement.cells),
+            placement_color(placement),
+        );
+
+        let grid_color = Vec4::new(1.0, 1.0, 1.0, 0.4);
+        let min = placement.cells.min - IVec3::new(GRID_PREVIEW_RADIUS, 0, GRID_PREVIEW_RADIUS);
+        let max = placement.cells.max + IVec3::new(GRID_PREVIEW_RADIUS, 0, GRID_PREVIEW_RADIUS);
+        for x in min.x..=max.x {
+            let a = self.grid.to_local(IVec3::new(x, min.y, min.z));
+            let b = self.grid.to_local(IVec3::new(x, min.y, max.z));
+            debug_draw::line(a, b, grid_color);
+        }
+        for z in min.z..=max.z {
+            let a = self.grid.to_local(IVec3::new(min.x, min.y, z));
+            let b = self.grid.to_local(IVec3::new(max.x, min.y, z));
+            debug_draw::line(a, b, grid_color);
+        }
+    }
+
+    pub fn shift_origin(&mut self, shift: Vec3) {
+        self.grid.shift_origin(shift);
+        self.physics.shift_origin(shift, self.grid);
+    }
+
+    /// The closest brick under the ray
+    pub fn brick_at(&self, ray: &Ray) -> Option<&Brick> {
+        self.pick(ray).map(|(index, _)| &self.bricks[index])
+    }
+
+    /// Applies the edit and remembers it for undo, unless it changes nothing
+    fn commit(&mut self, edit: Edit, instances: &mut InstancedRenderer) {
+        let empty = match &edit {
+            Edit::Add(records) | Edit::Remove(records) => records.is_empty(),
+            Edit::Move { ids, .. } => ids.is_empty(),
+            Edit::Recolor(changes) => changes.is_empty(),
+        };
+        if !empty {
+            self.apply(&edit, instances);
+            self.history.push(edit);
+            self.edits += 1;
+        }
+    }
+
+    /// Changes the bricks and their instances, without checking whether the bricks
+    /// can go where the edit puts them
+    fn apply(&mut self, edit: &Edit, instances: &mut InstancedRenderer) {
+        let (grid, types) = (self.grid, &self.types);
+        match edit {
+            Edit::Add(records) => {
+                for record in records {
+                    self.unchecked.push(record.cells);
+                    let instance = instances.add_colored_instance(
+                        self.meshes[record.kind],
+                        transform(grid, &types[record.kind], record.rotation, &record.cells),
+                        palette::to_linear(record.color),
+                    );
+                    self.slots.insert(record.id, self.bricks.len());
+                    self.index.insert(record.id, &record.cells);
+                    self.bricks.push(Brick {
+                        id: record.id,
+                        kind: record.kind,
+                        rotation: record.rotation,
+                        cells: record.cells,
+                        color: record.color,
+                        selected: false,
+                        instance,
+                    });
+                }
+            }
+            Edit::Remove(records) => {
+                for record in records {
+                    self.unchecked.push(record.cells);
+                    if let Some(brick) = self.take(record.id) {
+                        instances.remove_instance(brick.instance);
+                    }
+                }
+            }
+            Edit::Move { ids, offset } => {
+                // Bricks that fell off since are left out
+                let slots = &self.slots;
+                for index in ids.iter().filter_map(|id| slots.get(id).copied()) {
+                    let brick = &mut self.bricks[index];
+                    self.index.remove(brick.id, &brick.cells);
+                    self.unchecked.push(brick.cells);
+                    brick.cells = brick.cells.moved(*offset);
+                    self.index.insert(brick.id, &brick.cells);
+                    self.unchecked.push(brick.cells);
+                    let transform =
+                        transform(grid, &types[brick.kind], brick.rotation, &brick.cells);
+                    instances.set_transform(brick.instance, transform);
+                }
+            }
+            Edit::Recolor(changes) => {
+                for &(id, _, color) in changes {
+                    if let Some(&index) = self.slots.get(&id) {
+                        let brick = &mut self.bricks[index];
+                        brick.color = color;
+                        instances.set_color(brick.instance, palette::to_linear(color));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Takes the brick out of the grid, leaving its instance alone
+    fn take(&mut self, id: u32) -> Option<Brick> {
+        let index = self.slots.remove(&id)?;
+        let brick = self.bricks.swap_remove(index);
+        if let Some(moved) = self.bricks.get(index) {
+            self.slots.insert(moved.id, index);
+        }
+        self.index.remove(brick.id, &brick.cells);
+        Some(brick)
+    }
+
+    /// Groups of bricks connected to each other but not to anything standing on the
+    /// terrain, by id. Only the groups with bricks in the areas or next to them are
+    /// looked at, the rest haven't changed.
+    fn loose_groups(&self, areas: &[GridBox], heights: &HeightSnapshot) -> Vec<Vec<u32>> {
+        let mut seeds = vec![];
+        for area in areas {
+            // The bricks that were joined to one taken away are a cell further out
+            let around = GridBox {
+                min: area.min - IVec3::ONE,
+                max: area.max + IVec3::ONE,
+            };
+            seeds.extend(self.near(&around).map(|brick| brick.id));
+        }
+        ungrounded_groups(
+            seeds,
+            |id| self.joined_to(id),
+            |id| self.grounded(&self.bricks[self.slots[&id]].cells, heights),
+        )
+    }
+
+    /// Ids of the bricks above and below joined to the brick
+    fn joined_to(&self, id: u32) -> Vec<u32> {
+        let brick = &self.bricks[self.slots[&id]];
+        let around = GridBox {
+            min: brick.cells.min - IVec3::Y,
+            max: brick.cells.max + IVec3::Y,
+        };
+        self.near(&around)
+            .filter(|other| self.joined(brick.kind, brick.rotation, &brick.cells, other))
+            .map(|other| other.id)
+            .collect()
+    }
+
+    /// On the terrain or in it
+    fn grounded(&self, cells: &GridBox, heights: &HeightSnapshot) -> bool {
+        let (min, max) = (self.grid.to_local(cells.min), self.grid.to_local(cells.max));
+        heights
+            .range(min.xz(), max.xz())
+            .is_some_and(|(low, high)| cells.min.y <= self.foundation_between(low, high).level)
+    }
+
+    /// Whether a stud of the brick is in an anti-stud of the other one or the other way
+    /// round
+    fn joined(&self, kind: usize, rotation: u8, cells: &GridBox, other: &Brick) -> bool {
+        let brick_type = &self.types[kind];
+        let other_type = &self.types[other.kind];
+        if other.cells.max.y == cells.min.y {
+            connects(
+                &other_type.studs(other.rotation),
+                other.cells.min,
+                &brick_type.anti_studs(rotation),
+                cells.min,
+            )
+        } else if other.cells.min.y == cells.max.y {
+            connects(
+                &brick_type.studs(rotation),
+                cells.min,
+                &other_type.anti_studs(other.rotation),
+                other.cells.min,
+            )
+        } else {
+            false
+        }
+    }
+
+    /// A brick that isn't there yet, with a new id
+    fn new_record(&mut self, placement: &Placement, color: Vec3) -> BrickRecord {
+        self.next_id += 1;
+        BrickRecord {
+            id: self.next_id,
+            kind: placement.kind,
+            rotation: placement.rotation,
+            cells: placement.cells,
+            color,
+        }
+    }
+
+    fn selected_records(&self) -> Vec<BrickRecord> {
+        self.bricks
+            .iter()
+            .filter(|brick| brick.selected)
+            .map(Brick::record)
+            .collect()
+    }
+
+    /// Selects only the bricks with the same ids as the records
+    fn select_records(&mut self, records: &[BrickRecord]) {
+        let ids: HashSet<u32> = records.iter().map(|record| record.id).collect();
+        for brick in &mut self.bricks {
+            brick.selected = ids.contains(&brick.id);
+        }
+    }
+
+    /// Free of other bricks and out of the terrain. The selected bricks are ignored
+    /// when they're the ones being moved.
+    fn fits(&self, cells: &GridBox, terrain: &Terrain, ignore_selected: bool) -> bool {
+        let blocked = self
+            .near(cells)
+            .filter(|brick| !(ignore_selected && brick.selected))
+            .any(|brick| brick.cells.overlaps(cells));
+        !blocked
+            && self
+                .terrain_level(cells, terrain)
+                .is_none_or(|level| cells.min.y >= level)
+    }
+
+    fn transform(&self, kind: usize, rotation: u8, cells: &GridBox) -> Mat4 {
+        transform(self.grid, &self.types[kind], rotation, cells)
+    }
+
+    /// The closest brick the ray hits and the distance to it
+    fn pick(&self, ray: &Ray) -> Option<(usize, f32)> {
+        let mut chunks: Vec<(f32, &[u32])> = self
+            .index
+            .chunks()
+            .filter_map(|(cells, ids)| {
+                let hit = ray.hits_aabb(&self.grid.bounds(&cells))?;
+                Some((hit.t_min, ids))
+            })
+            .collect();
+        chunks.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut closest: Option<(usize, f32)> = None;
+        for (t_chunk, ids) in chunks {
+            // Nothing in the chunks further away can be closer
+            if closest.is_some_and(|(_, t)| t < t_chunk) {
+                break;
+            }
+            for id in ids {
+                let index = self.slots[id];
+                let brick = &self.bricks[index];
+                if let Some(hit) = ray.hits_aabb(&self.grid.bounds(&brick.cells)) {
+                    if closest.is_none_or(|(_, t)| hit.t_min < t) {
+                        closest = Some((index, hit.t_min));
+                    }
+                }
+            }
+        }
+        closest
+    }
+
+    /// The bricks in the chunks the area overlaps, which may not overlap it themselves
+    fn near(&self, area: &GridBox) -> impl Iterator<Item = &Brick> {
+        self.index
+            .near(area)
+            .into_iter()
+            .map(move |id| &self.bricks[self.slots[&id]])
+    }
+
+    /// Normal of the face of the box closest to the point
+    fn face_normal(&self, cells: &GridBox, point: Vec3) -> Vec3 {
+        let bounds = self.grid.bounds(cells);
+        let size = bounds.max - bounds.min;
+        // Relative to the box, so that the tall and flat sides compare fairly
+        let to_min = (point - bounds.min) / size;
+        let to_max = (bounds.max - point) / size;
+        let faces = [
+            (to_max.x, Vec3::X),
+            (to_min.x, -Vec3::X),
+            (to_max.y, Vec3::Y),
+            (to_min.y, -Vec3::Y),
+            (to_max.z, Vec3::Z),
+            (to_min.z, -Vec3::Z),
+        ];
+        faces
+            .iter()
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map_or(Vec3::Y, |face| face.1)
+    }
+
+    /// The lowest level a brick can stand on at the center of its footprint
+    fn terrain_level(&self, cells: &GridBox, terrain: &Terrain) -> Option<i32> {
+        self.foundation(cells, terrain)
+            .map(|foundation| foundation.level)
+    }
+
+    /// None if the cells aren't all over the terrain
+    pub fn foundation(&self, cells: &GridBox, terrain: &Terrain) -> Option<Foundation> {
+        let (min, max) = (self.grid.to_local(cells.min), self.grid.to_local(cells.max));
+        let (low, high) = terrain.height_range(min.xz(), max.xz())?;
+        Some(self.foundation_between(low, high))
+    }
+
+    /// For terrain from `low` to `high` under the brick
+    fn foundation_between(&self, low: f32, high: f32) -> Foundation {
+        let level = ((high - self.grid.origin.y) / PLATE_HEIGHT).ceil() as i32;
+        let base = self.grid.origin.y + level as f32 * PLATE_HEIGHT;
+        Foundation {
+            level,
+            gap: base - low,
+        }
+    }
+
+    /// Raises the terrain under the placements standing on it up to their base, so
+    /// that they don't float. Can't be undone.
+    pub fn flatten_foundations(&self, placements: &[Placement], terrain: &mut Terrain) {
+        for placement in placements {
+            let cells = &placement.cells;
+            let on_terrain = self
+                .foundation(cells, terrain)
+                .is_some_and(|foundation| foundation.level == cells.min.y);
+            if on_terrain {
+                let (min, max) = (self.grid.to_local(cells.min), self.grid.to_local(cells.max));
+                terrain.flatten(min.xz(), max.xz(), min.y);
+            }
+        }
+    }
+
+    /// Standing on the terrain with more than `max_gap` plates between them
+    fn floats(&self, cells: &GridBox, terrain: &Terrain, max_gap: f32) -> bool {
+        self.foundation(cells, terrain).is_some_and(|foundation| {
+            foundation.level == cells.min.y && foundation.gap > max_gap * PLATE_HEIGHT
+        })
+    }
+}
+
+impl Brick {
+    pub fn record(&self) -> BrickRecord {
+        BrickRecord {
+            id: self.id,
+            kind: self.kind,
+            rotation: self.rotation,
+            cells: self.cells,
+            color: self.color,
+        }
+    }
+}
+
+/// Green if the brick can go there, orange if it can but floats over the terrain,
+/// red if it can't
+fn placement_color(placement: &Placement) -> Vec4 {
+    let color = match (placement.valid, placement.floating) {
+        (false, _) => PLACEMENT_COLORS[2],
+        (true, true) => PLACEMENT_COLORS[1],
+        (true, false) => PLACEMENT_COLORS[0],
+    };
+    Vec4::from(color)
+}
+
+/// Local transform of a brick mesh
+fn transform(grid: StudGrid, brick_type: &BrickType, rotation: u8, cells: &GridBox) -> Mat4 {
+    Mat4::from_translation(grid.to_local(cells.min)) * brick_type.rotation_transform(rotation)
+}
+
+/// Flood fills from each of the `seeds` through `joined` and returns the groups with
+/// no brick `grounded`. Every brick ends up in one group at most, however many of the
+/// seeds it's reached from.
+fn ungrounded_groups(
+    seeds: impl IntoIterator<Item = u32>,
+    joined: impl Fn(u32) -> Vec<u32>,
+    grounded: impl Fn(u32) -> bool,
+) -> Vec<Vec<u32>> {
+    let mut reached = HashSet::new();
+    let mut groups = vec![];
+    for seed in seeds {
+        if !reached.insert(seed) {
+            continue;
+        }
+        let mut group = vec![seed];
+        let mut queue = vec![seed];
+        while let Some(id) = queue.pop() {
+            for other in joined(id) {
+                if reached.insert(other) {
+                    group.push(other);
+                    queue.push(other);
+                }
+            }
+        }
+        // Whatever a grounded brick holds up stays
+        if !group.iter().any(|&id| grounded(id)) {
+            groups.push(group);
+        }
+    }
+    groups
+}
+
+/// Whether a stud of the lower brick is in an anti-stud of the upper one
+fn connects(studs: &[IVec2], lower: IVec3, anti_studs: &[IVec2], upper: IVec3) -> bool {
+    let offset = upper.xz() - lower.xz();
+    studs
+        .iter()
+        .any(|&stud| anti_studs.contains(&(stud - offset)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bricks 1-2-3 in a column on brick 0, which stands on the terrain, and 4-5 joined
+    /// to each other off to the side
+    fn joined(id: u32) -> Vec<u32> {
+        match id {
+            0 => vec![1],
+            1 => vec![0, 2],
+            2 => vec![1, 3],
+            3 => vec![2],
+            4 => vec![5],
+            5 => vec![4],
+            _ => vec![],
+        }
+    }
+
+    fn sorted(mut groups: Vec<Vec<u32>>) -> Vec<Vec<u32>> {
+        for group in &mut groups {
+            group.sort_unstable();
+        }
+        groups.sort();
+        groups
+    }
+
+    #[test]
+    fn grounded_groups_stay() {
+        let groups = ungrounded_groups([3, 2], joined, |id| id == 0);
+        assert!(groups.is_empty());
+    }
+
+    #[test]
+    fn loose_groups_are_found_once() {
+        let groups = ungrounded_groups([5, 4, 2, 6], joined, |id| id == 0);
+        assert_eq!(sorted(groups), vec![vec![4, 5], vec![6]]);
+    }
+
+    #[test]
+    fn removing_the_ground_brick_frees_the_column() {
+        // Brick 0 taken away
+        let joined = |id| match id {
+            0 => vec![],
+            id => joined(id).into_iter().filter(|&other| other != 0).collect(),
+        };
+        let groups = ungrounded_groups([1], joined, |_| false);
+        assert_eq!(sorted(groups), vec![vec![1, 2, 3]]);
+    }
+}
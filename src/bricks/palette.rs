@@ -0,0 +1,42 @@
+//! The classic brick colors. They're sRGB, like the color pickers in the GUI, and
+//! converted to linear for rendering.
+
+use glam::{Vec3, Vec4};
+
+pub const COLORS: [(&str, u32); 16] = [
+    ("White", 0xF4F4F4),
+    ("Light Bluish Gray", 0xA0A5A9),
+    ("Dark Bluish Gray", 0x6C6E68),
+    ("Black", 0x1B2A34),
+    ("Red", 0xC91A09),
+    ("Dark Red", 0x720E0F),
+    ("Orange", 0xFE8A18),
+    ("Yellow", 0xF2CD37),
+    ("Tan", 0xE4CD9E),
+    ("Reddish Brown", 0x582A12),
+    ("Green", 0x237841),
+    ("Bright Green", 0x4B9F4A),
+    ("Lime", 0xBBE90B),
+    ("Blue", 0x0055BF),
+    ("Medium Azure", 0x36AEBF),
+    ("Dark Blue", 0x0A3463),
+];
+
+/// The color bricks get until another one is picked
+pub const DEFAULT_COLOR: usize = 4;
+
+pub fn srgb(hex: u32) -> Vec3 {
+    let channel = |shift: u32| ((hex >> shift) & 0xFF) as f32 / 255.0;
+    Vec3::new(channel(16), channel(8), channel(0))
+}
+
+pub fn to_linear(srgb: Vec3) -> Vec4 {
+    let channel = |c: f32| {
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    Vec4::new(channel(srgb.x), channel(srgb.y), channel(srgb.z), 1.0)
+}
@@ -0,0 +1,306 @@
+//! Bricks that lost their connection to the ground fall as rigid bodies, tumble on the
+//! terrain and the bricks still standing, and come to rest. Bricks still connected to
+//! each other fall together as one body. Once loose they're out of the grid, they're
+//! only drawn until cleared.
+//!
+//! The bricks standing near falling ones get fixed colliders while they're needed,
+//! and the terrain collider is taken from the heightmap whenever bricks start falling.
+//!
+//! The bodies move in the fixed steps of `timestep`, and the bricks are drawn between
+//! where the last two steps left them.
+
+use std::collections::HashMap;
+
+use glam::{Mat4, Vec3};
+use rapier3d::na::DMatrix;
+use rapier3d::prelude::*;
+
+use super::catalog::BrickType;
+use super::{Brick, GridBox, StudGrid};
+use crate::instancing::{InstanceId, InstancedRenderer};
+use crate::ray::AABB;
+use crate::terrain::HeightSnapshot;
+use crate::timestep;
+
+/// Bricks falling this far below the terrain are gone for good
+const FALL_LIMIT: f32 = 100.0;
+const GRAVITY: f32 = -9.81;
+
+struct LooseBrick {
+    body: RigidBodyHandle,
+    instance: InstanceId,
+    /// From the body to the mesh
+    offset: Mat4,
+    /// Of the body before the last step
+    previous: Isometry<Real>,
+}
+
+pub struct BrickPhysics {
+    pipeline: PhysicsPipeline,
+    parameters: IntegrationParameters,
+    islands: IslandManager,
+    broad_phase: BroadPhase,
+    narrow_phase: NarrowPhase,
+    bodies: RigidBodySet,
+    colliders: ColliderSet,
+    impulse_joints: ImpulseJointSet,
+    multibody_joints: MultibodyJointSet,
+    ccd_solver: CCDSolver,
+
+    terrain: Option<ColliderHandle>,
+    /// Bottom of the terrain when its collider was made
+    terrain_floor: f32,
+    /// Fixed colliders of the bricks standing near falling ones, by brick id
+    anchors: HashMap<u32, (ColliderHandle, GridBox)>,
+    loose: Vec<LooseBrick>,
+}
+
+impl BrickPhysics {
+    pub fn new() -> Self {
+        BrickPhysics {
+            pipeline: PhysicsPipeline::new(),
+            parameters: IntegrationParameters {
+                dt: timestep::STEP,
+                ..Default::default()
+            },
+            islands: IslandManager::new(),
+            broad_phase: BroadPhase::new(),
+            narrow_phase: NarrowPhase::new(),
+            bodies: RigidBodySet::new(),
+            colliders: ColliderSet::new(),
+            impulse_joints: ImpulseJointSet::new(),
+            multibody_joints: MultibodyJointSet::new(),
+            ccd_solver: CCDSolver::new(),
+
+            terrain: None,
+            terrain_floor: 0.0,
+            anchors: HashMap::new(),
+            loose: vec![],
+        }
+    }
+
+    pub fn loose_count(&self) -> usize {
+        self.loose.len()
+    }
+
+    /// Whether any of the loose bricks are still moving
+    pub fn is_active(&self) -> bool {
+        self.awake().next().is_some()
+    }
+
+    /// Replaces the terrain collider with one made from the heightmap
+    pub fn set_terrain(&mut self, heights: &HeightSnapshot) {
+        if let Some(collider) = self.terrain.take() {
+            self.colliders
+                .remove(collider, &mut self.islands, &mut self.bodies, true);
+        }
+        let count = heights.texture_size;
+        let matrix = DMatrix::from_fn(count, count, |row, column| heights.height(column, row));
+        // The vertices are the texel centers, half a texel in from the edges
+        let aabb = &heights.aabb;
+        let extent = (aabb.max.x - aabb.min.x) * (count - 1) as f32 / count as f32;
+        let center = (aabb.min + aabb.max) * 0.5;
+        let collider = ColliderBuilder::heightfield(matrix, vector![extent, 1.0, extent])
+            .translation(vector![center.x, 0.0, center.z])
+            .friction(0.8)
+            .build();
+        self.terrain = Some(self.colliders.insert(collider));
+        self.terrain_floor = aabb.min.y;
+    }
+
+    /// Lets a group of connected bricks fall as one body, taking over their instances
+    pub fn drop_group(&mut self, bricks: Vec<Brick>, grid: StudGrid, types: &[BrickType]) {
+        let bounds: Vec<AABB> = bricks
+            .iter()
+            .map(|brick| grid.bounds(&brick.cells))
+            .collect();
+        let center = bounds
+            .iter()
+            .map(|bounds| (bounds.min + bounds.max) * 0.5)
+            .fold(Vec3::ZERO, |sum, center| sum + center)
+            / bounds.len() as f32;
+        let body = RigidBodyBuilder::dynamic()
+            .translation(vector![center.x, center.y, center.z])
+            .ccd_enabled(true)
+            .build();
+        let body = self.bodies.insert(body);
+        let previous = *self.bodies[body].position();
+
+        for (brick, bounds) in bricks.into_iter().zip(bounds) {
+            let half = (bounds.max - bounds.min) * 0.5;
+            // Slopes are boxes too, close enough for a pile of rubble
+            let local = bounds.min + half - center;
+            let collider = ColliderBuilder::cuboid(half.x, half.y, half.z)
+                .translation(vector![local.x, local.y, local.z])
+                .friction(0.7)
+                .restitution(0.1)
+                .build();
+            self.colliders
+                .insert_with_parent(collider, body, &mut self.bodies);
+            let offset = Mat4::from_translation(bounds.min - center)
+                * types[brick.kind].rotation_transform(brick.rotation);
+            self.loose.push(LooseBrick {
+                body,
+                instance: brick.instance,
+                offset,
+                previous,
+            });
+        }
+    }
+
+    /// Boxes around the moving bodies, to find the bricks they could hit
+    pub fn active_bounds(&self) -> Vec<AABB> {
+        self.awake()
+            .flat_map(|body| self.bodies[body].colliders())
+            .map(|&collider| {
+                let aabb = self.colliders[collider].compute_aabb();
+                AABB::new(
+                    Vec3::new(aabb.mins.x, aabb.mins.y, aabb.mins.z),
+                    Vec3::new(aabb.maxs.x, aabb.maxs.y, aabb.maxs.z),
+                )
+            })
+            .collect()
+    }
+
+    /// Makes the standing bricks the falling ones can hit solid and forgets the rest
+    pub fn set_anchors(&mut self, anchors: HashMap<u32, GridBox>, grid: StudGrid) {
+        let stale: Vec<u32> = self
+            .anchors
+            .iter()
+            .filter(|(id, (_, cells))| anchors.get(id) != Some(cells))
+            .map(|(&id, _)| id)
+            .collect();
+        for id in stale {
+            let (collider, _) = self.anchors.remove(&id).unwrap();
+            self.colliders
+                .remove(collider, &mut self.islands, &mut self.bodies, true);
+        }
+        for (id, cells) in anchors {
+            if self.anchors.contains_key(&id) {
+                continue;
+            }
+            let bounds = grid.bounds(&cells);
+            let half = (bounds.max - bounds.min) * 0.5;
+            let center = bounds.min + half;
+            let collider = ColliderBuilder::cuboid(half.x, half.y, half.z)
+                .translation(vector![center.x, center.y, center.z])
+                .friction(0.7)
+                .build();
+            self.anchors
+                .insert(id, (self.colliders.insert(collider), cells));
+        }
+    }
+
+    /// Simulates the time passed in fixed steps and moves the instances along
+    /// Moves the bodies on by one of the fixed steps
+    pub fn step(&mut self, instances: &mut InstancedRenderer) {
+        for brick in &mut self.loose {
+            brick.previous = *self.bodies[brick.body].position();
+        }
+        self.pipeline.step(
+            &vector![0.0, GRAVITY, 0.0],
+            &self.parameters,
+            &mut self.islands,
+            &mut self.broad_phase,
+            &mut self.narrow_phase,
+            &mut self.bodies,
+            &mut self.colliders,
+            &mut self.impulse_joints,
+            &mut self.multibody_joints,
+            &mut self.ccd_solver,
+            None,
+            &(),
+            &(),
+        );
+
+        let floor = self.terrain_floor - FALL_LIMIT;
+        let fallen: Vec<RigidBodyHandle> = self
+            .awake()
+            .filter(|&body| self.bodies[body].translation().y < floor)
+            .collect();
+        for body in fallen {
+            self.remove_body(body, instances);
+        }
+    }
+
+    /// Draws the moving bricks `alpha` of the way from where they were before the last
+    /// step to where they are, see `FixedTimestep::alpha`
+    pub fn interpolate(&self, alpha: f32, instances: &mut InstancedRenderer) {
+        for brick in &self.loose {
+            let body = &self.bodies[brick.body];
+            if !body.is_sleeping() {
+                let position = brick.previous.lerp_slerp(body.position(), alpha);
+                let position = Mat4::from_cols_slice(position.to_homogeneous().as_slice());
+                instances.set_transform(brick.instance, position * brick.offset);
+            }
+        }
+    }
+
+    /// Removes all loose bricks
+    pub fn clear(&mut self, instances: &mut InstancedRenderer) {
+        let bodies: Vec<RigidBodyHandle> = self.bodies.iter().map(|(body, _)| body).collect();
+        for body in bodies {
+            self.remove_body(body, instances);
+        }
+        self.remove_anchors();
+    }
+
+    /// Moves the bodies and colliders along with the local origin
+    pub fn shift_origin(&mut self, shift: Vec3, grid: StudGrid) {
+        let shift = vector![shift.x, shift.y, shift.z];
+        for (_, body) in self.bodies.iter_mut() {
+            let mut position = *body.position();
+            position.translation.vector -= shift;
+            body.set_position(position, false);
+        }
+        for brick in &mut self.loose {
+            brick.previous.translation.vector -= shift;
+        }
+        if let Some(terrain) = self.terrain {
+            let collider = &mut self.colliders[terrain];
+            collider.set_translation(collider.translation() - shift);
+        }
+        // Made again where the grid is now
+        let anchors = self
+            .anchors
+            .iter()
+            .map(|(&id, &(_, cells))| (id, cells))
+            .collect();
+        self.remove_anchors();
+        self.set_anchors(anchors, grid);
+    }
+
+    fn remove_anchors(&mut self) {
+        for (_, (collider, _)) in self.anchors.drain() {
+            self.colliders
+                .remove(collider, &mut self.islands, &mut self.bodies, true);
+        }
+    }
+
+    /// New bodies are awake before the first step, unlike the island manager's active
+    /// ones
+    fn awake(&self) -> impl Iterator<Item = RigidBodyHandle> + '_ {
+        self.bodies
+            .iter()
+            .filter(|(_, body)| !body.is_sleeping())
+            .map(|(handle, _)| handle)
+    }
+
+    fn remove_body(&mut self, body: RigidBodyHandle, instances: &mut InstancedRenderer) {
+        self.bodies.remove(
+            body,
+            &mut self.islands,
+            &mut self.colliders,
+            &mut self.impulse_joints,
+            &mut self.multibody_joints,
+            true,
+        );
+        self.loose.retain(|brick| {
+            let removed = brick.body == body;
+            if removed {
+                instances.remove_instance(brick.instance);
+            }
+            !removed
+        });
+    }
+}
@@ -0,0 +1,52 @@
+//! Groups of bricks saved under a name to be stamped again and again. Each prefab is
+//! a file in the prefab directory named after it, in the same format as saved bricks.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::catalog::BrickType;
+use super::clipboard::Clipboard;
+use super::save;
+use crate::Result;
+
+const EXTENSION: &str = "json";
+
+/// Names of the prefabs in the directory in alphabetical order, none if it's missing
+pub fn list(dir: impl AsRef<Path>) -> Vec<String> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == EXTENSION))
+        .filter_map(|path| Some(path.file_stem()?.to_string_lossy().into_owned()))
+        .collect();
+    names.sort_by_key(|name| name.to_lowercase());
+    names
+}
+
+/// Saves the group, replacing the prefab with the same name if there is one
+pub fn save(
+    dir: impl AsRef<Path>,
+    name: &str,
+    clipboard: &Clipboard,
+    types: &[BrickType],
+) -> Result<()> {
+    fs::create_dir_all(&dir)?;
+    save::save_clipboard(path(dir, name)?, clipboard, types)
+}
+
+pub fn load(dir: impl AsRef<Path>, name: &str, types: &[BrickType]) -> Result<Clipboard> {
+    save::load_clipboard(path(dir, name)?, types)
+}
+
+/// Names end up as file names, so they can't point outside the directory
+fn path(dir: impl AsRef<Path>, name: &str) -> Result<PathBuf> {
+    let name = name.trim();
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\', ':']) {
+        return Err(format!("Bad prefab name \"{}\"", name).into());
+    }
+    Ok(dir.as_ref().join(format!("{}.{}", name, EXTENSION)))
+}
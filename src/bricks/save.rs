@@ -0,0 +1,120 @@
+//! Placed bricks saved as JSON. Kinds are saved by name so that the catalog can be
+//! reordered or grow without breaking old saves.
+
+use std::fs;
+use std::path::Path;
+
+use glam::{IVec3, Vec3};
+use serde::{Deserialize, Serialize};
+
+use super::catalog::BrickType;
+use super::clipboard::{Clipboard, ClipboardBrick};
+use super::{Brick, GridBox, Placement};
+use crate::Result;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedBrick {
+    kind: String,
+    /// Min corner of the cells, in grid coordinates
+    position: IVec3,
+    rotation: u8,
+    /// sRGB
+    color: Vec3,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SaveFile {
+    bricks: Vec<SavedBrick>,
+}
+
+pub fn save(path: impl AsRef<Path>, bricks: &[Brick], types: &[BrickType]) -> Result<()> {
+    fs::write(path, serde_json::to_string(&save_file(bricks, types))?)?;
+    Ok(())
+}
+
+/// Same as `save`, writing on a worker what's there when it's called
+pub fn save_job(
+    path: String,
+    bricks: &[Brick],
+    types: &[BrickType],
+) -> impl FnOnce() -> std::result::Result<(), String> + Send + 'static {
+    let file = save_file(bricks, types);
+    move || {
+        let text = serde_json::to_string(&file).map_err(|e| e.to_string())?;
+        fs::write(&path, text).map_err(|e| e.to_string())
+    }
+}
+
+fn save_file(bricks: &[Brick], types: &[BrickType]) -> SaveFile {
+    SaveFile {
+        bricks: bricks
+            .iter()
+            .map(|brick| SavedBrick {
+                kind: types[brick.kind].name.clone(),
+                position: brick.cells.min,
+                rotation: brick.rotation,
+                color: brick.color,
+            })
+            .collect(),
+    }
+}
+
+/// Same as `save`, with the positions relative to the min corner of the group
+pub fn save_clipboard(
+    path: impl AsRef<Path>,
+    clipboard: &Clipboard,
+    types: &[BrickType],
+) -> Result<()> {
+    let file = SaveFile {
+        bricks: clipboard
+            .bricks
+            .iter()
+            .map(|brick| SavedBrick {
+                kind: types[brick.kind].name.clone(),
+                position: brick.cells.min,
+                rotation: brick.rotation,
+                color: brick.color,
+            })
+            .collect(),
+    };
+    fs::write(path, serde_json::to_string_pretty(&file)?)?;
+    Ok(())
+}
+
+/// The saved bricks with their colors, fails on kinds missing from the catalog
+pub fn load(path: impl AsRef<Path>, types: &[BrickType]) -> Result<Vec<(Placement, Vec3)>> {
+    let file: SaveFile = serde_json::from_str(&fs::read_to_string(path)?)?;
+    file.bricks
+        .into_iter()
+        .map(|saved| {
+            let kind = types
+                .iter()
+                .position(|brick_type| brick_type.name == saved.kind)
+                .ok_or_else(|| format!("Unknown brick type {}", saved.kind))?;
+            let rotation = saved.rotation % 4;
+            let placement = Placement {
+                kind,
+                rotation,
+                cells: GridBox::new(saved.position, types[kind].size(rotation)),
+                valid: true,
+                floating: false,
+            };
+            Ok((placement, saved.color))
+        })
+        .collect()
+}
+
+/// Loads a file saved with `save_clipboard`, or any save to paste it elsewhere
+pub fn load_clipboard(path: impl AsRef<Path>, types: &[BrickType]) -> Result<Clipboard> {
+    let path = path.as_ref();
+    let bricks = load(path, types)?
+        .into_iter()
+        .map(|(placement, color)| ClipboardBrick {
+            kind: placement.kind,
+            rotation: placement.rotation,
+            cells: placement.cells,
+            color,
+        })
+        .collect();
+    Clipboard::new(bricks).ok_or_else(|| format!("{} has no bricks", path.display()).into())
+}
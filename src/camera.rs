@@ -23,7 +23,7 @@ pub enum Movement {
     Right,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct Camera {
     pub position: Vec3,
     pub direction: Vec3,
@@ -33,7 +33,8 @@ pub struct Camera {
     yaw: f32,
     pitch: f32,
 
-    movement_speed: f32,
+    /// World units per second, 10 times more with `speed_boost`
+    pub movement_speed: f32,
     sensitivity: f32,
     zoom: f32,
     screen_dimensions: Vec2,
@@ -95,6 +96,28 @@ impl Camera {
         }
     }
 
+    /// Moves the camera and points it in the direction, keeping the rest of it as it is
+    pub fn look_from(&mut self, position: Vec3, direction: Vec3) {
+        let moved = Camera::new(
+            position,
+            position + direction,
+            self.screen_dimensions.x as u32,
+            self.screen_dimensions.y as u32,
+        );
+        self.position = moved.position;
+        self.direction = moved.direction;
+        self.up = moved.up;
+        self.right = moved.right;
+        self.yaw = moved.yaw;
+        self.pitch = moved.pitch;
+    }
+
+    /// Call when the window is resized
+    pub fn resize(&mut self, screen_width: u32, screen_height: u32) {
+        self.screen_dimensions = Vec2::new(screen_width as f32, screen_height as f32);
+        self.aspect_ratio = self.screen_dimensions.x / self.screen_dimensions.y;
+    }
+
     /// Move the camera
     pub fn go(&mut self, direction: Movement, delta_time: f32) {
         let speed = if self.speed_boost {
@@ -105,7 +128,7 @@ impl Camera {
         let speed = speed * delta_time;
 
         let projected_direction = if self.locked {
-            Vec3::new(self.direction.x, 0.0, self.direction.z)
+            Vec3::new(self.direction.x, 0.0, self.direction.z).normalize_or_zero()
         } else {
             self.direction
         };
@@ -117,6 +140,12 @@ impl Camera {
         }
     }
 
+    /// Walking keeps going forward level with the ground wherever the camera looks,
+    /// rather than flying where it looks
+    pub fn set_walking(&mut self, walking: bool) {
+        self.locked = walking;
+    }
+
     /// Zoom is used to calculate the vertical FOV:
     ///
     /// 1.0 corresponds to FOV_MAX,
@@ -127,10 +156,18 @@ impl Camera {
     }
 
     pub fn rotate(&mut self, yaw_delta: f32, pitch_delta: f32) {
+        self.turn(
+            yaw_delta * self.sensitivity,
+            -pitch_delta * self.sensitivity,
+        );
+    }
+
+    /// By angles in radians rather than mouse movement, up for positive pitch
+    pub fn turn(&mut self, yaw: f32, pitch: f32) {
         // Adjust Euler angles
-        self.pitch -= pitch_delta * self.sensitivity;
+        self.pitch += pitch;
         self.pitch = self.pitch.clamp(PITCH_MIN, PITCH_MAX);
-        self.yaw += yaw_delta * self.sensitivity;
+        self.yaw += yaw;
 
         // Recalculate direction
         self.direction = Vec3::new(
@@ -164,6 +201,11 @@ impl Camera {
         Ray::new(self.position, direction)
     }
 
+    /// Through the middle of the screen, where the crosshair is in play mode
+    pub fn get_ray_forward(&self) -> Ray {
+        Ray::new(self.position, self.direction)
+    }
+
     pub fn get_view_matrix(&self) -> Mat4 {
         // Camera never turns upside down so true up is fixed
         Mat4::look_at_rh(self.position, self.position + self.direction, TRUE_UP)
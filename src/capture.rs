@@ -0,0 +1,148 @@
+use std::path::Path;
+
+use gl::types::*;
+use glam::{Mat4, Vec3};
+
+use crate::opengl::{self, bindings, IncompleteFramebuffer};
+use crate::Result;
+
+/// Face file names, directions and up vectors in the order `Skybox::decode_job` expects.
+/// Up vectors follow the GL cubemap convention which is upside down compared to
+/// normal rendering, so the pixels read back can be saved as is.
+const FACES: [(&str, Vec3, Vec3); 6] = [
+    (
+        "right",
+        glam::const_vec3!([1.0, 0.0, 0.0]),
+        glam::const_vec3!([0.0, -1.0, 0.0]),
+    ),
+    (
+        "left",
+        glam::const_vec3!([-1.0, 0.0, 0.0]),
+        glam::const_vec3!([0.0, -1.0, 0.0]),
+    ),
+    (
+        "top",
+        glam::const_vec3!([0.0, 1.0, 0.0]),
+        glam::const_vec3!([0.0, 0.0, 1.0]),
+    ),
+    (
+        "bottom",
+        glam::const_vec3!([0.0, -1.0, 0.0]),
+        glam::const_vec3!([0.0, 0.0, -1.0]),
+    ),
+    (
+        "front",
+        glam::const_vec3!([0.0, 0.0, 1.0]),
+        glam::const_vec3!([0.0, -1.0, 0.0]),
+    ),
+    (
+        "back",
+        glam::const_vec3!([0.0, 0.0, -1.0]),
+        glam::const_vec3!([0.0, -1.0, 0.0]),
+    ),
+];
+
+pub const FACE_COUNT: usize = FACES.len();
+
+/// Offscreen target for rendering the scene into the six faces of a cubemap
+pub struct CubemapCapture {
+    fbo: GLuint,
+    color: GLuint,
+    depth: GLuint,
+    face_size: i32,
+}
+
+impl CubemapCapture {
+    pub fn new(face_size: u32) -> std::result::Result<Self, IncompleteFramebuffer> {
+        let face_size = face_size as i32;
+        let mut fbo: GLuint = 0;
+        let mut color: GLuint = 0;
+        let mut depth: GLuint = 0;
+        unsafe {
+            gl::CreateFramebuffers(1, &mut fbo);
+
+            // sRGB so that the bytes read back are what would be shown on screen
+            gl::CreateTextures(gl::TEXTURE_2D, 1, &mut color);
+            gl::TextureStorage2D(color, 1, gl::SRGB8_ALPHA8, face_size, face_size);
+            gl::NamedFramebufferTexture(fbo, gl::COLOR_ATTACHMENT0, color, 0);
+
+            gl::CreateRenderbuffers(1, &mut depth);
+            gl::NamedRenderbufferStorage(depth, gl::DEPTH_COMPONENT24, face_size, face_size);
+            gl::NamedFramebufferRenderbuffer(fbo, gl::DEPTH_ATTACHMENT, gl::RENDERBUFFER, depth);
+        }
+
+        // Constructed first so that drop cleans up if the framebuffer is incomplete
+        let capture = CubemapCapture {
+            fbo,
+            color,
+            depth,
+            face_size,
+        };
+        opengl::check_framebuffer(fbo, "Cubemap capture")?;
+        Ok(capture)
+    }
+
+    /// 90 degree square frustum so that the faces meet exactly
+    pub fn projection(&self) -> Mat4 {
+        Mat4::perspective_infinite_rh(std::f32::consts::FRAC_PI_2, 1.0, 0.5)
+    }
+
+    pub fn face_view(&self, face: usize, position: Vec3) -> Mat4 {
+        let (_, direction, up) = FACES[face];
+        Mat4::look_at_rh(position, position + direction, up)
+    }
+
+    /// Makes the capture framebuffer the render target. Call `unbind` with the window
+    /// size when done.
+    pub fn bind(&self) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+            gl::Viewport(0, 0, self.face_size, self.face_size);
+        }
+    }
+
+    pub fn unbind(&self, width: i32, height: i32) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            gl::Viewport(0, 0, width, height);
+        }
+    }
+
+    /// Saves whatever has been rendered as `<dir>/<face name>.png`
+    pub fn save_face(&self, face: usize, dir: &Path) -> Result<()> {
+        let size = self.face_size as usize;
+        let mut pixels = vec![0u8; size * size * 3];
+        unsafe {
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+            gl::GetTextureImage(
+                self.color,
+                0,
+                gl::RGB,
+                gl::UNSIGNED_BYTE,
+                pixels.len() as i32,
+                pixels.as_mut_ptr() as *mut _,
+            );
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 4);
+        }
+
+        let (name, _, _) = FACES[face];
+        image::save_buffer(
+            dir.join(format!("{}.png", name)),
+            &pixels,
+            size as u32,
+            size as u32,
+            image::ColorType::Rgb8,
+        )?;
+        Ok(())
+    }
+}
+
+impl Drop for CubemapCapture {
+    fn drop(&mut self) {
+        bindings::delete_textures(&[self.color]);
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.fbo);
+            gl::DeleteRenderbuffers(1, &self.depth);
+        }
+    }
+}
@@ -0,0 +1,241 @@
+//! Subcommands which run without opening a window, for batch processing.
+//! Output is plain lines of text so it works well with scripts and screen readers.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::bake::Baker;
+use crate::benchmark;
+use crate::bricks::{catalog, ldraw, save};
+use crate::erosion::{self, ErosionParams};
+use crate::headless::HeadlessContext;
+use crate::heightfield::HeightField;
+use crate::jobs::JobSystem;
+use crate::recording::RecordingMode;
+use crate::terrain::{MAX_HEIGHT, TERRAIN_SIZE};
+use crate::Result;
+
+/// In texels of the heightmap
+const DEFAULT_AO_RADIUS: f32 = 32.0;
+
+const USAGE: &str = "\
+Usage: game2 [COMMAND]
+
+Without a command the editor is started.
+
+Commands:
+    convert-heightmap <input> <output> [--size N]
+        Convert an image to a 16-bit grayscale heightmap, optionally resizing it
+    erode <input> <output> [--iterations N] [--seed N]
+        Run hydraulic erosion on a heightmap
+    export-mesh <heightmap> <output.obj> [--step N]
+        Export the terrain as an OBJ mesh, taking every N-th heightmap pixel
+    bake <heightmap> <output-dir> [--erode N] [--seed N] [--ao-radius N]
+        Save the heightmap with its normal map and ambient occlusion to the directory,
+        eroding it with N droplets first if asked. Runs on the GPU without a window.
+    ldraw-to-scene <input.ldr> <output.json>
+        Convert an LDraw model into bricks, saved like a prefab so the editor can load
+        or paste them. Parts that don't fit on the stud grid are skipped.
+    record <file>
+        Start the editor and record its input to the file
+    replay <file>
+        Start the editor and play back the input recorded to the file, frame by frame
+    benchmark [output.csv]
+        Fly the camera around a standard terrain with the default settings and save the
+        time of every frame to the file, benchmark.csv by default, with a summary
+    help
+        Show this message";
+
+/// Runs the subcommand given on the command line.
+/// Returns None if there is none and the editor should start, see `recording_mode`.
+pub fn run(args: &[String]) -> Option<Result<()>> {
+    let (command, rest) = args.split_first()?;
+    let result = match command.as_str() {
+        "record" | "replay" if rest.len() == 1 => return None,
+        "record" | "replay" => Err(format!("Expected a file\n\n{}", USAGE).into()),
+        "benchmark" | "--benchmark" if rest.len() <= 1 => return None,
+        "benchmark" | "--benchmark" => Err(format!("Too many arguments\n\n{}", USAGE).into()),
+        "convert-heightmap" => convert_heightmap(rest),
+        "erode" => erode(rest),
+        "export-mesh" => export_mesh(rest),
+        "bake" => bake(rest),
+        "ldraw-to-scene" => ldraw_to_scene(rest),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => Err(format!("Unknown command '{}'\n\n{}", command, USAGE).into()),
+    };
+    Some(result)
+}
+
+/// Whether the editor records or plays back its input, once `run` has let it start
+pub fn recording_mode(args: &[String]) -> Option<RecordingMode> {
+    match args {
+        [command, path] if command == "record" => Some(RecordingMode::Record(path.into())),
+        [command, path] if command == "replay" => Some(RecordingMode::Replay(path.into())),
+        _ => None,
+    }
+}
+
+/// Where the benchmark run saves its results, None if it's not one
+pub fn benchmark_output(args: &[String]) -> Option<PathBuf> {
+    match args {
+        [command] if command == "benchmark" || command == "--benchmark" => {
+            Some(PathBuf::from(benchmark::DEFAULT_OUTPUT))
+        }
+        [command, path] if command == "benchmark" || command == "--benchmark" => {
+            Some(PathBuf::from(path))
+        }
+        _ => None,
+    }
+}
+
+fn convert_heightmap(args: &[String]) -> Result<()> {
+    let args = Args::parse(args, &["--size"])?;
+    let (input, output) = args.input_output()?;
+    let size = args.option("--size")?;
+
+    let field = HeightField::load(input, size)?;
+    field.save(output)?;
+    println!("Saved {0}x{0} heightmap to {1}", field.size, output);
+    Ok(())
+}
+
+fn erode(args: &[String]) -> Result<()> {
+    let args = Args::parse(args, &["--iterations", "--seed"])?;
+    let (input, output) = args.input_output()?;
+    let defaults = ErosionParams::default();
+    let params = ErosionParams {
+        iterations: args.option("--iterations")?.unwrap_or(defaults.iterations),
+        seed: args.option("--seed")?.unwrap_or(defaults.seed),
+        ..defaults
+    };
+
+    let mut field = HeightField::load(input, None)?;
+    println!("Eroding {} with {} droplets", input, params.iterations);
+    let texel_size = TERRAIN_SIZE / field.size as f32;
+    erosion::erode_parallel(
+        &mut field,
+        MAX_HEIGHT / texel_size,
+        &params,
+        &JobSystem::new(),
+    );
+    field.save(output)?;
+    println!("Saved eroded heightmap to {}", output);
+    Ok(())
+}
+
+fn export_mesh(args: &[String]) -> Result<()> {
+    let args = Args::parse(args, &["--step"])?;
+    let (input, output) = args.input_output()?;
+    let step = args.option("--step")?.unwrap_or(4);
+
+    let field = HeightField::load(input, None)?;
+    field.write_obj(output, step, TERRAIN_SIZE, MAX_HEIGHT)?;
+    println!("Saved terrain mesh to {}", output);
+    Ok(())
+}
+
+fn bake(args: &[String]) -> Result<()> {
+    let args = Args::parse(args, &["--erode", "--seed", "--ao-radius"])?;
+    let (input, output) = args.input_output()?;
+    let ao_radius = args.option("--ao-radius")?.unwrap_or(DEFAULT_AO_RADIUS);
+
+    let mut field = HeightField::load(input, None)?;
+    let texel_size = TERRAIN_SIZE / field.size as f32;
+    let height_scale = MAX_HEIGHT / texel_size;
+    if let Some(iterations) = args.option("--erode")? {
+        let defaults = ErosionParams::default();
+        let params = ErosionParams {
+            iterations,
+            seed: args.option("--seed")?.unwrap_or(defaults.seed),
+            ..defaults
+        };
+        println!("Eroding {} with {} droplets", input, params.iterations);
+        erosion::erode_parallel(&mut field, height_scale, &params, &JobSystem::new());
+    }
+
+    let dir = Path::new(output);
+    fs::create_dir_all(dir)?;
+    let heightmap = dir.join("heightmap.png");
+    field.save(&heightmap)?;
+    println!("Saved heightmap to {}", heightmap.display());
+
+    let _context = HeadlessContext::new()?;
+    let baker = Baker::new(&field, height_scale);
+    let size = field.size as u32;
+    let normals = dir.join("normals.png");
+    let pixels = baker.normal_map()?;
+    image::save_buffer(&normals, &pixels, size, size, image::ColorType::Rgba8)?;
+    println!("Saved normal map to {}", normals.display());
+    let occlusion = dir.join("ao.png");
+    let pixels = baker.ambient_occlusion(ao_radius)?;
+    image::save_buffer(&occlusion, &pixels, size, size, image::ColorType::L8)?;
+    println!("Saved ambient occlusion to {}", occlusion.display());
+    Ok(())
+}
+
+fn ldraw_to_scene(args: &[String]) -> Result<()> {
+    let args = Args::parse(args, &[])?;
+    let (input, output) = args.input_output()?;
+
+    let types = catalog::load(catalog::CATALOG_PATH)?;
+    let import = ldraw::import(input, &types)?;
+    save::save_clipboard(output, &import.clipboard, &types)?;
+    println!(
+        "Saved {} bricks to {}, skipped {} parts",
+        import.clipboard.bricks.len(),
+        output,
+        import.skipped
+    );
+    Ok(())
+}
+
+/// Positional arguments plus `--name value` options
+struct Args<'a> {
+    positional: Vec<&'a str>,
+    options: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> Args<'a> {
+    fn parse(args: &'a [String], known_options: &[&str]) -> Result<Self> {
+        let mut positional = vec![];
+        let mut options = vec![];
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if arg.starts_with("--") {
+                if !known_options.contains(&arg.as_str()) {
+                    return Err(format!("Unknown option '{}'", arg).into());
+                }
+                let value = iter
+                    .next()
+                    .ok_or_else(|| format!("Option '{}' needs a value", arg))?;
+                options.push((arg.as_str(), value.as_str()));
+            } else {
+                positional.push(arg.as_str());
+            }
+        }
+        Ok(Args {
+            positional,
+            options,
+        })
+    }
+
+    fn input_output(&self) -> Result<(&'a str, &'a str)> {
+        match self.positional[..] {
+            [input, output] => Ok((input, output)),
+            _ => Err(format!("Expected an input and an output path\n\n{}", USAGE).into()),
+        }
+    }
+
+    fn option<T: std::str::FromStr>(&self, name: &str) -> Result<Option<T>> {
+        match self.options.iter().find(|(option, _)| *option == name) {
+            Some((_, value)) => value
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("Invalid value '{}' for {}", value, name).into()),
+            None => Ok(None),
+        }
+    }
+}
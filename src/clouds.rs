@@ -0,0 +1,175 @@
+use gl::types::*;
+use thiserror::Error;
+
+use crate::atmosphere::Atmosphere;
+use crate::opengl::bindings::{self, Sampler, TextureUnit};
+use crate::opengl::empty_vertex_array;
+use crate::opengl::shader::{Program, ShaderError};
+use crate::origin::WorldOrigin;
+use crate::profiler;
+
+const NOISE_SIZE: usize = 64;
+/// World units per noise repeat, must match NOISE_SCALE in clouds.frag
+const NOISE_TILE: f64 = 1200.0;
+
+#[derive(Debug, Error)]
+pub enum CloudsError {
+    #[error("Clouds shader error: {0}")]
+    Shader(#[from] ShaderError),
+}
+
+/// Draws the cloud layer described by `Atmosphere::clouds` as a full-screen pass.
+/// Must go after the opaque geometry so that only the sky gets covered.
+pub struct CloudRenderer {
+    shader: Program,
+    vao: GLuint,
+    noise: GLuint,
+}
+
+impl CloudRenderer {
+    pub fn new() -> Result<Self, CloudsError> {
+        let shader = Program::new()
+            .vertex_shader(shader_file!("clouds/clouds.vert"))?
+            .fragment_shader(shader_file!("clouds/clouds.frag"))?
+            .link()?;
+
+        let noise = {
+            let voxels = tileable_fbm(NOISE_SIZE);
+            let size = NOISE_SIZE as i32;
+            let mut texture: GLuint = 0;
+            unsafe {
+                gl::CreateTextures(gl::TEXTURE_3D, 1, &mut texture);
+                gl::TextureStorage3D(texture, 1, gl::R8, size, size, size);
+                gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+                gl::TextureSubImage3D(
+                    texture,
+                    0,
+                    0,
+                    0,
+                    0,
+                    size,
+                    size,
+                    size,
+                    gl::RED,
+                    gl::UNSIGNED_BYTE,
+                    voxels.as_ptr() as *const _,
+                );
+                gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+            }
+            texture
+        };
+
+        let vao = empty_vertex_array();
+
+        Ok(CloudRenderer { shader, vao, noise })
+    }
+
+    pub fn draw(
+        &self,
+        atmosphere: &Atmosphere,
+        origin: &WorldOrigin,
+        time: f32,
+    ) -> Result<(), CloudsError> {
+        let clouds = &atmosphere.clouds;
+        if !clouds.enabled {
+            return Ok(());
+        }
+
+        let (view_steps, light_steps) = clouds.quality.steps();
+        let light = atmosphere.light();
+        self.shader.set_used();
+        self.shader.set_i32("view_steps", view_steps)?;
+        self.shader.set_i32("light_steps", light_steps)?;
+        self.shader.set_f32("coverage", clouds.coverage)?;
+        self.shader.set_f32("density", clouds.density)?;
+        self.shader.set_f32("cloud_bottom", clouds.bottom)?;
+        self.shader
+            .set_f32("cloud_top", clouds.top.max(clouds.bottom + 1.0))?;
+        // The noise is sampled in absolute coordinates so the clouds stay put on a rebase
+        let wind_offset = clouds.wind * time + origin.wrapped_xz(NOISE_TILE);
+        self.shader.set_vec2("wind_offset", &wind_offset)?;
+        self.shader.set_vec3("light_direction", &light.direction)?;
+        self.shader.set_vec3("light_color", &light.color)?;
+        self.shader
+            .set_vec3("ambient_color", &(0.6 * atmosphere.sky_tint()))?;
+
+        unsafe {
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::ONE, gl::ONE_MINUS_SRC_ALPHA);
+            gl::DepthFunc(gl::LEQUAL);
+            gl::DepthMask(gl::FALSE);
+
+            bindings::bind_texture_sampled(TextureUnit::SOURCE, self.noise, Sampler::LinearRepeat);
+            gl::BindVertexArray(self.vao);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            profiler::count_draw_call();
+
+            gl::DepthMask(gl::TRUE);
+            gl::DepthFunc(gl::LESS);
+            gl::Disable(gl::BLEND);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for CloudRenderer {
+    fn drop(&mut self) {
+        bindings::delete_textures(&[self.noise]);
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.vao);
+        }
+    }
+}
+
+/// Value noise summed over a few octaves, wrapping around in all three dimensions
+fn tileable_fbm(size: usize) -> Vec<u8> {
+    const OCTAVES: usize = 4;
+
+    // Hash of integer lattice coordinates, wrapped to the octave's period
+    let lattice = |x: usize, y: usize, z: usize, period: usize| -> f32 {
+        let (x, y, z) = (x % period, y % period, z % period);
+        let mut h = (x as u32).wrapping_mul(73_856_093)
+            ^ (y as u32).wrapping_mul(19_349_663)
+            ^ (z as u32).wrapping_mul(83_492_791);
+        h = (h ^ (h >> 13)).wrapping_mul(1_274_126_177);
+        h ^= h >> 16;
+        (h & 0xffff) as f32 / 65535.0
+    };
+    let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+
+    let mut voxels = Vec::with_capacity(size * size * size);
+    for z in 0..size {
+        for y in 0..size {
+            for x in 0..size {
+                let mut value = 0.0;
+                let mut amplitude = 0.5;
+                let mut period = 4;
+                for _ in 0..OCTAVES {
+                    let scale = period as f32 / size as f32;
+                    let (fx, fy, fz) = (x as f32 * scale, y as f32 * scale, z as f32 * scale);
+                    let (x0, y0, z0) = (fx as usize, fy as usize, fz as usize);
+                    let (tx, ty, tz) = (smooth(fx.fract()), smooth(fy.fract()), smooth(fz.fract()));
+
+                    let corner = |dx, dy, dz| lattice(x0 + dx, y0 + dy, z0 + dz, period);
+                    let front = lerp(
+                        lerp(corner(0, 0, 0), corner(1, 0, 0), tx),
+                        lerp(corner(0, 1, 0), corner(1, 1, 0), tx),
+                        ty,
+                    );
+                    let back = lerp(
+                        lerp(corner(0, 0, 1), corner(1, 0, 1), tx),
+                        lerp(corner(0, 1, 1), corner(1, 1, 1), tx),
+                        ty,
+                    );
+                    value += amplitude * lerp(front, back, tz);
+
+                    amplitude *= 0.5;
+                    period *= 2;
+                }
+                voxels.push((value.clamp(0.0, 1.0) * 255.0) as u8);
+            }
+        }
+    }
+    voxels
+}
@@ -3,6 +3,8 @@ use std::fs;
 use glam::Vec3;
 use serde::{Deserialize, Serialize};
 
+use crate::keybindings::KeyBindings;
+use crate::opengl::DebugSeverity;
 use crate::Result;
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -11,6 +13,47 @@ pub struct Config {
     pub start_with_flat_terrain: bool,
     pub camera_position: Option<Vec3>,
     pub camera_direction: Option<Vec3>,
+    /// How many brick edits can be undone
+    #[serde(default = "default_undo_depth")]
+    pub undo_depth: usize,
+    #[serde(default = "default_bricks_path")]
+    pub bricks_path: String,
+    /// Directory with a file for each prefab
+    #[serde(default = "default_prefabs_path")]
+    pub prefabs_path: String,
+    /// Shape of the terrain brush, the last one picked
+    #[serde(default = "default_brush_path")]
+    pub brush_path: String,
+    /// Equirectangular .hdr or .exr sky used instead of the default skybox faces
+    #[serde(default)]
+    pub sky_panorama: Option<String>,
+    /// Directory of the project last saved or opened, opened again on start
+    #[serde(default)]
+    pub project_path: Option<String>,
+    #[serde(default)]
+    pub keybindings: KeyBindings,
+    /// Least severe OpenGL driver message printed in debug builds
+    #[serde(default)]
+    pub gl_debug_severity: DebugSeverity,
+    /// Abort on the first OpenGL error in debug builds, printing where it happened
+    #[serde(default)]
+    pub gl_break_on_error: bool,
+}
+
+fn default_brush_path() -> String {
+    "textures/brushes/mountain05.tga".to_owned()
+}
+
+fn default_prefabs_path() -> String {
+    "prefabs".to_owned()
+}
+
+fn default_bricks_path() -> String {
+    "bricks.json".to_owned()
+}
+
+fn default_undo_depth() -> usize {
+    100
 }
 
 impl Config {
@@ -23,6 +66,15 @@ impl Config {
                 start_with_flat_terrain: true,
                 camera_position: None,
                 camera_direction: None,
+                undo_depth: default_undo_depth(),
+                bricks_path: default_bricks_path(),
+                prefabs_path: default_prefabs_path(),
+                brush_path: default_brush_path(),
+                sky_panorama: None,
+                project_path: None,
+                keybindings: KeyBindings::default(),
+                gl_debug_severity: DebugSeverity::default(),
+                gl_break_on_error: false,
             }
         };
         Ok(config)
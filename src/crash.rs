@@ -0,0 +1,168 @@
+//! A report to attach to bug reports when the editor crashes. It has the panic and its
+//! backtrace, the GL driver, the open project and the last lines of the log, and goes
+//! into the crashes directory in the platform's local data directory, see
+//! `install_panic_hook`.
+//!
+//! The log is whatever went through `log!`, which prints to stderr as well.
+
+use std::backtrace::Backtrace;
+use std::collections::VecDeque;
+use std::fs;
+use std::panic;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard, TryLockError};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rfd::{MessageButtons, MessageDialog, MessageLevel};
+
+use crate::Result;
+
+const APP_DIR: &str = "game2";
+const CRASH_DIR: &str = "crashes";
+/// Lines of the log kept for the report
+const LOG_LINES: usize = 100;
+
+/// Prints the line to stderr and keeps it for the crash report
+macro_rules! log {
+    ($($arg:tt)*) => {
+        crate::crash::log_line(format!($($arg)*))
+    };
+}
+
+struct Diagnostics {
+    /// Vendor, renderer and version, once there's a context
+    driver: Option<String>,
+    project: Option<String>,
+    log: VecDeque<String>,
+}
+
+static DIAGNOSTICS: Mutex<Diagnostics> = Mutex::new(Diagnostics {
+    driver: None,
+    project: None,
+    log: VecDeque::new(),
+});
+
+/// None if the thread panicked while holding the lock, there's no waiting for it then
+fn diagnostics() -> Option<MutexGuard<'static, Diagnostics>> {
+    match DIAGNOSTICS.try_lock() {
+        Ok(guard) => Some(guard),
+        Err(TryLockError::Poisoned(poisoned)) => Some(poisoned.into_inner()),
+        Err(TryLockError::WouldBlock) => None,
+    }
+}
+
+pub fn log_line(line: String) {
+    eprintln!("{}", line);
+    if let Some(mut diagnostics) = diagnostics() {
+        if diagnostics.log.len() == LOG_LINES {
+            diagnostics.log.pop_front();
+        }
+        diagnostics.log.push_back(line);
+    }
+}
+
+/// Asked once the context is made, the hook may run on a thread without it
+pub fn set_driver(driver: String) {
+    if let Some(mut diagnostics) = diagnostics() {
+        diagnostics.driver = Some(driver);
+    }
+}
+
+pub fn set_project(dir: Option<&str>) {
+    if let Some(mut diagnostics) = diagnostics() {
+        diagnostics.project = dir.map(str::to_owned);
+    }
+}
+
+/// Writes a report for every panic after printing it as usual. If it's the main
+/// thread, which takes the editor down with it, a message box says where the report is.
+pub fn install_panic_hook() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        let path = write_report(&info.to_string());
+        if thread::current().name() == Some("main") {
+            show_message(path.as_ref());
+        }
+    }));
+}
+
+/// For the crashes that aren't panics, like GL errors in debug builds which abort
+pub fn write_report(reason: &str) -> Option<PathBuf> {
+    let result = crash_dir().and_then(|dir| {
+        fs::create_dir_all(&dir)?;
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        let path = dir.join(format!("crash-{}.txt", seconds));
+        fs::write(&path, report(reason))?;
+        Ok(path)
+    });
+    match result {
+        Ok(path) => {
+            eprintln!("Crash report saved to {}", path.display());
+            Some(path)
+        }
+        Err(err) => {
+            eprintln!("Failed to save the crash report: {}", err);
+            None
+        }
+    }
+}
+
+fn report(reason: &str) -> String {
+    let thread = thread::current();
+    let mut report = format!(
+        "{} {} ({}, {} {})\n\nThread: {}\n{}\n",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        if cfg!(debug_assertions) {
+            "debug"
+        } else {
+            "release"
+        },
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        thread.name().unwrap_or("unnamed"),
+        reason,
+    );
+    match diagnostics() {
+        Some(diagnostics) => {
+            report += &format!(
+                "\nDriver: {}\nProject: {}\n\nLast lines of the log:\n",
+                diagnostics.driver.as_deref().unwrap_or("unknown"),
+                diagnostics.project.as_deref().unwrap_or("none"),
+            );
+            for line in &diagnostics.log {
+                report += line;
+                report += "\n";
+            }
+        }
+        None => report += "\nThe log was being written when it crashed\n",
+    }
+    report += &format!("\nBacktrace:\n{}\n", Backtrace::force_capture());
+    report
+}
+
+fn show_message(path: Option<&PathBuf>) {
+    let description = match path {
+        Some(path) => format!(
+            "The editor has crashed. Please attach this file to the bug report:\n\n{}",
+            path.display()
+        ),
+        None => String::from("The editor has crashed, and the crash report couldn't be saved."),
+    };
+    MessageDialog::new()
+        .set_level(MessageLevel::Error)
+        .set_title("Мёртвый трилистник")
+        .set_description(description)
+        .set_buttons(MessageButtons::Ok)
+        .show();
+}
+
+/// Fails if the platform has no data directory
+fn crash_dir() -> Result<PathBuf> {
+    let dir = dirs::data_local_dir().ok_or("No data directory")?;
+    Ok(dir.join(APP_DIR).join(CRASH_DIR))
+}
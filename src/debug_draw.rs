@@ -0,0 +1,202 @@
+//! Immediate-mode debug shapes. Anything on the render thread can queue lines with
+//! the free functions here, and `DebugRenderer::flush` draws them all in one go once
+//! per frame, after which the queue starts empty again.
+
+use std::cell::RefCell;
+use std::f32::consts::TAU;
+use std::mem::size_of;
+
+use gl::types::*;
+use glam::{Mat4, Vec3, Vec4};
+use memoffset::offset_of;
+
+use crate::opengl::shader::Program;
+use crate::profiler;
+use crate::ray::AABB;
+use crate::utils::size_of_slice;
+use crate::Result;
+
+/// Segments in each circle of a sphere
+const SPHERE_SEGMENTS: usize = 24;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct DebugVertex {
+    position: Vec3,
+    /// Linear
+    color: Vec4,
+}
+
+thread_local! {
+    /// Pairs of vertices, one pair per line
+    static LINES: RefCell<Vec<DebugVertex>> = const { RefCell::new(vec![]) };
+}
+
+pub fn line(a: Vec3, b: Vec3, color: Vec4) {
+    LINES.with(|lines| {
+        let mut lines = lines.borrow_mut();
+        lines.push(DebugVertex { position: a, color });
+        lines.push(DebugVertex { position: b, color });
+    });
+}
+
+pub fn aabb(aabb: &AABB, color: Vec4) {
+    let corner = |i: usize| {
+        Vec3::new(
+            if i & 1 == 0 { aabb.min.x } else { aabb.max.x },
+            if i & 2 == 0 { aabb.min.y } else { aabb.max.y },
+            if i & 4 == 0 { aabb.min.z } else { aabb.max.z },
+        )
+    };
+    // Each edge connects two corners that differ in one bit
+    for i in 0..8 {
+        for bit in [1, 2, 4] {
+            if i & bit == 0 {
+                line(corner(i), corner(i | bit), color);
+            }
+        }
+    }
+}
+
+/// Three circles around the axes
+pub fn sphere(center: Vec3, radius: f32, color: Vec4) {
+    let point = |axis: usize, i: usize| {
+        let angle = i as f32 * TAU / SPHERE_SEGMENTS as f32;
+        let (sin, cos) = angle.sin_cos();
+        let offset = match axis {
+            0 => Vec3::new(0.0, cos, sin),
+            1 => Vec3::new(cos, 0.0, sin),
+            _ => Vec3::new(cos, sin, 0.0),
+        };
+        center + offset * radius
+    };
+    for axis in 0..3 {
+        for i in 0..SPHERE_SEGMENTS {
+            line(point(axis, i), point(axis, i + 1), color);
+        }
+    }
+}
+
+/// The basis of the transform, X red, Y green and Z blue, `size` long
+pub fn axis(transform: &Mat4, size: f32) {
+    let origin = transform.transform_point3(Vec3::ZERO);
+    let axes = [
+        (Vec3::X, Vec4::new(1.0, 0.1, 0.1, 1.0)),
+        (Vec3::Y, Vec4::new(0.1, 1.0, 0.1, 1.0)),
+        (Vec3::Z, Vec4::new(0.1, 0.3, 1.0, 1.0)),
+    ];
+    for (direction, color) in axes {
+        let direction = transform.transform_vector3(direction).normalize_or_zero();
+        line(origin, origin + direction * size, color);
+    }
+}
+
+/// Draws the queued shapes
+pub struct DebugRenderer {
+    shader: Program,
+    vao: GLuint,
+    vbo: GLuint,
+    /// In vertices
+    capacity: usize,
+}
+
+impl DebugRenderer {
+    pub fn new() -> Result<Self> {
+        let shader = Program::new()
+            .vertex_shader(shader_file!("debug/lines.vert"))?
+            .fragment_shader(shader_file!("debug/lines.frag"))?
+            .link()?;
+
+        let mut vao: GLuint = 0;
+        unsafe {
+            gl::CreateVertexArrays(1, &mut vao);
+
+            // Position
+            gl::VertexArrayAttribFormat(
+                vao,
+                0,
+                3,
+                gl::FLOAT,
+                gl::FALSE,
+                offset_of!(DebugVertex, position) as u32,
+            );
+
+            // Color
+            gl::VertexArrayAttribFormat(
+                vao,
+                1,
+                4,
+                gl::FLOAT,
+                gl::FALSE,
+                offset_of!(DebugVertex, color) as u32,
+            );
+
+            gl::EnableVertexArrayAttrib(vao, 0);
+            gl::EnableVertexArrayAttrib(vao, 1);
+            gl::VertexArrayAttribBinding(vao, 0, 0);
+            gl::VertexArrayAttribBinding(vao, 1, 0);
+        }
+
+        Ok(DebugRenderer {
+            shader,
+            vao,
+            vbo: 0,
+            capacity: 0,
+        })
+    }
+
+    /// Draws everything queued since the last flush into the current framebuffer,
+    /// depth tested against the scene
+    pub fn flush(&mut self) -> Result<()> {
+        let vertices = LINES.with(|lines| std::mem::take(&mut *lines.borrow_mut()));
+        if vertices.is_empty() {
+            return Ok(());
+        }
+
+        if vertices.len() > self.capacity {
+            self.capacity = vertices.len().next_power_of_two();
+            unsafe {
+                if self.vbo != 0 {
+                    gl::DeleteBuffers(1, &self.vbo);
+                }
+                gl::CreateBuffers(1, &mut self.vbo);
+                gl::NamedBufferStorage(
+                    self.vbo,
+                    (self.capacity * size_of::<DebugVertex>()) as isize,
+                    std::ptr::null(),
+                    gl::DYNAMIC_STORAGE_BIT,
+                );
+                gl::VertexArrayVertexBuffer(
+                    self.vao,
+                    0,
+                    self.vbo,
+                    0,
+                    size_of::<DebugVertex>() as i32,
+                );
+            }
+        }
+
+        self.shader.set_used();
+        unsafe {
+            gl::NamedBufferSubData(
+                self.vbo,
+                0,
+                size_of_slice(&vertices) as isize,
+                vertices.as_ptr() as *const _,
+            );
+            gl::BindVertexArray(self.vao);
+            gl::DrawArrays(gl::LINES, 0, vertices.len() as i32);
+            profiler::count_draw_call();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for DebugRenderer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.vbo);
+            gl::DeleteVertexArrays(1, &self.vao);
+        }
+    }
+}
@@ -0,0 +1,140 @@
+//! Alternative ways to render the scene for finding rendering problems, switched with
+//! the F-keys in the editor. They draw straight to the screen without post-processing.
+
+use gl::types::*;
+use glam::Vec3;
+
+use crate::keybindings::KeyAction;
+use crate::opengl::empty_vertex_array;
+use crate::opengl::shader::Program;
+use crate::profiler;
+use crate::Result;
+
+/// Overdraw counts from 0 to this get their own color, anything above gets the last one
+const OVERDRAW_LEVELS: i32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugView {
+    /// The normal view with post-processing
+    Lit,
+    Wireframe,
+    /// Terrain normals drawn as lines
+    Normals,
+    /// Terrain triangles colored by the patch they're tessellated from
+    TessPatches,
+    /// How many times each pixel is drawn, from black to red
+    Overdraw,
+}
+
+impl DebugView {
+    pub const ALL: [DebugView; 5] = [
+        DebugView::Lit,
+        DebugView::Wireframe,
+        DebugView::Normals,
+        DebugView::TessPatches,
+        DebugView::Overdraw,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DebugView::Lit => "Lit",
+            DebugView::Wireframe => "Wireframe",
+            DebugView::Normals => "Normals",
+            DebugView::TessPatches => "Tessellation patches",
+            DebugView::Overdraw => "Overdraw",
+        }
+    }
+
+    pub fn key_action(self) -> Option<KeyAction> {
+        match self {
+            DebugView::Lit => None,
+            DebugView::Wireframe => Some(KeyAction::WireframeView),
+            DebugView::Normals => Some(KeyAction::NormalsView),
+            DebugView::TessPatches => Some(KeyAction::TessPatchesView),
+            DebugView::Overdraw => Some(KeyAction::OverdrawView),
+        }
+    }
+
+    /// Pressing the key of the current view goes back to the lit one
+    pub fn toggled_by(self, action: KeyAction) -> Option<DebugView> {
+        let view = DebugView::ALL
+            .iter()
+            .copied()
+            .find(|view| view.key_action() == Some(action))?;
+        Some(if view == self { DebugView::Lit } else { view })
+    }
+}
+
+/// Counts the fragments drawn into every pixel in the stencil buffer and shows the
+/// counts as a heatmap
+pub struct OverdrawHeatmap {
+    shader: Program,
+    vao: GLuint,
+}
+
+impl OverdrawHeatmap {
+    pub fn new() -> Result<Self> {
+        let shader = Program::new()
+            .vertex_shader(shader_file!("post/fullscreen.vert"))?
+            .fragment_shader(shader_file!("debug/overdraw.frag"))?
+            .link()?;
+        let vao = empty_vertex_array();
+        Ok(OverdrawHeatmap { shader, vao })
+    }
+
+    /// Everything drawn until `end` is counted, whether it passes the depth test or not
+    pub fn begin(&self) {
+        unsafe {
+            gl::ClearStencil(0);
+            gl::Clear(gl::STENCIL_BUFFER_BIT);
+            gl::Enable(gl::STENCIL_TEST);
+            gl::StencilFunc(gl::ALWAYS, 0, 0xFF);
+            gl::StencilOp(gl::INCR, gl::INCR, gl::INCR);
+        }
+    }
+
+    /// Replaces the picture with the heatmap, one full-screen pass per count
+    pub fn end(&self) -> Result<()> {
+        self.shader.set_used();
+        unsafe {
+            gl::StencilOp(gl::KEEP, gl::KEEP, gl::KEEP);
+            gl::Disable(gl::DEPTH_TEST);
+            gl::BindVertexArray(self.vao);
+        }
+        for level in 0..=OVERDRAW_LEVELS {
+            let t = level as f32 / OVERDRAW_LEVELS as f32;
+            let color = if level == 0 {
+                Vec3::ZERO
+            } else if t < 0.5 {
+                Vec3::new(0.0, 0.0, 1.0).lerp(Vec3::new(0.0, 1.0, 0.0), t * 2.0)
+            } else {
+                Vec3::new(1.0, 1.0, 0.0).lerp(Vec3::new(1.0, 0.0, 0.0), t * 2.0 - 1.0)
+            };
+            self.shader.set_vec3("color", &color)?;
+            // The reference value is compared to the count in the buffer
+            let func = if level == OVERDRAW_LEVELS {
+                gl::LEQUAL
+            } else {
+                gl::EQUAL
+            };
+            unsafe {
+                gl::StencilFunc(func, level, 0xFF);
+                gl::DrawArrays(gl::TRIANGLES, 0, 3);
+                profiler::count_draw_call();
+            }
+        }
+        unsafe {
+            gl::Disable(gl::STENCIL_TEST);
+            gl::Enable(gl::DEPTH_TEST);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for OverdrawHeatmap {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.vao);
+        }
+    }
+}
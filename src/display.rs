@@ -0,0 +1,133 @@
+//! Which monitor the editor is on, how big the window is and how it goes fullscreen.
+//! All of it is changed on the window, the GL context stays as it is. Vsync is the
+//! only display setting the context is made with, so it waits for a restart.
+
+use glutin::dpi::{PhysicalPosition, PhysicalSize};
+use glutin::monitor::{MonitorHandle, VideoMode};
+use glutin::window::Fullscreen;
+use serde::{Deserialize, Serialize};
+
+/// Left around the window when it's fitted to the monitor, for the taskbar and such
+const WINDOW_MARGIN: u32 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FullscreenMode {
+    /// A window covering the monitor, quick to switch in and out of
+    Borderless,
+    /// Switches the monitor to the resolution
+    Exclusive,
+}
+
+impl FullscreenMode {
+    pub const ALL: [FullscreenMode; 2] = [FullscreenMode::Borderless, FullscreenMode::Exclusive];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FullscreenMode::Borderless => "Borderless",
+            FullscreenMode::Exclusive => "Exclusive",
+        }
+    }
+}
+
+/// Kept in `Settings`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct DisplaySettings {
+    /// Waits for the monitor between frames. The context is made with it, so it only
+    /// changes when the editor starts.
+    pub vsync: bool,
+    /// Frames per second at most, None to render as fast as possible
+    pub max_fps: Option<u32>,
+    /// Name of the monitor, None for the one the window is on, or the primary one at
+    /// startup. The same goes for a monitor which isn't connected.
+    pub monitor: Option<String>,
+    /// What Alt+Enter switches to
+    pub fullscreen_mode: FullscreenMode,
+    /// Of the window, or of the monitor in exclusive fullscreen, in pixels. None fits
+    /// the window to the monitor and keeps the monitor at its resolution.
+    pub resolution: Option<[u32; 2]>,
+}
+
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        DisplaySettings {
+            vsync: true,
+            max_fps: None,
+            monitor: None,
+            fullscreen_mode: FullscreenMode::Borderless,
+            resolution: None,
+        }
+    }
+}
+
+/// The monitor in the settings if it's connected, `fallback` otherwise
+pub fn find_monitor(
+    settings: &DisplaySettings,
+    mut monitors: impl Iterator<Item = MonitorHandle>,
+    fallback: Option<MonitorHandle>,
+) -> Option<MonitorHandle> {
+    settings
+        .monitor
+        .as_deref()
+        .and_then(|name| monitors.find(|monitor| monitor.name().as_deref() == Some(name)))
+        .or(fallback)
+}
+
+/// Borderless if the monitor can't do the resolution exclusively
+pub fn fullscreen(settings: &DisplaySettings, monitor: Option<MonitorHandle>) -> Fullscreen {
+    match (settings.fullscreen_mode, monitor) {
+        (FullscreenMode::Exclusive, Some(monitor)) => {
+            match video_mode(&monitor, settings.resolution) {
+                Some(mode) => Fullscreen::Exclusive(mode),
+                None => Fullscreen::Borderless(Some(monitor)),
+            }
+        }
+        (_, monitor) => Fullscreen::Borderless(monitor),
+    }
+}
+
+/// The one with the resolution, or the one the monitor is in without it, at the
+/// highest refresh rate
+fn video_mode(monitor: &MonitorHandle, resolution: Option<[u32; 2]>) -> Option<VideoMode> {
+    let size = resolution.map_or(monitor.size(), |[width, height]| {
+        PhysicalSize::new(width, height)
+    });
+    monitor
+        .video_modes()
+        .filter(|mode| mode.size() == size)
+        .max_by_key(|mode| (mode.refresh_rate(), mode.bit_depth()))
+}
+
+/// Resolutions of the monitor's video modes, largest first
+pub fn resolutions(monitor: &MonitorHandle) -> Vec<[u32; 2]> {
+    let mut resolutions: Vec<[u32; 2]> = monitor
+        .video_modes()
+        .map(|mode| [mode.size().width, mode.size().height])
+        .collect();
+    resolutions.sort_unstable_by(|a, b| b.cmp(a));
+    resolutions.dedup();
+    resolutions
+}
+
+/// Where the window goes when it isn't fullscreen: centered on the monitor, with the
+/// resolution as its size
+pub fn window_rect(
+    settings: &DisplaySettings,
+    monitor: &MonitorHandle,
+) -> (PhysicalPosition<i32>, PhysicalSize<u32>) {
+    let area = monitor.size();
+    let size = match settings.resolution {
+        Some([width, height]) => PhysicalSize::new(width, height),
+        None => PhysicalSize::new(
+            area.width.saturating_sub(WINDOW_MARGIN),
+            area.height.saturating_sub(WINDOW_MARGIN),
+        ),
+    };
+    let offset = |area: u32, size: u32| (area.saturating_sub(size) / 2) as i32;
+    let origin = monitor.position();
+    let position = PhysicalPosition::new(
+        origin.x + offset(area.width, size.width),
+        origin.y + offset(area.height, size.height),
+    );
+    (position, size)
+}
@@ -0,0 +1,148 @@
+use glam::Vec3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl Axis {
+    pub const ALL: [Axis; 3] = [Axis::X, Axis::Y, Axis::Z];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Axis::X => "X",
+            Axis::Y => "Y",
+            Axis::Z => "Z",
+        }
+    }
+
+    fn get(&self, v: Vec3) -> f32 {
+        match self {
+            Axis::X => v.x,
+            Axis::Y => v.y,
+            Axis::Z => v.z,
+        }
+    }
+
+    fn set(&self, v: &mut Vec3, value: f32) {
+        match self {
+            Axis::X => v.x = value,
+            Axis::Y => v.y = value,
+            Axis::Z => v.z = value,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlignOp {
+    /// Make the given coordinate equal to that of the first selected object
+    Axis(Axis),
+    /// Put objects onto the terrain surface
+    Terrain,
+    /// Snap the horizontal position to the editor grid
+    Grid,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DistributeOp {
+    /// Even spacing between the extreme objects along an axis
+    Axis(Axis),
+    /// Even spacing along a Catmull-Rom spline through the objects in selection order
+    Spline,
+}
+
+/// The first position is the anchor, the rest are aligned to it
+pub fn align_to_axis(positions: &mut [Vec3], axis: Axis) {
+    if let Some(&anchor) = positions.first() {
+        let value = axis.get(anchor);
+        for pos in positions.iter_mut() {
+            axis.set(pos, value);
+        }
+    }
+}
+
+/// Snaps X and Z to the grid, Y is left alone
+pub fn align_to_grid(positions: &mut [Vec3], grid_size: f32) {
+    debug_assert!(grid_size > 0.0);
+    for pos in positions.iter_mut() {
+        pos.x = (pos.x / grid_size).round() * grid_size;
+        pos.z = (pos.z / grid_size).round() * grid_size;
+    }
+}
+
+/// Keeps the two extreme objects in place and spaces the rest evenly between them,
+/// preserving their order along the axis
+pub fn distribute_along_axis(positions: &mut [Vec3], axis: Axis) {
+    let count = positions.len();
+    if count < 3 {
+        return;
+    }
+
+    let mut order: Vec<usize> = (0..count).collect();
+    order.sort_by(|&a, &b| axis.get(positions[a]).total_cmp(&axis.get(positions[b])));
+
+    let min = axis.get(positions[order[0]]);
+    let max = axis.get(positions[order[count - 1]]);
+    let step = (max - min) / (count - 1) as f32;
+    for (i, &index) in order.iter().enumerate() {
+        axis.set(&mut positions[index], min + step * i as f32);
+    }
+}
+
+/// Uses the positions (in selection order) as control points of a Catmull-Rom spline
+/// and moves the objects so that they're evenly spaced along it by arc length
+pub fn distribute_along_spline(positions: &mut [Vec3]) {
+    const SAMPLES_PER_SEGMENT: usize = 32;
+
+    let count = positions.len();
+    if count < 3 {
+        return;
+    }
+
+    // Sample the spline and accumulate its length
+    let control_points = positions.to_vec();
+    let mut samples = vec![control_points[0]];
+    let mut lengths = vec![0.0];
+    for segment in 0..count - 1 {
+        let p0 = control_points[segment.saturating_sub(1)];
+        let p1 = control_points[segment];
+        let p2 = control_points[segment + 1];
+        let p3 = control_points[(segment + 2).min(count - 1)];
+        for step in 1..=SAMPLES_PER_SEGMENT {
+            let t = step as f32 / SAMPLES_PER_SEGMENT as f32;
+            let point = catmull_rom(p0, p1, p2, p3, t);
+            let length = lengths.last().unwrap() + point.distance(*samples.last().unwrap());
+            samples.push(point);
+            lengths.push(length);
+        }
+    }
+
+    // Walk the samples placing objects at equal distances
+    let total_length = *lengths.last().unwrap();
+    let spacing = total_length / (count - 1) as f32;
+    let mut sample = 0;
+    for (i, pos) in positions.iter_mut().enumerate() {
+        let target = spacing * i as f32;
+        while sample < samples.len() - 2 && lengths[sample + 1] < target {
+            sample += 1;
+        }
+        let segment_length = lengths[sample + 1] - lengths[sample];
+        let t = if segment_length > 0.0 {
+            ((target - lengths[sample]) / segment_length).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        *pos = samples[sample].lerp(samples[sample + 1], t);
+    }
+}
+
+fn catmull_rom(p0: Vec3, p1: Vec3, p2: Vec3, p3: Vec3, t: f32) -> Vec3 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    0.5 * ((2.0 * p1)
+        + (p2 - p0) * t
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+        + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3)
+}
@@ -0,0 +1,312 @@
+//! Browser for the images, brushes and models on disk. Thumbnails are decoded a few
+//! per frame so that opening the browser doesn't stall the editor.
+
+use std::fs;
+use std::path::Path;
+
+use egui::{CtxRef, Id, Sense, TextureId, Ui};
+use gl::types::*;
+use glam::Vec2;
+
+use crate::editor::gui::Action;
+use crate::editor::EditorState;
+use crate::opengl::bindings;
+use crate::terrain::BRUSHES_DIR;
+
+const TEXTURES_DIR: &str = "textures";
+const MODELS_DIR: &str = "models";
+const THUMBNAIL_SIZE: u32 = 64;
+/// Images decoded per frame while there are thumbnails missing
+const THUMBNAILS_PER_FRAME: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssetKind {
+    /// Shapes for the terrain brush
+    Brush,
+    /// Textures for the terrain layers
+    Image,
+    Model,
+}
+
+impl AssetKind {
+    pub const ALL: [AssetKind; 3] = [AssetKind::Brush, AssetKind::Image, AssetKind::Model];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AssetKind::Brush => "Brushes",
+            AssetKind::Image => "Images",
+            AssetKind::Model => "Models",
+        }
+    }
+}
+
+struct Asset {
+    path: String,
+    name: String,
+    kind: AssetKind,
+    /// GL texture, 0 until it's been decoded or if it can't be
+    thumbnail: GLuint,
+    /// Tried to decode the thumbnail already
+    decoded: bool,
+}
+
+#[derive(Default)]
+pub struct AssetBrowser {
+    assets: Vec<Asset>,
+    scanned: bool,
+    kind: Option<AssetKind>,
+    /// Index into `assets` of the one being dragged out of the browser
+    dragged: Option<usize>,
+}
+
+impl AssetBrowser {
+    /// Looks for assets again, e.g. after adding files
+    pub fn rescan(&mut self) {
+        self.delete_thumbnails();
+        self.assets.clear();
+        let mut paths = vec![];
+        find_files(Path::new(TEXTURES_DIR), &mut paths);
+        find_files(Path::new(MODELS_DIR), &mut paths);
+        paths.sort();
+        for path in paths {
+            let kind = match asset_kind(&path) {
+                Some(kind) => kind,
+                None => continue,
+            };
+            let name = Path::new(&path)
+                .file_stem()
+                .map_or(path.clone(), |stem| stem.to_string_lossy().into_owned());
+            self.assets.push(Asset {
+                path,
+                name,
+                kind,
+                thumbnail: 0,
+                // Models have no pictures to show
+                decoded: kind == AssetKind::Model,
+            });
+        }
+        self.scanned = true;
+    }
+
+    /// Clicking a brush uses it, clicking an image or a model makes it the one the
+    /// import buttons use. Models and brushes can also be dragged into the viewport.
+    pub fn show(
+        &mut self,
+        ctx: &CtxRef,
+        editor_state: &mut EditorState,
+        actions: &mut Vec<Action>,
+    ) {
+        if !editor_state.show_assets {
+            self.dragged = None;
+            return;
+        }
+        if !self.scanned {
+            self.rescan();
+        }
+        self.decode_thumbnails();
+
+        let mut open = true;
+        egui::Window::new("Assets")
+            .open(&mut open)
+            .default_width(360.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.selectable_value(&mut self.kind, None, "All");
+                    for kind in AssetKind::ALL {
+                        ui.selectable_value(&mut self.kind, Some(kind), kind.name());
+                    }
+                    if ui.button("Rescan").clicked() {
+                        self.rescan();
+                    }
+                });
+                ui.separator();
+                egui::ScrollArea::vertical()
+                    .max_height(300.0)
+                    .show(ui, |ui| {
+                        ui.horizontal_wrapped(|ui| {
+                            for index in 0..self.assets.len() {
+                                if self
+                                    .kind
+                                    .map_or(true, |kind| kind == self.assets[index].kind)
+                                {
+                                    self.tile(ui, index, editor_state, actions);
+                                }
+                            }
+                        });
+                    });
+            });
+        editor_state.show_assets = open;
+
+        self.drag(ctx, actions);
+    }
+
+    fn tile(
+        &mut self,
+        ui: &mut Ui,
+        index: usize,
+        editor_state: &mut EditorState,
+        actions: &mut Vec<Action>,
+    ) {
+        let asset = &self.assets[index];
+        let size = egui::Vec2::splat(THUMBNAIL_SIZE as f32);
+        let selected = match asset.kind {
+            AssetKind::Brush => false,
+            AssetKind::Image => editor_state.texture_import_path == asset.path,
+            AssetKind::Model => editor_state.model_import_path == asset.path,
+        };
+        let response = if asset.thumbnail != 0 {
+            ui.add(
+                egui::ImageButton::new(TextureId::User(asset.thumbnail as u64), size)
+                    .selected(selected)
+                    .sense(Sense::click_and_drag()),
+            )
+        } else {
+            let mut button = egui::Button::new(&asset.name)
+                .wrap(true)
+                .sense(Sense::click_and_drag());
+            if selected {
+                button = button.fill(ui.visuals().selection.bg_fill);
+            }
+            ui.add_sized(size, button)
+        };
+        let response = response.on_hover_text(&asset.path);
+
+        if response.clicked() {
+            match asset.kind {
+                AssetKind::Brush => actions.push(Action::LoadBrush {
+                    path: asset.path.clone(),
+                }),
+                AssetKind::Image => editor_state.texture_import_path = asset.path.clone(),
+                AssetKind::Model => editor_state.model_import_path = asset.path.clone(),
+            }
+        }
+        if response.double_clicked() && asset.kind == AssetKind::Model {
+            actions.push(Action::AddObject {
+                path: asset.path.clone(),
+            });
+        }
+        if response.drag_started() && asset.kind != AssetKind::Image {
+            self.dragged = Some(index);
+        }
+    }
+
+    /// Shows what's being dragged and drops it when the button is released
+    fn drag(&mut self, ctx: &CtxRef, actions: &mut Vec<Action>) {
+        let asset = match self.dragged.and_then(|index| self.assets.get(index)) {
+            Some(asset) => asset,
+            None => return,
+        };
+        egui::show_tooltip_at_pointer(ctx, Id::new("dragged_asset"), |ui| {
+            ui.label(&asset.name);
+        });
+
+        let input = ctx.input();
+        if !input.pointer.any_released() {
+            return;
+        }
+        let pointer = input.pointer.interact_pos();
+        drop(input);
+        // Only into the viewport, not onto another window
+        if let Some(pointer) = pointer.filter(|_| !ctx.is_pointer_over_area()) {
+            let path = asset.path.clone();
+            match asset.kind {
+                AssetKind::Brush => actions.push(Action::LoadBrush { path }),
+                AssetKind::Model => actions.push(Action::DropObject {
+                    path,
+                    pointer: Vec2::new(pointer.x, pointer.y),
+                }),
+                AssetKind::Image => {}
+            }
+        }
+        self.dragged = None;
+    }
+
+    fn decode_thumbnails(&mut self) {
+        for asset in self
+            .assets
+            .iter_mut()
+            .filter(|asset| !asset.decoded)
+            .take(THUMBNAILS_PER_FRAME)
+        {
+            asset.decoded = true;
+            match image::open(&asset.path) {
+                Ok(image) => asset.thumbnail = upload_thumbnail(&image),
+                Err(err) => log!("Failed to load {}: {}", asset.path, err),
+            }
+        }
+    }
+
+    fn delete_thumbnails(&mut self) {
+        for asset in &mut self.assets {
+            if asset.thumbnail != 0 {
+                bindings::delete_textures(&[asset.thumbnail]);
+                asset.thumbnail = 0;
+            }
+        }
+    }
+}
+
+impl Drop for AssetBrowser {
+    fn drop(&mut self) {
+        self.delete_thumbnails();
+    }
+}
+
+/// Paths of all the files under the directory, with '/' separators
+fn find_files(dir: &Path, paths: &mut Vec<String>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for path in entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+    {
+        if path.is_dir() {
+            find_files(&path, paths);
+        } else {
+            paths.push(path.to_string_lossy().replace('\\', "/"));
+        }
+    }
+}
+
+fn asset_kind(path: &str) -> Option<AssetKind> {
+    let extension = Path::new(path)
+        .extension()?
+        .to_string_lossy()
+        .to_lowercase();
+    match extension.as_str() {
+        "png" | "tga" if path.starts_with(BRUSHES_DIR) => Some(AssetKind::Brush),
+        "png" | "tga" | "jpg" | "jpeg" | "bmp" => Some(AssetKind::Image),
+        // Not .bin or the textures next to the glTF files
+        "gltf" | "glb" | "obj" => Some(AssetKind::Model),
+        _ => None,
+    }
+}
+
+fn upload_thumbnail(image: &image::DynamicImage) -> GLuint {
+    let thumbnail = image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).to_rgba8();
+    let mut texture: GLuint = 0;
+    unsafe {
+        gl::CreateTextures(gl::TEXTURE_2D, 1, &mut texture);
+        gl::TextureStorage2D(
+            texture,
+            1,
+            gl::SRGB8_ALPHA8,
+            thumbnail.width() as GLint,
+            thumbnail.height() as GLint,
+        );
+        gl::TextureSubImage2D(
+            texture,
+            0,
+            0,
+            0,
+            thumbnail.width() as GLint,
+            thumbnail.height() as GLint,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            thumbnail.as_raw().as_ptr() as *const _,
+        );
+    }
+    texture
+}
@@ -0,0 +1,229 @@
+use glam::Vec3;
+
+use crate::editor::align::{AlignOp, Axis, DistributeOp};
+use crate::editor::gui::Action;
+
+/// Makes an action out of the arguments typed in the console, or says what's wrong
+/// with them
+pub type Handler = fn(&[&str]) -> Result<Action, String>;
+
+/// A named editor command which produces an action when run.
+/// Shared by the command palette and the console.
+pub struct Command {
+    pub name: String,
+    pub description: String,
+    /// Arguments shown by `help` in the console, empty if there aren't any
+    pub usage: String,
+    run: Run,
+}
+
+enum Run {
+    Action(Action),
+    Handler(Handler),
+}
+
+impl Command {
+    /// Commands with arguments can only be run from the console
+    pub fn takes_args(&self) -> bool {
+        matches!(self.run, Run::Handler(_))
+    }
+
+    pub fn run(&self, args: &[&str]) -> Result<Action, String> {
+        match &self.run {
+            Run::Action(_) if !args.is_empty() => {
+                Err(format!("'{}' doesn't take arguments", self.name))
+            }
+            Run::Action(action) => Ok(action.clone()),
+            Run::Handler(handler) => handler(args),
+        }
+    }
+}
+
+/// Values that can be changed with `set` in the console
+pub const VARIABLES: [&str; 7] = [
+    "tess_level",
+    "max_height",
+    "brush_size",
+    "brush_strength",
+    "hour",
+    "grid_size",
+    "camera_speed",
+];
+
+#[derive(Default)]
+pub struct CommandRegistry {
+    commands: Vec<Command>,
+}
+
+impl CommandRegistry {
+    /// All the commands the editor knows about out of the box
+    pub fn with_default_commands() -> Self {
+        let mut registry = CommandRegistry::default();
+
+        registry.register("save", "Save terrain and bricks", Action::SaveAll);
+        registry.register("terrain.save", "Save terrain", Action::SaveTerrain);
+        registry.register("camera.save", "Save camera position", Action::SaveCamera);
+        registry.register("project.save", "Save project", Action::SaveProject);
+        registry.register("project.open", "Open project", Action::OpenProject);
+        registry.register(
+            "camera.bookmark",
+            "Bookmark the camera",
+            Action::AddBookmark,
+        );
+        registry.register(
+            "window.fullscreen",
+            "Toggle fullscreen (Alt+Enter)",
+            Action::ToggleFullscreen,
+        );
+        registry.register("quit", "Quit", Action::Quit);
+
+        for axis in Axis::ALL {
+            let name = axis.name().to_lowercase();
+            registry.register(
+                &format!("align.{}", name),
+                &format!("Align selection along {}", axis.name()),
+                Action::Align(AlignOp::Axis(axis)),
+            );
+            registry.register(
+                &format!("distribute.{}", name),
+                &format!("Distribute selection along {}", axis.name()),
+                Action::Distribute(DistributeOp::Axis(axis)),
+            );
+        }
+        registry.register(
+            "align.terrain",
+            "Align selection to terrain surface",
+            Action::Align(AlignOp::Terrain),
+        );
+        registry.register(
+            "align.grid",
+            "Align selection to grid",
+            Action::Align(AlignOp::Grid),
+        );
+        registry.register(
+            "distribute.spline",
+            "Distribute selection along spline",
+            Action::Distribute(DistributeOp::Spline),
+        );
+
+        registry.register(
+            "bricks.duplicate",
+            "Duplicate selected bricks",
+            Action::DuplicateBricks,
+        );
+        registry.register(
+            "bricks.delete",
+            "Delete selected bricks",
+            Action::DeleteBricks,
+        );
+        registry.register("bricks.paint", "Paint selected bricks", Action::PaintBricks);
+        registry.register("bricks.undo", "Undo brick edit", Action::UndoBricks);
+        registry.register("bricks.redo", "Redo brick edit", Action::RedoBricks);
+
+        // Settings toggles
+        registry.register("toggle.fog", "Toggle fog", Action::ToggleFog);
+        registry.register(
+            "toggle.day_night",
+            "Pause/resume day-night cycle",
+            Action::ToggleDayNightCycle,
+        );
+
+        // Console only
+        registry.register_handler("set", "Change a setting", "<name> <value>", set);
+        registry.register_handler(
+            "export",
+            "Save the heightmap to a file",
+            "heightmap <path>",
+            export,
+        );
+        registry.register_handler(
+            "teleport",
+            "Move the camera to world coordinates",
+            "<x> <y> <z>",
+            teleport,
+        );
+
+        registry
+    }
+
+    pub fn register(&mut self, name: &str, description: &str, action: Action) {
+        self.add(name, description, "", Run::Action(action));
+    }
+
+    /// Registers a command taking arguments, which is only available in the console
+    pub fn register_handler(
+        &mut self,
+        name: &str,
+        description: &str,
+        usage: &str,
+        handler: Handler,
+    ) {
+        self.add(name, description, usage, Run::Handler(handler));
+    }
+
+    fn add(&mut self, name: &str, description: &str, usage: &str, run: Run) {
+        debug_assert!(
+            self.get(name).is_none(),
+            "Command '{}' is already registered",
+            name
+        );
+        self.commands.push(Command {
+            name: name.to_owned(),
+            description: description.to_owned(),
+            usage: usage.to_owned(),
+            run,
+        });
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Command> {
+        self.commands.iter().find(|command| command.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Command> {
+        self.commands.iter()
+    }
+}
+
+fn set(args: &[&str]) -> Result<Action, String> {
+    let (name, value) = match args {
+        [name, value] => (*name, *value),
+        _ => return Err(String::from("Usage: set <name> <value>")),
+    };
+    if !VARIABLES.contains(&name) {
+        return Err(format!(
+            "Unknown setting '{}', try one of: {}",
+            name,
+            VARIABLES.join(", ")
+        ));
+    }
+    let value = parse_number(value)?;
+    Ok(Action::SetVariable {
+        name: name.to_owned(),
+        value,
+    })
+}
+
+fn export(args: &[&str]) -> Result<Action, String> {
+    match args {
+        ["heightmap", path] => Ok(Action::ExportHeightmap {
+            path: (*path).to_owned(),
+        }),
+        _ => Err(String::from("Usage: export heightmap <path>")),
+    }
+}
+
+fn teleport(args: &[&str]) -> Result<Action, String> {
+    match args {
+        [x, y, z] => Ok(Action::Teleport(Vec3::new(
+            parse_number(x)?,
+            parse_number(y)?,
+            parse_number(z)?,
+        ))),
+        _ => Err(String::from("Usage: teleport <x> <y> <z>")),
+    }
+}
+
+fn parse_number(text: &str) -> Result<f32, String> {
+    text.parse()
+        .map_err(|_| format!("'{}' isn't a number", text))
+}
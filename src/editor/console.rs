@@ -0,0 +1,148 @@
+use egui::{Align2, CtxRef, Event, Key};
+
+use crate::editor::commands::CommandRegistry;
+use crate::editor::gui::Action;
+
+const MAX_LINES: usize = 200;
+
+/// Drop-down console for running commands with arguments, e.g. `teleport 0 100 0`.
+/// Opened and closed with the backtick key.
+#[derive(Default)]
+pub struct Console {
+    open: bool,
+    input: String,
+    /// Commands and their output
+    lines: Vec<String>,
+    /// Commands run before, the last one at the end
+    history: Vec<String>,
+    /// Position in `history` while going through it with the arrows
+    history_index: Option<usize>,
+}
+
+impl Console {
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+        self.input.clear();
+        self.history_index = None;
+    }
+
+    pub fn print(&mut self, line: impl Into<String>) {
+        self.lines.push(line.into());
+        if self.lines.len() > MAX_LINES {
+            self.lines.remove(0);
+        }
+    }
+
+    pub fn show(&mut self, ctx: &CtxRef, commands: &CommandRegistry, actions: &mut Vec<Action>) {
+        let input = ctx.input();
+        // Not while typing somewhere else, but the console's own text box can close it
+        let backtick = input
+            .events
+            .iter()
+            .any(|event| matches!(event, Event::Text(text) if text == "`"));
+        if backtick && (self.open || !ctx.wants_keyboard_input()) {
+            drop(input);
+            self.toggle();
+            return;
+        }
+        if !self.open {
+            return;
+        }
+        if input.key_pressed(Key::Escape) {
+            drop(input);
+            self.toggle();
+            return;
+        }
+        let submitted = input.key_pressed(Key::Enter);
+        if input.key_pressed(Key::ArrowUp) && !self.history.is_empty() {
+            let index = match self.history_index {
+                Some(index) => index.saturating_sub(1),
+                None => self.history.len() - 1,
+            };
+            self.history_index = Some(index);
+            self.input = self.history[index].clone();
+        }
+        if input.key_pressed(Key::ArrowDown) {
+            if let Some(index) = self.history_index {
+                if index + 1 < self.history.len() {
+                    self.history_index = Some(index + 1);
+                    self.input = self.history[index + 1].clone();
+                } else {
+                    self.history_index = None;
+                    self.input.clear();
+                }
+            }
+        }
+        let screen_width = input.screen_rect.width();
+        drop(input);
+
+        let lines = &self.lines;
+        let text = &mut self.input;
+        egui::Window::new("Console")
+            .anchor(Align2::CENTER_TOP, egui::Vec2::ZERO)
+            .collapsible(false)
+            .resizable(false)
+            .title_bar(false)
+            .fixed_size(egui::Vec2::new(screen_width * 0.8, 300.0))
+            .show(ctx, |ui| {
+                egui::ScrollArea::vertical()
+                    .max_height(260.0)
+                    .stick_to_bottom()
+                    .show(ui, |ui| {
+                        for line in lines {
+                            ui.monospace(line);
+                        }
+                    });
+                let response = ui.add(
+                    egui::TextEdit::singleline(text)
+                        .hint_text("Type 'help' for the list of commands")
+                        .code_editor()
+                        .desired_width(f32::INFINITY)
+                        .lock_focus(true),
+                );
+                response.request_focus();
+            });
+
+        if submitted {
+            let line = std::mem::take(&mut self.input);
+            self.run(line.trim(), commands, actions);
+        }
+    }
+
+    fn run(&mut self, line: &str, commands: &CommandRegistry, actions: &mut Vec<Action>) {
+        if line.is_empty() {
+            return;
+        }
+        self.print(format!("> {}", line));
+        if self.history.last().map(String::as_str) != Some(line) {
+            self.history.push(line.to_owned());
+        }
+        self.history_index = None;
+
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        let args: Vec<&str> = words.collect();
+        if name == "help" {
+            let help: Vec<String> = commands
+                .iter()
+                .map(|command| {
+                    format!(
+                        "{} {}    {}",
+                        command.name, command.usage, command.description
+                    )
+                })
+                .collect();
+            for text in help {
+                self.print(text);
+            }
+            return;
+        }
+        match commands.get(name) {
+            Some(command) => match command.run(&args) {
+                Ok(action) => actions.push(action),
+                Err(err) => self.print(err),
+            },
+            None => self.print(format!("Unknown command '{}'", name)),
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use rfd::FileDialog;
+
+/// Kinds of files the editor opens and saves, each with its own filter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    Heightmap,
+    /// Brush shapes and terrain layer maps
+    Image,
+    Model,
+    Ldraw,
+    Bricks,
+}
+
+impl FileKind {
+    fn filter(self) -> (&'static str, &'static [&'static str]) {
+        match self {
+            FileKind::Heightmap => ("Heightmap", &["png"]),
+            FileKind::Image => ("Image", &["png", "tga", "jpg", "jpeg", "bmp"]),
+            FileKind::Model => ("Model", &["gltf", "glb", "obj"]),
+            FileKind::Ldraw => ("LDraw model", &["ldr", "mpd"]),
+            FileKind::Bricks => ("Bricks", &["json"]),
+        }
+    }
+}
+
+/// Asks for a file to open, starting next to `current`. Blocks until the dialog is closed
+/// and returns None if it was cancelled.
+pub fn open_file(kind: FileKind, current: &str) -> Option<String> {
+    dialog(kind, current).pick_file().map(to_project_path)
+}
+
+/// Asks where to save a file, suggesting `current`
+pub fn save_file(kind: FileKind, current: &str) -> Option<String> {
+    let mut dialog = dialog(kind, current);
+    if let Some(name) = Path::new(current).file_name() {
+        dialog = dialog.set_file_name(name.to_string_lossy());
+    }
+    dialog.save_file().map(to_project_path)
+}
+
+/// Asks for a directory, starting at `current` or next to it. Used for projects, so
+/// it's also how a new one is made.
+pub fn pick_folder(current: &str) -> Option<String> {
+    let mut dialog = FileDialog::new();
+    if let Some(dir) = start_dir(current) {
+        dialog = dialog.set_directory(dir);
+    }
+    dialog.pick_folder().map(to_project_path)
+}
+
+fn dialog(kind: FileKind, current: &str) -> FileDialog {
+    let (name, extensions) = kind.filter();
+    let mut dialog = FileDialog::new().add_filter(name, extensions);
+    if let Some(dir) = start_dir(current) {
+        dialog = dialog.set_directory(dir);
+    }
+    dialog
+}
+
+/// The directory of the current path if it exists, or the current path itself if it's
+/// a directory like "textures/"
+fn start_dir(current: &str) -> Option<PathBuf> {
+    let path = Path::new(current);
+    let dir = if path.is_dir() { path } else { path.parent()? };
+    env::current_dir().ok().map(|cwd| cwd.join(dir))
+}
+
+/// Paths inside the working directory are kept relative, like the ones typed in,
+/// so that config.json doesn't depend on where the repo is
+fn to_project_path(path: PathBuf) -> String {
+    let path = match env::current_dir() {
+        Ok(cwd) => path
+            .strip_prefix(&cwd)
+            .map(Path::to_path_buf)
+            .unwrap_or(path),
+        Err(_) => path,
+    };
+    path.to_string_lossy().replace('\\', "/")
+}
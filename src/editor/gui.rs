@@ -1,80 +1,238 @@
 use std::mem::size_of;
+use std::ops::Range;
 
-use egui::{Align2, ClippedMesh, CtxRef, LayerId, Output};
+use egui::{Align2, ClippedMesh, CtxRef, LayerId, Output, Rect, TextureId, Ui, Widget};
 use egui_gizmo::{Gizmo, GizmoMode, GizmoOrientation, GizmoVisuals};
 use egui_winit::State;
 use epaint::Color32;
 use gl::types::*;
-use glam::{Mat4, Vec2};
+use glam::{Mat4, Vec2, Vec3};
+use glutin::dpi::LogicalPosition;
 use glutin::window::Window;
+use hecs::Entity;
 use memoffset::offset_of;
 
-use crate::{opengl::shader::Program, texture::unit_to_gl_const, utils::size_of_slice, Result};
+use crate::app::{AppState, MenuChoice};
+use crate::atmosphere::{Atmosphere, CloudQuality};
+use crate::bricks::clipboard::ClipboardOp;
+use crate::bricks::{catalog, palette};
+use crate::debug_view::DebugView;
+use crate::display::{find_monitor, resolutions, DisplaySettings, FullscreenMode};
+use crate::editor::align::{AlignOp, Axis, DistributeOp};
+use crate::editor::assets::AssetBrowser;
+use crate::editor::commands::CommandRegistry;
+use crate::editor::console::Console;
+use crate::editor::dialogs::{self, FileKind};
+use crate::editor::outliner::{self, OutlinerItem, SceneItems};
+use crate::editor::palette::CommandPalette;
+use crate::editor::{BrickTool, EditorState, Tool, TransformMode, SNAP_ANGLE};
+use crate::gamepad::GamepadSettings;
+use crate::input::MouseSettings;
+use crate::instancing::MeshId;
+use crate::keybindings::{key_name, KeyAction, KeyBindings};
+use crate::material::{Material, ShaderVariant};
+use crate::opengl::bindings::{self, Sampler, TextureUnit};
+use crate::opengl::objects::{Texture2D, VertexArray};
+use crate::opengl::stream_buffer::StreamBuffer;
+use crate::particles::{EmitterPreset, EmitterSettings};
+use crate::postprocess::PostProcessSettings;
+use crate::profiler::{self, PassTiming, Profiler};
+use crate::project::Bookmark;
+use crate::render_settings::{
+    AntiAliasing, RenderSettings, ANISOTROPY_RANGE, RENDER_SCALE_RANGE, SHADOW_MAP_SIZES,
+};
+use crate::render_targets::{RenderTarget, RenderTargetViewer};
+use crate::splat::{LayerMap, MAX_LAYERS};
+use crate::temporal::TemporalQuality;
+use crate::terrain::{BrushMode, BrushSettings, Terrain};
+use crate::texture_manager;
+use crate::water::Water;
+use crate::{opengl::shader::Program, Result};
 
 /// An action to take as a result of interacting with the GUI
+#[derive(Clone)]
 pub enum Action {
+    /// The project if there's one, otherwise the terrain and the bricks, see Ctrl+S
+    SaveAll,
+    /// Borderless fullscreen or the window, see Alt+Enter
+    ToggleFullscreen,
+    /// Move the window to the monitor and resolution in the display settings
+    ApplyDisplaySettings,
     SaveTerrain,
+    /// Ask where to save the heightmap and use that file from then on
+    SaveTerrainAs,
+    /// Ask for a heightmap image to replace the terrain with
+    OpenHeightmap,
     SaveCamera,
+    SaveBricks,
+    LoadBricks,
+    /// Ask where to save the bricks and use that file from then on
+    SaveBricksAs,
+    OpenBricks,
+    /// Save the project where it was last saved or opened, asking if there's none yet
+    SaveProject,
+    /// Ask for a directory to save the project to and use that one from then on
+    SaveProjectAs,
+    OpenProject,
+    /// Remember where the camera is to jump back there later
+    AddBookmark,
+    GoToBookmark(usize),
+    RemoveBookmark(usize),
     Quit,
+    Align(AlignOp),
+    Distribute(DistributeOp),
+    ToggleFog,
+    ToggleDayNightCycle,
+    /// Load a glTF model and place it in front of the camera
+    AddObject {
+        path: String,
+    },
+    /// Load a model and place it on the terrain under the pointer, where it's been
+    /// dragged from the asset browser
+    DropObject {
+        path: String,
+        pointer: Vec2,
+    },
+    /// Place instances of a model at random around the point in front of the camera
+    ScatterInstances {
+        path: String,
+        count: u32,
+        radius: f32,
+    },
+    /// Remove the instances placed by the last scatter
+    UndoScatter,
+    /// Place a particle emitter in front of the camera
+    AddEmitter(EmitterPreset),
+    RemoveEmitter(Entity),
+    /// Put the scattered instances back onto the terrain after it's been reshaped
+    DropInstancesToTerrain,
+    CaptureSkybox,
+    SetCapturePositionToCamera,
+    AddTerrainLayer,
+    RemoveTerrainLayer(usize),
+    MoveTerrainLayer {
+        from: usize,
+        to: usize,
+    },
+    ImportLayerTexture {
+        layer: usize,
+        map: LayerMap,
+        path: String,
+    },
+    /// Copy the selected bricks on top of themselves
+    DuplicateBricks,
+    DeleteBricks,
+    CopyBricks,
+    TransformClipboard(ClipboardOp),
+    /// Remove the bricks that fell off
+    ClearLooseBricks,
+    /// Load an LDraw model into the clipboard to paste it
+    ImportLdraw {
+        path: String,
+    },
+    SetTerrainMaxHeight(f32),
+    /// Recreate what depends on the settings that changed
+    ApplyRenderSettings(RenderSettings),
+    /// Change the shape of the terrain brush to the image
+    LoadBrush {
+        path: String,
+    },
+    /// Save the selected bricks as a prefab
+    SavePrefab {
+        name: String,
+    },
+    /// Load a prefab into the clipboard to stamp it
+    StampPrefab {
+        name: String,
+    },
+    /// Give the selected bricks the current brick color
+    PaintBricks,
+    UndoBricks,
+    RedoBricks,
+    SetObjectVisible {
+        entity: hecs::Entity,
+        visible: bool,
+    },
+    /// Show or hide all instances of a scattered mesh
+    SetMeshVisible {
+        mesh: MeshId,
+        visible: bool,
+    },
+    /// Show or hide the bricks of a type from the catalog
+    SetBrickGroupVisible {
+        kind: usize,
+        visible: bool,
+    },
+    /// Select all bricks of a type, adding to the selection if `add`
+    SelectBrickGroup {
+        kind: usize,
+        add: bool,
+    },
+    ResetKeyBindings,
+    /// Change one of `commands::VARIABLES`
+    SetVariable {
+        name: String,
+        value: f32,
+    },
+    /// Save the heightmap to the file, leaving the configured one alone
+    ExportHeightmap {
+        path: String,
+    },
+    /// Move the camera to the world position
+    Teleport(Vec3),
+}
+
+/// An emitter in the scene as the particles panel edits it, see `Game::emitter_items`
+pub struct EmitterItem {
+    pub entity: Entity,
+    pub name: String,
+    pub enabled: bool,
+    /// Relative to the parent, or to the camera when following it
+    pub position: Vec3,
+    pub follow_camera: bool,
+    /// The object it moves with
+    pub parent: Option<Entity>,
+    pub settings: EmitterSettings,
 }
 
 pub struct Gui {
     screen_size: Vec2,
 
     ctx: CtxRef,
-    egui_texture: GLuint,
+    /// None until the first frame, see `upload_egui_texture`
+    egui_texture: Option<Texture2D>,
     egui_texture_version: Option<u64>,
 
     shader: Program,
 
+    commands: CommandRegistry,
+    palette: CommandPalette,
+    console: Console,
+    assets: AssetBrowser,
+
     // OpenGL buffers
-    vao: GLuint,
-    vbo: GLuint,
-    ebo: GLuint,
-    vertex_buffer_size: usize,
-    index_buffer_size: usize,
-    index_count: i32,
+    vao: VertexArray,
+    vertex_buffer: StreamBuffer,
+    index_buffer: StreamBuffer,
+    /// Where this frame's indices start in the index buffer, in bytes
+    index_offset: usize,
+    /// Ranges of the index buffer drawn with each texture
+    batches: Vec<(TextureId, Range<usize>)>,
 }
 
 impl Gui {
-    // Note: assuming non-resizable window for now
-    pub fn new(screen_size: Vec2) -> Result<Gui> {
-        let mut vao: GLuint = 0;
-        let mut vbo: GLuint = 0;
-        let mut ebo: GLuint = 0;
+    pub fn new(screen_size: Vec2, commands: CommandRegistry) -> Result<Gui> {
+        let vao = VertexArray::new();
 
-        // Initial size
-        let vertex_buffer_size = 1024 * 1024;
-        let index_buffer_size = 1024 * 1024;
+        // Enough for a frame with the hope that they won't have to grow often.
+        // They're attached to the vao every frame, see `layout_and_interact`.
+        let vertex_buffer = StreamBuffer::new("vertex", 1024 * 1024);
+        let index_buffer = StreamBuffer::new("index", 1024 * 1024);
 
         unsafe {
-            // Create objects
-            gl::CreateVertexArrays(1, &mut vao);
-            gl::CreateBuffers(1, &mut vbo);
-            gl::CreateBuffers(1, &mut ebo);
-
-            // Attach buffers to vao
-            gl::VertexArrayVertexBuffer(vao, 0, vbo, 0, size_of::<Vertex>() as i32);
-            gl::VertexArrayElementBuffer(vao, ebo);
-
-            // Allocate some initial storage for the buffers with the hope that
-            // it won't have to reallocate often
-            gl::NamedBufferStorage(
-                vbo,
-                vertex_buffer_size as isize,
-                std::ptr::null(),
-                gl::DYNAMIC_STORAGE_BIT,
-            );
-            gl::NamedBufferStorage(
-                ebo,
-                index_buffer_size as isize,
-                std::ptr::null(),
-                gl::DYNAMIC_STORAGE_BIT,
-            );
-
             // Position
             gl::VertexArrayAttribFormat(
-                vao,
+                vao.id(),
                 0,
                 2,
                 gl::FLOAT,
@@ -84,7 +242,7 @@ impl Gui {
 
             // UV
             gl::VertexArrayAttribFormat(
-                vao,
+                vao.id(),
                 1,
                 2,
                 gl::FLOAT,
@@ -94,7 +252,7 @@ impl Gui {
 
             // Color
             gl::VertexArrayAttribFormat(
-                vao,
+                vao.id(),
                 2,
                 4,
                 gl::UNSIGNED_BYTE,
@@ -102,35 +260,39 @@ impl Gui {
                 offset_of!(Vertex, srgba) as u32,
             );
 
-            gl::EnableVertexArrayAttrib(vao, 0);
-            gl::EnableVertexArrayAttrib(vao, 1);
-            gl::EnableVertexArrayAttrib(vao, 2);
+            gl::EnableVertexArrayAttrib(vao.id(), 0);
+            gl::EnableVertexArrayAttrib(vao.id(), 1);
+            gl::EnableVertexArrayAttrib(vao.id(), 2);
 
-            gl::VertexArrayAttribBinding(vao, 0, 0);
-            gl::VertexArrayAttribBinding(vao, 1, 0);
-            gl::VertexArrayAttribBinding(vao, 2, 0);
+            gl::VertexArrayAttribBinding(vao.id(), 0, 0);
+            gl::VertexArrayAttribBinding(vao.id(), 1, 0);
+            gl::VertexArrayAttribBinding(vao.id(), 2, 0);
         }
 
         let shader = Program::new()
-            .vertex_shader(include_str!("../shaders/editor/gui.vert"))?
-            .fragment_shader(include_str!("../shaders/editor/gui.frag"))?
+            .vertex_shader(shader_file!("editor/gui.vert"))?
+            .fragment_shader(shader_file!("editor/gui.frag"))?
             .link()?;
 
         Ok(Gui {
             screen_size,
 
             ctx: CtxRef::default(),
-            egui_texture: 0, // will be created before draw
+            egui_texture: None,
             egui_texture_version: None,
 
             shader,
 
+            commands,
+            palette: CommandPalette::default(),
+            console: Console::default(),
+            assets: AssetBrowser::default(),
+
             vao,
-            vbo,
-            ebo,
-            vertex_buffer_size,
-            index_buffer_size,
-            index_count: 0,
+            vertex_buffer,
+            index_buffer,
+            index_offset: 0,
+            batches: vec![],
         })
     }
 
@@ -138,17 +300,53 @@ impl Gui {
         &self.ctx
     }
 
+    /// In physical pixels, when the window is resized
+    pub fn resize(&mut self, screen_size: Vec2) {
+        self.screen_size = screen_size;
+    }
+
+    /// Shows the line in the console, for the results of its commands
+    pub fn console_print(&mut self, line: impl Into<String>) {
+        self.console.print(line);
+    }
+
     pub fn wants_input(&self) -> bool {
         self.ctx.wants_pointer_input() || self.ctx.wants_keyboard_input()
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn layout_and_interact(
         &mut self,
         state: &mut State,
         window: &Window,
         view_matrix: &Mat4,
         projection_matrix: &Mat4,
-        model_matrix: &mut Mat4,
+        model_matrix: Option<&mut Mat4>,
+        brick_gizmo: Option<&mut Vec3>,
+        scene_items: &SceneItems,
+        selected_materials: Option<&mut [Material]>,
+        editor_state: &mut EditorState,
+        atmosphere: &mut Atmosphere,
+        terrain: &mut Terrain,
+        brush_textures: &[String],
+        post_settings: &mut PostProcessSettings,
+        render_settings: &RenderSettings,
+        display: &mut DisplaySettings,
+        water: &mut Water,
+        emitters: &mut [EmitterItem],
+        render_targets: &[RenderTarget],
+        target_viewer: &RenderTargetViewer,
+        profiler: &Profiler,
+        brick_names: &[&str],
+        brick_selection: usize,
+        loose_bricks: usize,
+        prefab_names: &[String],
+        keybindings: &KeyBindings,
+        mouse: &mut MouseSettings,
+        gamepad: &mut GamepadSettings,
+        loading: Option<(usize, usize)>,
+        project_path: Option<&str>,
+        bookmarks: &[Bookmark],
     ) -> Vec<Action> {
         let input = state.take_egui_input(window);
         self.ctx.begin_frame(input);
@@ -156,17 +354,1004 @@ impl Gui {
 
         // ================== GUI starts ========================
 
+        self.palette.show(&self.ctx, &self.commands, &mut actions);
+        self.console.show(&self.ctx, &self.commands, &mut actions);
+        self.assets.show(&self.ctx, editor_state, &mut actions);
+        outliner::show(
+            &self.ctx,
+            scene_items,
+            editor_state,
+            atmosphere,
+            water,
+            &mut actions,
+        );
+
+        let grid_size = &mut editor_state.grid_size;
+        let transform_mode = &mut editor_state.transform_mode;
+        let snap_to_grid = &mut editor_state.snap_to_grid;
+        let tool = &mut editor_state.tool;
+        egui::TopBottomPanel::top("Toolbar").show(&self.ctx, |ui| {
+            ui.horizontal(|ui| {
+                for option in Tool::ALL {
+                    ui.selectable_value(tool, option, option.name());
+                }
+
+                ui.separator();
+
+                for mode in TransformMode::ALL {
+                    ui.selectable_value(transform_mode, mode, mode.name())
+                        .on_hover_text(key_name(keybindings.key(mode.key_action())));
+                }
+                ui.checkbox(snap_to_grid, "Snap").on_hover_text(format!(
+                    "Move by the grid size and rotate by {} degrees",
+                    SNAP_ANGLE
+                ));
+
+                ui.separator();
+
+                ui.label("Align:");
+                for axis in Axis::ALL {
+                    if ui.button(axis.name()).clicked() {
+                        actions.push(Action::Align(AlignOp::Axis(axis)));
+                    }
+                }
+                if ui.button("Terrain").clicked() {
+                    actions.push(Action::Align(AlignOp::Terrain));
+                }
+                if ui.button("Grid").clicked() {
+                    actions.push(Action::Align(AlignOp::Grid));
+                }
+                ui.add(
+                    egui::DragValue::new(grid_size)
+                        .speed(0.1)
+                        .clamp_range(0.1..=100.0)
+                        .prefix("grid: "),
+                );
+
+                ui.separator();
+
+                ui.label("Distribute:");
+                for axis in Axis::ALL {
+                    if ui.button(axis.name()).clicked() {
+                        actions.push(Action::Distribute(DistributeOp::Axis(axis)));
+                    }
+                }
+                if ui.button("Spline").clicked() {
+                    actions.push(Action::Distribute(DistributeOp::Spline));
+                }
+            });
+        });
+
+        egui::Window::new("Objects")
+            .anchor(Align2::LEFT_TOP, egui::Vec2::new(10.0, 40.0))
+            .resizable(false)
+            .show(&self.ctx, |ui| {
+                if let Some(materials) = selected_materials {
+                    ui.collapsing("Materials", |ui| {
+                        for material in materials.iter_mut() {
+                            ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.label(&material.name);
+                                for variant in ShaderVariant::ALL {
+                                    ui.radio_value(&mut material.variant, variant, variant.name());
+                                }
+                            });
+                            ui.horizontal(|ui| {
+                                let params = &mut material.params;
+                                let mut albedo = params.albedo.truncate().to_array();
+                                if ui.color_edit_button_rgb(&mut albedo).changed() {
+                                    params.albedo = Vec3::from(albedo).extend(params.albedo.w);
+                                }
+                                ui.label("Albedo");
+                            });
+                            ui.add(
+                                egui::Slider::new(&mut material.params.roughness, 0.0..=1.0)
+                                    .text("Roughness"),
+                            );
+                            ui.add(
+                                egui::Slider::new(&mut material.params.metallic, 0.0..=1.0)
+                                    .text("Metallic"),
+                            );
+                        }
+                    });
+                }
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(&mut editor_state.model_import_path)
+                        .on_hover_text("Path to a .gltf, .glb or .obj file");
+                    browse_button(ui, &mut editor_state.model_import_path, FileKind::Model);
+                    if ui.button("Add model").clicked() {
+                        actions.push(Action::AddObject {
+                            path: editor_state.model_import_path.clone(),
+                        });
+                    }
+                });
+                ui.horizontal(|ui| {
+                    if ui
+                        .button("Scatter")
+                        .on_hover_text("Instances of the model, drawn in batches")
+                        .clicked()
+                    {
+                        actions.push(Action::ScatterInstances {
+                            path: editor_state.model_import_path.clone(),
+                            count: editor_state.scatter_count,
+                            radius: editor_state.scatter_radius,
+                        });
+                    }
+                    ui.add(
+                        egui::DragValue::new(&mut editor_state.scatter_count)
+                            .clamp_range(1..=10_000)
+                            .suffix(" copies"),
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut editor_state.scatter_radius)
+                            .speed(0.5)
+                            .clamp_range(1.0..=500.0)
+                            .prefix("radius: "),
+                    );
+                });
+                ui.add(
+                    egui::Slider::new(&mut editor_state.impostor_distance, 10.0..=2000.0)
+                        .logarithmic(true)
+                        .text("Impostor distance"),
+                )
+                .on_hover_text("Instances further away are drawn as flat pictures");
+                ui.checkbox(&mut editor_state.occlusion_culling, "Occlusion culling")
+                    .on_hover_text("Skip instances hidden behind the terrain and objects");
+                ui.horizontal(|ui| {
+                    if ui.button("Undo scatter").clicked() {
+                        actions.push(Action::UndoScatter);
+                    }
+                    if ui.button("Drop to terrain").clicked() {
+                        actions.push(Action::DropInstancesToTerrain);
+                    }
+                });
+            });
+
         egui::Window::new("Tools")
             .anchor(Align2::RIGHT_TOP, egui::Vec2::new(-10.0, 10.0))
             .resizable(false)
             .show(&self.ctx, |ui| {
-                if ui.button("Save terrain").clicked() {
-                    actions.push(Action::SaveTerrain);
-                }
+                ui.collapsing("Project", |ui| {
+                    ui.label(project_path.unwrap_or("Not saved yet"));
+                    ui.horizontal(|ui| {
+                        if ui.button("Save").clicked() {
+                            actions.push(Action::SaveProject);
+                        }
+                        if ui.button("Save as...").clicked() {
+                            actions.push(Action::SaveProjectAs);
+                        }
+                        if ui.button("Open...").clicked() {
+                            actions.push(Action::OpenProject);
+                        }
+                    });
+                    ui.label("Bookmarks");
+                    for (index, bookmark) in bookmarks.iter().enumerate() {
+                        ui.horizontal(|ui| {
+                            if ui.button(&bookmark.name).clicked() {
+                                actions.push(Action::GoToBookmark(index));
+                            }
+                            if ui.button("✖").clicked() {
+                                actions.push(Action::RemoveBookmark(index));
+                            }
+                        });
+                    }
+                    if ui.button("Bookmark the camera").clicked() {
+                        actions.push(Action::AddBookmark);
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    if ui.button("Save terrain").clicked() {
+                        actions.push(Action::SaveTerrain);
+                    }
+                    if ui.button("Save as...").clicked() {
+                        actions.push(Action::SaveTerrainAs);
+                    }
+                    if ui.button("Open heightmap...").clicked() {
+                        actions.push(Action::OpenHeightmap);
+                    }
+                });
 
                 if ui.button("Save camera position").clicked() {
                     actions.push(Action::SaveCamera);
                 }
+                if ui.button("Save all (Ctrl+S)").clicked() {
+                    actions.push(Action::SaveAll);
+                }
+                if ui.button("Fullscreen (Alt+Enter)").clicked() {
+                    actions.push(Action::ToggleFullscreen);
+                }
+
+                ui.checkbox(&mut editor_state.show_labels, "Labels")
+                    .on_hover_text("Object names, cursor coordinates and the distance between two selected objects");
+                ui.checkbox(&mut editor_state.show_debug_shapes, "Debug shapes")
+                    .on_hover_text("Bounding boxes, object axes and terrain normals under the cursor");
+                ui.checkbox(&mut editor_state.show_render_targets, "Render targets")
+                    .on_hover_text("Shadow map, heightmap, splatmap and post-processing buffers");
+                ui.checkbox(&mut editor_state.show_profiler, "Profiler")
+                    .on_hover_text("GPU time of each pass and a graph of frame times");
+                ui.checkbox(&mut editor_state.show_graphics, "Graphics")
+                    .on_hover_text("Render scale, antialiasing, shadows, the frame rate and the monitor");
+                ui.checkbox(&mut editor_state.show_keybindings, "Key bindings");
+                ui.checkbox(&mut editor_state.show_assets, "Assets")
+                    .on_hover_text("Brushes, textures and models, drag them into the scene");
+                ui.collapsing("Debug view", |ui| {
+                    for view in DebugView::ALL {
+                        let text = match view.key_action() {
+                            Some(action) => {
+                                format!("{} ({})", view.name(), key_name(keybindings.key(action)))
+                            }
+                            None => view.name().to_string(),
+                        };
+                        ui.radio_value(&mut editor_state.debug_view, view, text);
+                    }
+                });
+
+                ui.collapsing("Terrain", |ui| {
+                    ui.add(
+                        egui::Slider::new(&mut terrain.tess_level, 1.0..=16.0)
+                            .text("Tessellation"),
+                    )
+                    .on_hover_text("Triangles along each side of a patch, before distance falloff");
+                    let mut max_height = terrain.max_height();
+                    let response = ui.add(
+                        egui::Slider::new(&mut max_height, 10.0..=1000.0)
+                            .logarithmic(true)
+                            .text("Max height"),
+                    );
+                    if response.on_hover_text("Height of the white parts of the heightmap").changed() {
+                        actions.push(Action::SetTerrainMaxHeight(max_height));
+                    }
+                    ui.add(
+                        egui::Slider::new(&mut terrain.material.tiling_scale, 0.1..=10.0)
+                            .logarithmic(true)
+                            .text("Tiling scale"),
+                    )
+                    .on_hover_text("Multiplies the tiling of every material layer");
+                });
+
+                ui.collapsing("Brush", |ui| {
+                    let flatten_height = terrain.flatten_height();
+                    let brush = &mut terrain.brush;
+                    let settings = &mut brush.settings;
+                    ui.horizontal(|ui| {
+                        for mode in BrushMode::ALL {
+                            ui.radio_value(&mut settings.mode, mode, mode.name());
+                        }
+                    })
+                    .response
+                    .on_hover_text(format!(
+                        "Hold {} or Shift to lower instead of raising and the other way round",
+                        key_name(keybindings.key(KeyAction::InvertBrush)),
+                    ));
+                    if settings.mode == BrushMode::Flatten {
+                        let text = match flatten_height {
+                            Some(height) => format!("Flattening to {:.1}", height),
+                            None => String::from("Flattening to the height under the cursor"),
+                        };
+                        ui.label(text)
+                            .on_hover_text("Hold Alt over the terrain to pick the height");
+                        if flatten_height.is_some() && ui.button("Follow the cursor").clicked() {
+                            brush.flatten_target = None;
+                        }
+                    }
+                    ui.add(
+                        egui::Slider::new(&mut settings.size, BrushSettings::SIZE_RANGE)
+                            .logarithmic(true)
+                            .text("Size"),
+                    )
+                    .on_hover_text("Scroll over the terrain to change");
+                    ui.add(
+                        egui::Slider::new(&mut settings.strength, BrushSettings::STRENGTH_RANGE)
+                            .logarithmic(true)
+                            .text("Strength"),
+                    )
+                    .on_hover_text("Ctrl+scroll over the terrain to change");
+                    ui.add(egui::Slider::new(&mut settings.falloff, 0.0..=1.0).text("Falloff"))
+                        .on_hover_text(
+                            "Part of the radius the brush fades out over. Tilting a pen softens it.",
+                        );
+                    egui::ComboBox::from_label("Shape")
+                        .selected_text(brush_file_name(&brush.path))
+                        .show_ui(ui, |ui| {
+                            for path in brush_textures {
+                                let selected = *path == brush.path;
+                                if ui
+                                    .selectable_label(selected, brush_file_name(path))
+                                    .clicked()
+                                    && !selected
+                                {
+                                    actions.push(Action::LoadBrush { path: path.clone() });
+                                }
+                            }
+                        });
+                    if ui.button("Open shape...").on_hover_text("Any square grayscale image").clicked() {
+                        if let Some(path) = dialogs::open_file(FileKind::Image, &brush.path) {
+                            actions.push(Action::LoadBrush { path });
+                        }
+                    }
+                });
+
+                ui.collapsing("Bricks", |ui| {
+                    let edit_key = key_name(keybindings.key(KeyAction::ToggleBrickMode));
+                    ui.selectable_value(
+                        &mut editor_state.tool,
+                        Tool::Bricks,
+                        format!("Edit bricks ({})", edit_key),
+                    )
+                    .on_hover_text("Clicks use the brick tool instead of sculpting");
+                    ui.horizontal(|ui| {
+                        if ui.button("Save bricks").clicked() {
+                            actions.push(Action::SaveBricks);
+                        }
+                        if ui.button("Load bricks").clicked() {
+                            actions.push(Action::LoadBricks);
+                        }
+                        if ui.button("Save as...").clicked() {
+                            actions.push(Action::SaveBricksAs);
+                        }
+                        if ui.button("Open...").clicked() {
+                            actions.push(Action::OpenBricks);
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        ui.text_edit_singleline(&mut editor_state.ldraw_import_path)
+                            .on_hover_text("Path to an LDraw .ldr or .mpd file");
+                        browse_button(ui, &mut editor_state.ldraw_import_path, FileKind::Ldraw);
+                        if ui.button("Import LDraw").clicked() {
+                            actions.push(Action::ImportLdraw {
+                                path: editor_state.ldraw_import_path.clone(),
+                            });
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut editor_state.freeze_physics, "Freeze physics")
+                            .on_hover_text("Bricks with nothing holding them up stay put until unfrozen");
+                        ui.label(format!("{} loose", loose_bricks));
+                        if ui.button("Clear").clicked() {
+                            actions.push(Action::ClearLooseBricks);
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        if ui.button("Undo (Ctrl+Z)").clicked() {
+                            actions.push(Action::UndoBricks);
+                        }
+                        if ui.button("Redo (Ctrl+Y)").clicked() {
+                            actions.push(Action::RedoBricks);
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        for tool in BrickTool::ALL {
+                            let text = match tool.key_action() {
+                                Some(action) => {
+                                    format!("{} ({})", tool.name(), key_name(keybindings.key(action)))
+                                }
+                                None => tool.name().to_string(),
+                            };
+                            ui.radio_value(&mut editor_state.brick_tool, tool, text);
+                        }
+                    });
+                    if editor_state.brick_tool == BrickTool::Select {
+                        ui.label(format!("{} selected", brick_selection))
+                            .on_hover_text("Arrows and Page Up/Down move the selection");
+                        ui.horizontal(|ui| {
+                            if ui.button("Duplicate (Ctrl+D)").clicked() {
+                                actions.push(Action::DuplicateBricks);
+                            }
+                            if ui.button("Delete (Del)").clicked() {
+                                actions.push(Action::DeleteBricks);
+                            }
+                            if ui.button("Copy (Ctrl+C)").clicked() {
+                                actions.push(Action::CopyBricks);
+                            }
+                            if ui.button("Paint").clicked() {
+                                actions.push(Action::PaintBricks);
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            ui.text_edit_singleline(&mut editor_state.prefab_name);
+                            if ui.button("Save prefab").clicked() {
+                                actions.push(Action::SavePrefab {
+                                    name: editor_state.prefab_name.clone(),
+                                });
+                            }
+                        });
+                    }
+                    if matches!(editor_state.brick_tool, BrickTool::Place | BrickTool::Paste) {
+                        ui.horizontal(|ui| {
+                            ui.label("Max gap under bricks");
+                            ui.add(
+                                egui::DragValue::new(&mut editor_state.max_foundation_gap)
+                                    .clamp_range(0.0..=12.0)
+                                    .speed(0.1)
+                                    .suffix(" plates"),
+                            )
+                            .on_hover_text("Bricks floating higher over the terrain show in orange");
+                        });
+                        ui.checkbox(
+                            &mut editor_state.flatten_foundations,
+                            "Flatten terrain under bricks",
+                        )
+                        .on_hover_text("Raises the terrain under placed bricks up to their base, can't be undone");
+                    }
+                    if editor_state.brick_tool == BrickTool::Paste {
+                        ui.horizontal(|ui| {
+                            for op in ClipboardOp::ALL {
+                                if ui.button(op.name()).clicked() {
+                                    actions.push(Action::TransformClipboard(op));
+                                }
+                            }
+                        });
+                    }
+                    for (index, name) in brick_names.iter().enumerate() {
+                        let text = match catalog::hotkey_name(index) {
+                            Some(key) => format!("{} ({})", name, key),
+                            None => name.to_string(),
+                        };
+                        ui.radio_value(&mut editor_state.brick_type, index, text);
+                    }
+                    if !prefab_names.is_empty() {
+                        ui.label("Prefabs");
+                        ui.horizontal_wrapped(|ui| {
+                            for name in prefab_names {
+                                if ui.button(name).on_hover_text("Stamp with the paste tool").clicked() {
+                                    actions.push(Action::StampPrefab { name: name.clone() });
+                                }
+                            }
+                        });
+                    }
+                    egui::Grid::new("brick_palette")
+                        .spacing([2.0, 2.0])
+                        .show(ui, |ui| {
+                            for (index, &(name, hex)) in palette::COLORS.iter().enumerate() {
+                                let color = palette::srgb(hex);
+                                let [r, g, b] = (color * 255.0).round().to_array();
+                                let selected = editor_state.brick_color == color;
+                                let button = egui::Button::new(if selected { "✔" } else { "  " })
+                                    .fill(Color32::from_rgb(r as u8, g as u8, b as u8));
+                                if ui.add(button).on_hover_text(name).clicked() {
+                                    editor_state.brick_color = color;
+                                }
+                                if index % 8 == 7 {
+                                    ui.end_row();
+                                }
+                            }
+                        });
+                    ui.horizontal(|ui| {
+                        let mut color = editor_state.brick_color.to_array();
+                        if ui.color_edit_button_rgb(&mut color).changed() {
+                            editor_state.brick_color = Vec3::from(color);
+                        }
+                        ui.label("Custom color")
+                            .on_hover_text("Alt+click a brick to pick up its color");
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label(format!("Rotation: {}°", editor_state.brick_rotation as u32 * 90));
+                        if ui.button("Rotate (R)").clicked() {
+                            editor_state.brick_rotation = (editor_state.brick_rotation + 1) % 4;
+                        }
+                    });
+                });
+                ui.collapsing("Atmosphere", |ui| {
+                    let time_of_day = &mut atmosphere.time_of_day;
+                    ui.add(
+                        egui::Slider::new(&mut time_of_day.hour, 0.0..=24.0).text("Time of day"),
+                    );
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut time_of_day.paused, "Pause");
+                        ui.add(
+                            egui::DragValue::new(&mut time_of_day.day_length)
+                                .speed(1.0)
+                                .clamp_range(10.0..=3600.0)
+                                .prefix("day length: ")
+                                .suffix(" s"),
+                        );
+                    });
+
+                    ui.separator();
+
+                    let sky = &mut atmosphere.sky;
+                    ui.checkbox(&mut sky.procedural, "Procedural sky");
+                    ui.add_enabled(
+                        sky.procedural,
+                        egui::Slider::new(&mut sky.turbidity, 1.7..=10.0).text("Turbidity"),
+                    );
+                    ui.add_enabled(
+                        sky.procedural,
+                        egui::Slider::new(&mut sky.exposure, 0.01..=1.0)
+                            .text("Exposure")
+                            .logarithmic(true),
+                    );
+
+                    ui.separator();
+
+                    let clouds = &mut atmosphere.clouds;
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut clouds.enabled, "Clouds");
+                        for quality in CloudQuality::ALL {
+                            ui.radio_value(&mut clouds.quality, quality, quality.name());
+                        }
+                    });
+                    ui.add_enabled_ui(clouds.enabled, |ui| {
+                        ui.add(egui::Slider::new(&mut clouds.coverage, 0.0..=1.0).text("Coverage"));
+                        ui.add(
+                            egui::Slider::new(&mut clouds.density, 0.001..=0.2)
+                                .text("Cloud density")
+                                .logarithmic(true),
+                        );
+                        ui.add(
+                            egui::Slider::new(&mut clouds.bottom, 200.0..=1500.0)
+                                .text("Cloud base"),
+                        );
+                        ui.add(
+                            egui::Slider::new(&mut clouds.top, 250.0..=2500.0).text("Cloud top"),
+                        );
+                    });
+
+                    ui.separator();
+
+                    let fog = &mut atmosphere.fog;
+                    ui.checkbox(&mut fog.enabled, "Fog");
+                    ui.horizontal(|ui| {
+                        let mut color = fog.color.to_array();
+                        if ui.color_edit_button_rgb(&mut color).changed() {
+                            fog.color = color.into();
+                        }
+                        ui.label("Fog color");
+                    });
+                    ui.add(
+                        egui::Slider::new(&mut fog.density, 0.0..=0.02)
+                            .text("Density")
+                            .logarithmic(true),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut fog.height_falloff, 0.0001..=0.1)
+                            .text("Height falloff")
+                            .logarithmic(true),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut fog.base_height, -100.0..=300.0).text("Base height"),
+                    );
+                });
+
+                ui.collapsing("Water", |ui| {
+                    ui.checkbox(&mut water.enabled, "Enabled");
+                    ui.add_enabled_ui(water.enabled, |ui| {
+                        ui.add(egui::Slider::new(&mut water.level, -50.0..=200.0).text("Level"));
+                        ui.horizontal(|ui| {
+                            let mut color = water.color.to_array();
+                            if ui.color_edit_button_rgb(&mut color).changed() {
+                                water.color = color.into();
+                            }
+                            ui.label("Deep water color");
+                        });
+                        ui.add(egui::Slider::new(&mut water.clarity, 1.0..=100.0).text("Clarity"));
+                        ui.add(
+                            egui::Slider::new(&mut water.wave_strength, 0.0..=0.2).text("Waves"),
+                        );
+
+                        let ssr = &mut water.ssr;
+                        ui.checkbox(&mut ssr.enabled, "Screen-space reflections");
+                        ui.add_enabled_ui(ssr.enabled, |ui| {
+                            ui.add(egui::Slider::new(&mut ssr.max_steps, 8..=256).text("Steps"));
+                            ui.add(
+                                egui::Slider::new(&mut ssr.step_size, 0.1..=10.0)
+                                    .text("Step size")
+                                    .logarithmic(true),
+                            );
+                            ui.add(
+                                egui::Slider::new(&mut ssr.thickness, 0.1..=50.0)
+                                    .text("Thickness")
+                                    .logarithmic(true),
+                            );
+                        });
+                    });
+                });
+
+                ui.collapsing("Particles", |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("Add:");
+                        for preset in EmitterPreset::ALL {
+                            if ui.button(preset.name()).clicked() {
+                                actions.push(Action::AddEmitter(preset));
+                            }
+                        }
+                    });
+                    for (index, emitter) in emitters.iter_mut().enumerate() {
+                        ui.separator();
+                        ui.horizontal(|ui| {
+                            ui.checkbox(&mut emitter.enabled, "");
+                            ui.text_edit_singleline(&mut emitter.name);
+                            if ui.button("✖").clicked() {
+                                actions.push(Action::RemoveEmitter(emitter.entity));
+                            }
+                        });
+                        egui::CollapsingHeader::new("Settings")
+                            .id_source(index)
+                            .show(ui, |ui| {
+                                emitter_settings_ui(ui, emitter, &scene_items.objects)
+                            });
+                    }
+                });
+
+                ui.collapsing("Post-processing", |ui| {
+                    let god_rays = &mut post_settings.god_rays;
+                    ui.checkbox(&mut god_rays.enabled, "God rays");
+                    ui.add_enabled_ui(god_rays.enabled, |ui| {
+                        ui.add(egui::Slider::new(&mut god_rays.samples, 8..=128).text("Samples"));
+                        ui.add(egui::Slider::new(&mut god_rays.density, 0.1..=1.0).text("Length"));
+                        ui.add(egui::Slider::new(&mut god_rays.weight, 0.01..=1.0).text("Weight"));
+                        ui.add(egui::Slider::new(&mut god_rays.decay, 0.8..=1.0).text("Decay"));
+                        ui.add(
+                            egui::Slider::new(&mut god_rays.exposure, 0.01..=1.0).text("Exposure"),
+                        );
+                    });
+
+                    ui.separator();
+                    let ssao = &mut post_settings.ssao;
+                    ui.checkbox(&mut ssao.enabled, "Ambient occlusion");
+                    ui.add_enabled_ui(ssao.enabled, |ui| {
+                        ui.add(egui::Slider::new(&mut ssao.radius, 0.1..=10.0).text("Radius"));
+                        ui.add(egui::Slider::new(&mut ssao.strength, 0.0..=1.0).text("Strength"));
+                    });
+                    ui.add(
+                        egui::Slider::new(&mut post_settings.soft_shadows.softness, 0.5..=10.0)
+                            .text("Shadow softness"),
+                    );
+                    ui.horizontal(|ui| {
+                        ui.label("Temporal accumulation:");
+                        for quality in TemporalQuality::ALL {
+                            ui.radio_value(
+                                &mut post_settings.temporal_quality,
+                                quality,
+                                quality.name(),
+                            );
+                        }
+                    });
+                });
+
+                ui.collapsing("Skybox capture", |ui| {
+                    let capture = &mut editor_state.skybox_capture;
+                    ui.horizontal(|ui| {
+                        ui.label("Position:");
+                        ui.add(egui::DragValue::new(&mut capture.position.x).prefix("x: "));
+                        ui.add(egui::DragValue::new(&mut capture.position.y).prefix("y: "));
+                        ui.add(egui::DragValue::new(&mut capture.position.z).prefix("z: "));
+                    });
+                    if ui.button("Use camera position").clicked() {
+                        actions.push(Action::SetCapturePositionToCamera);
+                    }
+                    ui.horizontal(|ui| {
+                        let mut fixed_time = capture.hour.is_some();
+                        if ui.checkbox(&mut fixed_time, "Time of day").changed() {
+                            capture.hour = if fixed_time {
+                                Some(atmosphere.time_of_day.hour)
+                            } else {
+                                None
+                            };
+                        }
+                        if let Some(hour) = capture.hour.as_mut() {
+                            ui.add(egui::Slider::new(hour, 0.0..=24.0));
+                        } else {
+                            ui.label("(current)");
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Face size:");
+                        for size in [256, 512, 1024, 2048] {
+                            ui.radio_value(&mut capture.face_size, size, size.to_string());
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Save to:");
+                        ui.text_edit_singleline(&mut capture.output_dir);
+                    });
+                    if ui.button("Capture").clicked() {
+                        actions.push(Action::CaptureSkybox);
+                    }
+                });
+
+                ui.collapsing("Terrain material", |ui| {
+                    let import_path = &mut editor_state.texture_import_path;
+                    let generate_normals = &mut editor_state.generate_normals;
+                    let normal_strength = &mut editor_state.normal_strength;
+                    let paint_layer = &mut editor_state.paint_layer;
+                    ui.horizontal(|ui| {
+                        ui.label("Image:");
+                        ui.text_edit_singleline(import_path);
+                        browse_button(ui, import_path, FileKind::Image);
+                    });
+                    ui.horizontal(|ui| {
+                        ui.checkbox(generate_normals, "Generate normals from albedo");
+                        ui.add_enabled(
+                            *generate_normals,
+                            egui::DragValue::new(normal_strength)
+                                .speed(0.05)
+                                .clamp_range(0.1..=20.0)
+                                .prefix("strength: "),
+                        );
+                    });
+
+                    let layer_count = terrain.material.layers.len();
+                    for (index, layer) in terrain.material.layers.iter_mut().enumerate() {
+                        ui.separator();
+                        ui.horizontal(|ui| {
+                            ui.radio_value(paint_layer, index, "")
+                                .on_hover_text("Paint with this layer");
+                            ui.text_edit_singleline(&mut layer.name);
+                            if ui.add_enabled(index > 0, egui::Button::new("⏶")).clicked() {
+                                actions.push(Action::MoveTerrainLayer {
+                                    from: index,
+                                    to: index - 1,
+                                });
+                            }
+                            let is_last = index + 1 == layer_count;
+                            if ui.add_enabled(!is_last, egui::Button::new("⏷")).clicked() {
+                                actions.push(Action::MoveTerrainLayer {
+                                    from: index,
+                                    to: index + 1,
+                                });
+                            }
+                            let can_remove = layer_count > 1;
+                            if ui.add_enabled(can_remove, egui::Button::new("✖")).clicked() {
+                                actions.push(Action::RemoveTerrainLayer(index));
+                            }
+                        });
+                        ui.add(
+                            egui::Slider::new(&mut layer.tiling, 0.5..=256.0)
+                                .text("Tiling")
+                                .logarithmic(true),
+                        );
+                        ui.horizontal(|ui| {
+                            let mut tint = layer.params.albedo.truncate().to_array();
+                            if ui.color_edit_button_rgb(&mut tint).changed() {
+                                layer.params.albedo = Vec3::from(tint).extend(1.0);
+                            }
+                            ui.label("Tint");
+                            ui.checkbox(&mut layer.triplanar, "Triplanar");
+                        });
+                        ui.add(
+                            egui::Slider::new(&mut layer.params.roughness, 0.0..=2.0)
+                                .text("Roughness scale"),
+                        );
+                        ui.add(
+                            egui::Slider::new(&mut layer.params.metallic, 0.0..=1.0)
+                                .text("Metallic"),
+                        );
+                        for map in LayerMap::ALL {
+                            ui.horizontal(|ui| {
+                                if ui.button(format!("Import {}", map.name())).clicked() {
+                                    actions.push(Action::ImportLayerTexture {
+                                        layer: index,
+                                        map,
+                                        path: import_path.clone(),
+                                    });
+                                }
+                                ui.label(layer.path(map).unwrap_or("-"));
+                            });
+                        }
+                    }
+
+                    ui.separator();
+                    let can_add = layer_count < MAX_LAYERS;
+                    if ui
+                        .add_enabled(can_add, egui::Button::new("Add layer"))
+                        .clicked()
+                    {
+                        actions.push(Action::AddTerrainLayer);
+                    }
+                });
+            });
+
+        let selected_target = &mut editor_state.render_target;
+        egui::Window::new("Render targets")
+            .open(&mut editor_state.show_render_targets)
+            .resizable(false)
+            .show(&self.ctx, |ui| {
+                ui.horizontal_wrapped(|ui| {
+                    for (index, target) in render_targets.iter().enumerate() {
+                        ui.selectable_value(selected_target, index, target.name);
+                    }
+                });
+                let width = 384.0;
+                let size = egui::Vec2::new(width, width / target_viewer.aspect_ratio());
+                // Flipped, GL textures start at the bottom
+                egui::Image::new(TextureId::User(target_viewer.preview() as u64), size)
+                    .uv(Rect::from_min_max(
+                        egui::pos2(0.0, 1.0),
+                        egui::pos2(1.0, 0.0),
+                    ))
+                    .ui(ui);
+            });
+
+        // Edited as a copy, the changes are applied by the game
+        let mut rendering = *render_settings;
+        let mut display_changed = false;
+        egui::Window::new("Graphics")
+            .open(&mut editor_state.show_graphics)
+            .resizable(false)
+            .show(&self.ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Antialiasing:");
+                    for mode in AntiAliasing::ALL {
+                        ui.radio_value(&mut rendering.anti_aliasing, mode, mode.name());
+                    }
+                });
+                ui.add(
+                    egui::Slider::new(&mut rendering.render_scale, RENDER_SCALE_RANGE)
+                        .text("Render scale"),
+                )
+                .on_hover_text("Of the window size, below 1 is faster and above 1 supersamples");
+                egui::ComboBox::from_label("Shadow map")
+                    .selected_text(rendering.shadow_map_size.to_string())
+                    .show_ui(ui, |ui| {
+                        for size in SHADOW_MAP_SIZES {
+                            ui.selectable_value(
+                                &mut rendering.shadow_map_size,
+                                size,
+                                size.to_string(),
+                            );
+                        }
+                    });
+                ui.add(
+                    egui::Slider::new(&mut rendering.anisotropy, ANISOTROPY_RANGE)
+                        .text("Anisotropic filtering"),
+                )
+                .on_hover_text("Up to what the driver supports");
+
+                ui.separator();
+                ui.checkbox(&mut display.vsync, "Vsync")
+                    .on_hover_text("Takes effect the next time the editor starts");
+                let mut limited = display.max_fps.is_some();
+                if ui.checkbox(&mut limited, "Limit the frame rate").changed() {
+                    display.max_fps = if limited { Some(60) } else { None };
+                }
+                if let Some(max_fps) = display.max_fps.as_mut() {
+                    ui.add(egui::Slider::new(max_fps, 10..=240).text("Frames per second"));
+                }
+
+                ui.separator();
+                let monitors: Vec<_> = window.available_monitors().collect();
+                let current = "Where the window is";
+                egui::ComboBox::from_label("Monitor")
+                    .selected_text(display.monitor.as_deref().unwrap_or(current))
+                    .show_ui(ui, |ui| {
+                        display_changed |= ui
+                            .selectable_value(&mut display.monitor, None, current)
+                            .clicked();
+                        for name in monitors.iter().filter_map(|monitor| monitor.name()) {
+                            display_changed |= ui
+                                .selectable_value(&mut display.monitor, Some(name.clone()), name)
+                                .clicked();
+                        }
+                    });
+                let monitor = find_monitor(display, monitors.into_iter(), window.current_monitor());
+                let resolution_name = |resolution: Option<[u32; 2]>| match resolution {
+                    Some([width, height]) => format!("{}×{}", width, height),
+                    None => String::from("Fit the monitor"),
+                };
+                egui::ComboBox::from_label("Resolution")
+                    .selected_text(resolution_name(display.resolution))
+                    .show_ui(ui, |ui| {
+                        let options = monitor.as_ref().map_or_else(Vec::new, resolutions);
+                        for resolution in std::iter::once(None).chain(options.into_iter().map(Some))
+                        {
+                            display_changed |= ui
+                                .selectable_value(
+                                    &mut display.resolution,
+                                    resolution,
+                                    resolution_name(resolution),
+                                )
+                                .clicked();
+                        }
+                    });
+                ui.horizontal(|ui| {
+                    ui.label("Fullscreen:");
+                    for mode in FullscreenMode::ALL {
+                        display_changed |= ui
+                            .radio_value(&mut display.fullscreen_mode, mode, mode.name())
+                            .clicked();
+                    }
+                })
+                .response
+                .on_hover_text("Exclusive switches the monitor to the resolution");
+            });
+        if rendering != *render_settings {
+            actions.push(Action::ApplyRenderSettings(rendering));
+        }
+        if display_changed {
+            actions.push(Action::ApplyDisplaySettings);
+        }
+
+        let rebinding = &mut editor_state.rebinding;
+        egui::Window::new("Key bindings")
+            .open(&mut editor_state.show_keybindings)
+            .resizable(false)
+            .show(&self.ctx, |ui| {
+                egui::Grid::new("keybindings").show(ui, |ui| {
+                    for action in KeyAction::ALL {
+                        ui.label(action.name());
+                        let text = if *rebinding == Some(action) {
+                            String::from("Press a key...")
+                        } else {
+                            key_name(keybindings.key(action))
+                        };
+                        if ui.button(text).clicked() {
+                            *rebinding = Some(action);
+                        }
+                        ui.end_row();
+                    }
+                });
+                if rebinding.is_some() {
+                    ui.label("Escape to cancel");
+                }
+                if ui.button("Reset to defaults").clicked() {
+                    actions.push(Action::ResetKeyBindings);
+                }
+
+                ui.separator();
+                ui.label("Mouse");
+                ui.add(egui::Slider::new(&mut mouse.sensitivity, 0.1..=5.0).text("Sensitivity"));
+                ui.checkbox(&mut mouse.invert_y, "Invert look");
+
+                ui.separator();
+                ui.label("Gamepad");
+                ui.add(egui::Slider::new(&mut gamepad.dead_zone, 0.0..=0.5).text("Dead zone"));
+                ui.add(
+                    egui::Slider::new(&mut gamepad.look_sensitivity, 0.5..=8.0)
+                        .text("Look sensitivity"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut gamepad.move_sensitivity, 0.1..=1.0)
+                        .text("Move sensitivity"),
+                );
+                ui.checkbox(&mut gamepad.invert_y, "Invert look");
+            });
+
+        if let Some((finished, total)) = loading {
+            egui::Window::new("Loading")
+                .resizable(false)
+                .collapsible(false)
+                .anchor(egui::Align2::RIGHT_BOTTOM, egui::vec2(-10.0, -10.0))
+                .show(&self.ctx, |ui| {
+                    ui.add(
+                        egui::ProgressBar::new(finished as f32 / total as f32)
+                            .text(format!("{} / {}", finished, total)),
+                    );
+                });
+        }
+
+        egui::Window::new("Profiler")
+            .open(&mut editor_state.show_profiler)
+            .resizable(false)
+            .show(&self.ctx, |ui| {
+                ui.label(format!("{:.0} FPS", profiler.fps()));
+                if let Some(last) = profiler.frame_times.back() {
+                    ui.label(format!("Frame time: {:.2} ms", last));
+                }
+                ui.label(format!("Draw calls: {}", profiler.draw_calls));
+                ui.label(format!(
+                    "Loaded textures: {}",
+                    texture_manager::loaded_count()
+                ));
+                ui.separator();
+                ui.label("CPU");
+                timings_grid(ui, "cpu_timings", &profiler.cpu_timings);
+                ui.label("GPU");
+                timings_grid(ui, "passes", &profiler.passes);
+                ui.separator();
+                let frame_times: Vec<f32> = profiler.frame_times.iter().copied().collect();
+                let graph = egui::plot::Line::new(egui::plot::Values::from_ys_f32(&frame_times));
+                ui.add(
+                    egui::plot::Plot::new("frame_times")
+                        .line(graph)
+                        .height(80.0)
+                        .include_y(0.0)
+                        .allow_zoom(false)
+                        .allow_drag(false),
+                );
             });
 
         egui::Area::new("Viewport")
@@ -177,24 +1362,105 @@ impl Gui {
                         gizmo_size: 100.0,
                         ..Default::default()
                     };
-                    let gizmo = Gizmo::new("gizmo")
-                        .view_matrix(view_matrix.to_cols_array_2d())
-                        .projection_matrix(projection_matrix.to_cols_array_2d())
-                        .model_matrix(model_matrix.to_cols_array_2d())
-                        .mode(GizmoMode::Translate)
-                        .orientation(GizmoOrientation::Global)
-                        .visuals(visuals);
+                    if let Some(model_matrix) = model_matrix {
+                        let mode = match editor_state.transform_mode {
+                            TransformMode::Translate => GizmoMode::Translate,
+                            TransformMode::Rotate => GizmoMode::Rotate,
+                            TransformMode::Scale => GizmoMode::Scale,
+                        };
+                        let gizmo = Gizmo::new("gizmo")
+                            .view_matrix(view_matrix.to_cols_array_2d())
+                            .projection_matrix(projection_matrix.to_cols_array_2d())
+                            .model_matrix(model_matrix.to_cols_array_2d())
+                            .mode(mode)
+                            .orientation(GizmoOrientation::Global)
+                            .snapping(editor_state.snap_to_grid)
+                            .snap_distance(editor_state.grid_size)
+                            .snap_angle(SNAP_ANGLE.to_radians())
+                            .visuals(visuals);
 
-                    if let Some(gizmo_result) = gizmo.interact(ui) {
-                        *model_matrix = Mat4::from_cols_array_2d(&gizmo_result.transform);
+                        if let Some(gizmo_result) = gizmo.interact(ui) {
+                            *model_matrix = Mat4::from_cols_array_2d(&gizmo_result.transform);
+                        }
+                    }
+                    if let Some(position) = brick_gizmo {
+                        // Moves smoothly, the bricks follow it a whole cell at a time
+                        let gizmo = Gizmo::new("brick_gizmo")
+                            .view_matrix(view_matrix.to_cols_array_2d())
+                            .projection_matrix(projection_matrix.to_cols_array_2d())
+                            .model_matrix(Mat4::from_translation(*position).to_cols_array_2d())
+                            .mode(GizmoMode::Translate)
+                            .orientation(GizmoOrientation::Global)
+                            .visuals(visuals);
+
+                        if let Some(gizmo_result) = gizmo.interact(ui) {
+                            let transform = Mat4::from_cols_array_2d(&gizmo_result.transform);
+                            *position = transform.w_axis.truncate();
+                        }
                     }
                 });
             });
 
         // ================== GUI ends ===========================
 
+        self.end_frame(state, window);
+        actions
+    }
+
+    /// The main menu over the scene. Returns what was picked, if anything.
+    pub fn menu(&mut self, state: &mut State, window: &Window) -> Option<MenuChoice> {
+        let input = state.take_egui_input(window);
+        self.ctx.begin_frame(input);
+        let mut choice = None;
+        egui::Window::new("Мёртвый трилистник")
+            .anchor(Align2::CENTER_CENTER, [0.0, 0.0])
+            .collapsible(false)
+            .resizable(false)
+            .show(&self.ctx, |ui| {
+                ui.vertical_centered_justified(|ui| {
+                    if ui.button("Edit the terrain").clicked() {
+                        choice = Some(MenuChoice::Open(AppState::Editor));
+                    }
+                    if ui.button("Play").clicked() {
+                        choice = Some(MenuChoice::Open(AppState::Play));
+                    }
+                    if ui.button("Quit").clicked() {
+                        choice = Some(MenuChoice::Quit);
+                    }
+                });
+            });
+        self.end_frame(state, window);
+        choice
+    }
+
+    /// A crosshair in the middle of the screen and what the keys do in play mode
+    pub fn play_hud(&mut self, state: &mut State, window: &Window, brick_name: &str) {
+        let input = state.take_egui_input(window);
+        self.ctx.begin_frame(input);
+        let size = self.screen_size / self.ctx.pixels_per_point();
+        let center = egui::pos2(size.x * 0.5, size.y * 0.5);
+        let painter = self.ctx.layer_painter(LayerId::background());
+        let stroke = egui::Stroke::new(2.0, Color32::WHITE);
+        for &offset in [egui::vec2(8.0, 0.0), egui::vec2(0.0, 8.0)].iter() {
+            painter.line_segment([center - offset, center + offset], stroke);
+        }
+        egui::Area::new("play_hud")
+            .anchor(Align2::LEFT_BOTTOM, [10.0, -10.0])
+            .show(&self.ctx, |ui| {
+                ui.label(brick_name);
+                ui.label("Click to place, right-click to remove, Escape for the menu");
+            });
+        self.end_frame(state, window);
+    }
+
+    /// Hands the output of the frame to the window and the shapes to the GPU
+    fn end_frame(&mut self, state: &mut State, window: &Window) {
         let (output, shapes) = self.ctx.end_frame();
 
+        // Keeps the IME candidate window next to the text being typed
+        if let Some(pos) = output.text_cursor_pos {
+            window.set_ime_position(LogicalPosition::new(pos.x, pos.y));
+        }
         state.handle_output(window, &self.ctx, output);
 
         // Send meshes and texture to GPU
@@ -206,72 +1472,49 @@ impl Gui {
         let mut indices: Vec<u32> = Vec::new();
         let mut vertex_count = 0;
 
+        self.batches.clear();
         for ClippedMesh(_clip_rect, mesh) in clipped_meshes {
             vertices.extend(mesh.vertices.iter().map(|v| Vertex {
                 pos: [v.pos.x, v.pos.y],
                 uv: [v.uv.x, v.uv.y],
                 srgba: v.color.to_array(),
             }));
+            let start = indices.len();
             indices.extend(mesh.indices.iter().map(|&i| i + vertex_count));
             vertex_count = vertices.len() as u32;
-        }
-        self.index_count = indices.len() as i32;
-
-        // Fill vertex buffer with data, reallocating if necessary
-        let required_size = size_of_slice(&vertices);
-        if self.vertex_buffer_size < required_size {
-            unsafe {
-                gl::DeleteBuffers(1, &self.vbo);
-                gl::CreateBuffers(1, &mut self.vbo);
-                gl::VertexArrayVertexBuffer(self.vao, 0, self.vbo, 0, size_of::<Vertex>() as i32);
-                gl::NamedBufferStorage(
-                    self.vbo,
-                    required_size as isize,
-                    vertices.as_ptr() as *const _,
-                    gl::DYNAMIC_STORAGE_BIT,
-                );
-            }
-            self.vertex_buffer_size = required_size;
-            println!("Reallocating vertex buffer to {}", required_size);
-        } else {
-            unsafe {
-                gl::NamedBufferSubData(
-                    self.vbo,
-                    0,
-                    required_size as isize,
-                    vertices.as_ptr() as *const _,
-                )
+
+            // Consecutive meshes with the same texture are drawn together
+            match self.batches.last_mut() {
+                Some((texture_id, range)) if *texture_id == mesh.texture_id => {
+                    range.end = indices.len()
+                }
+                _ => self.batches.push((mesh.texture_id, start..indices.len())),
             }
         }
 
-        // Fill index buffer with data, reallocating if necessary
-        let required_size = size_of_slice(&indices);
-        if self.index_buffer_size < required_size {
-            unsafe {
-                gl::DeleteBuffers(1, &self.ebo);
-                gl::CreateBuffers(1, &mut self.ebo);
-                gl::VertexArrayElementBuffer(self.vao, self.ebo);
-                gl::NamedBufferStorage(
-                    self.ebo,
-                    required_size as isize,
-                    indices.as_ptr() as *const _,
-                    gl::DYNAMIC_STORAGE_BIT,
-                );
-            }
-            self.index_buffer_size = required_size;
-            println!("Reallocating index buffer to {}", required_size);
-        } else {
-            unsafe {
-                gl::NamedBufferSubData(
-                    self.ebo,
-                    0,
-                    required_size as isize,
-                    indices.as_ptr() as *const _,
-                )
-            }
+        // Each frame goes into the next region of the buffers
+        let vertex_offset = self.vertex_buffer.upload(&vertices);
+        self.index_offset = self.index_buffer.upload(&indices);
+        unsafe {
+            gl::VertexArrayVertexBuffer(
+                self.vao.id(),
+                0,
+                self.vertex_buffer.id(),
+                vertex_offset as isize,
+                size_of::<Vertex>() as i32,
+            );
+            gl::VertexArrayElementBuffer(self.vao.id(), self.index_buffer.id());
         }
+    }
 
-        actions
+    /// Goes through an empty frame when the GUI is hidden, so that egui still sees
+    /// the input and nothing is drawn
+    pub fn skip_frame(&mut self, state: &mut State, window: &Window) {
+        let input = state.take_egui_input(window);
+        self.ctx.begin_frame(input);
+        let (output, _shapes) = self.ctx.end_frame();
+        state.handle_output(window, &self.ctx, output);
+        self.batches.clear();
     }
 
     pub fn draw(&mut self) {
@@ -283,10 +1526,7 @@ impl Gui {
             .set_vec2("u_screen_size", &screen_size_in_points)
             .unwrap();
         unsafe {
-            gl::ActiveTexture(unit_to_gl_const(0));
-            gl::BindTexture(gl::TEXTURE_2D, self.egui_texture);
-
-            gl::BindVertexArray(self.vao);
+            gl::BindVertexArray(self.vao.id());
             gl::Disable(gl::DEPTH_TEST);
             gl::Disable(gl::CULL_FACE);
             gl::Enable(gl::BLEND);
@@ -297,12 +1537,23 @@ impl Gui {
                 gl::ONE,
             );
 
-            gl::DrawElements(
-                gl::TRIANGLES,
-                self.index_count,
-                gl::UNSIGNED_INT,
-                std::ptr::null(),
-            );
+            for (texture_id, range) in &self.batches {
+                // User textures are GL texture names, see `RenderTargetViewer`
+                let texture = match *texture_id {
+                    TextureId::Egui => self.egui_texture.as_ref().map_or(0, Texture2D::id),
+                    TextureId::User(texture) => texture as GLuint,
+                };
+                bindings::bind_texture_sampled(TextureUnit::ALBEDO, texture, Sampler::LinearClamp);
+                gl::DrawElements(
+                    gl::TRIANGLES,
+                    range.len() as i32,
+                    gl::UNSIGNED_INT,
+                    (self.index_offset + range.start * size_of::<u32>()) as *const _,
+                );
+                profiler::count_draw_call();
+            }
+            self.vertex_buffer.fence();
+            self.index_buffer.fence();
 
             gl::Disable(gl::BLEND);
             gl::Enable(gl::DEPTH_TEST);
@@ -323,26 +1574,19 @@ impl Gui {
             .map(|&a| Color32::from_white_alpha(a).to_tuple())
             .collect();
 
-        let mut new_texture: GLuint = 0;
+        // The storage is immutable so the old texture is replaced and deleted
+        let new_texture = Texture2D::new();
+        let id = new_texture.id();
         unsafe {
-            if self.egui_texture != 0 {
-                // Delete old texture
-                gl::DeleteTextures(1, &self.egui_texture);
-            }
-            gl::CreateTextures(gl::TEXTURE_2D, 1, &mut new_texture);
-            gl::TextureParameteri(new_texture, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
-            gl::TextureParameteri(new_texture, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
-            gl::TextureParameteri(new_texture, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
-            gl::TextureParameteri(new_texture, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
             gl::TextureStorage2D(
-                new_texture,
+                id,
                 1,
                 gl::SRGB8_ALPHA8,
                 texture.width as GLint,
                 texture.height as GLint,
             );
             gl::TextureSubImage2D(
-                new_texture,
+                id,
                 0,
                 0,
                 0,
@@ -353,19 +1597,7 @@ impl Gui {
                 pixels.as_ptr() as *const _,
             );
         }
-        self.egui_texture = new_texture;
-    }
-}
-
-impl Drop for Gui {
-    fn drop(&mut self) {
-        unsafe {
-            let buffers = [self.vbo, self.ebo];
-            gl::DeleteBuffers(1, buffers.as_ptr());
-
-            let arrays = [self.vao];
-            gl::DeleteVertexArrays(1, arrays.as_ptr());
-        }
+        self.egui_texture = Some(new_texture);
     }
 }
 
@@ -376,3 +1608,122 @@ struct Vertex {
     uv: [f32; 2],
     srgba: [u8; 4],
 }
+
+/// Replaces the path with a file picked in a dialog
+fn browse_button(ui: &mut Ui, path: &mut String, kind: FileKind) {
+    if ui.button("...").on_hover_text("Browse").clicked() {
+        if let Some(picked) = dialogs::open_file(kind, path) {
+            *path = picked;
+        }
+    }
+}
+
+/// Nested scopes are indented under their parents
+fn timings_grid(ui: &mut Ui, id: &str, timings: &[PassTiming]) {
+    egui::Grid::new(id).show(ui, |ui| {
+        for timing in timings {
+            ui.label(format!("{}{}", "    ".repeat(timing.depth), timing.name));
+            ui.label(format!("{:.2} ms", timing.milliseconds));
+            ui.end_row();
+        }
+    });
+}
+
+/// Without the directory, which is the same for all of them
+fn brush_file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+fn emitter_settings_ui(ui: &mut Ui, emitter: &mut EmitterItem, objects: &[(Entity, OutlinerItem)]) {
+    ui.horizontal(|ui| {
+        ui.label("Position:");
+        ui.add(egui::DragValue::new(&mut emitter.position.x).prefix("x: "));
+        ui.add(egui::DragValue::new(&mut emitter.position.y).prefix("y: "));
+        ui.add(egui::DragValue::new(&mut emitter.position.z).prefix("z: "));
+    });
+    ui.checkbox(&mut emitter.follow_camera, "Follow camera")
+        .on_hover_text("The position is relative to the camera, for weather");
+    let parent_name = objects
+        .iter()
+        .find(|(entity, _)| Some(*entity) == emitter.parent)
+        .map_or("Nothing", |(_, item)| item.name.as_str());
+    ui.horizontal(|ui| {
+        ui.label("Attached to:")
+            .on_hover_text("Moves with the object, keeping where it is now");
+        egui::ComboBox::from_id_source(("Emitter parent", emitter.entity))
+            .selected_text(parent_name)
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut emitter.parent, None, "Nothing");
+                for (entity, item) in objects {
+                    ui.selectable_value(&mut emitter.parent, Some(*entity), item.name.as_str());
+                }
+            });
+    });
+
+    let settings = &mut emitter.settings;
+    ui.add(
+        egui::Slider::new(&mut settings.rate, 1.0..=10_000.0)
+            .text("Rate")
+            .logarithmic(true),
+    );
+    ui.add(egui::Slider::new(&mut settings.lifetime, 0.1..=30.0).text("Lifetime"));
+    ui.horizontal(|ui| {
+        ui.label("Direction:");
+        ui.add(
+            egui::DragValue::new(&mut settings.direction.x)
+                .speed(0.05)
+                .prefix("x: "),
+        );
+        ui.add(
+            egui::DragValue::new(&mut settings.direction.y)
+                .speed(0.05)
+                .prefix("y: "),
+        );
+        ui.add(
+            egui::DragValue::new(&mut settings.direction.z)
+                .speed(0.05)
+                .prefix("z: "),
+        );
+    });
+    ui.add(egui::Slider::new(&mut settings.spread, 0.0..=std::f32::consts::PI).text("Spread"));
+    ui.add(egui::Slider::new(&mut settings.speed, 0.0..=50.0).text("Speed"));
+    ui.horizontal(|ui| {
+        ui.label("Speed over lifetime:");
+        for value in settings.speed_curve.iter_mut() {
+            ui.add(
+                egui::DragValue::new(value)
+                    .speed(0.01)
+                    .clamp_range(0.0..=2.0),
+            );
+        }
+    });
+    ui.add(egui::Slider::new(&mut settings.gravity, -10.0..=20.0).text("Gravity"));
+    ui.add(egui::Slider::new(&mut settings.size_start, 0.01..=10.0).text("Start size"));
+    ui.add(egui::Slider::new(&mut settings.size_end, 0.01..=10.0).text("End size"));
+    ui.horizontal(|ui| {
+        let mut color = settings.color_start.to_array();
+        if ui.color_edit_button_rgba_unmultiplied(&mut color).changed() {
+            settings.color_start = color.into();
+        }
+        ui.label("Start");
+        let mut color = settings.color_end.to_array();
+        if ui.color_edit_button_rgba_unmultiplied(&mut color).changed() {
+            settings.color_end = color.into();
+        }
+        ui.label("End color");
+    });
+    ui.horizontal(|ui| {
+        ui.label("Spawn area:");
+        ui.add(
+            egui::DragValue::new(&mut settings.area.x)
+                .clamp_range(0.0..=500.0)
+                .prefix("x: "),
+        );
+        ui.add(
+            egui::DragValue::new(&mut settings.area.y)
+                .clamp_range(0.0..=500.0)
+                .prefix("z: "),
+        );
+    });
+    ui.add(egui::Slider::new(&mut settings.softness, 0.01..=5.0).text("Softness"));
+}
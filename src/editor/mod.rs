@@ -1 +1,271 @@
+pub mod align;
+pub mod assets;
+pub mod commands;
+pub mod console;
+pub mod dialogs;
 pub mod gui;
+pub mod outliner;
+pub mod palette;
+pub mod undo;
+
+use glam::Vec3;
+
+use crate::bricks;
+use crate::debug_view::DebugView;
+use crate::keybindings::KeyAction;
+
+/// Editor state shared between the GUI and the game loop
+pub struct EditorState {
+    /// Objects in `Game::scene`, the first one is the active object
+    pub selected_objects: Vec<hecs::Entity>,
+    pub grid_size: f32,
+    /// What the gizmo on the selected object does
+    pub transform_mode: TransformMode,
+    /// Gizmos move by `grid_size` and turn by `SNAP_ANGLE`
+    pub snap_to_grid: bool,
+    /// glTF file used by the "Add model" button
+    pub model_import_path: String,
+    /// Settings of the "Scatter" button
+    pub scatter_count: u32,
+    pub scatter_radius: f32,
+    /// Scattered instances further than this from the camera are drawn as impostors
+    pub impostor_distance: f32,
+    /// Skip the instances hidden behind the terrain and objects, see `hiz`
+    pub occlusion_culling: bool,
+    /// Image path used by the terrain layer import buttons
+    pub texture_import_path: String,
+    /// Derive a normal map from the albedo when importing one
+    pub generate_normals: bool,
+    pub normal_strength: f32,
+    pub skybox_capture: SkyboxCapture,
+    /// Toggled in the outliner
+    pub show_terrain: bool,
+    pub show_skybox: bool,
+    /// Text in the scene, see `Game::draw_labels`
+    pub show_labels: bool,
+    /// Bounds, axes and terrain normals, see `debug_draw`
+    pub show_debug_shapes: bool,
+    /// Switched with the F-keys
+    pub debug_view: DebugView,
+    pub show_render_targets: bool,
+    /// Index of the render target shown in the viewer
+    pub render_target: usize,
+    /// GPU pass timings and the frame time graph, see `profiler`
+    pub show_profiler: bool,
+    /// All the windows, see `KeyAction::ToggleGui`
+    pub show_gui: bool,
+    pub show_keybindings: bool,
+    /// Images, brushes and models on disk, see `assets`
+    pub show_assets: bool,
+    /// Render scale, antialiasing, shadows and the display, see `RenderSettings` and
+    /// `DisplaySettings`
+    pub show_graphics: bool,
+    /// Waiting for the key to bind to the action
+    pub rebinding: Option<KeyAction>,
+    /// What clicks in the viewport do, picked in the toolbar
+    pub tool: Tool,
+    /// Index of the terrain layer the paint tool paints with
+    pub paint_layer: usize,
+    /// What the bricks tool does
+    pub brick_tool: BrickTool,
+    /// Index into the brick catalog, picked with the number keys
+    pub brick_type: usize,
+    /// Quarter turns of the placed bricks around Y, see `KeyAction::RotateBrick`
+    pub brick_rotation: u8,
+    /// sRGB, from the palette or picked freely
+    pub brick_color: Vec3,
+    /// Plates a brick on the terrain can float over it before it's shown in orange
+    pub max_foundation_gap: f32,
+    /// Raise the terrain under bricks placed on it up to their base
+    pub flatten_foundations: bool,
+    /// Bricks don't fall while building
+    pub freeze_physics: bool,
+    /// An .ldr or .mpd file to paste
+    pub ldraw_import_path: String,
+    /// Name the selected bricks are saved under as a prefab
+    pub prefab_name: String,
+}
+
+impl Default for EditorState {
+    fn default() -> Self {
+        EditorState {
+            selected_objects: vec![],
+            grid_size: 10.0,
+            transform_mode: TransformMode::Translate,
+            snap_to_grid: false,
+            model_import_path: String::from("models/box/box.gltf"),
+            scatter_count: 100,
+            scatter_radius: 50.0,
+            impostor_distance: 150.0,
+            occlusion_culling: true,
+            texture_import_path: String::from("textures/"),
+            generate_normals: false,
+            normal_strength: 2.0,
+            skybox_capture: SkyboxCapture::default(),
+            show_terrain: true,
+            show_skybox: true,
+            show_labels: true,
+            show_debug_shapes: false,
+            debug_view: DebugView::Lit,
+            show_render_targets: false,
+            render_target: 0,
+            show_profiler: false,
+            show_gui: true,
+            show_keybindings: false,
+            show_assets: false,
+            show_graphics: false,
+            rebinding: None,
+            tool: Tool::Sculpt,
+            paint_layer: 0,
+            brick_tool: BrickTool::Place,
+            brick_type: 0,
+            brick_rotation: 0,
+            brick_color: bricks::palette::srgb(
+                bricks::palette::COLORS[bricks::palette::DEFAULT_COLOR].1,
+            ),
+            max_foundation_gap: 1.0,
+            flatten_foundations: false,
+            freeze_physics: false,
+            ldraw_import_path: String::from("models/bricks/wall.ldr"),
+            prefab_name: String::from("prefab"),
+        }
+    }
+}
+
+/// What clicks in the viewport do. The camera moves with the right mouse button
+/// whatever the tool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tool {
+    /// Shapes the terrain with the brush
+    Sculpt,
+    /// Paints a terrain layer with the brush
+    Paint,
+    /// Uses the brick tool, see `BrickTool`
+    Bricks,
+    /// Picks objects, shift adds to the selection
+    Select,
+    /// Clicks do nothing, for looking around
+    Camera,
+}
+
+impl Tool {
+    pub const ALL: [Tool; 5] = [
+        Tool::Sculpt,
+        Tool::Paint,
+        Tool::Bricks,
+        Tool::Select,
+        Tool::Camera,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Tool::Sculpt => "Sculpt",
+            Tool::Paint => "Paint",
+            Tool::Bricks => "Bricks",
+            Tool::Select => "Select",
+            Tool::Camera => "Camera",
+        }
+    }
+
+    /// Shows the brush on the terrain
+    pub fn uses_brush(self) -> bool {
+        matches!(self, Tool::Sculpt | Tool::Paint)
+    }
+}
+
+/// Gizmos turn objects by this many degrees at a time when snapping
+pub const SNAP_ANGLE: f32 = 15.0;
+
+/// Gizmo for the selected object. Bricks only ever get the translation one,
+/// since they stay on the grid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransformMode {
+    Translate,
+    Rotate,
+    Scale,
+}
+
+impl TransformMode {
+    pub const ALL: [TransformMode; 3] = [
+        TransformMode::Translate,
+        TransformMode::Rotate,
+        TransformMode::Scale,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TransformMode::Translate => "Move",
+            TransformMode::Rotate => "Rotate",
+            TransformMode::Scale => "Scale",
+        }
+    }
+
+    pub fn key_action(self) -> KeyAction {
+        match self {
+            TransformMode::Translate => KeyAction::TranslateGizmo,
+            TransformMode::Rotate => KeyAction::RotateGizmo,
+            TransformMode::Scale => KeyAction::ScaleGizmo,
+        }
+    }
+}
+
+/// What clicks do to bricks, see `bricks`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BrickTool {
+    Place,
+    /// Click or drag over an area to select bricks, shift adds to the selection
+    Select,
+    /// Click a brick to remove it, or drag over an area to remove everything there
+    Delete,
+    /// Click to put down a copy of the clipboard, chosen with Ctrl+V
+    Paste,
+}
+
+impl BrickTool {
+    pub const ALL: [BrickTool; 4] = [
+        BrickTool::Place,
+        BrickTool::Select,
+        BrickTool::Delete,
+        BrickTool::Paste,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BrickTool::Place => "Place",
+            BrickTool::Select => "Select",
+            BrickTool::Delete => "Delete",
+            BrickTool::Paste => "Paste",
+        }
+    }
+
+    /// Selects the tool when using the bricks tool
+    pub fn key_action(self) -> Option<KeyAction> {
+        match self {
+            BrickTool::Place => Some(KeyAction::PlaceTool),
+            BrickTool::Select => Some(KeyAction::SelectTool),
+            BrickTool::Delete => Some(KeyAction::DeleteTool),
+            BrickTool::Paste => None,
+        }
+    }
+}
+
+/// Settings of the tool which renders the scene into skybox images
+#[derive(Debug, Clone)]
+pub struct SkyboxCapture {
+    pub position: Vec3,
+    /// Time of day to capture at, current time if None
+    pub hour: Option<f32>,
+    pub face_size: u32,
+    pub output_dir: String,
+}
+
+impl Default for SkyboxCapture {
+    fn default() -> Self {
+        SkyboxCapture {
+            position: Vec3::new(0.0, 250.0, 0.0),
+            hour: None,
+            face_size: 1024,
+            output_dir: String::from("textures/skybox/captured"),
+        }
+    }
+}
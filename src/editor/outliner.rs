@@ -0,0 +1,126 @@
+use egui::{Align2, CtxRef, Ui};
+use hecs::Entity;
+
+use crate::atmosphere::Atmosphere;
+use crate::editor::gui::Action;
+use crate::editor::EditorState;
+use crate::instancing::MeshId;
+use crate::water::Water;
+
+/// A row of the outliner
+pub struct OutlinerItem {
+    pub name: String,
+    pub visible: bool,
+    pub selected: bool,
+    /// Of the instances or bricks, shown after the name
+    pub count: Option<usize>,
+}
+
+/// What's in the scene besides the terrain, sky and lights, which the outliner
+/// changes directly
+#[derive(Default)]
+pub struct SceneItems {
+    /// In the order of `Scene::objects`
+    pub objects: Vec<(Entity, OutlinerItem)>,
+    /// Scattered meshes
+    pub meshes: Vec<(MeshId, OutlinerItem)>,
+    /// Bricks of each type in the catalog, those with none placed are left out
+    pub brick_groups: Vec<(usize, OutlinerItem)>,
+}
+
+/// Lists everything in the scene with visibility toggles. Selecting objects here is the
+/// same as picking them in the viewport with the Select tool.
+pub fn show(
+    ctx: &CtxRef,
+    items: &SceneItems,
+    editor_state: &mut EditorState,
+    atmosphere: &mut Atmosphere,
+    water: &mut Water,
+    actions: &mut Vec<Action>,
+) {
+    egui::Window::new("Outliner")
+        .anchor(Align2::LEFT_BOTTOM, egui::Vec2::new(10.0, -10.0))
+        .resizable(false)
+        .show(ctx, |ui| {
+            ui.checkbox(&mut editor_state.show_terrain, "Terrain");
+            ui.checkbox(&mut water.enabled, "Water");
+            ui.checkbox(&mut editor_state.show_skybox, "Skybox");
+            ui.checkbox(&mut atmosphere.clouds.enabled, "Clouds");
+            ui.checkbox(&mut atmosphere.sun_enabled, "Sun")
+                .on_hover_text("The light and its shadows");
+
+            ui.collapsing("Objects", |ui| {
+                let shift = ui.input().modifiers.shift;
+                let selection = &mut editor_state.selected_objects;
+                for &(entity, ref item) in &items.objects {
+                    let (toggled, clicked) = row(ui, item);
+                    if let Some(visible) = toggled {
+                        actions.push(Action::SetObjectVisible { entity, visible });
+                    }
+                    if !clicked {
+                        continue;
+                    }
+                    if shift {
+                        // Add to or remove from selection
+                        if item.selected {
+                            selection.retain(|&e| e != entity);
+                        } else {
+                            selection.push(entity);
+                        }
+                    } else {
+                        selection.clear();
+                        selection.push(entity);
+                    }
+                }
+            });
+
+            if !items.meshes.is_empty() {
+                ui.collapsing("Scattered", |ui| {
+                    for (mesh, item) in &items.meshes {
+                        if let (Some(visible), _) = row(ui, item) {
+                            actions.push(Action::SetMeshVisible {
+                                mesh: *mesh,
+                                visible,
+                            });
+                        }
+                    }
+                });
+            }
+
+            if !items.brick_groups.is_empty() {
+                ui.collapsing("Bricks", |ui| {
+                    let add = ui.input().modifiers.shift;
+                    for (kind, item) in &items.brick_groups {
+                        let (toggled, clicked) = row(ui, item);
+                        if let Some(visible) = toggled {
+                            actions.push(Action::SetBrickGroupVisible {
+                                kind: *kind,
+                                visible,
+                            });
+                        }
+                        if clicked {
+                            actions.push(Action::SelectBrickGroup { kind: *kind, add });
+                        }
+                    }
+                });
+            }
+        });
+}
+
+/// Returns the new visibility if it's been toggled, and whether the name was clicked
+fn row(ui: &mut Ui, item: &OutlinerItem) -> (Option<bool>, bool) {
+    let mut visible = item.visible;
+    let text = match item.count {
+        Some(count) => format!("{} ({})", item.name, count),
+        None => item.name.clone(),
+    };
+    ui.horizontal(|ui| {
+        let toggled = ui
+            .checkbox(&mut visible, "")
+            .on_hover_text("Visible")
+            .changed();
+        let clicked = ui.selectable_label(item.selected, text).clicked();
+        (toggled.then(|| visible), clicked)
+    })
+    .inner
+}
@@ -0,0 +1,131 @@
+use egui::{Align2, CtxRef, Key};
+
+use crate::editor::commands::{Command, CommandRegistry};
+use crate::editor::gui::Action;
+
+const MAX_RESULTS: usize = 12;
+
+/// Ctrl+Shift+P fuzzy finder over the registered commands
+#[derive(Default)]
+pub struct CommandPalette {
+    open: bool,
+    query: String,
+    selected: usize,
+}
+
+impl CommandPalette {
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+        self.query.clear();
+        self.selected = 0;
+    }
+
+    pub fn show(&mut self, ctx: &CtxRef, commands: &CommandRegistry, actions: &mut Vec<Action>) {
+        let input = ctx.input();
+        if input.modifiers.ctrl && input.modifiers.shift && input.key_pressed(Key::P) {
+            self.toggle();
+        }
+        if !self.open {
+            return;
+        }
+
+        let mut matches: Vec<(i32, &Command)> = commands
+            .iter()
+            .filter(|command| !command.takes_args())
+            .filter_map(|command| {
+                let text = format!("{} {}", command.description, command.name);
+                fuzzy_score(&self.query, &text).map(|score| (score, command))
+            })
+            .collect();
+        matches.sort_by(|(a, _), (b, _)| b.cmp(a));
+        matches.truncate(MAX_RESULTS);
+
+        if input.key_pressed(Key::Escape) {
+            self.open = false;
+            return;
+        }
+        if input.key_pressed(Key::ArrowDown) {
+            self.selected += 1;
+        }
+        if input.key_pressed(Key::ArrowUp) {
+            self.selected = self.selected.saturating_sub(1);
+        }
+        self.selected = self.selected.min(matches.len().saturating_sub(1));
+
+        let mut chosen = None;
+        if input.key_pressed(Key::Enter) {
+            chosen = matches.get(self.selected).map(|(_, command)| *command);
+        }
+
+        let query = &mut self.query;
+        let selected = &mut self.selected;
+        egui::Window::new("Command palette")
+            .anchor(Align2::CENTER_TOP, egui::Vec2::new(0.0, 60.0))
+            .collapsible(false)
+            .resizable(false)
+            .title_bar(false)
+            .default_width(400.0)
+            .show(ctx, |ui| {
+                let response = ui.add(
+                    egui::TextEdit::singleline(query)
+                        .hint_text("Type a command")
+                        .desired_width(f32::INFINITY)
+                        .lock_focus(true),
+                );
+                response.request_focus();
+                if response.changed() {
+                    *selected = 0;
+                }
+
+                ui.separator();
+
+                for (i, (_, command)) in matches.iter().enumerate() {
+                    let label = format!("{}    ({})", command.description, command.name);
+                    if ui.selectable_label(i == *selected, label).clicked() {
+                        chosen = Some(*command);
+                    }
+                }
+                if matches.is_empty() {
+                    ui.label("No matching commands");
+                }
+            });
+
+        if let Some(command) = chosen {
+            // Only the commands without arguments are listed, so they can't fail
+            if let Ok(action) = command.run(&[]) {
+                actions.push(action);
+            }
+            self.open = false;
+        }
+    }
+}
+
+/// Case-insensitive subsequence match.
+/// Consecutive characters and characters at word starts score higher.
+fn fuzzy_score(pattern: &str, text: &str) -> Option<i32> {
+    let text: Vec<char> = text.chars().flat_map(char::to_lowercase).collect();
+    let mut score = 0;
+    let mut text_pos = 0;
+    let mut prev_match: Option<usize> = None;
+
+    for p in pattern.chars().flat_map(char::to_lowercase) {
+        if p.is_whitespace() {
+            continue;
+        }
+        let found = text[text_pos..].iter().position(|&c| c == p)? + text_pos;
+
+        score += 1;
+        if prev_match == Some(found.wrapping_sub(1)) {
+            score += 5; // consecutive
+        }
+        if found == 0 || !text[found - 1].is_alphanumeric() {
+            score += 3; // word start
+        }
+
+        prev_match = Some(found);
+        text_pos = found + 1;
+    }
+
+    // Prefer shorter texts when everything else is equal
+    Some(score * 100 - text.len() as i32)
+}
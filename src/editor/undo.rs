@@ -0,0 +1,191 @@
+//! What Ctrl+Z and Ctrl+Y go through, in the order the edits were made. Brick edits
+//! keep their own history in `bricks::history` and only leave a marker here. Sculpting
+//! and painting keep a copy of the terrain from before each stroke, and placed objects
+//! are kept when they're taken away, so that redo can put them back.
+
+use std::collections::VecDeque;
+
+use hecs::Entity;
+
+use crate::scene::RemovedObject;
+use crate::splat::SplatCopy;
+use crate::terrain::HeightsCopy;
+
+/// Copies of the terrain take megabytes each, the older ones are forgotten past this
+/// many whatever the depth
+const MAX_TERRAIN_COPIES: usize = 16;
+
+pub enum Undo {
+    /// The next edit in the brick history
+    Bricks,
+    /// The heightmap on the other side of a stroke, swapped with the terrain's
+    Heights(HeightsCopy),
+    /// The splatmap on the other side of a stroke, swapped likewise
+    Splat(SplatCopy),
+    /// Placed with the "Add model" button
+    Object(PlacedObject),
+}
+
+impl Undo {
+    fn is_terrain_copy(&self) -> bool {
+        matches!(self, Undo::Heights(_) | Undo::Splat(_))
+    }
+}
+
+pub enum PlacedObject {
+    /// In the scene, undoing takes it away
+    Spawned(Entity),
+    /// Taken away, redoing puts it back
+    Removed(RemovedObject),
+}
+
+pub struct UndoStack {
+    done: VecDeque<Undo>,
+    undone: Vec<Undo>,
+    /// The oldest edits are forgotten past this many
+    depth: usize,
+}
+
+impl UndoStack {
+    pub fn new(depth: usize) -> Self {
+        UndoStack {
+            done: VecDeque::new(),
+            undone: vec![],
+            depth,
+        }
+    }
+
+    /// Forgets the undone edits, they can't be redone after a new one
+    pub fn push(&mut self, edit: Undo) {
+        self.undone.clear();
+        self.done.push_back(edit);
+        while self.done.len() > self.depth {
+            self.done.pop_front();
+        }
+        let copies = self
+            .done
+            .iter()
+            .filter(|edit| edit.is_terrain_copy())
+            .count();
+        if copies > MAX_TERRAIN_COPIES {
+            if let Some(oldest) = self.done.iter().position(Undo::is_terrain_copy) {
+                self.done.remove(oldest);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.done.clear();
+        self.undone.clear();
+    }
+
+    /// The last edit, to be reverted and handed to `undone`
+    pub fn take_undo(&mut self) -> Option<Undo> {
+        self.done.pop_back()
+    }
+
+    /// The edit as it is after being reverted, for redo
+    pub fn undone(&mut self, edit: Undo) {
+        self.undone.push(edit);
+    }
+
+    /// The last undone edit, to be applied again and handed to `redone`
+    pub fn take_redo(&mut self) -> Option<Undo> {
+        self.undone.pop()
+    }
+
+    pub fn redone(&mut self, edit: Undo) {
+        self.done.push_back(edit);
+    }
+
+    /// Moves the last brick marker to redo, for when the bricks are undone on their own
+    /// from the brick panel
+    pub fn undo_bricks(&mut self) {
+        if let Some(index) = self
+            .done
+            .iter()
+            .rposition(|edit| matches!(edit, Undo::Bricks))
+        {
+            self.done.remove(index);
+            self.undone.push(Undo::Bricks);
+        }
+    }
+
+    /// The other way around from `undo_bricks`
+    pub fn redo_bricks(&mut self) {
+        if let Some(index) = self
+            .undone
+            .iter()
+            .rposition(|edit| matches!(edit, Undo::Bricks))
+        {
+            self.undone.remove(index);
+            self.done.push_back(Undo::Bricks);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spawned(world: &mut hecs::World) -> Undo {
+        Undo::Object(PlacedObject::Spawned(world.spawn(())))
+    }
+
+    fn entity(edit: Option<Undo>) -> Option<Entity> {
+        match edit {
+            Some(Undo::Object(PlacedObject::Spawned(entity))) => Some(entity),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn oldest_edits_are_forgotten_past_the_depth() {
+        let mut world = hecs::World::new();
+        let mut stack = UndoStack::new(3);
+        let entities: Vec<Entity> = (0..5).map(|_| world.spawn(())).collect();
+        for &placed in &entities {
+            stack.push(Undo::Object(PlacedObject::Spawned(placed)));
+        }
+        assert_eq!(entity(stack.take_undo()), Some(entities[4]));
+        assert_eq!(entity(stack.take_undo()), Some(entities[3]));
+        assert_eq!(entity(stack.take_undo()), Some(entities[2]));
+        assert!(stack.take_undo().is_none());
+    }
+
+    #[test]
+    fn a_new_edit_forgets_the_undone_ones() {
+        let mut world = hecs::World::new();
+        let mut stack = UndoStack::new(10);
+        stack.push(spawned(&mut world));
+        let edit = stack.take_undo().unwrap();
+        stack.undone(edit);
+        stack.push(spawned(&mut world));
+        assert!(stack.take_redo().is_none());
+    }
+
+    #[test]
+    fn brick_markers_move_between_undo_and_redo() {
+        let mut world = hecs::World::new();
+        let mut stack = UndoStack::new(10);
+        stack.push(Undo::Bricks);
+        stack.push(spawned(&mut world));
+        stack.undo_bricks();
+        // The object on top is still the next to undo
+        assert!(entity(stack.take_undo()).is_some());
+        assert!(stack.take_undo().is_none());
+
+        stack.redo_bricks();
+        assert!(matches!(stack.take_undo(), Some(Undo::Bricks)));
+        assert!(stack.take_redo().is_none());
+    }
+
+    #[test]
+    fn zero_depth_keeps_nothing() {
+        let mut world = hecs::World::new();
+        let mut stack = UndoStack::new(0);
+        stack.push(spawned(&mut world));
+        stack.push(Undo::Bricks);
+        assert!(stack.take_undo().is_none());
+    }
+}
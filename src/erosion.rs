@@ -0,0 +1,262 @@
+use glam::Vec2;
+
+use crate::heightfield::HeightField;
+use crate::jobs::JobSystem;
+use crate::utils::XorShift;
+
+/// Along each side of the field in `erode_parallel`
+const TILES: usize = 4;
+/// The droplets are split between them. Every other round the tiles are shifted by half
+/// a tile, so that the seams where the droplets of the other rounds stop get eroded too.
+const ROUNDS: usize = 4;
+
+/// Droplet-based hydraulic erosion parameters
+#[derive(Debug, Clone, Copy)]
+pub struct ErosionParams {
+    pub iterations: usize,
+    pub seed: u64,
+    /// How much a droplet keeps its direction instead of following the slope, [0, 1]
+    pub inertia: f32,
+    pub sediment_capacity: f32,
+    pub min_sediment_capacity: f32,
+    pub erode_speed: f32,
+    pub deposit_speed: f32,
+    pub evaporate_speed: f32,
+    pub gravity: f32,
+    pub max_droplet_lifetime: usize,
+}
+
+impl Default for ErosionParams {
+    fn default() -> Self {
+        ErosionParams {
+            iterations: 50_000,
+            seed: 1,
+            inertia: 0.05,
+            sediment_capacity: 4.0,
+            min_sediment_capacity: 0.01,
+            erode_speed: 0.3,
+            deposit_speed: 0.3,
+            evaporate_speed: 0.01,
+            gravity: 4.0,
+            max_droplet_lifetime: 30,
+        }
+    }
+}
+
+/// Simulates `params.iterations` rain droplets, each carving sediment out of the slopes
+/// it runs down and depositing it where it slows down.
+/// `height_scale` converts heightfield values to pixel units so that slopes are meaningful.
+pub fn erode(field: &mut HeightField, height_scale: f32, params: &ErosionParams) {
+    let max_pos = Vec2::new((field.size - 2) as f32, (field.rows() - 2) as f32);
+    let mut rng = XorShift(params.seed.max(1));
+
+    // Work in pixel units
+    for h in field.heights.iter_mut() {
+        *h *= height_scale;
+    }
+
+    for _ in 0..params.iterations {
+        let mut pos = Vec2::new(rng.next_f32() * max_pos.x, rng.next_f32() * max_pos.y);
+        let mut dir = Vec2::ZERO;
+        let mut speed = 1.0;
+        let mut water = 1.0;
+        let mut sediment = 0.0;
+
+        for _ in 0..params.max_droplet_lifetime {
+            let (old_height, gradient) = height_and_gradient(field, pos);
+
+            dir = dir * params.inertia - gradient * (1.0 - params.inertia);
+            if dir.length_squared() < 1e-12 {
+                break; // flat ground, the droplet stops
+            }
+            dir = dir.normalize();
+            let old_pos = pos;
+            pos += dir;
+            if pos.x < 0.0 || pos.y < 0.0 || pos.x > max_pos.x || pos.y > max_pos.y {
+                break;
+            }
+
+            let (new_height, _) = height_and_gradient(field, pos);
+            let delta_height = new_height - old_height;
+
+            let capacity = (-delta_height).max(params.min_sediment_capacity)
+                * speed
+                * water
+                * params.sediment_capacity;
+
+            if sediment > capacity || delta_height > 0.0 {
+                // Going uphill fills the pit behind, otherwise drop the excess
+                let amount = if delta_height > 0.0 {
+                    delta_height.min(sediment)
+                } else {
+                    (sediment - capacity) * params.deposit_speed
+                };
+                sediment -= amount;
+                distribute(field, old_pos, amount);
+            } else {
+                // Never dig deeper than the height difference, that would create spikes
+                let amount = ((capacity - sediment) * params.erode_speed).min(-delta_height);
+                sediment += amount;
+                distribute(field, old_pos, -amount);
+            }
+
+            speed = (speed * speed + delta_height.abs() * params.gravity).sqrt();
+            water *= 1.0 - params.evaporate_speed;
+        }
+    }
+
+    for h in field.heights.iter_mut() {
+        *h = (*h / height_scale).clamp(0.0, 1.0);
+    }
+}
+
+/// Same as `erode`, with the field cut into tiles which are eroded on the workers at
+/// the same time. Each droplet stays on its tile. Gives the same result for the same
+/// seed however many workers there are.
+pub fn erode_parallel(
+    field: &mut HeightField,
+    height_scale: f32,
+    params: &ErosionParams,
+    jobs: &JobSystem,
+) {
+    for round in 0..ROUNDS {
+        let round_iterations = share(params.iterations, ROUNDS, round);
+        let tiles = round_tiles(field.size, round);
+        let mut eroding = Vec::with_capacity(tiles.len());
+        for (tile, &(x, y, width, height)) in tiles.iter().enumerate() {
+            let mut region = field.region(x, y, width, height);
+            let tile_params = ErosionParams {
+                iterations: share(round_iterations, tiles.len(), tile),
+                seed: params
+                    .seed
+                    .wrapping_add((round * TILES * TILES + tile) as u64 * 0x9E37_79B9_7F4A_7C15),
+                ..*params
+            };
+            let job = jobs.spawn(move || {
+                erode(&mut region, height_scale, &tile_params);
+                region
+            });
+            eroding.push((x, y, job));
+        }
+        // The tiles don't overlap, only the next round has to wait
+        for (x, y, job) in eroding {
+            field.set_region(x, y, &job.wait());
+        }
+    }
+}
+
+/// The corner and the size of each tile of the round, row by row. The tiles along the far
+/// edges take the cells left over when the field doesn't divide evenly.
+fn round_tiles(size: usize, round: usize) -> Vec<(usize, usize, usize, usize)> {
+    let tile_size = size / TILES;
+    let (offset, tiles) = if round % 2 == 0 {
+        (0, TILES)
+    } else {
+        (tile_size / 2, TILES - 1)
+    };
+    let span = |index: usize| {
+        let start = offset + index * tile_size;
+        let end = if index + 1 == tiles {
+            size - offset
+        } else {
+            start + tile_size
+        };
+        (start, end - start)
+    };
+    (0..tiles * tiles)
+        .map(|tile| {
+            let (x, width) = span(tile % tiles);
+            let (y, height) = span(tile / tiles);
+            (x, y, width, height)
+        })
+        .collect()
+}
+
+/// Splits `total` into `parts` that differ by one at most, the first ones get the rest
+fn share(total: usize, parts: usize, index: usize) -> usize {
+    total / parts + usize::from(index < total % parts)
+}
+
+fn height_and_gradient(field: &HeightField, pos: Vec2) -> (f32, Vec2) {
+    let (x, y) = (pos.x as usize, pos.y as usize);
+    let (u, v) = (pos.x.fract(), pos.y.fract());
+    let h00 = field.get(x, y);
+    let h10 = field.get(x + 1, y);
+    let h01 = field.get(x, y + 1);
+    let h11 = field.get(x + 1, y + 1);
+
+    let gradient = Vec2::new(
+        (h10 - h00) * (1.0 - v) + (h11 - h01) * v,
+        (h01 - h00) * (1.0 - u) + (h11 - h10) * u,
+    );
+    let height =
+        h00 * (1.0 - u) * (1.0 - v) + h10 * u * (1.0 - v) + h01 * (1.0 - u) * v + h11 * u * v;
+    (height, gradient)
+}
+
+/// Adds `amount` to the 4 pixels around `pos` with bilinear weights
+fn distribute(field: &mut HeightField, pos: Vec2, amount: f32) {
+    let size = field.size;
+    let (x, y) = (pos.x as usize, pos.y as usize);
+    let (u, v) = (pos.x.fract(), pos.y.fract());
+    let index = y * size + x;
+    field.heights[index] += amount * (1.0 - u) * (1.0 - v);
+    field.heights[index + 1] += amount * u * (1.0 - v);
+    field.heights[index + size] += amount * (1.0 - u) * v;
+    field.heights[index + size + 1] += amount * u * v;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// How many tiles of the round cover each cell of the field
+    fn coverage(size: usize, round: usize) -> Vec<u32> {
+        let mut cells = vec![0; size * size];
+        for (x, y, width, height) in round_tiles(size, round) {
+            for row in y..y + height {
+                for column in x..x + width {
+                    cells[row * size + column] += 1;
+                }
+            }
+        }
+        cells
+    }
+
+    #[test]
+    fn even_rounds_cover_the_whole_field_once() {
+        for size in [16, 257, 1000, 1024] {
+            assert!(
+                coverage(size, 0).iter().all(|&count| count == 1),
+                "size {}",
+                size
+            );
+        }
+    }
+
+    #[test]
+    fn shifted_rounds_cover_the_inside_once() {
+        for size in [16, 257, 1000, 1024] {
+            let offset = size / TILES / 2;
+            let cells = coverage(size, 1);
+            for y in 0..size {
+                for x in 0..size {
+                    let inside = (offset..size - offset).contains(&x)
+                        && (offset..size - offset).contains(&y);
+                    assert_eq!(cells[y * size + x], u32::from(inside), "size {}", size);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn shares_add_up_to_the_total() {
+        for (total, parts) in [(0, 3), (10, 3), (100_000, 7), (5, 8)] {
+            let shares: Vec<usize> = (0..parts).map(|i| share(total, parts, i)).collect();
+            assert_eq!(shares.iter().sum::<usize>(), total);
+            let max = shares.iter().max().unwrap();
+            let min = shares.iter().min().unwrap();
+            assert!(max - min <= 1);
+        }
+    }
+}
@@ -0,0 +1,27 @@
+//! Caps the frame rate, to save the battery while editing or to hold it steady while
+//! comparing frame times with vsync off. Sleeping wakes up late by up to a scheduler
+//! tick, so the last moment before the frame is due is waited out spinning.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Sleeping is only trusted to wake up this long before the frame is due
+const SPIN_TIME: Duration = Duration::from_millis(2);
+
+/// Returns once the frame which started at `frame_start` has taken as long as a frame
+/// at `max_fps` does
+pub fn wait_for_frame(frame_start: Instant, max_fps: u32) {
+    let due = frame_start + Duration::from_secs_f64(1.0 / max_fps.max(1) as f64);
+    loop {
+        let now = Instant::now();
+        if now >= due {
+            return;
+        }
+        let left = due - now;
+        if left > SPIN_TIME {
+            thread::sleep(left - SPIN_TIME);
+        } else {
+            std::hint::spin_loop();
+        }
+    }
+}
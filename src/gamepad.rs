@@ -0,0 +1,129 @@
+//! Gamepads, through gilrs. The sticks and triggers end up in the same `Input` as
+//! the keyboard and mouse, so either can be used at any time: the left stick moves
+//! the camera, the right stick turns it, and the right and left triggers raise and
+//! lower the terrain under the cursor.
+
+use gilrs::{Axis, Button, Gilrs};
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+use crate::input::{Input, InputAxis};
+
+/// Kept in `Settings`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct GamepadSettings {
+    /// How far a stick or trigger has to move before it counts, [0, 1)
+    pub dead_zone: f32,
+    /// Radians per second with the right stick all the way over
+    pub look_sensitivity: f32,
+    /// Fraction of the camera speed with the left stick all the way over, at most 1
+    pub move_sensitivity: f32,
+    pub invert_y: bool,
+}
+
+impl Default for GamepadSettings {
+    fn default() -> Self {
+        GamepadSettings {
+            dead_zone: 0.15,
+            look_sensitivity: 2.5,
+            move_sensitivity: 1.0,
+            invert_y: false,
+        }
+    }
+}
+
+pub struct Gamepads {
+    /// None if the platform has no gamepad support
+    gilrs: Option<Gilrs>,
+    pub settings: GamepadSettings,
+}
+
+impl Gamepads {
+    pub fn new(settings: GamepadSettings) -> Self {
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => Some(gilrs),
+            Err(err) => {
+                log!("Gamepads are not available: {}", err);
+                None
+            }
+        };
+        Gamepads { gilrs, settings }
+    }
+
+    /// Puts the state of the sticks and triggers into the input. With several
+    /// gamepads connected, the one pushed furthest wins.
+    pub fn poll(&mut self, input: &mut Input) {
+        let gilrs = match &mut self.gilrs {
+            Some(gilrs) => gilrs,
+            None => return,
+        };
+        // The state is only updated while processing the events
+        while gilrs.next_event().is_some() {}
+
+        let settings = &self.settings;
+        let mut state = GamepadInput::default();
+        for (_, gamepad) in gilrs.gamepads() {
+            let stick = |x, y| {
+                let stick = Vec2::new(gamepad.value(x), gamepad.value(y));
+                radial_dead_zone(stick, settings.dead_zone)
+            };
+            let trigger = |button| {
+                let value = gamepad.button_data(button).map_or(0.0, |data| data.value());
+                axial_dead_zone(value, settings.dead_zone)
+            };
+            let pad = GamepadInput {
+                movement: stick(Axis::LeftStickX, Axis::LeftStickY),
+                look: stick(Axis::RightStickX, Axis::RightStickY),
+                raise: trigger(Button::RightTrigger2),
+                lower: trigger(Button::LeftTrigger2),
+            };
+            if pad.magnitude() > state.magnitude() {
+                state = pad;
+            }
+        }
+
+        // Up on the stick is forward, and looks up unless inverted
+        let movement = state.movement * settings.move_sensitivity;
+        input.set_analog(InputAxis::MoveForward, movement.y);
+        input.set_analog(InputAxis::MoveRight, movement.x);
+        let look_y = if settings.invert_y { -1.0 } else { 1.0 };
+        let look = Vec2::new(state.look.x, state.look.y * look_y) * settings.look_sensitivity;
+        input.set_analog(InputAxis::LookRight, look.x);
+        input.set_analog(InputAxis::LookUp, look.y);
+        input.set_analog(InputAxis::Brush, state.raise - state.lower);
+    }
+}
+
+#[derive(Default)]
+struct GamepadInput {
+    movement: Vec2,
+    look: Vec2,
+    raise: f32,
+    lower: f32,
+}
+
+impl GamepadInput {
+    fn magnitude(&self) -> f32 {
+        self.movement.length() + self.look.length() + self.raise + self.lower
+    }
+}
+
+/// Zero inside the dead zone, then growing from zero so that there's no jump at its
+/// edge. Round so that diagonals aren't harder to reach.
+fn radial_dead_zone(stick: Vec2, dead_zone: f32) -> Vec2 {
+    let length = stick.length();
+    if length <= dead_zone {
+        return Vec2::ZERO;
+    }
+    let scaled = ((length - dead_zone) / (1.0 - dead_zone)).min(1.0);
+    stick * (scaled / length)
+}
+
+fn axial_dead_zone(value: f32, dead_zone: f32) -> f32 {
+    if value <= dead_zone {
+        0.0
+    } else {
+        ((value - dead_zone) / (1.0 - dead_zone)).min(1.0)
+    }
+}
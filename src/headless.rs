@@ -0,0 +1,67 @@
+//! A GL context without a window, for the commands which need the GPU so that they can
+//! run in CI and batch jobs. It's surfaceless or backed by a pbuffer where the platform
+//! allows that, otherwise it falls back to a window which is never shown. On Linux
+//! there still has to be a display to connect to, e.g. one from xvfb-run.
+
+use glutin::dpi::PhysicalSize;
+use glutin::event_loop::EventLoop;
+use glutin::window::WindowBuilder;
+use glutin::{Api, ContextBuilder, GlProfile, GlRequest, NotCurrent};
+use glutin::{Context, PossiblyCurrent, WindowedContext};
+
+use crate::Result;
+
+/// Only kept alive, the GL calls go to the current context
+#[allow(dead_code)]
+enum Surface {
+    Headless(Context<PossiblyCurrent>),
+    /// Where there's no way to make a context without a window
+    Hidden(WindowedContext<PossiblyCurrent>),
+}
+
+pub struct HeadlessContext {
+    // Before the event loop, so that it's dropped first
+    _surface: Surface,
+    _event_loop: EventLoop<()>,
+}
+
+impl HeadlessContext {
+    /// Makes the context current and loads the GL functions. It has to stay alive for
+    /// as long as GL is used.
+    pub fn new() -> Result<Self> {
+        let event_loop = EventLoop::new();
+        let surface = match builder().build_headless(&event_loop, PhysicalSize::new(1, 1)) {
+            Ok(context) => {
+                let context = unsafe { context.make_current() }.map_err(|(_, err)| err)?;
+                gl::load_with(|s| context.get_proc_address(s) as *const _);
+                Surface::Headless(context)
+            }
+            Err(err) => {
+                log!("No headless context ({}), using a hidden window", err);
+                let window_builder = WindowBuilder::new()
+                    .with_title("Мёртвый трилистник")
+                    .with_visible(false);
+                let context = builder().build_windowed(window_builder, &event_loop)?;
+                let context = unsafe { context.make_current() }.map_err(|(_, err)| err)?;
+                gl::load_with(|s| context.get_proc_address(s) as *const _);
+                Surface::Hidden(context)
+            }
+        };
+
+        #[cfg(debug_assertions)]
+        crate::opengl::enable_debug_output(crate::opengl::DebugSeverity::default(), false);
+
+        Ok(HeadlessContext {
+            _surface: surface,
+            _event_loop: event_loop,
+        })
+    }
+}
+
+/// The same version and profile as the editor, without the framebuffer settings
+fn builder() -> ContextBuilder<'static, NotCurrent> {
+    ContextBuilder::new()
+        .with_gl(GlRequest::Specific(Api::OpenGl, (4, 5)))
+        .with_gl_profile(GlProfile::Core)
+        .with_gl_debug_flag(cfg!(debug_assertions))
+}
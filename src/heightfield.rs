@@ -0,0 +1,146 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use image::{GenericImageView, ImageBuffer, Luma};
+
+use crate::Result;
+
+pub const SUPPORTED_SIZES: [usize; 3] = [1024, 2048, 4096];
+
+/// CPU copy of a square 16-bit heightmap, 0 is the lowest point, 1 the highest
+pub struct HeightField {
+    /// Along a row, see `rows`
+    pub size: usize,
+    pub heights: Vec<f32>,
+}
+
+impl HeightField {
+    /// Loads any grayscale-convertible image. The size must be one the terrain supports
+    /// unless `resize_to` is given.
+    pub fn load(path: &str, resize_to: Option<usize>) -> Result<Self> {
+        let mut img = image::open(path)?;
+        if let Some(size) = resize_to {
+            img = img.resize_exact(
+                size as u32,
+                size as u32,
+                image::imageops::FilterType::Triangle,
+            );
+        }
+        let (width, height) = img.dimensions();
+        if width != height {
+            return Err(format!(
+                "Only square heightmaps are supported, got {}x{}",
+                width, height
+            )
+            .into());
+        }
+        let size = width as usize;
+        if !SUPPORTED_SIZES.contains(&size) {
+            return Err(format!(
+                "Only heightmaps with sizes {:?} are supported, got {}",
+                SUPPORTED_SIZES, size
+            )
+            .into());
+        }
+
+        let heights = img
+            .into_luma16()
+            .into_raw()
+            .into_iter()
+            .map(|h| h as f32 / u16::MAX as f32)
+            .collect();
+        Ok(HeightField { size, heights })
+    }
+
+    pub fn to_pixels(&self) -> Vec<u16> {
+        self.heights
+            .iter()
+            .map(|h| (h.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16)
+            .collect()
+    }
+
+    /// Saves as a 16-bit grayscale PNG, the format the editor saves terrain in
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let img: ImageBuffer<Luma<u16>, Vec<u16>> =
+            ImageBuffer::from_raw(self.size as u32, self.size as u32, self.to_pixels()).unwrap();
+        img.save(path)?;
+        Ok(())
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> f32 {
+        self.heights[y * self.size + x]
+    }
+
+    /// Same as `size` unless it's a `region`, which needn't be square
+    pub fn rows(&self) -> usize {
+        self.heights.len() / self.size
+    }
+
+    /// A copy of the rectangle with the corner at `x`, `y`
+    pub fn region(&self, x: usize, y: usize, width: usize, height: usize) -> HeightField {
+        let mut heights = Vec::with_capacity(width * height);
+        for row in y..y + height {
+            let start = row * self.size + x;
+            heights.extend_from_slice(&self.heights[start..start + width]);
+        }
+        HeightField {
+            size: width,
+            heights,
+        }
+    }
+
+    /// Copies a `region` back to where it was taken from
+    pub fn set_region(&mut self, x: usize, y: usize, region: &HeightField) {
+        for (row, heights) in region.heights.chunks_exact(region.size).enumerate() {
+            let start = (y + row) * self.size + x;
+            self.heights[start..start + region.size].copy_from_slice(heights);
+        }
+    }
+
+    /// Writes a Wavefront OBJ grid mesh of the terrain centered at the origin,
+    /// taking every `step`-th pixel
+    pub fn write_obj(
+        &self,
+        path: impl AsRef<Path>,
+        step: usize,
+        terrain_size: f32,
+        max_height: f32,
+    ) -> Result<()> {
+        let step = step.max(1);
+        let vertices_per_side = (self.size - 1) / step + 1;
+        let texel_size = terrain_size / self.size as f32;
+        let half_size = terrain_size / 2.0;
+
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "# Terrain {0}x{0}", vertices_per_side)?;
+        for row in 0..vertices_per_side {
+            for col in 0..vertices_per_side {
+                let (x, y) = (col * step, row * step);
+                // Texel centers, same as the editor samples them
+                let world_x = (x as f32 + 0.5) * texel_size - half_size;
+                let world_z = (y as f32 + 0.5) * texel_size - half_size;
+                let height = self.get(x, y) * max_height;
+                writeln!(out, "v {} {} {}", world_x, height, world_z)?;
+            }
+        }
+        for row in 0..vertices_per_side {
+            for col in 0..vertices_per_side {
+                let u = col as f32 / (vertices_per_side - 1) as f32;
+                let v = row as f32 / (vertices_per_side - 1) as f32;
+                writeln!(out, "vt {} {}", u, 1.0 - v)?;
+            }
+        }
+        // Counter-clockwise when looking from above, indices are 1-based
+        for row in 0..vertices_per_side - 1 {
+            for col in 0..vertices_per_side - 1 {
+                let i = row * vertices_per_side + col + 1;
+                let below = i + vertices_per_side;
+                writeln!(out, "f {0}/{0} {1}/{1} {2}/{2}", i, below, i + 1)?;
+                writeln!(out, "f {0}/{0} {1}/{1} {2}/{2}", i + 1, below, below + 1)?;
+            }
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
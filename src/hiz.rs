@@ -0,0 +1,108 @@
+//! Hierarchical depth buffer for occlusion culling. Every mip level keeps the furthest
+//! depth of the texels it covers, so a whole screen rectangle can be tested against
+//! the depth buffer with a few reads from the right level.
+
+use gl::types::*;
+
+use crate::opengl::bindings::{self, TextureUnit};
+use crate::opengl::shader::Program;
+use crate::texture::calculate_mip_levels;
+use crate::Result;
+
+/// Must match the local size in hiz.comp
+const GROUP_SIZE: i32 = 8;
+
+pub struct HiZBuffer {
+    shader: Program,
+    texture: GLuint,
+    width: i32,
+    height: i32,
+    levels: i32,
+}
+
+impl HiZBuffer {
+    pub fn new() -> Result<Self> {
+        let shader = Program::new()
+            .compute_shader(shader_file!("culling/hiz.comp"))?
+            .link()?;
+        let mut hiz = HiZBuffer {
+            shader,
+            texture: 0,
+            width: 0,
+            height: 0,
+            levels: 0,
+        };
+        // Resized to the depth buffer on the first build
+        hiz.allocate(1, 1);
+        Ok(hiz)
+    }
+
+    pub fn texture(&self) -> GLuint {
+        self.texture
+    }
+
+    /// Rebuilds all levels from a depth texture, reallocating if its size has changed
+    pub fn build(&mut self, depth: GLuint) -> Result<()> {
+        let (mut width, mut height) = (0, 0);
+        unsafe {
+            gl::GetTextureLevelParameteriv(depth, 0, gl::TEXTURE_WIDTH, &mut width);
+            gl::GetTextureLevelParameteriv(depth, 0, gl::TEXTURE_HEIGHT, &mut height);
+        }
+        if (width, height) != (self.width, self.height) {
+            self.allocate(width, height);
+        }
+
+        self.shader.set_used();
+        for level in 0..self.levels {
+            let source = if level == 0 { depth } else { self.texture };
+            self.shader.set_i32("source_level", (level - 1).max(0))?;
+            self.shader.set_i32("downsample", (level > 0) as i32)?;
+            let level_width = (width >> level).max(1);
+            let level_height = (height >> level).max(1);
+            unsafe {
+                bindings::bind_texture(TextureUnit::SOURCE, source);
+                gl::BindImageTexture(
+                    0,
+                    self.texture,
+                    level,
+                    gl::FALSE,
+                    0,
+                    gl::WRITE_ONLY,
+                    gl::R32F,
+                );
+            }
+            self.shader.dispatch(
+                ((level_width + GROUP_SIZE - 1) / GROUP_SIZE) as u32,
+                ((level_height + GROUP_SIZE - 1) / GROUP_SIZE) as u32,
+                1,
+            );
+            unsafe {
+                // The next level reads this one
+                gl::MemoryBarrier(gl::TEXTURE_FETCH_BARRIER_BIT);
+            }
+        }
+        Ok(())
+    }
+
+    fn allocate(&mut self, width: i32, height: i32) {
+        self.width = width;
+        self.height = height;
+        self.levels = calculate_mip_levels(width as usize, height as usize);
+        if self.texture != 0 {
+            bindings::delete_textures(&[self.texture]);
+        }
+        unsafe {
+            gl::CreateTextures(gl::TEXTURE_2D, 1, &mut self.texture);
+            // Only read with texelFetch
+            gl::TextureParameteri(self.texture, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+            gl::TextureParameteri(self.texture, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+            gl::TextureStorage2D(self.texture, self.levels, gl::R32F, width, height);
+        }
+    }
+}
+
+impl Drop for HiZBuffer {
+    fn drop(&mut self) {
+        bindings::delete_textures(&[self.texture]);
+    }
+}
@@ -0,0 +1,149 @@
+//! Image-based lighting: the skybox convolved into the light it casts on rough and
+//! shiny surfaces, so that the ambient light of the meshes and the terrain matches
+//! the visible sky. Uses the split-sum approximation from Karis, "Real Shading in
+//! Unreal Engine 4", and the maps are sampled in common/environment.glsl.
+
+use gl::types::*;
+
+use crate::opengl::bindings::{self, Sampler, TextureUnit};
+use crate::opengl::objects::{Cubemap, Texture2D};
+use crate::opengl::shader::Program;
+use crate::Result;
+
+const IRRADIANCE_SIZE: u32 = 32;
+const PREFILTERED_SIZE: u32 = 128;
+/// Roughness 0 to 1 in steps of 0.25
+const PREFILTERED_LEVELS: u32 = 5;
+const BRDF_LUT_SIZE: u32 = 256;
+/// Work group size of the shaders in ibl/
+const GROUP_SIZE: u32 = 8;
+
+pub struct EnvironmentLighting {
+    irradiance: Cubemap,
+    prefiltered: Cubemap,
+    /// Doesn't depend on the sky, so it's only made once
+    brdf_lut: Texture2D,
+    irradiance_shader: Program,
+    prefilter_shader: Program,
+    /// The skybox cubemap the maps were made from, 0 before the first update
+    source: GLuint,
+}
+
+impl EnvironmentLighting {
+    pub fn new() -> Result<Self> {
+        let irradiance_shader = Program::new()
+            .compute_shader(shader_file!("ibl/irradiance.comp"))?
+            .link()?;
+        let prefilter_shader = Program::new()
+            .compute_shader(shader_file!("ibl/prefilter.comp"))?
+            .link()?;
+        let brdf_shader = Program::new()
+            .compute_shader(shader_file!("ibl/brdf_lut.comp"))?
+            .link()?;
+
+        let irradiance = Cubemap::new();
+        let prefiltered = Cubemap::new();
+        let brdf_lut = Texture2D::new();
+        let (irradiance_size, prefiltered_size) = (IRRADIANCE_SIZE as i32, PREFILTERED_SIZE as i32);
+        unsafe {
+            gl::TextureStorage2D(
+                irradiance.id(),
+                1,
+                gl::RGBA16F,
+                irradiance_size,
+                irradiance_size,
+            );
+            gl::TextureStorage2D(
+                prefiltered.id(),
+                PREFILTERED_LEVELS as i32,
+                gl::RGBA16F,
+                prefiltered_size,
+                prefiltered_size,
+            );
+            gl::TextureStorage2D(
+                brdf_lut.id(),
+                1,
+                gl::RG16F,
+                BRDF_LUT_SIZE as i32,
+                BRDF_LUT_SIZE as i32,
+            );
+        }
+
+        brdf_shader.set_used();
+        unsafe {
+            gl::BindImageTexture(0, brdf_lut.id(), 0, gl::FALSE, 0, gl::WRITE_ONLY, gl::RG16F);
+        }
+        let groups = group_count(BRDF_LUT_SIZE);
+        brdf_shader.dispatch(groups, groups, 1);
+        unsafe {
+            gl::MemoryBarrier(gl::TEXTURE_FETCH_BARRIER_BIT);
+        }
+
+        Ok(EnvironmentLighting {
+            irradiance,
+            prefiltered,
+            brdf_lut,
+            irradiance_shader,
+            prefilter_shader,
+            source: 0,
+        })
+    }
+
+    /// Convolves the skybox again if it's not the cubemap the maps were made from.
+    /// The skybox needs its mipmaps, they're read where the samples are sparse.
+    pub fn update(&mut self, skybox: GLuint) -> Result<()> {
+        if skybox == self.source {
+            return Ok(());
+        }
+        self.source = skybox;
+        bindings::bind_texture_sampled(TextureUnit::SOURCE, skybox, Sampler::TrilinearRepeat);
+
+        self.irradiance_shader.set_used();
+        bind_face_image(self.irradiance.id(), 0);
+        let groups = group_count(IRRADIANCE_SIZE);
+        self.irradiance_shader.dispatch(groups, groups, 6);
+
+        self.prefilter_shader.set_used();
+        for level in 0..PREFILTERED_LEVELS {
+            let roughness = level as f32 / (PREFILTERED_LEVELS - 1) as f32;
+            self.prefilter_shader.set_f32("roughness", roughness)?;
+            bind_face_image(self.prefiltered.id(), level as GLint);
+            let groups = group_count((PREFILTERED_SIZE >> level).max(1));
+            self.prefilter_shader.dispatch(groups, groups, 6);
+        }
+
+        unsafe {
+            gl::MemoryBarrier(gl::TEXTURE_FETCH_BARRIER_BIT);
+        }
+        Ok(())
+    }
+
+    pub fn bind(&self) {
+        bindings::bind_texture_sampled(
+            TextureUnit::IRRADIANCE,
+            self.irradiance.id(),
+            Sampler::LinearClamp,
+        );
+        bindings::bind_texture_sampled(
+            TextureUnit::PREFILTERED_ENVIRONMENT,
+            self.prefiltered.id(),
+            Sampler::TrilinearRepeat,
+        );
+        bindings::bind_texture_sampled(
+            TextureUnit::BRDF_LUT,
+            self.brdf_lut.id(),
+            Sampler::LinearClamp,
+        );
+    }
+}
+
+/// All six faces of the level, for the compute shaders to write
+fn bind_face_image(cubemap: GLuint, level: GLint) {
+    unsafe {
+        gl::BindImageTexture(0, cubemap, level, gl::TRUE, 0, gl::WRITE_ONLY, gl::RGBA16F);
+    }
+}
+
+fn group_count(size: u32) -> u32 {
+    (size + GROUP_SIZE - 1) / GROUP_SIZE
+}
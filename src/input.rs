@@ -1,20 +1,50 @@
+use std::collections::BTreeSet;
+
 use glam::Vec2;
 use glutin::event::VirtualKeyCode;
+use serde::{Deserialize, Serialize};
+
+use crate::keybindings::KeyAction;
+
+/// Logical pixels the mouse has to move while a button is down for it to be a drag
+/// rather than a click
+const DRAG_THRESHOLD: f32 = 4.0;
+/// Seconds between the clicks of a double click
+const DOUBLE_CLICK_TIME: f32 = 0.4;
 
+/// The state of the keyboard, mouse, pens and gamepads, with what changed during the
+/// frame. The window events go in through the setters, which keep the changes and
+/// the event queue in step, and `renew` starts the next frame.
 #[derive(Default, Clone)]
 pub struct Input {
     // Raw
     pub pointer: Vec2,
     pub pointer_moved: bool,
+    /// How far the mouse itself moved, even while the cursor is grabbed
     pub pointer_delta: Vec2,
+    /// Where the pointer was when the frame started, see `cursor_delta`
+    frame_pointer: Vec2,
     pub scrolled: bool,
     pub scroll_delta: Vec2,
     pub modifiers: Modifiers,
     pub mouse_buttons: MouseButtons,
-    pub forward: bool,
-    pub back: bool,
-    pub left: bool,
-    pub right: bool,
+    /// Clicks and drags, see `update_gestures`
+    pub gestures: MouseGestures,
+    /// Set while a pen or finger is down, which also holds the primary button
+    pub pen: Option<Pen>,
+    /// The bound keys held down, see `pressed`
+    actions: BTreeSet<KeyAction>,
+    /// Pressed since the last frame
+    just_pressed: BTreeSet<KeyAction>,
+    /// Released since the last frame
+    just_released: BTreeSet<KeyAction>,
+    /// The buttons which went down and up since the last frame
+    buttons_pressed: MouseButtons,
+    buttons_released: MouseButtons,
+    /// Everything above that changed during the frame, see `events`
+    events: Vec<InputEvent>,
+    /// Set by the gamepads every frame, see `axis`
+    analog: [f32; InputAxis::COUNT],
     pub time: f32,
 
     // Processed
@@ -23,21 +53,320 @@ pub struct Input {
 }
 
 impl Input {
-    /// Clear volatiles, persist everything else
-    pub fn renew(&mut self) -> Input {
-        let old_input = self.clone();
+    /// How hard the primary button is pressed, [0, 1]. Mice always press fully.
+    pub fn pressure(&self) -> f32 {
+        self.pen.map_or(1.0, |pen| pen.pressure)
+    }
+
+    /// How far the pen leans over, see `Pen::tilt`. Mice are always upright.
+    pub fn tilt(&self) -> f32 {
+        self.pen.map_or(0.0, |pen| pen.tilt)
+    }
+
+    /// Whether the brush is applied this frame, and if so how. The primary button
+    /// wins over the gamepad triggers. Holding Shift inverts the brush too.
+    pub fn brush_stroke(&self) -> Option<Stroke> {
+        let invert = self.pressed(KeyAction::InvertBrush) || self.modifiers.shift;
+        let triggers = self.axis(InputAxis::Brush);
+        if self.mouse_buttons.primary {
+            Some(Stroke {
+                invert,
+                pressure: self.pressure(),
+                tilt: self.tilt(),
+            })
+        } else if triggers != 0.0 {
+            Some(Stroke {
+                invert: invert != (triggers < 0.0),
+                pressure: triggers.abs(),
+                tilt: 0.0,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Tells clicks from drags once the events of the frame are in
+    pub fn update_gestures(&mut self) {
+        // The pointer doesn't move while the cursor is grabbed, but the mouse does.
+        // Pens move the pointer without moving the mouse.
+        let motion = self
+            .pointer_delta
+            .length()
+            .max(self.cursor_delta().length());
+        let (pointer, time) = (self.pointer, self.time);
+        let buttons = self.mouse_buttons;
+        let gestures = &mut self.gestures;
+        gestures
+            .primary
+            .update(buttons.primary, pointer, motion, time);
+        gestures
+            .secondary
+            .update(buttons.secondary, pointer, motion, time);
+    }
+
+    /// Whether the key of the action is down
+    pub fn pressed(&self, action: KeyAction) -> bool {
+        self.actions.contains(&action)
+    }
+
+    /// Whether the key of the action went down since the last frame. Key repeats
+    /// don't count.
+    #[allow(dead_code)]
+    pub fn just_pressed(&self, action: KeyAction) -> bool {
+        self.just_pressed.contains(&action)
+    }
+
+    /// Whether the key of the action went up since the last frame
+    #[allow(dead_code)]
+    pub fn just_released(&self, action: KeyAction) -> bool {
+        self.just_released.contains(&action)
+    }
+
+    /// Whether the button went down since the last frame, even if it's up again
+    pub fn button_just_pressed(&self, button: PointerButton) -> bool {
+        self.buttons_pressed.get(button)
+    }
+
+    /// Whether the button went up since the last frame, even if it's down again
+    pub fn button_just_released(&self, button: PointerButton) -> bool {
+        self.buttons_released.get(button)
+    }
+
+    /// How far the pointer moved over the window since the last frame, in logical
+    /// pixels. Unlike `pointer_delta` it stays zero while the cursor is grabbed.
+    pub fn cursor_delta(&self) -> Vec2 {
+        self.pointer - self.frame_pointer
+    }
+
+    /// Takes the changes of the frame out in the order they happened, for a system
+    /// that handles each once. The edge states like `just_pressed` are kept.
+    pub fn drain_events(&mut self) -> Vec<InputEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// The keys and the gamepads added up. The keys only move, and are either
+    /// all the way or nothing.
+    pub fn axis(&self, axis: InputAxis) -> f32 {
+        let keys = |positive, negative| {
+            let value = |action| if self.pressed(action) { 1.0 } else { 0.0 };
+            value(positive) - value(negative)
+        };
+        let analog = self.analog[axis.index()];
+        match axis {
+            InputAxis::MoveForward => {
+                (keys(KeyAction::MoveForward, KeyAction::MoveBack) + analog).clamp(-1.0, 1.0)
+            }
+            InputAxis::MoveRight => {
+                (keys(KeyAction::MoveRight, KeyAction::MoveLeft) + analog).clamp(-1.0, 1.0)
+            }
+            InputAxis::LookRight | InputAxis::LookUp | InputAxis::Brush => analog,
+        }
+    }
+
+    /// Only the gamepad part of the axis
+    pub fn analog(&self, axis: InputAxis) -> f32 {
+        self.analog[axis.index()]
+    }
+
+    /// When a bound key goes down or up
+    pub fn set_action(&mut self, action: KeyAction, pressed: bool) {
+        if pressed {
+            if self.actions.insert(action) {
+                self.just_pressed.insert(action);
+                self.events.push(InputEvent::Pressed(action));
+            }
+        } else if self.actions.remove(&action) {
+            self.just_released.insert(action);
+            self.events.push(InputEvent::Released(action));
+        }
+    }
+
+    /// When a mouse button goes down or up, or a pen touches
+    pub fn set_button(&mut self, button: PointerButton, pressed: bool) {
+        let state = self.mouse_buttons.get_mut(button);
+        if *state == pressed {
+            return;
+        }
+        *state = pressed;
+        if pressed {
+            *self.buttons_pressed.get_mut(button) = true;
+            self.events.push(InputEvent::ButtonPressed(button));
+        } else {
+            *self.buttons_released.get_mut(button) = true;
+            self.events.push(InputEvent::ButtonReleased(button));
+        }
+    }
+
+    /// When the pointer moves over the window, in logical pixels
+    pub fn move_pointer(&mut self, pointer: Vec2) {
+        self.pointer = pointer;
+        self.pointer_moved = true;
+    }
+
+    /// When the mouse moves, whether or not the cursor does
+    pub fn move_mouse(&mut self, delta: Vec2) {
+        self.pointer_delta += delta;
+        self.pointer_moved = true;
+    }
+
+    /// In lines, up is positive
+    pub fn scroll(&mut self, delta: Vec2) {
+        self.scroll_delta += delta;
+        self.scrolled = true;
+        self.events.push(InputEvent::Scrolled(delta));
+    }
+
+    /// When the window gains or loses focus, since the keys may go up elsewhere
+    pub fn release_keys(&mut self) {
+        self.modifiers = Modifiers::default();
+        let held: Vec<KeyAction> = self.actions.iter().copied().collect();
+        for action in held {
+            self.set_action(action, false);
+        }
+    }
+
+    /// When the window loses focus, since the buttons may go up elsewhere
+    pub fn release_buttons(&mut self) {
+        for &button in PointerButton::ALL.iter() {
+            self.set_button(button, false);
+        }
+    }
+
+    pub fn set_analog(&mut self, axis: InputAxis, value: f32) {
+        self.analog[axis.index()] = value;
+    }
+
+    /// Clears what changed during the frame and keeps the state for the next one
+    pub fn renew(&mut self) {
         *self = Input {
             pointer: self.pointer,
+            frame_pointer: self.pointer,
             mouse_buttons: self.mouse_buttons,
-            forward: self.forward,
-            back: self.back,
-            left: self.left,
-            right: self.right,
+            gestures: self.gestures,
+            pen: self.pen,
+            actions: std::mem::take(&mut self.actions),
+            analog: self.analog,
             modifiers: self.modifiers,
             should_exit: self.should_exit,
             ..Default::default()
         };
-        old_input
+    }
+}
+
+/// Something with a range of values rather than pressed or not, see `Input::axis`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputAxis {
+    /// Back is negative
+    MoveForward,
+    /// Left is negative
+    MoveRight,
+    /// Radians per second
+    LookRight,
+    LookUp,
+    /// Raising the terrain, lowering it when negative
+    Brush,
+}
+
+impl InputAxis {
+    pub const ALL: [InputAxis; InputAxis::COUNT] = [
+        InputAxis::MoveForward,
+        InputAxis::MoveRight,
+        InputAxis::LookRight,
+        InputAxis::LookUp,
+        InputAxis::Brush,
+    ];
+    const COUNT: usize = 5;
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Kept in `Settings`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct MouseSettings {
+    /// Scales how far the camera turns per pixel the mouse moves
+    pub sensitivity: f32,
+    pub invert_y: bool,
+}
+
+impl Default for MouseSettings {
+    fn default() -> Self {
+        MouseSettings {
+            sensitivity: 1.0,
+            invert_y: false,
+        }
+    }
+}
+
+impl MouseSettings {
+    /// The mouse movement as it turns the camera, see `Camera::rotate`
+    pub fn look_delta(&self, pointer_delta: Vec2) -> Vec2 {
+        let y = if self.invert_y { -1.0 } else { 1.0 };
+        Vec2::new(pointer_delta.x, pointer_delta.y * y) * self.sensitivity
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MouseGestures {
+    pub primary: Gesture,
+    pub secondary: Gesture,
+}
+
+/// What a mouse button is doing beyond being up or down
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Gesture {
+    /// Where the pointer was and the time when the button went down, while it's down
+    press: Option<(Vec2, f32)>,
+    /// How far the mouse has moved since
+    travelled: f32,
+    /// Where and when the last click was
+    last_click: Option<(Vec2, f32)>,
+    /// Down and moved past the threshold
+    pub dragging: bool,
+    /// Released this frame without having been dragged
+    pub clicked: bool,
+    /// A click soon after the one before and close to it. A third click starts over.
+    pub double_clicked: bool,
+    /// Released this frame after a drag
+    pub drag_ended: bool,
+}
+
+impl Gesture {
+    fn update(&mut self, down: bool, pointer: Vec2, motion: f32, time: f32) {
+        self.clicked = false;
+        self.double_clicked = false;
+        self.drag_ended = false;
+        match (self.press, down) {
+            (None, true) => {
+                self.press = Some((pointer, time));
+                self.travelled = 0.0;
+            }
+            (Some((start, _)), true) => {
+                self.travelled += motion;
+                let moved = self.travelled.max(pointer.distance(start));
+                self.dragging |= moved > DRAG_THRESHOLD;
+            }
+            (Some(_), false) => {
+                self.press = None;
+                if self.dragging {
+                    self.dragging = false;
+                    self.drag_ended = true;
+                    return;
+                }
+                self.clicked = true;
+                self.double_clicked = self.last_click.map_or(false, |(at, when)| {
+                    time - when < DOUBLE_CLICK_TIME && pointer.distance(at) <= DRAG_THRESHOLD
+                });
+                self.last_click = if self.double_clicked {
+                    None
+                } else {
+                    Some((pointer, time))
+                };
+            }
+            (None, false) => {}
+        }
     }
 }
 
@@ -48,6 +377,74 @@ pub struct MouseButtons {
     pub secondary: bool,
 }
 
+impl MouseButtons {
+    fn get(&self, button: PointerButton) -> bool {
+        match button {
+            PointerButton::Primary => self.primary,
+            PointerButton::Middle => self.middle,
+            PointerButton::Secondary => self.secondary,
+        }
+    }
+
+    fn get_mut(&mut self, button: PointerButton) -> &mut bool {
+        match button {
+            PointerButton::Primary => &mut self.primary,
+            PointerButton::Middle => &mut self.middle,
+            PointerButton::Secondary => &mut self.secondary,
+        }
+    }
+}
+
+/// One of `MouseButtons`. A pen or a finger is the primary one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PointerButton {
+    Primary,
+    Middle,
+    Secondary,
+}
+
+impl PointerButton {
+    pub const ALL: [PointerButton; 3] = [
+        PointerButton::Primary,
+        PointerButton::Middle,
+        PointerButton::Secondary,
+    ];
+}
+
+/// A change to the input during the frame, see `Input::drain_events`. The pointer
+/// movement is left out, there are a lot of those and only the total matters.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputEvent {
+    Pressed(KeyAction),
+    Released(KeyAction),
+    ButtonPressed(PointerButton),
+    ButtonReleased(PointerButton),
+    /// In lines, up is positive
+    Scrolled(Vec2),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Pen {
+    /// Only the first touch is followed, the rest are ignored
+    pub touch_id: u64,
+    pub pressure: f32,
+    /// 0 with the pen upright, 1 lying flat. Only some platforms report it, the rest
+    /// leave it at 0.
+    pub tilt: f32,
+}
+
+/// How the brush is applied this frame, see `Input::brush_stroke`
+#[derive(Clone, Copy, Debug)]
+pub struct Stroke {
+    /// Swaps raising and lowering, or erases the paint
+    pub invert: bool,
+    /// [0, 1], scales the strength
+    pub pressure: f32,
+    /// [0, 1], softens the edge like shading with the side of a pencil. Sculpting
+    /// turns the brush shape with it too.
+    pub tilt: f32,
+}
+
 #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
 pub struct Modifiers {
     pub alt: bool,
@@ -0,0 +1,562 @@
+//! Draws many copies of the same mesh (rocks, trees, bricks) with one draw call per
+//! primitive. Instance transforms and colors live in a shader storage buffer per mesh,
+//! and only the ranges that changed since the last frame get uploaded, so adding or
+//! removing a few out of a hundred thousand doesn't send the rest again.
+//!
+//! Before drawing, a compute pass culls the instances outside the view or behind the
+//! Hi-Z buffer and sorts the rest into full meshes and impostors. The draws are
+//! indirect, so the counts never come back to the CPU.
+
+use std::mem::size_of;
+use std::ops::Range;
+
+use gl::types::*;
+use glam::{Mat4, Vec3, Vec4};
+use memoffset::offset_of;
+
+use crate::atmosphere::Atmosphere;
+use crate::billboard::Impostor;
+use crate::hiz::HiZBuffer;
+use crate::model::{DrawElementsIndirectCommand, Model};
+use crate::opengl::bindings::{self, TextureUnit};
+use crate::opengl::shader::Program;
+use crate::profiler;
+use crate::utils::size_of_slice;
+use crate::Result;
+
+/// Must match the buffer bindings in mesh_instanced.vert, impostor.vert and instances.comp
+const INSTANCES_SSBO_BINDING: u32 = 0;
+const VISIBLE_SSBO_BINDING: u32 = 1;
+/// Only in instances.comp, which writes both lists at once
+const IMPOSTORS_VISIBLE_SSBO_BINDING: u32 = 2;
+const COMMANDS_SSBO_BINDING: u32 = 3;
+/// Must match the local size in instances.comp
+const CULL_GROUP_SIZE: usize = 64;
+/// Dirty ranges closer than this many instances are uploaded as one
+const MERGE_GAP: usize = 256;
+
+/// Laid out the way `glDrawArraysIndirect` reads it
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct DrawArraysIndirectCommand {
+    count: u32,
+    instance_count: u32,
+    first: u32,
+    base_instance: u32,
+}
+
+/// Where the mesh commands start in the indirect buffer, after the impostor one
+const MESH_COMMANDS_OFFSET: usize = size_of::<DrawArraysIndirectCommand>();
+
+/// Laid out like `Instance` in the instancing shaders
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Instance {
+    transform: Mat4,
+    /// Linear, multiplies the albedo of the material
+    color: Vec4,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MeshId(usize);
+
+/// Stays valid until the instance is removed, no matter what happens to the others
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InstanceId {
+    mesh: usize,
+    handle: u32,
+}
+
+struct InstancedMesh {
+    model: Model,
+    /// Drawn instead of the model for distant instances
+    impostor: Option<Impostor>,
+    /// Packed without gaps so that they can be drawn in one go
+    instances: Vec<Instance>,
+    /// Handle of the instance at each index of `instances`
+    handles: Vec<u32>,
+    /// Index into `instances` for each handle, None if it's been removed
+    slots: Vec<Option<usize>>,
+    free_handles: Vec<u32>,
+
+    buffer: GLuint,
+    /// Indices of the instances that passed culling, written on the GPU
+    mesh_visible: GLuint,
+    impostor_visible: GLuint,
+    /// The impostor draw command followed by one for every primitive of the model
+    commands: GLuint,
+    primitive_count: usize,
+    /// In instances
+    capacity: usize,
+    /// Ranges of `instances` that differ from the buffer, unordered and possibly
+    /// overlapping until they're merged for upload
+    dirty: Vec<Range<usize>>,
+    /// Hidden in the outliner, neither culled nor drawn
+    hidden: bool,
+}
+
+impl InstancedMesh {
+    fn new(model: Model, impostor: Option<Impostor>) -> Self {
+        let impostor_command = DrawArraysIndirectCommand {
+            count: 4,
+            instance_count: 0,
+            first: 0,
+            base_instance: 0,
+        };
+        let mesh_commands = model.draw_commands();
+        // Culling writes the count of the first mesh command even if there isn't one
+        let size = MESH_COMMANDS_OFFSET
+            + mesh_commands.len().max(1) * size_of::<DrawElementsIndirectCommand>();
+        let mut commands: GLuint = 0;
+        unsafe {
+            gl::CreateBuffers(1, &mut commands);
+            gl::NamedBufferStorage(
+                commands,
+                size as isize,
+                std::ptr::null(),
+                gl::DYNAMIC_STORAGE_BIT,
+            );
+            gl::NamedBufferSubData(
+                commands,
+                0,
+                size_of::<DrawArraysIndirectCommand>() as isize,
+                &impostor_command as *const _ as *const _,
+            );
+            gl::NamedBufferSubData(
+                commands,
+                MESH_COMMANDS_OFFSET as isize,
+                size_of_slice(&mesh_commands) as isize,
+                mesh_commands.as_ptr() as *const _,
+            );
+        }
+
+        InstancedMesh {
+            model,
+            impostor,
+            instances: vec![],
+            handles: vec![],
+            slots: vec![],
+            free_handles: vec![],
+
+            buffer: 0,
+            mesh_visible: 0,
+            impostor_visible: 0,
+            commands,
+            primitive_count: mesh_commands.len(),
+            capacity: 0,
+            dirty: vec![],
+            hidden: false,
+        }
+    }
+
+    fn is_drawn(&self) -> bool {
+        !self.hidden && !self.instances.is_empty()
+    }
+
+    fn mark_dirty(&mut self, index: usize) {
+        // Instances are mostly added one after another, which extends the last range
+        match self.dirty.last_mut() {
+            Some(range) if range.contains(&index) => {}
+            Some(range) if range.end == index => range.end += 1,
+            _ => self.dirty.push(index..index + 1),
+        }
+    }
+
+    fn mark_all_dirty(&mut self) {
+        self.dirty.clear();
+        self.dirty.push(0..self.instances.len());
+    }
+
+    /// Sorted, without overlaps, and only within `instances`
+    fn take_dirty(&mut self) -> Vec<Range<usize>> {
+        let mut ranges = std::mem::take(&mut self.dirty);
+        ranges.sort_unstable_by_key(|range| range.start);
+        let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            // Indices past the end have been removed, nothing to upload there
+            let range = range.start..range.end.min(self.instances.len());
+            if range.is_empty() {
+                continue;
+            }
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end + MERGE_GAP => {
+                    last.end = last.end.max(range.end);
+                }
+                _ => merged.push(range),
+            }
+        }
+        merged
+    }
+
+    fn add(&mut self, instance: Instance) -> u32 {
+        let handle = self.free_handles.pop().unwrap_or_else(|| {
+            self.slots.push(None);
+            self.slots.len() as u32 - 1
+        });
+        self.slots[handle as usize] = Some(self.instances.len());
+        self.instances.push(instance);
+        self.handles.push(handle);
+        self.mark_dirty(self.instances.len() - 1);
+        handle
+    }
+
+    /// The last instance takes the place of the removed one
+    fn remove(&mut self, handle: u32) -> bool {
+        let index = match self.slots.get_mut(handle as usize).and_then(Option::take) {
+            Some(index) => index,
+            None => return false,
+        };
+        self.instances.swap_remove(index);
+        self.handles.swap_remove(index);
+        if index < self.instances.len() {
+            self.slots[self.handles[index] as usize] = Some(index);
+            self.mark_dirty(index);
+        }
+        self.free_handles.push(handle);
+        true
+    }
+
+    fn index(&self, handle: u32) -> Option<usize> {
+        self.slots.get(handle as usize).copied().flatten()
+    }
+
+    /// Sends the changed instances to the GPU, growing the buffer if needed
+    fn upload(&mut self) {
+        if self.instances.len() > self.capacity {
+            let old_capacity = self.capacity;
+            self.capacity = self.instances.len().next_power_of_two();
+            let mut buffer: GLuint = 0;
+            unsafe {
+                gl::CreateBuffers(1, &mut buffer);
+                gl::NamedBufferStorage(
+                    buffer,
+                    (self.capacity * size_of::<Instance>()) as isize,
+                    std::ptr::null(),
+                    gl::DYNAMIC_STORAGE_BIT,
+                );
+                if self.buffer != 0 {
+                    // What's already there stays on the GPU, the dirty ranges follow
+                    gl::CopyNamedBufferSubData(
+                        self.buffer,
+                        buffer,
+                        0,
+                        0,
+                        (old_capacity * size_of::<Instance>()) as isize,
+                    );
+                    let buffers = [self.buffer, self.mesh_visible, self.impostor_visible];
+                    gl::DeleteBuffers(buffers.len() as i32, buffers.as_ptr());
+                }
+                self.buffer = buffer;
+                for visible in [&mut self.mesh_visible, &mut self.impostor_visible] {
+                    gl::CreateBuffers(1, visible);
+                    gl::NamedBufferStorage(
+                        *visible,
+                        (self.capacity * size_of::<u32>()) as isize,
+                        std::ptr::null(),
+                        0,
+                    );
+                }
+            }
+        }
+
+        for range in self.take_dirty() {
+            let data = &self.instances[range.clone()];
+            unsafe {
+                gl::NamedBufferSubData(
+                    self.buffer,
+                    (range.start * size_of::<Instance>()) as isize,
+                    size_of_slice(data) as isize,
+                    data.as_ptr() as *const _,
+                );
+            }
+        }
+    }
+}
+
+impl Drop for InstancedMesh {
+    fn drop(&mut self) {
+        let buffers = [
+            self.buffer,
+            self.mesh_visible,
+            self.impostor_visible,
+            self.commands,
+        ];
+        unsafe {
+            gl::DeleteBuffers(buffers.len() as i32, buffers.as_ptr());
+        }
+    }
+}
+
+pub struct InstancedRenderer {
+    shader: Program,
+    impostor_shader: Program,
+    /// See `Impostor::bake`
+    bake_shader: Program,
+    cull_shader: Program,
+    meshes: Vec<InstancedMesh>,
+}
+
+impl InstancedRenderer {
+    pub fn new() -> Result<Self> {
+        let shader = Program::new()
+            .vertex_shader(shader_file!("mesh/mesh_instanced.vert"))?
+            .fragment_shader(shader_file!("mesh/mesh.frag"))?
+            .link()?;
+        let impostor_shader = Program::new()
+            .vertex_shader(shader_file!("billboard/impostor.vert"))?
+            .fragment_shader(shader_file!("billboard/impostor.frag"))?
+            .link()?;
+        let bake_shader = Impostor::bake_shader()?;
+        let cull_shader = Program::new()
+            .compute_shader(shader_file!("culling/instances.comp"))?
+            .link()?;
+
+        Ok(InstancedRenderer {
+            shader,
+            impostor_shader,
+            bake_shader,
+            cull_shader,
+            meshes: vec![],
+        })
+    }
+
+    /// The model is drawn once for every instance added with the returned id.
+    /// An impostor is baked for it, unless it has no geometry to bake.
+    pub fn add_mesh(&mut self, mut model: Model) -> MeshId {
+        let impostor = match Impostor::bake(&self.bake_shader, &mut model) {
+            Ok(impostor) => Some(impostor),
+            Err(err) => {
+                log!(
+                    "Couldn't bake an impostor, drawing the full mesh at any distance: {}",
+                    err
+                );
+                None
+            }
+        };
+        self.meshes.push(InstancedMesh::new(model, impostor));
+        MeshId(self.meshes.len() - 1)
+    }
+
+    pub fn add_instance(&mut self, mesh: MeshId, transform: Mat4) -> InstanceId {
+        self.add_colored_instance(mesh, transform, Vec4::ONE)
+    }
+
+    /// The color is linear and multiplies the albedo of the model's materials
+    pub fn add_colored_instance(
+        &mut self,
+        mesh: MeshId,
+        transform: Mat4,
+        color: Vec4,
+    ) -> InstanceId {
+        let handle = self.meshes[mesh.0].add(Instance { transform, color });
+        InstanceId {
+            mesh: mesh.0,
+            handle,
+        }
+    }
+
+    /// Returns false if the instance has already been removed
+    pub fn remove_instance(&mut self, id: InstanceId) -> bool {
+        self.meshes[id.mesh].remove(id.handle)
+    }
+
+    pub fn transform(&self, id: InstanceId) -> Option<Mat4> {
+        let mesh = &self.meshes[id.mesh];
+        mesh.index(id.handle)
+            .map(|index| mesh.instances[index].transform)
+    }
+
+    pub fn set_transform(&mut self, id: InstanceId, transform: Mat4) {
+        let mesh = &mut self.meshes[id.mesh];
+        if let Some(index) = mesh.index(id.handle) {
+            mesh.instances[index].transform = transform;
+            mesh.mark_dirty(index);
+        }
+    }
+
+    /// Linear color, multiplied with the albedo
+    pub fn set_color(&mut self, id: InstanceId, color: Vec4) {
+        let mesh = &mut self.meshes[id.mesh];
+        if let Some(index) = mesh.index(id.handle) {
+            mesh.instances[index].color = color;
+            mesh.mark_dirty(index);
+        }
+    }
+
+    pub fn set_visible(&mut self, mesh: MeshId, visible: bool) {
+        self.meshes[mesh.0].hidden = !visible;
+    }
+
+    pub fn is_visible(&self, mesh: MeshId) -> bool {
+        !self.meshes[mesh.0].hidden
+    }
+
+    /// Instances of one mesh
+    pub fn mesh_instance_count(&self, mesh: MeshId) -> usize {
+        self.meshes[mesh.0].instances.len()
+    }
+
+    pub fn instance_count(&self) -> usize {
+        self.meshes.iter().map(|mesh| mesh.instances.len()).sum()
+    }
+
+    /// Moves all instances along with the local origin
+    pub fn shift_origin(&mut self, shift: Vec3) {
+        let translation = Mat4::from_translation(-shift);
+        for mesh in &mut self.meshes {
+            for instance in &mut mesh.instances {
+                instance.transform = translation * instance.transform;
+            }
+            mesh.mark_all_dirty();
+        }
+    }
+
+    /// Expects the terrain shadow map to be bound to unit 3, like for the other meshes.
+    /// Instances further than `impostor_distance` from the camera are drawn as impostors.
+    /// Without a Hi-Z buffer only the instances outside the view are culled.
+    pub fn draw(
+        &mut self,
+        atmosphere: &Atmosphere,
+        deferred_shadows: bool,
+        impostor_distance: f32,
+        hiz: Option<&HiZBuffer>,
+    ) -> Result<()> {
+        if !self.meshes.iter().any(InstancedMesh::is_drawn) {
+            return Ok(());
+        }
+        self.cull(impostor_distance, hiz)?;
+
+        for shader in [&self.shader, &self.impostor_shader] {
+            shader.set_used();
+            shader.set_i32("deferred_shadows", deferred_shadows as i32)?;
+            atmosphere.set_lighting_uniforms(shader)?;
+            atmosphere.set_fog_uniforms(shader)?;
+        }
+
+        for mesh in &mut self.meshes {
+            if !mesh.is_drawn() {
+                continue;
+            }
+            unsafe {
+                gl::BindBufferBase(
+                    gl::SHADER_STORAGE_BUFFER,
+                    INSTANCES_SSBO_BINDING,
+                    mesh.buffer,
+                );
+                gl::BindBufferBase(
+                    gl::SHADER_STORAGE_BUFFER,
+                    VISIBLE_SSBO_BINDING,
+                    mesh.mesh_visible,
+                );
+                gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, mesh.commands);
+            }
+
+            self.shader.set_used();
+            mesh.model
+                .draw_indirect(&self.shader, MESH_COMMANDS_OFFSET)?;
+
+            if let Some(impostor) = &mesh.impostor {
+                self.impostor_shader.set_used();
+                impostor.bind(&self.impostor_shader)?;
+                unsafe {
+                    gl::BindBufferBase(
+                        gl::SHADER_STORAGE_BUFFER,
+                        VISIBLE_SSBO_BINDING,
+                        mesh.impostor_visible,
+                    );
+                    // The quads are generated in the shader, the model's VAO will do
+                    gl::DrawArraysIndirect(gl::TRIANGLE_STRIP, std::ptr::null());
+                    profiler::count_draw_call();
+                }
+            }
+        }
+        unsafe {
+            gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, 0);
+        }
+        Ok(())
+    }
+
+    /// Fills the visible instance lists and the instance counts of the draw commands
+    fn cull(&mut self, impostor_distance: f32, hiz: Option<&HiZBuffer>) -> Result<()> {
+        self.cull_shader.set_used();
+        self.cull_shader
+            .set_i32("occlusion_culling", hiz.is_some() as i32)?;
+        if let Some(hiz) = hiz {
+            bindings::bind_texture(TextureUnit::SOURCE, hiz.texture());
+        }
+
+        let impostor_count_offset = offset_of!(DrawArraysIndirectCommand, instance_count);
+        let mesh_count_offset =
+            MESH_COMMANDS_OFFSET + offset_of!(DrawElementsIndirectCommand, instance_count);
+        for mesh in &mut self.meshes {
+            if !mesh.is_drawn() {
+                continue;
+            }
+            mesh.upload();
+            let count = mesh.instances.len();
+            let lod_distance = match mesh.impostor {
+                Some(_) => impostor_distance,
+                None => f32::MAX,
+            };
+            self.cull_shader.set_u32("instance_count", count as u32)?;
+            self.cull_shader
+                .set_vec3("bounds_min", &mesh.model.bounds.min)?;
+            self.cull_shader
+                .set_vec3("bounds_max", &mesh.model.bounds.max)?;
+            self.cull_shader.set_f32("lod_distance", lod_distance)?;
+            let bindings = [
+                (INSTANCES_SSBO_BINDING, mesh.buffer),
+                (VISIBLE_SSBO_BINDING, mesh.mesh_visible),
+                (IMPOSTORS_VISIBLE_SSBO_BINDING, mesh.impostor_visible),
+                (COMMANDS_SSBO_BINDING, mesh.commands),
+            ];
+            unsafe {
+                // The counts start from zero every frame
+                for offset in [impostor_count_offset, mesh_count_offset] {
+                    gl::ClearNamedBufferSubData(
+                        mesh.commands,
+                        gl::R32UI,
+                        offset as isize,
+                        size_of::<u32>() as isize,
+                        gl::RED_INTEGER,
+                        gl::UNSIGNED_INT,
+                        std::ptr::null(),
+                    );
+                }
+                for (binding, buffer) in bindings {
+                    gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, binding, buffer);
+                }
+            }
+            self.cull_shader
+                .dispatch(count.div_ceil(CULL_GROUP_SIZE) as u32, 1, 1);
+        }
+
+        unsafe {
+            gl::MemoryBarrier(
+                gl::SHADER_STORAGE_BARRIER_BIT
+                    | gl::COMMAND_BARRIER_BIT
+                    | gl::BUFFER_UPDATE_BARRIER_BIT,
+            );
+        }
+        // Every primitive draws the same instances as the first one
+        for mesh in &self.meshes {
+            if !mesh.is_drawn() {
+                continue;
+            }
+            for primitive in 1..mesh.primitive_count {
+                let offset =
+                    mesh_count_offset + primitive * size_of::<DrawElementsIndirectCommand>();
+                unsafe {
+                    gl::CopyNamedBufferSubData(
+                        mesh.commands,
+                        mesh.commands,
+                        mesh_count_offset as isize,
+                        offset as isize,
+                        size_of::<u32>() as isize,
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
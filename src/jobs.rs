@@ -0,0 +1,147 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+type Job = Box<dyn FnOnce() + Send>;
+/// Gets what it needs from `C`, the main thread's state, see `Callbacks`
+pub type Callback<C> = Box<dyn FnOnce(&mut C) + Send>;
+
+/// A fixed pool of worker threads for CPU-heavy work that shouldn't stall the frame.
+/// Anything touching OpenGL has to stay on the main thread, so jobs only return data
+/// which the main thread picks up with `JobHandle::try_take`, or which is handed to
+/// a callback the main thread runs, see `spawn_then`.
+pub struct JobSystem {
+    sender: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl JobSystem {
+    pub fn new() -> Self {
+        let worker_count = thread::available_parallelism()
+            .map(|n| n.get().saturating_sub(1).max(1))
+            .unwrap_or(2);
+
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..worker_count)
+            .map(|i| {
+                let receiver = Arc::clone(&receiver);
+                thread::Builder::new()
+                    .name(format!("worker-{}", i))
+                    .spawn(move || loop {
+                        // The lock is released before running the job
+                        let job = receiver.lock().unwrap().recv();
+                        match job {
+                            // The panic hook reports it, the worker carries on
+                            Ok(job) => drop(panic::catch_unwind(AssertUnwindSafe(job))),
+                            Err(_) => break, // job system dropped
+                        }
+                    })
+                    .expect("Couldn't spawn worker thread")
+            })
+            .collect();
+
+        JobSystem {
+            sender: Some(sender),
+            workers,
+        }
+    }
+
+    pub fn spawn<T, F>(&self, work: F) -> JobHandle<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let (result_sender, result_receiver) = mpsc::channel();
+        let job: Job = Box::new(move || {
+            // Nobody may be waiting for the result anymore, that's fine
+            let _ = result_sender.send(work());
+        });
+        self.sender
+            .as_ref()
+            .unwrap()
+            .send(job)
+            .expect("Worker threads have died");
+
+        JobHandle {
+            receiver: result_receiver,
+        }
+    }
+
+    /// Runs `work` on a worker, then `then` with its result once the main thread takes
+    /// the callbacks. Nothing runs `then` if the job panics.
+    pub fn spawn_then<C, T, F, D>(&self, callbacks: &Callbacks<C>, work: F, then: D)
+    where
+        C: 'static,
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+        D: FnOnce(&mut C, T) + Send + 'static,
+    {
+        let sender = callbacks.sender.clone();
+        let job: Job = Box::new(move || {
+            let result = work();
+            let callback: Callback<C> = Box::new(move |context| then(context, result));
+            // Nobody may be taking the callbacks anymore when exiting
+            let _ = sender.send(callback);
+        });
+        self.sender
+            .as_ref()
+            .unwrap()
+            .send(job)
+            .expect("Worker threads have died");
+    }
+}
+
+impl Default for JobSystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for JobSystem {
+    fn drop(&mut self) {
+        // Closing the channel makes the workers exit after finishing their current job
+        self.sender.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+pub struct JobHandle<T> {
+    receiver: Receiver<T>,
+}
+
+impl<T> JobHandle<T> {
+    /// Returns the result if the job has finished. Doesn't block.
+    pub fn try_take(&self) -> Option<T> {
+        self.receiver.try_recv().ok()
+    }
+
+    /// Blocks until the job has finished, panics if the job did
+    pub fn wait(self) -> T {
+        self.receiver.recv().expect("The job has panicked")
+    }
+}
+
+/// Callbacks of the finished jobs, for the thread which owns `C`
+pub struct Callbacks<C> {
+    sender: Sender<Callback<C>>,
+    receiver: Receiver<Callback<C>>,
+}
+
+impl<C> Callbacks<C> {
+    /// Those of the jobs finished since the last call, in the order they finished.
+    /// Doesn't block.
+    pub fn take(&self) -> Vec<Callback<C>> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+impl<C> Default for Callbacks<C> {
+    fn default() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Callbacks { sender, receiver }
+    }
+}
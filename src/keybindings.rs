@@ -0,0 +1,165 @@
+use std::collections::BTreeMap;
+
+use glutin::event::VirtualKeyCode;
+use serde::{Deserialize, Serialize};
+
+/// Something done with a single key, which can be bound to another one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum KeyAction {
+    MoveForward,
+    MoveBack,
+    MoveLeft,
+    MoveRight,
+    /// Held to lower the terrain instead of raising it and the other way round
+    InvertBrush,
+    ToggleGui,
+    ToggleBrickMode,
+    PlaceTool,
+    SelectTool,
+    DeleteTool,
+    /// Turns the brick, or the clipboard when pasting
+    RotateBrick,
+    DeleteBricks,
+    WireframeView,
+    NormalsView,
+    TessPatchesView,
+    OverdrawView,
+    TranslateGizmo,
+    RotateGizmo,
+    ScaleGizmo,
+}
+
+impl KeyAction {
+    pub const ALL: [KeyAction; 19] = [
+        KeyAction::MoveForward,
+        KeyAction::MoveBack,
+        KeyAction::MoveLeft,
+        KeyAction::MoveRight,
+        KeyAction::InvertBrush,
+        KeyAction::ToggleGui,
+        KeyAction::ToggleBrickMode,
+        KeyAction::PlaceTool,
+        KeyAction::SelectTool,
+        KeyAction::DeleteTool,
+        KeyAction::RotateBrick,
+        KeyAction::DeleteBricks,
+        KeyAction::WireframeView,
+        KeyAction::NormalsView,
+        KeyAction::TessPatchesView,
+        KeyAction::OverdrawView,
+        KeyAction::TranslateGizmo,
+        KeyAction::RotateGizmo,
+        KeyAction::ScaleGizmo,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            KeyAction::MoveForward => "Move forward",
+            KeyAction::MoveBack => "Move back",
+            KeyAction::MoveLeft => "Move left",
+            KeyAction::MoveRight => "Move right",
+            KeyAction::InvertBrush => "Invert brush (hold)",
+            KeyAction::ToggleGui => "Show/hide GUI",
+            KeyAction::ToggleBrickMode => "Bricks tool",
+            KeyAction::PlaceTool => "Place bricks",
+            KeyAction::SelectTool => "Select bricks",
+            KeyAction::DeleteTool => "Delete bricks tool",
+            KeyAction::RotateBrick => "Rotate brick",
+            KeyAction::DeleteBricks => "Delete selected bricks",
+            KeyAction::WireframeView => "Wireframe view",
+            KeyAction::NormalsView => "Normals view",
+            KeyAction::TessPatchesView => "Tessellation view",
+            KeyAction::OverdrawView => "Overdraw view",
+            KeyAction::TranslateGizmo => "Move gizmo",
+            KeyAction::RotateGizmo => "Rotate gizmo",
+            KeyAction::ScaleGizmo => "Scale gizmo",
+        }
+    }
+
+    /// Does something for as long as the key is down rather than once when it's pressed
+    pub fn is_held(self) -> bool {
+        matches!(
+            self,
+            KeyAction::MoveForward
+                | KeyAction::MoveBack
+                | KeyAction::MoveLeft
+                | KeyAction::MoveRight
+                | KeyAction::InvertBrush
+        )
+    }
+
+    pub fn default_key(self) -> VirtualKeyCode {
+        match self {
+            KeyAction::MoveForward => VirtualKeyCode::W,
+            KeyAction::MoveBack => VirtualKeyCode::S,
+            KeyAction::MoveLeft => VirtualKeyCode::A,
+            KeyAction::MoveRight => VirtualKeyCode::D,
+            KeyAction::InvertBrush => VirtualKeyCode::LControl,
+            KeyAction::ToggleGui => VirtualKeyCode::Tab,
+            KeyAction::ToggleBrickMode => VirtualKeyCode::B,
+            KeyAction::PlaceTool => VirtualKeyCode::P,
+            KeyAction::SelectTool => VirtualKeyCode::V,
+            KeyAction::DeleteTool => VirtualKeyCode::X,
+            KeyAction::RotateBrick => VirtualKeyCode::R,
+            KeyAction::DeleteBricks => VirtualKeyCode::Delete,
+            KeyAction::WireframeView => VirtualKeyCode::F1,
+            KeyAction::NormalsView => VirtualKeyCode::F2,
+            KeyAction::TessPatchesView => VirtualKeyCode::F3,
+            KeyAction::OverdrawView => VirtualKeyCode::F4,
+            KeyAction::TranslateGizmo => VirtualKeyCode::G,
+            KeyAction::RotateGizmo => VirtualKeyCode::T,
+            KeyAction::ScaleGizmo => VirtualKeyCode::Y,
+        }
+    }
+}
+
+/// The key of every action, saved in config.json. Only the changed ones are stored,
+/// the rest use their default keys.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct KeyBindings {
+    keys: BTreeMap<KeyAction, VirtualKeyCode>,
+}
+
+impl KeyBindings {
+    pub fn key(&self, action: KeyAction) -> VirtualKeyCode {
+        self.keys
+            .get(&action)
+            .copied()
+            .unwrap_or_else(|| action.default_key())
+    }
+
+    /// Every key does at most one thing, see `bind`
+    pub fn action(&self, key: VirtualKeyCode) -> Option<KeyAction> {
+        KeyAction::ALL
+            .iter()
+            .copied()
+            .find(|&action| self.key(action) == key)
+    }
+
+    /// An action that had the key before gets the old key of this one
+    pub fn bind(&mut self, action: KeyAction, key: VirtualKeyCode) {
+        let old_key = self.key(action);
+        if let Some(other) = self.action(key).filter(|&other| other != action) {
+            self.set(other, old_key);
+        }
+        self.set(action, key);
+    }
+
+    pub fn reset(&mut self) {
+        self.keys.clear();
+    }
+
+    fn set(&mut self, action: KeyAction, key: VirtualKeyCode) {
+        if key == action.default_key() {
+            self.keys.remove(&action);
+        } else {
+            self.keys.insert(action, key);
+        }
+    }
+}
+
+/// How the key is shown in the GUI
+pub fn key_name(key: VirtualKeyCode) -> String {
+    format!("{:?}", key)
+}
@@ -0,0 +1,78 @@
+//! Images decoded on the worker threads and uploaded on the main thread a few per
+//! frame, so that big files neither hold up startup nor make a frame hitch. Until
+//! its image arrives a texture keeps its placeholder, and the GUI shows the progress.
+
+use std::collections::VecDeque;
+
+use crate::jobs::{JobHandle, JobSystem};
+use crate::skybox::SkyboxImages;
+use crate::splat::DecodedLayerImage;
+
+/// Uploading a big image takes a while too, so they're spread over frames
+const UPLOADS_PER_FRAME: usize = 2;
+
+/// What a decoded image is for, the main thread uploads it accordingly
+pub enum LoadedImage {
+    Skybox(SkyboxImages),
+    TerrainLayer(DecodedLayerImage),
+}
+
+#[derive(Default)]
+pub struct ImageLoader {
+    /// With the names for the error messages
+    decoding: Vec<(String, JobHandle<Result<LoadedImage, String>>)>,
+    /// Waiting for their turn to be uploaded
+    decoded: VecDeque<LoadedImage>,
+    /// Counted since the loader was last idle, for the progress bar
+    started: usize,
+    finished: usize,
+}
+
+impl ImageLoader {
+    pub fn load<F>(&mut self, jobs: &JobSystem, name: &str, decode: F)
+    where
+        F: FnOnce() -> Result<LoadedImage, String> + Send + 'static,
+    {
+        if self.is_idle() {
+            self.started = 0;
+            self.finished = 0;
+        }
+        self.started += 1;
+        self.decoding.push((name.to_owned(), jobs.spawn(decode)));
+    }
+
+    /// The images to upload this frame. The ones which failed to decode are
+    /// printed and count as finished.
+    pub fn poll(&mut self) -> Vec<LoadedImage> {
+        let decoded = &mut self.decoded;
+        let finished = &mut self.finished;
+        self.decoding.retain(|(name, job)| match job.try_take() {
+            Some(Ok(image)) => {
+                decoded.push_back(image);
+                false
+            }
+            Some(Err(err)) => {
+                log!("Failed to load {}: {}", name, err);
+                *finished += 1;
+                false
+            }
+            None => true,
+        });
+        let count = self.decoded.len().min(UPLOADS_PER_FRAME);
+        self.finished += count;
+        self.decoded.drain(..count).collect()
+    }
+
+    /// Finished and total images since the loader was last idle, None if it is
+    pub fn progress(&self) -> Option<(usize, usize)> {
+        if self.is_idle() {
+            None
+        } else {
+            Some((self.finished, self.started))
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.decoding.is_empty() && self.decoded.is_empty()
+    }
+}
@@ -1,52 +1,159 @@
 // #![allow(dead_code)]
 // #![allow(unused)]
 
+// First, so that their macros can be used in all the other modules
+#[macro_use]
+mod crash;
+#[macro_use]
+mod opengl;
+
+mod app;
+mod atmosphere;
+mod bake;
+mod benchmark;
+mod billboard;
+mod bricks;
 mod camera;
+mod capture;
+mod cli;
+mod clouds;
 mod config;
+mod debug_draw;
+mod debug_view;
+mod display;
 mod editor;
+mod erosion;
+mod frame_limiter;
+mod gamepad;
+mod headless;
+mod heightfield;
+mod hiz;
+mod ibl;
 mod input;
+mod instancing;
+mod jobs;
+mod keybindings;
+mod loading;
+mod material;
 mod model;
-mod opengl;
+mod obj;
+mod origin;
+mod particles;
+mod postprocess;
+mod profiler;
+mod project;
 mod ray;
+mod recording;
+mod render_settings;
+mod render_targets;
+mod scene;
+mod settings;
 mod skybox;
+mod splat;
+mod temporal;
 mod terrain;
+mod text;
 mod texture;
+mod texture_manager;
+mod texture_watcher;
+mod timestep;
 mod utils;
+mod water;
 
+use std::collections::HashMap;
 use std::error::Error;
+use std::f32::consts::TAU;
+use std::path::Path;
 use std::time::Instant;
 
-use egui::{Event as GuiEvent, Pos2, RawInput as EguiInput, Rect};
 use egui_winit::State as EguiState;
 use gl::types::GLuint;
-use glam::{Mat4, Quat, Vec2, Vec3};
+use glam::{IVec3, Mat4, Quat, Vec2, Vec3, Vec3Swizzles, Vec4};
+use glutin::dpi::PhysicalSize;
 use glutin::event::{
-    DeviceEvent, ElementState, Event, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode,
-    WindowEvent,
+    DeviceEvent, ElementState, Event, Force, KeyboardInput, MouseButton, MouseScrollDelta, Touch,
+    TouchPhase, VirtualKeyCode, WindowEvent,
 };
 use glutin::event_loop::{ControlFlow, EventLoop};
 use glutin::window::WindowBuilder;
 use glutin::{Api, GlProfile, GlRequest};
 use glutin::{PossiblyCurrent, WindowedContext};
 
+use app::{AppState, State};
+use atmosphere::Atmosphere;
+use benchmark::Benchmark;
+use billboard::{Billboard, BillboardMode, BillboardRenderer};
+use bricks::clipboard::ClipboardOp;
+use bricks::{BrickWorld, Placement};
 use camera::Camera;
+use capture::CubemapCapture;
+use clouds::CloudRenderer;
 use config::Config;
-use editor::gui::{Action, Gui};
-use input::{vec2_to_egui_pos2, vec2_to_egui_vec2, vkeycode_to_egui_key, Input, Modifiers};
+use debug_draw::DebugRenderer;
+use debug_view::{DebugView, OverdrawHeatmap};
+use display::DisplaySettings;
+use editor::align::{self, AlignOp, DistributeOp};
+use editor::commands::CommandRegistry;
+use editor::dialogs::{self, FileKind};
+use editor::gui::{Action, EmitterItem, Gui};
+use editor::outliner::{OutlinerItem, SceneItems};
+use editor::undo::{PlacedObject, Undo, UndoStack};
+use editor::{BrickTool, EditorState, SkyboxCapture, Tool, TransformMode};
+use gamepad::Gamepads;
+use hiz::HiZBuffer;
+use ibl::EnvironmentLighting;
+use input::{
+    vec2_to_egui_pos2, vec2_to_egui_vec2, vkeycode_to_egui_key, Input, InputAxis, InputEvent,
+    Modifiers, MouseSettings, Pen, PointerButton,
+};
+use instancing::{InstanceId, InstancedRenderer, MeshId};
+use jobs::{Callbacks, JobSystem};
+use keybindings::KeyAction;
+use loading::{ImageLoader, LoadedImage};
 use model::Model;
+use origin::WorldOrigin;
+use particles::{Emitter, ParticleSystem};
+use postprocess::{PostProcess, PostProcessSettings};
+use profiler::Profiler;
+use project::{Bookmark, Lighting, Project, SavedLayer, SavedObject};
+use ray::Ray;
+use recording::{InputPlayer, InputRecorder, RecordingMode};
+use render_settings::RenderSettings;
+use render_targets::{PreviewMode, RenderTarget, RenderTargetViewer};
+use scene::{FollowCamera, GlobalTransform, Scene, Transform};
+use settings::{Settings, WindowLayout};
 use skybox::Skybox;
-use terrain::Terrain;
-
+use splat::{GeneratedNormalMap, LayerMap, DEFAULT_ALBEDO};
+use terrain::{HeightmapReadback, Terrain};
+use text::{Label, LabelSize, TextRenderer};
+use texture_watcher::TextureWatcher;
+use timestep::FixedTimestep;
+use water::Water;
+
+use crate::opengl::bindings::{self, TextureUnit};
 use crate::opengl::shader::Program;
-use crate::texture::unit_to_gl_const;
+use crate::utils::XorShift;
 
 type Result<T> = std::result::Result<T, Box<dyn Error>>;
 
 // ==================================== Main loop =================================================
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(result) = cli::run(&args) {
+        if let Err(error) = result {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // The commands print their errors, the editor may not have a terminal
+    crash::install_panic_hook();
     let event_loop = EventLoop::new();
-    let mut game = Game::new(&event_loop).unwrap_or_else(|error| {
+    let recording = cli::recording_mode(&args);
+    let benchmark = cli::benchmark_output(&args).map(Benchmark::new);
+    let mut game = Game::new(&event_loop, recording, benchmark).unwrap_or_else(|error| {
         eprintln!("{}", error);
         std::process::exit(1);
     });
@@ -61,61 +168,20 @@ fn main() {
 
 // ==================================== Game ======================================================
 
-static mut WINDOW_WIDTH: usize = 0;
-static mut WINDOW_HEIGHT: usize = 0;
-
-struct DirectionalLight {
-    color: Vec3,
-    direction: Vec3,
-}
-
-enum GameMode {
-    Game,
-    Editor,
-    Menu,
-}
-
-enum EditorMode {
-    General,
-    Terrain { tool: TerrainTool },
-}
-
-struct EditorState {}
-
-enum TerrainTool {
-    Sculpt,
-    PaintTextures,
-    PaintTrees,
-    PaintVegetation,
-}
-
+/// Shared by all the shaders through the block in shaders/common/frame_uniforms.glsl,
+/// uploaded once per frame.
 // NOTE: no need to worry about std140 because Mat4's are aligned properly and with no gaps
 #[repr(C)]
-pub struct CameraTransforms {
+#[derive(Clone, Copy)]
+pub struct FrameUniforms {
     mvp: Mat4,
     proj: Mat4,
     view: Mat4,
     model: Mat4, // still unsure whether it belongs here
     sun_vp: Mat4,
-}
-
-// Intentionally dumb
-struct GameObject {
-    pos: Vec3,
-    orientation: Quat,
-    model: Model,
-}
-
-impl GameObject {
-    pub fn get_model_matrix(&self) -> Mat4 {
-        Mat4::from_rotation_translation(self.orientation, self.pos)
-    }
-
-    pub fn set_model_matrix(&mut self, model_matrix: &Mat4) {
-        let (_scale, orientation, pos) = model_matrix.to_scale_rotation_translation();
-        self.pos = pos;
-        self.orientation = orientation;
-    }
+    camera_position: Vec4, // w is unused
+    /// Seconds since the start, for animating things like waves
+    time: f32,
 }
 
 struct Game {
@@ -123,71 +189,152 @@ struct Game {
 
     windowed_context: WindowedContext<PossiblyCurrent>,
     in_focus: bool,
+    /// Where the cursor was when it was grabbed to turn the camera, see `grab_cursor`
+    grabbed_at: Option<Vec2>,
 
     game_start: Instant,
     frame_start: Instant,
+    /// Steps the simulation, see `timestep`
+    timestep: FixedTimestep,
 
+    /// Physical pixels per logical one. The pointer is in logical pixels like the
+    /// GUI, the window and the render targets are in physical ones.
     scale_factor: f32,
 
-    old_input: Input,
     input: Input,
 
     gui: Gui,
     gui_state: EguiState,
 
     camera: Camera,
+    origin: WorldOrigin,
 
     terrain: Terrain,
     skybox: Skybox,
-
-    mode: GameMode,
+    environment: EnvironmentLighting,
+    clouds: CloudRenderer,
+    particles: ParticleSystem,
+    billboards: BillboardRenderer,
+    text: TextRenderer,
+    debug_renderer: DebugRenderer,
+    overdraw: OverdrawHeatmap,
+    target_viewer: RenderTargetViewer,
+    hiz: HiZBuffer,
+    profiler: Profiler,
+    atmosphere: Atmosphere,
+    post_process: PostProcess,
+    post_settings: PostProcessSettings,
+    /// Applied with `apply_render_settings`
+    render_settings: RenderSettings,
+    water: Water,
+
+    /// Menu, editor or play mode, see `app`
+    state: Box<dyn State>,
+    /// The editor's camera while play mode walks with a copy of it
+    editor_camera: Option<Camera>,
 
     editor_state: EditorState,
-    editor_mode: EditorMode,
 
+    jobs: JobSystem,
+    image_loader: ImageLoader,
+    /// None if the textures directory can't be watched
+    texture_watcher: Option<TextureWatcher>,
+    mouse_settings: MouseSettings,
+    display_settings: DisplaySettings,
+    gamepads: Gamepads,
+    /// Set by the `record` command
+    recorder: Option<InputRecorder>,
+    /// Set by the `replay` command, the live input is ignored while it plays
+    player: Option<InputPlayer>,
+    /// Set by the `benchmark` command, which flies the camera and ignores the input
+    benchmark: Option<Benchmark>,
+    /// Of the jobs spawned with `JobSystem::spawn_then`, run in `collect_finished_jobs`
+    callbacks: Callbacks<Game>,
+    /// Heightmaps being read back from the GPU and the paths to save them to
+    heightmap_readbacks: Vec<(HeightmapReadback, String)>,
+    /// Paths of the heightmaps and splatmaps being encoded and whether that worked
     // tmp
-    camera_transforms_ubo: GLuint,
-    camera_transforms: CameraTransforms,
+    frame_uniforms_ubo: GLuint,
+    frame_uniforms: FrameUniforms,
 
     model_shader: Program,
-    game_objects: Vec<GameObject>,
+    /// The placed models, see `scene`
+    scene: Scene,
+    /// Saved with the project, see `project::Bookmark`
+    bookmarks: Vec<Bookmark>,
+
+    instances: InstancedRenderer,
+    /// Meshes already loaded for instancing, by path
+    instanced_meshes: HashMap<String, MeshId>,
+    /// Instances placed by each scatter, the last one can be undone
+    scatters: Vec<Vec<InstanceId>>,
+    bricks: BrickWorld,
+    /// Cell where the drag of the select or delete tool started
+    brick_drag_start: Option<IVec3>,
+    /// How far the brick gizmo has been dragged past the selected bricks, which only
+    /// move by whole cells
+    brick_gizmo_offset: Vec3,
+    /// Ctrl+Z and Ctrl+Y, see `editor::undo`
+    undo: UndoStack,
+    /// The terrain from before the sculpting or painting stroke in progress
+    stroke_undo: Option<Undo>,
+    /// `BrickWorld::edits` as of the last brick marker on `undo`
+    brick_edits: u64,
+    /// Names of the saved prefabs, see `bricks::prefab`
+    prefabs: Vec<String>,
+    /// Images in `terrain::BRUSHES_DIR`
+    brush_textures: Vec<String>,
 }
 
 impl Game {
     /// Creates a window and inits a new game
-    fn new(event_loop: &EventLoop<()>) -> Result<Self> {
-        let config = Config::load_or_default()?;
+    fn new(
+        event_loop: &EventLoop<()>,
+        recording: Option<RecordingMode>,
+        benchmark: Option<Benchmark>,
+    ) -> Result<Self> {
+        let mut config = Config::load_or_default()?;
+        let mut settings = Settings::load();
+        if benchmark.is_some() {
+            // The same scene with the same settings on every machine, whatever the
+            // editor was left with. Neither is saved.
+            settings = Settings::default();
+            settings.display.vsync = false;
+            settings.display.resolution = Some(benchmark::RESOLUTION);
+            config.project_path = None;
+            config.start_with_flat_terrain = false;
+            config.heightmap_path = benchmark::HEIGHTMAP.to_owned();
+            config.sky_panorama = None;
+        }
+        let (recorder, player) = match recording {
+            Some(RecordingMode::Record(path)) => (Some(InputRecorder::create(&path)?), None),
+            Some(RecordingMode::Replay(path)) => (None, Some(InputPlayer::open(&path)?)),
+            None => (None, None),
+        };
 
         // Create window
-        #[cfg(all(windows))]
-        let window_builder = {
-            let monitor = event_loop.primary_monitor().unwrap_or_else(|| {
-                event_loop
-                    .available_monitors()
-                    .next()
-                    .expect("Couldn't find monitor")
-            });
-            let inner_size = glutin::dpi::LogicalSize::new(
-                monitor.size().width - 100,
-                monitor.size().height - 100,
-            );
-
-            WindowBuilder::new()
-                .with_title("Мёртвый трилистник")
-                .with_resizable(false)
-                .with_position(glutin::dpi::LogicalPosition::new(70, 10))
-                .with_inner_size(inner_size)
+        let monitor = display::find_monitor(
+            &settings.display,
+            event_loop.available_monitors(),
+            event_loop
+                .primary_monitor()
+                .or_else(|| event_loop.available_monitors().next()),
+        );
+        let window_builder = WindowBuilder::new().with_title("Мёртвый трилистник");
+        let window_builder = match &monitor {
+            Some(monitor) => {
+                let (position, size) = display::window_rect(&settings.display, monitor);
+                window_builder.with_position(position).with_inner_size(size)
+            }
+            None => window_builder.with_inner_size(glutin::dpi::LogicalSize::new(1920, 1080)),
         };
 
-        #[cfg(not(windows))]
-        let window_builder = WindowBuilder::new()
-            .with_title("Мёртвый трилистник")
-            .with_position(glutin::dpi::LogicalPosition::new(70, 10))
-            // .with_fullscreen(Some(glutin::window::Fullscreen::Borderless(
-            //     event_loop.primary_monitor(),
-            // )))
-            .with_inner_size(glutin::dpi::LogicalSize::new(1920, 1080))
-            .with_resizable(false);
+        // The windowed size and position above are kept for when it's toggled off
+        let window_builder = if settings.fullscreen {
+            window_builder.with_fullscreen(Some(display::fullscreen(&settings.display, monitor)))
+        } else {
+            window_builder
+        };
 
         let gl_request = GlRequest::Specific(Api::OpenGl, (4, 5));
         let gl_profile = GlProfile::Core;
@@ -197,29 +344,33 @@ impl Game {
             .with_srgb(true)
             .with_double_buffer(Some(true))
             .with_depth_buffer(16)
-            .with_vsync(true)
+            .with_stencil_buffer(8)
+            .with_vsync(settings.display.vsync)
+            // Debug contexts report more, see `opengl::enable_debug_output`
+            .with_gl_debug_flag(cfg!(debug_assertions))
             .build_windowed(window_builder, event_loop)?;
 
         // Set up OpenGL
         let windowed_context = unsafe { windowed_context.make_current().unwrap() };
         gl::load_with(|s| windowed_context.get_proc_address(s) as *const _);
+        crash::set_driver(opengl::driver());
         let window = windowed_context.window();
-        // window.set_cursor_grab(true)?;
-        // window.set_cursor_visible(false);
         let window_size = window.inner_size();
         unsafe {
-            // Remember window dimensions for further viewport adjustments
-            WINDOW_WIDTH = window_size.width as usize;
-            WINDOW_HEIGHT = window_size.height as usize;
-
             gl::Viewport(0, 0, window_size.width as i32, window_size.height as i32);
             gl::ClearColor(0.05, 0.05, 0.05, 1.0);
             gl::Enable(gl::DEPTH_TEST);
             gl::Enable(gl::FRAMEBUFFER_SRGB);
             gl::Enable(gl::CULL_FACE);
+            // Filtering across the cubemap faces, the blurry IBL levels show seams otherwise
+            gl::Enable(gl::TEXTURE_CUBE_MAP_SEAMLESS);
+        }
 
-            gl::Enable(gl::DEBUG_OUTPUT);
-            gl::DebugMessageCallback(Some(opengl::debug_callback), std::ptr::null());
+        // Driver messages and edited shaders compiled again while the editor runs
+        #[cfg(debug_assertions)]
+        {
+            opengl::enable_debug_output(config.gl_debug_severity, config.gl_break_on_error);
+            opengl::hot_reload::watch();
         }
 
         // // Directional light
@@ -237,7 +388,8 @@ impl Game {
             .camera_position
             .unwrap_or_else(|| Vec3::new(520.0, 250.0, 100.0));
         let target = position + config.camera_direction.unwrap_or(-position);
-        let camera = Camera::new(position, target, window_size.width, window_size.height);
+        let mut camera = Camera::new(position, target, window_size.width, window_size.height);
+        camera.movement_speed = settings.camera_speed;
 
         // Set up camera transforms uniform buffer
         let mut transforms_ubo: GLuint = 0;
@@ -245,83 +397,115 @@ impl Game {
             gl::CreateBuffers(1, &mut transforms_ubo);
             gl::NamedBufferStorage(
                 transforms_ubo,
-                std::mem::size_of::<CameraTransforms>() as isize,
+                std::mem::size_of::<FrameUniforms>() as isize,
                 std::ptr::null(),
                 gl::DYNAMIC_STORAGE_BIT,
             );
             gl::BindBufferBase(gl::UNIFORM_BUFFER, 1, transforms_ubo);
         }
+        let atmosphere = Atmosphere::default();
         let transforms_data = {
             let proj = camera.get_projection_matrix();
             let view = camera.get_view_matrix();
             let model = Mat4::IDENTITY;
-            let sun_proj = Mat4::orthographic_rh_gl(-600.0, 600.0, -600.0, 600.0, 1.0, 1200.0);
-            let sun_view = Mat4::look_at_rh(
-                Vec3::new(0.0, 200.0, 500.0),
-                Vec3::new(0.0, 0.0, 0.0),
-                Vec3::new(0.0, 1.0, 0.0),
-            );
 
-            CameraTransforms {
+            FrameUniforms {
                 mvp: proj * view * model,
                 proj,
                 view,
                 model,
-                sun_vp: sun_proj * sun_view,
+                sun_vp: atmosphere.light_view_projection(),
+                camera_position: camera.position.extend(1.0),
+                time: 0.0,
             }
         };
 
-        let terrain = Terrain::new(
+        // The heightmap and the bricks of a project are loaded with the rest of it
+        let open_project = config.project_path.is_some();
+        let mut terrain = Terrain::new(
             Vec2::new(0.0, 0.0),
-            config.start_with_flat_terrain,
+            config.start_with_flat_terrain || open_project,
             &config.heightmap_path,
+            &config.brush_path,
         )?;
+        terrain.brush.settings = settings.brush.clone();
 
-        let skybox = Skybox::from([
-            "textures/skybox/default/right.png",
-            "textures/skybox/default/left.png",
-            "textures/skybox/default/top.png",
-            "textures/skybox/default/bottom.png",
-            "textures/skybox/default/front.png",
-            "textures/skybox/default/back.png",
-        ])?;
-
-        let game_objects = vec![
-            GameObject {
-                pos: Vec3::new(0.0, 0.0, 0.0),
-                orientation: Quat::default(),
-                model: Model::load("models/viking_room/scene.gltf")?,
-            },
-            GameObject {
-                pos: Vec3::new(100.0, 100.0, 0.0),
-                orientation: Quat::default(),
-                model: Model::load("models/box/box.gltf")?,
-            },
-            GameObject {
-                pos: Vec3::new(-100.0, 100.0, 0.0),
-                orientation: Quat::default(),
-                model: Model::load("models/box/box.gltf")?,
-            },
-        ];
+        // The big images are decoded on the workers, until then there are placeholders
+        let jobs = JobSystem::new();
+        let mut image_loader = ImageLoader::default();
+        let skybox = if let Some(path) = &config.sky_panorama {
+            Skybox::from_equirect(path)?
+        } else {
+            load_skybox_faces(&mut image_loader, &jobs);
+            Skybox::new()?
+        };
+        let decode_albedo = terrain
+            .material
+            .import_job(0, LayerMap::Albedo, DEFAULT_ALBEDO);
+        image_loader.load(&jobs, DEFAULT_ALBEDO, move || {
+            decode_albedo()
+                .map(LoadedImage::TerrainLayer)
+                .map_err(|err| err.to_string())
+        });
+        let clouds = CloudRenderer::new()?;
+        let post_process = PostProcess::new(window_size.width as i32, window_size.height as i32)?;
+
+        let mut scene = Scene::default();
+        for (name, path, position) in [
+            (
+                "Viking room",
+                "models/viking_room/scene.gltf",
+                Vec3::new(0.0, 0.0, 0.0),
+            ),
+            ("Box 1", "models/box/box.gltf", Vec3::new(100.0, 100.0, 0.0)),
+            (
+                "Box 2",
+                "models/box/box.gltf",
+                Vec3::new(-100.0, 100.0, 0.0),
+            ),
+        ] {
+            scene.spawn_object(
+                name.to_owned(),
+                Model::load(path)?,
+                path.to_owned(),
+                Transform::new(position, Quat::default()),
+                true,
+            );
+        }
+        let first_selected = scene.objects()[1];
 
         let model_shader = Program::new()
-            .vertex_shader(include_str!("shaders/simple/simple.vert"))?
-            .fragment_shader(include_str!("shaders/simple/simple.frag"))?
+            .vertex_shader(shader_file!("mesh/mesh.vert"))?
+            .fragment_shader(shader_file!("mesh/mesh.frag"))?
             .link()?;
 
         let screen_size_physical = Vec2::new(window_size.width as f32, window_size.height as f32);
 
         // Gui and its initial input
-        let gui = Gui::new(screen_size_physical)?;
+        let gui = Gui::new(
+            screen_size_physical,
+            CommandRegistry::with_default_commands(),
+        )?;
         let gui_state = EguiState::new(window);
 
+        let mut instances = InstancedRenderer::new()?;
+        let mut bricks = BrickWorld::new(&terrain, &mut instances, config.undo_depth)?;
+        let undo = UndoStack::new(config.undo_depth);
+        if !open_project && benchmark.is_none() && Path::new(&config.bricks_path).exists() {
+            // A broken save shouldn't keep the editor from starting
+            if let Err(err) = bricks.load(&config.bricks_path, &mut instances) {
+                log!("Failed to load {}: {}", config.bricks_path, err);
+            }
+        }
+        let prefabs = bricks::prefab::list(&config.prefabs_path);
+
         let now = Instant::now();
         let input = Input {
             camera_moved: true,
             ..Default::default()
         };
 
-        Ok(Game {
+        let mut game = Game {
             config,
 
             scale_factor: window.scale_factor() as f32,
@@ -329,39 +513,164 @@ impl Game {
 
             game_start: now,
             frame_start: now,
+            timestep: FixedTimestep::default(),
 
-            old_input: Input::default(),
             input,
 
             gui,
             gui_state,
 
             camera,
+            origin: WorldOrigin::default(),
             in_focus: true,
+            grabbed_at: None,
 
             terrain,
             skybox,
-
-            mode: GameMode::Editor,
-            editor_state: EditorState {},
-            editor_mode: EditorMode::Terrain {
-                tool: TerrainTool::Sculpt,
+            environment: EnvironmentLighting::new()?,
+            clouds,
+            particles: ParticleSystem::new()?,
+            billboards: BillboardRenderer::new()?,
+            text: TextRenderer::new()?,
+            debug_renderer: DebugRenderer::new()?,
+            overdraw: OverdrawHeatmap::new()?,
+            target_viewer: RenderTargetViewer::new()?,
+            hiz: HiZBuffer::new()?,
+            profiler: Profiler::default(),
+            atmosphere,
+            post_process,
+            post_settings: settings.graphics,
+            render_settings: RenderSettings::default(),
+            water: Water::default(),
+
+            state: app::new_state(if benchmark.is_some() {
+                AppState::Editor
+            } else {
+                AppState::Menu
+            }),
+            editor_camera: None,
+            editor_state: {
+                let mut editor_state = EditorState {
+                    selected_objects: vec![first_selected],
+                    ..Default::default()
+                };
+                settings.windows.apply(&mut editor_state);
+                editor_state.show_gui = benchmark.is_none();
+                editor_state
             },
 
-            camera_transforms_ubo: transforms_ubo,
-            camera_transforms: transforms_data,
-
-            game_objects,
+            jobs,
+            image_loader,
+            texture_watcher: TextureWatcher::new(),
+            mouse_settings: settings.mouse,
+            display_settings: settings.display,
+            gamepads: Gamepads::new(settings.gamepad),
+            recorder,
+            player,
+            benchmark,
+            callbacks: Callbacks::default(),
+            heightmap_readbacks: vec![],
+
+            frame_uniforms_ubo: transforms_ubo,
+            frame_uniforms: transforms_data,
+
+            scene,
+            bookmarks: vec![],
             model_shader,
-        })
+
+            instances,
+            instanced_meshes: HashMap::new(),
+            scatters: vec![],
+            bricks,
+            brick_drag_start: None,
+            brick_gizmo_offset: Vec3::ZERO,
+            undo,
+            stroke_undo: None,
+            brick_edits: 0,
+            prefabs,
+            brush_textures: terrain::brush_textures(),
+        };
+        game.apply_render_settings(settings.rendering);
+        game.text.set_scale_factor(game.scale_factor);
+        if let Some(dir) = game.config.project_path.clone() {
+            // Starts with an empty terrain rather than not at all
+            if let Err(err) = game.open_project(&dir) {
+                log!("Failed to open {}: {}", dir, err);
+            }
+        }
+        Ok(game)
     }
 
     fn process_event(&mut self, event: Event<()>, control_flow: &mut ControlFlow) -> Result<()> {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(&event);
+        }
+        if self.benchmark.is_some() && recording::is_input(&event) {
+            return Ok(());
+        }
+        if let Some(player) = &mut self.player {
+            if recording::is_input(&event) {
+                return Ok(());
+            }
+            if let Event::MainEventsCleared = event {
+                // The recorded events of the frame come in before it's drawn
+                let window_id = self.windowed_context.window().id();
+                match player.advance(window_id) {
+                    Some(events) => {
+                        for event in events {
+                            self.handle_event(event, control_flow)?;
+                        }
+                    }
+                    None => {
+                        log!("Finished replaying the input");
+                        self.player = None;
+                    }
+                }
+            }
+        }
+        self.handle_event(event, control_flow)
+    }
+
+    fn handle_event(&mut self, event: Event<()>, control_flow: &mut ControlFlow) -> Result<()> {
         match event {
             Event::WindowEvent { event, .. } => {
-                // Let egui know about the event
+                if let Some(action) = self.editor_state.rebinding {
+                    // The next key pressed is bound to the action, not given to egui
+                    if let WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(key),
+                                ..
+                            },
+                        ..
+                    } = event
+                    {
+                        if key != VirtualKeyCode::Escape {
+                            self.config.keybindings.bind(action, key);
+                            self.config.save();
+                        }
+                        self.editor_state.rebinding = None;
+                        return Ok(());
+                    }
+                }
+
+                // Let egui know about the event. It turns keys, typed characters,
+                // modifiers and clipboard shortcuts into its own input
                 let captured = self.gui_state.on_event(self.gui.ctx(), &event);
-                if captured {
+                // Key releases still go through, otherwise a key held when a text
+                // field takes the keyboard would stay down, e.g. moving the camera
+                let released = matches!(
+                    event,
+                    WindowEvent::KeyboardInput {
+                        input: KeyboardInput {
+                            state: ElementState::Released,
+                            ..
+                        },
+                        ..
+                    }
+                );
+                if captured && !released {
                     // Egui wants this event exclusively
                     return Ok(());
                 }
@@ -369,11 +678,16 @@ impl Game {
                 // Process window event
                 match event {
                     WindowEvent::CloseRequested => self.input.should_exit = true,
+                    WindowEvent::Resized(size) => self.resize(size),
+                    // E.g. moved to a monitor with a different DPI. Egui picks up the
+                    // new scale itself, see `EguiState::on_event`.
                     WindowEvent::ScaleFactorChanged {
                         scale_factor,
-                        new_inner_size: _,
+                        new_inner_size,
                     } => {
                         self.scale_factor = scale_factor as f32;
+                        self.text.set_scale_factor(self.scale_factor);
+                        self.resize(*new_inner_size);
                     }
                     WindowEvent::ModifiersChanged(state) => {
                         self.input.modifiers = Modifiers {
@@ -383,42 +697,75 @@ impl Game {
                             logo: state.logo(),
                         };
                     }
-                    WindowEvent::CursorMoved { position, .. } => {
+                    // The pointer stays put while the camera turns
+                    WindowEvent::CursorMoved { position, .. } if self.grabbed_at.is_none() => {
                         let pointer =
                             Vec2::new(position.x as f32, position.y as f32) / self.scale_factor;
-                        self.input.pointer = pointer;
-                        self.input.pointer_moved = true;
+                        self.input.move_pointer(pointer);
                     }
                     WindowEvent::MouseInput { button, state, .. } => {
                         let pressed = state == ElementState::Pressed;
-
-                        match button {
-                            MouseButton::Left => self.input.mouse_buttons.primary = pressed,
-                            MouseButton::Right => self.input.mouse_buttons.secondary = pressed,
-                            MouseButton::Middle => self.input.mouse_buttons.middle = pressed,
-                            _ => {}
-                        }
+                        let button = match button {
+                            MouseButton::Left => PointerButton::Primary,
+                            MouseButton::Right => PointerButton::Secondary,
+                            MouseButton::Middle => PointerButton::Middle,
+                            _ => return Ok(()),
+                        };
+                        self.input.set_button(button, pressed);
                     }
+                    WindowEvent::Touch(touch) => self.process_touch(touch),
                     WindowEvent::Focused(focused) => {
                         self.in_focus = focused;
-                        self.input.modifiers = Modifiers::default();
+                        self.input.release_keys();
+                        if !focused {
+                            // The buttons may be released in another window
+                            self.input.release_buttons();
+                            self.grab_cursor(false);
+                        }
                     }
                     WindowEvent::KeyboardInput {
                         input:
                             KeyboardInput {
                                 state,
-                                virtual_keycode: Some(virtual_key_code),
+                                virtual_keycode: Some(key),
                                 ..
                             },
                         ..
                     } => {
                         let pressed = state == ElementState::Pressed;
 
-                        match virtual_key_code {
-                            VirtualKeyCode::W => self.input.forward = pressed,
-                            VirtualKeyCode::A => self.input.left = pressed,
-                            VirtualKeyCode::S => self.input.back = pressed,
-                            VirtualKeyCode::D => self.input.right = pressed,
+                        if pressed && self.input.modifiers.alt && key == VirtualKeyCode::Return {
+                            self.toggle_fullscreen();
+                            return Ok(());
+                        }
+                        // The editor and play mode go back to the menu
+                        if pressed
+                            && key == VirtualKeyCode::Escape
+                            && self.state.kind() != AppState::Menu
+                        {
+                            self.change_state(AppState::Menu);
+                            return Ok(());
+                        }
+                        // Ctrl shortcuts win over the bound keys, even the held ones
+                        if pressed
+                            && self.input.modifiers.ctrl
+                            && self.with_state(|state, game| state.ctrl_shortcut(game, key))
+                        {
+                            return Ok(());
+                        }
+                        let action = self.config.keybindings.action(key);
+                        // Other keys with Ctrl do nothing, except for the held actions
+                        // like moving
+                        let shortcut = pressed
+                            && self.input.modifiers.ctrl
+                            && !action.map_or(false, KeyAction::is_held);
+                        match action {
+                            Some(action) if !shortcut => {
+                                self.input.set_action(action, pressed);
+                            }
+                            _ if pressed => {
+                                self.with_state(|state, game| state.key_pressed(game, key));
+                            }
                             _ => {}
                         }
                     }
@@ -429,15 +776,13 @@ impl Game {
                 DeviceEvent::MouseMotion { delta } if self.in_focus => {
                     let (x, y) = delta;
                     let delta = Vec2::new(x as f32, y as f32) / self.scale_factor;
-                    self.input.pointer_delta += delta;
-                    self.input.pointer_moved = true;
+                    self.input.move_mouse(delta);
                 }
                 DeviceEvent::MouseWheel {
                     delta: MouseScrollDelta::LineDelta(x, y),
                 } => {
                     let scroll_delta = Vec2::new(x, y) / self.scale_factor;
-                    self.input.scroll_delta += scroll_delta;
-                    self.input.scrolled = true;
+                    self.input.scroll(scroll_delta);
                 }
                 _ => {}
             },
@@ -445,6 +790,10 @@ impl Game {
                 if !self.input.should_exit {
                     self.update_and_render()?;
                 } else {
+                    if self.benchmark.is_none() {
+                        self.save_settings();
+                    }
+                    self.finish_heightmap_saves();
                     *control_flow = ControlFlow::Exit;
                 }
             }
@@ -453,185 +802,1957 @@ impl Game {
         Ok(())
     }
 
+    /// Does what the actions pressed since the last frame do in the order they were
+    /// pressed, see `keybindings`. The menu has none.
+    fn trigger_actions(&mut self) {
+        for event in self.input.drain_events() {
+            if let InputEvent::Pressed(action) = event {
+                self.with_state(|state, game| state.key_action(game, action));
+            }
+        }
+    }
+
+    /// Lends the active state the rest of the game
+    fn with_state<T>(&mut self, f: impl FnOnce(&mut dyn State, &mut Game) -> T) -> T {
+        // The menu has nothing in it, so nothing is allocated for the stand-in
+        let mut state = std::mem::replace(&mut self.state, Box::new(app::Menu));
+        let result = f(state.as_mut(), self);
+        self.state = state;
+        result
+    }
+
+    /// Leaves the current state for the new one. Play mode walks with a copy of the
+    /// editor camera, which is put back when it ends.
+    fn change_state(&mut self, state: AppState) {
+        if state == self.state.kind() {
+            return;
+        }
+        if let Some(camera) = self.editor_camera.take() {
+            self.camera = camera;
+        }
+        if state == AppState::Play {
+            let mut camera = self.camera.clone();
+            camera.set_walking(true);
+            camera.movement_speed = app::WALK_SPEED;
+            camera.speed_boost = false;
+            self.editor_camera = Some(std::mem::replace(&mut self.camera, camera));
+        }
+        self.grab_cursor(false);
+        self.terrain.hide_cursor();
+        self.set_cursor_visible(true);
+        self.input.camera_moved = true;
+        self.state = app::new_state(state);
+    }
+
+    fn key_action(&mut self, action: KeyAction) {
+        match action {
+            // Queried with `Input::pressed` where they're used
+            KeyAction::MoveForward
+            | KeyAction::MoveBack
+            | KeyAction::MoveLeft
+            | KeyAction::MoveRight
+            | KeyAction::InvertBrush => {}
+            KeyAction::ToggleGui => {
+                self.editor_state.show_gui = !self.editor_state.show_gui;
+            }
+            KeyAction::ToggleBrickMode => {
+                let tool = &mut self.editor_state.tool;
+                *tool = if *tool == Tool::Bricks {
+                    Tool::Sculpt
+                } else {
+                    Tool::Bricks
+                };
+            }
+            KeyAction::WireframeView
+            | KeyAction::NormalsView
+            | KeyAction::TessPatchesView
+            | KeyAction::OverdrawView => {
+                let view = self.editor_state.debug_view;
+                if let Some(view) = view.toggled_by(action) {
+                    self.editor_state.debug_view = view;
+                }
+            }
+            KeyAction::TranslateGizmo | KeyAction::RotateGizmo | KeyAction::ScaleGizmo => {
+                if let Some(&mode) = TransformMode::ALL
+                    .iter()
+                    .find(|mode| mode.key_action() == action)
+                {
+                    self.editor_state.transform_mode = mode;
+                }
+            }
+            KeyAction::PlaceTool
+            | KeyAction::SelectTool
+            | KeyAction::DeleteTool
+            | KeyAction::RotateBrick
+            | KeyAction::DeleteBricks => {
+                if self.editor_state.tool == Tool::Bricks {
+                    self.brick_key_action(action);
+                }
+            }
+        }
+    }
+
+    /// Fits the viewport, camera, GUI and the screen-sized targets to the new window size
+    fn resize(&mut self, size: PhysicalSize<u32>) {
+        if size.width == 0 || size.height == 0 {
+            return; // minimized
+        }
+        self.windowed_context.resize(size);
+        let (width, height) = (size.width as i32, size.height as i32);
+        unsafe {
+            gl::Viewport(0, 0, width, height);
+        }
+        self.camera.resize(size.width, size.height);
+        self.input.camera_moved = true;
+        self.gui
+            .resize(Vec2::new(size.width as f32, size.height as f32));
+        if let Err(err) = self.post_process.resize(width, height) {
+            log!("Couldn't resize the scene targets: {}", err);
+        }
+    }
+
+    /// Recreates the shadow map and the scene targets if their sizes changed, the
+    /// rest is changed in place
+    fn apply_render_settings(&mut self, settings: RenderSettings) {
+        self.terrain.set_shadow_map_size(settings.shadow_map_size);
+        bindings::set_anisotropy(settings.anisotropy);
+        let quality = self
+            .post_process
+            .set_quality(settings.render_scale, settings.anti_aliasing);
+        if let Err(err) = quality {
+            log!("Couldn't resize the scene targets: {}", err);
+        }
+        self.render_settings = settings;
+    }
+
+    /// Switches between borderless fullscreen on the monitor the window is on and the
+    /// window. The window then gets resized, which fits everything else to it.
+    fn toggle_fullscreen(&mut self) {
+        // Let go of the cursor before the window changes under it
+        self.grab_cursor(false);
+        let window = self.windowed_context.window();
+        if window.fullscreen().is_some() {
+            window.set_fullscreen(None);
+        } else {
+            let monitor = display::find_monitor(
+                &self.display_settings,
+                window.available_monitors(),
+                window.current_monitor(),
+            );
+            window.set_fullscreen(Some(display::fullscreen(&self.display_settings, monitor)));
+        }
+    }
+
+    /// Moves the window to the monitor and resolution in the display settings, or
+    /// switches to them if it's fullscreen. The resize that follows does the rest.
+    fn apply_display_settings(&mut self) {
+        self.grab_cursor(false);
+        let window = self.windowed_context.window();
+        let monitor = display::find_monitor(
+            &self.display_settings,
+            window.available_monitors(),
+            window.current_monitor(),
+        );
+        if window.fullscreen().is_some() {
+            window.set_fullscreen(Some(display::fullscreen(&self.display_settings, monitor)));
+        } else if let Some(monitor) = monitor {
+            let (position, size) = display::window_rect(&self.display_settings, &monitor);
+            window.set_inner_size(size);
+            window.set_outer_position(position);
+        }
+    }
+
+    /// Pens and touch screens act as the primary mouse button with pressure. The
+    /// tilt is only known where winit reports the altitude of the pen, on iOS.
+    fn process_touch(&mut self, touch: Touch) {
+        if let Some(pen) = self.input.pen {
+            if pen.touch_id != touch.id {
+                return;
+            }
+        }
+        let pointer =
+            Vec2::new(touch.location.x as f32, touch.location.y as f32) / self.scale_factor;
+        self.input.move_pointer(pointer);
+        match touch.phase {
+            TouchPhase::Started | TouchPhase::Moved => {
+                let pressure = touch.force.map_or(1.0, |force| force.normalized() as f32);
+                let tilt = match touch.force {
+                    // The altitude is 0 lying flat and a right angle upright
+                    Some(Force::Calibrated {
+                        altitude_angle: Some(altitude),
+                        ..
+                    }) => 1.0 - altitude as f32 / std::f32::consts::FRAC_PI_2,
+                    _ => 0.0,
+                };
+                self.input.pen = Some(Pen {
+                    touch_id: touch.id,
+                    pressure: pressure.clamp(0.0, 1.0),
+                    tilt: tilt.clamp(0.0, 1.0),
+                });
+                self.input.set_button(PointerButton::Primary, true);
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                self.input.pen = None;
+                self.input.set_button(PointerButton::Primary, false);
+            }
+        }
+    }
+
     fn update_and_render(&mut self) -> Result<()> {
         let now = Instant::now();
-        let delta_time = now.duration_since(self.frame_start).as_secs_f32();
+        let mut delta_time = now.duration_since(self.frame_start).as_secs_f32();
         self.frame_start = now;
         let time = now.duration_since(self.game_start).as_secs_f64();
         self.input.time = time as f32;
+        let replayed = self.player.as_ref().and_then(InputPlayer::frame);
+        if let Some(frame) = replayed {
+            // The recorded timing rather than the clock, so that every frame does the same
+            delta_time = frame.delta_time;
+            self.input.time = frame.time;
+            frame.apply_analog(&mut self.input);
+        } else {
+            self.gamepads.poll(&mut self.input);
+        }
+        if let Some(recorder) = &mut self.recorder {
+            recorder.end_frame(&self.input, delta_time);
+        }
 
-        let new_mode = match self.mode {
-            GameMode::Menu => unimplemented!("Menu is not implemented"),
-            GameMode::Game => unimplemented!("Game mode is not implemented"),
-            GameMode::Editor => self.draw_editor(delta_time)?,
-        };
-
-        self.mode = new_mode;
+        self.profiler.begin_frame(
+            self.editor_state.show_profiler || self.benchmark.is_some(),
+            delta_time,
+        );
+        if let Some(benchmark) = &mut self.benchmark {
+            benchmark.record(&self.profiler);
+            if benchmark.is_finished() {
+                self.finish_benchmark();
+                return Ok(());
+            }
+            let (position, direction) = benchmark.camera();
+            self.camera.look_from(position, direction);
+            self.input.camera_moved = true;
+            // Whatever the frame took, so that the sun and the clouds are the same
+            delta_time = timestep::STEP;
+        }
+        let steps = self.timestep.advance(delta_time);
+        self.atmosphere.update(steps as f32 * timestep::STEP);
+        self.collect_finished_jobs();
+        self.reload_changed_textures();
+        self.trigger_actions();
+        self.input.update_gestures();
+        self.terrain.update_heights_mirror(&self.jobs);
+        opengl::hot_reload::poll();
+
+        let frame_scope = profiler::scope("Frame");
+        let next_state = self.with_state(|state, game| -> Result<AppState> {
+            let next_state = state.update(game, delta_time, steps)?;
+            state.draw(game)?;
+            Ok(next_state)
+        })?;
+        drop(frame_scope);
+
+        self.change_state(next_state);
 
         Ok(())
     }
 
-    fn draw_editor(&mut self, delta_time: f32) -> Result<GameMode> {
-        let active_game_object = 1;
-        let mut model_matrix = self.game_objects[active_game_object].get_model_matrix();
+    /// Moves the camera right and forward by the keys or the left stick. Partly tilted
+    /// sticks move slower.
+    fn move_camera(&mut self, movement: Vec2, delta_time: f32) {
+        if movement == Vec2::ZERO {
+            return;
+        }
+        use camera::Movement::*;
+        let forward = if movement.y > 0.0 { Forward } else { Backward };
+        let right = if movement.x > 0.0 { Right } else { Left };
+        self.camera.go(forward, delta_time * movement.y.abs());
+        self.camera.go(right, delta_time * movement.x.abs());
+        self.input.camera_moved = true;
+    }
+
+    /// Turns the camera by the right stick, and by the mouse too with `mouse`
+    fn look_around(&mut self, mouse: bool, delta_time: f32) {
+        if mouse && self.input.pointer_moved {
+            let delta = self.mouse_settings.look_delta(self.input.pointer_delta);
+            self.camera.rotate(delta.x, delta.y);
+            self.input.camera_moved = true;
+        }
+        let look = Vec2::new(
+            self.input.axis(InputAxis::LookRight),
+            self.input.axis(InputAxis::LookUp),
+        ) * delta_time;
+        if look != Vec2::ZERO {
+            self.camera.turn(look.x, look.y);
+            self.input.camera_moved = true;
+        }
+    }
+
+    /// Moves what moves by itself: the falling bricks, the particles, and the origin
+    /// along with the camera. Then the transforms of the frame follow the camera and
+    /// the sun.
+    fn update_world(&mut self, delta_time: f32, steps: u32, sculpting: bool) -> Result<()> {
+        if let Some((min, max)) = self.terrain.take_changed_area() {
+            self.bricks.terrain_changed(min, max);
+        }
+        self.bricks.update_physics(
+            &self.terrain,
+            &mut self.instances,
+            steps,
+            self.timestep.alpha(),
+            self.editor_state.freeze_physics,
+        );
 
-        let actions = self.gui.layout_and_interact(
-            &mut self.gui_state,
-            self.windowed_context.window(),
-            &self.camera_transforms.view,
-            &self.camera_transforms.proj,
-            &mut model_matrix,
+        let brush_dust = &mut self.particles.brush_dust;
+        brush_dust.enabled = sculpting;
+        if sculpting {
+            let cursor = self.terrain.cursor;
+            let height = self.terrain.height_at(cursor).unwrap_or_default();
+            brush_dust.settings.area = Vec2::splat(self.terrain.brush.settings.size * 0.25);
+            self.particles.brush_dust_position = Vec3::new(cursor.x, height, cursor.y);
+        }
+        self.particles.update(
+            &mut self.scene.world,
+            delta_time,
+            self.input.time,
+            self.camera.position,
         );
-        self.game_objects[active_game_object].set_model_matrix(&model_matrix);
-        self.process_gui_actions(actions)?;
 
-        if self.gui.wants_input() {
-            // Pointer over UI or currently interacting with it
-            self.terrain.hide_cursor();
-            self.windowed_context.window().set_cursor_visible(true); // we always want cursor with UI
+        if let Some(shift) = self.origin.rebase(self.camera.position) {
+            self.shift_origin(shift)?;
+        }
+
+        // The sun moves with the time of day, so the transforms may change even if the camera doesn't
+        let sun_vp = self.atmosphere.light_view_projection();
+        if self.input.camera_moved || sun_vp != self.frame_uniforms.sun_vp {
+            self.frame_uniforms.view = self.camera.get_view_matrix();
+            self.frame_uniforms.proj = self.camera.get_projection_matrix();
+            self.frame_uniforms.mvp =
+                self.frame_uniforms.proj * self.frame_uniforms.view * self.frame_uniforms.model;
+            self.frame_uniforms.sun_vp = sun_vp;
+            self.frame_uniforms.camera_position = self.camera.position.extend(1.0);
+        }
+        // The time changes every frame anyway
+        self.frame_uniforms.time = self.input.time;
+        self.upload_frame_uniforms();
+        Ok(())
+    }
+
+    /// Starts the lit scene in the post-processing targets. The overlays are drawn
+    /// after it and before `end_world`.
+    fn begin_world(&mut self) -> Result<()> {
+        self.post_process.begin();
+        self.draw_scene(true)?;
+        if !self.particles.is_empty() {
+            self.particles
+                .draw(self.post_process.copy_depth(), &self.atmosphere)?;
+        }
+        Ok(())
+    }
+
+    /// Applies the post effects and puts the scene on the screen
+    fn end_world(&mut self) -> Result<()> {
+        self.debug_renderer.flush()?;
+        let _scope = profiler::scope("Post-process");
+        self.post_process.end(
+            &self.post_settings,
+            &self.atmosphere,
+            &self.water,
+            self.skybox.cubemap(),
+            self.terrain.shadow_map(),
+            &self.frame_uniforms.view,
+            &self.frame_uniforms.proj,
+        )?;
+        Ok(())
+    }
+
+    fn draw_gui(&mut self) {
+        let _scope = profiler::scope("GUI");
+        let _cpu_scope = profiler::cpu_scope("GUI draw");
+        self.gui.draw();
+    }
+
+    /// Shows the frame, waits for the next one if the frame rate is limited, and
+    /// starts its input
+    fn end_frame(&mut self) -> Result<()> {
+        self.windowed_context.swap_buffers()?;
+        if let Some(max_fps) = self.display_settings.max_fps {
+            frame_limiter::wait_for_frame(self.frame_start, max_fps);
+        }
+        self.input.renew();
+        Ok(())
+    }
+
+    /// Grabs and hides the cursor while the camera turns with the mouse, then puts it
+    /// back where it was. Some platforms only keep it inside the window rather than in
+    /// place, and some can't grab at all, but it's hidden and restored either way.
+    fn grab_cursor(&mut self, grab: bool) {
+        if grab == self.grabbed_at.is_some() {
+            return;
+        }
+        let window = self.windowed_context.window();
+        if grab {
+            window.set_cursor_grab(true).ok();
+            window.set_cursor_visible(false);
+            self.grabbed_at = Some(self.input.pointer);
         } else {
-            // Process input
-            self.camera.speed_boost = self.input.modifiers.shift;
-
-            // Move camera
-            if self.input.mouse_buttons.secondary {
-                use camera::Movement::*;
-                if self.input.forward {
-                    self.camera.go(Forward, delta_time);
-                    self.input.camera_moved = true;
+            window.set_cursor_grab(false).ok();
+            if let Some(pointer) = self.grabbed_at.take() {
+                let position = glutin::dpi::LogicalPosition::new(pointer.x, pointer.y);
+                window.set_cursor_position(position).ok();
+            }
+            window.set_cursor_visible(true);
+        }
+    }
+
+    /// Unless it's grabbed, when it stays hidden
+    fn set_cursor_visible(&self, visible: bool) {
+        if self.grabbed_at.is_none() {
+            self.windowed_context.window().set_cursor_visible(visible);
+        }
+    }
+
+    /// Through the pointer, which is in logical pixels while the camera's screen is in
+    /// physical ones
+    fn pointer_ray(&self) -> Ray {
+        self.camera
+            .get_ray_through_pixel(self.input.pointer * self.scale_factor)
+    }
+
+    /// Places or removes bricks under the pointer. Returns where bricks would be
+    /// placed to show them as ghosts.
+    fn use_brick_tool(&mut self) -> Vec<Placement> {
+        let ray = self.pointer_ray();
+        let pressed = self.input.mouse_buttons.primary;
+        let clicked = self.input.button_just_pressed(PointerButton::Primary);
+        let released = self.input.button_just_released(PointerButton::Primary);
+
+        if self.input.modifiers.alt {
+            // Pick up the color of a brick instead
+            if let Some(brick) = self.bricks.brick_at(&ray).filter(|_| clicked) {
+                self.editor_state.brick_color = brick.color;
+            }
+            return vec![];
+        }
+
+        match self.editor_state.brick_tool {
+            BrickTool::Place => self.place_brick(&ray, clicked),
+            BrickTool::Paste => {
+                let max_gap = self.editor_state.max_foundation_gap;
+                let placements = self.bricks.paste_placements(&ray, &self.terrain, max_gap);
+                if clicked && self.bricks.paste(&placements, &mut self.instances) {
+                    if self.editor_state.flatten_foundations {
+                        self.bricks
+                            .flatten_foundations(&placements, &mut self.terrain);
+                    }
+                    return vec![];
                 }
-                if self.input.left {
-                    self.camera.go(Left, delta_time);
-                    self.input.camera_moved = true;
+                placements
+            }
+            BrickTool::Select | BrickTool::Delete => {
+                let cell = self.bricks.cell_under(&ray, &self.terrain);
+                if clicked {
+                    self.brick_drag_start = cell;
                 }
-                if self.input.back {
-                    self.camera.go(Backward, delta_time);
-                    self.input.camera_moved = true;
+                // Dragging to another cell takes the whole area, otherwise it's a click
+                let gesture = self.input.gestures.primary;
+                let dragged = gesture.dragging || gesture.drag_ended;
+                let area = match (self.brick_drag_start, cell) {
+                    (Some(start), Some(end)) if dragged && start != end => {
+                        Some(self.bricks.area_between(start, end))
+                    }
+                    _ => None,
+                };
+                let delete = self.editor_state.brick_tool == BrickTool::Delete;
+                match &area {
+                    Some(area) if pressed => self.bricks.draw_area(area, delete),
+                    _ if delete => self.bricks.draw_removal(&ray),
+                    _ => {}
                 }
-                if self.input.right {
-                    self.camera.go(Right, delta_time);
-                    self.input.camera_moved = true;
+                if released && self.brick_drag_start.take().is_some() {
+                    let add = self.input.modifiers.shift;
+                    match (area, delete) {
+                        (Some(area), true) => {
+                            self.bricks.demolish(&area, &mut self.instances);
+                        }
+                        (None, true) => {
+                            self.bricks.remove_at(&ray, &mut self.instances);
+                        }
+                        (Some(area), false) => self.bricks.select_area(&area, add),
+                        // Double-clicking a brick selects all of its type
+                        (None, false) if gesture.double_clicked => {
+                            if let Some(kind) = self.bricks.brick_at(&ray).map(|brick| brick.kind) {
+                                self.bricks.select_kind(kind, add);
+                            }
+                        }
+                        (None, false) => self.bricks.select_at(&ray, add),
+                    }
                 }
+                vec![]
+            }
+        }
+    }
 
-                // Rotate camera
-                if self.input.pointer_moved {
-                    let delta = self.input.pointer_delta;
-                    self.camera.rotate(delta.x, delta.y);
-                    self.input.camera_moved = true;
-                }
+    /// Shows where the brick picked in the editor would go along the ray, and places
+    /// it when clicked. Returns where it would go to show it as a ghost.
+    fn place_brick(&mut self, ray: &Ray, clicked: bool) -> Vec<Placement> {
+        let kind = self.editor_state.brick_type;
+        let rotation = self.editor_state.brick_rotation;
+        let max_gap = self.editor_state.max_foundation_gap;
+        let placement = match self
+            .bricks
+            .placement(ray, &self.terrain, kind, rotation, max_gap)
+        {
+            Some(placement) => placement,
+            None => return vec![],
+        };
+        self.bricks.draw_placement(&placement);
+        if clicked && placement.valid {
+            let color = self.editor_state.brick_color;
+            let placed = self
+                .bricks
+                .place(&placement, color, &self.terrain, &mut self.instances);
+            if placed && self.editor_state.flatten_foundations {
+                self.bricks
+                    .flatten_foundations(&[placement], &mut self.terrain);
             }
+            // Shown where the next brick would go from the next frame
+            return vec![];
+        }
+        vec![placement]
+    }
 
-            if self.input.camera_moved {
-                // Update camera tranforms uniform buffer
-                self.camera_transforms.view = self.camera.get_view_matrix();
-                self.camera_transforms.proj = self.camera.get_projection_matrix();
-                self.camera_transforms.mvp = self.camera_transforms.proj
-                    * self.camera_transforms.view
-                    * self.camera_transforms.model;
-                let data = &self.camera_transforms as *const CameraTransforms;
-                unsafe {
-                    gl::NamedBufferSubData(
-                        self.camera_transforms_ubo,
-                        0,
-                        std::mem::size_of::<CameraTransforms>() as isize,
-                        data as *const _,
-                    )
+    /// Selects the closest visible object under the pointer, shift adds to or removes
+    /// from the selection like in the outliner
+    fn pick_object(&mut self) {
+        let ray = self.pointer_ray();
+        let picked = self
+            .scene
+            .objects()
+            .iter()
+            .filter(|&&entity| self.scene.is_visible(entity))
+            .filter_map(|&entity| {
+                let bounds = self.scene.world_bounds(entity)?;
+                ray.hits_aabb(&bounds).map(|hit| (entity, hit))
+            })
+            .min_by(|(_, a), (_, b)| a.t_min.total_cmp(&b.t_min))
+            .map(|(entity, _)| entity);
+        let selection = &mut self.editor_state.selected_objects;
+        match picked {
+            Some(entity) if self.input.modifiers.shift => {
+                if selection.contains(&entity) {
+                    selection.retain(|&e| e != entity);
+                } else {
+                    selection.push(entity);
                 }
             }
+            Some(entity) => *selection = vec![entity],
+            None if self.input.modifiers.shift => {}
+            None => selection.clear(),
+        }
+    }
 
-            if self.input.pointer_moved || self.input.camera_moved {
-                let ray = self.camera.get_ray_through_pixel(self.input.pointer);
-                let cursor_active = self.terrain.move_cursor(&ray);
-                self.windowed_context
-                    .window()
-                    .set_cursor_visible(!cursor_active);
-            }
+    /// What the outliner lists besides the terrain, sky and lights
+    /// The emitters in the scene for the particles panel, see `apply_emitter_items`
+    fn emitter_items(&self) -> Vec<EmitterItem> {
+        let scene = &self.scene;
+        scene
+            .emitters()
+            .iter()
+            .filter_map(|&entity| {
+                let emitter = scene.world.get::<&Emitter>(entity).ok()?;
+                Some(EmitterItem {
+                    entity,
+                    name: scene.name(entity),
+                    enabled: emitter.enabled,
+                    position: scene.transform(entity)?.position,
+                    follow_camera: scene.follows_camera(entity),
+                    parent: scene.parent(entity),
+                    settings: emitter.settings,
+                })
+            })
+            .collect()
+    }
 
-            if self.input.scrolled {
-                let y = self.input.scroll_delta.y;
-                self.terrain.brush.size = (self.terrain.brush.size - y * 5.5).clamp(0.1, 800.0);
-                // self.terrain.tess_level = (self.terrain.tess_level - y * 0.2).clamp(1.0, 16.0);
+    /// Puts what was changed in the particles panel into the scene
+    fn apply_emitter_items(&mut self, items: Vec<EmitterItem>) {
+        for item in items {
+            let entity = item.entity;
+            if let Ok(mut emitter) = self.scene.world.get::<&mut Emitter>(entity) {
+                emitter.enabled = item.enabled;
+                emitter.settings = item.settings;
             }
-
-            if self.input.mouse_buttons.primary && self.terrain.cursor.is_finite() {
-                self.terrain
-                    .shape_terrain(delta_time, !self.input.modifiers.ctrl);
+            self.scene.set_name(entity, item.name);
+            if let Some(transform) = self.scene.transform(entity) {
+                let transform = Transform {
+                    position: item.position,
+                    ..transform
+                };
+                self.scene.set_transform(entity, transform);
+            }
+            self.scene.set_follows_camera(entity, item.follow_camera);
+            if item.parent != self.scene.parent(entity) {
+                self.scene.set_parent(entity, item.parent);
             }
         }
+    }
 
-        // Draw
-        unsafe {
-            gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+    fn scene_items(&self) -> SceneItems {
+        let selection = &self.editor_state.selected_objects;
+        let objects = self
+            .scene
+            .objects()
+            .iter()
+            .map(|&entity| {
+                let item = OutlinerItem {
+                    name: self.scene.name(entity),
+                    visible: self.scene.is_visible(entity),
+                    selected: selection.contains(&entity),
+                    count: None,
+                };
+                (entity, item)
+            })
+            .collect();
+        let mut meshes: Vec<(MeshId, OutlinerItem)> = self
+            .instanced_meshes
+            .iter()
+            .map(|(path, &mesh)| {
+                let name = Path::new(path)
+                    .file_stem()
+                    .map_or(path.clone(), |stem| stem.to_string_lossy().into_owned());
+                let item = OutlinerItem {
+                    name,
+                    visible: self.instances.is_visible(mesh),
+                    selected: false,
+                    count: Some(self.instances.mesh_instance_count(mesh)),
+                };
+                (mesh, item)
+            })
+            .collect();
+        meshes.sort_by(|(_, a), (_, b)| a.name.cmp(&b.name));
+        let brick_groups = self
+            .bricks
+            .kind_counts()
+            .into_iter()
+            .enumerate()
+            .filter(|(_, (count, _))| *count > 0)
+            .map(|(kind, (count, selected))| {
+                let item = OutlinerItem {
+                    name: self.bricks.types[kind].name.clone(),
+                    visible: self.bricks.is_kind_visible(kind, &self.instances),
+                    selected: selected > 0,
+                    count: Some(count),
+                };
+                (kind, item)
+            })
+            .collect();
+        SceneItems {
+            objects,
+            meshes,
+            brick_groups,
         }
-        self.terrain.draw(self.input.time)?;
+    }
 
-        // Draw objects
-        self.model_shader.set_used();
-        for obj in &self.game_objects {
-            let transform = obj.get_model_matrix();
-            unsafe {
-                gl::BindVertexArray(obj.model.vao);
-            }
-            for node in &obj.model.drawable_nodes {
-                let transform = transform * node.transform;
-                self.model_shader.set_mat4("model", &transform)?;
-
-                for primitive in &node.primitives {
-                    let material = &obj.model.materials[primitive.material_index];
-                    unsafe {
-                        gl::ActiveTexture(unit_to_gl_const(0));
-                        gl::BindTexture(gl::TEXTURE_2D, material.base_color_texture);
-
-                        gl::DrawElements(
-                            gl::TRIANGLES,
-                            primitive.index_count as i32,
-                            gl::UNSIGNED_INT,
-                            primitive.first_index as *const _,
-                        );
-                    }
-                }
+    /// Does what Ctrl and the key do, if anything. Ctrl+Z and Ctrl+Y go through every
+    /// edit that can be undone whatever the tool, see `editor::undo`.
+    fn ctrl_shortcut(&mut self, key: VirtualKeyCode) -> bool {
+        let in_brick_mode = self.editor_state.tool == Tool::Bricks;
+        match key {
+            VirtualKeyCode::S => self.save_all(),
+            VirtualKeyCode::Z if self.input.modifiers.shift => self.redo(),
+            VirtualKeyCode::Z => self.undo(),
+            VirtualKeyCode::Y => self.redo(),
+            VirtualKeyCode::C if in_brick_mode => {
+                self.bricks.copy_selection();
+            }
+            VirtualKeyCode::V if in_brick_mode && self.bricks.clipboard.is_some() => {
+                self.editor_state.brick_tool = BrickTool::Paste;
             }
+            VirtualKeyCode::D if in_brick_mode => {
+                self.bricks
+                    .duplicate_selection(&self.terrain, &mut self.instances);
+            }
+            _ => return false,
         }
+        true
+    }
 
-        self.skybox.draw();
-
-        self.gui.draw();
-
-        self.windowed_context.swap_buffers()?;
-
-        // Clear old input
-        self.old_input = self.input.renew();
+    fn undo(&mut self) {
+        // A stroke still going on is ended first, so that it's what gets undone
+        if let Some(edit) = self.stroke_undo.take() {
+            self.undo.push(edit);
+        }
+        self.record_brick_edits();
+        if let Some(edit) = self.undo.take_undo() {
+            let edit = self.revert(edit, false);
+            self.undo.undone(edit);
+        }
+    }
 
-        Ok(GameMode::Editor)
+    fn redo(&mut self) {
+        if let Some(edit) = self.undo.take_redo() {
+            let edit = self.revert(edit, true);
+            self.undo.redone(edit);
+        }
     }
 
-    fn process_gui_actions(&mut self, actions: Vec<Action>) -> Result<()> {
-        for action in actions {
-            match action {
-                Action::SaveTerrain => {
-                    let (pixels, size) = self.terrain.get_heightmap_pixels();
-                    image::save_buffer(
-                        self.config.heightmap_path.clone(),
-                        &pixels,
-                        size as u32,
-                        size as u32,
-                        image::ColorType::L16,
-                    )?;
-                    self.config.start_with_flat_terrain = false;
-                    self.config.save();
+    /// Undoes the edit, or does it again with `redo`, and returns what turns it around
+    /// again. The terrain copies and placed objects swap places with what's there, so
+    /// only the bricks care which way it goes.
+    fn revert(&mut self, edit: Undo, redo: bool) -> Undo {
+        match edit {
+            Undo::Bricks => {
+                if redo {
+                    self.bricks.redo(&mut self.instances);
+                } else {
+                    self.bricks.undo(&mut self.instances);
                 }
-                Action::SaveCamera => {
-                    self.config.camera_position = Some(self.camera.position);
-                    self.config.camera_direction = Some(self.camera.direction);
-                    self.config.save();
+                Undo::Bricks
+            }
+            Undo::Heights(mut copy) => {
+                if !self.terrain.swap_heights(&mut copy) {
+                    log!("The heightmap has changed size since, can't swap it back");
                 }
-                Action::Quit => {
-                    self.input.should_exit = true;
+                Undo::Heights(copy)
+            }
+            Undo::Splat(mut copy) => {
+                self.terrain.material.swap_splatmap(&mut copy);
+                Undo::Splat(copy)
+            }
+            Undo::Object(PlacedObject::Spawned(entity)) => {
+                self.editor_state.selected_objects.retain(|&e| e != entity);
+                match self.scene.remove_object(entity) {
+                    Some(removed) => Undo::Object(PlacedObject::Removed(removed)),
+                    None => Undo::Object(PlacedObject::Spawned(entity)),
                 }
             }
+            Undo::Object(PlacedObject::Removed(removed)) => {
+                let entity = self.scene.restore_object(removed);
+                Undo::Object(PlacedObject::Spawned(entity))
+            }
         }
-        Ok(())
     }
+
+    /// Leaves a marker on the undo stack for each brick edit since the last call
+    fn record_brick_edits(&mut self) {
+        let edits = self.bricks.edits();
+        for _ in self.brick_edits..edits {
+            self.undo.push(Undo::Bricks);
+        }
+        self.brick_edits = edits;
+    }
+
+    /// Keys that only do something in brick mode
+    fn brick_hotkey(&mut self, key: VirtualKeyCode) {
+        self.select_brick_kind(key);
+        if self.editor_state.brick_tool == BrickTool::Paste {
+            let op = match key {
+                VirtualKeyCode::M => Some(ClipboardOp::MirrorX),
+                VirtualKeyCode::N => Some(ClipboardOp::MirrorZ),
+                _ => None,
+            };
+            if let (Some(clipboard), Some(op)) = (&mut self.bricks.clipboard, op) {
+                clipboard.apply(op);
+                return;
+            }
+        }
+        self.move_bricks(key);
+    }
+
+    /// The number keys pick the brick to place
+    fn select_brick_kind(&mut self, key: VirtualKeyCode) {
+        if let Some(kind) =
+            bricks::catalog::hotkey_index(key).filter(|&kind| kind < self.bricks.types.len())
+        {
+            self.editor_state.brick_type = kind;
+        }
+    }
+
+    /// Bound keys that only do something in brick mode
+    fn brick_key_action(&mut self, action: KeyAction) {
+        if let Some(&tool) = BrickTool::ALL
+            .iter()
+            .find(|tool| tool.key_action() == Some(action))
+        {
+            self.editor_state.brick_tool = tool;
+            return;
+        }
+        match action {
+            KeyAction::RotateBrick if self.editor_state.brick_tool == BrickTool::Paste => {
+                if let Some(clipboard) = &mut self.bricks.clipboard {
+                    clipboard.apply(ClipboardOp::Rotate);
+                }
+            }
+            KeyAction::RotateBrick => {
+                let rotation = &mut self.editor_state.brick_rotation;
+                *rotation = (*rotation + 1) % 4;
+            }
+            KeyAction::DeleteBricks => {
+                self.bricks.delete_selection(&mut self.instances);
+            }
+            _ => {}
+        }
+    }
+
+    /// Moves the selected bricks with the arrow keys relative to the camera, up and
+    /// down with Page Up and Page Down
+    fn move_bricks(&mut self, key: VirtualKeyCode) {
+        let forward = self.camera.direction.xz();
+        let forward = if forward.x.abs() > forward.y.abs() {
+            IVec3::new(forward.x.signum() as i32, 0, 0)
+        } else {
+            IVec3::new(0, 0, forward.y.signum() as i32)
+        };
+        let right = IVec3::new(-forward.z, 0, forward.x);
+        let offset = match key {
+            VirtualKeyCode::Up => forward,
+            VirtualKeyCode::Down => -forward,
+            VirtualKeyCode::Right => right,
+            VirtualKeyCode::Left => -right,
+            VirtualKeyCode::PageUp => IVec3::Y,
+            VirtualKeyCode::PageDown => -IVec3::Y,
+            _ => return,
+        };
+        self.bricks
+            .move_selection(offset, &self.terrain, &mut self.instances);
+    }
+
+    /// Loads the model and selects it
+    fn add_object(&mut self, path: &str, pos: Vec3) {
+        match Model::load(path) {
+            Ok(model) => {
+                let name = std::path::Path::new(path)
+                    .file_stem()
+                    .map_or(path.to_owned(), |stem| stem.to_string_lossy().into_owned());
+                let entity = self.scene.spawn_object(
+                    name,
+                    model,
+                    path.to_owned(),
+                    Transform::new(pos, Quat::default()),
+                    true,
+                );
+                self.editor_state.selected_objects = vec![entity];
+                self.undo.push(Undo::Object(PlacedObject::Spawned(entity)));
+            }
+            Err(err) => log!("Failed to load {}: {}", path, err),
+        }
+    }
+
+    /// Moves the selected bricks by the whole cells the gizmo has been dragged, the rest
+    /// is kept until the drag ends
+    fn move_bricks_with_gizmo(&mut self, offset: Vec3) {
+        if !self.input.mouse_buttons.primary {
+            self.brick_gizmo_offset = Vec3::ZERO;
+            return;
+        }
+        let grid = self.bricks.grid;
+        let cells = grid.cells_in(offset);
+        let moved = cells != IVec3::ZERO
+            && self
+                .bricks
+                .move_selection(cells, &self.terrain, &mut self.instances);
+        self.brick_gizmo_offset = if moved {
+            offset - grid.distance(cells)
+        } else {
+            offset
+        };
+    }
+
+    /// Moves everything positioned in world space by `-shift` so that the camera stays
+    /// close to the origin, see `WorldOrigin`
+    fn shift_origin(&mut self, shift: Vec3) -> Result<()> {
+        self.camera.position -= shift;
+        if let Some(camera) = &mut self.editor_camera {
+            camera.position -= shift;
+        }
+        self.scene.shift_origin(shift);
+        self.terrain.shift_origin(shift)?;
+        self.atmosphere.shadow_center -= shift;
+        self.editor_state.skybox_capture.position -= shift;
+        self.instances.shift_origin(shift);
+        self.bricks.shift_origin(shift);
+        self.particles.shift_origin(shift);
+        self.post_process.shift_origin(shift);
+        self.input.camera_moved = true;
+        Ok(())
+    }
+
+    fn upload_frame_uniforms(&self) {
+        let data = &self.frame_uniforms as *const FrameUniforms;
+        unsafe {
+            gl::NamedBufferSubData(
+                self.frame_uniforms_ubo,
+                0,
+                std::mem::size_of::<FrameUniforms>() as isize,
+                data as *const _,
+            )
+        }
+    }
+
+    /// Draws everything except the GUI into the current framebuffer.
+    /// Deferred shadows are only possible when rendering for post-processing.
+    fn draw_scene(&mut self, deferred_shadows: bool) -> Result<()> {
+        unsafe {
+            gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+        }
+        self.environment.update(self.skybox.cubemap())?;
+        self.environment.bind();
+        if self.editor_state.show_terrain {
+            let _scope = profiler::cpu_scope("Terrain draw");
+            self.terrain
+                .draw(self.input.time, &self.atmosphere, deferred_shadows)?;
+        }
+
+        // Draw objects
+        let objects_scope = profiler::scope("Objects");
+        self.model_shader.set_used();
+        self.model_shader
+            .set_i32("deferred_shadows", deferred_shadows as i32)?;
+        self.atmosphere.set_lighting_uniforms(&self.model_shader)?;
+        self.atmosphere.set_fog_uniforms(&self.model_shader)?;
+        bindings::bind_texture(TextureUnit::SHADOW_MAP, self.terrain.shadow_map());
+        self.scene.propagate_transforms();
+        self.scene
+            .cull(self.frame_uniforms.proj * self.frame_uniforms.view);
+        self.scene.draw(&self.model_shader)?;
+
+        // The terrain and the objects hide the instances behind them. The scene depth
+        // is only a texture when rendering for post-processing.
+        let hiz = if deferred_shadows && self.editor_state.occlusion_culling {
+            let _scope = profiler::scope("Hi-Z");
+            self.hiz.build(self.post_process.depth())?;
+            Some(&self.hiz)
+        } else {
+            None
+        };
+        self.instances.draw(
+            &self.atmosphere,
+            deferred_shadows,
+            self.editor_state.impostor_distance,
+            hiz,
+        )?;
+        drop(objects_scope);
+
+        if self.editor_state.show_skybox {
+            let _scope = profiler::scope("Skybox");
+            self.skybox.draw(&self.atmosphere)?;
+        }
+        let _scope = profiler::scope("Clouds");
+        self.clouds
+            .draw(&self.atmosphere, &self.origin, self.input.time)?;
+
+        Ok(())
+    }
+
+    /// Textures that can be inspected in the render target viewer
+    fn render_targets(&self) -> Vec<RenderTarget> {
+        let target = |name, texture, mode| RenderTarget {
+            name,
+            texture,
+            mode,
+        };
+        vec![
+            target("Shadow map", self.terrain.shadow_map(), PreviewMode::Depth),
+            target("Heightmap", self.terrain.heightmap(), PreviewMode::Red),
+            target("Brush", self.terrain.brush_texture(), PreviewMode::Red),
+            target(
+                "Splatmap",
+                self.terrain.material.splatmap(),
+                PreviewMode::Color,
+            ),
+            target("Scene color", self.post_process.color(), PreviewMode::Color),
+            target("Sunlight", self.post_process.sunlight(), PreviewMode::Color),
+            target(
+                "Scene depth",
+                self.post_process.depth(),
+                PreviewMode::PerspectiveDepth,
+            ),
+            target(
+                "Occlusion",
+                self.post_process.occlusion(),
+                PreviewMode::Color,
+            ),
+            target("Hi-Z", self.hiz.texture(), PreviewMode::PerspectiveDepth),
+        ]
+    }
+
+    /// Draws the scene straight to the screen the way the debug view shows it
+    fn draw_debug_view(&mut self, view: DebugView) -> Result<()> {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+        match view {
+            DebugView::Lit => self.draw_scene(false)?,
+            DebugView::Wireframe => {
+                unsafe {
+                    gl::PolygonMode(gl::FRONT_AND_BACK, gl::LINE);
+                }
+                let result = self.draw_scene(false);
+                unsafe {
+                    gl::PolygonMode(gl::FRONT_AND_BACK, gl::FILL);
+                }
+                result?;
+            }
+            DebugView::Normals => {
+                self.draw_scene(false)?;
+                self.terrain.draw_normals()?;
+            }
+            DebugView::TessPatches => {
+                self.draw_scene(false)?;
+                self.terrain.draw_patches()?;
+            }
+            DebugView::Overdraw => {
+                self.overdraw.begin();
+                self.draw_scene(false)?;
+                self.overdraw.end()?;
+            }
+        }
+        if self.editor_state.show_debug_shapes {
+            self.queue_debug_shapes();
+        }
+        self.debug_renderer.flush()
+    }
+
+    /// Editor-only markers for things that have no geometry of their own
+    fn draw_markers(&mut self) -> Result<()> {
+        let mut markers: Vec<Billboard> = self
+            .scene
+            .world
+            .query::<&GlobalTransform>()
+            .with::<&Emitter>()
+            .without::<&FollowCamera>()
+            .iter()
+            .map(|(_, global)| Billboard {
+                position: global.0.w_axis.truncate(),
+                size: Vec2::splat(1.5),
+                color: Vec4::new(1.0, 0.6, 0.1, 0.8),
+                mode: BillboardMode::Spherical,
+            })
+            .collect();
+        markers.push(Billboard {
+            position: self.editor_state.skybox_capture.position,
+            size: Vec2::new(0.5, 3.0),
+            color: Vec4::new(0.2, 0.6, 1.0, 0.8),
+            mode: BillboardMode::Cylindrical,
+        });
+        self.billboards.draw(&markers, None)
+    }
+
+    fn queue_debug_shapes(&self) {
+        self.terrain.draw_debug();
+        for &entity in self.scene.objects() {
+            if !self.scene.is_visible(entity) {
+                continue;
+            }
+            let (bounds, model, transform) = match (
+                self.scene.world_bounds(entity),
+                self.scene.model(entity),
+                self.scene.global_transform(entity),
+            ) {
+                (Some(bounds), Some(model), Some(transform)) => (bounds, model, transform),
+                _ => continue,
+            };
+            debug_draw::aabb(&bounds, Vec4::new(0.3, 1.0, 0.3, 1.0));
+            if self.editor_state.selected_objects.contains(&entity) {
+                let size = (model.bounds.max - model.bounds.min).max_element().max(1.0);
+                debug_draw::axis(&transform, size);
+            }
+        }
+        for (_, (emitter, global)) in self
+            .scene
+            .world
+            .query::<(&Emitter, &GlobalTransform)>()
+            .without::<&FollowCamera>()
+            .iter()
+        {
+            let radius = emitter.settings.area.max_element().max(0.5);
+            let position = global.0.w_axis.truncate();
+            debug_draw::sphere(position, radius, Vec4::new(1.0, 0.6, 0.1, 1.0));
+        }
+    }
+
+    /// Names of the objects, world coordinates under the cursor, and the distance
+    /// between the first two selected objects
+    fn draw_labels(&mut self) -> Result<()> {
+        let mut labels: Vec<Label> = self
+            .scene
+            .objects()
+            .iter()
+            .filter_map(|&entity| {
+                let position = self.object_position(entity)?;
+                let top = self.scene.model(entity)?.bounds.max.y.max(0.0);
+                Some(Label {
+                    text: self.scene.name(entity),
+                    position: position + Vec3::new(0.0, top, 0.0),
+                    color: Vec4::new(1.0, 1.0, 1.0, 0.9),
+                    size: LabelSize::Pixels(16.0),
+                    on_top: false,
+                })
+            })
+            .collect();
+
+        let cursor = self.terrain.cursor;
+        if let Some(height) = self.terrain.height_at(cursor) {
+            let position = Vec3::new(cursor.x, height, cursor.y);
+            let world = self.origin.to_world(position);
+            labels.push(Label {
+                text: format!("{:.1}, {:.1}, {:.1}", world.x, world.y, world.z),
+                position,
+                color: Vec4::new(1.0, 0.9, 0.4, 1.0),
+                size: LabelSize::Pixels(14.0),
+                on_top: true,
+            });
+        }
+
+        if let [first, second, ..] = self.editor_state.selected_objects[..] {
+            let a = self.object_position(first).unwrap_or_default();
+            let b = self.object_position(second).unwrap_or_default();
+            labels.push(Label {
+                text: format!("{:.2}", a.distance(b)),
+                position: (a + b) * 0.5,
+                color: Vec4::new(0.4, 0.9, 1.0, 1.0),
+                // Sized like the gap it measures
+                size: LabelSize::World((a.distance(b) * 0.1).max(0.2)),
+                on_top: true,
+            });
+        }
+
+        self.text.draw(&labels, self.post_process.depth())
+    }
+
+    /// Renders the scene into six cubemap faces and saves them as skybox images
+    fn capture_skybox(&mut self, settings: &SkyboxCapture) -> Result<()> {
+        let dir = std::path::Path::new(&settings.output_dir);
+        std::fs::create_dir_all(dir)?;
+        let capture = CubemapCapture::new(settings.face_size)?;
+
+        let saved_transforms = self.frame_uniforms;
+        let saved_hour = self.atmosphere.time_of_day.hour;
+        if let Some(hour) = settings.hour {
+            self.atmosphere.time_of_day.hour = hour;
+        }
+
+        capture.bind();
+        let mut result = Ok(());
+        for face in 0..capture::FACE_COUNT {
+            let transforms = &mut self.frame_uniforms;
+            transforms.view = capture.face_view(face, settings.position);
+            transforms.proj = capture.projection();
+            transforms.mvp = transforms.proj * transforms.view * transforms.model;
+            transforms.sun_vp = self.atmosphere.light_view_projection();
+            transforms.camera_position = settings.position.extend(1.0);
+            self.upload_frame_uniforms();
+
+            result = self
+                .draw_scene(false)
+                .and_then(|_| capture.save_face(face, dir));
+            if result.is_err() {
+                break;
+            }
+        }
+        let window_size = self.windowed_context.window().inner_size();
+        capture.unbind(window_size.width as i32, window_size.height as i32);
+
+        // Put everything back the way it was
+        self.atmosphere.time_of_day.hour = saved_hour;
+        self.frame_uniforms = saved_transforms;
+        self.upload_frame_uniforms();
+
+        result
+    }
+
+    fn process_gui_actions(&mut self, actions: Vec<Action>) -> Result<()> {
+        for action in actions {
+            match action {
+                Action::SaveAll => self.save_all(),
+                Action::ToggleFullscreen => self.toggle_fullscreen(),
+                Action::ApplyDisplaySettings => self.apply_display_settings(),
+                Action::SaveTerrain => self.save_terrain(),
+                Action::SaveTerrainAs => {
+                    if let Some(path) =
+                        dialogs::save_file(FileKind::Heightmap, &self.config.heightmap_path)
+                    {
+                        self.config.heightmap_path = path;
+                        self.save_terrain();
+                    }
+                }
+                Action::OpenHeightmap => {
+                    if let Some(path) =
+                        dialogs::open_file(FileKind::Heightmap, &self.config.heightmap_path)
+                    {
+                        match self.terrain.load_heightmap(&path) {
+                            Ok(()) => {
+                                self.config.heightmap_path = path;
+                                self.config.start_with_flat_terrain = false;
+                                self.config.save();
+                            }
+                            Err(err) => log!("Failed to load {}: {}", path, err),
+                        }
+                    }
+                }
+                Action::SaveBricks => {
+                    self.bricks.save(&self.config.bricks_path)?;
+                }
+                Action::LoadBricks => self.load_bricks(),
+                Action::SaveBricksAs => {
+                    if let Some(path) =
+                        dialogs::save_file(FileKind::Bricks, &self.config.bricks_path)
+                    {
+                        self.bricks.save(&path)?;
+                        self.config.bricks_path = path;
+                        self.config.save();
+                    }
+                }
+                Action::OpenBricks => {
+                    if let Some(path) =
+                        dialogs::open_file(FileKind::Bricks, &self.config.bricks_path)
+                    {
+                        self.config.bricks_path = path;
+                        self.config.save();
+                        self.load_bricks();
+                    }
+                }
+                Action::SaveCamera => {
+                    let position = self.origin.to_world(self.camera.position);
+                    self.config.camera_position = Some(position.as_vec3());
+                    self.config.camera_direction = Some(self.camera.direction);
+                    self.config.save();
+                }
+                Action::SaveProject => match self.config.project_path.clone() {
+                    Some(dir) => {
+                        if let Err(err) = self.save_project(&dir) {
+                            log!("Failed to save {}: {}", dir, err);
+                        }
+                    }
+                    None => self.save_project_as(),
+                },
+                Action::SaveProjectAs => self.save_project_as(),
+                Action::OpenProject => {
+                    let current = self.config.project_path.clone().unwrap_or_default();
+                    if let Some(dir) = dialogs::pick_folder(&current) {
+                        if let Err(err) = self.open_project(&dir) {
+                            log!("Failed to open {}: {}", dir, err);
+                        }
+                    }
+                }
+                Action::AddBookmark => {
+                    let name = format!("Bookmark {}", self.bookmarks.len() + 1);
+                    let bookmark = self.camera_bookmark(name);
+                    self.bookmarks.push(bookmark);
+                }
+                Action::GoToBookmark(index) => {
+                    if let Some(bookmark) = self.bookmarks.get(index).cloned() {
+                        self.go_to_bookmark(&bookmark);
+                    }
+                }
+                Action::RemoveBookmark(index) => {
+                    if index < self.bookmarks.len() {
+                        self.bookmarks.remove(index);
+                    }
+                }
+                Action::Quit => {
+                    self.input.should_exit = true;
+                }
+                Action::Align(op) => {
+                    let mut positions = self.selected_positions();
+                    match op {
+                        AlignOp::Axis(axis) => align::align_to_axis(&mut positions, axis),
+                        AlignOp::Grid => {
+                            align::align_to_grid(&mut positions, self.editor_state.grid_size)
+                        }
+                        AlignOp::Terrain => {
+                            for pos in positions.iter_mut() {
+                                if let Some(height) = self.terrain.height_at(pos.xz()) {
+                                    pos.y = height;
+                                }
+                            }
+                        }
+                    }
+                    self.set_selected_positions(&positions);
+                }
+                Action::Distribute(op) => {
+                    let mut positions = self.selected_positions();
+                    match op {
+                        DistributeOp::Axis(axis) => {
+                            align::distribute_along_axis(&mut positions, axis)
+                        }
+                        DistributeOp::Spline => align::distribute_along_spline(&mut positions),
+                    }
+                    self.set_selected_positions(&positions);
+                }
+                Action::ToggleFog => self.atmosphere.fog.enabled = !self.atmosphere.fog.enabled,
+                Action::ToggleDayNightCycle => {
+                    let time_of_day = &mut self.atmosphere.time_of_day;
+                    time_of_day.paused = !time_of_day.paused;
+                }
+                Action::AddObject { path } => {
+                    let mut pos = self.camera.position + self.camera.direction * 50.0;
+                    if let Some(height) = self.terrain.height_at(pos.xz()) {
+                        pos.y = height;
+                    }
+                    self.add_object(&path, pos);
+                }
+                Action::DropObject { path, pointer } => {
+                    let ray = self.camera.get_ray_through_pixel(pointer);
+                    match self.terrain.intersect_with_ray(&ray) {
+                        Some(mut pos) => {
+                            // The ray is only tested against the bottom of the terrain
+                            pos.y = self.terrain.height_at(pos.xz()).unwrap_or(pos.y);
+                            self.add_object(&path, pos);
+                        }
+                        None => log!("{} can only be dropped onto the terrain", path),
+                    }
+                }
+                Action::ScatterInstances {
+                    path,
+                    count,
+                    radius,
+                } => self.scatter_instances(&path, count, radius),
+                Action::UndoScatter => {
+                    for id in self.scatters.pop().unwrap_or_default() {
+                        self.instances.remove_instance(id);
+                    }
+                }
+                Action::DropInstancesToTerrain => {
+                    for &id in self.scatters.iter().flatten() {
+                        let transform = match self.instances.transform(id) {
+                            Some(transform) => transform,
+                            None => continue,
+                        };
+                        let (scale, rotation, mut pos) = transform.to_scale_rotation_translation();
+                        if let Some(height) = self.terrain.height_at(pos.xz()) {
+                            pos.y = height;
+                            let transform =
+                                Mat4::from_scale_rotation_translation(scale, rotation, pos);
+                            self.instances.set_transform(id, transform);
+                        }
+                    }
+                }
+                Action::AddEmitter(preset) => {
+                    let mut pos = self.camera.position + self.camera.direction * 50.0;
+                    if let Some(height) = self.terrain.height_at(pos.xz()) {
+                        pos.y = height;
+                    }
+                    if preset.follows_camera() {
+                        // Above the camera
+                        pos = Vec3::new(0.0, 30.0, 0.0);
+                    }
+                    let emitter = self.particles.new_emitter(preset);
+                    let name = preset.name().to_owned();
+                    self.scene
+                        .spawn_emitter(name, emitter, pos, preset.follows_camera());
+                }
+                Action::RemoveEmitter(entity) => {
+                    if let Some(emitter) = self.scene.remove_emitter(entity) {
+                        self.particles.remove_emitter(emitter);
+                    }
+                }
+                Action::CaptureSkybox => {
+                    let settings = self.editor_state.skybox_capture.clone();
+                    match self.capture_skybox(&settings) {
+                        Ok(()) => log!("Saved skybox to {}", settings.output_dir),
+                        Err(err) => log!("Skybox capture failed: {}", err),
+                    }
+                }
+                Action::SetCapturePositionToCamera => {
+                    self.editor_state.skybox_capture.position = self.camera.position;
+                }
+                Action::AddTerrainLayer => {
+                    self.terrain.material.add_layer();
+                }
+                Action::RemoveTerrainLayer(index) => self.terrain.material.remove_layer(index),
+                Action::MoveTerrainLayer { from, to } => self.terrain.material.move_layer(from, to),
+                Action::DuplicateBricks => {
+                    self.bricks
+                        .duplicate_selection(&self.terrain, &mut self.instances);
+                }
+                Action::DeleteBricks => {
+                    self.bricks.delete_selection(&mut self.instances);
+                }
+                Action::CopyBricks => {
+                    self.bricks.copy_selection();
+                }
+                Action::PaintBricks => {
+                    self.bricks
+                        .paint_selection(self.editor_state.brick_color, &mut self.instances);
+                }
+                Action::SetObjectVisible { entity, visible } => {
+                    self.scene.set_visible(entity, visible);
+                    if !visible {
+                        self.editor_state.selected_objects.retain(|&e| e != entity);
+                    }
+                }
+                Action::SetMeshVisible { mesh, visible } => {
+                    self.instances.set_visible(mesh, visible);
+                }
+                Action::SetBrickGroupVisible { kind, visible } => {
+                    self.bricks
+                        .set_kind_visible(kind, visible, &mut self.instances);
+                }
+                Action::SelectBrickGroup { kind, add } => {
+                    self.bricks.select_kind(kind, add);
+                    self.editor_state.tool = Tool::Bricks;
+                    self.editor_state.brick_tool = BrickTool::Select;
+                }
+                Action::SetVariable { name, value } => match self.set_variable(&name, value) {
+                    Ok(()) => self.gui.console_print(format!("{} = {}", name, value)),
+                    Err(err) => self.gui.console_print(err.to_string()),
+                },
+                Action::ExportHeightmap { path } => self.write_heightmap(path),
+                Action::Teleport(position) => {
+                    self.camera.position = self.origin.to_local(position.as_dvec3());
+                    self.input.camera_moved = true;
+                }
+                Action::ResetKeyBindings => {
+                    self.config.keybindings.reset();
+                    self.config.save();
+                }
+                Action::UndoBricks => {
+                    self.record_brick_edits();
+                    if self.bricks.undo(&mut self.instances) {
+                        self.undo.undo_bricks();
+                    }
+                }
+                Action::RedoBricks => {
+                    if self.bricks.redo(&mut self.instances) {
+                        self.undo.redo_bricks();
+                    }
+                }
+                Action::ClearLooseBricks => self.bricks.clear_loose(&mut self.instances),
+                Action::ImportLdraw { path } => {
+                    match bricks::ldraw::import(&path, &self.bricks.types) {
+                        Ok(import) => {
+                            if import.skipped > 0 {
+                                log!("Skipped {} parts of {}", import.skipped, path);
+                            }
+                            self.bricks.clipboard = Some(import.clipboard);
+                            self.editor_state.tool = Tool::Bricks;
+                            self.editor_state.brick_tool = BrickTool::Paste;
+                        }
+                        Err(err) => log!("Failed to import {}: {}", path, err),
+                    }
+                }
+                Action::SetTerrainMaxHeight(max_height) => {
+                    self.terrain.set_max_height(max_height)?;
+                }
+                Action::ApplyRenderSettings(settings) => self.apply_render_settings(settings),
+                Action::LoadBrush { path } => match self.terrain.brush.load(&path) {
+                    Ok(()) => {
+                        self.config.brush_path = path;
+                        self.config.save();
+                    }
+                    Err(err) => log!("Failed to load brush {}: {}", path, err),
+                },
+                Action::SavePrefab { name } => {
+                    match self.bricks.save_prefab(&self.config.prefabs_path, &name) {
+                        Ok(true) => self.prefabs = bricks::prefab::list(&self.config.prefabs_path),
+                        Ok(false) => log!("Select some bricks to save them as a prefab"),
+                        Err(err) => log!("Failed to save prefab {}: {}", name, err),
+                    }
+                }
+                Action::StampPrefab { name } => {
+                    match bricks::prefab::load(&self.config.prefabs_path, &name, &self.bricks.types)
+                    {
+                        Ok(clipboard) => {
+                            self.bricks.clipboard = Some(clipboard);
+                            self.editor_state.tool = Tool::Bricks;
+                            self.editor_state.brick_tool = BrickTool::Paste;
+                        }
+                        Err(err) => log!("Failed to load prefab {}: {}", name, err),
+                    }
+                }
+                Action::TransformClipboard(op) => {
+                    if let Some(clipboard) = &mut self.bricks.clipboard {
+                        clipboard.apply(op);
+                    }
+                }
+                Action::ImportLayerTexture { layer, map, path } => {
+                    self.import_layer_texture(layer, map, &path);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Decodes the image for the layer on a worker, and generates the normal map from
+    /// an albedo too if that's turned on
+    fn import_layer_texture(&mut self, layer: usize, map: LayerMap, path: &str) {
+        self.decode_layer_texture(layer, map, path);
+        if map == LayerMap::Albedo && self.editor_state.generate_normals {
+            let job = self.terrain.material.normal_generation_job(
+                layer,
+                path,
+                self.editor_state.normal_strength,
+            );
+            self.spawn_normal_map(job);
+        }
+    }
+
+    /// Generates the normal map on a worker and uploads it once it's done
+    fn spawn_normal_map(
+        &self,
+        job: impl FnOnce() -> std::result::Result<GeneratedNormalMap, image::ImageError>
+            + Send
+            + 'static,
+    ) {
+        self.jobs.spawn_then(
+            &self.callbacks,
+            job,
+            |game: &mut Game, result| match result {
+                Ok(normal_map) => game.terrain.material.apply_generated_normal_map(normal_map),
+                Err(err) => log!("Failed to generate normal map: {}", err),
+            },
+        );
+    }
+
+    fn decode_layer_texture(&mut self, layer: usize, map: LayerMap, path: &str) {
+        let decode = self.terrain.material.import_job(layer, map, path);
+        self.image_loader.load(&self.jobs, path, move || {
+            decode()
+                .map(LoadedImage::TerrainLayer)
+                .map_err(|err| err.to_string())
+        });
+    }
+
+    /// Picks up the images changed on disk. The ones loaded through the texture manager
+    /// are updated in place, the terrain layers and the skybox are loaded again.
+    fn reload_changed_textures(&mut self) {
+        let changed = match &mut self.texture_watcher {
+            Some(watcher) => watcher.poll(),
+            None => return,
+        };
+        for path in changed {
+            match texture_manager::reload(&path) {
+                Ok(true) => log!("Reloaded {}", path.display()),
+                Ok(false) => {}
+                Err(err) => log!("Failed to reload {}: {}", path.display(), err),
+            }
+
+            let mut layer_maps = vec![];
+            for (index, layer) in self.terrain.material.layers.iter().enumerate() {
+                for map in LayerMap::ALL {
+                    if let Some(layer_path) = layer.path(map) {
+                        if texture_watcher::is_same_file(layer_path, &path) {
+                            layer_maps.push((index, map, layer_path.to_owned()));
+                        }
+                    }
+                }
+            }
+            for (index, map, layer_path) in layer_maps {
+                self.import_layer_texture(index, map, &layer_path);
+            }
+
+            match &self.config.sky_panorama {
+                Some(panorama) if texture_watcher::is_same_file(panorama, &path) => {
+                    if let Err(err) = self.skybox.load_equirect(panorama) {
+                        log!("Failed to reload {}: {}", panorama, err);
+                    }
+                }
+                Some(_) => {}
+                None => {
+                    let is_face = skybox::DEFAULT_FACES
+                        .iter()
+                        .any(|face| texture_watcher::is_same_file(face, &path));
+                    if is_face {
+                        load_skybox_faces(&mut self.image_loader, &self.jobs);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Picks up the results of background jobs. GL uploads have to happen on this thread.
+    fn collect_finished_jobs(&mut self) {
+        for image in self.image_loader.poll() {
+            match image {
+                LoadedImage::Skybox(images) => self.skybox.set_images(&images),
+                LoadedImage::TerrainLayer(image) => self.terrain.material.apply_import(image),
+            }
+        }
+
+        for callback in self.callbacks.take() {
+            callback(self);
+        }
+
+        let mut finished = vec![];
+        self.heightmap_readbacks
+            .retain(|(readback, path)| match readback.try_take() {
+                Some((pixels, size)) => {
+                    finished.push((path.clone(), pixels, size));
+                    false
+                }
+                None => true,
+            });
+        for (path, pixels, size) in finished {
+            self.spawn_heightmap_save(path, pixels, size);
+        }
+    }
+
+    /// Writes the file on a worker and says in the console when it's done. Failures
+    /// are logged by the worker, so they aren't lost if the editor exits before the
+    /// callback runs.
+    fn spawn_file_save<F>(&self, path: String, save: F)
+    where
+        F: FnOnce() -> std::result::Result<(), String> + Send + 'static,
+    {
+        let file = path.clone();
+        let work = move || {
+            let result = save().map_err(|err| format!("Failed to save {}: {}", file, err));
+            if let Err(err) = &result {
+                log!("{}", err);
+            }
+            result
+        };
+        self.jobs.spawn_then(
+            &self.callbacks,
+            work,
+            move |game: &mut Game, result| match result {
+                Ok(()) => game.gui.console_print(format!("Saved {}", path)),
+                Err(err) => game.gui.console_print(err),
+            },
+        );
+    }
+
+    /// The project if there's one open, otherwise the terrain and the bricks to the
+    /// files they came from
+    fn save_all(&mut self) {
+        if let Some(dir) = self.config.project_path.clone() {
+            if let Err(err) = self.save_project(&dir) {
+                log!("Failed to save {}: {}", dir, err);
+            }
+            return;
+        }
+        self.save_terrain();
+        if let Err(err) = self.bricks.save(&self.config.bricks_path) {
+            log!("Failed to save {}: {}", self.config.bricks_path, err);
+        }
+    }
+
+    fn save_terrain(&mut self) {
+        self.write_heightmap(self.config.heightmap_path.clone());
+        self.config.start_with_flat_terrain = false;
+        self.config.save();
+    }
+
+    /// Saves the heightmap a few frames later, see `collect_finished_jobs`
+    fn write_heightmap(&mut self, path: String) {
+        let readback = self.terrain.read_heightmap();
+        self.heightmap_readbacks.push((readback, path));
+    }
+
+    /// Encodes the heightmap on a worker, the file is written once it's done
+    fn spawn_heightmap_save(&mut self, path: String, pixels: Vec<u8>, size: usize) {
+        let file = path.clone();
+        self.spawn_file_save(path, move || {
+            image::save_buffer(
+                &file,
+                &pixels,
+                size as u32,
+                size as u32,
+                image::ColorType::L16,
+            )
+            .map_err(|err| err.to_string())
+        });
+    }
+
+    /// Waits for the heightmaps still on the GPU so that they're saved before exiting.
+    /// The workers finish encoding them when the job system is dropped.
+    fn finish_heightmap_saves(&mut self) {
+        for (readback, path) in std::mem::take(&mut self.heightmap_readbacks) {
+            let (pixels, size) = readback.wait();
+            self.spawn_heightmap_save(path, pixels, size);
+        }
+    }
+
+    /// Writes everything made in the editor to the project directory, see `project`.
+    /// The files are written on the workers, the heightmap once it's read back.
+    fn save_project(&mut self, dir: &str) -> Result<()> {
+        let origin = self.origin;
+        let project = Project {
+            camera: Some(self.camera_bookmark("Camera".to_owned())),
+            bookmarks: self.bookmarks.clone(),
+            brush: self.terrain.brush.settings.clone(),
+            layers: self
+                .terrain
+                .material
+                .layers
+                .iter()
+                .map(SavedLayer::of)
+                .collect(),
+            objects: self
+                .scene
+                .objects()
+                .iter()
+                .filter_map(|&entity| {
+                    let transform = self.scene.transform(entity)?;
+                    Some(SavedObject {
+                        name: self.scene.name(entity),
+                        model: self.scene.model_path(entity),
+                        position: origin.to_world(transform.position),
+                        orientation: transform.orientation,
+                        visible: self.scene.is_visible(entity),
+                    })
+                })
+                .collect(),
+            lighting: Lighting::of(&self.atmosphere),
+        };
+        // Before any of the files go into it
+        std::fs::create_dir_all(dir)?;
+        let path = project::file(dir, project::MANIFEST);
+        self.spawn_file_save(path.clone(), project.write_job(path));
+        let path = project::file(dir, project::BRICKS);
+        self.spawn_file_save(path.clone(), self.bricks.save_job(path));
+        self.write_heightmap(project::file(dir, project::HEIGHTMAP));
+
+        let (pixels, size) = self.terrain.material.splatmap_pixels();
+        let path = project::file(dir, project::SPLATMAP);
+        let file = path.clone();
+        self.spawn_file_save(path, move || {
+            image::save_buffer(
+                &file,
+                &pixels,
+                size as u32,
+                size as u32,
+                image::ColorType::Rgba8,
+            )
+            .map_err(|err| err.to_string())
+        });
+
+        self.config.project_path = Some(dir.to_owned());
+        self.config.save();
+        crash::set_project(Some(dir));
+        Ok(())
+    }
+
+    fn save_project_as(&mut self) {
+        let current = self.config.project_path.clone().unwrap_or_default();
+        if let Some(dir) = dialogs::pick_folder(&current) {
+            if let Err(err) = self.save_project(&dir) {
+                log!("Failed to save {}: {}", dir, err);
+            }
+        }
+    }
+
+    /// Replaces what's in the editor with the project. The layer images are decoded on
+    /// the workers and show up a few frames later.
+    fn open_project(&mut self, dir: &str) -> Result<()> {
+        let project = Project::read(Path::new(dir))?;
+
+        let heightmap = project::file(dir, project::HEIGHTMAP);
+        if Path::new(&heightmap).exists() {
+            self.terrain.load_heightmap(&heightmap)?;
+        }
+        let splatmap = project::file(dir, project::SPLATMAP);
+        if Path::new(&splatmap).exists() {
+            self.terrain.material.load_splatmap(&splatmap)?;
+        }
+        self.terrain.material.reset_layers(project.layers.len());
+        for (index, saved) in project.layers.iter().enumerate() {
+            match self.terrain.material.layers.get_mut(index) {
+                Some(layer) => saved.apply(layer),
+                None => break,
+            }
+            for map in LayerMap::ALL {
+                let path = match saved.path(map) {
+                    Some(path) => path,
+                    None => continue,
+                };
+                match splat::generated_from(path) {
+                    Some(source) if map == LayerMap::Normal => {
+                        let job = self.terrain.material.normal_generation_job(
+                            index,
+                            source,
+                            self.editor_state.normal_strength,
+                        );
+                        self.spawn_normal_map(job);
+                    }
+                    _ => self.decode_layer_texture(index, map, path),
+                }
+            }
+        }
+        self.terrain.brush.settings = project.brush.clone();
+
+        let bricks = project::file(dir, project::BRICKS);
+        if Path::new(&bricks).exists() {
+            self.bricks.load(&bricks, &mut self.instances)?;
+        }
+
+        // Nothing from before refers to the project that's loaded
+        self.undo.clear();
+        self.stroke_undo = None;
+        self.editor_state.selected_objects.clear();
+        self.scene.clear_objects();
+        for saved in &project.objects {
+            match Model::load(&saved.model) {
+                Ok(model) => {
+                    self.scene.spawn_object(
+                        saved.name.clone(),
+                        model,
+                        saved.model.clone(),
+                        Transform::new(self.origin.to_local(saved.position), saved.orientation),
+                        saved.visible,
+                    );
+                }
+                Err(err) => log!("Failed to load {}: {}", saved.model, err),
+            }
+        }
+
+        if let Some(camera) = &project.camera {
+            self.go_to_bookmark(camera);
+        }
+        self.bookmarks = project.bookmarks;
+        project.lighting.apply(&mut self.atmosphere);
+
+        self.config.project_path = Some(dir.to_owned());
+        self.config.save();
+        crash::set_project(Some(dir));
+        Ok(())
+    }
+
+    /// Where the editor camera is, play mode walks with a copy of it
+    fn camera_bookmark(&self, name: String) -> Bookmark {
+        let camera = self.editor_camera.as_ref().unwrap_or(&self.camera);
+        Bookmark {
+            name,
+            position: self.origin.to_world(camera.position),
+            direction: camera.direction,
+        }
+    }
+
+    fn go_to_bookmark(&mut self, bookmark: &Bookmark) {
+        let position = self.origin.to_local(bookmark.position);
+        self.camera.look_from(position, bookmark.direction);
+        self.input.camera_moved = true;
+    }
+
+    /// Keeps the editor preferences for the next run, see `settings`
+    /// Saves the results of the benchmark and closes the editor
+    fn finish_benchmark(&mut self) {
+        if let Some(benchmark) = &self.benchmark {
+            let size = self.windowed_context.window().inner_size();
+            let about = format!(
+                "Driver: {}\nResolution: {}x{}\n",
+                opengl::driver(),
+                size.width,
+                size.height
+            );
+            match benchmark.save(&about) {
+                Ok(summary) => println!("{}", summary),
+                Err(err) => log!("Failed to save the benchmark results: {}", err),
+            }
+        }
+        self.input.should_exit = true;
+    }
+
+    fn save_settings(&self) {
+        let settings = Settings {
+            camera_speed: self
+                .editor_camera
+                .as_ref()
+                .unwrap_or(&self.camera)
+                .movement_speed,
+            mouse: self.mouse_settings,
+            brush: self.terrain.brush.settings.clone(),
+            windows: WindowLayout::of(&self.editor_state),
+            graphics: self.post_settings,
+            rendering: self.render_settings,
+            display: self.display_settings.clone(),
+            gamepad: self.gamepads.settings,
+            fullscreen: self.windowed_context.window().fullscreen().is_some(),
+        };
+        settings.save();
+    }
+
+    /// Changes one of the settings the console can `set`, see `commands::VARIABLES`
+    fn set_variable(&mut self, name: &str, value: f32) -> Result<()> {
+        match name {
+            "tess_level" => self.terrain.tess_level = value.clamp(1.0, 16.0),
+            "max_height" => self.terrain.set_max_height(value.max(1.0))?,
+            "brush_size" => self.terrain.brush.settings.size = value.clamp(0.1, 800.0),
+            "brush_strength" => self.terrain.brush.settings.strength = value.max(0.0),
+            "hour" => self.atmosphere.time_of_day.hour = value.rem_euclid(24.0),
+            "grid_size" => self.editor_state.grid_size = value.max(0.1),
+            "camera_speed" => self.camera.movement_speed = value.max(0.1),
+            _ => return Err(format!("Unknown setting {}", name).into()),
+        }
+        Ok(())
+    }
+
+    fn load_bricks(&mut self) {
+        if let Err(err) = self
+            .bricks
+            .load(&self.config.bricks_path, &mut self.instances)
+        {
+            log!("Failed to load {}: {}", self.config.bricks_path, err);
+        }
+    }
+
+    /// Places instances of the model uniformly over a disk in front of the camera,
+    /// standing on the terrain and randomly rotated
+    fn scatter_instances(&mut self, path: &str, count: u32, radius: f32) {
+        let mesh = match self.instanced_meshes.get(path) {
+            Some(&mesh) => mesh,
+            None => match Model::load(path) {
+                Ok(model) => {
+                    let mesh = self.instances.add_mesh(model);
+                    self.instanced_meshes.insert(path.to_owned(), mesh);
+                    mesh
+                }
+                Err(err) => {
+                    log!("Failed to load {}: {}", path, err);
+                    return;
+                }
+            },
+        };
+
+        let center = self.camera.position + self.camera.direction * 50.0;
+        let mut rng = XorShift(self.instances.instance_count() as u64 + 1);
+        let mut ids = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let angle = rng.next_f32() * TAU;
+            let distance = rng.next_f32().sqrt() * radius;
+            let mut pos = center + Vec3::new(angle.cos(), 0.0, angle.sin()) * distance;
+            if let Some(height) = self.terrain.height_at(pos.xz()) {
+                pos.y = height;
+            }
+            let rotation = Quat::from_rotation_y(rng.next_f32() * TAU);
+            let transform = Mat4::from_rotation_translation(rotation, pos);
+            ids.push(self.instances.add_instance(mesh, transform));
+        }
+        self.scatters.push(ids);
+    }
+
+    fn selected_positions(&self) -> Vec<Vec3> {
+        self.editor_state
+            .selected_objects
+            .iter()
+            .map(|&entity| self.scene.transform(entity).unwrap_or_default().position)
+            .collect()
+    }
+
+    fn set_selected_positions(&mut self, positions: &[Vec3]) {
+        for (&entity, &position) in self.editor_state.selected_objects.iter().zip(positions) {
+            if let Some(transform) = self.scene.transform(entity) {
+                self.scene.set_transform(
+                    entity,
+                    Transform {
+                        position,
+                        ..transform
+                    },
+                );
+            }
+        }
+    }
+
+    /// In world space, where the object was last drawn
+    fn object_position(&self, entity: hecs::Entity) -> Option<Vec3> {
+        let transform = self.scene.global_transform(entity)?;
+        Some(transform.w_axis.truncate())
+    }
+}
+
+/// Decodes the default skybox faces on a worker, see `ImageLoader`
+fn load_skybox_faces(image_loader: &mut ImageLoader, jobs: &JobSystem) {
+    let decode = Skybox::decode_job(skybox::DEFAULT_FACES);
+    image_loader.load(jobs, "skybox", move || decode().map(LoadedImage::Skybox));
 }
 
 /// Winit sends special keys (backspace, delete, F1, ...) as characters.
@@ -0,0 +1,171 @@
+//! Surface description shared by meshes and terrain layers.
+//! Materials upload their own uniforms, so draw code only has to call `bind`.
+
+use std::mem::size_of;
+
+use gl::types::*;
+use glam::Vec4;
+
+use crate::opengl::bindings::{self, Sampler, TextureUnit};
+
+/// Uniform buffer binding used by mesh.frag
+const MATERIAL_UBO_BINDING: u32 = 3;
+
+// Must match the flags in mesh.frag
+const FLAG_HAS_ALBEDO: i32 = 1;
+const FLAG_HAS_NORMAL: i32 = 2;
+const FLAG_HAS_METALLIC_ROUGHNESS: i32 = 4;
+const FLAG_UNLIT: i32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShaderVariant {
+    Lit,
+    /// Albedo only, ignores lights and shadows
+    Unlit,
+}
+
+impl ShaderVariant {
+    pub const ALL: [ShaderVariant; 2] = [ShaderVariant::Lit, ShaderVariant::Unlit];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ShaderVariant::Lit => "Lit",
+            ShaderVariant::Unlit => "Unlit",
+        }
+    }
+}
+
+/// Scalar parameters of the metallic-roughness model, multiplied with the textures
+/// where there are any. Albedo is linear.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MaterialParams {
+    pub albedo: Vec4,
+    pub roughness: f32,
+    pub metallic: f32,
+}
+
+impl Default for MaterialParams {
+    /// What glTF says to use for primitives without a material
+    fn default() -> Self {
+        MaterialParams {
+            albedo: Vec4::ONE,
+            roughness: 1.0,
+            metallic: 1.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Material {
+    pub name: String,
+    pub params: MaterialParams,
+    pub variant: ShaderVariant,
+    /// The textures are owned by whoever loaded them, e.g. the model
+    pub albedo_texture: Option<GLuint>,
+    pub normal_texture: Option<GLuint>,
+    /// Roughness in the green channel and metallic in the blue one, like in glTF
+    pub metallic_roughness_texture: Option<GLuint>,
+
+    ubo: GLuint,
+    uploaded_block: Option<MaterialBlock>,
+}
+
+impl Material {
+    pub fn new(name: &str) -> Self {
+        let mut ubo: GLuint = 0;
+        unsafe {
+            gl::CreateBuffers(1, &mut ubo);
+            gl::NamedBufferStorage(
+                ubo,
+                size_of::<MaterialBlock>() as isize,
+                std::ptr::null(),
+                gl::DYNAMIC_STORAGE_BIT,
+            );
+        }
+        Material {
+            name: name.to_owned(),
+            params: MaterialParams::default(),
+            variant: ShaderVariant::Lit,
+            albedo_texture: None,
+            normal_texture: None,
+            metallic_roughness_texture: None,
+
+            ubo,
+            uploaded_block: None,
+        }
+    }
+
+    /// Binds the textures and uploads the parameters if they have changed
+    pub fn bind(&mut self) {
+        let block = MaterialBlock::from(&*self);
+        if self.uploaded_block.as_ref() != Some(&block) {
+            unsafe {
+                gl::NamedBufferSubData(
+                    self.ubo,
+                    0,
+                    size_of::<MaterialBlock>() as isize,
+                    &block as *const MaterialBlock as *const _,
+                );
+            }
+            self.uploaded_block = Some(block);
+        }
+
+        unsafe {
+            gl::BindBufferBase(gl::UNIFORM_BUFFER, MATERIAL_UBO_BINDING, self.ubo);
+        }
+        let textures = [
+            (TextureUnit::ALBEDO, self.albedo_texture),
+            (TextureUnit::NORMAL_MAP, self.normal_texture),
+            (
+                TextureUnit::METALLIC_ROUGHNESS,
+                self.metallic_roughness_texture,
+            ),
+        ];
+        for (unit, texture) in textures {
+            if let Some(texture) = texture {
+                bindings::bind_texture_sampled(unit, texture, Sampler::TrilinearRepeat);
+            }
+        }
+    }
+}
+
+impl Drop for Material {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.ubo);
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct MaterialBlock {
+    albedo: Vec4,
+    roughness: f32,
+    metallic: f32,
+    flags: i32,
+}
+
+impl From<&Material> for MaterialBlock {
+    fn from(material: &Material) -> Self {
+        let mut flags = 0;
+        if material.albedo_texture.is_some() {
+            flags |= FLAG_HAS_ALBEDO;
+        }
+        if material.normal_texture.is_some() {
+            flags |= FLAG_HAS_NORMAL;
+        }
+        if material.metallic_roughness_texture.is_some() {
+            flags |= FLAG_HAS_METALLIC_ROUGHNESS;
+        }
+        if material.variant == ShaderVariant::Unlit {
+            flags |= FLAG_UNLIT;
+        }
+        MaterialBlock {
+            albedo: material.params.albedo,
+            roughness: material.params.roughness,
+            metallic: material.params.metallic,
+            flags,
+        }
+    }
+}
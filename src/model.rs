@@ -1,14 +1,20 @@
 use std::mem::size_of;
+use std::path::Path;
 
 use gl::types::*;
-use glam::{Mat4, Vec2, Vec3, Vec4};
-use gltf::accessor::DataType;
-use gltf::accessor::Dimensions;
+use glam::{Mat4, Vec2, Vec3};
 use gltf::image::Format;
 use gltf::Document;
 use memoffset::offset_of;
 
-use crate::texture::calculate_mip_levels;
+use crate::material::{Material, MaterialParams};
+use crate::obj::{self, ObjMaterial};
+use crate::opengl::bindings::delete_textures;
+use crate::opengl::shader::Program;
+use crate::profiler;
+use crate::ray::AABB;
+use crate::texture::create_texture;
+use crate::texture_manager::{self, TextureHandle, TextureKind};
 use crate::utils::size_of_slice;
 use crate::Result;
 
@@ -17,14 +23,33 @@ pub struct Model {
     pub vao: GLuint,
     vbo: GLuint,
     ebo: GLuint,
+    /// Embedded in the file or generated, owned by the model
     texture_ids: Vec<GLuint>,
+    /// Loaded from files, shared with other models
+    shared_textures: Vec<TextureHandle>,
 
     pub drawable_nodes: Vec<DrawableNode>,
     pub materials: Vec<Material>,
+    default_material: Material,
+    /// Of all nodes with their transforms applied
+    pub bounds: AABB,
 }
 
 impl Model {
+    /// Loads a glTF or, going by the extension, a Wavefront OBJ file
     pub fn load(path: &str) -> Result<Model> {
+        let path = Path::new(path);
+        let is_obj = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("obj"));
+        if is_obj {
+            Self::load_obj(path)
+        } else {
+            Self::load_gltf(path)
+        }
+    }
+
+    fn load_gltf(path: &Path) -> Result<Model> {
         let (gltf, buffers, images) = gltf::import(path)?;
 
         // Get drawable nodes and primitives
@@ -37,116 +62,44 @@ impl Model {
         {
             let mut primitives = vec![];
             for primitive in node.mesh().unwrap().primitives() {
-                let first_index = indices.len();
-                let vertex_start = vertices.len();
-
-                // Add vertices to our buffer
-                {
-                    // Gather all attributes in separate vecs (yes, inefficient)
-                    let mut positions = vec![];
-                    let mut normals = vec![];
-                    let mut uvs = vec![];
-
-                    for (attr, accessor) in primitive.attributes() {
-                        let buffer_view = accessor.view().unwrap();
-                        let offset = buffer_view.offset() + accessor.offset();
-                        let stride = buffer_view.stride().unwrap_or_else(|| {
-                            accessor.dimensions().multiplicity()
-                                * match accessor.data_type() {
-                                    DataType::I8 => 1,
-                                    DataType::U8 => 1,
-                                    DataType::I16 => 2,
-                                    DataType::U16 => 2,
-                                    DataType::U32 => 4,
-                                    DataType::F32 => 4,
-                                }
-                        });
-                        let count = accessor.count();
-
-                        let src_buffer = &buffers[buffer_view.buffer().index()];
-
-                        unsafe {
-                            // Get a pointer to the first element of attribute
-                            let mut cursor = src_buffer.as_ptr().add(offset);
-
-                            use gltf::Semantic::*;
-                            match attr {
-                                Positions => {
-                                    debug_assert_eq!(accessor.dimensions(), Dimensions::Vec3);
-                                    for _ in 0..count {
-                                        let position = cursor as *const Vec3;
-                                        positions.push(*position);
-                                        cursor = cursor.add(stride);
-                                    }
-                                }
-                                Normals => {
-                                    debug_assert_eq!(accessor.dimensions(), Dimensions::Vec3);
-                                    for _ in 0..count {
-                                        let normal = cursor as *const Vec3;
-                                        normals.push(*normal);
-                                        cursor = cursor.add(stride);
-                                    }
-                                }
-                                TexCoords(0) => {
-                                    debug_assert_eq!(accessor.dimensions(), Dimensions::Vec2);
-                                    for _ in 0..count {
-                                        let uv = cursor as *const Vec2;
-                                        uvs.push(*uv);
-                                        cursor = cursor.add(stride);
-                                    }
-                                }
-                                TexCoords(_) => unimplemented!("Only one texture is supported"),
-                                _ => {}
-                            }
-                        }
-                    }
-                    assert_eq!(positions.len(), normals.len());
-                    assert_eq!(positions.len(), uvs.len());
-
-                    for i in 0..positions.len() {
-                        vertices.push(Vertex {
-                            pos: positions[i],
-                            normal: normals[i],
-                            uv: uvs[i],
-                        })
-                    }
+                if primitive.mode() != gltf::mesh::Mode::Triangles {
+                    continue; // lines and points are not supported
                 }
+                let first_index = indices.len();
+                let vertex_start = vertices.len() as u32;
+
+                let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+                let positions: Vec<Vec3> = reader
+                    .read_positions()
+                    .ok_or("Mesh primitive has no positions")?
+                    .map(Vec3::from)
+                    .collect();
+                let primitive_indices: Vec<u32> = match reader.read_indices() {
+                    Some(read_indices) => read_indices.into_u32().collect(),
+                    None => (0..positions.len() as u32).collect(),
+                };
+                let normals: Vec<Vec3> = match reader.read_normals() {
+                    Some(normals) => normals.map(Vec3::from).collect(),
+                    None => smooth_normals(&positions, &primitive_indices),
+                };
+                let uvs: Vec<Vec2> = match reader.read_tex_coords(0) {
+                    Some(uvs) => uvs.into_f32().map(Vec2::from).collect(),
+                    None => vec![Vec2::ZERO; positions.len()],
+                };
 
-                // Add indices to our buffer
-                let accessor = primitive.indices().unwrap();
-                {
-                    let buffer_view = accessor.view().unwrap();
-                    let offset = buffer_view.offset() + accessor.offset();
-                    let stride = buffer_view.stride().unwrap_or(0);
-                    assert_eq!(stride, 0); // support only tightly packed indices
-
-                    let src_buffer = &buffers[buffer_view.buffer().index()];
-                    let vertex_start = vertex_start as u32;
-
-                    match accessor.data_type() {
-                        DataType::U32 => unsafe {
-                            let src_buffer = src_buffer.as_ptr().add(offset) as *const u32;
-                            let index_slice =
-                                std::slice::from_raw_parts(src_buffer, accessor.count());
-                            indices.extend(index_slice.iter().map(|&index| vertex_start + index));
-                        },
-                        DataType::U16 => unsafe {
-                            let src_buffer = src_buffer.as_ptr().add(offset) as *const u16;
-                            let index_slice =
-                                std::slice::from_raw_parts(src_buffer, accessor.count());
-                            indices.extend(
-                                index_slice.iter().map(|&index| vertex_start + index as u32),
-                            );
-                        },
-                        _ => unimplemented!("We only support indices of types U16 and U32"),
-                    }
-                }
-                assert_eq!(indices.len(), first_index + accessor.count());
+                vertices.extend(
+                    positions
+                        .iter()
+                        .zip(&normals)
+                        .zip(&uvs)
+                        .map(|((&pos, &normal), &uv)| Vertex { pos, normal, uv }),
+                );
+                indices.extend(primitive_indices.iter().map(|&index| vertex_start + index));
 
                 primitives.push(Primitive {
                     first_index,
-                    index_count: accessor.count(),
-                    material_index: primitive.material().index().unwrap(),
+                    index_count: primitive_indices.len(),
+                    material_index: primitive.material().index(),
                 });
             }
 
@@ -156,7 +109,156 @@ impl Model {
             });
         }
 
-        // Send the vertex and index buffers to GPU
+        // Normal and metallic-roughness maps hold data rather than colors
+        let mut srgb = vec![true; images.len()];
+        for material in gltf.materials() {
+            let normal = material.normal_texture().map(|info| info.texture());
+            let metallic_roughness = material
+                .pbr_metallic_roughness()
+                .metallic_roughness_texture()
+                .map(|info| info.texture());
+            for texture in normal.into_iter().chain(metallic_roughness) {
+                srgb[texture.source().index()] = false;
+            }
+        }
+
+        // Load textures
+        let mut texture_ids = Vec::with_capacity(images.len());
+        for (image, srgb) in images.into_iter().zip(srgb) {
+            let format = match image.format {
+                Format::B8G8R8A8 => gl::BGRA,
+                Format::R8G8B8A8 => gl::RGBA,
+                Format::R8G8B8 => gl::RGB,
+                Format::B8G8R8 => gl::BGR,
+                Format::R8G8 => gl::RG,
+                Format::R8 => gl::RED,
+                format => {
+                    delete_textures(&texture_ids);
+                    return Err(format!("Unsupported texture format {:?}", format).into());
+                }
+            };
+            texture_ids.push(create_texture(
+                image.width,
+                image.height,
+                format,
+                &image.pixels,
+                srgb,
+            ));
+        }
+
+        // Fill in textures
+        let textures = gltf
+            .textures()
+            .map(|texture| texture_ids[texture.source().index()])
+            .collect::<Vec<_>>();
+
+        // Load materials
+        let materials = gltf
+            .materials()
+            .map(|material| {
+                let pbr = material.pbr_metallic_roughness();
+                let mut result = Material::new(material.name().unwrap_or("Unnamed"));
+                result.params = MaterialParams {
+                    albedo: pbr.base_color_factor().into(),
+                    roughness: pbr.roughness_factor(),
+                    metallic: pbr.metallic_factor(),
+                };
+                result.albedo_texture = pbr
+                    .base_color_texture()
+                    .map(|info| textures[info.texture().index()]);
+                result.normal_texture = material
+                    .normal_texture()
+                    .map(|info| textures[info.texture().index()]);
+                result.metallic_roughness_texture = pbr
+                    .metallic_roughness_texture()
+                    .map(|info| textures[info.texture().index()]);
+                result
+            })
+            .collect::<Vec<_>>();
+
+        Ok(Model::upload(
+            &vertices,
+            &indices,
+            drawable_nodes,
+            materials,
+            texture_ids,
+            vec![],
+        ))
+    }
+
+    fn load_obj(path: &Path) -> Result<Model> {
+        let mesh = obj::load(path)?;
+
+        let mut textures = ObjTextures::default();
+        let mut materials = Vec::with_capacity(mesh.materials.len());
+        for obj_material in &mesh.materials {
+            match textures.material(obj_material) {
+                Ok(material) => materials.push(material),
+                Err(e) => {
+                    delete_textures(&textures.texture_ids);
+                    return Err(e);
+                }
+            }
+        }
+
+        let drawable_nodes = vec![DrawableNode {
+            primitives: mesh.primitives,
+            transform: Mat4::IDENTITY,
+        }];
+
+        Ok(Model::upload(
+            &mesh.vertices,
+            &mesh.indices,
+            drawable_nodes,
+            materials,
+            textures.texture_ids,
+            textures.shared,
+        ))
+    }
+
+    /// A single mesh made in code, drawn with one material
+    pub fn from_geometry(vertices: &[Vertex], indices: &[u32], material: Material) -> Model {
+        let drawable_nodes = vec![DrawableNode {
+            primitives: vec![Primitive {
+                first_index: 0,
+                index_count: indices.len(),
+                material_index: Some(0),
+            }],
+            transform: Mat4::IDENTITY,
+        }];
+        Model::upload(
+            vertices,
+            indices,
+            drawable_nodes,
+            vec![material],
+            vec![],
+            vec![],
+        )
+    }
+
+    /// Sends the vertex and index buffers to GPU
+    fn upload(
+        vertices: &[Vertex],
+        indices: &[u32],
+        drawable_nodes: Vec<DrawableNode>,
+        materials: Vec<Material>,
+        texture_ids: Vec<GLuint>,
+        shared_textures: Vec<TextureHandle>,
+    ) -> Model {
+        let mut bounds = AABB::empty();
+        for node in &drawable_nodes {
+            for primitive in &node.primitives {
+                let range = primitive.first_index..primitive.first_index + primitive.index_count;
+                for &index in &indices[range] {
+                    let pos = node
+                        .transform
+                        .transform_point3(vertices[index as usize].pos);
+                    bounds.min = bounds.min.min(pos);
+                    bounds.max = bounds.max.max(pos);
+                }
+            }
+        }
+
         let mut vao: GLuint = 0;
         let mut vbo: GLuint = 0;
         let mut ebo: GLuint = 0;
@@ -210,7 +312,7 @@ impl Model {
             // Vertex data
             gl::NamedBufferStorage(
                 vbo,
-                size_of_slice(&vertices) as isize,
+                size_of_slice(vertices) as isize,
                 vertices.as_ptr() as *const _,
                 0,
             );
@@ -218,101 +320,198 @@ impl Model {
             // Index data
             gl::NamedBufferStorage(
                 ebo,
-                size_of_slice(&indices) as isize,
+                size_of_slice(indices) as isize,
                 indices.as_ptr() as *const _,
                 0,
             );
         }
 
-        // Load textures
-        let num_textures = images.len();
-        let mut texture_ids = Vec::with_capacity(num_textures);
+        Model {
+            vao,
+            vbo,
+            ebo,
+            texture_ids,
+            shared_textures,
+
+            drawable_nodes,
+            materials,
+            default_material: Material::new("Default"),
+            bounds,
+        }
+    }
+
+    /// Draws all nodes with the mesh shader, which must be in use
+    pub fn draw(&mut self, shader: &Program, transform: &Mat4) -> Result<()> {
         unsafe {
-            texture_ids.set_len(num_textures);
-            gl::CreateTextures(
-                gl::TEXTURE_2D,
-                num_textures as i32,
-                texture_ids.as_mut_ptr(),
-            );
-            for &texture in &texture_ids {
-                // Default sampler parameters
-                gl::TextureParameteri(texture, gl::TEXTURE_WRAP_S, gl::REPEAT as GLint);
-                gl::TextureParameteri(texture, gl::TEXTURE_WRAP_T, gl::REPEAT as GLint);
-                gl::TextureParameteri(
-                    texture,
-                    gl::TEXTURE_MIN_FILTER,
-                    gl::LINEAR_MIPMAP_LINEAR as GLint,
-                );
-                gl::TextureParameteri(texture, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
-            }
+            gl::BindVertexArray(self.vao);
         }
-        for (image, texture_id) in images.into_iter().zip(&texture_ids) {
-            let texture = *texture_id;
-            unsafe {
-                gl::TextureStorage2D(
-                    texture,
-                    calculate_mip_levels(image.width as usize, image.height as usize),
-                    gl::SRGB8,
-                    image.width as i32,
-                    image.height as i32,
-                );
-                let format = match image.format {
-                    Format::B8G8R8A8 => gl::BGRA,
-                    Format::R8G8B8A8 => gl::RGBA,
-                    Format::R8G8B8 => gl::RGB,
-                    Format::B8G8R8 => gl::BGR,
-                    _ => panic!("Unsupported texture format"),
-                };
-                if format == gl::RGB || format == gl::BGR {
-                    gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+        for node in &self.drawable_nodes {
+            shader.set_mat4("model", &(*transform * node.transform))?;
+
+            for primitive in &node.primitives {
+                match primitive.material_index {
+                    Some(index) => self.materials[index].bind(),
+                    None => self.default_material.bind(),
                 }
-                gl::TextureSubImage2D(
-                    texture,
-                    0,
-                    0,
-                    0,
-                    image.width as i32,
-                    image.height as i32,
-                    format,
-                    gl::UNSIGNED_BYTE,
-                    image.pixels.as_ptr() as *const _,
-                );
-                gl::GenerateTextureMipmap(texture);
-                if format == gl::RGB || format == gl::BGR {
-                    gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+                unsafe {
+                    gl::DrawElements(
+                        gl::TRIANGLES,
+                        primitive.index_count as i32,
+                        gl::UNSIGNED_INT,
+                        (primitive.first_index * size_of::<u32>()) as *const _,
+                    );
+                    profiler::count_draw_call();
                 }
             }
         }
+        Ok(())
+    }
 
-        // Fill in textures
-        let textures = gltf
-            .textures()
-            .map(|texture| texture_ids[texture.source().index()])
-            .collect::<Vec<_>>();
+    /// One command per primitive with no instances, in the order `draw_indirect` uses them
+    pub fn draw_commands(&self) -> Vec<DrawElementsIndirectCommand> {
+        self.drawable_nodes
+            .iter()
+            .flat_map(|node| &node.primitives)
+            .map(|primitive| DrawElementsIndirectCommand {
+                count: primitive.index_count as u32,
+                instance_count: 0,
+                first_index: primitive.first_index as u32,
+                base_vertex: 0,
+                base_instance: 0,
+            })
+            .collect()
+    }
 
-        // Load materials
-        let materials = gltf
-            .materials()
-            .map(|material| {
-                let pbr = material.pbr_metallic_roughness();
-                let texture_index = pbr.base_color_texture().unwrap().texture().index();
-                Material {
-                    base_color_factor: Vec4::from(pbr.base_color_factor()),
-                    base_color_texture: textures[texture_index],
+    /// Draws all nodes with the commands from `draw_commands`, starting at `offset` in
+    /// the bound indirect buffer. The shader must be in use and take the instance
+    /// transforms from somewhere else, `model` is only the node transform.
+    pub fn draw_indirect(&mut self, shader: &Program, offset: usize) -> Result<()> {
+        unsafe {
+            gl::BindVertexArray(self.vao);
+        }
+        let mut command = 0;
+        for node in &self.drawable_nodes {
+            shader.set_mat4("model", &node.transform)?;
+
+            for primitive in &node.primitives {
+                match primitive.material_index {
+                    Some(index) => self.materials[index].bind(),
+                    None => self.default_material.bind(),
                 }
-            })
-            .collect::<Vec<_>>();
+                unsafe {
+                    gl::DrawElementsIndirect(
+                        gl::TRIANGLES,
+                        gl::UNSIGNED_INT,
+                        (offset + command * size_of::<DrawElementsIndirectCommand>()) as *const _,
+                    );
+                    profiler::count_draw_call();
+                }
+                command += 1;
+            }
+        }
+        Ok(())
+    }
+}
 
-        Ok(Model {
-            vao,
-            vbo,
-            ebo,
-            texture_ids,
+/// Creates materials for an OBJ model. The texture files go through the texture
+/// manager, so materials and other models using the same file share it.
+#[derive(Default)]
+struct ObjTextures {
+    /// The packed metallic-roughness maps
+    texture_ids: Vec<GLuint>,
+    shared: Vec<TextureHandle>,
+}
 
-            drawable_nodes,
-            materials,
-        })
+impl ObjTextures {
+    fn material(&mut self, obj_material: &ObjMaterial) -> Result<Material> {
+        let mut material = Material::new(&obj_material.name);
+        material.params = MaterialParams {
+            albedo: obj_material.diffuse.extend(obj_material.opacity),
+            roughness: obj_material.roughness,
+            metallic: obj_material.metallic,
+        };
+        material.albedo_texture = self.load(obj_material.diffuse_map.as_deref(), true)?;
+        material.normal_texture = self.load(obj_material.normal_map.as_deref(), false)?;
+        let packed = pack_metallic_roughness(
+            obj_material.roughness_map.as_deref(),
+            obj_material.metallic_map.as_deref(),
+        )?;
+        material.metallic_roughness_texture = packed.map(|image| self.upload(&image, false));
+        Ok(material)
+    }
+
+    fn load(&mut self, path: Option<&Path>, srgb: bool) -> Result<Option<GLuint>> {
+        let path = match path {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let kind = if srgb {
+            TextureKind::Color
+        } else {
+            TextureKind::Data
+        };
+        let texture = texture_manager::load(path, kind)?;
+        let id = texture.id();
+        self.shared.push(texture);
+        Ok(Some(id))
     }
+
+    fn upload(&mut self, image: &image::RgbaImage, srgb: bool) -> GLuint {
+        let texture = create_texture(image.width(), image.height(), gl::RGBA, image, srgb);
+        self.texture_ids.push(texture);
+        texture
+    }
+}
+
+/// OBJ keeps roughness and metallic in separate grayscale images,
+/// while the shader expects them in the green and blue channels like glTF
+fn pack_metallic_roughness(
+    roughness: Option<&Path>,
+    metallic: Option<&Path>,
+) -> Result<Option<image::RgbaImage>> {
+    let open = |path: Option<&Path>| -> Result<Option<image::GrayImage>> {
+        match path {
+            Some(path) => Ok(Some(
+                image::open(path)
+                    .map_err(|e| format!("{}: {}", path.display(), e))?
+                    .into_luma8(),
+            )),
+            None => Ok(None),
+        }
+    };
+    let roughness = open(roughness)?;
+    let metallic = open(metallic)?;
+    let (width, height) = match (&roughness, &metallic) {
+        (Some(image), _) | (None, Some(image)) => image.dimensions(),
+        (None, None) => return Ok(None),
+    };
+    // Missing maps are white so that only the scalar factors apply
+    let sample = |image: &Option<image::GrayImage>, x: u32, y: u32| match image {
+        Some(image) => {
+            let (w, h) = image.dimensions();
+            image.get_pixel(x * w / width, y * h / height)[0]
+        }
+        None => 255,
+    };
+    Ok(Some(image::RgbaImage::from_fn(width, height, |x, y| {
+        image::Rgba([0, sample(&roughness, x, y), sample(&metallic, x, y), 255])
+    })))
+}
+
+/// Area-weighted vertex normals for meshes that come without them
+pub fn smooth_normals(positions: &[Vec3], indices: &[u32]) -> Vec<Vec3> {
+    let mut normals = vec![Vec3::ZERO; positions.len()];
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|i| i as usize);
+        let face_normal = (positions[b] - positions[a]).cross(positions[c] - positions[a]);
+        normals[a] += face_normal;
+        normals[b] += face_normal;
+        normals[c] += face_normal;
+    }
+    normals
+        .into_iter()
+        .map(|n| n.try_normalize().unwrap_or(Vec3::Y))
+        .collect()
 }
 
 impl Drop for Model {
@@ -321,23 +520,17 @@ impl Drop for Model {
             gl::DeleteBuffers(1, &self.vbo);
             gl::DeleteBuffers(1, &self.ebo);
             gl::DeleteVertexArrays(1, &self.vao);
-            gl::DeleteTextures(self.texture_ids.len() as i32, self.texture_ids.as_ptr());
         }
+        delete_textures(&self.texture_ids);
     }
 }
 
 #[repr(C)]
 #[derive(Debug)]
 pub struct Vertex {
-    pos: Vec3,
-    normal: Vec3,
-    uv: Vec2,
-}
-
-#[derive(Debug)]
-pub struct Material {
-    pub base_color_factor: Vec4,
-    pub base_color_texture: GLuint,
+    pub pos: Vec3,
+    pub normal: Vec3,
+    pub uv: Vec2,
 }
 
 #[derive(Debug)]
@@ -345,11 +538,23 @@ pub struct Texture {
     pub image_index: usize,
 }
 
+/// Laid out the way `glDrawElementsIndirect` reads it
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct DrawElementsIndirectCommand {
+    pub count: u32,
+    pub instance_count: u32,
+    pub first_index: u32,
+    pub base_vertex: i32,
+    pub base_instance: u32,
+}
+
 #[derive(Debug)]
 pub struct Primitive {
     pub first_index: usize,
     pub index_count: usize,
-    pub material_index: usize,
+    /// None means the default material
+    pub material_index: Option<usize>,
 }
 
 #[derive(Debug)]
@@ -0,0 +1,267 @@
+//! Reader for Wavefront OBJ files with MTL materials, for quick prop imports.
+//! Only what's needed for static meshes is supported: positions, normals, UVs,
+//! polygonal faces, the diffuse colour, opacity, the PBR extension's roughness and
+//! metallic, and the diffuse, normal, roughness and metallic textures.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use glam::{Vec2, Vec3};
+
+use crate::model::{smooth_normals, Primitive, Vertex};
+use crate::Result;
+
+pub struct ObjMesh {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    pub primitives: Vec<Primitive>,
+    pub materials: Vec<ObjMaterial>,
+}
+
+pub struct ObjMaterial {
+    pub name: String,
+    pub diffuse: Vec3,
+    pub opacity: f32,
+    pub roughness: f32,
+    pub metallic: f32,
+    pub diffuse_map: Option<PathBuf>,
+    pub normal_map: Option<PathBuf>,
+    pub roughness_map: Option<PathBuf>,
+    pub metallic_map: Option<PathBuf>,
+}
+
+impl ObjMaterial {
+    fn new(name: &str) -> Self {
+        ObjMaterial {
+            name: name.to_owned(),
+            diffuse: Vec3::ONE,
+            opacity: 1.0,
+            roughness: 1.0,
+            metallic: 0.0,
+            diffuse_map: None,
+            normal_map: None,
+            roughness_map: None,
+            metallic_map: None,
+        }
+    }
+}
+
+pub fn load(path: &Path) -> Result<ObjMesh> {
+    let source = fs::read_to_string(path)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut positions: Vec<Vec3> = vec![];
+    let mut normals: Vec<Vec3> = vec![];
+    let mut uvs: Vec<Vec2> = vec![];
+
+    let mut mesh = ObjMesh {
+        vertices: vec![],
+        indices: vec![],
+        primitives: vec![],
+        materials: vec![],
+    };
+    let mut has_all_normals = true;
+    // Each unique position/uv/normal combination becomes one vertex
+    let mut vertex_lookup: HashMap<(usize, Option<usize>, Option<usize>), u32> = HashMap::new();
+    let mut current_material: Option<usize> = None;
+    let mut first_index = 0;
+
+    for (line_number, line) in source.lines().enumerate() {
+        let error = |message: &str| format!("{}:{}: {}", path.display(), line_number + 1, message);
+        let line = line.split('#').next().unwrap().trim();
+        let (keyword, rest) = match line.split_once(char::is_whitespace) {
+            Some((keyword, rest)) => (keyword, rest.trim()),
+            None => (line, ""),
+        };
+
+        match keyword {
+            "v" => positions.push(parse_vec3(rest).ok_or_else(|| error("bad position"))?),
+            "vn" => normals.push(parse_vec3(rest).ok_or_else(|| error("bad normal"))?),
+            "vt" => {
+                let mut values = rest.split_whitespace().map(str::parse::<f32>);
+                match (values.next(), values.next()) {
+                    // OBJ has V pointing up, while textures are uploaded top row first
+                    (Some(Ok(u)), Some(Ok(v))) => uvs.push(Vec2::new(u, 1.0 - v)),
+                    (Some(Ok(u)), None) => uvs.push(Vec2::new(u, 1.0)),
+                    _ => return Err(error("bad texture coordinate").into()),
+                }
+            }
+            "f" => {
+                let mut face = vec![];
+                for corner in rest.split_whitespace() {
+                    let mut parts = corner.split('/');
+                    let position = resolve_index(parts.next(), positions.len())
+                        .ok_or_else(|| error("bad face position index"))?;
+                    let uv = match parts.next() {
+                        Some("") | None => None,
+                        index => Some(
+                            resolve_index(index, uvs.len())
+                                .ok_or_else(|| error("bad face uv index"))?,
+                        ),
+                    };
+                    let normal = match parts.next() {
+                        Some("") | None => None,
+                        index => Some(
+                            resolve_index(index, normals.len())
+                                .ok_or_else(|| error("bad face normal index"))?,
+                        ),
+                    };
+                    has_all_normals &= normal.is_some();
+
+                    let vertices = &mut mesh.vertices;
+                    let vertex =
+                        *vertex_lookup
+                            .entry((position, uv, normal))
+                            .or_insert_with(|| {
+                                vertices.push(Vertex {
+                                    pos: positions[position],
+                                    normal: normal.map_or(Vec3::Y, |n| normals[n]),
+                                    uv: uv.map_or(Vec2::ZERO, |t| uvs[t]),
+                                });
+                                vertices.len() as u32 - 1
+                            });
+                    face.push(vertex);
+                }
+                if face.len() < 3 {
+                    return Err(error("face needs at least 3 vertices").into());
+                }
+                // Triangle fan, fine for the convex polygons modeling tools export
+                for i in 1..face.len() - 1 {
+                    mesh.indices.extend([face[0], face[i], face[i + 1]]);
+                }
+            }
+            "usemtl" => {
+                close_primitive(&mut mesh, first_index, current_material);
+                first_index = mesh.indices.len();
+                // Unknown materials fall back to the default one
+                current_material = mesh.materials.iter().position(|m| m.name == rest);
+            }
+            "mtllib" => {
+                let materials = load_mtl(&dir.join(rest))?;
+                mesh.materials.extend(materials);
+            }
+            _ => {} // groups, smoothing groups, lines etc. don't matter for drawing
+        }
+    }
+    close_primitive(&mut mesh, first_index, current_material);
+
+    if mesh.indices.is_empty() {
+        return Err(format!("{} has no faces", path.display()).into());
+    }
+    if !has_all_normals {
+        let positions: Vec<Vec3> = mesh.vertices.iter().map(|v| v.pos).collect();
+        let normals = smooth_normals(&positions, &mesh.indices);
+        for (vertex, normal) in mesh.vertices.iter_mut().zip(normals) {
+            vertex.normal = normal;
+        }
+    }
+
+    Ok(mesh)
+}
+
+fn close_primitive(mesh: &mut ObjMesh, first_index: usize, material_index: Option<usize>) {
+    let index_count = mesh.indices.len() - first_index;
+    if index_count > 0 {
+        mesh.primitives.push(Primitive {
+            first_index,
+            index_count,
+            material_index,
+        });
+    }
+}
+
+fn load_mtl(path: &Path) -> Result<Vec<ObjMaterial>> {
+    let source = fs::read_to_string(path)
+        .map_err(|e| format!("Couldn't read material library {}: {}", path.display(), e))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut materials: Vec<ObjMaterial> = vec![];
+    let mut has_roughness = false;
+    let mut has_metallic = false;
+    for (line_number, line) in source.lines().enumerate() {
+        let error = |message: &str| format!("{}:{}: {}", path.display(), line_number + 1, message);
+        let line = line.split('#').next().unwrap().trim();
+        let (keyword, rest) = match line.split_once(char::is_whitespace) {
+            Some((keyword, rest)) => (keyword, rest.trim()),
+            None => (line, ""),
+        };
+
+        if keyword == "newmtl" {
+            materials.push(ObjMaterial::new(rest));
+            has_roughness = false;
+            has_metallic = false;
+            continue;
+        }
+        let material = match materials.last_mut() {
+            Some(material) => material,
+            None => continue, // nothing to apply the property to
+        };
+        match keyword {
+            "Kd" => material.diffuse = parse_vec3(rest).ok_or_else(|| error("bad Kd"))?,
+            "d" => material.opacity = rest.parse().map_err(|_| error("bad d"))?,
+            "Tr" => material.opacity = 1.0 - rest.parse::<f32>().map_err(|_| error("bad Tr"))?,
+            "Pr" => {
+                material.roughness = rest.parse().map_err(|_| error("bad Pr"))?;
+                has_roughness = true;
+            }
+            "Pm" => {
+                material.metallic = rest.parse().map_err(|_| error("bad Pm"))?;
+                has_metallic = true;
+            }
+            "Ns" => {
+                // Phong exponent, only when there's no proper roughness
+                let shininess: f32 = rest.parse().map_err(|_| error("bad Ns"))?;
+                if !has_roughness {
+                    material.roughness = (2.0 / (shininess.max(0.0) + 2.0)).sqrt();
+                }
+            }
+            "map_Kd" => {
+                material.diffuse_map = Some(map_path(dir, rest).ok_or_else(|| error("bad map_Kd"))?)
+            }
+            "map_Bump" | "bump" | "norm" => {
+                material.normal_map =
+                    Some(map_path(dir, rest).ok_or_else(|| error("bad normal map"))?)
+            }
+            "map_Pr" => {
+                material.roughness_map =
+                    Some(map_path(dir, rest).ok_or_else(|| error("bad map_Pr"))?)
+            }
+            "map_Pm" => {
+                material.metallic_map =
+                    Some(map_path(dir, rest).ok_or_else(|| error("bad map_Pm"))?);
+                if !has_metallic {
+                    material.metallic = 1.0; // let the map decide
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(materials)
+}
+
+/// Options like `-s 1 1 1` come before the file name
+fn map_path(dir: &Path, args: &str) -> Option<PathBuf> {
+    args.split_whitespace().last().map(|file| dir.join(file))
+}
+
+fn parse_vec3(s: &str) -> Option<Vec3> {
+    let mut values = s.split_whitespace().map(str::parse::<f32>);
+    match (values.next(), values.next(), values.next()) {
+        (Some(Ok(x)), Some(Ok(y)), Some(Ok(z))) => Some(Vec3::new(x, y, z)),
+        _ => None,
+    }
+}
+
+/// OBJ indices are 1-based, negative ones count back from the last element
+fn resolve_index(index: Option<&str>, count: usize) -> Option<usize> {
+    let index: i64 = index?.parse().ok()?;
+    let resolved = if index < 0 {
+        count as i64 + index
+    } else {
+        index - 1
+    };
+    (0..count as i64)
+        .contains(&resolved)
+        .then_some(resolved as usize)
+}
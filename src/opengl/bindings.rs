@@ -0,0 +1,218 @@
+//! Which texture and sampler is bound to each texture unit. The shaders pick their
+//! textures with `layout(binding = N)`, and the units are named here so that the
+//! passes agree on them. Binding what's already bound is skipped.
+//!
+//! Filtering and wrapping come from a few shared sampler objects rather than the
+//! parameters of each texture. Textures with state of their own, like the shadow map
+//! or the mipmapped atlases, are bound without a sampler.
+
+use std::cell::RefCell;
+
+use gl::types::*;
+
+use crate::texture::get_max_anisotropy;
+
+/// Units tracked, the minimum every GL 4.5 driver has per stage
+const MAX_UNITS: usize = 16;
+/// Of `Sampler::TrilinearRepeat` until `set_anisotropy` is called
+const DEFAULT_ANISOTROPY: f32 = 8.0;
+
+/// A texture unit, the `binding` of a sampler in the shaders
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextureUnit(pub u32);
+
+impl TextureUnit {
+    /// Albedo of a mesh, or the sprite, atlas or scene colour of a 2D pass
+    pub const ALBEDO: TextureUnit = TextureUnit(0);
+    /// The only texture of a pass, e.g. the skybox, the cloud noise or the brush
+    /// when it's stamped into the heightmap
+    pub const SOURCE: TextureUnit = TextureUnit(0);
+    pub const HEIGHTMAP: TextureUnit = TextureUnit(1);
+    pub const SCENE_DEPTH: TextureUnit = TextureUnit(1);
+    pub const NORMAL_MAP: TextureUnit = TextureUnit(1);
+    pub const BRUSH: TextureUnit = TextureUnit(2);
+    pub const METALLIC_ROUGHNESS: TextureUnit = TextureUnit(2);
+    /// The skybox cubemap when post-processing
+    pub const ENVIRONMENT: TextureUnit = TextureUnit(2);
+    pub const SHADOW_MAP: TextureUnit = TextureUnit(3);
+    /// The part of the scene lit by the sun when post-processing
+    pub const SUNLIGHT: TextureUnit = TextureUnit(3);
+    pub const OCCLUSION: TextureUnit = TextureUnit(4);
+    pub const HISTORY: TextureUnit = TextureUnit(4);
+    pub const SPLAT_ALBEDO: TextureUnit = TextureUnit(4);
+    pub const SPLAT_NORMAL: TextureUnit = TextureUnit(5);
+    pub const SPLAT_ROUGHNESS: TextureUnit = TextureUnit(6);
+    pub const SPLATMAP: TextureUnit = TextureUnit(7);
+    /// Image-based lighting, see `ibl`
+    pub const IRRADIANCE: TextureUnit = TextureUnit(8);
+    pub const PREFILTERED_ENVIRONMENT: TextureUnit = TextureUnit(9);
+    pub const BRDF_LUT: TextureUnit = TextureUnit(10);
+}
+
+/// Shared sampler objects
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sampler {
+    /// Render targets, the GUI and heightmaps
+    LinearClamp,
+    /// Depth and other textures which mustn't be blended between texels
+    NearestClamp,
+    /// Tiling volume textures without mipmaps, like the cloud noise
+    LinearRepeat,
+    /// Mipmapped surface textures, with anisotropic filtering
+    TrilinearRepeat,
+}
+
+impl Sampler {
+    const COUNT: usize = 4;
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    fn create(self, anisotropy: f32) -> GLuint {
+        let (min_filter, mag_filter, wrap) = match self {
+            Sampler::LinearClamp => (gl::LINEAR, gl::LINEAR, gl::CLAMP_TO_EDGE),
+            Sampler::NearestClamp => (gl::NEAREST, gl::NEAREST, gl::CLAMP_TO_EDGE),
+            Sampler::LinearRepeat => (gl::LINEAR, gl::LINEAR, gl::REPEAT),
+            Sampler::TrilinearRepeat => (gl::LINEAR_MIPMAP_LINEAR, gl::LINEAR, gl::REPEAT),
+        };
+        let mut sampler: GLuint = 0;
+        unsafe {
+            gl::CreateSamplers(1, &mut sampler);
+            gl::SamplerParameteri(sampler, gl::TEXTURE_MIN_FILTER, min_filter as GLint);
+            gl::SamplerParameteri(sampler, gl::TEXTURE_MAG_FILTER, mag_filter as GLint);
+            for &coord in &[gl::TEXTURE_WRAP_S, gl::TEXTURE_WRAP_T, gl::TEXTURE_WRAP_R] {
+                gl::SamplerParameteri(sampler, coord, wrap as GLint);
+            }
+            if self == Sampler::TrilinearRepeat {
+                gl::SamplerParameterf(sampler, gl::TEXTURE_MAX_ANISOTROPY, anisotropy);
+            }
+        }
+        sampler
+    }
+}
+
+#[derive(Default)]
+struct BindingState {
+    /// Texture and sampler on each unit, 0 for none
+    units: [(GLuint, GLuint); MAX_UNITS],
+    /// Created the first time they're used
+    samplers: [GLuint; Sampler::COUNT],
+    /// Set with `set_anisotropy`
+    anisotropy: Option<f32>,
+}
+
+impl BindingState {
+    /// Records the texture and sampler on the unit. Returns whether each of them has to
+    /// be bound, i.e. wasn't there already.
+    fn record(&mut self, index: usize, texture: GLuint, sampler: GLuint) -> (bool, bool) {
+        let bound = &mut self.units[index];
+        let changed = (bound.0 != texture, bound.1 != sampler);
+        *bound = (texture, sampler);
+        changed
+    }
+
+    fn forget_textures(&mut self, textures: &[GLuint]) {
+        for bound in self.units.iter_mut() {
+            if bound.0 != 0 && textures.contains(&bound.0) {
+                // Deleting a bound texture unbinds it
+                bound.0 = 0;
+            }
+        }
+    }
+}
+
+thread_local! {
+    // GL calls only happen on the main thread
+    static STATE: RefCell<BindingState> = RefCell::new(BindingState::default());
+}
+
+/// Binds the texture with its own filtering and wrapping
+pub fn bind_texture(unit: TextureUnit, texture: GLuint) {
+    bind(unit, texture, 0);
+}
+
+/// Binds the texture with one of the shared samplers
+pub fn bind_texture_sampled(unit: TextureUnit, texture: GLuint, sampler: Sampler) {
+    let sampler = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let anisotropy = state.anisotropy.unwrap_or(DEFAULT_ANISOTROPY);
+        let id = &mut state.samplers[sampler.index()];
+        if *id == 0 {
+            *id = sampler.create(clamp_anisotropy(anisotropy));
+        }
+        *id
+    });
+    bind(unit, texture, sampler);
+}
+
+/// Anisotropic filtering of `Sampler::TrilinearRepeat`, as much of it as the driver
+/// allows. The sampler is changed in place, so it applies to every surface texture.
+pub fn set_anisotropy(anisotropy: f32) {
+    let anisotropy = clamp_anisotropy(anisotropy);
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.anisotropy = Some(anisotropy);
+        let sampler = state.samplers[Sampler::TrilinearRepeat.index()];
+        if sampler != 0 {
+            unsafe {
+                gl::SamplerParameterf(sampler, gl::TEXTURE_MAX_ANISOTROPY, anisotropy);
+            }
+        }
+    });
+}
+
+fn clamp_anisotropy(anisotropy: f32) -> f32 {
+    anisotropy.min(get_max_anisotropy()).max(1.0)
+}
+
+fn bind(unit: TextureUnit, texture: GLuint, sampler: GLuint) {
+    let index = unit.0 as usize;
+    assert!(index < MAX_UNITS, "Unsupported texture unit {}", unit.0);
+    let (texture_changed, sampler_changed) =
+        STATE.with(|state| state.borrow_mut().record(index, texture, sampler));
+    unsafe {
+        if texture_changed {
+            gl::BindTextureUnit(unit.0, texture);
+        }
+        if sampler_changed {
+            gl::BindSampler(unit.0, sampler);
+        }
+    }
+}
+
+/// Deletes the textures and forgets where they were bound, since GL reuses the names
+pub fn delete_textures(textures: &[GLuint]) {
+    STATE.with(|state| state.borrow_mut().forget_textures(textures));
+    unsafe {
+        gl::DeleteTextures(textures.len() as GLsizei, textures.as_ptr());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_changes_are_bound() {
+        let mut state = BindingState::default();
+        assert_eq!(state.record(0, 5, 0), (true, false));
+        assert_eq!(state.record(0, 5, 0), (false, false));
+        assert_eq!(state.record(0, 5, 2), (false, true));
+        assert_eq!(state.record(0, 6, 2), (true, false));
+        // Units are tracked separately
+        assert_eq!(state.record(3, 6, 2), (true, true));
+        assert_eq!(state.record(0, 6, 2), (false, false));
+    }
+
+    #[test]
+    fn deleted_textures_are_bound_again() {
+        let mut state = BindingState::default();
+        state.record(0, 5, 1);
+        state.record(1, 7, 0);
+        state.forget_textures(&[5]);
+        // GL may hand the name out again for a new texture
+        assert_eq!(state.record(0, 5, 1), (true, false));
+        assert_eq!(state.record(1, 7, 0), (false, false));
+    }
+}
@@ -0,0 +1,105 @@
+//! Watches src/shaders so that programs can compile their shaders again when the files
+//! change, see `Program::set_used`. Only debug builds start watching, release ones use
+//! the code included in the binary.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+const CRATE_DIR: &str = env!("CARGO_MANIFEST_DIR");
+const SHADERS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders");
+
+struct ShaderWatcher {
+    /// Stops watching when dropped
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    /// Goes up with every change, so that programs can tell if they're out of date
+    generation: u64,
+    /// Generation of the last change of each file
+    changed: HashMap<PathBuf, u64>,
+}
+
+thread_local! {
+    // Programs are only used on the main thread
+    static WATCHER: RefCell<Option<ShaderWatcher>> = RefCell::new(None);
+}
+
+/// Starts watching the shader files, does nothing but print the error if it can't
+pub fn watch() {
+    let (sender, events) = mpsc::channel();
+    let result = notify::recommended_watcher(sender).and_then(|mut watcher| {
+        watcher.watch(Path::new(SHADERS_DIR), RecursiveMode::Recursive)?;
+        Ok(watcher)
+    });
+    match result {
+        Ok(watcher) => WATCHER.with(|cell| {
+            *cell.borrow_mut() = Some(ShaderWatcher {
+                _watcher: watcher,
+                events,
+                generation: 0,
+                changed: HashMap::new(),
+            });
+        }),
+        Err(err) => log!("Failed to watch {}: {}", SHADERS_DIR, err),
+    }
+}
+
+/// Picks up the files changed since the last call, once a frame
+pub fn poll() {
+    WATCHER.with(|cell| {
+        let mut cell = cell.borrow_mut();
+        let watcher = match cell.as_mut() {
+            Some(watcher) => watcher,
+            None => return,
+        };
+        for event in watcher.events.try_iter() {
+            match event {
+                Ok(event) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+                    watcher.generation += 1;
+                    for path in event.paths {
+                        watcher.changed.insert(path, watcher.generation);
+                    }
+                }
+                Ok(_) => {}
+                Err(err) => log!("Shader watcher error: {}", err),
+            }
+        }
+    });
+}
+
+/// 0 if nothing is being watched
+pub fn generation() -> u64 {
+    WATCHER.with(|cell| {
+        cell.borrow()
+            .as_ref()
+            .map_or(0, |watcher| watcher.generation)
+    })
+}
+
+/// Whether any of the files, relative to the crate root, changed after the generation
+pub fn changed_since<'a>(paths: impl Iterator<Item = &'a str>, generation: u64) -> bool {
+    WATCHER.with(|cell| {
+        let cell = cell.borrow();
+        let watcher = match cell.as_ref() {
+            Some(watcher) if watcher.generation > generation => watcher,
+            _ => return false,
+        };
+        let mut paths = paths;
+        paths.any(|path| {
+            watcher
+                .changed
+                .iter()
+                .any(|(changed, &changed_at)| changed_at > generation && changed.ends_with(path))
+        })
+    })
+}
+
+/// Current code of the file, relative to the crate root
+pub fn read(path: &str) -> io::Result<String> {
+    fs::read_to_string(Path::new(CRATE_DIR).join(path))
+}
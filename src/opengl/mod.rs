@@ -1,11 +1,42 @@
 #![macro_use]
 #![allow(dead_code)]
 
-use std::ffi::CStr;
+use std::backtrace::Backtrace;
+use std::ffi::{c_void, CStr};
 
 use gl::types::*;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
+pub mod bindings;
+pub mod hot_reload;
+pub mod objects;
+pub mod program_cache;
+pub mod readback;
 pub mod shader;
+#[cfg(feature = "spirv")]
+pub mod spirv;
+pub mod stream_buffer;
+
+/// Vendor, renderer and version of the driver the context is on
+pub fn driver() -> String {
+    let string = |name| unsafe {
+        let ptr = gl::GetString(name);
+        if ptr.is_null() {
+            String::new()
+        } else {
+            CStr::from_ptr(ptr as *const _)
+                .to_string_lossy()
+                .into_owned()
+        }
+    };
+    format!(
+        "{} {} {}",
+        string(gl::VENDOR),
+        string(gl::RENDERER),
+        string(gl::VERSION)
+    )
+}
 
 pub fn gl_check_error(file: &str, line: u32) {
     let error_code = unsafe { gl::GetError() };
@@ -23,6 +54,17 @@ pub fn gl_check_error(file: &str, line: u32) {
     }
 }
 
+/// Code of a shader under src/shaders, included in the binary. The path is kept so
+/// that debug builds can reload it, see `hot_reload`.
+macro_rules! shader_file {
+    ($path:literal) => {
+        crate::opengl::shader::ShaderSource {
+            path: concat!("src/shaders/", $path),
+            code: include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders/", $path)),
+        }
+    };
+}
+
 #[allow(unused_macros)]
 macro_rules! gl_check_error {
     () => {
@@ -30,22 +72,124 @@ macro_rules! gl_check_error {
     };
 }
 
-pub extern "system" fn debug_callback(
+/// How much a driver message matters, from KHR_debug
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum DebugSeverity {
+    /// Things like where buffers were put, very chatty on some drivers
+    Notification,
+    Low,
+    Medium,
+    High,
+}
+
+impl Default for DebugSeverity {
+    fn default() -> Self {
+        DebugSeverity::Low
+    }
+}
+
+impl DebugSeverity {
+    pub const ALL: [DebugSeverity; 4] = [
+        DebugSeverity::Notification,
+        DebugSeverity::Low,
+        DebugSeverity::Medium,
+        DebugSeverity::High,
+    ];
+
+    fn to_gl(self) -> GLenum {
+        match self {
+            DebugSeverity::Notification => gl::DEBUG_SEVERITY_NOTIFICATION,
+            DebugSeverity::Low => gl::DEBUG_SEVERITY_LOW,
+            DebugSeverity::Medium => gl::DEBUG_SEVERITY_MEDIUM,
+            DebugSeverity::High => gl::DEBUG_SEVERITY_HIGH,
+        }
+    }
+
+    fn from_gl(severity: GLenum) -> Option<Self> {
+        DebugSeverity::ALL
+            .iter()
+            .copied()
+            .find(|s| s.to_gl() == severity)
+    }
+}
+
+/// Prints the driver's messages of `min_severity` and above. With `break_on_error`
+/// the first GL error aborts with a backtrace, which shows the call that caused it
+/// because the messages are synchronous.
+pub fn enable_debug_output(min_severity: DebugSeverity, break_on_error: bool) {
+    unsafe {
+        gl::Enable(gl::DEBUG_OUTPUT);
+        gl::Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
+        // Everything off, then the wanted severities back on
+        gl::DebugMessageControl(
+            gl::DONT_CARE,
+            gl::DONT_CARE,
+            gl::DONT_CARE,
+            0,
+            std::ptr::null(),
+            gl::FALSE,
+        );
+        for severity in DebugSeverity::ALL.iter().filter(|&&s| s >= min_severity) {
+            gl::DebugMessageControl(
+                gl::DONT_CARE,
+                gl::DONT_CARE,
+                severity.to_gl(),
+                0,
+                std::ptr::null(),
+                gl::TRUE,
+            );
+        }
+        // The flag is passed as the user pointer, null when it's off
+        let break_on_error = break_on_error as usize as *const c_void;
+        gl::DebugMessageCallback(Some(debug_callback), break_on_error);
+    }
+}
+
+extern "system" fn debug_callback(
     source: GLenum,
     gltype: GLenum,
     id: GLuint,
     severity: GLenum,
-    length: GLsizei,
+    _length: GLsizei,
     message: *const GLchar,
-    user_param: *mut std::os::raw::c_void,
+    user_param: *mut c_void,
 ) {
-    let msg_type = if gltype == gl::DEBUG_TYPE_ERROR {
-        "** GL ERROR ** "
-    } else {
-        "** GL DEBUG **"
+    let kind = match gltype {
+        gl::DEBUG_TYPE_ERROR => "error",
+        gl::DEBUG_TYPE_DEPRECATED_BEHAVIOR => "deprecated",
+        gl::DEBUG_TYPE_UNDEFINED_BEHAVIOR => "undefined behavior",
+        gl::DEBUG_TYPE_PORTABILITY => "portability",
+        gl::DEBUG_TYPE_PERFORMANCE => "performance",
+        _ => "message",
+    };
+    let source = match source {
+        gl::DEBUG_SOURCE_API => "API",
+        gl::DEBUG_SOURCE_WINDOW_SYSTEM => "window system",
+        gl::DEBUG_SOURCE_SHADER_COMPILER => "shader compiler",
+        gl::DEBUG_SOURCE_THIRD_PARTY => "third party",
+        gl::DEBUG_SOURCE_APPLICATION => "application",
+        _ => "other",
     };
-    let msg = unsafe { CStr::from_ptr(message) };
-    eprintln!("{} {}", msg_type, msg.to_str().unwrap().to_owned());
+    let severity = DebugSeverity::from_gl(severity).map_or_else(
+        || "unknown".to_owned(),
+        |s| format!("{:?}", s).to_lowercase(),
+    );
+    let message = unsafe { CStr::from_ptr(message) }.to_string_lossy();
+    log!(
+        "GL {} ({}, {}, id {}): {}",
+        kind,
+        severity,
+        source,
+        id,
+        message
+    );
+
+    if gltype == gl::DEBUG_TYPE_ERROR && !user_param.is_null() {
+        eprintln!("{}", Backtrace::force_capture());
+        // Not a panic, that would unwind into the driver
+        crate::crash::write_report(&format!("GL error: {}", message));
+        std::process::abort();
+    }
 }
 
 pub fn get_framebuffer_status_str(fbo: GLuint, target: GLenum) -> &'static str {
@@ -65,3 +209,61 @@ pub fn get_framebuffer_status_str(fbo: GLuint, target: GLenum) -> &'static str {
         _ => "Unknown",
     }
 }
+
+#[derive(Debug, Error)]
+#[error("{name} framebuffer is incomplete: {status}")]
+pub struct IncompleteFramebuffer {
+    name: &'static str,
+    status: &'static str,
+}
+
+/// Fails with the status unless the framebuffer can be drawn into. The name is for the
+/// error message.
+pub fn check_framebuffer(fbo: GLuint, name: &'static str) -> Result<(), IncompleteFramebuffer> {
+    match get_framebuffer_status_str(fbo, gl::FRAMEBUFFER) {
+        "FRAMEBUFFER_COMPLETE" => Ok(()),
+        status => Err(IncompleteFramebuffer { name, status }),
+    }
+}
+
+/// The bound draw framebuffer and viewport. Offscreen passes put them back when done
+/// rather than binding the screen, because they also run while drawing into other
+/// targets, e.g. the post-processing ones or a skybox capture.
+#[derive(Debug, Clone, Copy)]
+pub struct SavedTarget {
+    framebuffer: GLuint,
+    viewport: [GLint; 4],
+}
+
+impl SavedTarget {
+    pub fn current() -> Self {
+        let mut framebuffer: GLint = 0;
+        let mut viewport: [GLint; 4] = [0; 4];
+        unsafe {
+            gl::GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut framebuffer);
+            gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+        }
+        SavedTarget {
+            framebuffer: framebuffer as GLuint,
+            viewport,
+        }
+    }
+
+    pub fn restore(&self) {
+        let [x, y, width, height] = self.viewport;
+        unsafe {
+            gl::Viewport(x, y, width, height);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
+        }
+    }
+}
+
+/// A vertex array with no attributes, for draws that generate their vertices in the
+/// vertex shader from `gl_VertexID`. GL won't draw without one bound.
+pub fn empty_vertex_array() -> GLuint {
+    let mut vao: GLuint = 0;
+    unsafe {
+        gl::CreateVertexArrays(1, &mut vao);
+    }
+    vao
+}
@@ -0,0 +1,74 @@
+//! GL objects which are deleted when they're dropped. They're created with the DSA
+//! functions like the rest of the renderer, and `id()` gives the name for GL calls.
+//! Textures are deleted through `bindings`, which forgets where they were bound.
+
+use gl::types::*;
+
+use crate::opengl::bindings;
+
+macro_rules! gl_object {
+    (
+        $(#[$doc:meta])* $name:ident,
+        |$id:ident| $create:expr,
+        |$del_id:ident| $delete:expr
+    ) => {
+        $(#[$doc])*
+        pub struct $name {
+            id: GLuint,
+        }
+
+        impl $name {
+            pub fn new() -> Self {
+                let mut $id: GLuint = 0;
+                unsafe {
+                    $create;
+                }
+                $name { id: $id }
+            }
+
+            pub fn id(&self) -> GLuint {
+                self.id
+            }
+        }
+
+        impl Default for $name {
+            fn default() -> Self {
+                $name::new()
+            }
+        }
+
+        impl Drop for $name {
+            fn drop(&mut self) {
+                let $del_id = self.id;
+                $delete;
+            }
+        }
+    };
+}
+
+gl_object!(
+    Texture2D,
+    |id| gl::CreateTextures(gl::TEXTURE_2D, 1, &mut id),
+    |id| bindings::delete_textures(&[id])
+);
+gl_object!(
+    Cubemap,
+    |id| gl::CreateTextures(gl::TEXTURE_CUBE_MAP, 1, &mut id),
+    |id| bindings::delete_textures(&[id])
+);
+gl_object!(
+    Framebuffer,
+    |id| gl::CreateFramebuffers(1, &mut id),
+    |id| unsafe { gl::DeleteFramebuffers(1, &id) }
+);
+gl_object!(
+    /// Vertex, index, uniform or storage buffer, they're all the same in DSA
+    Buffer,
+    |id| gl::CreateBuffers(1, &mut id),
+    |id| unsafe { gl::DeleteBuffers(1, &id) }
+);
+gl_object!(
+    VertexArray,
+    |id| gl::CreateVertexArrays(1, &mut id),
+    |id| unsafe { gl::DeleteVertexArrays(1, &id) }
+);
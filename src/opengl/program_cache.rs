@@ -0,0 +1,107 @@
+//! Linked programs saved with glGetProgramBinary, so that the next launch can skip
+//! compiling the shaders. A program is stored under the hash of its code and the
+//! driver, and anything that doesn't load is simply compiled again.
+
+use std::collections::hash_map::DefaultHasher;
+use std::convert::TryInto;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+
+use gl::types::*;
+
+use super::driver;
+
+const APP_DIR: &str = "game2";
+const CACHE_DIR: &str = "shaders";
+
+/// Identifies the code of the shaders on this driver. The hasher isn't stable between
+/// Rust versions, which only means a rebuilt game compiles everything once more.
+pub fn key(shaders: &[(GLenum, &str)]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    // Binaries only load on the same driver, and the version changes when it's updated
+    driver().hash(&mut hasher);
+    for (kind, code) in shaders {
+        kind.hash(&mut hasher);
+        code.hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Whether the program got its binary from the cache and linked
+pub fn load(program: GLuint, key: u64) -> bool {
+    let path = match cache_path(key) {
+        Some(path) => path,
+        None => return false,
+    };
+    let data = match fs::read(&path) {
+        Ok(data) if data.len() > 4 => data,
+        _ => return false,
+    };
+    let (format, binary) = data.split_at(4);
+    let format = GLenum::from_le_bytes(format.try_into().unwrap());
+    let mut success: GLint = 0;
+    unsafe {
+        gl::ProgramBinary(
+            program,
+            format,
+            binary.as_ptr() as *const _,
+            binary.len() as GLsizei,
+        );
+        gl::GetProgramiv(program, gl::LINK_STATUS, &mut success);
+    }
+    if success == 0 {
+        // E.g. the driver was updated without changing its version string
+        let _ = fs::remove_file(&path);
+        return false;
+    }
+    true
+}
+
+/// Stores the binary of a linked program. It has to have been linked with
+/// PROGRAM_BINARY_RETRIEVABLE_HINT for some drivers to give it out.
+pub fn save(program: GLuint, key: u64) {
+    let path = match cache_path(key) {
+        Some(path) => path,
+        None => return,
+    };
+    let mut len: GLint = 0;
+    unsafe {
+        gl::GetProgramiv(program, gl::PROGRAM_BINARY_LENGTH, &mut len);
+    }
+    if len <= 0 {
+        return; // The driver doesn't support any binary formats
+    }
+    let mut binary: Vec<u8> = vec![0; len as usize];
+    let mut format: GLenum = 0;
+    let mut written: GLsizei = 0;
+    unsafe {
+        gl::GetProgramBinary(
+            program,
+            len,
+            &mut written,
+            &mut format,
+            binary.as_mut_ptr() as *mut _,
+        );
+    }
+    binary.truncate(written as usize);
+
+    let mut data = format.to_le_bytes().to_vec();
+    data.extend_from_slice(&binary);
+    let result = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&path, data));
+    if let Err(err) = result {
+        log!("Failed to save {}: {}", path.display(), err);
+    }
+}
+
+/// None if the platform has no cache directory
+fn cache_path(key: u64) -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| {
+        dir.join(APP_DIR)
+            .join(CACHE_DIR)
+            .join(format!("{:016x}.bin", key))
+    })
+}
@@ -0,0 +1,79 @@
+//! Copies a texture into a pixel buffer without waiting for it. The GPU does the copy
+//! when it gets to it, and the pixels can be taken a frame or two later.
+
+use std::ptr;
+
+use gl::types::*;
+
+use crate::opengl::objects::Buffer;
+
+pub struct TextureReadback {
+    buffer: Buffer,
+    /// Signalled when the copy is done
+    fence: GLsync,
+    size: usize,
+}
+
+impl TextureReadback {
+    /// Level 0 of the texture, `size` is its size in bytes in the format and type
+    pub fn start(texture: GLuint, format: GLenum, kind: GLenum, size: usize) -> Self {
+        let buffer = Buffer::new();
+        let fence = unsafe {
+            gl::NamedBufferStorage(
+                buffer.id(),
+                size as isize,
+                ptr::null(),
+                // Read back on the CPU
+                gl::CLIENT_STORAGE_BIT,
+            );
+            gl::BindBuffer(gl::PIXEL_PACK_BUFFER, buffer.id());
+            // With a pack buffer bound the pointer is the offset into it
+            gl::GetTextureImage(texture, 0, format, kind, size as i32, ptr::null_mut());
+            gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+            gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0)
+        };
+        TextureReadback {
+            buffer,
+            fence,
+            size,
+        }
+    }
+
+    /// Returns the pixels if the copy has finished. Doesn't block.
+    pub fn try_take(&self) -> Option<Vec<u8>> {
+        let status = unsafe { gl::ClientWaitSync(self.fence, gl::SYNC_FLUSH_COMMANDS_BIT, 0) };
+        match status {
+            gl::ALREADY_SIGNALED | gl::CONDITION_SATISFIED => Some(self.read()),
+            _ => None,
+        }
+    }
+
+    /// Blocks until the copy has finished, e.g. when exiting
+    pub fn wait(&self) -> Vec<u8> {
+        unsafe {
+            gl::ClientWaitSync(self.fence, gl::SYNC_FLUSH_COMMANDS_BIT, u64::MAX);
+        }
+        self.read()
+    }
+
+    fn read(&self) -> Vec<u8> {
+        let mut pixels = vec![0u8; self.size];
+        unsafe {
+            gl::GetNamedBufferSubData(
+                self.buffer.id(),
+                0,
+                self.size as isize,
+                pixels.as_mut_ptr() as *mut _,
+            );
+        }
+        pixels
+    }
+}
+
+impl Drop for TextureReadback {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteSync(self.fence);
+        }
+    }
+}
@@ -1,108 +1,183 @@
+use std::cell::Cell;
 use std::ffi::CString;
-use std::fs;
 use std::io;
 
 use gl::types::*;
 use glam::Vec2;
-use glam::{Mat4, Vec3};
+use glam::{Mat4, Vec3, Vec4};
 use thiserror::Error;
 
+#[cfg(feature = "spirv")]
+use crate::opengl::spirv;
+use crate::opengl::{hot_reload, program_cache};
+
 #[derive(Debug, Error)]
 pub enum ShaderError {
-    #[error("Failed to compile {name}: {message}")]
+    #[error("Failed to compile {name}:\n{message}")]
     CompileError { name: String, message: String },
+    #[error("Couldn't find the included file '{0}'")]
+    IncludeNotFound(String),
+    #[error("Failed to read {path}: {source}")]
+    ReadError { path: String, source: io::Error },
     #[error("Failed to link program: {0}")]
     LinkError(String),
     #[error("Couldn't get uniform location for '{name}'")]
     UniformLocationNotFound { name: String },
     #[error("Couldn't get uniform block index for '{name}'")]
     UniformBlockIndexNotFound { name: String },
+    #[error("Uniform block '{name}' is {actual} bytes, expected {expected}")]
+    UniformBlockSizeMismatch {
+        name: String,
+        expected: usize,
+        actual: usize,
+    },
 }
 
 pub type Result<T> = std::result::Result<T, ShaderError>;
 
+/// Code of a shader, built with `shader_file!` so that the file it came from is known
+#[derive(Debug, Clone, Copy)]
+pub struct ShaderSource {
+    /// Relative to the crate root, e.g. "src/shaders/post/post.frag"
+    pub path: &'static str,
+    /// Included in the binary
+    pub code: &'static str,
+}
+
+/// Files with the code shared between shaders, which they get with `#include "file"`.
+/// Paths are relative to src/shaders.
+const INCLUDES: [ShaderSource; 7] = [
+    shader_file!("common/frame_uniforms.glsl"),
+    shader_file!("common/heightmap.glsl"),
+    shader_file!("common/cubemap.glsl"),
+    shader_file!("common/ggx_sampling.glsl"),
+    shader_file!("common/environment.glsl"),
+    shader_file!("common/brdf.glsl"),
+    shader_file!("common/fog.glsl"),
+];
+
 pub struct Program {
-    id: GLuint,
+    /// Replaced when the shaders are reloaded, see `hot_reload`
+    id: Cell<GLuint>,
+    /// Attached shaders, kept to compile them again when their files change
+    sources: Vec<(GLenum, ShaderSource)>,
+    /// Paths of the files the sources include, which are watched too
+    includes: Vec<&'static str>,
+    /// `hot_reload::generation` when the shaders were last compiled
+    compiled_at: Cell<u64>,
 }
 
 impl Program {
     pub fn new() -> Self {
         let id = unsafe { gl::CreateProgram() };
-        Program { id }
+        Program {
+            id: Cell::new(id),
+            sources: vec![],
+            includes: vec![],
+            compiled_at: Cell::new(0),
+        }
     }
 
-    fn attach_shader(&self, code: &str, kind: GLenum) -> Result<()> {
-        let shader = Shader::new(kind, code)?;
-        unsafe {
-            gl::AttachShader(self.id, shader.id());
+    /// The shaders are compiled when the program is linked
+    fn shader(mut self, source: ShaderSource, kind: GLenum) -> Result<Self> {
+        let mut included = vec![];
+        expand_includes(source.code, 0, false, &mut included)?;
+        self.sources.push((kind, source));
+        for path in included {
+            if !self.includes.contains(&path) {
+                self.includes.push(path);
+            }
         }
-        Ok(())
+        Ok(self)
     }
 
-    pub fn vertex_shader(self, code: &str) -> Result<Self> {
-        self.attach_shader(code, gl::VERTEX_SHADER)?;
-        Ok(self)
+    pub fn vertex_shader(self, source: ShaderSource) -> Result<Self> {
+        self.shader(source, gl::VERTEX_SHADER)
     }
 
-    pub fn fragment_shader(self, code: &str) -> Result<Self> {
-        self.attach_shader(code, gl::FRAGMENT_SHADER)?;
-        Ok(self)
+    pub fn fragment_shader(self, source: ShaderSource) -> Result<Self> {
+        self.shader(source, gl::FRAGMENT_SHADER)
     }
 
-    pub fn tess_control_shader(self, code: &str) -> Result<Self> {
-        self.attach_shader(code, gl::TESS_CONTROL_SHADER)?;
-        Ok(self)
+    pub fn tess_control_shader(self, source: ShaderSource) -> Result<Self> {
+        self.shader(source, gl::TESS_CONTROL_SHADER)
     }
 
-    pub fn tess_evaluation_shader(self, code: &str) -> Result<Self> {
-        self.attach_shader(code, gl::TESS_EVALUATION_SHADER)?;
-        Ok(self)
+    pub fn tess_evaluation_shader(self, source: ShaderSource) -> Result<Self> {
+        self.shader(source, gl::TESS_EVALUATION_SHADER)
     }
 
-    pub fn geometry_shader(self, code: &str) -> Result<Self> {
-        self.attach_shader(code, gl::GEOMETRY_SHADER)?;
-        Ok(self)
+    pub fn geometry_shader(self, source: ShaderSource) -> Result<Self> {
+        self.shader(source, gl::GEOMETRY_SHADER)
+    }
+
+    pub fn compute_shader(self, source: ShaderSource) -> Result<Self> {
+        self.shader(source, gl::COMPUTE_SHADER)
     }
 
+    /// Loads the program from `program_cache` if it's been linked before with the same
+    /// code on this driver, otherwise compiles the shaders and caches the result
     pub fn link(self) -> Result<Self> {
+        build(self.id.get(), &self.sources, false)?;
+        self.compiled_at.set(hot_reload::generation());
+        Ok(self)
+    }
+
+    pub fn set_used(&self) {
+        #[cfg(debug_assertions)]
+        self.reload_if_changed();
         unsafe {
-            gl::LinkProgram(self.id);
+            gl::UseProgram(self.id.get());
         }
-        let mut success: GLint = 1;
+    }
+
+    /// Runs the compute shader over the work groups, using the program. Which memory
+    /// barrier is needed after it depends on how the results are read, so that's left
+    /// to the caller.
+    pub fn dispatch(&self, groups_x: u32, groups_y: u32, groups_z: u32) {
         unsafe {
-            gl::GetProgramiv(self.id, gl::LINK_STATUS, &mut success);
+            // Not `set_used`, a reload would lose the uniforms set since
+            gl::UseProgram(self.id.get());
+            gl::DispatchCompute(groups_x, groups_y, groups_z);
         }
-        if success == 0 {
-            let mut len: GLint = 0;
-            unsafe {
-                gl::GetProgramiv(self.id, gl::INFO_LOG_LENGTH, &mut len);
-            }
-            let error = new_cstring(len as usize);
-            unsafe {
-                gl::GetProgramInfoLog(
-                    self.id,
-                    len,
-                    std::ptr::null_mut(),
-                    error.as_ptr() as *mut GLchar,
-                )
+    }
+
+    /// Compiles the shaders again if any of their files changed on disk. The old
+    /// program is kept if that fails, so a typo doesn't bring the editor down.
+    #[cfg(debug_assertions)]
+    fn reload_if_changed(&self) {
+        let paths = self
+            .sources
+            .iter()
+            .map(|(_, source)| source.path)
+            .chain(self.includes.iter().copied());
+        if !hot_reload::changed_since(paths, self.compiled_at.get()) {
+            return;
+        }
+        self.compiled_at.set(hot_reload::generation());
+        let names: Vec<&str> = self.sources.iter().map(|(_, source)| source.path).collect();
+        match self.recompile() {
+            Ok(program) => {
+                // The old program is deleted with the new one's wrapper
+                self.id.swap(&program.id);
+                log!("Reloaded {}", names.join(", "));
             }
-            return Err(ShaderError::LinkError(error.to_string_lossy().into_owned()));
+            Err(err) => log!("Failed to reload {}: {}", names.join(", "), err),
         }
-
-        Ok(self)
     }
 
-    pub fn set_used(&self) {
-        unsafe {
-            gl::UseProgram(self.id);
-        }
+    #[cfg(debug_assertions)]
+    fn recompile(&self) -> Result<Program> {
+        let program = Program::new();
+        build(program.id.get(), &self.sources, true)?;
+        Ok(program)
     }
 
     // @Speed: don't get uniform location every time
     pub fn get_uniform_location(&self, name: &str) -> Result<GLint> {
         let name_cstr = CString::new(name).unwrap();
         let location =
-            unsafe { gl::GetUniformLocation(self.id, name_cstr.as_ptr() as *const GLchar) };
+            unsafe { gl::GetUniformLocation(self.id.get(), name_cstr.as_ptr() as *const GLchar) };
         if location < 0 {
             return Err(ShaderError::UniformLocationNotFound {
                 name: name.to_owned(),
@@ -113,7 +188,8 @@ impl Program {
 
     fn get_uniform_block_index(&self, name: &str) -> Result<GLuint> {
         let name_cstr = CString::new(name).unwrap();
-        let index = unsafe { gl::GetUniformBlockIndex(self.id, name_cstr.as_ptr() as *const _) };
+        let index =
+            unsafe { gl::GetUniformBlockIndex(self.id.get(), name_cstr.as_ptr() as *const _) };
         if index == gl::INVALID_INDEX {
             return Err(ShaderError::UniformBlockIndexNotFound {
                 name: name.to_owned(),
@@ -122,10 +198,25 @@ impl Program {
         Ok(index)
     }
 
+    /// Bytes the block takes in the buffer, to check the struct it's filled from
+    pub fn uniform_block_size(&self, name: &str) -> Result<usize> {
+        let index = self.get_uniform_block_index(name)?;
+        let mut size: GLint = 0;
+        unsafe {
+            gl::GetActiveUniformBlockiv(
+                self.id.get(),
+                index,
+                gl::UNIFORM_BLOCK_DATA_SIZE,
+                &mut size,
+            );
+        }
+        Ok(size as usize)
+    }
+
     pub fn bind_uniform_block(&self, name: &str, binding: u32) -> Result<()> {
         let index = self.get_uniform_block_index(name)?;
         unsafe {
-            gl::UniformBlockBinding(self.id, index, binding);
+            gl::UniformBlockBinding(self.id.get(), index, binding);
         }
         Ok(())
     }
@@ -146,6 +237,14 @@ impl Program {
         Ok(())
     }
 
+    pub fn set_vec4(&self, name: &str, vec: &Vec4) -> Result<()> {
+        let location = self.get_uniform_location(name)?;
+        unsafe {
+            gl::Uniform4fv(location, 1, vec.to_array().as_ptr());
+        }
+        Ok(())
+    }
+
     pub fn set_float3(&self, name: &str, vec: &[f32]) -> Result<()> {
         let location = self.get_uniform_location(name)?;
         unsafe {
@@ -190,7 +289,7 @@ impl Program {
 impl Drop for Program {
     fn drop(&mut self) {
         unsafe {
-            gl::DeleteProgram(self.id);
+            gl::DeleteProgram(self.id.get());
         }
     }
 }
@@ -200,40 +299,67 @@ struct Shader {
 }
 
 impl Shader {
-    pub fn new(kind: GLenum, code: &str) -> Result<Self> {
+    /// The error is the driver's info log
+    pub fn new(kind: GLenum, code: &str) -> std::result::Result<Self, String> {
         let source = CString::new(code).unwrap();
         let id = unsafe { gl::CreateShader(kind) };
         unsafe {
             gl::ShaderSource(id, 1, &source.as_ptr(), std::ptr::null());
             gl::CompileShader(id);
         }
+        let shader = Shader { id };
+        shader.check_compiled()?;
+        Ok(shader)
+    }
+
+    /// From the SPIR-V made by build.rs, the error is the driver's info log
+    #[cfg(feature = "spirv")]
+    pub fn from_spirv(kind: GLenum, binary: &[u8]) -> std::result::Result<Self, String> {
+        let id = unsafe { gl::CreateShader(kind) };
+        let entry_point = CString::new("main").unwrap();
+        unsafe {
+            gl::ShaderBinary(
+                1,
+                &id,
+                gl::SHADER_BINARY_FORMAT_SPIR_V,
+                binary.as_ptr() as *const _,
+                binary.len() as GLsizei,
+            );
+            gl::SpecializeShader(
+                id,
+                entry_point.as_ptr(),
+                0,
+                std::ptr::null(),
+                std::ptr::null(),
+            );
+        }
+        let shader = Shader { id };
+        shader.check_compiled()?;
+        Ok(shader)
+    }
+
+    fn check_compiled(&self) -> std::result::Result<(), String> {
         let mut success: GLint = 1;
         unsafe {
-            gl::GetShaderiv(id, gl::COMPILE_STATUS, &mut success);
+            gl::GetShaderiv(self.id, gl::COMPILE_STATUS, &mut success);
         }
         if success == 0 {
             let mut len: GLint = 0;
             unsafe {
-                gl::GetShaderiv(id, gl::INFO_LOG_LENGTH, &mut len);
+                gl::GetShaderiv(self.id, gl::INFO_LOG_LENGTH, &mut len);
             }
             let error = new_cstring(len as usize);
             unsafe {
-                gl::GetShaderInfoLog(id, len, std::ptr::null_mut(), error.as_ptr() as *mut GLchar);
+                gl::GetShaderInfoLog(
+                    self.id,
+                    len,
+                    std::ptr::null_mut(),
+                    error.as_ptr() as *mut GLchar,
+                );
             }
-            let name = match kind {
-                gl::VERTEX_SHADER => "vertex shader",
-                gl::FRAGMENT_SHADER => "fragment shader",
-                gl::TESS_CONTROL_SHADER => "tessellation control shader",
-                gl::TESS_EVALUATION_SHADER => "tessellation evaluation shader",
-                gl::GEOMETRY_SHADER => "geometry shader",
-                _ => panic!("Unknown shader type, can't get error message"),
-            };
-            return Err(ShaderError::CompileError {
-                name: name.to_owned(),
-                message: error.to_string_lossy().into_owned(),
-            });
+            return Err(error.to_string_lossy().into_owned());
         }
-        Ok(Shader { id })
+        Ok(())
     }
 
     pub fn id(&self) -> GLuint {
@@ -249,6 +375,329 @@ impl Drop for Shader {
     }
 }
 
+/// Compiles and links the shaders into the program. Reloaded shaders are read from
+/// disk and not cached, since they're only reloaded while they're being edited.
+fn build(program: GLuint, sources: &[(GLenum, ShaderSource)], from_disk: bool) -> Result<()> {
+    // The line numbers in the errors refer to the code before the includes
+    let mut codes = Vec::with_capacity(sources.len());
+    let mut expanded = Vec::with_capacity(sources.len());
+    for &(kind, source) in sources {
+        let code = if from_disk {
+            read_from_disk(source.path)?
+        } else {
+            source.code.to_owned()
+        };
+        expanded.push((kind, expand_includes(&code, 0, from_disk, &mut vec![])?));
+        codes.push(code);
+    }
+
+    let key = if from_disk {
+        None
+    } else {
+        let shaders: Vec<(GLenum, &str)> = expanded
+            .iter()
+            .map(|(kind, code)| (*kind, code.as_str()))
+            .collect();
+        Some(program_cache::key(&shaders))
+    };
+    if let Some(key) = key {
+        if program_cache::load(program, key) {
+            return Ok(());
+        }
+    }
+
+    // The GLSL is compiled instead if the SPIR-V doesn't load for some reason
+    #[cfg(feature = "spirv")]
+    if !from_disk && spirv::supported() {
+        match build_spirv(program, sources) {
+            Ok(()) => {
+                if let Some(key) = key {
+                    program_cache::save(program, key);
+                }
+                return Ok(());
+            }
+            Err(err) => log!("Failed to load SPIR-V, compiling GLSL: {}", err),
+        }
+    }
+
+    // Shaders are deleted once they're linked
+    let mut shaders = Vec::with_capacity(sources.len());
+    for ((&(kind, source), code), (_, expanded)) in sources.iter().zip(&codes).zip(&expanded) {
+        let shader = Shader::new(kind, expanded).map_err(|log| ShaderError::CompileError {
+            name: format!("{} ({})", source.path, stage_name(kind)),
+            message: annotate_log(&log, source.path, code, from_disk),
+        })?;
+        unsafe {
+            gl::AttachShader(program, shader.id());
+        }
+        shaders.push(shader);
+    }
+    link_program(program)?;
+
+    if let Some(key) = key {
+        program_cache::save(program, key);
+    }
+    Ok(())
+}
+
+/// Detaches the shaders again if any of them fails
+#[cfg(feature = "spirv")]
+fn build_spirv(
+    program: GLuint,
+    sources: &[(GLenum, ShaderSource)],
+) -> std::result::Result<(), String> {
+    let mut shaders = Vec::with_capacity(sources.len());
+    let result = attach_spirv(program, sources, &mut shaders)
+        .and_then(|_| link_program(program).map_err(|err| err.to_string()));
+    if result.is_err() {
+        for shader in &shaders {
+            unsafe {
+                gl::DetachShader(program, shader.id());
+            }
+        }
+    }
+    result
+}
+
+#[cfg(feature = "spirv")]
+fn attach_spirv(
+    program: GLuint,
+    sources: &[(GLenum, ShaderSource)],
+    shaders: &mut Vec<Shader>,
+) -> std::result::Result<(), String> {
+    for &(kind, source) in sources {
+        let binary = spirv::binary(source.path)
+            .ok_or_else(|| format!("{} wasn't compiled to SPIR-V", source.path))?;
+        let shader =
+            Shader::from_spirv(kind, binary).map_err(|log| format!("{}: {}", source.path, log))?;
+        unsafe {
+            gl::AttachShader(program, shader.id());
+        }
+        shaders.push(shader);
+    }
+    Ok(())
+}
+
+fn link_program(program: GLuint) -> Result<()> {
+    unsafe {
+        gl::ProgramParameteri(
+            program,
+            gl::PROGRAM_BINARY_RETRIEVABLE_HINT,
+            gl::TRUE as GLint,
+        );
+        gl::LinkProgram(program);
+    }
+    let mut success: GLint = 1;
+    unsafe {
+        gl::GetProgramiv(program, gl::LINK_STATUS, &mut success);
+    }
+    if success == 0 {
+        let mut len: GLint = 0;
+        unsafe {
+            gl::GetProgramiv(program, gl::INFO_LOG_LENGTH, &mut len);
+        }
+        let error = new_cstring(len as usize);
+        unsafe {
+            gl::GetProgramInfoLog(
+                program,
+                len,
+                std::ptr::null_mut(),
+                error.as_ptr() as *mut GLchar,
+            )
+        }
+        return Err(ShaderError::LinkError(error.to_string_lossy().into_owned()));
+    }
+    Ok(())
+}
+
+fn stage_name(kind: GLenum) -> &'static str {
+    match kind {
+        gl::VERTEX_SHADER => "vertex shader",
+        gl::FRAGMENT_SHADER => "fragment shader",
+        gl::TESS_CONTROL_SHADER => "tessellation control shader",
+        gl::TESS_EVALUATION_SHADER => "tessellation evaluation shader",
+        gl::GEOMETRY_SHADER => "geometry shader",
+        gl::COMPUTE_SHADER => "compute shader",
+        _ => panic!("Unknown shader type, can't get error message"),
+    }
+}
+
+/// Lines shown before and after the one with the error
+const ERROR_CONTEXT_LINES: usize = 2;
+
+/// Puts the file name in front of each line of the info log that points at a line,
+/// and shows that line with a few around it. The other lines are kept as they are.
+fn annotate_log(log: &str, path: &str, code: &str, from_disk: bool) -> String {
+    let mut output = String::new();
+    for log_line in log.lines().filter(|line| !line.trim().is_empty()) {
+        let location = match parse_location(log_line) {
+            Some(location) => location,
+            None => {
+                output.push_str(log_line);
+                output.push('\n');
+                continue;
+            }
+        };
+
+        // Source string 0 is the shader itself, the others are the includes
+        let file = match location.source_number {
+            0 => Some((path, code.to_owned())),
+            number => INCLUDES.get(number - 1).map(|file| {
+                let code = if from_disk {
+                    read_from_disk(file.path).unwrap_or_else(|_| file.code.to_owned())
+                } else {
+                    file.code.to_owned()
+                };
+                (file.path, code)
+            }),
+        };
+        let (file_path, file_code) = match file {
+            Some(file) => file,
+            None => {
+                output.push_str(log_line);
+                output.push('\n');
+                continue;
+            }
+        };
+
+        let message = log_line[location.end..].trim_start_matches(|c| c == ':' || c == ' ');
+        output.push_str(&format!(
+            "{}:{}: {}{}\n",
+            file_path,
+            location.line,
+            &log_line[..location.start],
+            message
+        ));
+        let first = location.line.saturating_sub(ERROR_CONTEXT_LINES).max(1);
+        let last = location.line + ERROR_CONTEXT_LINES;
+        for (index, line) in file_code.lines().enumerate() {
+            let number = index + 1;
+            if number < first || number > last {
+                continue;
+            }
+            let marker = if number == location.line { ">" } else { " " };
+            output.push_str(&format!("{} {:>4} | {}\n", marker, number, line));
+        }
+    }
+    output
+}
+
+struct LogLocation {
+    source_number: usize,
+    line: usize,
+    /// Byte range of the location in the log line
+    start: usize,
+    end: usize,
+}
+
+/// Finds where the log line points to. Drivers write it differently, e.g.
+/// "0(12) : error C0000: ..." on NVIDIA and "0:12(5): error: ..." on Mesa.
+fn parse_location(log_line: &str) -> Option<LogLocation> {
+    let bytes = log_line.as_bytes();
+    let digits_end = |from: usize| {
+        (from..bytes.len())
+            .find(|&i| !bytes[i].is_ascii_digit())
+            .unwrap_or(bytes.len())
+    };
+    let number = |from: usize, to: usize| log_line[from..to].parse::<usize>().ok();
+
+    for start in 0..bytes.len() {
+        if !bytes[start].is_ascii_digit() || (start > 0 && bytes[start - 1].is_ascii_alphanumeric())
+        {
+            continue;
+        }
+        let source_end = digits_end(start);
+        let separator = match bytes.get(source_end) {
+            Some(&separator) if separator == b'(' || separator == b':' => separator,
+            _ => continue,
+        };
+        let line_end = digits_end(source_end + 1);
+        let (source_number, line) =
+            match (number(start, source_end), number(source_end + 1, line_end)) {
+                (Some(source_number), Some(line)) => (source_number, line),
+                _ => continue,
+            };
+        let mut end = line_end;
+        if separator == b'(' {
+            if bytes.get(end) != Some(&b')') {
+                continue;
+            }
+            end += 1;
+        } else if bytes.get(end) == Some(&b'(') {
+            // Column
+            let column_end = digits_end(end + 1);
+            if column_end > end + 1 && bytes.get(column_end) == Some(&b')') {
+                end = column_end + 1;
+            }
+        }
+        return Some(LogLocation {
+            source_number,
+            line,
+            start,
+            end,
+        });
+    }
+    None
+}
+
+/// Replaces the `#include "file"` lines with the code of the files, each file only
+/// once. `#line` directives keep the line numbers in the errors right, with the
+/// source string number being the index of the file in `INCLUDES` plus one.
+fn expand_includes(
+    code: &str,
+    source_number: usize,
+    from_disk: bool,
+    included: &mut Vec<&'static str>,
+) -> Result<String> {
+    let mut output = String::with_capacity(code.len());
+    for (index, line) in code.lines().enumerate() {
+        let name = match include_name(line) {
+            Some(name) => name,
+            None => {
+                output.push_str(line);
+                output.push('\n');
+                continue;
+            }
+        };
+        let (include_index, file) = INCLUDES
+            .iter()
+            .enumerate()
+            .find(|(_, file)| file.path.strip_prefix("src/shaders/") == Some(name))
+            .ok_or_else(|| ShaderError::IncludeNotFound(name.to_owned()))?;
+        if !included.contains(&file.path) {
+            included.push(file.path);
+            let file_code = if from_disk {
+                read_from_disk(file.path)?
+            } else {
+                file.code.to_owned()
+            };
+            output.push_str(&format!("#line 1 {}\n", include_index + 1));
+            output.push_str(&expand_includes(
+                &file_code,
+                include_index + 1,
+                from_disk,
+                included,
+            )?);
+        }
+        // Lines are numbered from 1, so the next one is index + 2
+        output.push_str(&format!("#line {} {}\n", index + 2, source_number));
+    }
+    Ok(output)
+}
+
+/// The file of an `#include "file"` line
+fn include_name(line: &str) -> Option<&str> {
+    let rest = line.trim().strip_prefix("#include")?;
+    rest.trim().strip_prefix('"')?.strip_suffix('"')
+}
+
+fn read_from_disk(path: &str) -> Result<String> {
+    hot_reload::read(path).map_err(|err| ShaderError::ReadError {
+        path: path.to_owned(),
+        source: err,
+    })
+}
+
 fn new_cstring(len: usize) -> CString {
     let buffer: Vec<u8> = vec![0; len];
     unsafe { CString::from_vec_unchecked(buffer) }
@@ -0,0 +1,24 @@
+//! Shaders compiled to SPIR-V by build.rs with the "spirv" feature, which programs load
+//! instead of compiling the GLSL when the driver can take them, see `shader::build`.
+//! The binaries keep their debug names so that uniforms can still be found by name.
+
+use gl::types::*;
+
+// `binary(path)` with the SPIR-V of every shader under src/shaders
+include!(concat!(env!("OUT_DIR"), "/spirv.rs"));
+
+/// Needs OpenGL 4.6 or ARB_gl_spirv
+pub fn supported() -> bool {
+    let mut count: GLint = 0;
+    unsafe {
+        gl::GetIntegerv(gl::NUM_SHADER_BINARY_FORMATS, &mut count);
+    }
+    if count <= 0 {
+        return false;
+    }
+    let mut formats: Vec<GLint> = vec![0; count as usize];
+    unsafe {
+        gl::GetIntegerv(gl::SHADER_BINARY_FORMATS, formats.as_mut_ptr());
+    }
+    formats.contains(&(gl::SHADER_BINARY_FORMAT_SPIR_V as GLint))
+}
@@ -0,0 +1,132 @@
+//! Buffer for data written by the CPU every frame. It stays mapped and is split into
+//! a few regions used in turn, each with a fence, so that writing one frame doesn't
+//! wait for the GPU to finish drawing the previous one.
+
+use std::ptr;
+
+use gl::types::*;
+
+use crate::opengl::objects::Buffer;
+use crate::utils::size_of_slice;
+
+/// Frames the GPU can be behind before `upload` has to wait
+const REGIONS: usize = 3;
+/// How long to wait for a fence before trying again, in nanoseconds
+const FENCE_TIMEOUT: u64 = 1_000_000_000;
+
+pub struct StreamBuffer {
+    buffer: Buffer,
+    /// Start of the mapping, coherent so there's nothing to flush
+    data: *mut u8,
+    region_size: usize,
+    /// The one written last
+    region: usize,
+    /// Signalled when the GPU is done with the region, null if nothing was drawn from it
+    fences: [GLsync; REGIONS],
+    /// For the message when the buffer has to grow
+    name: &'static str,
+}
+
+impl StreamBuffer {
+    /// The buffer grows if a frame needs more than `region_size` bytes
+    pub fn new(name: &'static str, region_size: usize) -> Self {
+        let (buffer, data) = create_mapped(region_size * REGIONS);
+        StreamBuffer {
+            buffer,
+            data,
+            region_size,
+            region: 0,
+            fences: [ptr::null(); REGIONS],
+            name,
+        }
+    }
+
+    /// Replaced when it grows, so the vertex array has to be pointed at it after
+    /// every `upload`
+    pub fn id(&self) -> GLuint {
+        self.buffer.id()
+    }
+
+    /// Copies the data into the next region and returns its offset in bytes
+    pub fn upload<T>(&mut self, data: &[T]) -> usize {
+        let size = size_of_slice(data);
+        if size > self.region_size {
+            self.grow(size);
+        }
+        self.region = (self.region + 1) % REGIONS;
+        self.wait(self.region);
+        let offset = self.region * self.region_size;
+        unsafe {
+            ptr::copy_nonoverlapping(data.as_ptr() as *const u8, self.data.add(offset), size);
+        }
+        offset
+    }
+
+    /// Call after the draw calls which read the data of the last `upload`
+    pub fn fence(&mut self) {
+        let fence = &mut self.fences[self.region];
+        unsafe {
+            if !fence.is_null() {
+                gl::DeleteSync(*fence);
+            }
+            *fence = gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+        }
+    }
+
+    fn wait(&mut self, region: usize) {
+        let fence = &mut self.fences[region];
+        if fence.is_null() {
+            return;
+        }
+        unsafe {
+            loop {
+                let result = gl::ClientWaitSync(*fence, gl::SYNC_FLUSH_COMMANDS_BIT, FENCE_TIMEOUT);
+                if result != gl::TIMEOUT_EXPIRED {
+                    break;
+                }
+            }
+            gl::DeleteSync(*fence);
+        }
+        *fence = ptr::null();
+    }
+
+    fn grow(&mut self, size: usize) {
+        // The old buffer may still be in use
+        for region in 0..REGIONS {
+            self.wait(region);
+        }
+        // Some room so that it doesn't grow again a few frames later
+        self.region_size = size.next_power_of_two();
+        let (buffer, data) = create_mapped(self.region_size * REGIONS);
+        self.buffer = buffer;
+        self.data = data;
+        log!(
+            "Reallocating {} buffer to {}",
+            self.name,
+            self.region_size * REGIONS
+        );
+    }
+}
+
+impl Drop for StreamBuffer {
+    fn drop(&mut self) {
+        for fence in &self.fences {
+            if !fence.is_null() {
+                unsafe {
+                    gl::DeleteSync(*fence);
+                }
+            }
+        }
+        // Deleting the buffer unmaps it
+    }
+}
+
+fn create_mapped(size: usize) -> (Buffer, *mut u8) {
+    let buffer = Buffer::new();
+    let flags = gl::MAP_WRITE_BIT | gl::MAP_PERSISTENT_BIT | gl::MAP_COHERENT_BIT;
+    let data = unsafe {
+        gl::NamedBufferStorage(buffer.id(), size as isize, ptr::null(), flags);
+        gl::MapNamedBufferRange(buffer.id(), 0, size as isize, flags)
+    };
+    (buffer, data as *mut u8)
+}
@@ -0,0 +1,50 @@
+//! Floating origin. Everything is rendered relative to a local origin that follows
+//! the camera, so coordinates stay small and f32 precision doesn't cause jitter far
+//! from the world center. The absolute position of the local origin is kept in f64.
+
+use glam::{DVec3, Vec2, Vec3, Vec3Swizzles};
+
+/// How far the camera can get from the local origin before everything is shifted
+const REBASE_DISTANCE: f32 = 4096.0;
+/// Shifts are whole multiples of this, so textures repeating every power-of-two
+/// world units (terrain layers, the heightmap) stay aligned across a rebase
+const REBASE_GRID: f64 = 1024.0;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct WorldOrigin {
+    /// Absolute world position of the local origin
+    offset: DVec3,
+}
+
+impl WorldOrigin {
+    /// Moves the local origin closer to the camera if it's gone too far. Returns the
+    /// shift that has to be subtracted from every local position.
+    /// Only horizontal: heights are bounded by the terrain and are absolute for fog,
+    /// water and clouds.
+    pub fn rebase(&mut self, camera_position: Vec3) -> Option<Vec3> {
+        if camera_position.xz().abs().max_element() < REBASE_DISTANCE {
+            return None;
+        }
+        let snap = |x: f32| (x as f64 / REBASE_GRID).round() * REBASE_GRID;
+        let shift = DVec3::new(snap(camera_position.x), 0.0, snap(camera_position.z));
+        self.offset += shift;
+        Some(shift.as_vec3())
+    }
+
+    pub fn to_world(self, local: Vec3) -> DVec3 {
+        self.offset + local.as_dvec3()
+    }
+
+    pub fn to_local(self, world: DVec3) -> Vec3 {
+        (world - self.offset).as_vec3()
+    }
+
+    /// Horizontal offset modulo `period`, for things that repeat in world space and
+    /// would otherwise jump on a rebase
+    pub fn wrapped_xz(&self, period: f64) -> Vec2 {
+        Vec2::new(
+            self.offset.x.rem_euclid(period) as f32,
+            self.offset.z.rem_euclid(period) as f32,
+        )
+    }
+}
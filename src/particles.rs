@@ -0,0 +1,506 @@
+//! Particles for dust, waterfalls and weather. The CPU only writes a small spawn
+//! record for each new particle into a ring buffer, everything after that
+//! (motion over the lifetime, size, color) is evaluated in the vertex shader.
+
+use std::f32::consts::TAU;
+use std::mem::size_of;
+use std::ops::Range;
+
+use gl::types::*;
+use glam::{Vec2, Vec3, Vec4};
+use hecs::World;
+use thiserror::Error;
+
+use crate::atmosphere::Atmosphere;
+use crate::opengl::bindings::{self, Sampler, TextureUnit};
+use crate::opengl::empty_vertex_array;
+use crate::opengl::shader::{Program, ShaderError};
+use crate::profiler;
+use crate::scene::{FollowCamera, GlobalTransform};
+use crate::utils::{size_of_slice, XorShift};
+
+/// Particles alive at the same time across all emitters, the oldest ones get replaced
+const MAX_PARTICLES: usize = 65536;
+
+// Must match particles.vert
+const PARTICLES_SSBO_BINDING: u32 = 0;
+const EMITTERS_SSBO_BINDING: u32 = 1;
+
+#[derive(Debug, Error)]
+pub enum ParticlesError {
+    #[error("Particles shader error: {0}")]
+    Shader(#[from] ShaderError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmitterPreset {
+    Dust,
+    Waterfall,
+    Snow,
+    Rain,
+}
+
+impl EmitterPreset {
+    pub const ALL: [EmitterPreset; 4] = [
+        EmitterPreset::Dust,
+        EmitterPreset::Waterfall,
+        EmitterPreset::Snow,
+        EmitterPreset::Rain,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EmitterPreset::Dust => "Dust",
+            EmitterPreset::Waterfall => "Waterfall",
+            EmitterPreset::Snow => "Snow",
+            EmitterPreset::Rain => "Rain",
+        }
+    }
+
+    /// Weather comes down around the camera wherever it goes
+    pub fn follows_camera(&self) -> bool {
+        matches!(self, EmitterPreset::Snow | EmitterPreset::Rain)
+    }
+
+    pub fn settings(&self) -> EmitterSettings {
+        match self {
+            EmitterPreset::Dust => EmitterSettings {
+                rate: 60.0,
+                lifetime: 2.5,
+                direction: Vec3::Y,
+                spread: 1.2,
+                speed: 4.0,
+                speed_curve: [1.0, 0.3, 0.1, 0.0],
+                gravity: 0.0,
+                size_start: 1.0,
+                size_end: 5.0,
+                color_start: Vec4::new(0.45, 0.38, 0.3, 0.5),
+                color_end: Vec4::new(0.45, 0.38, 0.3, 0.0),
+                area: Vec2::ZERO,
+                softness: 1.0,
+            },
+            EmitterPreset::Waterfall => EmitterSettings {
+                rate: 400.0,
+                lifetime: 3.0,
+                direction: Vec3::X,
+                spread: 0.15,
+                speed: 6.0,
+                speed_curve: [1.0, 1.0, 1.0, 1.0],
+                gravity: 9.8,
+                size_start: 0.6,
+                size_end: 2.5,
+                color_start: Vec4::new(0.8, 0.9, 1.0, 0.6),
+                color_end: Vec4::new(0.9, 0.95, 1.0, 0.0),
+                area: Vec2::new(2.0, 0.5),
+                softness: 0.5,
+            },
+            EmitterPreset::Snow => EmitterSettings {
+                rate: 1500.0,
+                lifetime: 12.0,
+                direction: -Vec3::Y,
+                spread: 0.3,
+                speed: 2.0,
+                speed_curve: [1.0, 1.0, 1.0, 1.0],
+                gravity: 0.0,
+                size_start: 0.15,
+                size_end: 0.15,
+                color_start: Vec4::new(1.0, 1.0, 1.0, 0.9),
+                color_end: Vec4::new(1.0, 1.0, 1.0, 0.9),
+                area: Vec2::new(60.0, 60.0),
+                softness: 0.2,
+            },
+            EmitterPreset::Rain => EmitterSettings {
+                rate: 4000.0,
+                lifetime: 1.5,
+                direction: -Vec3::Y,
+                spread: 0.05,
+                speed: 30.0,
+                speed_curve: [1.0, 1.0, 1.0, 1.0],
+                gravity: 0.0,
+                size_start: 0.08,
+                size_end: 0.08,
+                color_start: Vec4::new(0.7, 0.75, 0.8, 0.5),
+                color_end: Vec4::new(0.7, 0.75, 0.8, 0.5),
+                area: Vec2::new(40.0, 40.0),
+                softness: 0.2,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct EmitterSettings {
+    /// Particles per second
+    pub rate: f32,
+    /// Seconds
+    pub lifetime: f32,
+    pub direction: Vec3,
+    /// Half-angle of the cone the particles are emitted in, radians
+    pub spread: f32,
+    /// World units per second at the start
+    pub speed: f32,
+    /// Speed multiplier at 0, 1/3, 2/3 and the end of the lifetime
+    pub speed_curve: [f32; 4],
+    pub gravity: f32,
+    pub size_start: f32,
+    pub size_end: f32,
+    /// Linear, alpha fades the particle out
+    pub color_start: Vec4,
+    pub color_end: Vec4,
+    /// Half-size of the horizontal rectangle particles spawn in, zero for a point
+    pub area: Vec2,
+    /// Distance over which particles fade out in front of geometry
+    pub softness: f32,
+}
+
+/// A component of the scene. The particles come from the entity's position, which is
+/// relative to the camera with `FollowCamera`.
+pub struct Emitter {
+    pub enabled: bool,
+    pub settings: EmitterSettings,
+    /// Fraction of a particle left over from the previous frame
+    spawn_debt: f32,
+    /// Of the settings on the GPU, which the emitter's particles refer to it by
+    slot: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct ParticleRecord {
+    position: Vec3,
+    spawn_time: f32,
+    /// At the start, before the speed curve is applied
+    velocity: Vec3,
+    emitter: u32,
+}
+
+impl ParticleRecord {
+    const DEAD: ParticleRecord = ParticleRecord {
+        position: Vec3::ZERO,
+        spawn_time: f32::NEG_INFINITY,
+        velocity: Vec3::ZERO,
+        emitter: 0,
+    };
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct EmitterBlock {
+    color_start: Vec4,
+    color_end: Vec4,
+    speed_curve: [f32; 4],
+    lifetime: f32,
+    size_start: f32,
+    size_end: f32,
+    gravity: f32,
+    softness: f32,
+    _padding: [f32; 3],
+}
+
+impl From<&EmitterSettings> for EmitterBlock {
+    fn from(settings: &EmitterSettings) -> Self {
+        EmitterBlock {
+            color_start: settings.color_start,
+            color_end: settings.color_end,
+            speed_curve: settings.speed_curve,
+            lifetime: settings.lifetime.max(0.01),
+            size_start: settings.size_start,
+            size_end: settings.size_end,
+            gravity: settings.gravity,
+            softness: settings.softness.max(0.01),
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+pub struct ParticleSystem {
+    /// Kicks up dust where the terrain is being sculpted. Not in the scene, it's part
+    /// of the brush.
+    pub brush_dust: Emitter,
+    pub brush_dust_position: Vec3,
+    /// The settings of every emitter by slot, the brush dust's first
+    blocks: Vec<EmitterBlock>,
+    /// Left by the removed emitters, for the next ones
+    free_slots: Vec<u32>,
+
+    /// CPU copy of the ring buffer, needed to clear it up when emitters are removed
+    particles: Vec<ParticleRecord>,
+    /// Where the next particle goes
+    head: usize,
+    /// How many slots back from `head` may still hold live particles, the ones before
+    /// them are all dead and aren't drawn
+    live: usize,
+    /// Range of `particles` that differs from the buffer
+    dirty: Option<Range<usize>>,
+    rng: XorShift,
+
+    particle_buffer: GLuint,
+    emitter_buffer: GLuint,
+    /// In emitters
+    emitter_capacity: usize,
+    shader: Program,
+    vao: GLuint,
+}
+
+impl ParticleSystem {
+    pub fn new() -> Result<Self, ParticlesError> {
+        let particles = vec![ParticleRecord::DEAD; MAX_PARTICLES];
+        let mut particle_buffer: GLuint = 0;
+        unsafe {
+            gl::CreateBuffers(1, &mut particle_buffer);
+            gl::NamedBufferStorage(
+                particle_buffer,
+                size_of_slice(&particles) as isize,
+                particles.as_ptr() as *const _,
+                gl::DYNAMIC_STORAGE_BIT,
+            );
+        }
+
+        let shader = Program::new()
+            .vertex_shader(shader_file!("particles/particles.vert"))?
+            .fragment_shader(shader_file!("particles/particles.frag"))?
+            .link()?;
+
+        let vao = empty_vertex_array();
+
+        let dust = EmitterPreset::Dust.settings();
+        Ok(ParticleSystem {
+            brush_dust: Emitter {
+                enabled: false,
+                settings: dust,
+                spawn_debt: 0.0,
+                slot: 0,
+            },
+            brush_dust_position: Vec3::ZERO,
+            blocks: vec![EmitterBlock::from(&dust)],
+            free_slots: vec![],
+
+            particles,
+            head: 0,
+            live: 0,
+            dirty: None,
+            rng: XorShift(1),
+
+            particle_buffer,
+            emitter_buffer: 0,
+            emitter_capacity: 0,
+            shader,
+            vao,
+        })
+    }
+
+    /// An emitter with the preset's settings, to be put into the scene
+    pub fn new_emitter(&mut self, preset: EmitterPreset) -> Emitter {
+        let settings = preset.settings();
+        let slot = match self.free_slots.pop() {
+            Some(slot) => slot,
+            None => {
+                self.blocks.push(EmitterBlock::from(&settings));
+                self.blocks.len() as u32 - 1
+            }
+        };
+        Emitter {
+            enabled: true,
+            settings,
+            spawn_debt: 0.0,
+            slot,
+        }
+    }
+
+    /// Existing particles of the emitter taken out of the scene disappear with it
+    pub fn remove_emitter(&mut self, emitter: Emitter) {
+        for particle in &mut self.particles {
+            if particle.emitter == emitter.slot {
+                *particle = ParticleRecord::DEAD;
+            }
+        }
+        self.dirty = Some(0..MAX_PARTICLES);
+        self.free_slots.push(emitter.slot);
+    }
+
+    /// Spawns the particles of the brush dust and the emitters in the world for this
+    /// frame. They come from where the transforms were last propagated to.
+    pub fn update(&mut self, world: &mut World, delta_time: f32, time: f32, camera_position: Vec3) {
+        let placed = world.query_mut::<(&mut Emitter, &GlobalTransform, Option<&FollowCamera>)>();
+        let placed = placed
+            .into_iter()
+            .map(|(_, (emitter, global, follow_camera))| {
+                let position = global.0.w_axis.truncate();
+                match follow_camera {
+                    Some(_) => (emitter, camera_position + position),
+                    None => (emitter, position),
+                }
+            });
+        let brush_dust = (&mut self.brush_dust, self.brush_dust_position);
+        for (emitter, origin) in std::iter::once(brush_dust).chain(placed) {
+            let slot = emitter.slot;
+            self.blocks[slot as usize] = EmitterBlock::from(&emitter.settings);
+            if !emitter.enabled {
+                emitter.spawn_debt = 0.0;
+                continue;
+            }
+            let settings = &emitter.settings;
+            let direction = settings.direction.try_normalize().unwrap_or(Vec3::Y);
+            // Any vector perpendicular to the direction will do
+            let side = direction.any_orthonormal_vector();
+            let up = direction.cross(side);
+
+            // Too many to keep within the buffer would just overwrite each other
+            let count = (emitter.spawn_debt + settings.rate * delta_time).min(MAX_PARTICLES as f32);
+            emitter.spawn_debt = count.fract();
+            for _ in 0..count as usize {
+                let rng = &mut self.rng;
+                let offset =
+                    (Vec2::new(rng.next_f32(), rng.next_f32()) * 2.0 - Vec2::ONE) * settings.area;
+                let position = origin + Vec3::new(offset.x, 0.0, offset.y);
+
+                // Uniform over the spherical cap
+                let cos_angle = 1.0 - rng.next_f32() * (1.0 - settings.spread.cos());
+                let sin_angle = (1.0 - cos_angle * cos_angle).max(0.0).sqrt();
+                let around = rng.next_f32() * TAU;
+                let dir =
+                    direction * cos_angle + (side * around.cos() + up * around.sin()) * sin_angle;
+                let speed = settings.speed * (0.8 + 0.4 * rng.next_f32());
+
+                self.particles[self.head] = ParticleRecord {
+                    position,
+                    // Spread over the frame so they don't come out in clumps
+                    spawn_time: time - rng.next_f32() * delta_time,
+                    velocity: dir * speed,
+                    emitter: slot,
+                };
+                mark_dirty(&mut self.dirty, self.head);
+                self.head = (self.head + 1) % MAX_PARTICLES;
+                self.live = (self.live + 1).min(MAX_PARTICLES);
+            }
+        }
+
+        // Mostly the oldest ones die first, the few outliving them keep the ones after
+        // them drawn a little longer
+        while self.live > 0 {
+            let oldest = &self.particles[(self.head + MAX_PARTICLES - self.live) % MAX_PARTICLES];
+            if oldest.spawn_time + self.blocks[oldest.emitter as usize].lifetime > time {
+                break;
+            }
+            self.live -= 1;
+        }
+    }
+
+    /// Whether there are particles to draw
+    pub fn is_empty(&self) -> bool {
+        self.live == 0
+    }
+
+    /// Moves the particles along with the local origin
+    pub fn shift_origin(&mut self, shift: Vec3) {
+        for particle in &mut self.particles {
+            particle.position -= shift;
+        }
+        self.brush_dust_position -= shift;
+        self.dirty = Some(0..MAX_PARTICLES);
+    }
+
+    /// Draws on top of the scene, which must be in the current framebuffer. The depth
+    /// is sampled to fade out the particles near geometry, so it must be a copy rather
+    /// than the framebuffer's own, see `PostProcess::copy_depth`.
+    pub fn draw(
+        &mut self,
+        scene_depth: GLuint,
+        atmosphere: &Atmosphere,
+    ) -> Result<(), ParticlesError> {
+        self.upload();
+
+        let light = atmosphere.light();
+        let light = light.color * light.direction.y.max(0.0) + 0.35 * atmosphere.sky_tint();
+        self.shader.set_used();
+        self.shader.set_vec3("light", &light)?;
+        let first = (self.head + MAX_PARTICLES - self.live) % MAX_PARTICLES;
+        self.shader.set_u32("first_particle", first as u32)?;
+
+        unsafe {
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::ONE, gl::ONE_MINUS_SRC_ALPHA);
+            gl::DepthMask(gl::FALSE);
+
+            bindings::bind_texture_sampled(
+                TextureUnit::SCENE_DEPTH,
+                scene_depth,
+                Sampler::NearestClamp,
+            );
+            gl::BindBufferBase(
+                gl::SHADER_STORAGE_BUFFER,
+                PARTICLES_SSBO_BINDING,
+                self.particle_buffer,
+            );
+            gl::BindBufferBase(
+                gl::SHADER_STORAGE_BUFFER,
+                EMITTERS_SSBO_BINDING,
+                self.emitter_buffer,
+            );
+            gl::BindVertexArray(self.vao);
+            gl::DrawArraysInstanced(gl::TRIANGLE_STRIP, 0, 4, self.live as i32);
+            profiler::count_draw_call();
+
+            gl::DepthMask(gl::TRUE);
+            gl::Disable(gl::BLEND);
+        }
+        Ok(())
+    }
+
+    /// Sends the new particles and the current emitter settings to the GPU
+    fn upload(&mut self) {
+        if let Some(range) = self.dirty.take() {
+            let data = &self.particles[range.clone()];
+            unsafe {
+                gl::NamedBufferSubData(
+                    self.particle_buffer,
+                    (range.start * size_of::<ParticleRecord>()) as isize,
+                    size_of_slice(data) as isize,
+                    data.as_ptr() as *const _,
+                );
+            }
+        }
+
+        // Few and small, so they're sent every frame to pick up the edits
+        if self.blocks.len() > self.emitter_capacity {
+            self.emitter_capacity = self.blocks.len().next_power_of_two();
+            unsafe {
+                if self.emitter_buffer != 0 {
+                    gl::DeleteBuffers(1, &self.emitter_buffer);
+                }
+                gl::CreateBuffers(1, &mut self.emitter_buffer);
+                gl::NamedBufferStorage(
+                    self.emitter_buffer,
+                    (self.emitter_capacity * size_of::<EmitterBlock>()) as isize,
+                    std::ptr::null(),
+                    gl::DYNAMIC_STORAGE_BIT,
+                );
+            }
+        }
+        unsafe {
+            gl::NamedBufferSubData(
+                self.emitter_buffer,
+                0,
+                size_of_slice(&self.blocks) as isize,
+                self.blocks.as_ptr() as *const _,
+            );
+        }
+    }
+}
+
+fn mark_dirty(dirty: &mut Option<Range<usize>>, index: usize) {
+    *dirty = Some(match dirty.take() {
+        Some(range) => range.start.min(index)..range.end.max(index + 1),
+        None => index..index + 1,
+    });
+}
+
+impl Drop for ParticleSystem {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.particle_buffer);
+            gl::DeleteBuffers(1, &self.emitter_buffer);
+            gl::DeleteVertexArrays(1, &self.vao);
+        }
+    }
+}
@@ -0,0 +1,459 @@
+use gl::types::*;
+use glam::{Mat4, Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::atmosphere::Atmosphere;
+use crate::opengl::bindings::{self, Sampler, TextureUnit};
+use crate::opengl::shader::{Program, ShaderError};
+use crate::opengl::{check_framebuffer, empty_vertex_array, IncompleteFramebuffer};
+use crate::profiler;
+use crate::render_settings::AntiAliasing;
+use crate::temporal::{SoftShadows, Ssao, TemporalAccumulation, TemporalError, TemporalQuality};
+use crate::water::Water;
+
+#[derive(Debug, Error)]
+pub enum PostProcessError {
+    #[error("Post-processing shader error: {0}")]
+    Shader(#[from] ShaderError),
+    #[error(transparent)]
+    Framebuffer(#[from] IncompleteFramebuffer),
+    #[error(transparent)]
+    Temporal(#[from] TemporalError),
+}
+
+/// Crepuscular rays: a radial blur of the sky pixels towards the sun
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GodRays {
+    pub enabled: bool,
+    pub samples: i32,
+    /// How far towards the sun the samples reach, [0, 1]
+    pub density: f32,
+    pub weight: f32,
+    /// Falloff of each subsequent sample
+    pub decay: f32,
+    pub exposure: f32,
+}
+
+impl Default for GodRays {
+    fn default() -> Self {
+        GodRays {
+            enabled: true,
+            samples: 64,
+            density: 0.9,
+            weight: 0.4,
+            decay: 0.96,
+            exposure: 0.25,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PostProcessSettings {
+    // Before the tables, toml can't write plain values after them
+    pub temporal_quality: TemporalQuality,
+    pub god_rays: GodRays,
+    pub ssao: Ssao,
+    pub soft_shadows: SoftShadows,
+}
+
+impl Default for PostProcessSettings {
+    fn default() -> Self {
+        PostProcessSettings {
+            temporal_quality: TemporalQuality::Low,
+            god_rays: GodRays::default(),
+            ssao: Ssao::default(),
+            soft_shadows: SoftShadows::default(),
+        }
+    }
+}
+
+/// The scene is rendered into an offscreen target which is then composited
+/// onto the screen with the post effects applied.
+/// Scene shaders write the sunlight they receive into a second target,
+/// so that shadows and ambient occlusion can be applied here.
+/// The targets can be smaller or larger than the window, see `PostProcess::set_quality`.
+pub struct PostProcess {
+    fbo: GLuint,
+    color: GLuint,
+    sunlight: GLuint,
+    depth: GLuint,
+    /// See `copy_depth`
+    depth_copy: GLuint,
+    shader: Program,
+    vao: GLuint,
+    temporal: TemporalAccumulation,
+    /// The effects are drawn here rather than onto the screen when the image still
+    /// has to be scaled or antialiased on the way there
+    output_fbo: GLuint,
+    /// sRGB like the screen, the size of the other targets
+    output: GLuint,
+    present_shader: Program,
+    window_size: (i32, i32),
+    /// Of the targets relative to the window
+    scale: f32,
+    anti_aliasing: AntiAliasing,
+}
+
+impl PostProcess {
+    pub fn new(width: i32, height: i32) -> Result<Self, PostProcessError> {
+        let mut fbo: GLuint = 0;
+        let mut output_fbo: GLuint = 0;
+        unsafe {
+            gl::CreateFramebuffers(1, &mut fbo);
+            gl::CreateFramebuffers(1, &mut output_fbo);
+        }
+        let (color, sunlight, depth) = create_targets(fbo, width, height)?;
+        let depth_copy = create_depth_copy(width, height);
+        let output = create_output(output_fbo, width, height)?;
+
+        let shader = Program::new()
+            .vertex_shader(shader_file!("post/fullscreen.vert"))?
+            .fragment_shader(shader_file!("post/post.frag"))?
+            .link()?;
+        let present_shader = Program::new()
+            .vertex_shader(shader_file!("post/fullscreen.vert"))?
+            .fragment_shader(shader_file!("post/present.frag"))?
+            .link()?;
+
+        let vao = empty_vertex_array();
+
+        Ok(PostProcess {
+            fbo,
+            color,
+            sunlight,
+            depth,
+            depth_copy,
+            shader,
+            vao,
+            temporal: TemporalAccumulation::new(width, height)?,
+            output_fbo,
+            output,
+            present_shader,
+            window_size: (width, height),
+            scale: 1.0,
+            anti_aliasing: AntiAliasing::Off,
+        })
+    }
+
+    /// Reallocates the targets for the new window size
+    pub fn resize(&mut self, width: i32, height: i32) -> Result<(), PostProcessError> {
+        self.window_size = (width, height);
+        self.reallocate()
+    }
+
+    /// Renders the scene at `scale` times the window size and smooths the edges of
+    /// the image with `anti_aliasing`. The targets are reallocated if the size changes.
+    pub fn set_quality(
+        &mut self,
+        scale: f32,
+        anti_aliasing: AntiAliasing,
+    ) -> Result<(), PostProcessError> {
+        self.anti_aliasing = anti_aliasing;
+        if scale != self.scale {
+            self.scale = scale;
+            self.reallocate()?;
+        }
+        Ok(())
+    }
+
+    /// Size of the targets the scene is rendered into
+    fn size(&self) -> (i32, i32) {
+        let (width, height) = self.window_size;
+        let scaled = |size: i32| ((size as f32 * self.scale).round() as i32).max(1);
+        (scaled(width), scaled(height))
+    }
+
+    fn reallocate(&mut self) -> Result<(), PostProcessError> {
+        let (width, height) = self.size();
+        let (color, sunlight, depth) = create_targets(self.fbo, width, height)?;
+        let output = create_output(self.output_fbo, width, height)?;
+        bindings::delete_textures(&[
+            self.color,
+            self.sunlight,
+            self.depth,
+            self.depth_copy,
+            self.output,
+        ]);
+        self.color = color;
+        self.sunlight = sunlight;
+        self.depth = depth;
+        self.depth_copy = create_depth_copy(width, height);
+        self.output = output;
+        self.temporal.resize(width, height)?;
+        Ok(())
+    }
+
+    /// Whether the effects can go straight onto the screen
+    fn is_direct(&self) -> bool {
+        self.size() == self.window_size && self.anti_aliasing == AntiAliasing::Off
+    }
+
+    /// Moves the accumulated history along with the local origin
+    pub fn shift_origin(&mut self, shift: Vec3) {
+        self.temporal.shift_origin(shift);
+    }
+
+    /// Depth of the scene rendered since `begin`
+    pub fn depth(&self) -> GLuint {
+        self.depth
+    }
+
+    /// Copies the depth rendered so far and returns the copy, for drawing on top of the
+    /// scene while sampling it. Sampling `depth` itself while it's attached to the
+    /// target being drawn into would be a feedback loop.
+    pub fn copy_depth(&self) -> GLuint {
+        let (width, height) = self.size();
+        unsafe {
+            gl::CopyImageSubData(
+                self.depth,
+                gl::TEXTURE_2D,
+                0,
+                0,
+                0,
+                0,
+                self.depth_copy,
+                gl::TEXTURE_2D,
+                0,
+                0,
+                0,
+                0,
+                width,
+                height,
+                1,
+            );
+        }
+        self.depth_copy
+    }
+
+    /// HDR color of the scene rendered since `begin`
+    pub fn color(&self) -> GLuint {
+        self.color
+    }
+
+    /// Direct sunlight of the scene, see `PostProcess::end` for how it's used
+    pub fn sunlight(&self) -> GLuint {
+        self.sunlight
+    }
+
+    /// Accumulated ambient occlusion and soft shadows from the last frame
+    pub fn occlusion(&self) -> GLuint {
+        self.temporal.history()
+    }
+
+    /// Redirects rendering into the offscreen target
+    pub fn begin(&self) {
+        let (width, height) = self.size();
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+            gl::Viewport(0, 0, width, height);
+        }
+    }
+
+    /// Draws the offscreen target onto the screen with the effects applied.
+    /// The viewport is the window's again afterwards.
+    #[allow(clippy::too_many_arguments)]
+    pub fn end(
+        &mut self,
+        settings: &PostProcessSettings,
+        atmosphere: &Atmosphere,
+        water: &Water,
+        skybox_cubemap: GLuint,
+        shadow_map: GLuint,
+        view: &Mat4,
+        proj: &Mat4,
+    ) -> Result<(), PostProcessError> {
+        unsafe {
+            gl::Disable(gl::DEPTH_TEST);
+        }
+        bindings::bind_texture_sampled(TextureUnit::SCENE_DEPTH, self.depth, Sampler::NearestClamp);
+        bindings::bind_texture(TextureUnit::SHADOW_MAP, shadow_map);
+        let occlusion = self.temporal.accumulate(
+            settings.temporal_quality,
+            &settings.ssao,
+            &settings.soft_shadows,
+            view,
+            proj,
+        )?;
+        let direct = self.is_direct();
+        unsafe {
+            let target = if direct { 0 } else { self.output_fbo };
+            gl::BindFramebuffer(gl::FRAMEBUFFER, target);
+        }
+
+        self.shader.set_used();
+        let god_rays = &settings.god_rays;
+        let light = atmosphere.light();
+        let (light_pos, light_visibility) = light_screen_position(light.direction, view, proj);
+        let intensity = if god_rays.enabled {
+            light_visibility
+        } else {
+            0.0
+        };
+        self.shader.set_f32("god_rays.intensity", intensity)?;
+        self.shader.set_vec2("god_rays.light_pos", &light_pos)?;
+        self.shader.set_vec3("god_rays.color", &light.color)?;
+        self.shader.set_i32("god_rays.samples", god_rays.samples)?;
+        self.shader.set_f32("god_rays.density", god_rays.density)?;
+        self.shader.set_f32("god_rays.weight", god_rays.weight)?;
+        self.shader.set_f32("god_rays.decay", god_rays.decay)?;
+        self.shader
+            .set_f32("god_rays.exposure", god_rays.exposure)?;
+
+        self.shader.set_i32("water.enabled", water.enabled as i32)?;
+        self.shader.set_f32("water.level", water.level)?;
+        self.shader
+            .set_vec3("water.color", &(water.color * atmosphere.sky_tint()))?;
+        self.shader.set_f32("water.clarity", water.clarity)?;
+        self.shader
+            .set_f32("water.wave_strength", water.wave_strength)?;
+        let ssr = &water.ssr;
+        self.shader.set_i32("ssr.enabled", ssr.enabled as i32)?;
+        self.shader.set_i32("ssr.max_steps", ssr.max_steps)?;
+        self.shader.set_f32("ssr.step_size", ssr.step_size)?;
+        self.shader.set_f32("ssr.thickness", ssr.thickness)?;
+        self.shader.set_vec3("sky_tint", &atmosphere.sky_tint())?;
+        self.shader.set_vec3("sun.direction", &light.direction)?;
+        self.shader.set_vec3("sun.color", &light.color)?;
+        self.shader
+            .set_f32("shadow_strength", atmosphere.shadow_strength())?;
+        let ssao_strength = if settings.ssao.enabled {
+            settings.ssao.strength
+        } else {
+            0.0
+        };
+        self.shader.set_f32("ssao_strength", ssao_strength)?;
+
+        bindings::bind_texture_sampled(TextureUnit::ALBEDO, self.color, Sampler::LinearClamp);
+        bindings::bind_texture_sampled(TextureUnit::SCENE_DEPTH, self.depth, Sampler::NearestClamp);
+        bindings::bind_texture_sampled(
+            TextureUnit::ENVIRONMENT,
+            skybox_cubemap,
+            Sampler::LinearClamp,
+        );
+        bindings::bind_texture_sampled(TextureUnit::SUNLIGHT, self.sunlight, Sampler::LinearClamp);
+        bindings::bind_texture_sampled(TextureUnit::OCCLUSION, occlusion, Sampler::LinearClamp);
+        unsafe {
+            gl::BindVertexArray(self.vao);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            profiler::count_draw_call();
+        }
+
+        if !direct {
+            self.present()?;
+        }
+        unsafe {
+            let (width, height) = self.window_size;
+            gl::Viewport(0, 0, width, height);
+            gl::Enable(gl::DEPTH_TEST);
+        }
+        Ok(())
+    }
+
+    /// Scales the output to the window, antialiased if that's on
+    fn present(&self) -> Result<(), PostProcessError> {
+        let (width, height) = self.window_size;
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            gl::Viewport(0, 0, width, height);
+        }
+        self.present_shader.set_used();
+        self.present_shader
+            .set_i32("fxaa", (self.anti_aliasing == AntiAliasing::Fxaa) as i32)?;
+        bindings::bind_texture_sampled(TextureUnit::SOURCE, self.output, Sampler::LinearClamp);
+        unsafe {
+            gl::BindVertexArray(self.vao);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            profiler::count_draw_call();
+        }
+        Ok(())
+    }
+}
+
+/// Color, sunlight and depth targets attached to the framebuffer
+fn create_targets(
+    fbo: GLuint,
+    width: i32,
+    height: i32,
+) -> Result<(GLuint, GLuint, GLuint), IncompleteFramebuffer> {
+    let mut color: GLuint = 0;
+    let mut sunlight: GLuint = 0;
+    let mut depth: GLuint = 0;
+    unsafe {
+        for (texture, attachment) in [
+            (&mut color, gl::COLOR_ATTACHMENT0),
+            (&mut sunlight, gl::COLOR_ATTACHMENT1),
+        ] {
+            gl::CreateTextures(gl::TEXTURE_2D, 1, texture);
+            let texture = *texture;
+            gl::TextureStorage2D(texture, 1, gl::RGBA16F, width, height);
+            gl::NamedFramebufferTexture(fbo, attachment, texture, 0);
+        }
+        let draw_buffers = [gl::COLOR_ATTACHMENT0, gl::COLOR_ATTACHMENT1];
+        gl::NamedFramebufferDrawBuffers(fbo, draw_buffers.len() as i32, draw_buffers.as_ptr());
+
+        // A texture rather than a renderbuffer because the effects need to know
+        // where the sky is
+        gl::CreateTextures(gl::TEXTURE_2D, 1, &mut depth);
+        gl::TextureStorage2D(depth, 1, gl::DEPTH_COMPONENT24, width, height);
+        gl::NamedFramebufferTexture(fbo, gl::DEPTH_ATTACHMENT, depth, 0);
+    }
+    check_framebuffer(fbo, "Post-processing")?;
+    Ok((color, sunlight, depth))
+}
+
+/// Same format as the depth target so that it can be copied into
+fn create_depth_copy(width: i32, height: i32) -> GLuint {
+    let mut depth: GLuint = 0;
+    unsafe {
+        gl::CreateTextures(gl::TEXTURE_2D, 1, &mut depth);
+        gl::TextureStorage2D(depth, 1, gl::DEPTH_COMPONENT24, width, height);
+    }
+    depth
+}
+
+/// sRGB color target the effects are drawn into before `PostProcess::present`
+fn create_output(fbo: GLuint, width: i32, height: i32) -> Result<GLuint, IncompleteFramebuffer> {
+    let mut output: GLuint = 0;
+    unsafe {
+        gl::CreateTextures(gl::TEXTURE_2D, 1, &mut output);
+        gl::TextureStorage2D(output, 1, gl::SRGB8_ALPHA8, width, height);
+        gl::NamedFramebufferTexture(fbo, gl::COLOR_ATTACHMENT0, output, 0);
+    }
+    check_framebuffer(fbo, "Post-processing output")?;
+    Ok(output)
+}
+
+impl Drop for PostProcess {
+    fn drop(&mut self) {
+        bindings::delete_textures(&[
+            self.color,
+            self.sunlight,
+            self.depth,
+            self.depth_copy,
+            self.output,
+        ]);
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.fbo);
+            gl::DeleteFramebuffers(1, &self.output_fbo);
+            gl::DeleteVertexArrays(1, &self.vao);
+        }
+    }
+}
+
+/// Returns the light's position in [0, 1] screen coordinates and how visible it is:
+/// 0 when behind the camera, fading out as it leaves the screen
+fn light_screen_position(direction: Vec3, view: &Mat4, proj: &Mat4) -> (Vec2, f32) {
+    // The light is infinitely far, so ignore the camera translation
+    let rotation = Mat4::from_mat3(glam::Mat3::from_mat4(*view));
+    let clip = *proj * rotation * direction.extend(0.0);
+    if clip.w <= 0.0 {
+        return (Vec2::ZERO, 0.0);
+    }
+    let ndc = Vec2::new(clip.x, clip.y) / clip.w;
+    let distance_off_screen = (ndc.abs().max_element() - 1.0).max(0.0);
+    let visibility = (1.0 - distance_off_screen).max(0.0);
+    (ndc * 0.5 + Vec2::splat(0.5), visibility)
+}
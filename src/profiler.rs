@@ -0,0 +1,275 @@
+//! GPU pass timings. Passes are wrapped in `scope` guards wherever they are drawn,
+//! which put timestamp queries around them. The results are read a few frames later,
+//! when the GPU has caught up, so measuring never stalls the pipeline.
+//!
+//! CPU work is measured the same way with `cpu_scope`, and every draw call reports
+//! itself with `count_draw_call`. Both are known by the end of the frame.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::time::Instant;
+
+use gl::types::*;
+
+/// Frame times kept for the graph
+const FRAME_HISTORY: usize = 240;
+/// Frames whose queries are still waiting for results, older ones are dropped
+const MAX_FRAMES_IN_FLIGHT: usize = 4;
+
+#[derive(Debug)]
+struct ScopeRecord {
+    name: &'static str,
+    /// Number of scopes this one is nested in
+    depth: usize,
+    start: GLuint,
+    /// None until the scope ends
+    end: Option<GLuint>,
+}
+
+#[derive(Debug)]
+struct CpuScopeRecord {
+    name: &'static str,
+    depth: usize,
+    start: Instant,
+    /// None until the scope ends
+    milliseconds: Option<f32>,
+}
+
+/// Scopes recorded during the current frame
+#[derive(Debug, Default)]
+struct Recorder {
+    enabled: bool,
+    scopes: Vec<ScopeRecord>,
+    depth: usize,
+    cpu_scopes: Vec<CpuScopeRecord>,
+    cpu_depth: usize,
+    draw_calls: u32,
+    /// Queries whose results have been read
+    free_queries: Vec<GLuint>,
+}
+
+impl Recorder {
+    fn timestamp(&mut self) -> GLuint {
+        let query = self.free_queries.pop().unwrap_or_else(|| {
+            let mut query: GLuint = 0;
+            unsafe {
+                gl::GenQueries(1, &mut query);
+            }
+            query
+        });
+        unsafe {
+            gl::QueryCounter(query, gl::TIMESTAMP);
+        }
+        query
+    }
+}
+
+thread_local! {
+    static RECORDER: RefCell<Recorder> = RefCell::new(Recorder::default());
+}
+
+/// Ends the scope when dropped
+#[must_use = "the scope ends when the guard is dropped"]
+pub struct ScopeGuard {
+    /// Index into the recorded scopes, None when profiling is off
+    index: Option<usize>,
+}
+
+/// Measures the GPU time of everything drawn until the guard is dropped
+pub fn scope(name: &'static str) -> ScopeGuard {
+    RECORDER.with(|recorder| {
+        let mut recorder = recorder.borrow_mut();
+        if !recorder.enabled {
+            return ScopeGuard { index: None };
+        }
+        let start = recorder.timestamp();
+        let depth = recorder.depth;
+        recorder.scopes.push(ScopeRecord {
+            name,
+            depth,
+            start,
+            end: None,
+        });
+        recorder.depth += 1;
+        ScopeGuard {
+            index: Some(recorder.scopes.len() - 1),
+        }
+    })
+}
+
+impl Drop for ScopeGuard {
+    fn drop(&mut self) {
+        if let Some(index) = self.index {
+            RECORDER.with(|recorder| {
+                let mut recorder = recorder.borrow_mut();
+                let end = recorder.timestamp();
+                recorder.scopes[index].end = Some(end);
+                recorder.depth -= 1;
+            });
+        }
+    }
+}
+
+/// Ends the CPU scope when dropped
+#[must_use = "the scope ends when the guard is dropped"]
+pub struct CpuScopeGuard {
+    index: Option<usize>,
+}
+
+/// Measures the CPU time spent until the guard is dropped
+pub fn cpu_scope(name: &'static str) -> CpuScopeGuard {
+    RECORDER.with(|recorder| {
+        let mut recorder = recorder.borrow_mut();
+        if !recorder.enabled {
+            return CpuScopeGuard { index: None };
+        }
+        let depth = recorder.cpu_depth;
+        recorder.cpu_scopes.push(CpuScopeRecord {
+            name,
+            depth,
+            start: Instant::now(),
+            milliseconds: None,
+        });
+        recorder.cpu_depth += 1;
+        CpuScopeGuard {
+            index: Some(recorder.cpu_scopes.len() - 1),
+        }
+    })
+}
+
+impl Drop for CpuScopeGuard {
+    fn drop(&mut self) {
+        if let Some(index) = self.index {
+            RECORDER.with(|recorder| {
+                let mut recorder = recorder.borrow_mut();
+                let scope = &mut recorder.cpu_scopes[index];
+                scope.milliseconds = Some(scope.start.elapsed().as_secs_f32() * 1000.0);
+                recorder.cpu_depth -= 1;
+            });
+        }
+    }
+}
+
+/// Call after every glDraw*
+pub fn count_draw_call() {
+    RECORDER.with(|recorder| recorder.borrow_mut().draw_calls += 1);
+}
+
+#[derive(Debug, Clone)]
+pub struct PassTiming {
+    pub name: &'static str,
+    pub depth: usize,
+    pub milliseconds: f32,
+}
+
+/// Collects the results of the scopes and keeps the frame times
+#[derive(Debug, Default)]
+pub struct Profiler {
+    in_flight: VecDeque<Vec<ScopeRecord>>,
+    /// Of the latest frame with results, in the order the passes started
+    pub passes: Vec<PassTiming>,
+    /// Of the previous frame, in the order the scopes started
+    pub cpu_timings: Vec<PassTiming>,
+    /// Made during the previous frame
+    pub draw_calls: u32,
+    /// CPU time between frames in milliseconds, oldest first
+    pub frame_times: VecDeque<f32>,
+}
+
+impl Profiler {
+    /// Call at the start of every frame. Scopes are only recorded while `enabled`.
+    pub fn begin_frame(&mut self, enabled: bool, delta_time: f32) {
+        if self.frame_times.len() == FRAME_HISTORY {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(delta_time * 1000.0);
+
+        let (finished, cpu_scopes) = RECORDER.with(|recorder| {
+            let mut recorder = recorder.borrow_mut();
+            recorder.enabled = enabled;
+            recorder.depth = 0;
+            recorder.cpu_depth = 0;
+            self.draw_calls = std::mem::take(&mut recorder.draw_calls);
+            (
+                std::mem::take(&mut recorder.scopes),
+                std::mem::take(&mut recorder.cpu_scopes),
+            )
+        });
+        self.cpu_timings = cpu_scopes
+            .into_iter()
+            .filter_map(|scope| {
+                Some(PassTiming {
+                    name: scope.name,
+                    depth: scope.depth,
+                    milliseconds: scope.milliseconds?,
+                })
+            })
+            .collect();
+        if !finished.is_empty() {
+            self.in_flight.push_back(finished);
+        }
+        if self.in_flight.len() > MAX_FRAMES_IN_FLIGHT {
+            let dropped = self.in_flight.pop_front().unwrap_or_default();
+            free_queries(dropped);
+        }
+
+        // Frames finish in order, so only the oldest one needs checking
+        while let Some(frame) = self.in_flight.front() {
+            let available = frame.iter().filter_map(|scope| scope.end).all(|query| {
+                let mut available: GLint = 0;
+                unsafe {
+                    gl::GetQueryObjectiv(query, gl::QUERY_RESULT_AVAILABLE, &mut available);
+                }
+                available != 0
+            });
+            if !available {
+                break;
+            }
+
+            let frame = self.in_flight.pop_front().unwrap_or_default();
+            self.passes = frame
+                .iter()
+                .filter_map(|scope| {
+                    let end = scope.end?;
+                    let nanoseconds = query_result(end).saturating_sub(query_result(scope.start));
+                    Some(PassTiming {
+                        name: scope.name,
+                        depth: scope.depth,
+                        milliseconds: nanoseconds as f32 / 1e6,
+                    })
+                })
+                .collect();
+            free_queries(frame);
+        }
+    }
+
+    /// Averaged over the last second or so to be readable
+    pub fn fps(&self) -> f32 {
+        let frames = self.frame_times.iter().rev().take(60);
+        let count = frames.len();
+        let total: f32 = frames.sum();
+        if total > 0.0 {
+            count as f32 * 1000.0 / total
+        } else {
+            0.0
+        }
+    }
+}
+
+fn query_result(query: GLuint) -> u64 {
+    let mut result: GLuint64 = 0;
+    unsafe {
+        gl::GetQueryObjectui64v(query, gl::QUERY_RESULT, &mut result);
+    }
+    result
+}
+
+fn free_queries(frame: Vec<ScopeRecord>) {
+    RECORDER.with(|recorder| {
+        let mut recorder = recorder.borrow_mut();
+        for scope in frame {
+            recorder.free_queries.push(scope.start);
+            recorder.free_queries.extend(scope.end);
+        }
+    });
+}
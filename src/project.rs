@@ -0,0 +1,238 @@
+//! A project is a directory with what's been made in the editor. The heightmap, the
+//! splatmap and the bricks have their own files, and project.toml has the rest: the
+//! terrain layers, the brush, the camera and its bookmarks, the placed objects and the
+//! lighting. The layer images and the models stay where they were imported from and
+//! are kept by path. Positions are saved absolute, not relative to the `WorldOrigin`.
+
+use std::fs;
+use std::path::Path;
+
+use glam::{DVec3, Quat, Vec3, Vec4};
+use serde::{Deserialize, Serialize};
+
+use crate::atmosphere::{Atmosphere, Clouds, Fog, Sky, TimeOfDay};
+use crate::splat::{LayerMap, SplatLayer};
+use crate::terrain::BrushSettings;
+use crate::Result;
+
+pub const MANIFEST: &str = "project.toml";
+pub const HEIGHTMAP: &str = "heightmap.png";
+pub const SPLATMAP: &str = "splatmap.png";
+pub const BRICKS: &str = "bricks.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Project {
+    /// Where the editor camera was when the project was saved
+    pub camera: Option<Bookmark>,
+    // Empty lists are left out, toml writes them as plain values which can't go after
+    // the camera table
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub bookmarks: Vec<Bookmark>,
+    pub brush: BrushSettings,
+    /// In the order they're blended in
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub layers: Vec<SavedLayer>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub objects: Vec<SavedObject>,
+    pub lighting: Lighting,
+}
+
+impl Project {
+    pub fn read(dir: &Path) -> Result<Self> {
+        let path = dir.join(MANIFEST);
+        let text = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?)
+    }
+
+    /// Writes the manifest to the path on a worker, the directory has to be there
+    pub fn write_job(
+        self,
+        path: String,
+    ) -> impl FnOnce() -> std::result::Result<(), String> + Send + 'static {
+        move || {
+            let text = toml::to_string_pretty(&self).map_err(|e| e.to_string())?;
+            fs::write(&path, text).map_err(|e| e.to_string())
+        }
+    }
+}
+
+/// Path of one of the files in the project, with forward slashes like the other
+/// paths in config.json
+pub fn file(dir: &str, name: &str) -> String {
+    Path::new(dir)
+        .join(name)
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+/// A place to jump the camera back to
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Bookmark {
+    pub name: String,
+    pub position: DVec3,
+    pub direction: Vec3,
+}
+
+/// Everything in `SplatLayer` but where it is in the texture arrays
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SavedLayer {
+    pub name: String,
+    pub tiling: f32,
+    pub triplanar: bool,
+    pub albedo: Vec4,
+    pub roughness: f32,
+    pub metallic: f32,
+    /// The images are imported again on load. Separate fields rather than an array,
+    /// toml has no way to write a missing one in between.
+    pub albedo_map: Option<String>,
+    pub normal_map: Option<String>,
+    pub roughness_map: Option<String>,
+}
+
+impl SavedLayer {
+    pub fn of(layer: &SplatLayer) -> Self {
+        SavedLayer {
+            name: layer.name.clone(),
+            tiling: layer.tiling,
+            triplanar: layer.triplanar,
+            albedo: layer.params.albedo,
+            roughness: layer.params.roughness,
+            metallic: layer.params.metallic,
+            albedo_map: layer.path(LayerMap::Albedo).map(str::to_owned),
+            normal_map: layer.path(LayerMap::Normal).map(str::to_owned),
+            roughness_map: layer.path(LayerMap::Roughness).map(str::to_owned),
+        }
+    }
+
+    pub fn path(&self, map: LayerMap) -> Option<&str> {
+        match map {
+            LayerMap::Albedo => self.albedo_map.as_deref(),
+            LayerMap::Normal => self.normal_map.as_deref(),
+            LayerMap::Roughness => self.roughness_map.as_deref(),
+        }
+    }
+
+    /// All but the paths, which are set once the images are imported
+    pub fn apply(&self, layer: &mut SplatLayer) {
+        layer.name = self.name.clone();
+        layer.tiling = self.tiling;
+        layer.triplanar = self.triplanar;
+        layer.params.albedo = self.albedo;
+        layer.params.roughness = self.roughness;
+        layer.params.metallic = self.metallic;
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SavedObject {
+    pub name: String,
+    /// Path of the model file
+    pub model: String,
+    pub position: DVec3,
+    pub orientation: Quat,
+    pub visible: bool,
+}
+
+/// The parts of `Atmosphere` set in the editor
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct Lighting {
+    pub sun_enabled: bool,
+    pub time_of_day: TimeOfDay,
+    pub fog: Fog,
+    pub sky: Sky,
+    pub clouds: Clouds,
+}
+
+impl Default for Lighting {
+    fn default() -> Self {
+        Lighting::of(&Atmosphere::default())
+    }
+}
+
+impl Lighting {
+    pub fn of(atmosphere: &Atmosphere) -> Self {
+        Lighting {
+            sun_enabled: atmosphere.sun_enabled,
+            time_of_day: atmosphere.time_of_day,
+            fog: atmosphere.fog,
+            sky: atmosphere.sky,
+            clouds: atmosphere.clouds,
+        }
+    }
+
+    pub fn apply(&self, atmosphere: &mut Atmosphere) {
+        atmosphere.time_of_day = self.time_of_day;
+        atmosphere.sun_enabled = self.sun_enabled;
+        atmosphere.fog = self.fog;
+        atmosphere.sky = self.sky;
+        atmosphere.clouds = self.clouds;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project() -> Project {
+        let bookmark = |name: &str, x| Bookmark {
+            name: name.to_owned(),
+            position: DVec3::new(x, 20.0, 1e7),
+            direction: Vec3::new(0.0, -0.6, 0.8),
+        };
+        Project {
+            camera: Some(bookmark("Camera", 10.5)),
+            bookmarks: vec![bookmark("Lake", -300.0), bookmark("Hill", 4096.25)],
+            brush: BrushSettings::default(),
+            layers: vec![SavedLayer {
+                name: "Grass".to_owned(),
+                tiling: 8.0,
+                triplanar: false,
+                albedo: Vec4::new(0.3, 0.5, 0.2, 1.0),
+                roughness: 0.9,
+                metallic: 0.0,
+                albedo_map: Some("textures/grass.png".to_owned()),
+                normal_map: None,
+                roughness_map: Some("textures/grass_roughness.png".to_owned()),
+            }],
+            objects: vec![SavedObject {
+                name: "Tree".to_owned(),
+                model: "models/tree.glb".to_owned(),
+                position: DVec3::new(1e6, 3.0, -2.5),
+                orientation: Quat::from_rotation_y(1.0),
+                visible: false,
+            }],
+            lighting: Lighting::default(),
+        }
+    }
+
+    #[test]
+    fn manifest_round_trips() {
+        let dir = std::env::temp_dir().join(format!("project-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(MANIFEST).to_string_lossy().into_owned();
+        project().write_job(path.clone())().unwrap();
+        let read = Project::read(&dir);
+        let written = fs::read_to_string(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let read = read.unwrap();
+        assert_eq!(toml::to_string_pretty(&read).unwrap(), written);
+        assert_eq!(read.bookmarks.len(), 2);
+        assert_eq!(read.layers[0].path(LayerMap::Normal), None);
+        assert_eq!(
+            read.layers[0].path(LayerMap::Roughness),
+            Some("textures/grass_roughness.png")
+        );
+        assert_eq!(read.objects[0].position, DVec3::new(1e6, 3.0, -2.5));
+    }
+
+    #[test]
+    fn empty_project_round_trips() {
+        let text = toml::to_string_pretty(&Project::default()).unwrap();
+        let read: Project = toml::from_str(&text).unwrap();
+        assert!(read.camera.is_none());
+        assert!(read.bookmarks.is_empty() && read.layers.is_empty() && read.objects.is_empty());
+    }
+}
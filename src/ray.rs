@@ -96,6 +96,10 @@ impl Ray {
         })
     }
 
+    pub fn origin(&self) -> Vec3 {
+        self.origin
+    }
+
     pub fn get_point_at(&self, t: f32) -> Vec3 {
         self.origin + self.direction * t
     }
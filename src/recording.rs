@@ -0,0 +1,300 @@
+//! Records the input of the editor to a file and plays it back, to reproduce bugs and
+//! to profile the same session again after a change. Each frame is a line of JSON
+//! with its time, its duration, the gamepad axes and the events which came in during
+//! it. Playback uses the recorded durations rather than the clock, so the same frames
+//! get the same input whatever the frame rate. Things finished by the worker threads,
+//! like loading images, may still arrive on different frames.
+
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use glutin::dpi::PhysicalPosition;
+use glutin::event::{
+    DeviceEvent, DeviceId, ElementState, Event, Force, KeyboardInput, ModifiersState, MouseButton,
+    MouseScrollDelta, Touch, TouchPhase, VirtualKeyCode, WindowEvent,
+};
+use glutin::window::WindowId;
+use serde::{Deserialize, Serialize};
+
+use crate::input::{Input, InputAxis};
+use crate::Result;
+
+/// From the `record` and `replay` commands, see `cli`
+pub enum RecordingMode {
+    Record(PathBuf),
+    Replay(PathBuf),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecordedFrame {
+    /// Seconds since the start, see `Input::time`
+    pub time: f32,
+    /// Seconds since the previous frame
+    pub delta_time: f32,
+    /// In the order of `InputAxis::ALL`
+    analog: Vec<f32>,
+    events: Vec<RecordedEvent>,
+}
+
+impl RecordedFrame {
+    /// Puts the recorded gamepad axes into the input instead of the gamepads
+    pub fn apply_analog(&self, input: &mut Input) {
+        for (&axis, &value) in InputAxis::ALL.iter().zip(&self.analog) {
+            input.set_analog(axis, value);
+        }
+    }
+}
+
+/// The events the editor reacts to, without the parts that can't be saved
+#[derive(Serialize, Deserialize, Debug, Clone)]
+enum RecordedEvent {
+    CursorMoved {
+        x: f64,
+        y: f64,
+    },
+    MouseInput {
+        button: MouseButton,
+        state: ElementState,
+    },
+    MouseWheel {
+        delta: MouseScrollDelta,
+    },
+    Key {
+        scancode: u32,
+        state: ElementState,
+        key: Option<VirtualKeyCode>,
+    },
+    Modifiers(ModifiersState),
+    Character(char),
+    Touch {
+        phase: TouchPhase,
+        x: f64,
+        y: f64,
+        /// Normalized
+        force: Option<f64>,
+        /// Of the pen in radians, where it's reported
+        #[serde(default)]
+        altitude: Option<f64>,
+        id: u64,
+    },
+    Focused(bool),
+    MouseMotion {
+        x: f64,
+        y: f64,
+    },
+    DeviceWheel {
+        delta: MouseScrollDelta,
+    },
+}
+
+impl RecordedEvent {
+    /// None if the event isn't input, see `is_input`
+    fn from_event(event: &Event<()>) -> Option<Self> {
+        let recorded = match event {
+            Event::WindowEvent { event, .. } => match *event {
+                WindowEvent::CursorMoved { position, .. } => RecordedEvent::CursorMoved {
+                    x: position.x,
+                    y: position.y,
+                },
+                WindowEvent::MouseInput { button, state, .. } => {
+                    RecordedEvent::MouseInput { button, state }
+                }
+                WindowEvent::MouseWheel { delta, .. } => RecordedEvent::MouseWheel { delta },
+                WindowEvent::KeyboardInput { input, .. } => RecordedEvent::Key {
+                    scancode: input.scancode,
+                    state: input.state,
+                    key: input.virtual_keycode,
+                },
+                WindowEvent::ModifiersChanged(state) => RecordedEvent::Modifiers(state),
+                WindowEvent::ReceivedCharacter(c) => RecordedEvent::Character(c),
+                WindowEvent::Touch(touch) => RecordedEvent::Touch {
+                    phase: touch.phase,
+                    x: touch.location.x,
+                    y: touch.location.y,
+                    force: touch.force.map(|force| force.normalized()),
+                    altitude: match touch.force {
+                        Some(Force::Calibrated { altitude_angle, .. }) => altitude_angle,
+                        _ => None,
+                    },
+                    id: touch.id,
+                },
+                WindowEvent::Focused(focused) => RecordedEvent::Focused(focused),
+                _ => return None,
+            },
+            Event::DeviceEvent { event, .. } => match *event {
+                DeviceEvent::MouseMotion { delta: (x, y) } => RecordedEvent::MouseMotion { x, y },
+                DeviceEvent::MouseWheel { delta } => RecordedEvent::DeviceWheel { delta },
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(recorded)
+    }
+
+    // The modifiers inside the events are deprecated, ModifiersChanged is used instead
+    #[allow(deprecated)]
+    fn to_event(&self, window_id: WindowId) -> Event<'static, ()> {
+        // Nothing looks at which device it was
+        let device_id = unsafe { DeviceId::dummy() };
+        let modifiers = ModifiersState::empty();
+        let window_event = |event| Event::WindowEvent { window_id, event };
+        match *self {
+            RecordedEvent::CursorMoved { x, y } => window_event(WindowEvent::CursorMoved {
+                device_id,
+                position: PhysicalPosition::new(x, y),
+                modifiers,
+            }),
+            RecordedEvent::MouseInput { button, state } => window_event(WindowEvent::MouseInput {
+                device_id,
+                state,
+                button,
+                modifiers,
+            }),
+            RecordedEvent::MouseWheel { delta } => window_event(WindowEvent::MouseWheel {
+                device_id,
+                delta,
+                phase: TouchPhase::Moved,
+                modifiers,
+            }),
+            RecordedEvent::Key {
+                scancode,
+                state,
+                key,
+            } => window_event(WindowEvent::KeyboardInput {
+                device_id,
+                input: KeyboardInput {
+                    scancode,
+                    state,
+                    virtual_keycode: key,
+                    modifiers,
+                },
+                is_synthetic: false,
+            }),
+            RecordedEvent::Modifiers(state) => window_event(WindowEvent::ModifiersChanged(state)),
+            RecordedEvent::Character(c) => window_event(WindowEvent::ReceivedCharacter(c)),
+            RecordedEvent::Touch {
+                phase,
+                x,
+                y,
+                force,
+                altitude,
+                id,
+            } => window_event(WindowEvent::Touch(Touch {
+                device_id,
+                phase,
+                location: PhysicalPosition::new(x, y),
+                force: force.map(|force| match altitude {
+                    // Calibrated only to carry the altitude, the force is already normalized
+                    Some(altitude) => Force::Calibrated {
+                        force,
+                        max_possible_force: 1.0,
+                        altitude_angle: Some(altitude),
+                    },
+                    None => Force::Normalized(force),
+                }),
+                id,
+            })),
+            RecordedEvent::Focused(focused) => window_event(WindowEvent::Focused(focused)),
+            RecordedEvent::MouseMotion { x, y } => Event::DeviceEvent {
+                device_id,
+                event: DeviceEvent::MouseMotion { delta: (x, y) },
+            },
+            RecordedEvent::DeviceWheel { delta } => Event::DeviceEvent {
+                device_id,
+                event: DeviceEvent::MouseWheel { delta },
+            },
+        }
+    }
+}
+
+/// Whether the event is recorded, and ignored while playing back
+pub fn is_input(event: &Event<()>) -> bool {
+    RecordedEvent::from_event(event).is_some()
+}
+
+pub struct InputRecorder {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    events: Vec<RecordedEvent>,
+}
+
+impl InputRecorder {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(InputRecorder {
+            path: path.to_owned(),
+            writer: BufWriter::new(file),
+            events: vec![],
+        })
+    }
+
+    pub fn record(&mut self, event: &Event<()>) {
+        if let Some(event) = RecordedEvent::from_event(event) {
+            self.events.push(event);
+        }
+    }
+
+    /// Writes the frame with the events recorded since the last one. Every frame is
+    /// flushed, so the recording survives a crash.
+    pub fn end_frame(&mut self, input: &Input, delta_time: f32) {
+        let frame = RecordedFrame {
+            time: input.time,
+            delta_time,
+            analog: InputAxis::ALL
+                .iter()
+                .map(|&axis| input.analog(axis))
+                .collect(),
+            events: std::mem::take(&mut self.events),
+        };
+        let result = serde_json::to_writer(&mut self.writer, &frame)
+            .map_err(std::io::Error::from)
+            .and_then(|_| writeln!(self.writer))
+            .and_then(|_| self.writer.flush());
+        if let Err(err) = result {
+            log!("Failed to record to {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+pub struct InputPlayer {
+    frames: VecDeque<RecordedFrame>,
+    /// The one being played
+    frame: Option<RecordedFrame>,
+}
+
+impl InputPlayer {
+    pub fn open(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let frames = text
+            .lines()
+            .enumerate()
+            .map(|(index, line)| {
+                serde_json::from_str(line)
+                    .map_err(|e| format!("{}:{}: {}", path.display(), index + 1, e))
+            })
+            .collect::<std::result::Result<_, _>>()?;
+        Ok(InputPlayer {
+            frames,
+            frame: None,
+        })
+    }
+
+    /// Moves on to the next frame and returns its events, None once all are played
+    pub fn advance(&mut self, window_id: WindowId) -> Option<Vec<Event<'static, ()>>> {
+        self.frame = self.frames.pop_front();
+        let frame = self.frame.as_ref()?;
+        Some(
+            frame
+                .events
+                .iter()
+                .map(|event| event.to_event(window_id))
+                .collect(),
+        )
+    }
+
+    pub fn frame(&self) -> Option<&RecordedFrame> {
+        self.frame.as_ref()
+    }
+}
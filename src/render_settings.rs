@@ -0,0 +1,57 @@
+//! How expensive the frame is: the resolution the scene is rendered at, antialiasing,
+//! the shadow map and texture filtering. Changed in the Graphics window and applied
+//! right away, each by recreating what depends on it, see `Game::apply_render_settings`.
+
+use serde::{Deserialize, Serialize};
+
+/// The sizes offered for the terrain shadow map
+pub const SHADOW_MAP_SIZES: [i32; 5] = [512, 1024, 2048, 4096, 8192];
+/// Fraction of the window size the scene can be rendered at
+pub const RENDER_SCALE_RANGE: std::ops::RangeInclusive<f32> = 0.5..=2.0;
+/// Anisotropic filtering of the surface textures, 1 is off. Drivers can have a lower
+/// limit, see `bindings::set_anisotropy`.
+pub const ANISOTROPY_RANGE: std::ops::RangeInclusive<f32> = 1.0..=16.0;
+
+/// Multisampling isn't offered, the post effects read the depth and the sunlight of
+/// each pixel, so the edges are smoothed after them instead
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AntiAliasing {
+    Off,
+    /// Fast approximate antialiasing on the final image
+    Fxaa,
+}
+
+impl AntiAliasing {
+    pub const ALL: [AntiAliasing; 2] = [AntiAliasing::Off, AntiAliasing::Fxaa];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AntiAliasing::Off => "Off",
+            AntiAliasing::Fxaa => "FXAA",
+        }
+    }
+}
+
+/// Kept in `Settings`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct RenderSettings {
+    pub anti_aliasing: AntiAliasing,
+    /// Size of the scene targets relative to the window, the image is scaled to fit.
+    /// Below 1 is faster, above 1 supersamples.
+    pub render_scale: f32,
+    /// Texels along each side of the terrain shadow map
+    pub shadow_map_size: i32,
+    pub anisotropy: f32,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            anti_aliasing: AntiAliasing::Off,
+            render_scale: 1.0,
+            shadow_map_size: 2048,
+            anisotropy: 8.0,
+        }
+    }
+}
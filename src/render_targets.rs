@@ -0,0 +1,124 @@
+//! Previews of textures the renderer draws into, shown in the editor for debugging.
+//! Depth and single-channel targets can't be shown by the GUI as they are, so the
+//! selected one is first converted into a color texture.
+
+use gl::types::*;
+
+use crate::opengl::bindings::{self, Sampler, TextureUnit};
+use crate::opengl::shader::Program;
+use crate::opengl::{check_framebuffer, empty_vertex_array, SavedTarget};
+use crate::profiler;
+use crate::Result;
+
+const PREVIEW_SIZE: i32 = 512;
+/// Distance that is shown as white in perspective depth previews
+const MAX_PREVIEW_DISTANCE: f32 = 2000.0;
+
+/// How to turn the texels into colors, must match preview.frag
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreviewMode {
+    Color = 0,
+    /// Grayscale from the first channel
+    Red = 1,
+    /// Depth from an orthographic projection
+    Depth = 2,
+    /// Depth from the camera projection, linearized
+    PerspectiveDepth = 3,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RenderTarget {
+    pub name: &'static str,
+    pub texture: GLuint,
+    pub mode: PreviewMode,
+}
+
+pub struct RenderTargetViewer {
+    shader: Program,
+    vao: GLuint,
+    fbo: GLuint,
+    preview: GLuint,
+    /// Width over height of the last target drawn into the preview
+    aspect_ratio: f32,
+}
+
+impl RenderTargetViewer {
+    pub fn new() -> Result<Self> {
+        let shader = Program::new()
+            .vertex_shader(shader_file!("post/fullscreen.vert"))?
+            .fragment_shader(shader_file!("debug/preview.frag"))?
+            .link()?;
+        shader.set_used();
+        shader.set_f32("max_distance", MAX_PREVIEW_DISTANCE)?;
+
+        let vao = empty_vertex_array();
+        let mut fbo: GLuint = 0;
+        let mut preview: GLuint = 0;
+        unsafe {
+            gl::CreateTextures(gl::TEXTURE_2D, 1, &mut preview);
+            // The GUI expects sRGB textures
+            gl::TextureStorage2D(preview, 1, gl::SRGB8_ALPHA8, PREVIEW_SIZE, PREVIEW_SIZE);
+
+            gl::CreateFramebuffers(1, &mut fbo);
+            gl::NamedFramebufferTexture(fbo, gl::COLOR_ATTACHMENT0, preview, 0);
+        }
+
+        let viewer = RenderTargetViewer {
+            shader,
+            vao,
+            fbo,
+            preview,
+            aspect_ratio: 1.0,
+        };
+        check_framebuffer(fbo, "Render target preview")?;
+        Ok(viewer)
+    }
+
+    /// Texture for the GUI to show
+    pub fn preview(&self) -> GLuint {
+        self.preview
+    }
+
+    pub fn aspect_ratio(&self) -> f32 {
+        self.aspect_ratio
+    }
+
+    /// Draws the target into the preview texture
+    pub fn update(&mut self, target: &RenderTarget) -> Result<()> {
+        self.shader.set_used();
+        self.shader.set_i32("mode", target.mode as i32)?;
+        unsafe {
+            let (mut width, mut height) = (0, 0);
+            gl::GetTextureLevelParameteriv(target.texture, 0, gl::TEXTURE_WIDTH, &mut width);
+            gl::GetTextureLevelParameteriv(target.texture, 0, gl::TEXTURE_HEIGHT, &mut height);
+            self.aspect_ratio = width as f32 / height.max(1) as f32;
+
+            let saved_target = SavedTarget::current();
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+            gl::Viewport(0, 0, PREVIEW_SIZE, PREVIEW_SIZE);
+            gl::Disable(gl::DEPTH_TEST);
+            bindings::bind_texture_sampled(
+                TextureUnit::SOURCE,
+                target.texture,
+                Sampler::LinearClamp,
+            );
+            gl::BindVertexArray(self.vao);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            profiler::count_draw_call();
+            gl::Enable(gl::DEPTH_TEST);
+
+            saved_target.restore();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for RenderTargetViewer {
+    fn drop(&mut self) {
+        bindings::delete_textures(&[self.preview]);
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.fbo);
+            gl::DeleteVertexArrays(1, &self.vao);
+        }
+    }
+}
@@ -0,0 +1,444 @@
+//! What's placed in the editor, as entities in a `hecs::World` with the components
+//! below: the models, and the particle emitters with an `Emitter` component. The editor
+//! and the renderer go through the same components, and the systems at the bottom run
+//! once per drawn frame: `propagate_transforms`, `cull` and `draw`. The particles are
+//! spawned from the emitters by `ParticleSystem::update`.
+//!
+//! Bricks aren't in the world yet, they're in `BrickWorld`, and the only light is the
+//! sun, which `Atmosphere` moves with the time of day.
+
+use glam::{Mat4, Quat, Vec3, Vec4};
+use hecs::{Entity, World};
+
+use crate::model::Model;
+use crate::opengl::shader::Program;
+use crate::particles::Emitter;
+use crate::ray::AABB;
+use crate::Result;
+
+/// Parents deeper than this are ignored, it's most likely a cycle
+const MAX_DEPTH: usize = 32;
+
+/// Shown in the outliner and the labels
+pub struct Name(pub String);
+
+/// Relative to the parent, or to the world without one
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Transform {
+    pub position: Vec3,
+    pub orientation: Quat,
+}
+
+impl Transform {
+    pub fn new(position: Vec3, orientation: Quat) -> Self {
+        Transform {
+            position,
+            orientation,
+        }
+    }
+
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::from_rotation_translation(self.orientation, self.position)
+    }
+
+    /// Drops the scale, the gizmo doesn't scale objects
+    pub fn from_matrix(matrix: &Mat4) -> Self {
+        let (_scale, orientation, position) = matrix.to_scale_rotation_translation();
+        Transform {
+            position,
+            orientation,
+        }
+    }
+}
+
+/// Moves the entity with the parent's transform
+pub struct Parent(pub Entity);
+
+/// The transform is relative to the camera rather than the world, like the weather's.
+/// The entity doesn't move with the origin then.
+pub struct FollowCamera;
+
+/// In world space, written by `propagate_transforms`
+#[derive(Debug, Clone, Copy)]
+pub struct GlobalTransform(pub Mat4);
+
+/// Toggled in the outliner, hidden objects can't be picked either
+pub struct Visible(pub bool);
+
+/// Written by `cull`, whether the object is in the view the frame is drawn for
+struct InView(bool);
+
+pub struct ObjectModel {
+    /// Into `Scene::models`
+    index: usize,
+    /// Where the model was loaded from, to load it again with the project
+    pub path: String,
+}
+
+/// An object taken out of the scene, see `Scene::restore_object`
+pub struct RemovedObject {
+    name: String,
+    model: Model,
+    path: String,
+    transform: Transform,
+    visible: bool,
+    /// Into `Scene::objects`
+    list_index: usize,
+}
+
+pub struct Scene {
+    pub world: World,
+    /// The order the objects are listed in the outliner and saved in
+    objects: Vec<Entity>,
+    /// Of `ObjectModel`s. The models share their textures through `Rc`s, which can't go
+    /// into the world, so they're kept next to it.
+    models: Vec<Model>,
+    /// The order the emitters are listed in
+    emitters: Vec<Entity>,
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Scene {
+            world: World::new(),
+            objects: vec![],
+            models: vec![],
+            emitters: vec![],
+        }
+    }
+}
+
+impl Scene {
+    /// A model without a parent, listed after the others
+    pub fn spawn_object(
+        &mut self,
+        name: String,
+        model: Model,
+        path: String,
+        transform: Transform,
+        visible: bool,
+    ) -> Entity {
+        let entity = self.world.spawn((
+            Name(name),
+            transform,
+            GlobalTransform(transform.matrix()),
+            Visible(visible),
+            InView(true),
+            ObjectModel {
+                index: self.models.len(),
+                path,
+            },
+        ));
+        self.models.push(model);
+        self.objects.push(entity);
+        entity
+    }
+
+    /// Takes the object out of the scene with everything needed to put it back, None if
+    /// it's not there
+    pub fn remove_object(&mut self, entity: Entity) -> Option<RemovedObject> {
+        let list_index = self.objects.iter().position(|&e| e == entity)?;
+        // Whatever was attached to it stays where it is
+        let children: Vec<Entity> = self
+            .world
+            .query::<&Parent>()
+            .iter()
+            .filter(|(_, parent)| parent.0 == entity)
+            .map(|(child, _)| child)
+            .collect();
+        for child in children {
+            self.set_parent(child, None);
+        }
+        let (name, transform, visible, object_model) = self
+            .world
+            .remove::<(Name, Transform, Visible, ObjectModel)>(entity)
+            .ok()?;
+        self.world.despawn(entity).ok();
+        self.objects.remove(list_index);
+
+        // The last model takes the place of the removed one
+        let index = object_model.index;
+        let model = self.models.swap_remove(index);
+        for (_, moved) in self.world.query_mut::<&mut ObjectModel>() {
+            if moved.index == self.models.len() {
+                moved.index = index;
+            }
+        }
+        Some(RemovedObject {
+            name: name.0,
+            model,
+            path: object_model.path,
+            transform,
+            visible: visible.0,
+            list_index,
+        })
+    }
+
+    /// Where it was in the outliner, as a new entity
+    pub fn restore_object(&mut self, removed: RemovedObject) -> Entity {
+        let entity = self.spawn_object(
+            removed.name,
+            removed.model,
+            removed.path,
+            removed.transform,
+            removed.visible,
+        );
+        self.objects.pop();
+        let list_index = removed.list_index.min(self.objects.len());
+        self.objects.insert(list_index, entity);
+        entity
+    }
+
+    /// Takes out all the objects. The emitters aren't saved with the project, so they
+    /// stay.
+    pub fn clear_objects(&mut self) {
+        for entity in self.objects.drain(..) {
+            self.world.despawn(entity).ok();
+        }
+        self.models.clear();
+    }
+
+    pub fn objects(&self) -> &[Entity] {
+        &self.objects
+    }
+
+    /// An emitter listed after the others, see `ParticleSystem::new_emitter`
+    pub fn spawn_emitter(
+        &mut self,
+        name: String,
+        emitter: Emitter,
+        position: Vec3,
+        follow_camera: bool,
+    ) -> Entity {
+        let transform = Transform::new(position, Quat::IDENTITY);
+        let entity = self.world.spawn((
+            Name(name),
+            transform,
+            GlobalTransform(transform.matrix()),
+            emitter,
+        ));
+        if follow_camera {
+            self.world.insert_one(entity, FollowCamera).ok();
+        }
+        self.emitters.push(entity);
+        entity
+    }
+
+    /// Takes the emitter out of the scene, for `ParticleSystem::remove_emitter`. None if
+    /// it's not there.
+    pub fn remove_emitter(&mut self, entity: Entity) -> Option<Emitter> {
+        let list_index = self.emitters.iter().position(|&e| e == entity)?;
+        let emitter = self.world.remove_one::<Emitter>(entity).ok()?;
+        self.world.despawn(entity).ok();
+        self.emitters.remove(list_index);
+        Some(emitter)
+    }
+
+    pub fn emitters(&self) -> &[Entity] {
+        &self.emitters
+    }
+
+    pub fn follows_camera(&self, entity: Entity) -> bool {
+        self.world.get::<&FollowCamera>(entity).is_ok()
+    }
+
+    pub fn set_follows_camera(&mut self, entity: Entity, follow_camera: bool) {
+        // Either fails only if there's nothing to change
+        if follow_camera {
+            self.world.insert_one(entity, FollowCamera).ok();
+        } else {
+            self.world.remove_one::<FollowCamera>(entity).ok();
+        }
+    }
+
+    pub fn name(&self, entity: Entity) -> String {
+        self.world
+            .get::<&Name>(entity)
+            .map_or_else(|_| String::new(), |name| name.0.clone())
+    }
+
+    pub fn set_name(&mut self, entity: Entity, name: String) {
+        if let Ok(mut current) = self.world.get::<&mut Name>(entity) {
+            current.0 = name;
+        }
+    }
+
+    pub fn transform(&self, entity: Entity) -> Option<Transform> {
+        self.world.get::<&Transform>(entity).ok().map(|t| *t)
+    }
+
+    pub fn set_transform(&mut self, entity: Entity, transform: Transform) {
+        if let Ok(mut current) = self.world.get::<&mut Transform>(entity) {
+            *current = transform;
+        }
+    }
+
+    /// In world space as of the last `propagate_transforms`
+    pub fn global_transform(&self, entity: Entity) -> Option<Mat4> {
+        self.world.get::<&GlobalTransform>(entity).ok().map(|t| t.0)
+    }
+
+    pub fn is_visible(&self, entity: Entity) -> bool {
+        self.world
+            .get::<&Visible>(entity)
+            .map_or(false, |visible| visible.0)
+    }
+
+    pub fn set_visible(&mut self, entity: Entity, visible: bool) {
+        if let Ok(mut current) = self.world.get::<&mut Visible>(entity) {
+            current.0 = visible;
+        }
+    }
+
+    pub fn model(&self, entity: Entity) -> Option<&Model> {
+        let index = self.world.get::<&ObjectModel>(entity).ok()?.index;
+        self.models.get(index)
+    }
+
+    pub fn model_mut(&mut self, entity: Entity) -> Option<&mut Model> {
+        let index = self.world.get::<&ObjectModel>(entity).ok()?.index;
+        self.models.get_mut(index)
+    }
+
+    pub fn model_path(&self, entity: Entity) -> String {
+        self.world
+            .get::<&ObjectModel>(entity)
+            .map_or_else(|_| String::new(), |model| model.path.clone())
+    }
+
+    /// Bounds of the model after the transform, in world space. Goes by the transform
+    /// the object was last drawn with, so it's what's on the screen that gets picked.
+    pub fn world_bounds(&self, entity: Entity) -> Option<AABB> {
+        let transform = self.global_transform(entity)?;
+        let model = self.model(entity)?;
+        Some(transformed_bounds(&model.bounds, &transform))
+    }
+
+    pub fn parent(&self, entity: Entity) -> Option<Entity> {
+        self.world
+            .get::<&Parent>(entity)
+            .ok()
+            .map(|parent| parent.0)
+    }
+
+    /// Attaches the child to the parent, or to nothing with None, without moving it.
+    /// Its transform is made relative to the parent's, going by where both were drawn
+    /// last.
+    pub fn set_parent(&mut self, child: Entity, parent: Option<Entity>) {
+        let global = match self.global_transform(child) {
+            Some(global) => global,
+            None => return,
+        };
+        let local = match parent.and_then(|parent| self.global_transform(parent)) {
+            Some(parent_global) => parent_global.inverse() * global,
+            None => global,
+        };
+        let local = Transform::from_matrix(&local);
+        // Either fails only if there's nothing to change
+        match parent {
+            Some(parent) => self.world.insert_one(child, Parent(parent)).ok(),
+            None => self.world.remove_one::<Parent>(child).ok().map(drop),
+        };
+        self.set_transform(child, local);
+    }
+
+    /// Only the entities without a parent move, the rest move with them. Nor do the
+    /// ones following the camera.
+    pub fn shift_origin(&mut self, shift: Vec3) {
+        for (_, transform) in self
+            .world
+            .query_mut::<&mut Transform>()
+            .without::<&Parent>()
+            .without::<&FollowCamera>()
+        {
+            transform.position -= shift;
+        }
+    }
+
+    // ================================ Systems =====================================
+
+    /// Multiplies each transform by those of its parents
+    pub fn propagate_transforms(&mut self) {
+        let globals: Vec<(Entity, Mat4)> = self
+            .world
+            .query::<(&Transform, Option<&Parent>)>()
+            .with::<&GlobalTransform>()
+            .iter()
+            .map(|(entity, (transform, parent))| {
+                let mut matrix = transform.matrix();
+                let mut parent = parent.map(|p| p.0);
+                for _ in 0..MAX_DEPTH {
+                    let next = match parent {
+                        Some(next) => next,
+                        None => break,
+                    };
+                    if let Ok(transform) = self.world.get::<&Transform>(next) {
+                        matrix = transform.matrix() * matrix;
+                    }
+                    parent = self.world.get::<&Parent>(next).ok().map(|p| p.0);
+                }
+                (entity, matrix)
+            })
+            .collect();
+        for (entity, matrix) in globals {
+            if let Ok(mut global) = self.world.get::<&mut GlobalTransform>(entity) {
+                global.0 = matrix;
+            }
+        }
+    }
+
+    /// Marks the objects outside the view so that `draw` skips them
+    pub fn cull(&mut self, view_projection: Mat4) {
+        let planes = frustum_planes(&view_projection);
+        for (_, (global, model, in_view)) in self
+            .world
+            .query_mut::<(&GlobalTransform, &ObjectModel, &mut InView)>()
+        {
+            let bounds = transformed_bounds(&self.models[model.index].bounds, &global.0);
+            in_view.0 = planes.iter().all(|plane| in_front(plane, &bounds));
+        }
+    }
+
+    /// Draws the visible objects in the view
+    pub fn draw(&mut self, shader: &Program) -> Result<()> {
+        for (_, (global, visible, in_view, model)) in
+            self.world
+                .query_mut::<(&GlobalTransform, &Visible, &InView, &ObjectModel)>()
+        {
+            if visible.0 && in_view.0 {
+                self.models[model.index].draw(shader, &global.0)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn transformed_bounds(bounds: &AABB, transform: &Mat4) -> AABB {
+    let mut world_bounds = AABB::empty();
+    for i in 0..8 {
+        let corner = Vec3::select(
+            glam::BVec3::new(i & 1 != 0, i & 2 != 0, i & 4 != 0),
+            bounds.max,
+            bounds.min,
+        );
+        let corner = transform.transform_point3(corner);
+        world_bounds.min = world_bounds.min.min(corner);
+        world_bounds.max = world_bounds.max.max(corner);
+    }
+    world_bounds
+}
+
+/// Left, right, bottom and top, pointing inwards. The projection is infinite, and the
+/// near plane is half a unit from the camera, so neither of the others is worth it.
+fn frustum_planes(view_projection: &Mat4) -> [Vec4; 4] {
+    let m = view_projection.transpose();
+    let (x, y, w) = (m.x_axis, m.y_axis, m.w_axis);
+    [w + x, w - x, w + y, w - y]
+}
+
+/// Whether some of the box is on the inner side of the plane
+fn in_front(plane: &Vec4, bounds: &AABB) -> bool {
+    let normal = plane.truncate();
+    let farthest = Vec3::select(normal.cmpge(Vec3::ZERO), bounds.max, bounds.min);
+    normal.dot(farthest) + plane.w >= 0.0
+}
@@ -0,0 +1,134 @@
+//! Editor preferences which aren't tied to the project, kept in settings.toml in the
+//! platform config directory. The paths and key bindings stay in config.json.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::display::DisplaySettings;
+use crate::editor::EditorState;
+use crate::gamepad::GamepadSettings;
+use crate::input::MouseSettings;
+use crate::postprocess::PostProcessSettings;
+use crate::render_settings::RenderSettings;
+use crate::terrain::BrushSettings;
+use crate::Result;
+
+const APP_DIR: &str = "game2";
+const FILE_NAME: &str = "settings.toml";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Settings {
+    /// World units per second, see `Camera::go`
+    pub camera_speed: f32,
+    /// In the mode of `DisplaySettings::fullscreen_mode`, toggled with Alt+Enter
+    pub fullscreen: bool,
+    // Plain values go before the tables, toml can't write them after
+    pub mouse: MouseSettings,
+    pub brush: BrushSettings,
+    pub windows: WindowLayout,
+    pub graphics: PostProcessSettings,
+    pub rendering: RenderSettings,
+    pub display: DisplaySettings,
+    pub gamepad: GamepadSettings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            camera_speed: 10.0,
+            fullscreen: false,
+            mouse: MouseSettings::default(),
+            brush: BrushSettings::default(),
+            windows: WindowLayout::default(),
+            graphics: PostProcessSettings::default(),
+            rendering: RenderSettings::default(),
+            display: DisplaySettings::default(),
+            gamepad: GamepadSettings::default(),
+        }
+    }
+}
+
+impl Settings {
+    /// The defaults are used if there's no file yet or it can't be read
+    pub fn load() -> Self {
+        let path = match settings_path() {
+            Some(path) if path.exists() => path,
+            _ => return Settings::default(),
+        };
+        Settings::read(&path).unwrap_or_else(|err| {
+            log!("Failed to load {}: {}", path.display(), err);
+            Settings::default()
+        })
+    }
+
+    pub fn save(&self) {
+        let path = match settings_path() {
+            Some(path) => path,
+            None => return,
+        };
+        if let Err(err) = self.write(&path) {
+            log!("Failed to save {}: {}", path.display(), err);
+        }
+    }
+
+    fn read(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+        Ok(toml::from_str(&text)?)
+    }
+
+    fn write(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Which editor windows are open
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct WindowLayout {
+    pub show_gui: bool,
+    pub show_profiler: bool,
+    pub show_keybindings: bool,
+    pub show_assets: bool,
+    pub show_render_targets: bool,
+    pub show_graphics: bool,
+}
+
+impl Default for WindowLayout {
+    fn default() -> Self {
+        WindowLayout::of(&EditorState::default())
+    }
+}
+
+impl WindowLayout {
+    pub fn of(editor_state: &EditorState) -> Self {
+        WindowLayout {
+            show_gui: editor_state.show_gui,
+            show_profiler: editor_state.show_profiler,
+            show_keybindings: editor_state.show_keybindings,
+            show_assets: editor_state.show_assets,
+            show_render_targets: editor_state.show_render_targets,
+            show_graphics: editor_state.show_graphics,
+        }
+    }
+
+    pub fn apply(&self, editor_state: &mut EditorState) {
+        editor_state.show_gui = self.show_gui;
+        editor_state.show_profiler = self.show_profiler;
+        editor_state.show_keybindings = self.show_keybindings;
+        editor_state.show_assets = self.show_assets;
+        editor_state.show_render_targets = self.show_render_targets;
+        editor_state.show_graphics = self.show_graphics;
+    }
+}
+
+/// None if the platform has no config directory
+fn settings_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join(APP_DIR).join(FILE_NAME))
+}
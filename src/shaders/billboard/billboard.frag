@@ -0,0 +1,31 @@
+#version 450 core
+layout(location = 0) out vec4 FragColor;
+layout(location = 1) out vec4 Sunlight;
+
+layout(binding = 0) uniform sampler2D sprite;
+
+in VS_OUT {
+    vec2 uv;
+    vec4 color;
+}
+fs_in;
+
+uniform bool has_sprite;  // without one the billboards are round dots
+
+void main() {
+    vec4 color = fs_in.color;
+    if (has_sprite) {
+        color *= texture(sprite, fs_in.uv);
+    } else {
+        float radius = length(fs_in.uv * 2.0 - 1.0);
+        color.a *= 1.0 - smoothstep(0.8, 1.0, radius);
+    }
+    if (color.a < 0.002) {
+        discard;
+    }
+
+    // Premultiplied alpha, not lit
+    FragColor = vec4(color.rgb * color.a, color.a);
+    // Covers up the sunlight of whatever is behind, same as the color
+    Sunlight = vec4(0.0, 0.0, 0.0, color.a);
+}
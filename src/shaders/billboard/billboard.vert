@@ -0,0 +1,47 @@
+#version 450 core
+
+#include "common/frame_uniforms.glsl"
+
+// Must match BillboardBlock and BillboardMode in billboard.rs
+struct Billboard {
+    vec3 position;
+    int mode;
+    vec2 size;
+    vec4 color;
+};
+const int MODE_SPHERICAL = 0;
+const int MODE_CYLINDRICAL = 1;
+
+layout(std430, binding = 0) readonly buffer Billboards {
+    Billboard billboards[];
+};
+
+out VS_OUT {
+    vec2 uv;
+    vec4 color;
+}
+vs_out;
+
+const vec2 CORNERS[] = vec2[](vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(-1.0, 1.0), vec2(1.0, 1.0));
+
+void main() {
+    Billboard billboard = billboards[gl_InstanceID];
+    vec2 offset = CORNERS[gl_VertexID] * 0.5 * billboard.size;
+
+    if (billboard.mode == MODE_SPHERICAL) {
+        // Parallel to the screen
+        vec4 view_pos = uTransforms.view * vec4(billboard.position, 1.0);
+        view_pos.xy += offset;
+        gl_Position = uTransforms.proj * view_pos;
+    } else {
+        // Turns around the vertical axis only
+        vec3 to_camera = uTransforms.camera_position.xyz - billboard.position;
+        vec3 right = length(to_camera.xz) > 0.0001 ? normalize(vec3(to_camera.z, 0.0, -to_camera.x))
+                                                 : vec3(1.0, 0.0, 0.0);
+        vec3 pos = billboard.position + right * offset.x + vec3(0.0, offset.y, 0.0);
+        gl_Position = uTransforms.proj * uTransforms.view * vec4(pos, 1.0);
+    }
+
+    vs_out.uv = CORNERS[gl_VertexID] * 0.5 + 0.5;
+    vs_out.color = billboard.color;
+}
@@ -0,0 +1,63 @@
+#version 450 core
+
+layout(binding = 0) uniform sampler2D albedo_atlas;
+layout(binding = 1) uniform sampler2D normal_atlas;
+layout(binding = 3) uniform sampler2D shadow_map;  // rendered by the terrain
+
+#include "common/frame_uniforms.glsl"
+#include "common/environment.glsl"
+
+in VS_OUT {
+    vec3 frag_pos;
+    vec2 uv;
+    flat mat3 normal_matrix;
+    flat vec4 color;
+}
+fs_in;
+
+layout(location = 0) out vec4 Color;
+layout(location = 1) out vec4 Sunlight;  // for the shadows applied in post-processing
+
+struct DirectionalLight {
+    vec3 direction;  // points towards the light
+    vec3 color;
+};
+uniform DirectionalLight sun;
+uniform float shadow_strength;
+uniform bool deferred_shadows;  // leave shadows to post-processing
+
+float calc_shadow(vec4 frag_pos) {
+    vec3 proj_coords = frag_pos.xyz / frag_pos.w;
+    proj_coords = proj_coords * 0.5 + 0.5;
+    float bias = 0.003;
+    float depth = texture(shadow_map, proj_coords.xy).r;
+    return (proj_coords.z - bias) > depth ? 1.0 : 0.0;
+}
+
+#include "common/fog.glsl"
+
+void main() {
+    vec4 albedo = texture(albedo_atlas, fs_in.uv);
+    if (albedo.a < 0.5) {
+        discard;
+    }
+    albedo.rgb *= fs_in.color.rgb;
+    vec3 normal = normalize(fs_in.normal_matrix * (texture(normal_atlas, fs_in.uv).xyz * 2.0 - 1.0));
+
+    vec3 camera_pos = uTransforms.camera_position.xyz;
+    vec3 to_frag = fs_in.frag_pos - camera_pos;
+    float dist = length(to_frag);
+    vec3 ray_dir = to_frag / dist;
+
+    // Plain diffuse, the details of the full material don't show from afar
+    float shadow =
+        deferred_shadows ? 0.0 : calc_shadow(uTransforms.sun_vp * vec4(fs_in.frag_pos, 1.0));
+    vec3 ambient = albedo.rgb * sky_irradiance(normal);
+    vec3 direct = (1.0 - shadow * shadow_strength) * albedo.rgb * sun.color *
+                  max(dot(normal, sun.direction), 0.0);
+
+    vec3 lighting = apply_fog(ambient + direct, camera_pos, ray_dir, dist);
+    Color = vec4(lighting, 1.0);
+    Sunlight = deferred_shadows ? vec4(lighting - apply_fog(ambient, camera_pos, ray_dir, dist), 1.0)
+                                : vec4(0.0);
+}
@@ -0,0 +1,57 @@
+#version 450 core
+
+#include "common/frame_uniforms.glsl"
+
+// Same instances as for the full meshes, see instancing.rs
+struct Instance {
+    mat4 transform;
+    vec4 color;  // linear, multiplies the albedo
+};
+layout(std430, binding = 0) readonly buffer Instances {
+    Instance instances[];
+};
+
+// Indices of the instances that passed culling as impostors, see instances.comp
+layout(std430, binding = 1) readonly buffer Visible {
+    uint visible[];
+};
+
+uniform vec3 center;     // of the baked model
+uniform vec2 half_size;  // of the quad in model units
+uniform int views;       // baked around the vertical axis, side by side in the atlas
+
+out VS_OUT {
+    vec3 frag_pos;
+    vec2 uv;
+    flat mat3 normal_matrix;
+    flat vec4 color;
+}
+vs_out;
+
+const float PI = 3.14159265;
+const vec2 CORNERS[] = vec2[](vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(-1.0, 1.0), vec2(1.0, 1.0));
+
+void main() {
+    mat4 instance = instances[visible[gl_InstanceID]].transform;
+    vec3 camera = uTransforms.camera_position.xyz;
+
+    // The baked view closest to the direction the model is seen from
+    vec3 world_center = (instance * vec4(center, 1.0)).xyz;
+    vec3 to_camera = camera - world_center;
+    vec3 local_dir = inverse(mat3(instance)) * to_camera;
+    float view_step = 2.0 * PI / float(views);
+    float view = mod(round(atan(local_dir.x, local_dir.z) / view_step), float(views));
+
+    // Rotates around the vertical axis only, so that it stays upright
+    vec3 right = length(to_camera.xz) > 0.0001 ? normalize(vec3(to_camera.z, 0.0, -to_camera.x))
+                                                 : vec3(1.0, 0.0, 0.0);
+    vec2 corner = CORNERS[gl_VertexID];
+    float scale = length(instance[0].xyz);
+    vec3 pos = world_center + (right * corner.x * half_size.x + vec3(0.0, corner.y * half_size.y, 0.0)) * scale;
+
+    vs_out.frag_pos = pos;
+    vs_out.uv = vec2((view + corner.x * 0.5 + 0.5) / float(views), corner.y * 0.5 + 0.5);
+    vs_out.normal_matrix = mat3(instance) / scale;
+    vs_out.color = instances[visible[gl_InstanceID]].color;
+    gl_Position = uTransforms.proj * uTransforms.view * vec4(pos, 1.0);
+}
@@ -0,0 +1,37 @@
+#version 450 core
+layout(location = 0) out vec4 Albedo;
+layout(location = 1) out vec4 Normal;  // in model space, packed into [0, 1]
+
+layout(binding = 0) uniform sampler2D albedo_texture;
+
+in VS_OUT {
+    vec3 normal;
+    vec2 uv;
+}
+fs_in;
+
+// Must match the flags in material.rs
+const int FLAG_HAS_ALBEDO = 1;
+
+layout(std140, binding = 3) uniform UMaterial {
+    vec4 albedo;  // linear
+    float roughness;
+    float metallic;
+    int flags;
+}
+uMaterial;
+
+void main() {
+    vec4 base_color = uMaterial.albedo;
+    if ((uMaterial.flags & FLAG_HAS_ALBEDO) != 0) {
+        base_color *= texture(albedo_texture, fs_in.uv);
+    }
+    vec3 normal = normalize(fs_in.normal);
+    if (!gl_FrontFacing) {
+        normal = -normal;
+    }
+
+    // Opaque, the impostor is alpha tested
+    Albedo = vec4(base_color.rgb, 1.0);
+    Normal = vec4(normal * 0.5 + 0.5, 1.0);
+}
@@ -0,0 +1,20 @@
+#version 450 core
+
+layout(location = 0) in vec3 in_position;
+layout(location = 1) in vec3 in_normal;
+layout(location = 2) in vec2 in_uv;
+
+out VS_OUT {
+    vec3 normal;
+    vec2 uv;
+}
+vs_out;
+
+uniform mat4 view_proj;
+uniform mat4 model;
+
+void main() {
+    vs_out.normal = mat3(transpose(inverse(model))) * in_normal;
+    vs_out.uv = in_uv;
+    gl_Position = view_proj * model * vec4(in_position, 1.0);
+}
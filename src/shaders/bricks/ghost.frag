@@ -0,0 +1,16 @@
+#version 450 core
+layout(location = 0) out vec4 FragColor;
+layout(location = 1) out vec4 Sunlight;
+
+in vec3 normal;
+
+uniform vec4 color;
+
+void main() {
+    // Just enough shading to tell the faces apart
+    float light = 0.6 + 0.4 * dot(normalize(normal), normalize(vec3(0.3, 1.0, 0.5)));
+    // Premultiplied alpha
+    FragColor = vec4(color.rgb * light * color.a, color.a);
+    // Covers up the sunlight of whatever is behind, same as the color
+    Sunlight = vec4(0.0, 0.0, 0.0, color.a);
+}
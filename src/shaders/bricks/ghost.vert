@@ -0,0 +1,15 @@
+#version 450 core
+
+#include "common/frame_uniforms.glsl"
+
+layout(location = 0) in vec3 in_position;
+layout(location = 1) in vec3 in_normal;
+
+out vec3 normal;
+
+uniform mat4 model;
+
+void main() {
+    normal = mat3(model) * in_normal;
+    gl_Position = uTransforms.mvp * model * vec4(in_position, 1.0);
+}
@@ -0,0 +1,107 @@
+#version 450 core
+layout(location = 0) out vec4 FragColor;
+layout(location = 1) out vec4 Sunlight;
+
+in vec3 ray_dir;
+
+layout(binding = 0) uniform sampler3D noise;
+
+#include "common/frame_uniforms.glsl"
+
+uniform int view_steps;
+uniform int light_steps;
+uniform float coverage;
+uniform float density;
+uniform float cloud_bottom;
+uniform float cloud_top;
+uniform vec2 wind_offset;
+
+uniform vec3 light_direction;  // points towards the light
+uniform vec3 light_color;
+uniform vec3 ambient_color;
+
+const float NOISE_SCALE = 1.0 / 1200.0;  // world units per noise tile
+const float DETAIL_SCALE = 1.0 / 300.0;
+const float MAX_DISTANCE = 8000.0;
+const float PI = 3.14159265;
+
+float cloud_density(vec3 pos) {
+    vec3 wind = vec3(wind_offset.x, 0.0, wind_offset.y);
+    float shape = texture(noise, (pos + wind) * NOISE_SCALE).r;
+    float detail = texture(noise, (pos + wind * 1.5) * DETAIL_SCALE).r;
+
+    // Rounded bottoms, wispy tops
+    float height = (pos.y - cloud_bottom) / (cloud_top - cloud_bottom);
+    float height_gradient = smoothstep(0.0, 0.15, height) * smoothstep(1.0, 0.4, height);
+
+    float value = shape * height_gradient - (1.0 - coverage);
+    value -= (1.0 - detail) * 0.15;
+    return max(value, 0.0) * density;
+}
+
+float henyey_greenstein(float cos_angle, float g) {
+    float g2 = g * g;
+    return (1.0 - g2) / (4.0 * PI * pow(1.0 + g2 - 2.0 * g * cos_angle, 1.5));
+}
+
+float light_transmittance(vec3 pos) {
+    float step_size = (cloud_top - cloud_bottom) / float(light_steps) / max(light_direction.y, 0.2);
+    float optical_depth = 0.0;
+    for (int i = 0; i < light_steps; ++i) {
+        pos += light_direction * step_size;
+        optical_depth += cloud_density(pos) * step_size;
+    }
+    return exp(-optical_depth);
+}
+
+void main() {
+    vec3 dir = normalize(ray_dir);
+    vec3 origin = uTransforms.camera_position.xyz;
+
+    // Intersect with the cloud slab
+    if (abs(dir.y) < 0.0001) {
+        discard;
+    }
+    float t0 = (cloud_bottom - origin.y) / dir.y;
+    float t1 = (cloud_top - origin.y) / dir.y;
+    float t_enter = max(min(t0, t1), 0.0);
+    float t_exit = min(max(t0, t1), MAX_DISTANCE);
+    if (t_exit <= t_enter) {
+        discard;
+    }
+
+    float step_size = (t_exit - t_enter) / float(view_steps);
+    // Dither the start to trade banding for noise
+    float jitter = fract(sin(dot(gl_FragCoord.xy, vec2(12.9898, 78.233))) * 43758.5453);
+    float t = t_enter + step_size * jitter;
+
+    float cos_angle = dot(dir, light_direction);
+    float phase = mix(henyey_greenstein(cos_angle, 0.6), henyey_greenstein(cos_angle, -0.3), 0.3);
+
+    float transmittance = 1.0;
+    vec3 scattered = vec3(0.0);
+    for (int i = 0; i < view_steps; ++i) {
+        vec3 pos = origin + dir * t;
+        float d = cloud_density(pos);
+        if (d > 0.0) {
+            vec3 light = light_color * light_transmittance(pos) * phase * 4.0 * PI + ambient_color;
+            float step_transmittance = exp(-d * step_size);
+            // Energy-conserving integration of the in-scattered light over the step
+            scattered += transmittance * light * (1.0 - step_transmittance);
+            transmittance *= step_transmittance;
+            if (transmittance < 0.01) {
+                break;
+            }
+        }
+        t += step_size;
+    }
+
+    // Fade out towards the horizon where the slab gets very long
+    float fade = 1.0 - smoothstep(MAX_DISTANCE * 0.5, MAX_DISTANCE, t_enter);
+    float alpha = (1.0 - transmittance) * fade;
+
+    // Premultiplied alpha
+    FragColor = vec4(scattered * fade, alpha);
+    // Covers up the sunlight of whatever is behind, same as the color
+    Sunlight = vec4(0.0, 0.0, 0.0, alpha);
+}
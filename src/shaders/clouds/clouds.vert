@@ -0,0 +1,15 @@
+#version 450 core
+
+out vec3 ray_dir;
+
+#include "common/frame_uniforms.glsl"
+
+void main() {
+    // Full-screen triangle
+    vec2 pos = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2) * 2.0 - 1.0;
+
+    vec4 view_pos = inverse(uTransforms.proj) * vec4(pos, 1.0, 1.0);
+    ray_dir = inverse(mat3(uTransforms.view)) * (view_pos.xyz / view_pos.w);
+
+    gl_Position = vec4(pos, 1.0, 1.0);  // at the far plane, behind everything but the sky
+}
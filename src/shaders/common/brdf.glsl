@@ -0,0 +1,42 @@
+// Metallic-roughness BRDF: GGX distribution, Smith-Schlick geometry, Schlick fresnel.
+// Expects the `sun` uniform to be declared.
+
+const float PI = 3.14159265;
+
+float distribution_ggx(float n_dot_h, float roughness) {
+    float a = roughness * roughness;
+    float a2 = a * a;
+    float d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+float geometry_smith(float n_dot_v, float n_dot_l, float roughness) {
+    float k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    float g_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    float g_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return g_v * g_l;
+}
+
+vec3 fresnel_schlick(float cos_theta, vec3 f0) {
+    return f0 + (1.0 - f0) * pow(1.0 - cos_theta, 5.0);
+}
+
+// Light reflected from the sun towards the viewer.
+// The sun color is scaled so that a white Lambertian surface facing it reflects exactly that color.
+vec3 sun_light(vec3 normal, vec3 view_dir, vec3 albedo, float metallic, float roughness) {
+    float n_dot_l = max(dot(normal, sun.direction), 0.0);
+    if (n_dot_l <= 0.0) {
+        return vec3(0.0);
+    }
+    vec3 halfway = normalize(sun.direction + view_dir);
+    float n_dot_v = max(dot(normal, view_dir), 0.0001);
+    float n_dot_h = max(dot(normal, halfway), 0.0);
+
+    vec3 f0 = mix(vec3(0.04), albedo, metallic);
+    vec3 fresnel = fresnel_schlick(max(dot(halfway, view_dir), 0.0), f0);
+    roughness = max(roughness, 0.04);  // a perfect mirror would reflect an infinitely small sun
+    vec3 specular = distribution_ggx(n_dot_h, roughness) * geometry_smith(n_dot_v, n_dot_l, roughness) *
+                    fresnel / (4.0 * n_dot_v * n_dot_l);
+    vec3 diffuse = (1.0 - fresnel) * (1.0 - metallic) * albedo / PI;
+    return (diffuse + specular) * sun.color * PI * n_dot_l;
+}
@@ -0,0 +1,18 @@
+// Direction through a point on a cubemap face, with uv in [-1, 1] from the first texel.
+// Follows the face layout in the GL spec, so the faces are the layers of an imageCube.
+vec3 cubemap_direction(int face, vec2 uv) {
+    switch (face) {
+        case 0: return vec3(1.0, -uv.y, -uv.x);
+        case 1: return vec3(-1.0, -uv.y, uv.x);
+        case 2: return vec3(uv.x, 1.0, uv.y);
+        case 3: return vec3(uv.x, -1.0, -uv.y);
+        case 4: return vec3(uv.x, -uv.y, 1.0);
+        default: return vec3(-uv.x, -uv.y, -1.0);
+    }
+}
+
+// Direction through the centre of the texel a compute invocation writes to
+vec3 cubemap_texel_direction(ivec3 pos, int size) {
+    vec2 uv = (vec2(pos.xy) + 0.5) / float(size) * 2.0 - 1.0;
+    return normalize(cubemap_direction(pos.z, uv));
+}
@@ -0,0 +1,32 @@
+// Ambient light from the sky. With the skybox it's image-based, from the maps made by
+// ibl.rs, otherwise the sky is taken to be uniformly ambient_color.
+layout(binding = 8) uniform samplerCube irradiance_map;
+layout(binding = 9) uniform samplerCube prefiltered_map;  // roughness increases with the level
+layout(binding = 10) uniform sampler2D brdf_lut;
+
+uniform vec3 ambient_color;
+uniform vec3 sky_tint;  // the skybox is drawn tinted by it, so its light is too
+uniform bool image_based_lighting;
+
+// Light arriving at a surface facing the normal, for diffuse-only materials
+vec3 sky_irradiance(vec3 normal) {
+    return image_based_lighting ? texture(irradiance_map, normal).rgb * sky_tint : ambient_color;
+}
+
+// Sky light reflected towards the viewer, with the split-sum approximation from
+// Karis, "Real Shading in Unreal Engine 4"
+vec3 ambient_light(vec3 normal, vec3 view_dir, vec3 albedo, float metallic, float roughness) {
+    vec3 f0 = mix(vec3(0.04), albedo, metallic);
+    float n_dot_v = max(dot(normal, view_dir), 0.0);
+    vec3 fresnel = f0 + (max(vec3(1.0 - roughness), f0) - f0) * pow(1.0 - n_dot_v, 5.0);
+    vec3 diffuse = (1.0 - fresnel) * (1.0 - metallic) * albedo;
+    if (!image_based_lighting) {
+        return (diffuse + fresnel) * ambient_color;
+    }
+
+    float lod = roughness * float(textureQueryLevels(prefiltered_map) - 1);
+    vec3 prefiltered = textureLod(prefiltered_map, reflect(-view_dir, normal), lod).rgb;
+    vec2 brdf = texture(brdf_lut, vec2(n_dot_v, roughness)).rg;
+    vec3 specular = prefiltered * (fresnel * brdf.x + brdf.y);
+    return (diffuse * texture(irradiance_map, normal).rgb + specular) * sky_tint;
+}
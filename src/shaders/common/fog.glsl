@@ -0,0 +1,20 @@
+// Height fog, the uniforms are set by Atmosphere::set_fog_uniforms
+
+struct Fog {
+    vec3 color;
+    float density;
+    float height_falloff;
+    float base_height;
+};
+uniform Fog fog;
+
+// Exponential height fog integrated along the view ray
+vec3 apply_fog(vec3 color, vec3 camera_pos, vec3 ray_dir, float dist) {
+    float falloff = max(fog.height_falloff, 0.0001);
+    float origin_density = fog.density * exp(-falloff * (camera_pos.y - fog.base_height));
+    float fog_amount = origin_density * dist;
+    if (abs(ray_dir.y) > 0.0001) {
+        fog_amount *= (1.0 - exp(-falloff * ray_dir.y * dist)) / (falloff * ray_dir.y * dist);
+    }
+    return mix(color, fog.color, 1.0 - exp(-fog_amount));
+}
@@ -0,0 +1,11 @@
+// Must match FrameUniforms in main.rs
+layout(std140, binding = 1) uniform UFrameUniforms {
+    mat4 mvp;
+    mat4 proj;
+    mat4 view;
+    mat4 model;
+    mat4 sun_vp;
+    vec4 camera_position;
+    float time;
+}
+uTransforms;
@@ -0,0 +1,21 @@
+// Importance sampling of the GGX distribution, for the IBL precomputation.
+// Expects PI to be defined.
+
+// Evenly spread points in [0, 1]^2, see "Hammersley Points on the Hemisphere"
+vec2 hammersley(uint i, uint count) {
+    return vec2(float(i) / float(count), float(bitfieldReverse(i)) * 2.3283064365386963e-10);
+}
+
+// Halfway vector around the normal, distributed like the GGX lobe of the roughness
+vec3 importance_sample_ggx(vec2 xi, vec3 normal, float roughness) {
+    float a = roughness * roughness;
+    float phi = 2.0 * PI * xi.x;
+    float cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    float sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+
+    vec3 up = abs(normal.z) < 0.999 ? vec3(0.0, 0.0, 1.0) : vec3(1.0, 0.0, 0.0);
+    vec3 tangent = normalize(cross(up, normal));
+    vec3 bitangent = cross(normal, tangent);
+    return normalize(tangent * (cos(phi) * sin_theta) + bitangent * (sin(phi) * sin_theta) +
+                     normal * cos_theta);
+}
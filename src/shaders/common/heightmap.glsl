@@ -0,0 +1,5 @@
+layout(binding = 1) uniform sampler2D heightmap;
+
+uniform float terrain_max_height;
+
+float sample_height(vec2 uv) { return texture(heightmap, uv).r * terrain_max_height; }
@@ -0,0 +1,11 @@
+#version 450 core
+layout(location = 0) out vec4 FragColor;
+layout(location = 1) out vec4 Sunlight;
+
+in vec4 color;
+
+void main() {
+    FragColor = color;
+    // Not lit, so nothing for the deferred shadows to take away
+    Sunlight = vec4(0.0);
+}
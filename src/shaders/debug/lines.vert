@@ -0,0 +1,13 @@
+#version 450 core
+
+#include "common/frame_uniforms.glsl"
+
+layout(location = 0) in vec3 in_position;
+layout(location = 1) in vec4 in_color;
+
+out vec4 color;
+
+void main() {
+    gl_Position = uTransforms.proj * uTransforms.view * vec4(in_position, 1.0);
+    color = in_color;
+}
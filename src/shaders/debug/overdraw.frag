@@ -0,0 +1,7 @@
+#version 450 core
+
+uniform vec3 color;
+
+out vec4 FragColor;
+
+void main() { FragColor = vec4(color, 1.0); }
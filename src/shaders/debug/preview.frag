@@ -0,0 +1,37 @@
+#version 450 core
+
+#include "common/frame_uniforms.glsl"
+
+layout(binding = 0) uniform sampler2D source;
+
+// Must match PreviewMode in render_targets.rs
+const int MODE_COLOR = 0;
+const int MODE_RED = 1;
+const int MODE_DEPTH = 2;
+const int MODE_PERSPECTIVE_DEPTH = 3;
+uniform int mode;
+uniform float max_distance;  // for perspective depth, shown as white
+
+in vec2 uv;
+
+out vec4 FragColor;
+
+void main() {
+    vec4 texel = texture(source, uv);
+    vec3 color;
+    if (mode == MODE_COLOR) {
+        color = texel.rgb;
+    } else if (mode == MODE_RED) {
+        color = texel.rrr;
+    } else if (mode == MODE_DEPTH) {
+        // Orthographic, already linear
+        color = vec3(texel.r);
+    } else {
+        // Distance from the camera on a log scale, so that both near and far stand out
+        float near = uTransforms.proj[3][2] / (uTransforms.proj[2][2] - 1.0);
+        float ndc = texel.r * 2.0 - 1.0;
+        float distance = texel.r < 1.0 ? uTransforms.proj[3][2] / (ndc + uTransforms.proj[2][2]) : 1e9;
+        color = vec3(clamp(log(abs(distance) / near) / log(max_distance / near), 0.0, 1.0));
+    }
+    FragColor = vec4(color, 1.0);
+}
@@ -14,14 +14,7 @@ out vec3 fColor;
 
 const float MAGNITUDE = 2.0;
 
-layout(std140, binding = 1) uniform UTransforms {
-    mat4 mvp;
-    mat4 proj;
-    mat4 view;
-    mat4 model;
-    mat4 sun_vp;
-}
-uTransforms;
+#include "common/frame_uniforms.glsl"
 
 void GenerateLine(int index) {
     vec4 vertex_pos = vec4(gs_in[index].frag_pos, 1.0);
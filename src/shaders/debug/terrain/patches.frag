@@ -0,0 +1,22 @@
+#version 450 core
+
+in TES_OUT {
+    vec4 frag_pos_sun_space;
+    vec3 frag_pos;
+    vec3 normal;
+    vec2 tile_uv;
+}
+fs_in;
+
+uniform int num_patches;
+
+out vec4 Color;
+
+void main() {
+    // Neighbouring patches get different colors, like a checkerboard
+    vec2 patch_index = floor(fs_in.tile_uv * float(num_patches));
+    float checker = mod(patch_index.x + patch_index.y, 2.0);
+    vec3 color = mix(vec3(1.0, 0.45, 0.05), vec3(0.05, 0.55, 1.0), checker);
+    // A bit of shading to show the slopes
+    Color = vec4(color * (0.6 + 0.4 * fs_in.normal.y), 1.0);
+}
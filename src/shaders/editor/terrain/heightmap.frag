@@ -6,16 +6,53 @@ fs_in;
 uniform vec2 cursor;       // normalised [0:1]
 uniform float brush_size;  // normalised [0:1]
 uniform float delta_time;
+uniform float strength;
+uniform float falloff;  // part of the radius the brush fades out over
+uniform float rotation;  // of the brush shape, radians
+uniform int mode;       // 0 - raise or lower, 1 - smooth, 2 - flatten
+uniform float flatten_target;  // normalised, negative for the height under the cursor
 
 layout(binding = 0) uniform sampler2D brush_texture;
+// Copy of the heightmap, for smoothing and flattening
+layout(binding = 1) uniform sampler2D heightmap;
 
 layout(location = 0) out vec4 Color;
 
+// How fast smoothing and flattening get to their target at full strength
+const float BLEND_RATE = 5.0;
+// Texels between the samples averaged for smoothing
+const float SMOOTH_STEP = 2.0;
+
 void main() {
     // Note that brush_size is actually more like brush radius (i.e. half brush real size)
     vec2 brush_uv = vec2(0.5, 0.5) + (fs_in.uv - cursor) / brush_size;
-    vec3 brush_value = texture(brush_texture, brush_uv).rrr * delta_time;  // TODO: sensitivity
+    float edge = length(brush_uv - 0.5) * 2.0;
+    float fade = 1.0 - smoothstep(1.0 - max(falloff, 0.001), 1.0, edge);
+    mat2 turn = mat2(cos(rotation), sin(rotation), -sin(rotation), cos(rotation));
+    vec2 turned = turn * (brush_uv - 0.5);
+    float brush_value = texture(brush_texture, turned + 0.5).r * fade * strength * delta_time;
+
+    if (mode == 0) {
+        // Will be added to or subtracted from what's currently in the heightmap
+        Color = vec4(vec3(brush_value), 1.0);
+        return;
+    }
 
-    // Will be blended with what's currently in the heightmap
-    Color = vec4(brush_value, 1.0);
+    float target;
+    if (mode == 1) {
+        vec2 texel = SMOOTH_STEP / vec2(textureSize(heightmap, 0));
+        float sum = 0.0;
+        for (int x = -2; x <= 2; x++) {
+            for (int y = -2; y <= 2; y++) {
+                sum += texture(heightmap, fs_in.uv + vec2(x, y) * texel).r;
+            }
+        }
+        target = sum / 25.0;
+    } else if (flatten_target >= 0.0) {
+        target = flatten_target;
+    } else {
+        target = texture(heightmap, cursor).r;
+    }
+    // Blended over what's in the heightmap by alpha
+    Color = vec4(vec3(target), clamp(brush_value * BLEND_RATE, 0.0, 1.0));
 }
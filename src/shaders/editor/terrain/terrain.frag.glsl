@@ -8,15 +8,146 @@ in TES_OUT {
 }
 fs_in;
 
-out vec4 Color;
+layout(location = 0) out vec4 Color;
+layout(location = 1) out vec4 Sunlight;  // for the shadows applied in post-processing
+
+#include "common/frame_uniforms.glsl"
 
 uniform vec2 cursor;
 uniform float brush_size;
 
-layout(binding = 0) uniform sampler2D terrain_texture;
 layout(binding = 2) uniform sampler2D brush_texture;
 layout(binding = 3) uniform sampler2D shadow_map;
 
+// ================================ Splat material ====================================
+
+const int MAX_LAYERS = 4;
+
+// Must match the flags in splat.rs
+const int FLAG_TRIPLANAR = 1;
+const int FLAG_HAS_NORMAL = 2;
+const int FLAG_HAS_ROUGHNESS = 4;
+
+struct SplatLayer {
+    vec4 albedo;      // multiplied with the albedo texture
+    float tiling;     // world units per texture repeat
+    int slice;        // slice in the texture arrays
+    int flags;
+    float roughness;  // multiplied with the roughness texture
+    float metallic;
+    float _padding0;
+    float _padding1;
+    float _padding2;
+};
+
+layout(std140, binding = 2) uniform UTerrainMaterial {
+    SplatLayer layers[MAX_LAYERS];
+    int layer_count;
+}
+uMaterial;
+
+layout(binding = 4) uniform sampler2DArray albedo_layers;
+layout(binding = 5) uniform sampler2DArray normal_layers;
+layout(binding = 6) uniform sampler2DArray roughness_layers;
+layout(binding = 7) uniform sampler2D splatmap;
+
+struct SurfaceSample {
+    vec3 albedo;
+    vec3 normal;
+    float roughness;
+    float metallic;
+};
+
+vec3 unpack_normal(vec4 texel) { return texel.xyz * 2.0 - 1.0; }
+
+SurfaceSample sample_layer(SplatLayer layer, vec3 pos, vec3 normal) {
+    SurfaceSample s;
+    if ((layer.flags & FLAG_TRIPLANAR) != 0) {
+        vec3 weights = pow(abs(normal), vec3(4.0));
+        weights /= weights.x + weights.y + weights.z;
+        vec3 uv_x = vec3(pos.zy / layer.tiling, layer.slice);
+        vec3 uv_y = vec3(pos.xz / layer.tiling, layer.slice);
+        vec3 uv_z = vec3(pos.xy / layer.tiling, layer.slice);
+
+        s.albedo = texture(albedo_layers, uv_x).rgb * weights.x +
+                   texture(albedo_layers, uv_y).rgb * weights.y +
+                   texture(albedo_layers, uv_z).rgb * weights.z;
+        s.roughness = texture(roughness_layers, uv_x).r * weights.x +
+                      texture(roughness_layers, uv_y).r * weights.y +
+                      texture(roughness_layers, uv_z).r * weights.z;
+
+        s.normal = normal;
+        if ((layer.flags & FLAG_HAS_NORMAL) != 0) {
+            // UDN blend
+            vec3 tn_x = unpack_normal(texture(normal_layers, uv_x));
+            vec3 tn_y = unpack_normal(texture(normal_layers, uv_y));
+            vec3 tn_z = unpack_normal(texture(normal_layers, uv_z));
+            vec3 n_x = vec3(tn_x.xy + normal.zy, normal.x).zyx;
+            vec3 n_y = vec3(tn_y.xy + normal.xz, normal.y).xzy;
+            vec3 n_z = vec3(tn_z.xy + normal.xy, normal.z);
+            s.normal = normalize(n_x * weights.x + n_y * weights.y + n_z * weights.z);
+        }
+    } else {
+        vec3 uv = vec3(pos.xz / layer.tiling, layer.slice);
+        s.albedo = texture(albedo_layers, uv).rgb;
+        s.roughness = texture(roughness_layers, uv).r;
+
+        s.normal = normal;
+        if ((layer.flags & FLAG_HAS_NORMAL) != 0) {
+            // Planar projection along Y: texture U goes along X, V along Z
+            vec3 tangent = normalize(vec3(1.0, 0.0, 0.0) - normal * normal.x);
+            vec3 bitangent = cross(normal, tangent);
+            vec3 tn = unpack_normal(texture(normal_layers, uv));
+            s.normal = normalize(mat3(tangent, bitangent, normal) * tn);
+        }
+    }
+    if ((layer.flags & FLAG_HAS_ROUGHNESS) == 0) {
+        s.roughness = 0.8;
+    }
+    s.albedo *= layer.albedo.rgb;
+    s.roughness = clamp(s.roughness * layer.roughness, 0.0, 1.0);
+    s.metallic = layer.metallic;
+    return s;
+}
+
+SurfaceSample sample_material(vec3 pos, vec3 normal, vec2 splat_uv) {
+    vec4 splat = texture(splatmap, splat_uv);
+    SurfaceSample result = SurfaceSample(vec3(0.0), vec3(0.0), 0.0, 0.0);
+    float total_weight = 0.0;
+    for (int i = 0; i < uMaterial.layer_count; ++i) {
+        // Weights are kept in the layer's slice, which stays put when layers are moved
+        float weight = splat[uMaterial.layers[i].slice];
+        if (weight <= 0.001) {
+            continue;
+        }
+        SurfaceSample s = sample_layer(uMaterial.layers[i], pos, normal);
+        result.albedo += s.albedo * weight;
+        result.normal += s.normal * weight;
+        result.roughness += s.roughness * weight;
+        result.metallic += s.metallic * weight;
+        total_weight += weight;
+    }
+    if (total_weight <= 0.001) {
+        // Nothing painted here
+        return SurfaceSample(vec3(0.5), normal, 0.8, 0.0);
+    }
+    result.albedo /= total_weight;
+    result.normal = normalize(result.normal);
+    result.roughness /= total_weight;
+    result.metallic /= total_weight;
+    return result;
+}
+
+// ====================================================================================
+
+struct DirectionalLight {
+    vec3 direction;  // towards the light
+    vec3 color;
+};
+uniform DirectionalLight sun;
+uniform float shadow_strength;
+uniform bool deferred_shadows;  // leave shadows to post-processing
+
 float calc_shadow(vec4 frag_pos) {
     vec3 proj_coords = frag_pos.xyz / frag_pos.w;
     proj_coords = proj_coords * 0.5 + 0.5;
@@ -34,11 +165,15 @@ float calc_shadow(vec4 frag_pos) {
     return shadow / 9.0;
 }
 
-const float ENABLE_SHADOWS = 1.0;
+// ================================= Lighting =========================================
+
+#include "common/brdf.glsl"
+#include "common/environment.glsl"
+#include "common/fog.glsl"
 
 void main() {
-    vec2 patch_uv = fs_in.tile_uv * 64.0;
-    vec4 terrain_color = texture(terrain_texture, patch_uv);
+    SurfaceSample surface = sample_material(fs_in.frag_pos, normalize(fs_in.normal), fs_in.tile_uv);
+    vec4 terrain_color = vec4(surface.albedo, 1.0);
     vec2 brush_uv = vec2(0.5, 0.5) + (fs_in.frag_pos.xz - cursor) / brush_size;
     const vec4 brush_color = vec4(0.75, 0.45, 0.92, 1.0);
     const vec3 brush_border_color = vec3(0.69, 0.67, 0.91);
@@ -48,16 +183,22 @@ void main() {
 
     base_color = mix(base_color, brush_border_color, t);
 
-    vec3 ambient = 0.35 * base_color;
-    vec3 normal = normalize(fs_in.normal);
-    vec3 light_color = vec3(1.0);
-    vec3 light_dir = normalize(vec3(0.0, 200.0, 500.0));  // @hardcoded
-    float diff = max(dot(light_dir, normal), 0.0);
-    vec3 diffuse = diff * light_color;
+    vec3 camera_pos = uTransforms.camera_position.xyz;
+    vec3 to_frag = fs_in.frag_pos - camera_pos;
+    float dist = length(to_frag);
+    vec3 view_dir = -to_frag / dist;
 
-    float shadow = calc_shadow(fs_in.frag_pos_sun_space);
+    vec3 normal = surface.normal;
+    float shadow = deferred_shadows ? 0.0 : calc_shadow(fs_in.frag_pos_sun_space);
 
-    vec3 lighting = (ambient + (1.0 - shadow * ENABLE_SHADOWS) * diffuse) * base_color;
+    vec3 ambient = ambient_light(normal, view_dir, base_color, surface.metallic, surface.roughness);
+    vec3 direct = (1.0 - shadow * shadow_strength) *
+                  sun_light(normal, view_dir, base_color, surface.metallic, surface.roughness);
+    vec3 ray_dir = to_frag / dist;
+    vec3 lighting = apply_fog(ambient + direct, camera_pos, ray_dir, dist);
 
     Color = vec4(lighting, 1.0);
+    // Fog is linear in color, so this is exactly the fogged direct light
+    Sunlight = deferred_shadows ? vec4(lighting - apply_fog(ambient, camera_pos, ray_dir, dist), 1.0)
+                                : vec4(0.0);
 }
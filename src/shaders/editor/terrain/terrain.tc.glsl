@@ -2,55 +2,46 @@
 
 layout(vertices = 4) out;
 
-in VS_OUT { vec2 tile_uv; }
+in VS_OUT {
+    vec2 tile_uv;
+    int patch_index;
+}
 tcs_in[];
 
 out TCS_OUT { vec2 tile_uv; }
 tcs_out[];
 
-layout(std140, binding = 1) uniform UTransforms {
-    mat4 mvp;
-    mat4 proj;
-    mat4 view;
-    mat4 model;
-    mat4 sun_vp;
-}
-uTransforms;
+// Written by terrain_patches.comp, which has culled the patches already
+layout(std430, binding = 1) readonly buffer TessLevels {
+    float tess_levels[];
+};
+
+uniform int num_patches;
 
-uniform float tess_level;
+// The level of the edge shared with the neighbour, the same on both sides so that
+// there are no cracks between patches with different levels
+float edge_level(int x, int y, float level) {
+    if (x < 0 || y < 0 || x >= num_patches || y >= num_patches) {
+        return level;
+    }
+    return max(level, tess_levels[y * num_patches + x]);
+}
 
 void main() {
     if (gl_InvocationID == 0) {
-        vec4 p0 = uTransforms.mvp * gl_in[0].gl_Position;
-        vec4 p1 = uTransforms.mvp * gl_in[1].gl_Position;
-        vec4 p2 = uTransforms.mvp * gl_in[2].gl_Position;
-        vec4 p3 = uTransforms.mvp * gl_in[3].gl_Position;
-
-        if (p0.z <= 0.0 && p1.z <= 0.0 && p2.z <= 0.0 && p3.z <= 0.0) {
-            // Patch is behind the camera - cull
-            // TODO: understand why visible patches are culled sometimes
-            gl_TessLevelOuter[0] = 0.0;
-            gl_TessLevelOuter[1] = 0.0;
-            gl_TessLevelOuter[2] = 0.0;
-            gl_TessLevelOuter[3] = 0.0;
-        } else {
-            // float l0 = length(p2.xy - p0.xy) + 1.0;
-            // float l1 = length(p3.xy - p2.xy) + 1.0;
-            // float l2 = length(p3.xy - p1.xy) + 1.0;
-            // float l3 = length(p1.xy - p0.xy) + 1.0;
-            float l0 = tess_level;
-            float l1 = tess_level;
-            float l2 = tess_level;
-            float l3 = tess_level;
-
-            gl_TessLevelOuter[0] = l0;
-            gl_TessLevelOuter[1] = l1;
-            gl_TessLevelOuter[2] = l2;
-            gl_TessLevelOuter[3] = l3;
-
-            gl_TessLevelInner[0] = min(l1, l3);
-            gl_TessLevelInner[1] = min(l0, l2);
-        }
+        int index = tcs_in[0].patch_index;
+        int x = index % num_patches;
+        int y = index / num_patches;
+        float level = tess_levels[index];
+
+        // The edges are u = 0, v = 0, u = 1, v = 1, see terrain.te.glsl
+        gl_TessLevelOuter[0] = edge_level(x - 1, y, level);
+        gl_TessLevelOuter[1] = edge_level(x, y + 1, level);
+        gl_TessLevelOuter[2] = edge_level(x + 1, y, level);
+        gl_TessLevelOuter[3] = edge_level(x, y - 1, level);
+
+        gl_TessLevelInner[0] = level;
+        gl_TessLevelInner[1] = level;
     }
 
     gl_out[gl_InvocationID].gl_Position = gl_in[gl_InvocationID].gl_Position;
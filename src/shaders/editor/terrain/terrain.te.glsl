@@ -2,18 +2,9 @@
 
 layout(quads, fractional_odd_spacing) in;
 
-layout(std140, binding = 1) uniform UTransforms {
-    mat4 mvp;
-    mat4 proj;
-    mat4 view;
-    mat4 model;
-    mat4 sun_vp;
-}
-uTransforms;
-
-layout(binding = 1) uniform sampler2D heightmap;
+#include "common/frame_uniforms.glsl"
+#include "common/heightmap.glsl"
 
-uniform float terrain_max_height;
 uniform float terrain_size;
 
 in TCS_OUT { vec2 tile_uv; }
@@ -27,8 +18,6 @@ out TES_OUT {
 }
 tes_out;
 
-float sample_height(vec2 uv) { return texture(heightmap, uv).r * terrain_max_height; }
-
 vec3 calc_normal(vec2 uv) {
     // @speed: maybe pass texture size in the uniform
     // or maybe build a normal map while drawing on heightmap
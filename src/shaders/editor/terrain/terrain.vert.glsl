@@ -6,18 +6,24 @@ uniform vec2 terrain_center;
 uniform int num_patches;
 uniform float patch_size;
 
-out VS_OUT { vec2 tile_uv; }
+out VS_OUT {
+    vec2 tile_uv;
+    // Set by the draw command of the patch, see terrain_patches.comp
+    int patch_index;
+}
 vs_out;
 
 void main() {
-    vec2 vertex = VERTICES[gl_VertexID];
+    int patch_index = gl_VertexID / 4;
+    vec2 vertex = VERTICES[gl_VertexID % 4];
 
-    int x = gl_InstanceID % num_patches;
-    int y = gl_InstanceID / num_patches;
+    int x = patch_index % num_patches;
+    int y = patch_index / num_patches;
     vec2 offset = vec2(x, y);
 
     // Texture coords
     vs_out.tile_uv = (vertex + offset) / float(num_patches);
+    vs_out.patch_index = patch_index;
 
     // Position
     float half_num = float(num_patches) / 2.0;
@@ -0,0 +1,128 @@
+#version 450 core
+
+layout(binding = 0) uniform sampler2D albedo_texture;
+layout(binding = 1) uniform sampler2D normal_texture;
+layout(binding = 2) uniform sampler2D metallic_roughness_texture;  // G and B, like in glTF
+layout(binding = 3) uniform sampler2D shadow_map;  // rendered by the terrain
+
+#include "common/frame_uniforms.glsl"
+
+in VS_OUT {
+    vec3 frag_pos;
+    vec3 normal;
+    vec2 uv;
+    vec4 color;
+}
+fs_in;
+
+layout(location = 0) out vec4 Color;
+layout(location = 1) out vec4 Sunlight;  // for the shadows applied in post-processing
+
+// Must match the flags in material.rs
+const int FLAG_HAS_ALBEDO = 1;
+const int FLAG_HAS_NORMAL = 2;
+const int FLAG_HAS_METALLIC_ROUGHNESS = 4;
+const int FLAG_UNLIT = 8;
+
+layout(std140, binding = 3) uniform UMaterial {
+    vec4 albedo;  // linear
+    float roughness;
+    float metallic;
+    int flags;
+}
+uMaterial;
+
+struct DirectionalLight {
+    vec3 direction;  // points towards the light
+    vec3 color;
+};
+uniform DirectionalLight sun;
+uniform float shadow_strength;
+uniform bool deferred_shadows;  // leave shadows to post-processing
+
+// Only the terrain casts shadows for now
+float calc_shadow(vec4 frag_pos) {
+    vec3 proj_coords = frag_pos.xyz / frag_pos.w;
+    proj_coords = proj_coords * 0.5 + 0.5;
+    float frag_depth = proj_coords.z;
+    float bias = 0.003;
+    float shadow = 0.0;
+    vec2 texel_size = 1.0 / textureSize(shadow_map, 0);
+    for (int x = -1; x <= 1; ++x) {
+        for (int y = -1; y <= 1; ++y) {
+            float pcf_depth = texture(shadow_map, proj_coords.xy + vec2(x, y) * texel_size).r;
+            shadow += (frag_depth - bias) > pcf_depth ? 1.0 : 0.0;
+        }
+    }
+    return shadow / 9.0;
+}
+
+// ================================= Lighting =========================================
+
+#include "common/brdf.glsl"
+#include "common/environment.glsl"
+#include "common/fog.glsl"
+
+// Tangent frame from screen-space derivatives, so meshes don't need tangents
+vec3 perturb_normal(vec3 normal, vec3 tangent_normal) {
+    vec3 dp1 = dFdx(fs_in.frag_pos);
+    vec3 dp2 = dFdy(fs_in.frag_pos);
+    vec2 duv1 = dFdx(fs_in.uv);
+    vec2 duv2 = dFdy(fs_in.uv);
+
+    vec3 dp2perp = cross(dp2, normal);
+    vec3 dp1perp = cross(normal, dp1);
+    vec3 tangent = dp2perp * duv1.x + dp1perp * duv2.x;
+    vec3 bitangent = dp2perp * duv1.y + dp1perp * duv2.y;
+    float inv_max = inversesqrt(max(dot(tangent, tangent), dot(bitangent, bitangent)));
+    return normalize(mat3(tangent * inv_max, bitangent * inv_max, normal) * tangent_normal);
+}
+
+void main() {
+    vec4 base_color = uMaterial.albedo * fs_in.color;
+    if ((uMaterial.flags & FLAG_HAS_ALBEDO) != 0) {
+        base_color *= texture(albedo_texture, fs_in.uv);
+    }
+
+    vec3 camera_pos = uTransforms.camera_position.xyz;
+    vec3 to_frag = fs_in.frag_pos - camera_pos;
+    float dist = length(to_frag);
+    vec3 ray_dir = to_frag / dist;
+
+    if ((uMaterial.flags & FLAG_UNLIT) != 0) {
+        Color = vec4(apply_fog(base_color.rgb, camera_pos, ray_dir, dist), base_color.a);
+        Sunlight = vec4(0.0, 0.0, 0.0, base_color.a);
+        return;
+    }
+
+    vec3 normal = normalize(fs_in.normal);
+    if (!gl_FrontFacing) {
+        normal = -normal;  // double-sided materials
+    }
+    if ((uMaterial.flags & FLAG_HAS_NORMAL) != 0) {
+        normal = perturb_normal(normal, texture(normal_texture, fs_in.uv).xyz * 2.0 - 1.0);
+    }
+    float roughness = uMaterial.roughness;
+    float metallic = uMaterial.metallic;
+    if ((uMaterial.flags & FLAG_HAS_METALLIC_ROUGHNESS) != 0) {
+        vec4 texel = texture(metallic_roughness_texture, fs_in.uv);
+        roughness *= texel.g;
+        metallic *= texel.b;
+    }
+    roughness = clamp(roughness, 0.0, 1.0);
+    metallic = clamp(metallic, 0.0, 1.0);
+
+    vec3 view_dir = -ray_dir;
+    float shadow =
+        deferred_shadows ? 0.0 : calc_shadow(uTransforms.sun_vp * vec4(fs_in.frag_pos, 1.0));
+    vec3 ambient = ambient_light(normal, view_dir, base_color.rgb, metallic, roughness);
+    vec3 direct = (1.0 - shadow * shadow_strength) *
+                  sun_light(normal, view_dir, base_color.rgb, metallic, roughness);
+
+    vec3 lighting = apply_fog(ambient + direct, camera_pos, ray_dir, dist);
+
+    Color = vec4(lighting, base_color.a);
+    Sunlight = deferred_shadows
+                   ? vec4(lighting - apply_fog(ambient, camera_pos, ray_dir, dist), base_color.a)
+                   : vec4(0.0);
+}
@@ -0,0 +1,26 @@
+#version 450 core
+
+#include "common/frame_uniforms.glsl"
+
+layout(location = 0) in vec3 in_position;
+layout(location = 1) in vec3 in_normal;
+layout(location = 2) in vec2 in_uv;
+
+out VS_OUT {
+    vec3 frag_pos;
+    vec3 normal;
+    vec2 uv;
+    vec4 color;  // of the instance, see mesh_instanced.vert
+}
+vs_out;
+
+uniform mat4 model;
+
+void main() {
+    vec4 world_pos = model * vec4(in_position, 1.0);
+    vs_out.frag_pos = world_pos.xyz;
+    vs_out.normal = mat3(transpose(inverse(model))) * in_normal;
+    vs_out.uv = in_uv;
+    vs_out.color = vec4(1.0);
+    gl_Position = uTransforms.mvp * world_pos;
+}
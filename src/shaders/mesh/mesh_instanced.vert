@@ -0,0 +1,42 @@
+#version 450 core
+
+#include "common/frame_uniforms.glsl"
+
+// See instancing.rs
+struct Instance {
+    mat4 transform;
+    vec4 color;  // linear, multiplies the albedo
+};
+layout(std430, binding = 0) readonly buffer Instances {
+    Instance instances[];
+};
+
+// Indices of the instances that passed culling as full meshes, see instances.comp
+layout(std430, binding = 1) readonly buffer Visible {
+    uint visible[];
+};
+
+layout(location = 0) in vec3 in_position;
+layout(location = 1) in vec3 in_normal;
+layout(location = 2) in vec2 in_uv;
+
+out VS_OUT {
+    vec3 frag_pos;
+    vec3 normal;
+    vec2 uv;
+    vec4 color;
+}
+vs_out;
+
+uniform mat4 model;  // transform of the node within the mesh
+
+void main() {
+    Instance instance = instances[visible[gl_InstanceID]];
+    mat4 transform = instance.transform * model;
+    vec4 world_pos = transform * vec4(in_position, 1.0);
+    vs_out.frag_pos = world_pos.xyz;
+    vs_out.normal = mat3(transpose(inverse(transform))) * in_normal;
+    vs_out.uv = in_uv;
+    vs_out.color = instance.color;
+    gl_Position = uTransforms.mvp * world_pos;
+}
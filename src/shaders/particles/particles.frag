@@ -0,0 +1,41 @@
+#version 450 core
+layout(location = 0) out vec4 FragColor;
+layout(location = 1) out vec4 Sunlight;
+
+in VS_OUT {
+    vec2 corner;
+    vec4 color;
+    float view_distance;
+    float softness;
+}
+fs_in;
+
+layout(binding = 1) uniform sampler2D scene_depth;
+
+#include "common/frame_uniforms.glsl"
+
+uniform vec3 light;  // sun and sky light combined
+
+float view_distance(float depth) {
+    float ndc = depth * 2.0 - 1.0;
+    return uTransforms.proj[3][2] / (ndc + uTransforms.proj[2][2]);
+}
+
+void main() {
+    // Round particles with soft edges
+    float alpha = fs_in.color.a * (1.0 - smoothstep(0.5, 1.0, length(fs_in.corner)));
+
+    // Fade out where the particle cuts into geometry instead of showing a hard edge
+    float depth = texelFetch(scene_depth, ivec2(gl_FragCoord.xy), 0).r;
+    float scene_distance = depth < 1.0 ? view_distance(depth) : 1e9;
+    float gap = scene_distance - fs_in.view_distance;
+    alpha *= clamp(gap / fs_in.softness, 0.0, 1.0);
+    if (alpha < 0.002) {
+        discard;
+    }
+
+    // Premultiplied alpha
+    FragColor = vec4(fs_in.color.rgb * light * alpha, alpha);
+    // Covers up the sunlight of whatever is behind, same as the color
+    Sunlight = vec4(0.0, 0.0, 0.0, alpha);
+}
@@ -0,0 +1,82 @@
+#version 450 core
+
+#include "common/frame_uniforms.glsl"
+
+// Must match ParticleRecord and EmitterBlock in particles.rs
+struct Particle {
+    vec3 position;
+    float spawn_time;
+    vec3 velocity;
+    uint emitter;
+};
+
+struct Emitter {
+    vec4 color_start;
+    vec4 color_end;
+    vec4 speed_curve;  // speed multiplier at 0, 1/3, 2/3 and 1 of the lifetime
+    float lifetime;
+    float size_start;
+    float size_end;
+    float gravity;
+    float softness;
+};
+
+layout(std430, binding = 0) readonly buffer Particles {
+    Particle particles[];
+};
+layout(std430, binding = 1) readonly buffer Emitters {
+    Emitter emitters[];
+};
+
+out VS_OUT {
+    vec2 corner;
+    vec4 color;
+    float view_distance;
+    float softness;
+}
+vs_out;
+
+// The live particles start here and wrap around the end of the ring buffer
+uniform uint first_particle;
+
+const vec2 CORNERS[] = vec2[](vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(-1.0, 1.0), vec2(1.0, 1.0));
+
+// Integral of the piecewise linear speed curve from 0 to t
+float speed_integral(vec4 curve, float t) {
+    float total = 0.0;
+    for (int i = 0; i < 3; ++i) {
+        float s0 = float(i) / 3.0;
+        float s1 = min(t, float(i + 1) / 3.0);
+        if (s1 <= s0) {
+            break;
+        }
+        float end_speed = mix(curve[i], curve[i + 1], (s1 - s0) * 3.0);
+        total += 0.5 * (curve[i] + end_speed) * (s1 - s0);
+    }
+    return total;
+}
+
+void main() {
+    Particle p = particles[(first_particle + uint(gl_InstanceID)) % uint(particles.length())];
+    Emitter e = emitters[p.emitter];
+    float age = uTransforms.time - p.spawn_time;
+    float t = age / e.lifetime;
+    if (!(t >= 0.0 && t < 1.0)) {
+        gl_Position = vec4(2.0, 2.0, 2.0, 1.0);  // dead or not born yet, outside the clip volume
+        return;
+    }
+
+    vec3 pos = p.position + p.velocity * e.lifetime * speed_integral(e.speed_curve, t);
+    pos.y -= 0.5 * e.gravity * age * age;
+
+    // Camera-facing quad
+    vec2 corner = CORNERS[gl_VertexID];
+    vec4 view_pos = uTransforms.view * vec4(pos, 1.0);
+    view_pos.xy += corner * 0.5 * mix(e.size_start, e.size_end, t);
+
+    vs_out.corner = corner;
+    vs_out.color = mix(e.color_start, e.color_end, t);
+    vs_out.view_distance = -view_pos.z;
+    vs_out.softness = e.softness;
+    gl_Position = uTransforms.proj * view_pos;
+}
@@ -0,0 +1,205 @@
+#version 450 core
+out vec4 FragColor;
+
+in vec2 uv;
+
+layout(binding = 0) uniform sampler2D scene_color;
+layout(binding = 1) uniform sampler2D scene_depth;
+layout(binding = 2) uniform samplerCube skybox;
+layout(binding = 3) uniform sampler2D scene_sunlight;  // the part of scene_color lit by the sun
+layout(binding = 4) uniform sampler2D occlusion;       // accumulated shadow and ambient visibility
+
+#include "common/frame_uniforms.glsl"
+
+uniform vec3 sky_tint;
+
+struct DirectionalLight {
+    vec3 direction;  // points towards the light
+    vec3 color;
+};
+uniform DirectionalLight sun;
+
+struct GodRays {
+    float intensity;  // 0 disables the effect
+    vec2 light_pos;   // screen uv
+    vec3 color;
+    int samples;
+    float density;
+    float weight;
+    float decay;
+    float exposure;
+};
+uniform GodRays god_rays;
+
+uniform float shadow_strength;
+uniform float ssao_strength;
+
+// Scene color with the shadows and ambient occlusion applied
+vec3 lit_scene(vec2 coord) {
+    vec3 color = texture(scene_color, coord).rgb;
+    vec3 sunlight = texture(scene_sunlight, coord).rgb;
+    vec2 shadow_and_visibility = texture(occlusion, coord).rg;
+    vec3 ambient = (color - sunlight) * mix(1.0, shadow_and_visibility.g, ssao_strength);
+    return ambient + sunlight * (1.0 - shadow_and_visibility.r * shadow_strength);
+}
+
+// ===================================== Water ========================================
+
+struct Water {
+    bool enabled;
+    float level;
+    vec3 color;
+    float clarity;
+    float wave_strength;
+};
+uniform Water water;
+
+struct ScreenSpaceReflections {
+    bool enabled;
+    int max_steps;
+    float step_size;
+    float thickness;
+};
+uniform ScreenSpaceReflections ssr;
+
+const int SSR_REFINE_STEPS = 6;
+
+vec3 view_pos_from_depth(vec2 coord, float depth) {
+    vec4 pos = inverse(uTransforms.proj) * vec4(vec3(coord, depth) * 2.0 - 1.0, 1.0);
+    return pos.xyz / pos.w;
+}
+
+vec2 project_to_screen(vec3 view_pos, out bool in_front) {
+    vec4 clip = uTransforms.proj * vec4(view_pos, 1.0);
+    in_front = clip.w > 0.0;
+    return clip.xy / clip.w * 0.5 + 0.5;
+}
+
+vec3 water_normal(vec2 pos) {
+    // A few sine waves in different directions
+    vec2 slope = vec2(0.0);
+    slope += vec2(0.8, 0.6) * cos(dot(pos, vec2(0.8, 0.6)) * 0.15 + uTransforms.time * 1.1);
+    slope += vec2(-0.5, 0.87) * cos(dot(pos, vec2(-0.5, 0.87)) * 0.31 + uTransforms.time * 1.7) * 0.6;
+    slope += vec2(0.2, -0.98) * cos(dot(pos, vec2(0.2, -0.98)) * 0.73 + uTransforms.time * 2.3) * 0.3;
+    slope *= water.wave_strength;
+    return normalize(vec3(-slope.x, 1.0, -slope.y));
+}
+
+// Marches the reflected ray in view space against the depth buffer.
+// Returns the color with alpha being the confidence of the hit.
+vec4 trace_screen_space(vec3 origin, vec3 dir) {
+    float step_size = ssr.step_size;
+    vec3 prev = origin;
+    vec3 pos = origin;
+    for (int i = 0; i < ssr.max_steps; ++i) {
+        pos += dir * step_size;
+        step_size *= 1.05;
+
+        bool in_front;
+        vec2 coord = project_to_screen(pos, in_front);
+        if (!in_front || any(lessThan(coord, vec2(0.0))) || any(greaterThan(coord, vec2(1.0)))) {
+            break;
+        }
+        float depth = texture(scene_depth, coord).r;
+        if (depth < 1.0) {
+            float behind = view_pos_from_depth(coord, depth).z - pos.z;
+            if (behind > 0.0 && behind < ssr.thickness) {
+                // Binary search between the last two samples for the exact hit
+                vec3 a = prev;
+                vec3 b = pos;
+                for (int j = 0; j < SSR_REFINE_STEPS; ++j) {
+                    vec3 mid = (a + b) * 0.5;
+                    coord = project_to_screen(mid, in_front);
+                    float mid_depth = texture(scene_depth, coord).r;
+                    if (mid_depth < 1.0 && view_pos_from_depth(coord, mid_depth).z > mid.z) {
+                        b = mid;
+                    } else {
+                        a = mid;
+                    }
+                }
+                coord = project_to_screen(b, in_front);
+
+                // Fade out near the screen edges where the information runs out
+                vec2 edge = smoothstep(vec2(0.0), vec2(0.1), coord) *
+                            smoothstep(vec2(1.0), vec2(0.9), coord);
+                return vec4(lit_scene(coord), edge.x * edge.y);
+            }
+        }
+        prev = pos;
+    }
+    return vec4(0.0);
+}
+
+// Shades the water surface if it's in front of the scene. Returns alpha 0 otherwise.
+vec4 shade_water(vec3 scene) {
+    vec3 camera_pos = uTransforms.camera_position.xyz;
+    vec3 view_dir = normalize(view_pos_from_depth(uv, 0.5));
+    vec3 dir = normalize(inverse(mat3(uTransforms.view)) * view_dir);
+    if (camera_pos.y <= water.level || dir.y >= 0.0) {
+        return vec4(0.0);
+    }
+
+    float water_dist = (water.level - camera_pos.y) / dir.y;
+    float depth = texture(scene_depth, uv).r;
+    float scene_dist = depth < 1.0 ? length(view_pos_from_depth(uv, depth)) : 1e20;
+    if (water_dist >= scene_dist) {
+        return vec4(0.0);
+    }
+
+    vec3 hit = camera_pos + dir * water_dist;
+    vec3 normal = water_normal(hit.xz);
+    vec3 reflected = reflect(dir, normal);
+    reflected.y = abs(reflected.y);  // waves shouldn't reflect the underwater
+
+    // Refraction: the bottom fades into the water color with depth
+    float absorption = exp(-(scene_dist - water_dist) / water.clarity);
+    vec3 refraction = mix(water.color, scene, absorption);
+
+    vec3 reflection = texture(skybox, reflected).rgb * sky_tint;
+    if (ssr.enabled) {
+        vec3 origin = (uTransforms.view * vec4(hit, 1.0)).xyz;
+        vec4 traced = trace_screen_space(origin, mat3(uTransforms.view) * reflected);
+        reflection = mix(reflection, traced.rgb, traced.a);
+    }
+
+    // Schlick's approximation for water
+    float cos_theta = max(dot(normal, -dir), 0.0);
+    float fresnel = 0.02 + 0.98 * pow(1.0 - cos_theta, 5.0);
+
+    vec3 specular = pow(max(dot(reflected, sun.direction), 0.0), 256.0) * sun.color;
+
+    return vec4(mix(refraction, reflection, fresnel) + specular, 1.0);
+}
+
+// ===================================================================================
+
+// Only the sky lets light through, terrain and objects block it
+vec3 light_source(vec2 coord) {
+    float is_sky = step(1.0, texture(scene_depth, coord).r);
+    return texture(scene_color, coord).rgb * is_sky;
+}
+
+vec3 calc_god_rays() {
+    vec2 delta = (uv - god_rays.light_pos) * god_rays.density / float(god_rays.samples);
+    vec2 coord = uv;
+    float illumination_decay = 1.0;
+    vec3 rays = vec3(0.0);
+    for (int i = 0; i < god_rays.samples; ++i) {
+        coord -= delta;
+        rays += light_source(coord) * illumination_decay * god_rays.weight;
+        illumination_decay *= god_rays.decay;
+    }
+    return rays * god_rays.color * god_rays.exposure * god_rays.intensity;
+}
+
+void main() {
+    vec3 color = lit_scene(uv);
+    if (water.enabled) {
+        vec4 water_color = shade_water(color);
+        color = mix(color, water_color.rgb, water_color.a);
+    }
+    if (god_rays.intensity > 0.0) {
+        color += calc_god_rays();
+    }
+    FragColor = vec4(color, 1.0);
+}
@@ -0,0 +1,56 @@
+#version 450 core
+out vec4 FragColor;
+
+in vec2 uv;
+
+// The post-processed scene at the render scale, filtered to the window size
+layout(binding = 0) uniform sampler2D source;
+
+uniform bool fxaa;
+
+// Below this local contrast a pixel isn't an edge
+const float EDGE_THRESHOLD = 0.125;
+const float EDGE_THRESHOLD_MIN = 0.0312;
+// How far along an edge it blurs, in source texels
+const float SPAN_MAX = 8.0;
+const float REDUCE_MUL = 1.0 / 8.0;
+const float REDUCE_MIN = 1.0 / 128.0;
+
+// Perceptual, the source is linear
+float luma(vec3 color) {
+    return sqrt(dot(color, vec3(0.299, 0.587, 0.114)));
+}
+
+// Blurs along the edge through the pixel, if there's one
+vec3 antialias(vec2 coord) {
+    vec2 texel = 1.0 / vec2(textureSize(source, 0));
+    vec3 center = texture(source, coord).rgb;
+    float nw = luma(texture(source, coord + vec2(-1.0, 1.0) * texel).rgb);
+    float ne = luma(texture(source, coord + vec2(1.0, 1.0) * texel).rgb);
+    float sw = luma(texture(source, coord + vec2(-1.0, -1.0) * texel).rgb);
+    float se = luma(texture(source, coord + vec2(1.0, -1.0) * texel).rgb);
+    float m = luma(center);
+    float luma_min = min(m, min(min(nw, ne), min(sw, se)));
+    float luma_max = max(m, max(max(nw, ne), max(sw, se)));
+    if (luma_max - luma_min < max(EDGE_THRESHOLD_MIN, luma_max * EDGE_THRESHOLD)) {
+        return center;
+    }
+
+    // Perpendicular to the gradient
+    vec2 dir = vec2(-((nw + ne) - (sw + se)), (nw + sw) - (ne + se));
+    float reduce = max((nw + ne + sw + se) * 0.25 * REDUCE_MUL, REDUCE_MIN);
+    float scale = 1.0 / (min(abs(dir.x), abs(dir.y)) + reduce);
+    dir = clamp(dir * scale, -SPAN_MAX, SPAN_MAX) * texel;
+
+    vec3 near = 0.5 * (texture(source, coord - dir / 6.0).rgb + texture(source, coord + dir / 6.0).rgb);
+    vec3 far = 0.5 * near +
+               0.25 * (texture(source, coord - dir * 0.5).rgb + texture(source, coord + dir * 0.5).rgb);
+    // The far samples went past the edge
+    float luma_far = luma(far);
+    return (luma_far < luma_min || luma_far > luma_max) ? near : far;
+}
+
+void main() {
+    vec3 color = fxaa ? antialias(uv) : texture(source, uv).rgb;
+    FragColor = vec4(color, 1.0);
+}
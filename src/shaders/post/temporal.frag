@@ -0,0 +1,121 @@
+#version 450 core
+out vec4 Result;  // shadow, ambient visibility, distance to camera, frames accumulated
+
+in vec2 uv;
+
+layout(binding = 1) uniform sampler2D scene_depth;
+layout(binding = 3) uniform sampler2D shadow_map;
+layout(binding = 4) uniform sampler2D history;
+
+#include "common/frame_uniforms.glsl"
+
+uniform mat4 prev_view_proj;
+uniform vec3 prev_camera_position;
+uniform bool history_valid;
+uniform float history_length;
+uniform uint frame_index;
+uniform int samples;
+
+struct Ssao {
+    bool enabled;
+    float radius;
+};
+uniform Ssao ssao;
+
+uniform float shadow_softness;
+
+const float PI = 3.14159265;
+const float GOLDEN_ANGLE = 2.39996323;
+
+vec3 view_pos_from_depth(vec2 coord, float depth) {
+    vec4 pos = inverse(uTransforms.proj) * vec4(vec3(coord, depth) * 2.0 - 1.0, 1.0);
+    return pos.xyz / pos.w;
+}
+
+// Different for every pixel and frame, so the accumulated samples don't repeat
+float interleaved_gradient_noise(float offset) {
+    vec2 pos = gl_FragCoord.xy + 5.588238 * float(frame_index % 64u) + offset;
+    return fract(52.9829189 * fract(dot(pos, vec2(0.06711056, 0.00583715))));
+}
+
+float soft_shadow(vec3 world_pos, float noise) {
+    vec4 sun_space = uTransforms.sun_vp * vec4(world_pos, 1.0);
+    vec3 proj_coords = sun_space.xyz / sun_space.w * 0.5 + 0.5;
+    float bias = 0.003;
+    vec2 radius = shadow_softness / vec2(textureSize(shadow_map, 0));
+    float shadow = 0.0;
+    // Vogel disk rotated by the noise
+    for (int i = 0; i < samples; ++i) {
+        float r = sqrt((float(i) + 0.5) / float(samples));
+        float theta = float(i) * GOLDEN_ANGLE + noise * 2.0 * PI;
+        vec2 offset = vec2(cos(theta), sin(theta)) * r * radius;
+        float depth = texture(shadow_map, proj_coords.xy + offset).r;
+        shadow += (proj_coords.z - bias) > depth ? 1.0 : 0.0;
+    }
+    return shadow / float(samples);
+}
+
+float ambient_occlusion(vec3 view_pos, float noise) {
+    vec3 normal = normalize(cross(dFdx(view_pos), dFdy(view_pos)));
+    if (dot(normal, view_pos) > 0.0) {
+        normal = -normal;
+    }
+    float occlusion = 0.0;
+    for (int i = 0; i < samples; ++i) {
+        // Random direction in the hemisphere, denser close to the point
+        float t = (float(i) + noise) / float(samples);
+        float phi = float(i) * GOLDEN_ANGLE + noise * 2.0 * PI;
+        float z = interleaved_gradient_noise(float(i) * 7.0) * 2.0 - 1.0;
+        vec3 dir = vec3(sqrt(1.0 - z * z) * vec2(cos(phi), sin(phi)), z);
+        dir *= sign(dot(dir, normal));
+        vec3 sample_pos = view_pos + dir * ssao.radius * mix(0.1, 1.0, t * t);
+
+        vec4 clip = uTransforms.proj * vec4(sample_pos, 1.0);
+        vec2 coord = clip.xy / clip.w * 0.5 + 0.5;
+        float depth = texture(scene_depth, coord).r;
+        float scene_z = view_pos_from_depth(coord, depth).z;
+        float in_range = smoothstep(0.0, 1.0, ssao.radius / abs(view_pos.z - scene_z));
+        occlusion += (scene_z > sample_pos.z + 0.05 ? 1.0 : 0.0) * in_range;
+    }
+    return 1.0 - occlusion / float(samples);
+}
+
+void main() {
+    float depth = texture(scene_depth, uv).r;
+    if (depth == 1.0) {
+        Result = vec4(0.0, 1.0, 0.0, 0.0);  // sky
+        return;
+    }
+
+    vec3 view_pos = view_pos_from_depth(uv, depth);
+    vec3 world_pos = (inverse(uTransforms.view) * vec4(view_pos, 1.0)).xyz;
+    float noise = interleaved_gradient_noise(0.0);
+
+    float shadow = soft_shadow(world_pos, noise);
+    float visibility = ssao.enabled ? ambient_occlusion(view_pos, noise) : 1.0;
+    vec2 current = vec2(shadow, visibility);
+    float distance_to_camera = length(view_pos);
+
+    // Where the point was on screen last frame. The difference to uv is the velocity
+    // caused by the camera motion.
+    vec4 prev_clip = prev_view_proj * vec4(world_pos, 1.0);
+    vec2 prev_uv = prev_clip.xy / prev_clip.w * 0.5 + 0.5;
+    bool reprojected = history_valid && prev_clip.w > 0.0 &&
+                       all(greaterThanEqual(prev_uv, vec2(0.0))) &&
+                       all(lessThanEqual(prev_uv, vec2(1.0)));
+
+    float frames = 1.0;
+    vec2 result = current;
+    if (reprojected) {
+        vec4 prev = texture(history, prev_uv);
+        // The history belongs to a different surface if it was at another distance,
+        // e.g. it's just been disoccluded
+        float expected_distance = distance(world_pos, prev_camera_position);
+        if (abs(prev.b - expected_distance) < 0.05 * expected_distance + 0.5) {
+            frames = min(prev.a + 1.0, history_length);
+            result = mix(prev.rg, current, 1.0 / frames);
+        }
+    }
+
+    Result = vec4(result, distance_to_camera, frames);
+}
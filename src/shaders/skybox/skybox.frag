@@ -1,10 +1,74 @@
 #version 450 core
-out vec4 FragColor;
+layout(location = 0) out vec4 FragColor;
+layout(location = 1) out vec4 Sunlight;  // the sky is never shadowed
 
 in vec3 TexCoords;
 
 layout(binding = 0) uniform samplerCube skybox;
 
+uniform vec3 sky_tint;  // driven by the time of day
+
+// Procedural sky, see PreethamSky in skybox.rs
+uniform bool procedural;
+uniform vec3 sun_direction;
+uniform vec3 sun_color;
+uniform float daylight;
+uniform float exposure;
+
+// Each component holds the coefficient for Y, x and y
+struct Perez {
+    vec3 a;
+    vec3 b;
+    vec3 c;
+    vec3 d;
+    vec3 e;
+};
+uniform Perez perez;
+uniform vec3 zenith;  // divided by the Perez function at the zenith
+
+#include "common/frame_uniforms.glsl"
+
+#include "common/fog.glsl"
+
+// The sky is infinitely far, so fog it as if it were at a fixed distance
+const float SKY_FOG_DISTANCE = 3000.0;
+
+const float SUN_ANGULAR_RADIUS = 0.01;
+
+vec3 preetham_sky(vec3 ray_dir) {
+    float cos_theta = max(ray_dir.y, 0.01);
+    float cos_gamma = clamp(dot(ray_dir, sun_direction), -1.0, 1.0);
+    float gamma = acos(cos_gamma);
+
+    vec3 f = (1.0 + perez.a * exp(perez.b / cos_theta)) *
+             (1.0 + perez.c * exp(perez.d * gamma) + perez.e * cos_gamma * cos_gamma);
+    vec3 Yxy = zenith * f;
+
+    // Tone map luminance, then xyY -> XYZ -> linear sRGB
+    float Y = 1.0 - exp(-exposure * Yxy.x);
+    float x = Yxy.y;
+    float y = max(Yxy.z, 0.0001);
+    vec3 XYZ = vec3(x * Y / y, Y, (1.0 - x - y) * Y / y);
+    vec3 rgb = mat3(3.2406, -0.9689, 0.0557,
+                    -1.5372, 1.8758, -0.2040,
+                    -0.4986, 0.0415, 1.0570) * XYZ;
+    rgb = max(rgb, vec3(0.0));
+
+    float sun_disk = smoothstep(SUN_ANGULAR_RADIUS, SUN_ANGULAR_RADIUS * 0.8, gamma);
+    rgb += sun_disk * sun_color * step(0.0, ray_dir.y);
+
+    // Fade to the night sky once the sun is down
+    return mix(sky_tint * 0.5, rgb, daylight);
+}
+
 void main() {
-    FragColor = texture(skybox, TexCoords);  // * vec4(1.0, 0.7, 0.7, 1.0);
+    vec3 ray_dir = normalize(TexCoords);
+    vec4 sky;
+    if (procedural) {
+        sky = vec4(preetham_sky(ray_dir), 1.0);
+    } else {
+        sky = texture(skybox, TexCoords) * vec4(sky_tint, 1.0);
+    }
+    FragColor = vec4(apply_fog(sky.rgb, uTransforms.camera_position.xyz, ray_dir, SKY_FOG_DISTANCE), sky.a);
+    Sunlight = vec4(0.0, 0.0, 0.0, 1.0);
 }
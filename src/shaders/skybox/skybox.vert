@@ -3,14 +3,7 @@ layout(location = 0) in vec3 Position;
 
 out vec3 TexCoords;
 
-layout(std140, binding = 1) uniform UTransforms {
-    mat4 mvp;
-    mat4 proj;
-    mat4 view;
-    mat4 model;
-    mat4 sun_vp;
-}
-uTransforms;
+#include "common/frame_uniforms.glsl"
 
 void main() {
     TexCoords = Position;
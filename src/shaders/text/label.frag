@@ -0,0 +1,37 @@
+#version 450 core
+layout(location = 0) out vec4 FragColor;
+
+layout(binding = 0) uniform sampler2D font_atlas;   // signed distance, 0.5 on the edge
+layout(binding = 1) uniform sampler2D scene_depth;  // the labels are drawn after post-processing
+
+// In pixels, the scene depth can be at a different resolution, see `RenderSettings`
+uniform vec2 viewport_size;
+
+in VS_OUT {
+    vec2 uv;
+    vec4 color;
+    flat int on_top;
+}
+fs_in;
+
+// Dark outline so that the text is readable over anything, in distance units
+const float OUTLINE_WIDTH = 0.12;
+
+void main() {
+    ivec2 depth_texel = ivec2(gl_FragCoord.xy / viewport_size * vec2(textureSize(scene_depth, 0)));
+    if (fs_in.on_top == 0 && gl_FragCoord.z > texelFetch(scene_depth, depth_texel, 0).r) {
+        discard;
+    }
+
+    float distance = texture(font_atlas, fs_in.uv).r;
+    float aa = max(fwidth(distance), 0.001);
+    float fill = smoothstep(0.5 - aa, 0.5 + aa, distance);
+    float outline = smoothstep(0.5 - OUTLINE_WIDTH - aa, 0.5 - OUTLINE_WIDTH + aa, distance);
+    float alpha = outline * fs_in.color.a;
+    if (alpha < 0.002) {
+        discard;
+    }
+
+    // Premultiplied alpha
+    FragColor = vec4(fs_in.color.rgb * fill * alpha, alpha);
+}
@@ -0,0 +1,52 @@
+#version 450 core
+
+#include "common/frame_uniforms.glsl"
+
+// Must match GlyphBlock in text.rs
+struct Glyph {
+    vec3 anchor;
+    float height;  // of a line, world units or pixels
+    vec2 offset;   // of the bottom left corner from the anchor, in lines
+    vec2 size;     // in lines
+    vec2 uv_min;
+    vec2 uv_max;
+    vec4 color;
+    uint flags;
+};
+const uint FLAG_SCREEN_SIZE = 1u;
+const uint FLAG_ON_TOP = 2u;
+
+layout(std430, binding = 0) readonly buffer Glyphs {
+    Glyph glyphs[];
+};
+
+uniform vec2 viewport_size;
+
+out VS_OUT {
+    vec2 uv;
+    vec4 color;
+    flat int on_top;
+}
+vs_out;
+
+const vec2 CORNERS[] = vec2[](vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(0.0, 1.0), vec2(1.0, 1.0));
+
+void main() {
+    Glyph glyph = glyphs[gl_InstanceID];
+    vec2 corner = CORNERS[gl_VertexID];
+    vec2 offset = (glyph.offset + corner * glyph.size) * glyph.height;
+
+    // Parallel to the screen, like spherical billboards
+    vec4 view_pos = uTransforms.view * vec4(glyph.anchor, 1.0);
+    if ((glyph.flags & FLAG_SCREEN_SIZE) != 0u) {
+        gl_Position = uTransforms.proj * view_pos;
+        gl_Position.xy += offset * 2.0 / viewport_size * gl_Position.w;
+    } else {
+        view_pos.xy += offset;
+        gl_Position = uTransforms.proj * view_pos;
+    }
+
+    vs_out.uv = mix(glyph.uv_min, glyph.uv_max, corner);
+    vs_out.color = glyph.color;
+    vs_out.on_top = int((glyph.flags & FLAG_ON_TOP) != 0u);
+}
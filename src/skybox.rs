@@ -1,86 +1,68 @@
 use std::mem::size_of;
+use std::path::Path;
 
 use gl::types::*;
+use glam::Vec3;
 use thiserror::Error;
 
+use crate::atmosphere::Atmosphere;
+use crate::opengl::bindings::{self, Sampler, TextureUnit};
+use crate::opengl::objects::{Buffer, Cubemap, VertexArray};
 use crate::opengl::shader::{Program, ShaderError};
+use crate::profiler;
+use crate::texture::{calculate_mip_levels, HdrFormat, HdrImage};
 use crate::utils::size_of_slice;
 
 #[derive(Debug, Error)]
 pub enum SkyboxError {
     #[error("Skybox shader error: {0}")]
     Shader(#[from] ShaderError),
+    #[error("Skybox image error: {0}")]
+    Image(String),
 }
 
+/// Faces shipped with the game, in the order `Skybox::decode_job` expects
+pub const DEFAULT_FACES: [&str; 6] = [
+    "textures/skybox/default/right.png",
+    "textures/skybox/default/left.png",
+    "textures/skybox/default/top.png",
+    "textures/skybox/default/bottom.png",
+    "textures/skybox/default/front.png",
+    "textures/skybox/default/back.png",
+];
+
+/// Work group size of skybox/equirect.comp
+const EQUIRECT_GROUP_SIZE: u32 = 8;
+
 pub struct Skybox {
-    id: GLuint,
+    cubemap: Cubemap,
     shader: Program,
-    vao: GLuint,
-    vbo: GLuint,
+    vao: VertexArray,
+    /// Only kept alive for the vertex array
+    _vbo: Buffer,
 }
 
-impl Skybox {
-    /// right, left, top, bottom, front, back
-    pub fn from(paths: [&str; 6]) -> Result<Self, SkyboxError> {
-        // Generate texture
-        let mut id: GLuint = 0;
-        unsafe {
-            gl::GenTextures(1, &mut id);
-            gl::BindTexture(gl::TEXTURE_CUBE_MAP, id);
-
-            gl::TexParameteri(
-                gl::TEXTURE_CUBE_MAP,
-                gl::TEXTURE_WRAP_S,
-                gl::CLAMP_TO_EDGE as GLint,
-            );
-            gl::TexParameteri(
-                gl::TEXTURE_CUBE_MAP,
-                gl::TEXTURE_WRAP_T,
-                gl::CLAMP_TO_EDGE as GLint,
-            );
-            gl::TexParameteri(
-                gl::TEXTURE_CUBE_MAP,
-                gl::TEXTURE_WRAP_R,
-                gl::CLAMP_TO_EDGE as GLint,
-            );
-            gl::TexParameteri(
-                gl::TEXTURE_CUBE_MAP,
-                gl::TEXTURE_MIN_FILTER,
-                gl::LINEAR as GLint,
-            );
-            gl::TexParameteri(
-                gl::TEXTURE_CUBE_MAP,
-                gl::TEXTURE_MAG_FILTER,
-                gl::LINEAR as GLint,
-            );
-        }
+/// The six faces decoded on a worker thread, see `Skybox::decode_job`
+pub struct SkyboxImages {
+    size: u32,
+    faces: Vec<image::RgbImage>,
+}
 
-        // Load images
-        for (i, path) in paths.iter().enumerate() {
-            let img = image::open(path)
-                .expect("Can't load skybox image")
-                .into_rgb8();
-            let (width, height) = img.dimensions();
-            unsafe {
-                // Send to GPU
-                gl::TexImage2D(
-                    gl::TEXTURE_CUBE_MAP_POSITIVE_X + i as u32,
-                    0,
-                    gl::SRGB8 as GLint,
-                    width as GLint,
-                    height as GLint,
-                    0,
-                    gl::RGB,
-                    gl::UNSIGNED_BYTE,
-                    img.as_raw().as_ptr() as *const std::ffi::c_void,
-                );
-            }
-        }
+impl Skybox {
+    /// Starts with a plain grey cubemap, the faces are set with `set_images` once
+    /// they're decoded
+    pub fn new() -> Result<Self, SkyboxError> {
+        let grey = image::RgbImage::from_pixel(1, 1, image::Rgb([128, 128, 128]));
+        let placeholder = SkyboxImages {
+            size: 1,
+            faces: vec![grey; 6],
+        };
+        let cubemap = upload_cubemap(&placeholder);
 
         // Create shader
         let shader = Program::new()
-            .vertex_shader(include_str!("shaders/skybox/skybox.vert"))?
-            .fragment_shader(include_str!("shaders/skybox/skybox.frag"))?
+            .vertex_shader(shader_file!("skybox/skybox.vert"))?
+            .fragment_shader(shader_file!("skybox/skybox.frag"))?
             .link()?;
         shader.set_used();
 
@@ -131,54 +113,249 @@ impl Skybox {
         ];
 
         // Init buffers
-        let mut vao: GLuint = 0;
-        let mut vbo: GLuint = 0;
+        let vao = VertexArray::new();
+        let vbo = Buffer::new();
         unsafe {
-            gl::CreateVertexArrays(1, &mut vao);
-            gl::CreateBuffers(1, &mut vbo);
-
             // Upload vertices
             gl::NamedBufferStorage(
-                vbo,
+                vbo.id(),
                 size_of_slice(&vertices) as isize,
                 vertices.as_ptr() as *const _,
                 0,
             );
 
             // Describe vertex buffer
-            gl::VertexArrayVertexBuffer(vao, 0, vbo, 0, (size_of::<f32>() * 3) as i32);
-            gl::VertexArrayAttribFormat(vao, 0, 3, gl::FLOAT, gl::FALSE, 0);
-            gl::EnableVertexArrayAttrib(vao, 0);
+            let stride = (size_of::<f32>() * 3) as i32;
+            gl::VertexArrayVertexBuffer(vao.id(), 0, vbo.id(), 0, stride);
+            gl::VertexArrayAttribFormat(vao.id(), 0, 3, gl::FLOAT, gl::FALSE, 0);
+            gl::EnableVertexArrayAttrib(vao.id(), 0);
         }
 
         Ok(Skybox {
-            id,
+            cubemap,
             shader,
             vao,
-            vbo,
+            _vbo: vbo,
         })
     }
 
-    pub fn draw(&self) {
+    /// Projects an equirectangular panorama, like most HDR skies come as, onto the
+    /// cubemap. The image is decoded here, so it holds up startup for a moment.
+    pub fn from_equirect(path: &str) -> Result<Self, SkyboxError> {
+        let mut skybox = Skybox::new()?;
+        skybox.load_equirect(path)?;
+        Ok(skybox)
+    }
+
+    /// Replaces the cubemap with the panorama, see `from_equirect`
+    pub fn load_equirect(&mut self, path: &str) -> Result<(), SkyboxError> {
+        let panorama =
+            HdrImage::load(Path::new(path)).map_err(|e| SkyboxError::Image(e.to_string()))?;
+        self.cubemap = equirect_to_cubemap(&panorama)?;
+        Ok(())
+    }
+
+    /// Decodes the faces, in the order right, left, top, bottom, front, back.
+    /// They have to be square and all the same size.
+    pub fn decode_job(
+        paths: [&str; 6],
+    ) -> impl FnOnce() -> Result<SkyboxImages, String> + Send + 'static {
+        let paths = paths.map(str::to_owned);
+        move || {
+            let mut faces = Vec::with_capacity(6);
+            for path in &paths {
+                let face = image::open(path)
+                    .map_err(|e| format!("{}: {}", path, e))?
+                    .into_rgb8();
+                faces.push(face);
+            }
+            let size = faces[0].width();
+            if faces.iter().any(|face| face.dimensions() != (size, size)) {
+                return Err(format!("Skybox faces in {} aren't the same size", paths[0]));
+            }
+            Ok(SkyboxImages { size, faces })
+        }
+    }
+
+    /// Replaces the cubemap with the decoded faces
+    pub fn set_images(&mut self, images: &SkyboxImages) {
+        self.cubemap = upload_cubemap(images);
+    }
+
+    pub fn cubemap(&self) -> GLuint {
+        self.cubemap.id()
+    }
+
+    pub fn draw(&self, atmosphere: &Atmosphere) -> Result<(), SkyboxError> {
         unsafe {
             gl::DepthFunc(gl::LEQUAL);
         }
         self.shader.set_used();
+        self.shader.set_vec3("sky_tint", &atmosphere.sky_tint())?;
+        atmosphere.set_fog_uniforms(&self.shader)?;
+
+        let sky = &atmosphere.sky;
+        self.shader.set_i32("procedural", sky.procedural as i32)?;
+        if sky.procedural {
+            let sun_direction = atmosphere.time_of_day.sun_direction();
+            let model = PreethamSky::new(sun_direction, sky.turbidity);
+            self.shader.set_vec3("sun_direction", &sun_direction)?;
+            self.shader
+                .set_vec3("sun_color", &atmosphere.light().color)?;
+            self.shader.set_f32("daylight", atmosphere.daylight())?;
+            self.shader.set_f32("exposure", sky.exposure)?;
+            for (name, coeffs) in ["perez.a", "perez.b", "perez.c", "perez.d", "perez.e"]
+                .iter()
+                .zip(model.perez.iter())
+            {
+                self.shader.set_vec3(name, coeffs)?;
+            }
+            self.shader.set_vec3("zenith", &model.zenith)?;
+        }
 
         unsafe {
-            gl::BindVertexArray(self.vao);
-            gl::BindTexture(gl::TEXTURE_CUBE_MAP, self.id);
+            gl::BindVertexArray(self.vao.id());
+            bindings::bind_texture_sampled(
+                TextureUnit::SOURCE,
+                self.cubemap.id(),
+                Sampler::LinearClamp,
+            );
             gl::DrawArrays(gl::TRIANGLES, 0, 36);
+            profiler::count_draw_call();
             gl::DepthFunc(gl::LESS);
         }
+        Ok(())
     }
 }
 
-impl Drop for Skybox {
-    fn drop(&mut self) {
-        unsafe {
-            gl::DeleteBuffers(1, &self.vbo as *const _);
-            gl::DeleteVertexArrays(1, &self.vao as *const _);
+/// Preetham et al. "A Practical Analytic Model for Daylight".
+/// All vectors hold (Y, x, y): luminance and chromaticity.
+struct PreethamSky {
+    /// Perez distribution coefficients A to E, already divided by the value at the zenith
+    perez: [Vec3; 5],
+    zenith: Vec3,
+}
+
+impl PreethamSky {
+    fn new(sun_direction: Vec3, turbidity: f32) -> Self {
+        let t = turbidity;
+        // The model is only valid with the sun above the horizon
+        let theta_s = sun_direction.y.clamp(0.0, 1.0).acos().min(1.55);
+
+        let perez = [
+            Vec3::new(
+                0.1787 * t - 1.4630,
+                -0.0193 * t - 0.2592,
+                -0.0167 * t - 0.2608,
+            ),
+            Vec3::new(
+                -0.3554 * t + 0.4275,
+                -0.0665 * t + 0.0008,
+                -0.0950 * t + 0.0092,
+            ),
+            Vec3::new(
+                -0.0227 * t + 5.3251,
+                -0.0004 * t + 0.2125,
+                -0.0079 * t + 0.2102,
+            ),
+            Vec3::new(
+                0.1206 * t - 2.5771,
+                -0.0641 * t - 0.8989,
+                -0.0441 * t - 1.6537,
+            ),
+            Vec3::new(
+                -0.0670 * t + 0.3703,
+                -0.0033 * t + 0.0452,
+                -0.0109 * t + 0.0529,
+            ),
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (std::f32::consts::PI - 2.0 * theta_s);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+
+        let th = Vec3::new(theta_s.powi(3), theta_s.powi(2), theta_s);
+        let zenith_x = t * t * (th.dot(Vec3::new(0.00166, -0.00375, 0.00209)))
+            + t * (th.dot(Vec3::new(-0.02903, 0.06377, -0.03202)) + 0.00394)
+            + (th.dot(Vec3::new(0.11693, -0.21196, 0.06052)) + 0.25886);
+        let zenith_y = t * t * (th.dot(Vec3::new(0.00275, -0.00610, 0.00317)))
+            + t * (th.dot(Vec3::new(-0.04214, 0.08970, -0.04153)) + 0.00516)
+            + (th.dot(Vec3::new(0.15346, -0.26756, 0.06670)) + 0.26688);
+
+        // The shader evaluates F(theta, gamma) / F(0, theta_s), so bake the denominator
+        // into the zenith values
+        let [a, b, c, d, e] = perez;
+        let f0 = (Vec3::ONE + a * b.exp())
+            * (Vec3::ONE + c * (d * theta_s).exp() + e * theta_s.cos() * theta_s.cos());
+        let zenith = Vec3::new(zenith_luminance.max(0.0), zenith_x, zenith_y) / f0;
+
+        PreethamSky { perez, zenith }
+    }
+}
+
+/// Mipmapped for the IBL precomputation, see `ibl`
+fn upload_cubemap(images: &SkyboxImages) -> Cubemap {
+    let cubemap = Cubemap::new();
+    let size = images.size as GLint;
+    let levels = calculate_mip_levels(images.size as usize, images.size as usize);
+    unsafe {
+        // SRGB8 can't generate mipmaps since it's not renderable
+        gl::TextureStorage2D(cubemap.id(), levels, gl::SRGB8_ALPHA8, size, size);
+        // The faces are layers in the order of the paths
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+        for (i, face) in images.faces.iter().enumerate() {
+            gl::TextureSubImage3D(
+                cubemap.id(),
+                0,
+                0,
+                0,
+                i as GLint,
+                size,
+                size,
+                1,
+                gl::RGB,
+                gl::UNSIGNED_BYTE,
+                face.as_raw().as_ptr() as *const _,
+            );
         }
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+        gl::GenerateTextureMipmap(cubemap.id());
+    }
+    cubemap
+}
+
+/// Renders the panorama into a mipmapped float cubemap with a compute pass
+fn equirect_to_cubemap(panorama: &HdrImage) -> Result<Cubemap, SkyboxError> {
+    let shader = Program::new()
+        .compute_shader(shader_file!("skybox/equirect.comp"))?
+        .link()?;
+    // A face covers a quarter of the panorama's width
+    let size = (panorama.width / 4).max(1);
+    let source = panorama.upload(HdrFormat::Rgb16F);
+    let cubemap = Cubemap::new();
+    unsafe {
+        let levels = calculate_mip_levels(size as usize, size as usize);
+        gl::TextureStorage2D(
+            cubemap.id(),
+            levels,
+            gl::RGBA16F,
+            size as GLint,
+            size as GLint,
+        );
+    }
+
+    shader.set_used();
+    // Repeats so that the seam at the back is filtered across
+    bindings::bind_texture_sampled(TextureUnit::SOURCE, source, Sampler::LinearRepeat);
+    unsafe {
+        gl::BindImageTexture(0, cubemap.id(), 0, gl::TRUE, 0, gl::WRITE_ONLY, gl::RGBA16F);
+    }
+    let groups = (size + EQUIRECT_GROUP_SIZE - 1) / EQUIRECT_GROUP_SIZE;
+    shader.dispatch(groups, groups, 6);
+    unsafe {
+        // Sampled by the skybox pass, and the mipmaps are made from it
+        gl::MemoryBarrier(gl::TEXTURE_FETCH_BARRIER_BIT | gl::TEXTURE_UPDATE_BARRIER_BIT);
+        gl::GenerateTextureMipmap(cubemap.id());
     }
+    bindings::delete_textures(&[source]);
+    Ok(cubemap)
 }
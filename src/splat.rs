@@ -0,0 +1,639 @@
+use std::mem::size_of;
+
+use gl::types::*;
+use glam::{IVec2, Vec2, Vec4};
+use image::imageops::FilterType;
+use image::{GenericImageView, GrayImage, ImageError};
+
+use crate::material::MaterialParams;
+use crate::opengl::bindings::{self, Sampler, TextureUnit};
+use crate::opengl::shader::{Program, ShaderError};
+use crate::texture::{create_texture_array, upload_texture_layer};
+
+pub const MAX_LAYERS: usize = 4;
+/// Albedo of the first layer of a new terrain
+pub const DEFAULT_ALBEDO: &str = "textures/checkerboard.png";
+
+/// All layer images are resized to this on import so they fit into texture arrays
+const LAYER_TEXTURE_SIZE: u32 = 1024;
+const SPLATMAP_SIZE: usize = 1024;
+
+/// Appended to the path of the albedo a normal map was generated from
+const GENERATED_SUFFIX: &str = " (generated)";
+
+/// Uniform buffer binding used by the terrain fragment shader
+const MATERIAL_UBO_BINDING: u32 = 2;
+
+// Must match the flags in terrain.frag.glsl
+const FLAG_TRIPLANAR: i32 = 1;
+const FLAG_HAS_NORMAL: i32 = 2;
+const FLAG_HAS_ROUGHNESS: i32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayerMap {
+    Albedo,
+    Normal,
+    Roughness,
+}
+
+impl LayerMap {
+    pub const ALL: [LayerMap; 3] = [LayerMap::Albedo, LayerMap::Normal, LayerMap::Roughness];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LayerMap::Albedo => "Albedo",
+            LayerMap::Normal => "Normal",
+            LayerMap::Roughness => "Roughness",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SplatLayer {
+    pub name: String,
+    /// World units per texture repeat
+    pub tiling: f32,
+    pub triplanar: bool,
+    /// Multiplied with the layer textures
+    pub params: MaterialParams,
+    /// Paths of the imported images, in `LayerMap` order
+    pub paths: [Option<String>; 3],
+
+    /// Slice in the texture arrays. Layers can be reordered without touching the textures.
+    slice: usize,
+    /// Never given to another layer, unlike the slice which is reused after a removal
+    id: u64,
+}
+
+impl SplatLayer {
+    pub fn path(&self, map: LayerMap) -> Option<&str> {
+        self.paths[map as usize].as_deref()
+    }
+}
+
+/// The albedo a normal map path says it was generated from, see
+/// `SplatMaterial::apply_generated_normal_map`
+pub fn generated_from(path: &str) -> Option<&str> {
+    path.strip_suffix(GENERATED_SUFFIX)
+}
+
+/// Splatmap weights from before or after a stroke, see `SplatMaterial::swap_splatmap`
+pub struct SplatCopy(Vec<[u8; 4]>);
+
+/// Up to `MAX_LAYERS` textured layers blended by the RGBA weights in the splatmap
+pub struct SplatMaterial {
+    pub layers: Vec<SplatLayer>,
+    /// Multiplies the tiling of every layer
+    pub tiling_scale: f32,
+
+    albedo_array: GLuint,
+    normal_array: GLuint,
+    roughness_array: GLuint,
+    splatmap: GLuint,
+    /// What's in the splatmap, kept to paint on
+    splat_pixels: Vec<[u8; 4]>,
+
+    ubo: GLuint,
+    uploaded_block: Option<MaterialBlock>,
+
+    next_layer_id: u64,
+}
+
+impl SplatMaterial {
+    /// Starts with one grey layer, see `DEFAULT_ALBEDO`
+    pub fn new() -> Self {
+        let create_array = |format: GLenum| {
+            create_texture_array(LAYER_TEXTURE_SIZE, LAYER_TEXTURE_SIZE, MAX_LAYERS, format)
+        };
+        let albedo_array = create_array(gl::SRGB8_ALPHA8);
+        let normal_array = create_array(gl::RGBA8);
+        let roughness_array = create_array(gl::R8);
+
+        // Everything is painted with the first layer initially
+        let mut splatmap: GLuint = 0;
+        let pixels: Vec<[u8; 4]> = vec![[255, 0, 0, 0]; SPLATMAP_SIZE * SPLATMAP_SIZE];
+        unsafe {
+            gl::CreateTextures(gl::TEXTURE_2D, 1, &mut splatmap);
+            gl::TextureStorage2D(
+                splatmap,
+                1,
+                gl::RGBA8,
+                SPLATMAP_SIZE as i32,
+                SPLATMAP_SIZE as i32,
+            );
+            gl::TextureSubImage2D(
+                splatmap,
+                0,
+                0,
+                0,
+                SPLATMAP_SIZE as i32,
+                SPLATMAP_SIZE as i32,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                pixels.as_ptr() as *const _,
+            );
+        }
+
+        let mut ubo: GLuint = 0;
+        unsafe {
+            gl::CreateBuffers(1, &mut ubo);
+            gl::NamedBufferStorage(
+                ubo,
+                size_of::<MaterialBlock>() as isize,
+                std::ptr::null(),
+                gl::DYNAMIC_STORAGE_BIT,
+            );
+        }
+
+        let mut material = SplatMaterial {
+            layers: vec![],
+            tiling_scale: 1.0,
+
+            albedo_array,
+            normal_array,
+            roughness_array,
+            splatmap,
+            splat_pixels: pixels,
+
+            ubo,
+            uploaded_block: None,
+
+            next_layer_id: 0,
+        };
+        material.add_layer();
+        material
+    }
+
+    /// Returns false if there's no room for another layer
+    pub fn add_layer(&mut self) -> bool {
+        let free_slice =
+            (0..MAX_LAYERS).find(|&slice| self.layers.iter().all(|l| l.slice != slice));
+        if let Some(slice) = free_slice {
+            // Neutral defaults until something is imported
+            self.clear_slice(self.albedo_array, slice, [128, 128, 128, 255]);
+            self.clear_slice(self.normal_array, slice, [128, 128, 255, 255]);
+            self.clear_slice(self.roughness_array, slice, [200, 0, 0, 0]);
+
+            self.layers.push(SplatLayer {
+                name: format!("Layer {}", self.layers.len() + 1),
+                tiling: 16.0,
+                triplanar: false,
+                params: MaterialParams {
+                    metallic: 0.0,
+                    ..MaterialParams::default()
+                },
+                paths: [None, None, None],
+                slice,
+                id: self.next_layer_id,
+            });
+            self.next_layer_id += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Starts over with `count` neutral layers, at least one
+    pub fn reset_layers(&mut self, count: usize) {
+        self.layers.clear();
+        for _ in 0..count.clamp(1, MAX_LAYERS) {
+            self.add_layer();
+        }
+    }
+
+    pub fn remove_layer(&mut self, index: usize) {
+        // Always keep at least one layer
+        if self.layers.len() > 1 && index < self.layers.len() {
+            let layer = self.layers.remove(index);
+            // So that the next layer to get the slice starts out unpainted
+            for texel in &mut self.splat_pixels {
+                texel[layer.slice] = 0;
+            }
+            self.upload_splatmap();
+        }
+    }
+
+    pub fn move_layer(&mut self, from: usize, to: usize) {
+        if from < self.layers.len() && to < self.layers.len() {
+            let layer = self.layers.remove(from);
+            self.layers.insert(to, layer);
+        }
+    }
+
+    /// Returns what's needed to decode an image for the layer on a worker thread, the
+    /// pixels are uploaded with `apply_import`. The layer is identified by its id
+    /// like in `normal_generation_job`.
+    pub fn import_job(
+        &self,
+        index: usize,
+        map: LayerMap,
+        path: &str,
+    ) -> impl FnOnce() -> std::result::Result<DecodedLayerImage, ImageError> + Send + 'static {
+        let layer_id = self.layers[index].id;
+        let path = path.to_owned();
+        move || {
+            let img = image::open(&path)?;
+            let img = if img.width() != LAYER_TEXTURE_SIZE || img.height() != LAYER_TEXTURE_SIZE {
+                img.resize_exact(LAYER_TEXTURE_SIZE, LAYER_TEXTURE_SIZE, FilterType::Triangle)
+            } else {
+                img
+            };
+            let pixels = match map {
+                LayerMap::Albedo | LayerMap::Normal => img.into_rgba8().into_raw(),
+                LayerMap::Roughness => img.into_luma8().into_raw(),
+            };
+            Ok(DecodedLayerImage {
+                layer_id,
+                map,
+                path,
+                pixels,
+            })
+        }
+    }
+
+    /// Uploads an image decoded by `import_job`.
+    /// Does nothing if the layer has been removed since.
+    pub fn apply_import(&mut self, image: DecodedLayerImage) {
+        let layer = match self.layers.iter_mut().find(|l| l.id == image.layer_id) {
+            Some(layer) => layer,
+            None => return,
+        };
+        let (texture, format) = match image.map {
+            LayerMap::Albedo => (self.albedo_array, gl::RGBA),
+            LayerMap::Normal => (self.normal_array, gl::RGBA),
+            LayerMap::Roughness => (self.roughness_array, gl::RED),
+        };
+        upload_texture_layer(
+            texture,
+            layer.slice,
+            LAYER_TEXTURE_SIZE,
+            LAYER_TEXTURE_SIZE,
+            format,
+            &image.pixels,
+        );
+        layer.paths[image.map as usize] = Some(image.path);
+    }
+
+    /// Returns what's needed to generate a normal map for the layer on a worker thread.
+    /// The layer is identified by its id so that reordering or replacing it in the
+    /// meantime is harmless.
+    pub fn normal_generation_job(
+        &self,
+        index: usize,
+        source_path: &str,
+        strength: f32,
+    ) -> impl FnOnce() -> std::result::Result<GeneratedNormalMap, ImageError> + Send + 'static {
+        let layer_id = self.layers[index].id;
+        let source_path = source_path.to_owned();
+        move || {
+            let img = image::open(&source_path)?.resize_exact(
+                LAYER_TEXTURE_SIZE,
+                LAYER_TEXTURE_SIZE,
+                FilterType::Triangle,
+            );
+            let pixels = normal_map_from_height(&img.into_luma8(), strength);
+            Ok(GeneratedNormalMap {
+                layer_id,
+                source_path,
+                pixels,
+            })
+        }
+    }
+
+    /// Uploads a normal map produced by `normal_generation_job`.
+    /// Does nothing if the layer has been removed since.
+    pub fn apply_generated_normal_map(&mut self, normal_map: GeneratedNormalMap) {
+        let layer = match self.layers.iter_mut().find(|l| l.id == normal_map.layer_id) {
+            Some(layer) => layer,
+            None => return,
+        };
+        upload_texture_layer(
+            self.normal_array,
+            layer.slice,
+            LAYER_TEXTURE_SIZE,
+            LAYER_TEXTURE_SIZE,
+            gl::RGBA,
+            &normal_map.pixels,
+        );
+        layer.paths[LayerMap::Normal as usize] =
+            Some(format!("{}{}", normal_map.source_path, GENERATED_SUFFIX));
+    }
+
+    fn clear_slice(&self, texture: GLuint, slice: usize, color: [u8; 4]) {
+        unsafe {
+            gl::ClearTexSubImage(
+                texture,
+                0,
+                0,
+                0,
+                slice as i32,
+                LAYER_TEXTURE_SIZE as i32,
+                LAYER_TEXTURE_SIZE as i32,
+                1,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                color.as_ptr() as *const _,
+            );
+            gl::GenerateTextureMipmap(texture);
+        }
+    }
+
+    /// Blends the weights in a circle toward the layer, fading out over `falloff` of the
+    /// radius. The center and radius are in [0, 1] terrain coordinates and `amount` is
+    /// how far the center goes toward the layer, [0, 1].
+    pub fn paint(&mut self, layer: usize, center: Vec2, radius: f32, falloff: f32, amount: f32) {
+        if layer >= self.layers.len() || radius <= 0.0 {
+            return;
+        }
+        let layer_channel = self.layers[layer].slice;
+        let size = SPLATMAP_SIZE as f32;
+        let max_texel = IVec2::splat(SPLATMAP_SIZE as i32 - 1);
+        let first = ((center - radius) * size)
+            .floor()
+            .as_ivec2()
+            .clamp(IVec2::ZERO, max_texel);
+        let last = ((center + radius) * size)
+            .ceil()
+            .as_ivec2()
+            .clamp(IVec2::ZERO, max_texel);
+        let hard_radius = radius * (1.0 - falloff);
+
+        for y in first.y..=last.y {
+            for x in first.x..=last.x {
+                let uv = (Vec2::new(x as f32, y as f32) + 0.5) / size;
+                let distance = uv.distance(center);
+                if distance >= radius {
+                    continue;
+                }
+                let fade = if distance <= hard_radius {
+                    1.0
+                } else {
+                    1.0 - (distance - hard_radius) / (radius - hard_radius)
+                };
+                let t = (amount * fade).clamp(0.0, 1.0);
+                let texel = &mut self.splat_pixels[y as usize * SPLATMAP_SIZE + x as usize];
+                for (channel, weight) in texel.iter_mut().enumerate() {
+                    let target = if channel == layer_channel { 255.0 } else { 0.0 };
+                    let blended = *weight as f32 + (target - *weight as f32) * t;
+                    *weight = blended.round() as u8;
+                }
+            }
+        }
+
+        // Only the painted rectangle is uploaded
+        let extent = last - first + IVec2::ONE;
+        let offset = first.y as usize * SPLATMAP_SIZE + first.x as usize;
+        unsafe {
+            gl::PixelStorei(gl::UNPACK_ROW_LENGTH, SPLATMAP_SIZE as GLint);
+            gl::TextureSubImage2D(
+                self.splatmap,
+                0,
+                first.x,
+                first.y,
+                extent.x,
+                extent.y,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                self.splat_pixels[offset..].as_ptr() as *const _,
+            );
+            gl::PixelStorei(gl::UNPACK_ROW_LENGTH, 0);
+        }
+    }
+
+    /// The weights as RGBA8 rows and the size of the splatmap, for saving it. The
+    /// channels are in the order of the layers, which is how `reset_layers` hands out
+    /// the slices when the project is opened again.
+    pub fn splatmap_pixels(&self) -> (Vec<u8>, usize) {
+        let mut pixels = Vec::with_capacity(self.splat_pixels.len() * 4);
+        for texel in &self.splat_pixels {
+            let mut ordered = [0; 4];
+            for (weight, layer) in ordered.iter_mut().zip(&self.layers) {
+                *weight = texel[layer.slice];
+            }
+            pixels.extend_from_slice(&ordered);
+        }
+        (pixels, SPLATMAP_SIZE)
+    }
+
+    /// The weights as they are now, to undo a stroke with
+    pub fn copy_splatmap(&self) -> SplatCopy {
+        SplatCopy(self.splat_pixels.clone())
+    }
+
+    /// Puts the copy on the terrain and the weights into the copy, so that the same
+    /// copy swaps them back
+    pub fn swap_splatmap(&mut self, copy: &mut SplatCopy) {
+        std::mem::swap(&mut self.splat_pixels, &mut copy.0);
+        self.upload_splatmap();
+    }
+
+    /// Replaces the weights with the image, resized to the splatmap if it's not the
+    /// same size. The channels are taken as slices, which they are once `reset_layers`
+    /// has made the project's layers.
+    pub fn load_splatmap(&mut self, path: &str) -> Result<(), ImageError> {
+        let img = image::open(path)?;
+        let size = SPLATMAP_SIZE as u32;
+        let img = if img.width() != size || img.height() != size {
+            img.resize_exact(size, size, FilterType::Triangle)
+        } else {
+            img
+        };
+        for (texel, pixel) in self.splat_pixels.iter_mut().zip(img.into_rgba8().pixels()) {
+            *texel = pixel.0;
+        }
+        self.upload_splatmap();
+        Ok(())
+    }
+
+    fn upload_splatmap(&self) {
+        unsafe {
+            gl::TextureSubImage2D(
+                self.splatmap,
+                0,
+                0,
+                0,
+                SPLATMAP_SIZE as i32,
+                SPLATMAP_SIZE as i32,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                self.splat_pixels.as_ptr() as *const _,
+            );
+        }
+    }
+
+    /// Layer weights, each layer's in the channel of its slice
+    pub fn splatmap(&self) -> GLuint {
+        self.splatmap
+    }
+
+    /// Fails if the material block in the shader isn't the size of `MaterialBlock`
+    pub fn check_block_size(shader: &Program) -> Result<(), ShaderError> {
+        let name = "UTerrainMaterial";
+        let size = shader.uniform_block_size(name)?;
+        if size != size_of::<MaterialBlock>() {
+            return Err(ShaderError::UniformBlockSizeMismatch {
+                name: name.to_owned(),
+                expected: size_of::<MaterialBlock>(),
+                actual: size,
+            });
+        }
+        Ok(())
+    }
+
+    /// Binds the textures and uploads the layer parameters if they have changed
+    pub fn bind(&mut self) {
+        let mut block = MaterialBlock::from(&self.layers[..]);
+        for layer in &mut block.layers {
+            layer.tiling *= self.tiling_scale;
+        }
+        if self.uploaded_block.as_ref() != Some(&block) {
+            unsafe {
+                gl::NamedBufferSubData(
+                    self.ubo,
+                    0,
+                    size_of::<MaterialBlock>() as isize,
+                    &block as *const MaterialBlock as *const _,
+                );
+            }
+            self.uploaded_block = Some(block);
+        }
+
+        unsafe {
+            gl::BindBufferBase(gl::UNIFORM_BUFFER, MATERIAL_UBO_BINDING, self.ubo);
+        }
+        let layers = [
+            (TextureUnit::SPLAT_ALBEDO, self.albedo_array),
+            (TextureUnit::SPLAT_NORMAL, self.normal_array),
+            (TextureUnit::SPLAT_ROUGHNESS, self.roughness_array),
+        ];
+        for (unit, texture) in layers {
+            bindings::bind_texture_sampled(unit, texture, Sampler::TrilinearRepeat);
+        }
+        bindings::bind_texture_sampled(TextureUnit::SPLATMAP, self.splatmap, Sampler::LinearClamp);
+    }
+}
+
+impl Drop for SplatMaterial {
+    fn drop(&mut self) {
+        bindings::delete_textures(&[
+            self.albedo_array,
+            self.normal_array,
+            self.roughness_array,
+            self.splatmap,
+        ]);
+        unsafe {
+            gl::DeleteBuffers(1, &self.ubo);
+        }
+    }
+}
+
+/// Pixels of an imported image decoded and resized off the main thread
+pub struct DecodedLayerImage {
+    layer_id: u64,
+    map: LayerMap,
+    path: String,
+    pixels: Vec<u8>,
+}
+
+/// RGBA8 normal map pixels computed off the main thread
+pub struct GeneratedNormalMap {
+    layer_id: u64,
+    source_path: String,
+    pixels: Vec<u8>,
+}
+
+/// Treats brightness as height and takes the Sobel gradient, wrapping around the edges
+/// since layer textures tile. Green points towards -V to match the TBN in terrain.frag.glsl.
+fn normal_map_from_height(height: &GrayImage, strength: f32) -> Vec<u8> {
+    let (width, height_px) = height.dimensions();
+    let (w, h) = (width as i64, height_px as i64);
+    let sample = |x: i64, y: i64| -> f32 {
+        let x = x.rem_euclid(w) as u32;
+        let y = y.rem_euclid(h) as u32;
+        height.get_pixel(x, y)[0] as f32 / 255.0
+    };
+
+    let mut pixels = Vec::with_capacity((w * h * 4) as usize);
+    for y in 0..h {
+        for x in 0..w {
+            let tl = sample(x - 1, y - 1);
+            let t = sample(x, y - 1);
+            let tr = sample(x + 1, y - 1);
+            let l = sample(x - 1, y);
+            let r = sample(x + 1, y);
+            let bl = sample(x - 1, y + 1);
+            let b = sample(x, y + 1);
+            let br = sample(x + 1, y + 1);
+
+            let dx = (tr + 2.0 * r + br) - (tl + 2.0 * l + bl);
+            let dy = (bl + 2.0 * b + br) - (tl + 2.0 * t + tr);
+            let normal = glam::Vec3::new(-dx * strength, dy * strength, 1.0).normalize();
+
+            let encode = |v: f32| ((v * 0.5 + 0.5) * 255.0).round() as u8;
+            pixels.extend_from_slice(&[encode(normal.x), encode(normal.y), encode(normal.z), 255]);
+        }
+    }
+    pixels
+}
+
+// NOTE: std140 - each array element of a struct is aligned to 16 bytes, which this is a multiple of
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct LayerBlock {
+    albedo: Vec4,
+    tiling: f32,
+    slice: i32,
+    flags: i32,
+    roughness: f32,
+    metallic: f32,
+    _padding: [f32; 3],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct MaterialBlock {
+    layers: [LayerBlock; MAX_LAYERS],
+    layer_count: i32,
+    // std140 rounds the block up to a multiple of 16 bytes
+    _padding: [i32; 3],
+}
+
+impl From<&[SplatLayer]> for MaterialBlock {
+    fn from(layers: &[SplatLayer]) -> Self {
+        let mut block = MaterialBlock {
+            layers: [LayerBlock {
+                albedo: Vec4::ONE,
+                tiling: 1.0,
+                slice: 0,
+                flags: 0,
+                roughness: 1.0,
+                metallic: 0.0,
+                _padding: [0.0; 3],
+            }; MAX_LAYERS],
+            layer_count: layers.len() as i32,
+            _padding: [0; 3],
+        };
+        for (layer, dst) in layers.iter().zip(block.layers.iter_mut()) {
+            let mut flags = 0;
+            if layer.triplanar {
+                flags |= FLAG_TRIPLANAR;
+            }
+            if layer.path(LayerMap::Normal).is_some() {
+                flags |= FLAG_HAS_NORMAL;
+            }
+            if layer.path(LayerMap::Roughness).is_some() {
+                flags |= FLAG_HAS_ROUGHNESS;
+            }
+            *dst = LayerBlock {
+                albedo: layer.params.albedo,
+                tiling: layer.tiling,
+                slice: layer.slice as i32,
+                flags,
+                roughness: layer.params.roughness,
+                metallic: layer.params.metallic,
+                _padding: [0.0; 3],
+            };
+        }
+        block
+    }
+}
@@ -0,0 +1,252 @@
+//! Temporal accumulation of the screen-space shadow and ambient occlusion terms.
+//! Each frame takes only a few noisy samples, which get blended with the previous
+//! frames reprojected using the camera matrices.
+
+use gl::types::*;
+use glam::{Mat4, Vec3};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::opengl::bindings::{self, Sampler, TextureUnit};
+use crate::opengl::shader::{Program, ShaderError};
+use crate::opengl::{self, empty_vertex_array, IncompleteFramebuffer};
+use crate::profiler;
+
+/// Moving further than this in one frame is a teleport, the history is useless then
+const TELEPORT_DISTANCE: f32 = 50.0;
+
+#[derive(Debug, Error)]
+pub enum TemporalError {
+    #[error("Temporal accumulation shader error: {0}")]
+    Shader(#[from] ShaderError),
+    #[error(transparent)]
+    Framebuffer(#[from] IncompleteFramebuffer),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TemporalQuality {
+    /// All samples every frame, no history
+    Off,
+    Low,
+    High,
+}
+
+impl TemporalQuality {
+    pub const ALL: [TemporalQuality; 3] = [
+        TemporalQuality::Off,
+        TemporalQuality::Low,
+        TemporalQuality::High,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TemporalQuality::Off => "Off",
+            TemporalQuality::Low => "Low",
+            TemporalQuality::High => "High",
+        }
+    }
+
+    /// Samples taken per pixel each frame and how many frames get blended together
+    pub fn samples_and_history(&self) -> (i32, f32) {
+        match self {
+            TemporalQuality::Off => (16, 1.0),
+            TemporalQuality::Low => (4, 8.0),
+            TemporalQuality::High => (8, 24.0),
+        }
+    }
+}
+
+/// Screen-space ambient occlusion
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Ssao {
+    pub enabled: bool,
+    /// World units
+    pub radius: f32,
+    pub strength: f32,
+}
+
+impl Default for Ssao {
+    fn default() -> Self {
+        Ssao {
+            enabled: true,
+            radius: 2.0,
+            strength: 0.8,
+        }
+    }
+}
+
+/// Shadow map filtering with a wide rotated kernel instead of a fixed 3x3 one
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SoftShadows {
+    /// Kernel radius in shadow map texels
+    pub softness: f32,
+}
+
+impl Default for SoftShadows {
+    fn default() -> Self {
+        SoftShadows { softness: 3.0 }
+    }
+}
+
+pub struct TemporalAccumulation {
+    /// Ping-pong history: one is read while the other is written
+    fbos: [GLuint; 2],
+    history: [GLuint; 2],
+    current: usize,
+    shader: Program,
+    vao: GLuint,
+
+    prev_view_proj: Mat4,
+    prev_camera_position: Vec3,
+    prev_quality: TemporalQuality,
+    frame_index: u32,
+    history_valid: bool,
+}
+
+impl TemporalAccumulation {
+    pub fn new(width: i32, height: i32) -> Result<Self, TemporalError> {
+        let mut fbos: [GLuint; 2] = [0; 2];
+        unsafe {
+            gl::CreateFramebuffers(2, fbos.as_mut_ptr());
+        }
+        let history = create_history(&fbos, width, height)?;
+
+        let shader = Program::new()
+            .vertex_shader(shader_file!("post/fullscreen.vert"))?
+            .fragment_shader(shader_file!("post/temporal.frag"))?
+            .link()?;
+
+        let vao = empty_vertex_array();
+
+        Ok(TemporalAccumulation {
+            fbos,
+            history,
+            current: 0,
+            shader,
+            vao,
+
+            prev_view_proj: Mat4::IDENTITY,
+            prev_camera_position: Vec3::ZERO,
+            prev_quality: TemporalQuality::Off,
+            frame_index: 0,
+            history_valid: false,
+        })
+    }
+
+    /// Throws away the accumulated frames, e.g. when the view changes abruptly
+    fn reset(&mut self) {
+        self.history_valid = false;
+    }
+
+    /// Reallocates the history for the new window size, the old frames are lost
+    pub fn resize(&mut self, width: i32, height: i32) -> Result<(), TemporalError> {
+        let history = create_history(&self.fbos, width, height)?;
+        bindings::delete_textures(&self.history);
+        self.history = history;
+        self.reset();
+        Ok(())
+    }
+
+    /// Keeps the history usable when the local origin moves
+    pub fn shift_origin(&mut self, shift: Vec3) {
+        self.prev_view_proj *= Mat4::from_translation(shift);
+        self.prev_camera_position -= shift;
+    }
+
+    /// The last accumulated result
+    pub fn history(&self) -> GLuint {
+        self.history[self.current]
+    }
+
+    /// Renders this frame's shadow and occlusion into the history and returns the
+    /// texture with the accumulated result: shadow in R, ambient visibility in G.
+    /// Expects the scene depth to be bound to unit 1 and the shadow map to unit 3.
+    pub fn accumulate(
+        &mut self,
+        quality: TemporalQuality,
+        ssao: &Ssao,
+        soft_shadows: &SoftShadows,
+        view: &Mat4,
+        proj: &Mat4,
+    ) -> Result<GLuint, TemporalError> {
+        let camera_position = view.inverse().w_axis.truncate();
+        if quality != self.prev_quality
+            || camera_position.distance(self.prev_camera_position) > TELEPORT_DISTANCE
+        {
+            self.reset();
+        }
+
+        let previous = self.current;
+        self.current = 1 - self.current;
+        let (samples, history_length) = quality.samples_and_history();
+
+        self.shader.set_used();
+        self.shader
+            .set_mat4("prev_view_proj", &self.prev_view_proj)?;
+        self.shader
+            .set_vec3("prev_camera_position", &self.prev_camera_position)?;
+        self.shader
+            .set_i32("history_valid", self.history_valid as i32)?;
+        self.shader.set_f32("history_length", history_length)?;
+        self.shader.set_u32("frame_index", self.frame_index)?;
+        self.shader.set_i32("samples", samples)?;
+        self.shader.set_i32("ssao.enabled", ssao.enabled as i32)?;
+        self.shader.set_f32("ssao.radius", ssao.radius)?;
+        self.shader
+            .set_f32("shadow_softness", soft_shadows.softness)?;
+
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbos[self.current]);
+            bindings::bind_texture_sampled(
+                TextureUnit::HISTORY,
+                self.history[previous],
+                Sampler::LinearClamp,
+            );
+
+            gl::BindVertexArray(self.vao);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            profiler::count_draw_call();
+        }
+
+        self.prev_view_proj = *proj * *view;
+        self.prev_camera_position = camera_position;
+        self.prev_quality = quality;
+        self.frame_index = self.frame_index.wrapping_add(1);
+        self.history_valid = true;
+
+        Ok(self.history[self.current])
+    }
+}
+
+/// A history texture attached to each of the framebuffers
+fn create_history(
+    fbos: &[GLuint; 2],
+    width: i32,
+    height: i32,
+) -> Result<[GLuint; 2], IncompleteFramebuffer> {
+    let mut history: [GLuint; 2] = [0; 2];
+    unsafe {
+        gl::CreateTextures(gl::TEXTURE_2D, 2, history.as_mut_ptr());
+        for (&fbo, &texture) in fbos.iter().zip(&history) {
+            // Shadow, occlusion, distance to the camera, number of frames accumulated
+            gl::TextureStorage2D(texture, 1, gl::RGBA16F, width, height);
+            gl::NamedFramebufferTexture(fbo, gl::COLOR_ATTACHMENT0, texture, 0);
+        }
+    }
+    for &fbo in fbos {
+        opengl::check_framebuffer(fbo, "Temporal accumulation")?;
+    }
+    Ok(history)
+}
+
+impl Drop for TemporalAccumulation {
+    fn drop(&mut self) {
+        bindings::delete_textures(&self.history);
+        unsafe {
+            gl::DeleteFramebuffers(2, self.fbos.as_ptr());
+            gl::DeleteVertexArrays(1, &self.vao);
+        }
+    }
+}
@@ -1,26 +1,56 @@
 use std::ffi::c_void;
+use std::fs;
+use std::mem::size_of;
+use std::ops::RangeInclusive;
 
 use gl::types::*;
 use glam::Vec3Swizzles;
-use glam::{Vec2, Vec3};
-use image::GenericImageView;
-
-use crate::texture::{calculate_mip_levels, get_max_anisotropy, unit_to_gl_const};
+use glam::{IVec2, Mat4, Vec2, Vec3, Vec4};
+use serde::{Deserialize, Serialize};
+
+use crate::atmosphere::Atmosphere;
+use crate::debug_draw;
+use crate::heightfield::HeightField;
+use crate::input::Stroke;
+use crate::jobs::{JobHandle, JobSystem};
+use crate::opengl::bindings::{self, Sampler, TextureUnit};
+use crate::opengl::objects::{Buffer, Framebuffer, Texture2D, VertexArray};
+use crate::opengl::readback::TextureReadback;
+use crate::opengl::SavedTarget;
+use crate::profiler;
+use crate::splat::SplatMaterial;
+use crate::texture_manager::{self, TextureHandle, TextureKind};
 use crate::{
     opengl::shader::Program,
     ray::{Ray, AABB},
     utils::vec2_infinity,
     Result,
 };
-use crate::{WINDOW_HEIGHT, WINDOW_WIDTH};
+
+pub const MAX_HEIGHT: f32 = 200.0;
+const NUM_PATCHES: i32 = 64;
+const PATCH_SIZE: f32 = 16.0;
+/// Side of the square terrain in world units
+pub const TERRAIN_SIZE: f32 = PATCH_SIZE * NUM_PATCHES as f32;
+/// Patches closer to the camera get the full tess level, see terrain_patches.comp
+const LOD_DISTANCE: f32 = 128.0;
+/// Must match the buffer bindings in terrain_patches.comp and terrain.tc.glsl
+const COMMANDS_SSBO_BINDING: u32 = 0;
+const TESS_LEVELS_SSBO_BINDING: u32 = 1;
+/// Must match the local size in terrain_patches.comp
+const CULL_GROUP_SIZE: usize = 64;
+/// Count, instance count, first vertex and base instance, see `glDrawArraysIndirect`
+const DRAW_COMMAND_SIZE: usize = 4 * size_of::<u32>();
 
 struct Heightmap {
-    texture: GLuint,
+    texture: Texture2D,
     texture_size: usize,
 
     // For drawing on heightmap
-    fbo: GLuint,
+    fbo: Framebuffer,
     shader: Program,
+    /// The heightmap as it was before the brush, which can't read what it draws on
+    copy: Texture2D,
 }
 
 impl Heightmap {
@@ -37,29 +67,17 @@ impl Heightmap {
         debug_assert!(!(path.is_none() && texture_size.is_none()));
 
         let (pixels, texture_size) = if let Some(path) = path {
-            let img = image::open(path)?;
-            let (width, height) = img.dimensions();
-            assert_eq!(width, height, "Only square heightmaps are supported");
-            assert!(
-                width == 1024 || width == 2048 || width == 4096,
-                "Only heightmaps with sizes 1024, 2048 and 4096 are supported"
-            );
-
-            (img.into_luma16().into_raw(), width as usize)
+            let heightfield = HeightField::load(path, None)?;
+            (heightfield.to_pixels(), heightfield.size)
         } else {
             let size = texture_size.unwrap();
 
             (vec![0u16; size * size], size)
         };
 
-        let mut texture: GLuint = 0;
+        let texture_object = Texture2D::new();
+        let texture = texture_object.id();
         unsafe {
-            gl::CreateTextures(gl::TEXTURE_2D, 1, &mut texture);
-            gl::BindTexture(gl::TEXTURE_2D, texture);
-            gl::TextureParameteri(texture, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
-            gl::TextureParameteri(texture, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
-            gl::TextureParameteri(texture, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
-            gl::TextureParameteri(texture, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
             gl::TextureStorage2D(
                 texture,
                 1,
@@ -82,68 +100,108 @@ impl Heightmap {
         }
 
         // Framebuffer object for rendering to heightmap
-        let mut fbo: GLuint = 0;
+        let fbo = Framebuffer::new();
         unsafe {
-            gl::CreateFramebuffers(1, &mut fbo);
-            gl::NamedFramebufferTexture(fbo, gl::COLOR_ATTACHMENT0, texture, 0);
+            gl::NamedFramebufferTexture(fbo.id(), gl::COLOR_ATTACHMENT0, texture, 0);
             let draw_buffers = [gl::COLOR_ATTACHMENT0];
-            gl::NamedFramebufferDrawBuffers(fbo, 1, draw_buffers.as_ptr() as *const _);
+            gl::NamedFramebufferDrawBuffers(fbo.id(), 1, draw_buffers.as_ptr() as *const _);
             assert_eq!(
-                gl::CheckNamedFramebufferStatus(fbo, gl::FRAMEBUFFER),
+                gl::CheckNamedFramebufferStatus(fbo.id(), gl::FRAMEBUFFER),
                 gl::FRAMEBUFFER_COMPLETE,
                 "Heightmap texture framebuffer is incomplete",
             );
         }
 
+        let copy = Texture2D::new();
+        unsafe {
+            let id = copy.id();
+            gl::TextureStorage2D(id, 1, gl::R16, texture_size as i32, texture_size as i32);
+        }
+
         let shader = Program::new()
-            .vertex_shader(include_str!("shaders/editor/terrain/heightmap.vert"))?
-            .fragment_shader(include_str!("shaders/editor/terrain/heightmap.frag"))?
+            .vertex_shader(shader_file!("editor/terrain/heightmap.vert"))?
+            .fragment_shader(shader_file!("editor/terrain/heightmap.frag"))?
             .link()?;
 
         Ok(Heightmap {
-            texture,
+            texture: texture_object,
             texture_size,
 
             fbo,
             shader,
+            copy,
         })
     }
 
+    /// With the settings of the stroke rather than the brush's own
     fn draw_on_heightmap(
         &self,
         cursor: Vec2,
         brush: &Brush,
+        settings: &BrushSettings,
+        rotation: f32,
         terrain_size: f32,
         delta_time: f32,
-        raise: bool,
     ) {
         self.shader.set_used();
         debug_assert!(cursor.x <= 1.0 && cursor.x >= 0.0);
         debug_assert!(cursor.y <= 1.0 && cursor.y >= 0.0);
+        let mode = settings.mode;
         self.shader.set_vec2("cursor", &cursor).unwrap();
-        let brush_size = brush.size as f32 / terrain_size;
+        let brush_size = settings.size / terrain_size;
         self.shader.set_f32("brush_size", brush_size).unwrap();
         self.shader.set_f32("delta_time", delta_time).unwrap();
+        self.shader.set_f32("strength", settings.strength).unwrap();
+        self.shader.set_f32("falloff", settings.falloff).unwrap();
+        self.shader.set_f32("rotation", rotation).unwrap();
+        // Raising and lowering add the same thing, the others blend toward a height
+        let blend = matches!(mode, BrushMode::Smooth | BrushMode::Flatten);
+        let shader_mode = match mode {
+            BrushMode::Raise | BrushMode::Lower => 0,
+            BrushMode::Smooth => 1,
+            BrushMode::Flatten => 2,
+        };
+        self.shader.set_i32("mode", shader_mode).unwrap();
+        let flatten_target = brush.flatten_target.unwrap_or(-1.0);
+        self.shader
+            .set_f32("flatten_target", flatten_target)
+            .unwrap();
+
+        if blend {
+            copy_texture(self.texture.id(), self.copy.id(), self.texture_size);
+        }
 
         unsafe {
-            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+            let mut viewport: [GLint; 4] = [0; 4];
+            gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo.id());
             gl::Disable(gl::FRAMEBUFFER_SRGB);
             gl::Viewport(0, 0, self.texture_size as i32, self.texture_size as i32);
 
-            gl::ActiveTexture(unit_to_gl_const(0));
-            gl::BindTexture(gl::TEXTURE_2D, brush.texture);
+            // The brush is stamped from unit 0 here, see heightmap.frag
+            bindings::bind_texture(TextureUnit::SOURCE, brush.texture.id());
+            bindings::bind_texture_sampled(
+                TextureUnit::HEIGHTMAP,
+                self.copy.id(),
+                Sampler::LinearClamp,
+            );
 
             gl::Enable(gl::BLEND);
             gl::Disable(gl::DEPTH_TEST);
 
-            gl::BlendFunc(gl::ONE, gl::ONE);
-            gl::BlendEquation(if raise {
-                gl::FUNC_ADD
+            if blend {
+                gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
             } else {
+                gl::BlendFunc(gl::ONE, gl::ONE);
+            }
+            gl::BlendEquation(if mode == BrushMode::Lower {
                 gl::FUNC_REVERSE_SUBTRACT
+            } else {
+                gl::FUNC_ADD
             });
 
             gl::DrawArrays(gl::TRIANGLE_FAN, 0, 4);
+            profiler::count_draw_call();
             gl::MemoryBarrier(gl::FRAMEBUFFER_BARRIER_BIT); // not critical
 
             // Reset everything back
@@ -152,83 +210,185 @@ impl Heightmap {
             gl::BlendEquation(gl::FUNC_ADD);
             gl::Enable(gl::FRAMEBUFFER_SRGB);
             gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
-            gl::Viewport(0, 0, WINDOW_WIDTH as i32, WINDOW_HEIGHT as i32);
+            gl::Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
         }
     }
 }
 
-pub struct Brush {
-    texture: GLuint,
-    texture_size: usize,
+/// How fast painting covers the terrain at strength 1, per second
+const PAINT_SPEED: f32 = 4.0;
+
+/// Grayscale images to pick the brush shape from
+pub const BRUSHES_DIR: &str = "textures/brushes";
+
+/// What the brush does to the terrain under it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BrushMode {
+    Raise,
+    Lower,
+    /// Evens out the bumps
+    Smooth,
+    /// Pulls the terrain toward the height under the brush center
+    Flatten,
+}
+
+impl BrushMode {
+    pub const ALL: [BrushMode; 4] = [
+        BrushMode::Raise,
+        BrushMode::Lower,
+        BrushMode::Smooth,
+        BrushMode::Flatten,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BrushMode::Raise => "Raise",
+            BrushMode::Lower => "Lower",
+            BrushMode::Smooth => "Smooth",
+            BrushMode::Flatten => "Flatten",
+        }
+    }
+
+    /// Used while Ctrl is held
+    pub fn inverted(self) -> Self {
+        match self {
+            BrushMode::Raise => BrushMode::Lower,
+            BrushMode::Lower => BrushMode::Raise,
+            mode => mode,
+        }
+    }
+}
+
+/// Brush settings shared by the GUI and the terrain
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BrushSettings {
+    /// In world units, changed with the scroll wheel too
     pub size: f32,
+    /// 1 raises the center by the whole terrain height in a second
+    pub strength: f32,
+    /// Part of the radius the brush fades out over, 0 for a hard edge
+    pub falloff: f32,
+    pub mode: BrushMode,
 }
 
-impl Brush {
-    pub fn new(path: &str, size: f32) -> Self {
-        let img = image::open(path)
-            .expect("Can't load brush image")
-            .into_luma16();
-        let (width, height) = img.dimensions();
-        assert_eq!(width, height, "Only square brushes are supported");
-        let texture_size = width as usize;
-
-        let mut texture: GLuint = 0;
-        unsafe {
-            gl::CreateTextures(gl::TEXTURE_2D, 1, &mut texture);
-            gl::TextureParameteri(texture, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_BORDER as GLint);
-            gl::TextureParameteri(texture, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_BORDER as GLint);
-            gl::TextureParameteri(
-                texture,
-                gl::TEXTURE_MIN_FILTER,
-                gl::LINEAR_MIPMAP_LINEAR as GLint,
-            );
-            gl::TextureParameteri(texture, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
-            gl::TextureStorage2D(
-                texture,
-                calculate_mip_levels(texture_size, texture_size),
-                gl::R16,
-                texture_size as i32,
-                texture_size as i32,
-            );
-            gl::TextureSubImage2D(
-                texture,
-                0,
-                0,
-                0,
-                texture_size as i32,
-                texture_size as i32,
-                gl::RED,
-                gl::UNSIGNED_SHORT,
-                img.as_raw().as_ptr() as *const _,
-            );
-            gl::GenerateTextureMipmap(texture);
+impl Default for BrushSettings {
+    fn default() -> Self {
+        BrushSettings {
+            size: 100.0,
+            strength: 1.0,
+            falloff: 0.5,
+            mode: BrushMode::Raise,
         }
+    }
+}
 
-        Brush {
-            texture,
-            size,
-            texture_size,
+impl BrushSettings {
+    pub const SIZE_RANGE: RangeInclusive<f32> = 0.1..=800.0;
+    pub const STRENGTH_RANGE: RangeInclusive<f32> = 0.01..=5.0;
+
+    /// Tilting the pen softens the edge, all the way to fading out from the center
+    pub fn tilted_falloff(&self, tilt: f32) -> f32 {
+        self.falloff + (1.0 - self.falloff) * tilt
+    }
+
+    /// Scrolling down makes the brush bigger, or stronger with `strength`
+    pub fn scroll(&mut self, lines: f32, strength: bool) {
+        if strength {
+            // By a tenth per line, the range is too wide for even steps
+            let range = Self::STRENGTH_RANGE;
+            self.strength =
+                (self.strength * 1.1f32.powf(-lines)).clamp(*range.start(), *range.end());
+        } else {
+            let range = Self::SIZE_RANGE;
+            self.size = (self.size - lines * 5.5).clamp(*range.start(), *range.end());
         }
     }
 }
 
+pub struct Brush {
+    texture: TextureHandle,
+    /// Of the image the shape was loaded from
+    pub path: String,
+    pub settings: BrushSettings,
+    /// Heightmap value [0, 1] the flatten mode blends toward, None for the height
+    /// under the cursor. See `Terrain::sample_flatten_height`.
+    pub flatten_target: Option<f32>,
+}
+
+impl Brush {
+    pub fn new(path: &str) -> Result<Self> {
+        Ok(Brush {
+            texture: load_brush_texture(path)?,
+            path: path.to_owned(),
+            settings: BrushSettings::default(),
+            flatten_target: None,
+        })
+    }
+
+    /// Replaces the shape with the image, the old one stays if it can't be loaded
+    pub fn load(&mut self, path: &str) -> Result<()> {
+        self.texture = load_brush_texture(path)?;
+        self.path = path.to_owned();
+        Ok(())
+    }
+}
+
+/// Brushes have to be square
+fn load_brush_texture(path: &str) -> Result<TextureHandle> {
+    let texture = texture_manager::load(path, TextureKind::Brush)?;
+    if texture.width() != texture.height() {
+        return Err(format!("{} isn't square, only square brushes are supported", path).into());
+    }
+    Ok(texture)
+}
+
+/// Paths of the brush images to pick from, in alphabetical order
+pub fn brush_textures() -> Vec<String> {
+    let mut paths: Vec<String> = fs::read_dir(BRUSHES_DIR)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| {
+                    path.extension()
+                        .is_some_and(|ext| ext == "png" || ext == "tga")
+                })
+                .map(|path| path.to_string_lossy().replace('\\', "/"))
+                .collect()
+        })
+        .unwrap_or_default();
+    paths.sort();
+    paths
+}
+
 pub struct Terrain {
     pub aabb: AABB,
 
-    vao: GLuint,
+    vao: VertexArray,
     shader: Program,
     pub tess_level: f32,
 
-    texture: GLuint,
+    pub material: SplatMaterial,
     heightmap: Heightmap,
 
     pub cursor: Vec2,
     pub brush: Brush,
 
-    shadow_map_fbo: GLuint,
-    shadow_map: GLuint,
+    shadow_map_fbo: Framebuffer,
+    shadow_map: Texture2D,
     shadow_map_size: i32,
     shadow_map_shader: Program,
+    /// The shadow map is only re-rendered when something that affects it changes
+    shadow_map_dirty: bool,
+    shadow_map_sun_vp: Mat4,
+    shadow_map_tess_level: f32,
+
+    /// Writes the draw command and the tess level of every patch
+    cull_shader: Program,
+    camera_patches: PatchDraws,
+    /// Every patch at the full tess level
+    shadow_patches: PatchDraws,
 
     debug: TerrainDebug,
 
@@ -237,24 +397,130 @@ pub struct Terrain {
     max_height: f32,
     num_patches: i32,
     patch_size: f32,
+
+    /// Goes up with every change to the heightmap
+    heights_version: u64,
+    /// Copy of the heightmap on the CPU and its version, see `update_heights_mirror`
+    heights_mirror: Option<(Vec<u16>, u64)>,
+    heights_readback: Option<(HeightmapReadback, u64)>,
+    /// The texels read back, being converted on a worker
+    heights_conversion: Option<(JobHandle<Vec<u16>>, u64)>,
+    /// Where the heightmap has changed since `take_changed_area`, in world units
+    changed_area: Option<(Vec2, Vec2)>,
+}
+
+/// Heightmap from before or after a stroke, see `Terrain::swap_heights`
+pub struct HeightsCopy {
+    texture: Texture2D,
+    texture_size: usize,
+}
+
+/// Heightmap pixels on their way back from the GPU, see `Terrain::read_heightmap`
+pub struct HeightmapReadback {
+    readback: TextureReadback,
+    texture_size: usize,
+}
+
+impl HeightmapReadback {
+    /// 16-bit pixels and the size of the heightmap, once the copy has finished
+    pub fn try_take(&self) -> Option<(Vec<u8>, usize)> {
+        let pixels = self.readback.try_take()?;
+        Some((pixels, self.texture_size))
+    }
+
+    /// Blocks until the copy has finished
+    pub fn wait(&self) -> (Vec<u8>, usize) {
+        (self.readback.wait(), self.texture_size)
+    }
+}
+
+/// The heightmap on the CPU as it was last read back, see `Terrain::mirrored_heights`
+pub struct HeightSnapshot<'a> {
+    /// Rows along Z, from the min corner
+    texels: &'a [u16],
+    pub texture_size: usize,
+    /// Of the terrain
+    pub aabb: AABB,
+    max_height: f32,
+}
+
+impl HeightSnapshot<'_> {
+    /// Of the texel at column x and row y
+    pub fn height(&self, x: usize, y: usize) -> f32 {
+        let texel = self.texels[y * self.texture_size + x];
+        self.aabb.min.y + texel as f32 / u16::MAX as f32 * self.max_height
+    }
+
+    /// Same as `Terrain::height_range`
+    pub fn range(&self, min: Vec2, max: Vec2) -> Option<(f32, f32)> {
+        let (x, y, width, height) = texels_under(&self.aabb, self.texture_size, min, max)?;
+        let (mut low, mut high) = (f32::MAX, f32::MIN);
+        for row in y..y + height {
+            for column in x..x + width {
+                let height = self.height(column as usize, row as usize);
+                low = low.min(height);
+                high = high.max(height);
+            }
+        }
+        Some((low, high))
+    }
+}
+
+/// A draw command per patch, with no instances if the patch is culled, and the tess
+/// levels. Only the GPU reads and writes them.
+struct PatchDraws {
+    commands: Buffer,
+    tess_levels: Buffer,
+}
+
+impl PatchDraws {
+    fn new() -> Self {
+        let count = (NUM_PATCHES * NUM_PATCHES) as usize;
+        let commands = Buffer::new();
+        let tess_levels = Buffer::new();
+        unsafe {
+            gl::NamedBufferStorage(
+                commands.id(),
+                (count * DRAW_COMMAND_SIZE) as isize,
+                std::ptr::null(),
+                0,
+            );
+            gl::NamedBufferStorage(
+                tess_levels.id(),
+                (count * size_of::<f32>()) as isize,
+                std::ptr::null(),
+                0,
+            );
+        }
+        PatchDraws {
+            commands,
+            tess_levels,
+        }
+    }
 }
 
+/// Shaders for the debug views, see `DebugView`
 struct TerrainDebug {
-    aabb_shader: Program,
     normal_shader: Program,
+    patch_shader: Program,
 }
 
 impl Terrain {
-    pub fn new(center: Vec2, start_flat: bool, heightmap_path: &str) -> Result<Self> {
+    pub fn new(
+        center: Vec2,
+        start_flat: bool,
+        heightmap_path: &str,
+        brush_path: &str,
+    ) -> Result<Self> {
         // TODO: support centers other than 0, 0
         // (currently hard-coded in terrain.vert.glsl)
         assert_eq!(center, Vec2::new(0.0, 0.0));
 
-        let max_height = 200.0;
-        let num_patches = 64;
-        let patch_size = 16.0;
+        let max_height = MAX_HEIGHT;
+        let num_patches = NUM_PATCHES;
+        let patch_size = PATCH_SIZE;
 
-        let terrain_size = patch_size * num_patches as f32;
+        let terrain_size = TERRAIN_SIZE;
         let aabb = {
             let half_size = terrain_size / 2.0;
             let min = Vec3::new(-half_size, 0.0, -half_size);
@@ -262,55 +528,9 @@ impl Terrain {
             AABB::new(min, max)
         };
 
-        let mut vao: GLuint = 0;
-        unsafe {
-            gl::CreateVertexArrays(1, &mut vao);
-        }
-
-        let texture = {
-            let img = image::open("textures/checkerboard.png")
-                .unwrap()
-                .flipv()
-                .into_rgb8();
-            let (width, height) = img.dimensions();
-            assert_eq!(width, height);
-            let size = width as usize;
-
-            let mut texture: GLuint = 0;
-            unsafe {
-                gl::CreateTextures(gl::TEXTURE_2D, 1, &mut texture);
-                gl::TextureParameteri(texture, gl::TEXTURE_WRAP_S, gl::REPEAT as GLint);
-                gl::TextureParameteri(texture, gl::TEXTURE_WRAP_T, gl::REPEAT as GLint);
-                gl::TextureParameteri(
-                    texture,
-                    gl::TEXTURE_MIN_FILTER,
-                    gl::LINEAR_MIPMAP_LINEAR as GLint,
-                );
-                gl::TextureParameteri(texture, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
-                gl::TextureParameterf(texture, gl::TEXTURE_MAX_ANISOTROPY, get_max_anisotropy());
-                gl::TextureStorage2D(
-                    texture,
-                    calculate_mip_levels(size, size),
-                    gl::SRGB8,
-                    size as i32,
-                    size as i32,
-                );
-                gl::TextureSubImage2D(
-                    texture,
-                    0,
-                    0,
-                    0,
-                    size as i32,
-                    size as i32,
-                    gl::RGB,
-                    gl::UNSIGNED_BYTE,
-                    img.as_raw().as_ptr() as *const _,
-                );
-                gl::GenerateTextureMipmap(texture);
-            }
+        let vao = VertexArray::new();
 
-            texture
-        };
+        let material = SplatMaterial::new();
 
         let cursor = vec2_infinity();
         let heightmap = if start_flat {
@@ -318,14 +538,15 @@ impl Terrain {
         } else {
             Heightmap::from_image(heightmap_path)?
         };
-        let brush = Brush::new("textures/brushes/mountain05.tga", 100.0);
+        let brush = Brush::new(brush_path)?;
 
         let shader = Program::new()
-            .vertex_shader(include_str!("shaders/editor/terrain/terrain.vert.glsl"))?
-            .tess_control_shader(include_str!("shaders/editor/terrain/terrain.tc.glsl"))?
-            .tess_evaluation_shader(include_str!("shaders/editor/terrain/terrain.te.glsl"))?
-            .fragment_shader(include_str!("shaders/editor/terrain/terrain.frag.glsl"))?
+            .vertex_shader(shader_file!("editor/terrain/terrain.vert.glsl"))?
+            .tess_control_shader(shader_file!("editor/terrain/terrain.tc.glsl"))?
+            .tess_evaluation_shader(shader_file!("editor/terrain/terrain.te.glsl"))?
+            .fragment_shader(shader_file!("editor/terrain/terrain.frag.glsl"))?
             .link()?;
+        SplatMaterial::check_block_size(&shader)?;
         shader.set_used();
         shader.set_vec2("terrain_center", &center)?;
         shader.set_f32("terrain_max_height", max_height)?;
@@ -333,39 +554,29 @@ impl Terrain {
         shader.set_i32("num_patches", num_patches)?;
         shader.set_f32("patch_size", patch_size)?;
 
+        let cull_shader = Program::new()
+            .compute_shader(shader_file!("culling/terrain_patches.comp"))?
+            .link()?;
+        cull_shader.set_used();
+        cull_shader.set_vec2("terrain_center", &center)?;
+        cull_shader.set_f32("terrain_max_height", max_height)?;
+        cull_shader.set_i32("num_patches", num_patches)?;
+        cull_shader.set_f32("patch_size", patch_size)?;
+        cull_shader.set_f32("lod_distance", LOD_DISTANCE)?;
+
         // Shadow map
-        let mut shadow_map_fbo: GLuint = 0;
-        let mut shadow_map: GLuint = 0;
+        let shadow_map_fbo = Framebuffer::new();
         let shadow_map_size = 2048;
         unsafe {
-            gl::CreateFramebuffers(1, &mut shadow_map_fbo);
-            gl::CreateTextures(gl::TEXTURE_2D, 1, &mut shadow_map);
-            gl::TextureParameteri(shadow_map, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
-            gl::TextureParameteri(shadow_map, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
-            gl::TextureParameteri(shadow_map, gl::TEXTURE_WRAP_S, gl::REPEAT as i32);
-            gl::TextureParameteri(shadow_map, gl::TEXTURE_WRAP_T, gl::REPEAT as i32);
-            gl::TextureStorage2D(
-                shadow_map,
-                1,
-                gl::DEPTH_COMPONENT16,
-                shadow_map_size,
-                shadow_map_size,
-            );
-            gl::NamedFramebufferTexture(shadow_map_fbo, gl::DEPTH_ATTACHMENT, shadow_map, 0);
-            gl::NamedFramebufferDrawBuffer(shadow_map_fbo, gl::NONE);
-            gl::NamedFramebufferReadBuffer(shadow_map_fbo, gl::NONE);
-
-            assert_eq!(
-                gl::CheckNamedFramebufferStatus(shadow_map_fbo, gl::FRAMEBUFFER),
-                gl::FRAMEBUFFER_COMPLETE,
-                "Shadow map framebuffer is incomplete",
-            );
+            gl::NamedFramebufferDrawBuffer(shadow_map_fbo.id(), gl::NONE);
+            gl::NamedFramebufferReadBuffer(shadow_map_fbo.id(), gl::NONE);
         }
+        let shadow_map = create_shadow_map(&shadow_map_fbo, shadow_map_size);
         let shadow_map_shader = Program::new()
-            .vertex_shader(include_str!("shaders/editor/terrain/terrain.vert.glsl"))?
-            .tess_control_shader(include_str!("shaders/editor/terrain/terrain.tc.glsl"))?
-            .tess_evaluation_shader(include_str!("shaders/editor/terrain/shadow.te.glsl"))?
-            .fragment_shader(include_str!("shaders/editor/terrain/shadow.frag.glsl"))?
+            .vertex_shader(shader_file!("editor/terrain/terrain.vert.glsl"))?
+            .tess_control_shader(shader_file!("editor/terrain/terrain.tc.glsl"))?
+            .tess_evaluation_shader(shader_file!("editor/terrain/shadow.te.glsl"))?
+            .fragment_shader(shader_file!("editor/terrain/shadow.frag.glsl"))?
             .link()?;
         shadow_map_shader.set_used();
         shadow_map_shader.set_vec2("terrain_center", &center)?;
@@ -374,26 +585,31 @@ impl Terrain {
         shadow_map_shader.set_f32("patch_size", patch_size)?;
 
         let debug = {
-            let aabb_shader = Program::new()
-                .vertex_shader(include_str!("shaders/debug/aabb.vert"))?
-                .fragment_shader(include_str!("shaders/debug/aabb.frag"))?
-                .link()?;
-            aabb_shader.set_used();
-            aabb_shader.set_vec3("aabb_min", &aabb.min)?;
-            aabb_shader.set_vec3("aabb_max", &aabb.max)?;
-
             let normal_shader = Program::new()
-                .vertex_shader(include_str!("shaders/editor/terrain/terrain.vert.glsl"))?
-                .tess_control_shader(include_str!("shaders/editor/terrain/terrain.tc.glsl"))?
-                .tess_evaluation_shader(include_str!("shaders/editor/terrain/terrain.te.glsl"))?
-                .geometry_shader(include_str!("shaders/debug/terrain/normals.geometry.glsl"))?
-                .fragment_shader(include_str!("shaders/debug/terrain/normals.frag.glsl"))?
+                .vertex_shader(shader_file!("editor/terrain/terrain.vert.glsl"))?
+                .tess_control_shader(shader_file!("editor/terrain/terrain.tc.glsl"))?
+                .tess_evaluation_shader(shader_file!("editor/terrain/terrain.te.glsl"))?
+                .geometry_shader(shader_file!("debug/terrain/normals.geometry.glsl"))?
+                .fragment_shader(shader_file!("debug/terrain/normals.frag.glsl"))?
                 .link()?;
-            normal_shader.set_used();
+            let patch_shader = Program::new()
+                .vertex_shader(shader_file!("editor/terrain/terrain.vert.glsl"))?
+                .tess_control_shader(shader_file!("editor/terrain/terrain.tc.glsl"))?
+                .tess_evaluation_shader(shader_file!("editor/terrain/terrain.te.glsl"))?
+                .fragment_shader(shader_file!("debug/terrain/patches.frag"))?
+                .link()?;
+            for shader in [&normal_shader, &patch_shader] {
+                shader.set_used();
+                shader.set_vec2("terrain_center", &center)?;
+                shader.set_f32("terrain_max_height", max_height)?;
+                shader.set_f32("terrain_size", terrain_size)?;
+                shader.set_i32("num_patches", num_patches)?;
+                shader.set_f32("patch_size", patch_size)?;
+            }
 
             TerrainDebug {
-                aabb_shader,
                 normal_shader,
+                patch_shader,
             }
         };
 
@@ -404,7 +620,7 @@ impl Terrain {
             shader,
             tess_level: 11.0,
 
-            texture,
+            material,
             heightmap,
 
             cursor,
@@ -414,6 +630,13 @@ impl Terrain {
             shadow_map,
             shadow_map_size,
             shadow_map_shader,
+            shadow_map_dirty: true,
+            shadow_map_sun_vp: Mat4::IDENTITY,
+            shadow_map_tess_level: 0.0,
+
+            cull_shader,
+            camera_patches: PatchDraws::new(),
+            shadow_patches: PatchDraws::new(),
 
             debug,
 
@@ -421,107 +644,516 @@ impl Terrain {
             max_height,
             num_patches,
             patch_size,
+
+            heights_version: 0,
+            heights_mirror: None,
+            heights_readback: None,
+            heights_conversion: None,
+            changed_area: None,
         })
     }
 
     // TODO: use a renderer
-    pub fn draw(&mut self, time: f32) -> Result<()> {
+    /// With `deferred_shadows` the shadows are left for post-processing to apply
+    pub fn draw(
+        &mut self,
+        time: f32,
+        atmosphere: &Atmosphere,
+        deferred_shadows: bool,
+    ) -> Result<()> {
         // Set common stuff for shadow pass / render pass
         unsafe {
             gl::PatchParameteri(gl::PATCH_VERTICES, 4);
-            gl::BindVertexArray(self.vao);
+            gl::BindVertexArray(self.vao.id());
+        }
+        bindings::bind_texture_sampled(
+            TextureUnit::HEIGHTMAP,
+            self.heightmap.texture.id(),
+            Sampler::LinearClamp,
+        );
+        // Both have their own border and filtering
+        bindings::bind_texture(TextureUnit::BRUSH, self.brush.texture.id());
+        bindings::bind_texture(TextureUnit::SHADOW_MAP, self.shadow_map.id());
 
-            // Default texture
-            gl::ActiveTexture(unit_to_gl_const(0));
-            gl::BindTexture(gl::TEXTURE_2D, self.texture);
+        // Draw into shadow map
+        let sun_vp = atmosphere.light_view_projection();
+        if self.shadow_map_dirty
+            || sun_vp != self.shadow_map_sun_vp
+            || self.tess_level != self.shadow_map_tess_level
+        {
+            self.cull_patches(&self.shadow_patches, false)?;
+            self.render_shadow_map()?;
+            self.shadow_map_dirty = false;
+            self.shadow_map_sun_vp = sun_vp;
+            self.shadow_map_tess_level = self.tess_level;
+        }
 
-            // Heightmap
-            gl::ActiveTexture(unit_to_gl_const(1));
-            gl::BindTexture(gl::TEXTURE_2D, self.heightmap.texture);
+        // Draw the scene
+        let _scope = profiler::scope("Terrain");
+        self.cull_patches(&self.camera_patches, true)?;
+        self.shader.set_used();
+        self.shader.set_vec2("cursor", &self.cursor)?;
+        self.shader
+            .set_f32("brush_size", self.brush.settings.size)?;
+        self.shader
+            .set_i32("deferred_shadows", deferred_shadows as i32)?;
+        atmosphere.set_lighting_uniforms(&self.shader)?;
+        atmosphere.set_fog_uniforms(&self.shader)?;
+        self.material.bind();
+        self.draw_patch_commands(&self.camera_patches);
 
-            // Brush
-            gl::ActiveTexture(unit_to_gl_const(2));
-            gl::BindTexture(gl::TEXTURE_2D, self.brush.texture);
+        Ok(())
+    }
 
-            // Shadow map
-            gl::ActiveTexture(unit_to_gl_const(3));
-            gl::BindTexture(gl::TEXTURE_2D, self.shadow_map);
+    /// Draws the normals of the tessellated terrain as lines over the scene
+    pub fn draw_normals(&self) -> Result<()> {
+        self.draw_overlay(&self.debug.normal_shader)
+    }
+
+    /// Draws the tessellated triangles in wireframe, colored by patch
+    pub fn draw_patches(&self) -> Result<()> {
+        unsafe {
+            gl::PolygonMode(gl::FRONT_AND_BACK, gl::LINE);
+        }
+        let result = self.draw_overlay(&self.debug.patch_shader);
+        unsafe {
+            gl::PolygonMode(gl::FRONT_AND_BACK, gl::FILL);
         }
+        result
+    }
 
-        // Draw into shadow map
+    /// Draws the terrain patches with another shader, tessellated the same way.
+    /// Uses the patches culled in `draw`.
+    fn draw_overlay(&self, shader: &Program) -> Result<()> {
+        shader.set_used();
+        unsafe {
+            gl::PatchParameteri(gl::PATCH_VERTICES, 4);
+            gl::BindVertexArray(self.vao.id());
+            bindings::bind_texture_sampled(
+                TextureUnit::HEIGHTMAP,
+                self.heightmap.texture.id(),
+                Sampler::LinearClamp,
+            );
+            // Lines on the surface would fight with it otherwise
+            gl::DepthFunc(gl::LEQUAL);
+        }
+        self.draw_patch_commands(&self.camera_patches);
+        unsafe {
+            gl::DepthFunc(gl::LESS);
+        }
+        Ok(())
+    }
+
+    /// Fills the draw commands and tess levels of the patches. The shadow pass doesn't
+    /// cull, since the shadows mustn't depend on where the camera is.
+    fn cull_patches(&self, draws: &PatchDraws, camera_culling: bool) -> Result<()> {
+        self.cull_shader.set_used();
+        self.cull_shader.set_f32("tess_level", self.tess_level)?;
+        self.cull_shader
+            .set_i32("camera_culling", camera_culling as i32)?;
+        let count = (self.num_patches * self.num_patches) as usize;
+        unsafe {
+            gl::BindBufferBase(
+                gl::SHADER_STORAGE_BUFFER,
+                COMMANDS_SSBO_BINDING,
+                draws.commands.id(),
+            );
+            gl::BindBufferBase(
+                gl::SHADER_STORAGE_BUFFER,
+                TESS_LEVELS_SSBO_BINDING,
+                draws.tess_levels.id(),
+            );
+        }
+        self.cull_shader
+            .dispatch(count.div_ceil(CULL_GROUP_SIZE) as u32, 1, 1);
+        unsafe {
+            gl::MemoryBarrier(gl::SHADER_STORAGE_BARRIER_BIT | gl::COMMAND_BARRIER_BIT);
+        }
+        Ok(())
+    }
+
+    /// One indirect draw for all the patches, expects the program and the vao bound
+    fn draw_patch_commands(&self, draws: &PatchDraws) {
+        let count = self.num_patches * self.num_patches;
+        unsafe {
+            gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, draws.commands.id());
+            gl::BindBufferBase(
+                gl::SHADER_STORAGE_BUFFER,
+                TESS_LEVELS_SSBO_BINDING,
+                draws.tess_levels.id(),
+            );
+            gl::MultiDrawArraysIndirect(gl::PATCHES, std::ptr::null(), count, 0);
+            gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, 0);
+        }
+        profiler::count_draw_call();
+    }
+
+    /// Queues the bounds and the normals around the cursor for `DebugRenderer`
+    pub fn draw_debug(&self) {
+        debug_draw::aabb(&self.aabb, Vec4::new(1.0, 0.8, 0.0, 1.0));
+
+        if !self.cursor.is_finite() {
+            return;
+        }
+        let step = self.brush.settings.size * 0.25;
+        let color = Vec4::new(0.2, 0.6, 1.0, 1.0);
+        for i in -2..=2 {
+            for j in -2..=2 {
+                let point = self.cursor + Vec2::new(i as f32, j as f32) * step;
+                let sample = |dx: f32, dz: f32| self.height_at(point + Vec2::new(dx, dz));
+                if let (Some(height), Some(right), Some(forward)) =
+                    (sample(0.0, 0.0), sample(1.0, 0.0), sample(0.0, 1.0))
+                {
+                    let normal = Vec3::new(height - right, 1.0, height - forward).normalize();
+                    let base = Vec3::new(point.x, height, point.y);
+                    debug_draw::line(base, base + normal * step * 0.5, color);
+                }
+            }
+        }
+    }
+
+    pub fn shadow_map(&self) -> GLuint {
+        self.shadow_map.id()
+    }
+
+    pub fn heightmap(&self) -> GLuint {
+        self.heightmap.texture.id()
+    }
+
+    pub fn brush_texture(&self) -> GLuint {
+        self.brush.texture.id()
+    }
+
+    /// Recreates the shadow map with the new size
+    pub fn set_shadow_map_size(&mut self, size: i32) {
+        if size == self.shadow_map_size {
+            return;
+        }
+        self.shadow_map = create_shadow_map(&self.shadow_map_fbo, size);
+        self.shadow_map_size = size;
+        self.shadow_map_dirty = true;
+    }
+
+    /// Height of the terrain where the heightmap is white
+    pub fn max_height(&self) -> f32 {
+        self.max_height
+    }
+
+    /// Stretches the terrain vertically, the heightmap stays the same
+    pub fn set_max_height(&mut self, max_height: f32) -> Result<()> {
+        self.max_height = max_height;
+        self.aabb.max.y = self.aabb.min.y + max_height;
+        for shader in [
+            &self.shader,
+            &self.shadow_map_shader,
+            &self.cull_shader,
+            &self.debug.normal_shader,
+            &self.debug.patch_shader,
+        ] {
+            shader.set_used();
+            shader.set_f32("terrain_max_height", max_height)?;
+        }
+        self.shadow_map_dirty = true;
+        Ok(())
+    }
+
+    /// Expects the terrain VAO and textures to be bound
+    fn render_shadow_map(&self) -> Result<()> {
+        let _scope = profiler::scope("Shadow map");
         self.shadow_map_shader.set_used();
-        self.shadow_map_shader
-            .set_f32("tess_level", self.tess_level)?;
         unsafe {
-            gl::BindFramebuffer(gl::FRAMEBUFFER, self.shadow_map_fbo);
+            let saved_target = SavedTarget::current();
+            // The wireframe debug view mustn't end up in the shadows
+            let mut polygon_mode: [GLint; 2] = [0; 2];
+            gl::GetIntegerv(gl::POLYGON_MODE, polygon_mode.as_mut_ptr());
+
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.shadow_map_fbo.id());
             gl::Viewport(0, 0, self.shadow_map_size, self.shadow_map_size);
+            gl::PolygonMode(gl::FRONT_AND_BACK, gl::FILL);
             gl::Clear(gl::DEPTH_BUFFER_BIT);
+        }
+        self.draw_patch_commands(&self.shadow_patches);
+        unsafe {
+            gl::PolygonMode(gl::FRONT_AND_BACK, polygon_mode[0] as GLenum);
+            saved_target.restore();
+        }
+        Ok(())
+    }
 
-            gl::DrawArraysInstanced(gl::PATCHES, 0, 4, 64 * 64);
+    /// Replaces the heightmap with the image, the old one stays if it can't be loaded
+    pub fn load_heightmap(&mut self, path: &str) -> Result<()> {
+        self.heightmap = Heightmap::from_image(path)?;
+        self.shadow_map_dirty = true;
+        self.heights_version += 1;
+        self.add_changed_area(self.aabb.min.xz(), self.aabb.max.xz());
+        Ok(())
+    }
 
-            gl::Viewport(0, 0, WINDOW_WIDTH as i32, WINDOW_HEIGHT as i32);
-            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+    /// The heightmap as it is now, kept on the GPU, to undo a stroke with
+    pub fn copy_heights(&self) -> HeightsCopy {
+        let texture_size = self.heightmap.texture_size;
+        let texture = Texture2D::new();
+        unsafe {
+            let size = texture_size as i32;
+            gl::TextureStorage2D(texture.id(), 1, gl::R16, size, size);
         }
+        copy_texture(self.heightmap.texture.id(), texture.id(), texture_size);
+        HeightsCopy {
+            texture,
+            texture_size,
+        }
+    }
 
-        // Draw the scene
-        self.shader.set_used();
-        self.shader.set_vec2("cursor", &self.cursor)?;
-        self.shader.set_f32("brush_size", self.brush.size)?;
-        self.shader.set_f32("tess_level", self.tess_level)?;
+    /// Puts the copy on the terrain and the heightmap into the copy, so that the same
+    /// copy swaps them back. Returns false if a heightmap of another size has been
+    /// loaded since, that copy is no use then.
+    pub fn swap_heights(&mut self, copy: &mut HeightsCopy) -> bool {
+        let heightmap = &self.heightmap;
+        if copy.texture_size != heightmap.texture_size {
+            return false;
+        }
+        // Through the brush's copy, which is the same size
+        let size = heightmap.texture_size;
+        copy_texture(heightmap.texture.id(), heightmap.copy.id(), size);
+        copy_texture(copy.texture.id(), heightmap.texture.id(), size);
+        copy_texture(heightmap.copy.id(), copy.texture.id(), size);
+        self.shadow_map_dirty = true;
+        self.heights_version += 1;
+        self.add_changed_area(self.aabb.min.xz(), self.aabb.max.xz());
+        true
+    }
+
+    /// Starts copying the heightmap to the CPU without stalling, poll the result once
+    /// a frame
+    pub fn read_heightmap(&self) -> HeightmapReadback {
+        let texture_size = self.heightmap.texture_size;
+        HeightmapReadback {
+            readback: TextureReadback::start(
+                self.heightmap.texture.id(),
+                gl::RED,
+                gl::UNSIGNED_SHORT,
+                texture_size * texture_size * size_of::<u16>(),
+            ),
+            texture_size,
+        }
+    }
+
+    /// Keeps a copy of the heightmap on the CPU for `mirrored_heights`, read back a few frames
+    /// after the terrain stops changing. Call once a frame.
+    pub fn update_heights_mirror(&mut self, jobs: &JobSystem) {
+        if let Some((readback, version)) = &self.heights_readback {
+            let (pixels, _) = match readback.try_take() {
+                Some(pixels) => pixels,
+                None => return,
+            };
+            // Millions of texels on the bigger heightmaps, too many for the frame
+            let convert = jobs.spawn(move || {
+                pixels
+                    .chunks_exact(2)
+                    .map(|bytes| u16::from_ne_bytes([bytes[0], bytes[1]]))
+                    .collect()
+            });
+            self.heights_conversion = Some((convert, *version));
+            self.heights_readback = None;
+        }
+        if let Some((convert, version)) = &self.heights_conversion {
+            let texels = match convert.try_take() {
+                Some(texels) => texels,
+                None => return,
+            };
+            self.heights_mirror = Some((texels, *version));
+            self.heights_conversion = None;
+        }
+        let up_to_date = matches!(
+            self.heights_mirror,
+            Some((_, version)) if version == self.heights_version
+        );
+        if !up_to_date {
+            self.heights_readback = Some((self.read_heightmap(), self.heights_version));
+        }
+    }
+
+    pub fn size(&self) -> f32 {
+        self.aabb.max.x - self.aabb.min.x
+    }
 
+    /// Returns terrain height at a world position by reading back the heightmap texels
+    /// around it, or None if the position is outside the terrain
+    pub fn height_at(&self, point: Vec2) -> Option<f32> {
+        let uv = (point - self.aabb.min.xz()) / self.size();
+        if !(0.0..=1.0).contains(&uv.x) || !(0.0..=1.0).contains(&uv.y) {
+            return None;
+        }
+
+        // Bilinear filtering between the texel centers, same as the sampler does
+        let max_texel = self.heightmap.texture_size as i32 - 1;
+        let texel = uv * self.heightmap.texture_size as f32 - Vec2::new(0.5, 0.5);
+        let x = (texel.x.floor() as i32).clamp(0, max_texel - 1);
+        let y = (texel.y.floor() as i32).clamp(0, max_texel - 1);
+        let t = (texel - Vec2::new(x as f32, y as f32)).clamp(Vec2::ZERO, Vec2::ONE);
+
+        let mut texels = [0u16; 4];
         unsafe {
-            // gl::PolygonMode(gl::FRONT_AND_BACK, gl::LINE);
-            gl::DrawArraysInstanced(gl::PATCHES, 0, 4, 64 * 64);
-            // gl::PolygonMode(gl::FRONT_AND_BACK, gl::FILL);
-        }
-
-        // // Draw debug stuff
-        // {
-        //     // Draw AABB
-        //     let debug = &mut self.debug;
-        //     debug.aabb_shader.set_used();
-        //     debug.aabb_shader.set_f32("time", time)?;
-        //     unsafe {
-        //         gl::DrawArrays(gl::LINE_STRIP, 0, 16);
-        //     }
-
-        //     // Draw normals
-        //     debug.normal_shader.set_used();
-        //     debug.normal_shader.set_f32("tess_level", self.tess_level)?;
-        //     unsafe {
-        //         gl::DrawArraysInstanced(gl::PATCHES, 0, 4, 64 * 64);
-        //     }
-        // }
+            gl::GetTextureSubImage(
+                self.heightmap.texture.id(),
+                0,
+                x,
+                y,
+                0,
+                2,
+                2,
+                1,
+                gl::RED,
+                gl::UNSIGNED_SHORT,
+                std::mem::size_of_val(&texels) as i32,
+                texels.as_mut_ptr() as *mut c_void,
+            );
+        }
+        let [h00, h10, h01, h11] = texels.map(|h| h as f32 / u16::MAX as f32);
+        let bottom = h00 + (h10 - h00) * t.x;
+        let top = h01 + (h11 - h01) * t.x;
+        let height = bottom + (top - bottom) * t.y;
 
-        Ok(())
+        Some(self.aabb.min.y + height * self.max_height)
     }
 
-    pub fn get_heightmap_pixels(&self) -> (Vec<u8>, usize) {
-        let buffer_size = self.heightmap.texture_size * self.heightmap.texture_size * 2;
-        let mut pixels = Vec::<u8>::with_capacity(buffer_size);
+    /// Lowest and highest terrain between the corners of the rectangle, or None if any
+    /// of it is outside the terrain
+    pub fn height_range(&self, min: Vec2, max: Vec2) -> Option<(f32, f32)> {
+        let (x, y, width, height) = self.texels_under(min, max)?;
+        let mut texels = vec![0u16; (width * height) as usize];
         unsafe {
-            pixels.set_len(buffer_size);
-            gl::GetTextureImage(
-                self.heightmap.texture,
+            gl::GetTextureSubImage(
+                self.heightmap.texture.id(),
+                0,
+                x,
+                y,
                 0,
+                width,
+                height,
+                1,
                 gl::RED,
                 gl::UNSIGNED_SHORT,
-                buffer_size as i32,
-                pixels.as_mut_ptr() as *mut c_void,
+                (texels.len() * std::mem::size_of::<u16>()) as i32,
+                texels.as_mut_ptr() as *mut c_void,
             );
         }
-        (pixels, self.heightmap.texture_size)
+        let to_height = |h: u16| self.aabb.min.y + h as f32 / u16::MAX as f32 * self.max_height;
+        let low = texels.iter().copied().min()?;
+        let high = texels.iter().copied().max()?;
+        Some((to_height(low), to_height(high)))
     }
 
-    pub fn size(&self) -> f32 {
-        self.aabb.max.x - self.aabb.min.x
+    /// Sets the terrain between the corners of the rectangle to the height, returns
+    /// false if any of it is outside the terrain
+    pub fn flatten(&mut self, min: Vec2, max: Vec2, height: f32) -> bool {
+        let (x, y, width, texels_height) = match self.texels_under(min, max) {
+            Some(texels) => texels,
+            None => return false,
+        };
+        let value = ((height - self.aabb.min.y) / self.max_height).clamp(0.0, 1.0);
+        let texels =
+            vec![(value * u16::MAX as f32).round() as u16; (width * texels_height) as usize];
+        unsafe {
+            gl::TextureSubImage2D(
+                self.heightmap.texture.id(),
+                0,
+                x,
+                y,
+                width,
+                texels_height,
+                gl::RED,
+                gl::UNSIGNED_SHORT,
+                texels.as_ptr() as *const _,
+            );
+        }
+        self.shadow_map_dirty = true;
+        self.heights_version += 1;
+        self.add_changed_area(min, max);
+        true
     }
 
-    pub fn shape_terrain(&mut self, delta_time: f32, raise: bool) {
+    fn texels_under(&self, min: Vec2, max: Vec2) -> Option<(i32, i32, i32, i32)> {
+        texels_under(&self.aabb, self.heightmap.texture_size, min, max)
+    }
+
+    /// The whole heightmap for when there are lots of heights to look up, or None until
+    /// the mirror has caught up with the last change. Never stalls on the GPU.
+    pub fn mirrored_heights(&self) -> Option<HeightSnapshot> {
+        match &self.heights_mirror {
+            Some((texels, version)) if *version == self.heights_version => Some(HeightSnapshot {
+                texels,
+                texture_size: self.heightmap.texture_size,
+                aabb: AABB::new(self.aabb.min, self.aabb.max),
+                max_height: self.max_height,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Pressure scales the brush strength, and tilt softens its edge and turns its
+    /// shape by as much as the pen leans. Inverting swaps raising and lowering.
+    pub fn shape_terrain(&mut self, delta_time: f32, stroke: Stroke) {
         let terrain_size = self.size();
         let cursor = (self.cursor - self.aabb.min.xz()) / terrain_size;
-        self.heightmap
-            .draw_on_heightmap(cursor, &self.brush, terrain_size, delta_time, raise);
+        let mut settings = self.brush.settings.clone();
+        settings.falloff = settings.tilted_falloff(stroke.tilt);
+        if stroke.invert {
+            settings.mode = settings.mode.inverted();
+        }
+        self.heightmap.draw_on_heightmap(
+            cursor,
+            &self.brush,
+            &settings,
+            stroke.tilt * std::f32::consts::FRAC_PI_2,
+            terrain_size,
+            delta_time * stroke.pressure,
+        );
+        self.shadow_map_dirty = true;
+        self.heights_version += 1;
+        let radius = Vec2::splat(settings.size * 0.5);
+        self.add_changed_area(self.cursor - radius, self.cursor + radius);
+    }
+
+    fn add_changed_area(&mut self, min: Vec2, max: Vec2) {
+        self.changed_area = Some(match self.changed_area {
+            Some((old_min, old_max)) => (old_min.min(min), old_max.max(max)),
+            None => (min, max),
+        });
+    }
+
+    /// Where the heightmap has changed since the last call, for whatever stands on it
+    pub fn take_changed_area(&mut self) -> Option<(Vec2, Vec2)> {
+        self.changed_area.take()
+    }
+
+    /// Makes the flatten mode blend toward the height under the cursor. Called every
+    /// frame while Alt is held, the last height sampled stays once it's let go.
+    pub fn sample_flatten_height(&mut self) {
+        if let Some(height) = self.height_at(self.cursor) {
+            let target = (height - self.aabb.min.y) / self.max_height;
+            self.brush.flatten_target = Some(target);
+        }
+    }
+
+    /// What the flatten mode blends toward in world units, None if it follows the cursor
+    pub fn flatten_height(&self) -> Option<f32> {
+        let target = self.brush.flatten_target?;
+        Some(self.aabb.min.y + target * self.max_height)
+    }
+
+    /// Paints the layer onto the terrain under the cursor, or the first layer back over
+    /// it when inverted. Uses the brush size, strength and falloff but not its shape.
+    pub fn paint_layer(&mut self, layer: usize, delta_time: f32, stroke: Stroke) {
+        let terrain_size = self.size();
+        let center = (self.cursor - self.aabb.min.xz()) / terrain_size;
+        let settings = &self.brush.settings;
+        // The size is the width of the brush
+        let radius = settings.size * 0.5 / terrain_size;
+        let layer = if stroke.invert { 0 } else { layer };
+        let amount = settings.strength * delta_time * stroke.pressure * PAINT_SPEED;
+        let falloff = settings.tilted_falloff(stroke.tilt);
+        self.material.paint(layer, center, radius, falloff, amount);
     }
 
     /// Currently only intersects with the bottom plane of the AABB
@@ -550,13 +1182,106 @@ impl Terrain {
     pub fn hide_cursor(&mut self) {
         self.cursor = vec2_infinity();
     }
-}
 
-impl Drop for Terrain {
-    fn drop(&mut self) {
-        unsafe {
-            gl::DeleteVertexArrays(1, &self.vao);
-            gl::DeleteTextures(1, &self.texture);
+    /// Moves the terrain along with the local origin
+    pub fn shift_origin(&mut self, shift: Vec3) -> Result<()> {
+        self.center -= shift.xz();
+        self.aabb = AABB::new(self.aabb.min - shift, self.aabb.max - shift);
+        self.cursor -= shift.xz();
+        if let Some((min, max)) = &mut self.changed_area {
+            *min -= shift.xz();
+            *max -= shift.xz();
         }
+
+        self.shader.set_used();
+        self.shader.set_vec2("terrain_center", &self.center)?;
+        self.shadow_map_shader.set_used();
+        self.shadow_map_shader
+            .set_vec2("terrain_center", &self.center)?;
+        for shader in [
+            &self.cull_shader,
+            &self.debug.normal_shader,
+            &self.debug.patch_shader,
+        ] {
+            shader.set_used();
+            shader.set_vec2("terrain_center", &self.center)?;
+        }
+        self.shadow_map_dirty = true;
+        Ok(())
+    }
+}
+
+/// All of one square texture into another of the same size and format
+fn copy_texture(from: GLuint, to: GLuint, size: usize) {
+    let size = size as i32;
+    unsafe {
+        gl::CopyImageSubData(
+            from,
+            gl::TEXTURE_2D,
+            0,
+            0,
+            0,
+            0,
+            to,
+            gl::TEXTURE_2D,
+            0,
+            0,
+            0,
+            0,
+            size,
+            size,
+            1,
+        );
+    }
+}
+
+/// Makes a depth texture and attaches it to the framebuffer
+fn create_shadow_map(fbo: &Framebuffer, size: i32) -> Texture2D {
+    let texture = Texture2D::new();
+    let (fbo, shadow_map) = (fbo.id(), texture.id());
+    unsafe {
+        gl::TextureParameteri(shadow_map, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+        gl::TextureParameteri(shadow_map, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+        gl::TextureParameteri(shadow_map, gl::TEXTURE_WRAP_S, gl::REPEAT as i32);
+        gl::TextureParameteri(shadow_map, gl::TEXTURE_WRAP_T, gl::REPEAT as i32);
+        gl::TextureStorage2D(shadow_map, 1, gl::DEPTH_COMPONENT16, size, size);
+        gl::NamedFramebufferTexture(fbo, gl::DEPTH_ATTACHMENT, shadow_map, 0);
+
+        assert_eq!(
+            gl::CheckNamedFramebufferStatus(fbo, gl::FRAMEBUFFER),
+            gl::FRAMEBUFFER_COMPLETE,
+            "Shadow map framebuffer is incomplete",
+        );
+    }
+    texture
+}
+
+/// The texels the heightmap is filtered from between the corners of the rectangle:
+/// x, y, width and height. None if any of it is outside the terrain.
+fn texels_under(
+    aabb: &AABB,
+    texture_size: usize,
+    min: Vec2,
+    max: Vec2,
+) -> Option<(i32, i32, i32, i32)> {
+    let to_uv = |point: Vec2| (point - aabb.min.xz()) / (aabb.max.x - aabb.min.x);
+    let (uv_min, uv_max) = (to_uv(min), to_uv(max));
+    let inside = |uv: Vec2| (0.0..=1.0).contains(&uv.x) && (0.0..=1.0).contains(&uv.y);
+    if !inside(uv_min) || !inside(uv_max) {
+        return None;
     }
+    // Every texel whose center is within one texel of the rectangle, same as the
+    // bilinear filtering in `height_at`
+    let size = texture_size as f32;
+    let max_texel = texture_size as i32 - 1;
+    let first = (uv_min * size - Vec2::splat(0.5))
+        .floor()
+        .as_ivec2()
+        .clamp(IVec2::ZERO, IVec2::splat(max_texel));
+    let last = (uv_max * size - Vec2::splat(0.5))
+        .ceil()
+        .as_ivec2()
+        .clamp(IVec2::ZERO, IVec2::splat(max_texel));
+    let count = last - first + IVec2::ONE;
+    Some((first.x, first.y, count.x, count.y))
 }
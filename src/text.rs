@@ -0,0 +1,429 @@
+//! Text labels anchored to world positions, for names, coordinates and measurements
+//! in the editor. Glyphs come from a signed distance field atlas, so the text stays
+//! sharp at any size, and are drawn as screen-facing quads like spherical billboards.
+
+use std::collections::HashMap;
+use std::mem::size_of;
+
+use ab_glyph::{Font, FontRef, PxScale, ScaleFont};
+use gl::types::*;
+use glam::{Vec2, Vec3, Vec4};
+use thiserror::Error;
+
+use crate::opengl::bindings::{self, Sampler, TextureUnit};
+use crate::opengl::empty_vertex_array;
+use crate::opengl::shader::Program;
+use crate::profiler;
+use crate::utils::size_of_slice;
+use crate::Result;
+
+/// Must match the buffer binding in label.vert
+const GLYPHS_SSBO_BINDING: u32 = 0;
+
+/// Pixels per line when rasterizing the glyphs into the atlas
+const RASTER_SIZE: f32 = 32.0;
+/// How far from the edges the distance field goes, in atlas pixels
+const SPREAD: usize = 4;
+const ATLAS_WIDTH: usize = 512;
+
+#[derive(Debug, Error)]
+pub enum TextError {
+    #[error("Couldn't read the font: {0}")]
+    InvalidFont(#[from] ab_glyph::InvalidFont),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum LabelSize {
+    /// Height of a line in world units, gets smaller with distance
+    World(f32),
+    /// Height of a line in logical pixels at any distance, like the GUI's text
+    Pixels(f32),
+}
+
+#[derive(Debug, Clone)]
+pub struct Label {
+    /// May have several lines
+    pub text: String,
+    /// The text is centered horizontally above this point
+    pub position: Vec3,
+    /// Linear
+    pub color: Vec4,
+    pub size: LabelSize,
+    /// Visible through the scene
+    pub on_top: bool,
+}
+
+/// Where a glyph is in the atlas and how to place it, in lines
+#[derive(Debug, Clone, Copy)]
+struct GlyphInfo {
+    uv_min: Vec2,
+    uv_max: Vec2,
+    /// Bottom left corner relative to the pen position on the baseline
+    offset: Vec2,
+    size: Vec2,
+    advance: f32,
+}
+
+/// Printable ASCII characters rendered into a signed distance field texture
+struct SdfFont {
+    atlas: GLuint,
+    glyphs: HashMap<char, GlyphInfo>,
+    /// Distance between baselines, in lines
+    line_gap: f32,
+    /// Below the baseline, in lines
+    descent: f32,
+}
+
+impl SdfFont {
+    fn new(font_data: &[u8]) -> Result<Self> {
+        let font = FontRef::try_from_slice(font_data).map_err(TextError::from)?;
+        let scaled = font.as_scaled(PxScale::from(RASTER_SIZE));
+        let line_height = scaled.height();
+
+        // Shelf packing: glyphs go left to right in rows as tall as the tallest one
+        let mut fields = vec![];
+        let (mut x, mut y, mut row_height) = (0, 0, 0);
+        for c in (' '..='~').chain(std::iter::once('°')) {
+            let id = scaled.glyph_id(c);
+            let advance = scaled.h_advance(id) / line_height;
+            let outline = font.outline_glyph(id.with_scale(PxScale::from(RASTER_SIZE)));
+            let outline = match outline {
+                Some(outline) => outline,
+                None => {
+                    fields.push((c, None, advance));
+                    continue;
+                }
+            };
+
+            let bounds = outline.px_bounds();
+            let width = bounds.width() as usize + 2 * SPREAD;
+            let height = bounds.height() as usize + 2 * SPREAD;
+            let mut inside = vec![false; width * height];
+            outline.draw(|gx, gy, coverage| {
+                let index = (gy as usize + SPREAD) * width + gx as usize + SPREAD;
+                inside[index] = coverage >= 0.5;
+            });
+
+            if x + width > ATLAS_WIDTH {
+                x = 0;
+                y += row_height;
+                row_height = 0;
+            }
+            let placement = (x, y, width, height, bounds.min.x, bounds.max.y);
+            fields.push((
+                c,
+                Some((placement, signed_distance_field(&inside, width, height))),
+                advance,
+            ));
+            x += width;
+            row_height = row_height.max(height);
+        }
+        let atlas_height = (y + row_height).next_power_of_two();
+
+        let mut pixels = vec![0u8; ATLAS_WIDTH * atlas_height];
+        let mut glyphs = HashMap::new();
+        let atlas_size = Vec2::new(ATLAS_WIDTH as f32, atlas_height as f32);
+        for (c, field, advance) in fields {
+            let mut info = GlyphInfo {
+                uv_min: Vec2::ZERO,
+                uv_max: Vec2::ZERO,
+                offset: Vec2::ZERO,
+                size: Vec2::ZERO,
+                advance,
+            };
+            if let Some(((x, y, width, height, left, bottom), field)) = field {
+                for row in 0..height {
+                    let start = (y + row) * ATLAS_WIDTH + x;
+                    pixels[start..start + width]
+                        .copy_from_slice(&field[row * width..(row + 1) * width]);
+                }
+                info.uv_min = Vec2::new(x as f32, (y + height) as f32) / atlas_size;
+                info.uv_max = Vec2::new((x + width) as f32, y as f32) / atlas_size;
+                // Rasterized top to bottom, the quads are laid out bottom to top
+                info.offset =
+                    Vec2::new(left - SPREAD as f32, -bottom - SPREAD as f32) / line_height;
+                info.size = Vec2::new(width as f32, height as f32) / line_height;
+            }
+            glyphs.insert(c, info);
+        }
+
+        let mut atlas: GLuint = 0;
+        unsafe {
+            gl::CreateTextures(gl::TEXTURE_2D, 1, &mut atlas);
+            gl::TextureStorage2D(atlas, 1, gl::R8, ATLAS_WIDTH as i32, atlas_height as i32);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::TextureSubImage2D(
+                atlas,
+                0,
+                0,
+                0,
+                ATLAS_WIDTH as i32,
+                atlas_height as i32,
+                gl::RED,
+                gl::UNSIGNED_BYTE,
+                pixels.as_ptr() as *const _,
+            );
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+        }
+
+        Ok(SdfFont {
+            atlas,
+            glyphs,
+            line_gap: (line_height + scaled.line_gap()) / line_height,
+            descent: -scaled.descent() / line_height,
+        })
+    }
+}
+
+impl Drop for SdfFont {
+    fn drop(&mut self) {
+        bindings::delete_textures(&[self.atlas]);
+    }
+}
+
+/// Signed distance to the nearest edge for every pixel, mapped to bytes with the
+/// edge at 128 and `SPREAD` pixels on either side covering the rest
+fn signed_distance_field(inside: &[bool], width: usize, height: usize) -> Vec<u8> {
+    let to_inside = distance_transform(inside, width, height, true);
+    let to_outside = distance_transform(inside, width, height, false);
+    inside
+        .iter()
+        .zip(to_inside.iter().zip(to_outside))
+        .map(|(&inside, (&to_inside, to_outside))| {
+            // The edge is half way between the pixel centers
+            let distance = if inside {
+                to_outside - 0.5
+            } else {
+                0.5 - to_inside
+            };
+            let value = 0.5 + distance / (2.0 * SPREAD as f32);
+            (value.clamp(0.0, 1.0) * 255.0).round() as u8
+        })
+        .collect()
+}
+
+/// Euclidean distance from every pixel to the nearest one where `inside` equals
+/// `target`, using the two-pass 8SSEDT approximation
+fn distance_transform(inside: &[bool], width: usize, height: usize, target: bool) -> Vec<f32> {
+    const FAR: (i32, i32) = (9999, 9999);
+    let mut nearest: Vec<(i32, i32)> = inside
+        .iter()
+        .map(|&pixel| if pixel == target { (0, 0) } else { FAR })
+        .collect();
+    let length2 = |(dx, dy): (i32, i32)| dx * dx + dy * dy;
+    let compare = |nearest: &mut Vec<(i32, i32)>, x: usize, y: usize, ox: i32, oy: i32| {
+        let (nx, ny) = (x as i32 + ox, y as i32 + oy);
+        if nx < 0 || ny < 0 || nx >= width as i32 || ny >= height as i32 {
+            return;
+        }
+        let other = nearest[ny as usize * width + nx as usize];
+        let candidate = (other.0 + ox, other.1 + oy);
+        let current = &mut nearest[y * width + x];
+        if length2(candidate) < length2(*current) {
+            *current = candidate;
+        }
+    };
+
+    for y in 0..height {
+        for x in 0..width {
+            for (ox, oy) in [(-1, 0), (0, -1), (-1, -1), (1, -1)] {
+                compare(&mut nearest, x, y, ox, oy);
+            }
+        }
+        for x in (0..width).rev() {
+            compare(&mut nearest, x, y, 1, 0);
+        }
+    }
+    for y in (0..height).rev() {
+        for x in (0..width).rev() {
+            for (ox, oy) in [(1, 0), (0, 1), (-1, 1), (1, 1)] {
+                compare(&mut nearest, x, y, ox, oy);
+            }
+        }
+        for x in 0..width {
+            compare(&mut nearest, x, y, -1, 0);
+        }
+    }
+
+    nearest
+        .into_iter()
+        .map(|offset| (length2(offset) as f32).sqrt())
+        .collect()
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct GlyphBlock {
+    anchor: Vec3,
+    height: f32,
+    offset: Vec2,
+    size: Vec2,
+    uv_min: Vec2,
+    uv_max: Vec2,
+    color: Vec4,
+    flags: u32,
+    _padding: [u32; 3],
+}
+
+/// Must match the flags in label.vert
+const FLAG_SCREEN_SIZE: u32 = 1;
+const FLAG_ON_TOP: u32 = 2;
+
+pub struct TextRenderer {
+    font: SdfFont,
+    shader: Program,
+    vao: GLuint,
+    buffer: GLuint,
+    /// In glyphs
+    capacity: usize,
+    /// Physical pixels per logical one, for `LabelSize::Pixels`
+    scale_factor: f32,
+}
+
+impl TextRenderer {
+    pub fn new() -> Result<Self> {
+        // The GUI's monospace font, good for numbers
+        let fonts = epaint::text::FontDefinitions::default();
+        let font = SdfFont::new(&fonts.font_data["Hack"])?;
+
+        let shader = Program::new()
+            .vertex_shader(shader_file!("text/label.vert"))?
+            .fragment_shader(shader_file!("text/label.frag"))?
+            .link()?;
+
+        let vao = empty_vertex_array();
+
+        Ok(TextRenderer {
+            font,
+            shader,
+            vao,
+            buffer: 0,
+            capacity: 0,
+            scale_factor: 1.0,
+        })
+    }
+
+    /// When the window is created or moves to a monitor with a different DPI
+    pub fn set_scale_factor(&mut self, scale_factor: f32) {
+        self.scale_factor = scale_factor;
+    }
+
+    /// Lays out the glyphs of a label. Unknown characters show up as '?'.
+    fn layout(&self, label: &Label, glyphs: &mut Vec<GlyphBlock>) {
+        let (height, mut flags) = match label.size {
+            LabelSize::World(height) => (height, 0),
+            LabelSize::Pixels(height) => (height * self.scale_factor, FLAG_SCREEN_SIZE),
+        };
+        if label.on_top {
+            flags |= FLAG_ON_TOP;
+        }
+
+        let font = &self.font;
+        let glyph = |c: char| font.glyphs.get(&c).or_else(|| font.glyphs.get(&'?'));
+        let lines: Vec<&str> = label.text.lines().collect();
+        for (index, line) in lines.iter().enumerate() {
+            let width: f32 = line
+                .chars()
+                .filter_map(glyph)
+                .map(|info| info.advance)
+                .sum();
+            let baseline = font.descent + (lines.len() - 1 - index) as f32 * font.line_gap;
+            let mut pen = Vec2::new(-0.5 * width, baseline);
+            for info in line.chars().filter_map(glyph) {
+                if info.size.x > 0.0 {
+                    glyphs.push(GlyphBlock {
+                        anchor: label.position,
+                        height,
+                        offset: pen + info.offset,
+                        size: info.size,
+                        uv_min: info.uv_min,
+                        uv_max: info.uv_max,
+                        color: label.color,
+                        flags,
+                        _padding: [0; 3],
+                    });
+                }
+                pen.x += info.advance;
+            }
+        }
+    }
+
+    /// Draws onto the current framebuffer, hiding the text behind the scene unless
+    /// the label is on top. `scene_depth` must be the same size as the framebuffer.
+    pub fn draw(&mut self, labels: &[Label], scene_depth: GLuint) -> Result<()> {
+        let mut glyphs = vec![];
+        for label in labels {
+            self.layout(label, &mut glyphs);
+        }
+        if glyphs.is_empty() {
+            return Ok(());
+        }
+
+        if glyphs.len() > self.capacity {
+            self.capacity = glyphs.len().next_power_of_two();
+            unsafe {
+                if self.buffer != 0 {
+                    gl::DeleteBuffers(1, &self.buffer);
+                }
+                gl::CreateBuffers(1, &mut self.buffer);
+                gl::NamedBufferStorage(
+                    self.buffer,
+                    (self.capacity * size_of::<GlyphBlock>()) as isize,
+                    std::ptr::null(),
+                    gl::DYNAMIC_STORAGE_BIT,
+                );
+            }
+        }
+
+        let mut viewport: [GLint; 4] = [0; 4];
+        unsafe {
+            gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+        }
+        self.shader.set_used();
+        self.shader.set_vec2(
+            "viewport_size",
+            &Vec2::new(viewport[2] as f32, viewport[3] as f32),
+        )?;
+        unsafe {
+            gl::NamedBufferSubData(
+                self.buffer,
+                0,
+                size_of_slice(&glyphs) as isize,
+                glyphs.as_ptr() as *const _,
+            );
+            bindings::bind_texture_sampled(
+                TextureUnit::ALBEDO,
+                self.font.atlas,
+                Sampler::LinearClamp,
+            );
+            bindings::bind_texture_sampled(
+                TextureUnit::SCENE_DEPTH,
+                scene_depth,
+                Sampler::NearestClamp,
+            );
+
+            // The depth test is done in the shader
+            gl::Disable(gl::DEPTH_TEST);
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::ONE, gl::ONE_MINUS_SRC_ALPHA);
+
+            gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, GLYPHS_SSBO_BINDING, self.buffer);
+            gl::BindVertexArray(self.vao);
+            gl::DrawArraysInstanced(gl::TRIANGLE_STRIP, 0, 4, glyphs.len() as i32);
+            profiler::count_draw_call();
+
+            gl::Disable(gl::BLEND);
+            gl::Enable(gl::DEPTH_TEST);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TextRenderer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.buffer);
+            gl::DeleteVertexArrays(1, &self.vao);
+        }
+    }
+}
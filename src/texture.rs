@@ -1,41 +1,676 @@
-use gl::types::GLenum;
+use std::convert::TryInto;
+use std::fs;
+use std::path::Path;
+
+use gl::types::*;
+
+use crate::Result;
 
 pub fn calculate_mip_levels(width: usize, height: usize) -> i32 {
     let dimension = width.max(height) as f32;
     dimension.log2().floor() as i32 + 1
 }
 
+/// The most the driver supports
 pub fn get_max_anisotropy() -> f32 {
-    const MAX_ANISOTROPY: f32 = 8.0;
     let mut value: f32 = 0.0;
     unsafe {
         gl::GetFloatv(gl::MAX_TEXTURE_MAX_ANISOTROPY, &mut value);
     }
-    if value < MAX_ANISOTROPY {
-        value
+    value
+}
+
+/// Texture with a full mip chain, sampled with `Sampler::TrilinearRepeat`
+pub fn create_texture(
+    width: u32,
+    height: u32,
+    format: GLenum,
+    pixels: &[u8],
+    srgb: bool,
+) -> GLuint {
+    let mut texture: GLuint = 0;
+    unsafe {
+        gl::CreateTextures(gl::TEXTURE_2D, 1, &mut texture);
+        gl::TextureStorage2D(
+            texture,
+            calculate_mip_levels(width as usize, height as usize),
+            if srgb { gl::SRGB8_ALPHA8 } else { gl::RGBA8 },
+            width as i32,
+            height as i32,
+        );
+        let tightly_packed = format != gl::RGBA && format != gl::BGRA;
+        if tightly_packed {
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+        }
+        gl::TextureSubImage2D(
+            texture,
+            0,
+            0,
+            0,
+            width as i32,
+            height as i32,
+            format,
+            gl::UNSIGNED_BYTE,
+            pixels.as_ptr() as *const _,
+        );
+        gl::GenerateTextureMipmap(texture);
+        if tightly_packed {
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+        }
+    }
+    texture
+}
+
+/// Mipmapped array for `layers` images of the same size, sampled with
+/// `Sampler::TrilinearRepeat`. The layers are filled in with `upload_texture_layer`.
+pub fn create_texture_array(
+    width: u32,
+    height: u32,
+    layers: usize,
+    internal_format: GLenum,
+) -> GLuint {
+    let mut texture: GLuint = 0;
+    unsafe {
+        gl::CreateTextures(gl::TEXTURE_2D_ARRAY, 1, &mut texture);
+        gl::TextureStorage3D(
+            texture,
+            calculate_mip_levels(width as usize, height as usize),
+            internal_format,
+            width as i32,
+            height as i32,
+            layers as i32,
+        );
+    }
+    texture
+}
+
+/// Replaces a layer of an array from `create_texture_array` and regenerates the mipmaps.
+/// The pixels have one byte per channel and must be the size of the array.
+pub fn upload_texture_layer(
+    texture: GLuint,
+    layer: usize,
+    width: u32,
+    height: u32,
+    format: GLenum,
+    pixels: &[u8],
+) {
+    let channels = match format {
+        gl::RED => 1,
+        gl::RG => 2,
+        gl::RGB | gl::BGR => 3,
+        _ => 4,
+    };
+    assert_eq!(
+        pixels.len(),
+        (width * height) as usize * channels,
+        "Layer image doesn't match the texture array"
+    );
+    unsafe {
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+        gl::TextureSubImage3D(
+            texture,
+            0,
+            0,
+            0,
+            layer as i32,
+            width as i32,
+            height as i32,
+            1,
+            format,
+            gl::UNSIGNED_BYTE,
+            pixels.as_ptr() as *const _,
+        );
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+        gl::GenerateTextureMipmap(texture);
+    }
+}
+
+// S3TC isn't core, the names are from EXT_texture_compression_s3tc and EXT_texture_sRGB
+const COMPRESSED_RGBA_S3TC_DXT1: GLenum = 0x83F1;
+const COMPRESSED_SRGB_ALPHA_S3TC_DXT1: GLenum = 0x8C4D;
+
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+const KTX2_HEADER_SIZE: usize = 80;
+const DDS_MAGIC: &[u8; 4] = b"DDS ";
+const DDS_HEADER_SIZE: usize = 128;
+const DDS_DX10_HEADER_SIZE: usize = 20;
+
+/// Block-compressed formats the loaders accept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockFormat {
+    /// RGB with 1-bit alpha, 8 bytes per block
+    Bc1,
+    /// Two channels, for normal maps
+    Bc5,
+    /// High quality RGBA
+    Bc7,
+}
+
+impl BlockFormat {
+    /// Bytes per 4x4 block
+    fn block_size(self) -> usize {
+        match self {
+            BlockFormat::Bc1 => 8,
+            BlockFormat::Bc5 | BlockFormat::Bc7 => 16,
+        }
+    }
+
+    /// BC5 holds data, so it's never sRGB
+    fn internal_format(self, srgb: bool) -> GLenum {
+        match (self, srgb) {
+            (BlockFormat::Bc1, false) => COMPRESSED_RGBA_S3TC_DXT1,
+            (BlockFormat::Bc1, true) => COMPRESSED_SRGB_ALPHA_S3TC_DXT1,
+            (BlockFormat::Bc5, _) => gl::COMPRESSED_RG_RGTC2,
+            (BlockFormat::Bc7, false) => gl::COMPRESSED_RGBA_BPTC_UNORM,
+            (BlockFormat::Bc7, true) => gl::COMPRESSED_SRGB_ALPHA_BPTC_UNORM,
+        }
+    }
+}
+
+/// A KTX2 or DDS file with its mip chain, still compressed
+pub struct CompressedImage {
+    pub format: BlockFormat,
+    pub width: u32,
+    pub height: u32,
+    data: Vec<u8>,
+    /// Where each level is in `data`, the largest first
+    levels: Vec<(usize, usize)>,
+}
+
+/// Whether the file should be loaded with `CompressedImage::load` rather than `image`
+pub fn is_compressed(path: &Path) -> bool {
+    let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+    matches!(extension.as_deref(), Some("ktx2") | Some("dds"))
+}
+
+impl CompressedImage {
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = fs::read(path)?;
+        let image = if data.starts_with(&KTX2_IDENTIFIER) {
+            parse_ktx2(data)
+        } else if data.starts_with(DDS_MAGIC) {
+            parse_dds(data)
+        } else {
+            Err("Not a KTX2 or DDS file".into())
+        };
+        image.map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+
+    /// Creates an immutable texture with the levels in the file. Compressed textures
+    /// can't generate their own mipmaps, so the chain has to be baked in.
+    pub fn upload(&self, srgb: bool) -> GLuint {
+        let internal_format = self.format.internal_format(srgb);
+        let mut texture: GLuint = 0;
+        unsafe {
+            gl::CreateTextures(gl::TEXTURE_2D, 1, &mut texture);
+            gl::TextureStorage2D(
+                texture,
+                self.levels.len() as i32,
+                internal_format,
+                self.width as i32,
+                self.height as i32,
+            );
+        }
+        self.write_levels(texture, internal_format);
+        texture
+    }
+
+    /// Replaces the levels of a texture made by `upload`, which must have the same
+    /// size, format and number of levels
+    pub fn upload_into(&self, texture: GLuint, srgb: bool) -> Result<()> {
+        let internal_format = self.format.internal_format(srgb);
+        let (mut format, mut levels) = (0, 0);
+        unsafe {
+            gl::GetTextureLevelParameteriv(texture, 0, gl::TEXTURE_INTERNAL_FORMAT, &mut format);
+            gl::GetTextureParameteriv(texture, gl::TEXTURE_IMMUTABLE_LEVELS, &mut levels);
+        }
+        if format as GLenum != internal_format || levels as usize != self.levels.len() {
+            return Err("Different format or number of mip levels".into());
+        }
+        self.write_levels(texture, internal_format);
+        Ok(())
+    }
+
+    fn write_levels(&self, texture: GLuint, internal_format: GLenum) {
+        for (level, &(offset, size)) in self.levels.iter().enumerate() {
+            let (width, height) = level_size(self.width, self.height, level);
+            unsafe {
+                gl::CompressedTextureSubImage2D(
+                    texture,
+                    level as i32,
+                    0,
+                    0,
+                    width as i32,
+                    height as i32,
+                    internal_format,
+                    size as i32,
+                    self.data[offset..].as_ptr() as *const _,
+                );
+            }
+        }
+    }
+
+    /// Checks that every level fits in the file and has the size its blocks need
+    fn validate(&self) -> Result<()> {
+        if self.width == 0 || self.height == 0 {
+            return Err("Empty image".into());
+        }
+        if self.levels.is_empty() {
+            return Err("No mip levels".into());
+        }
+        for (level, &(offset, size)) in self.levels.iter().enumerate() {
+            let (width, height) = level_size(self.width, self.height, level);
+            let blocks = ((width + 3) / 4) as usize * ((height + 3) / 4) as usize;
+            if size != blocks * self.format.block_size() {
+                return Err(format!("Mip level {} has the wrong size", level).into());
+            }
+            if offset
+                .checked_add(size)
+                .map_or(true, |end| end > self.data.len())
+            {
+                return Err(format!("Mip level {} is cut off", level).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+fn level_size(width: u32, height: u32, level: usize) -> (u32, u32) {
+    ((width >> level).max(1), (height >> level).max(1))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// Only 2D textures without supercompression, see the KTX 2.0 spec
+fn parse_ktx2(data: Vec<u8>) -> Result<CompressedImage> {
+    if data.len() < KTX2_HEADER_SIZE {
+        return Err("Truncated KTX2 header".into());
+    }
+    let vk_format = read_u32(&data, 12);
+    let width = read_u32(&data, 20);
+    let height = read_u32(&data, 24);
+    let depth = read_u32(&data, 28);
+    let layers = read_u32(&data, 32);
+    let faces = read_u32(&data, 36);
+    // 0 means the loader should generate them, which compressed textures can't
+    let level_count = read_u32(&data, 40).max(1) as usize;
+    let supercompression = read_u32(&data, 44);
+
+    // VkFormat values, both the UNORM and the sRGB ones
+    let format = match vk_format {
+        131..=134 => BlockFormat::Bc1,
+        141 => BlockFormat::Bc5,
+        145 | 146 => BlockFormat::Bc7,
+        _ => return Err(format!("Unsupported KTX2 format {}", vk_format).into()),
+    };
+    if depth > 1 || layers > 1 || faces != 1 {
+        return Err("Only 2D KTX2 textures are supported".into());
+    }
+    if supercompression != 0 {
+        return Err("Supercompressed KTX2 files aren't supported".into());
+    }
+
+    let index_end = KTX2_HEADER_SIZE + level_count * 24;
+    if data.len() < index_end {
+        return Err("Truncated KTX2 level index".into());
+    }
+    let levels = (0..level_count)
+        .map(|level| {
+            let entry = KTX2_HEADER_SIZE + level * 24;
+            let offset = read_u64(&data, entry) as usize;
+            let size = read_u64(&data, entry + 8) as usize;
+            (offset, size)
+        })
+        .collect();
+    let image = CompressedImage {
+        format,
+        width,
+        height,
+        data,
+        levels,
+    };
+    image.validate()?;
+    Ok(image)
+}
+
+/// BC1 and BC5 with the legacy FourCC codes, and all three with the DX10 header
+fn parse_dds(data: Vec<u8>) -> Result<CompressedImage> {
+    if data.len() < DDS_HEADER_SIZE {
+        return Err("Truncated DDS header".into());
+    }
+    let height = read_u32(&data, 12);
+    let width = read_u32(&data, 16);
+    let level_count = read_u32(&data, 28).max(1) as usize;
+    let four_cc = &data[84..88];
+
+    let (format, mut offset) = if four_cc == b"DX10" {
+        if data.len() < DDS_HEADER_SIZE + DDS_DX10_HEADER_SIZE {
+            return Err("Truncated DDS DX10 header".into());
+        }
+        let dxgi_format = read_u32(&data, DDS_HEADER_SIZE);
+        let array_size = read_u32(&data, DDS_HEADER_SIZE + 12);
+        if array_size > 1 {
+            return Err("DDS texture arrays aren't supported".into());
+        }
+        // DXGI_FORMAT values, both the UNORM and the sRGB ones
+        let format = match dxgi_format {
+            71 | 72 => BlockFormat::Bc1,
+            83 => BlockFormat::Bc5,
+            98 | 99 => BlockFormat::Bc7,
+            _ => return Err(format!("Unsupported DXGI format {}", dxgi_format).into()),
+        };
+        (format, DDS_HEADER_SIZE + DDS_DX10_HEADER_SIZE)
+    } else {
+        let format = match four_cc {
+            b"DXT1" => BlockFormat::Bc1,
+            b"ATI2" | b"BC5U" => BlockFormat::Bc5,
+            _ => {
+                let code = String::from_utf8_lossy(four_cc);
+                return Err(format!("Unsupported DDS format {:?}", code).into());
+            }
+        };
+        (format, DDS_HEADER_SIZE)
+    };
+
+    // The levels follow each other, the largest first
+    let mut levels = Vec::with_capacity(level_count);
+    for level in 0..level_count {
+        let (level_width, level_height) = level_size(width, height, level);
+        let blocks = ((level_width + 3) / 4) as usize * ((level_height + 3) / 4) as usize;
+        let size = blocks * format.block_size();
+        levels.push((offset, size));
+        offset += size;
+    }
+    let image = CompressedImage {
+        format,
+        width,
+        height,
+        data,
+        levels,
+    };
+    image.validate()?;
+    Ok(image)
+}
+
+/// How a float image is stored on the GPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HdrFormat {
+    /// Half floats, 6 bytes per texel
+    Rgb16F,
+    /// Shared exponent, 4 bytes per texel but nothing negative. Not renderable, so
+    /// there are no mipmaps.
+    Rgb9E5,
+}
+
+/// Linear colours which go above 1, e.g. skies and light sources
+pub struct HdrImage {
+    pub width: u32,
+    pub height: u32,
+    /// Rows from the top
+    pub pixels: Vec<[f32; 3]>,
+}
+
+const EXR_MAGIC: [u8; 4] = [0x76, 0x2F, 0x31, 0x01];
+// Version field flags
+const EXR_TILED: u32 = 0x200;
+const EXR_DEEP_OR_MULTIPART: u32 = 0x1800;
+
+/// Whether the file should be loaded with `HdrImage::load`
+pub fn is_hdr(path: &Path) -> bool {
+    let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+    matches!(extension.as_deref(), Some("hdr") | Some("exr"))
+}
+
+impl HdrImage {
+    /// Radiance .hdr, or OpenEXR scanlines without compression
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = fs::read(path)?;
+        let image = if data.starts_with(&EXR_MAGIC) {
+            parse_exr(&data)
+        } else {
+            decode_radiance(&data)
+        };
+        image.map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+
+    pub fn upload(&self, format: HdrFormat) -> GLuint {
+        let (internal_format, levels) = match format {
+            HdrFormat::Rgb16F => (
+                gl::RGB16F,
+                calculate_mip_levels(self.width as usize, self.height as usize),
+            ),
+            HdrFormat::Rgb9E5 => (gl::RGB9_E5, 1),
+        };
+        let mut texture: GLuint = 0;
+        unsafe {
+            gl::CreateTextures(gl::TEXTURE_2D, 1, &mut texture);
+            gl::TextureStorage2D(
+                texture,
+                levels,
+                internal_format,
+                self.width as i32,
+                self.height as i32,
+            );
+            // The driver converts the floats to the storage format
+            gl::TextureSubImage2D(
+                texture,
+                0,
+                0,
+                0,
+                self.width as i32,
+                self.height as i32,
+                gl::RGB,
+                gl::FLOAT,
+                self.pixels.as_ptr() as *const _,
+            );
+            if levels > 1 {
+                gl::GenerateTextureMipmap(texture);
+            }
+        }
+        texture
+    }
+}
+
+fn decode_radiance(data: &[u8]) -> Result<HdrImage> {
+    let decoder = image::codecs::hdr::HdrDecoder::new(data)?;
+    let metadata = decoder.metadata();
+    let pixels = decoder
+        .read_image_hdr()?
+        .into_iter()
+        .map(|pixel| pixel.0)
+        .collect();
+    Ok(HdrImage {
+        width: metadata.width,
+        height: metadata.height,
+        pixels,
+    })
+}
+
+/// Reads the header fields of an EXR file in order
+struct ExrReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> ExrReader<'a> {
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8]> {
+        let end = self.offset.saturating_add(count);
+        let bytes = self
+            .data
+            .get(self.offset..end)
+            .ok_or("Truncated EXR file")?;
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn i32(&mut self) -> Result<i32> {
+        Ok(i32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    /// Null-terminated, empty at the end of a list
+    fn name(&mut self) -> Result<&'a str> {
+        let rest = &self.data[self.offset.min(self.data.len())..];
+        let length = rest
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or("Truncated EXR file")?;
+        let name = std::str::from_utf8(&rest[..length]).map_err(|_| "Bad EXR attribute name")?;
+        self.offset += length + 1;
+        Ok(name)
+    }
+}
+
+/// Channel pixel types in EXR files
+const EXR_UINT: i32 = 0;
+const EXR_HALF: i32 = 1;
+const EXR_FLOAT: i32 = 2;
+
+fn exr_type_size(pixel_type: i32) -> usize {
+    if pixel_type == EXR_HALF {
+        2
+    } else {
+        4
+    }
+}
+
+/// Single-part scanline files with R, G and B channels, see "The OpenEXR File Layout".
+/// Compressed files should be re-saved without compression or as .hdr.
+fn parse_exr(data: &[u8]) -> Result<HdrImage> {
+    let mut reader = ExrReader { data, offset: 4 };
+    let version = reader.i32()? as u32;
+    if version & (EXR_TILED | EXR_DEEP_OR_MULTIPART) != 0 {
+        return Err("Only scanline EXR files are supported".into());
+    }
+
+    // Name and pixel type, sorted by name like in the file
+    let mut channels: Vec<(&str, i32)> = vec![];
+    let mut compression = None;
+    let mut data_window = None;
+    loop {
+        let name = reader.name()?;
+        if name.is_empty() {
+            break;
+        }
+        let _kind = reader.name()?;
+        let size = reader.i32()? as usize;
+        let mut value = ExrReader {
+            data: reader.bytes(size)?,
+            offset: 0,
+        };
+        match name {
+            "channels" => loop {
+                let channel = value.name()?;
+                if channel.is_empty() {
+                    break;
+                }
+                let pixel_type = value.i32()?;
+                // pLinear and reserved
+                value.bytes(4)?;
+                let (x_sampling, y_sampling) = (value.i32()?, value.i32()?);
+                if pixel_type > EXR_FLOAT || x_sampling != 1 || y_sampling != 1 {
+                    return Err(format!("Unsupported EXR channel {}", channel).into());
+                }
+                channels.push((channel, pixel_type));
+            },
+            "compression" => compression = Some(value.bytes(1)?[0]),
+            "dataWindow" => {
+                let (x_min, y_min) = (value.i32()?, value.i32()?);
+                let (x_max, y_max) = (value.i32()?, value.i32()?);
+                data_window = Some((x_min, y_min, x_max, y_max));
+            }
+            _ => {}
+        }
+    }
+
+    if compression != Some(0) {
+        return Err("Compressed EXR files aren't supported".into());
+    }
+    let (x_min, y_min, x_max, y_max) = data_window.ok_or("EXR file without a data window")?;
+    if x_max < x_min || y_max < y_min {
+        return Err("Empty image".into());
+    }
+    let width = (x_max - x_min + 1) as usize;
+    let height = (y_max - y_min + 1) as usize;
+    let find = |wanted: &str| {
+        channels
+            .iter()
+            .position(|&(name, _)| name == wanted)
+            .ok_or_else(|| format!("EXR file without a {} channel", wanted))
+    };
+    let rgb = [find("R")?, find("G")?, find("B")?];
+    // Where each channel starts within a scanline
+    let mut channel_offsets = Vec::with_capacity(channels.len());
+    let mut line_size = 0;
+    for &(_, pixel_type) in &channels {
+        channel_offsets.push(line_size);
+        line_size += width * exr_type_size(pixel_type);
+    }
+
+    // Uncompressed blocks hold one scanline each
+    let line_offsets = (0..height)
+        .map(|_| reader.u64())
+        .collect::<Result<Vec<_>>>()?;
+    let mut pixels = vec![[0.0; 3]; width * height];
+    for offset in line_offsets {
+        let mut block = ExrReader {
+            data,
+            offset: offset as usize,
+        };
+        let y = block.i32()?;
+        let size = block.i32()? as usize;
+        let row = y.checked_sub(y_min).filter(|&row| (row as usize) < height);
+        let row = row.ok_or("EXR scanline outside the data window")? as usize;
+        if size != line_size {
+            return Err("EXR scanline has the wrong size".into());
+        }
+        let line = block.bytes(size)?;
+        for (component, &channel) in rgb.iter().enumerate() {
+            let pixel_type = channels[channel].1;
+            let start = channel_offsets[channel];
+            let type_size = exr_type_size(pixel_type);
+            for x in 0..width {
+                let bytes = &line[start + x * type_size..start + (x + 1) * type_size];
+                pixels[row * width + x][component] = match pixel_type {
+                    EXR_HALF => half_to_f32(u16::from_le_bytes(bytes.try_into().unwrap())),
+                    EXR_UINT => u32::from_le_bytes(bytes.try_into().unwrap()) as f32,
+                    _ => f32::from_le_bytes(bytes.try_into().unwrap()),
+                };
+            }
+        }
+    }
+    Ok(HdrImage {
+        width: width as u32,
+        height: height as u32,
+        pixels,
+    })
+}
+
+fn half_to_f32(bits: u16) -> f32 {
+    let exponent = ((bits >> 10) & 0x1F) as u32;
+    let mantissa = (bits & 0x3FF) as u32;
+    let magnitude = match exponent {
+        // Subnormal
+        0 => mantissa as f32 * 2f32.powi(-24),
+        0x1F if mantissa == 0 => f32::INFINITY,
+        0x1F => f32::NAN,
+        // Rebias the exponent from 15 to 127
+        _ => f32::from_bits(((exponent + 112) << 23) | (mantissa << 13)),
+    };
+    if bits & 0x8000 != 0 {
+        -magnitude
     } else {
-        MAX_ANISOTROPY
-    }
-}
-
-pub fn unit_to_gl_const(unit: i32) -> GLenum {
-    match unit {
-        0 => gl::TEXTURE0,
-        1 => gl::TEXTURE1,
-        2 => gl::TEXTURE2,
-        3 => gl::TEXTURE3,
-        4 => gl::TEXTURE4,
-        5 => gl::TEXTURE5,
-        6 => gl::TEXTURE6,
-        7 => gl::TEXTURE7,
-        8 => gl::TEXTURE8,
-        9 => gl::TEXTURE9,
-        10 => gl::TEXTURE10,
-        11 => gl::TEXTURE11,
-        12 => gl::TEXTURE12,
-        13 => gl::TEXTURE13,
-        14 => gl::TEXTURE14,
-        15 => gl::TEXTURE15,
-        _ => panic!("Unsupported texture unit"),
+        magnitude
     }
 }
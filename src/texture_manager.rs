@@ -0,0 +1,251 @@
+//! Textures loaded from files, shared by everything that asks for the same file.
+//! `load` hands out reference-counted handles, and a texture is deleted as soon as
+//! the last handle to it is dropped, so loading a file again while it's in use costs
+//! nothing and switching away from it frees it. `reload` puts the changed file into
+//! the textures already loaded from it.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::{Rc, Weak};
+
+use gl::types::*;
+
+use crate::opengl::bindings;
+use crate::texture::{self, calculate_mip_levels, create_texture, CompressedImage};
+use crate::Result;
+
+/// How the file is turned into a texture. The same file loaded as different kinds
+/// gives different textures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TextureKind {
+    /// sRGB colors, e.g. albedo
+    Color,
+    /// Linear values, e.g. normal maps
+    Data,
+    /// 16-bit grayscale with a transparent border, for terrain brushes
+    Brush,
+}
+
+#[derive(Debug)]
+struct LoadedTexture {
+    id: GLuint,
+    width: u32,
+    height: u32,
+}
+
+impl Drop for LoadedTexture {
+    fn drop(&mut self) {
+        bindings::delete_textures(&[self.id]);
+    }
+}
+
+/// Keeps the texture alive, cloning it is cheap
+#[derive(Debug, Clone)]
+pub struct TextureHandle(Rc<LoadedTexture>);
+
+impl TextureHandle {
+    pub fn id(&self) -> GLuint {
+        self.0.id
+    }
+
+    pub fn width(&self) -> u32 {
+        self.0.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.0.height
+    }
+}
+
+#[derive(Default)]
+struct TextureManager {
+    /// Dead entries are left for `load` to clean up
+    textures: HashMap<(PathBuf, TextureKind), Weak<LoadedTexture>>,
+}
+
+thread_local! {
+    // Textures are only created on the main thread
+    static MANAGER: RefCell<TextureManager> = RefCell::new(TextureManager::default());
+}
+
+/// The texture of the file, loaded unless something else is still using it
+pub fn load(path: impl AsRef<Path>, kind: TextureKind) -> Result<TextureHandle> {
+    let path = path.as_ref();
+    let key = (canonical(path), kind);
+    let cached = MANAGER.with(|manager| {
+        let mut manager = manager.borrow_mut();
+        manager
+            .textures
+            .retain(|_, texture| texture.strong_count() > 0);
+        manager.textures.get(&key).and_then(Weak::upgrade)
+    });
+    if let Some(texture) = cached {
+        return Ok(TextureHandle(texture));
+    }
+
+    let texture = Rc::new(upload(path, kind)?);
+    MANAGER.with(|manager| {
+        let mut manager = manager.borrow_mut();
+        manager.textures.insert(key, Rc::downgrade(&texture));
+    });
+    Ok(TextureHandle(texture))
+}
+
+/// Number of textures alive, for the stats
+pub fn loaded_count() -> usize {
+    MANAGER.with(|manager| {
+        let manager = manager.borrow();
+        manager
+            .textures
+            .values()
+            .filter(|texture| texture.strong_count() > 0)
+            .count()
+    })
+}
+
+/// Uploads the file again into the textures loaded from it, as whichever kinds.
+/// Returns whether there were any. The new image has to be the same size, since the
+/// textures are immutable and their names are held elsewhere, e.g. in materials.
+pub fn reload(path: impl AsRef<Path>) -> Result<bool> {
+    let path = path.as_ref();
+    let path_key = canonical(path);
+    let loaded: Vec<(TextureKind, Rc<LoadedTexture>)> = MANAGER.with(|manager| {
+        let manager = manager.borrow();
+        manager
+            .textures
+            .iter()
+            .filter(|((loaded_path, _), _)| *loaded_path == path_key)
+            .filter_map(|(&(_, kind), texture)| Some((kind, texture.upgrade()?)))
+            .collect()
+    });
+    for (kind, texture) in &loaded {
+        let image = decode(path, *kind)?;
+        if image.dimensions() != (texture.width, texture.height) {
+            return Err(format!("{} changed size, restart to see it", path.display()).into());
+        }
+        image
+            .upload_into(texture.id, *kind)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    Ok(!loaded.is_empty())
+}
+
+/// So that different spellings of the same path share the texture
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_owned())
+}
+
+/// A file ready to be uploaded as the kind
+enum DecodedImage {
+    Compressed(CompressedImage),
+    Rgba(image::RgbaImage),
+    Luma16(image::ImageBuffer<image::Luma<u16>, Vec<u16>>),
+}
+
+impl DecodedImage {
+    fn dimensions(&self) -> (u32, u32) {
+        match self {
+            DecodedImage::Compressed(image) => (image.width, image.height),
+            DecodedImage::Rgba(image) => image.dimensions(),
+            DecodedImage::Luma16(image) => image.dimensions(),
+        }
+    }
+
+    fn upload(&self, kind: TextureKind) -> GLuint {
+        let (width, height) = self.dimensions();
+        let srgb = kind == TextureKind::Color;
+        match self {
+            DecodedImage::Compressed(image) => image.upload(srgb),
+            DecodedImage::Rgba(image) => create_texture(width, height, gl::RGBA, image, srgb),
+            DecodedImage::Luma16(image) => create_brush_texture(width, height, image),
+        }
+    }
+
+    /// Into a texture made by `upload` from an image of the same size
+    fn upload_into(&self, texture: GLuint, kind: TextureKind) -> Result<()> {
+        let (width, height) = self.dimensions();
+        let (format, kind, pixels) = match self {
+            DecodedImage::Compressed(image) => {
+                return image.upload_into(texture, kind == TextureKind::Color)
+            }
+            DecodedImage::Rgba(image) => (gl::RGBA, gl::UNSIGNED_BYTE, image.as_ptr()),
+            DecodedImage::Luma16(image) => {
+                (gl::RED, gl::UNSIGNED_SHORT, image.as_ptr() as *const u8)
+            }
+        };
+        unsafe {
+            gl::TextureSubImage2D(
+                texture,
+                0,
+                0,
+                0,
+                width as i32,
+                height as i32,
+                format,
+                kind,
+                pixels as *const _,
+            );
+            gl::GenerateTextureMipmap(texture);
+        }
+        Ok(())
+    }
+}
+
+fn decode(path: &Path, kind: TextureKind) -> Result<DecodedImage> {
+    if kind != TextureKind::Brush && texture::is_compressed(path) {
+        return Ok(DecodedImage::Compressed(CompressedImage::load(path)?));
+    }
+    let image = image::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(match kind {
+        TextureKind::Color | TextureKind::Data => DecodedImage::Rgba(image.into_rgba8()),
+        TextureKind::Brush => DecodedImage::Luma16(image.into_luma16()),
+    })
+}
+
+fn upload(path: &Path, kind: TextureKind) -> Result<LoadedTexture> {
+    let image = decode(path, kind)?;
+    let (width, height) = image.dimensions();
+    Ok(LoadedTexture {
+        id: image.upload(kind),
+        width,
+        height,
+    })
+}
+
+/// Mipmapped, and zero outside so that the brush fades out at its edges
+fn create_brush_texture(width: u32, height: u32, pixels: &[u16]) -> GLuint {
+    let mut texture: GLuint = 0;
+    unsafe {
+        gl::CreateTextures(gl::TEXTURE_2D, 1, &mut texture);
+        gl::TextureParameteri(texture, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_BORDER as GLint);
+        gl::TextureParameteri(texture, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_BORDER as GLint);
+        gl::TextureParameteri(
+            texture,
+            gl::TEXTURE_MIN_FILTER,
+            gl::LINEAR_MIPMAP_LINEAR as GLint,
+        );
+        gl::TextureParameteri(texture, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+        gl::TextureStorage2D(
+            texture,
+            calculate_mip_levels(width as usize, height as usize),
+            gl::R16,
+            width as i32,
+            height as i32,
+        );
+        gl::TextureSubImage2D(
+            texture,
+            0,
+            0,
+            0,
+            width as i32,
+            height as i32,
+            gl::RED,
+            gl::UNSIGNED_SHORT,
+            pixels.as_ptr() as *const _,
+        );
+        gl::GenerateTextureMipmap(texture);
+    }
+    texture
+}
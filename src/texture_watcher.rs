@@ -0,0 +1,80 @@
+//! Watches the textures directory so that images edited while the editor runs are
+//! reloaded, see `Game::reload_changed_textures`. Unlike the shaders the textures are
+//! always read from disk, so release builds watch them too.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant};
+
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+const TEXTURES_DIR: &str = "textures";
+/// Image editors often write a file in several steps, so it's only read once they stop
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+
+pub struct TextureWatcher {
+    /// Stops watching when dropped
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    /// Changed files and when they last changed
+    pending: HashMap<PathBuf, Instant>,
+}
+
+impl TextureWatcher {
+    /// None if the directory can't be watched, after printing why
+    pub fn new() -> Option<Self> {
+        let (sender, events) = mpsc::channel();
+        // Canonical so that the paths in the events can be compared with `is_same_file`
+        let result = fs::canonicalize(TEXTURES_DIR)
+            .map_err(notify::Error::io)
+            .and_then(|dir| {
+                let mut watcher = notify::recommended_watcher(sender)?;
+                watcher.watch(&dir, RecursiveMode::Recursive)?;
+                Ok(watcher)
+            });
+        match result {
+            Ok(watcher) => Some(TextureWatcher {
+                _watcher: watcher,
+                events,
+                pending: HashMap::new(),
+            }),
+            Err(err) => {
+                log!("Failed to watch {}: {}", TEXTURES_DIR, err);
+                None
+            }
+        }
+    }
+
+    /// The files which changed and have been left alone for a moment since
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        let now = Instant::now();
+        for event in self.events.try_iter() {
+            match event {
+                Ok(event) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+                    for path in event.paths {
+                        self.pending.insert(path, now);
+                    }
+                }
+                Ok(_) => {}
+                Err(err) => log!("Texture watcher error: {}", err),
+            }
+        }
+
+        let mut settled = vec![];
+        self.pending.retain(|path, changed_at| {
+            if now.duration_since(*changed_at) < SETTLE_TIME {
+                return true;
+            }
+            settled.push(path.clone());
+            false
+        });
+        settled
+    }
+}
+
+/// Whether the path, as it was loaded, is the file from `TextureWatcher::poll`
+pub fn is_same_file(path: &str, changed: &Path) -> bool {
+    fs::canonicalize(path).map_or(false, |path| path == changed)
+}
@@ -0,0 +1,31 @@
+//! The simulation advances in fixed steps, so that the bricks fall, the brush builds up
+//! and the sun moves the same at any frame rate. The frames take whatever they take and
+//! the steps catch up with them. What's left over says how far the frame is between the
+//! last two steps, for drawing the moving things in between, see `FixedTimestep::alpha`.
+
+/// Seconds per step
+pub const STEP: f32 = 1.0 / 60.0;
+/// After a long frame the rest is dropped, the simulation slows down instead of taking
+/// longer and longer to catch up
+const MAX_STEPS: u32 = 4;
+
+#[derive(Default)]
+pub struct FixedTimestep {
+    /// Time that hasn't been simulated yet, less than a step after `advance`
+    lag: f32,
+}
+
+impl FixedTimestep {
+    /// How many steps to simulate for the frame
+    pub fn advance(&mut self, delta_time: f32) -> u32 {
+        self.lag = (self.lag + delta_time).min(MAX_STEPS as f32 * STEP);
+        let steps = (self.lag / STEP) as u32;
+        self.lag -= steps as f32 * STEP;
+        steps
+    }
+
+    /// How far the frame is past the last step, [0, 1) of the way to the next one
+    pub fn alpha(&self) -> f32 {
+        (self.lag / STEP).clamp(0.0, 1.0)
+    }
+}
@@ -14,3 +14,16 @@ pub fn vec2_infinity() -> Vec2 {
 pub fn size_of_slice<T>(slice: &[T]) -> usize {
     std::mem::size_of::<T>() * slice.len()
 }
+
+/// Deterministic random numbers, so that the same seed gives the same result
+pub struct XorShift(pub u64);
+
+impl XorShift {
+    /// In [0, 1)
+    pub fn next_f32(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
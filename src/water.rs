@@ -0,0 +1,51 @@
+use glam::Vec3;
+
+/// An infinite water plane at `level`, shaded in the post-processing pass
+/// from the depth and color of the scene behind it
+#[derive(Debug, Clone, Copy)]
+pub struct Water {
+    pub enabled: bool,
+    pub level: f32,
+    /// Color of deep water
+    pub color: Vec3,
+    /// Depth in world units at which the bottom stops being visible
+    pub clarity: f32,
+    pub wave_strength: f32,
+    pub ssr: ScreenSpaceReflections,
+}
+
+impl Default for Water {
+    fn default() -> Self {
+        Water {
+            // Off by default, a fresh flat terrain would be all underwater
+            enabled: false,
+            level: 20.0,
+            color: Vec3::new(0.02, 0.12, 0.16),
+            clarity: 15.0,
+            wave_strength: 0.04,
+            ssr: ScreenSpaceReflections::default(),
+        }
+    }
+}
+
+/// Reflections traced through the depth buffer, with the skybox as the fallback
+#[derive(Debug, Clone, Copy)]
+pub struct ScreenSpaceReflections {
+    pub enabled: bool,
+    pub max_steps: i32,
+    /// Length of the first step in world units, subsequent steps get longer
+    pub step_size: f32,
+    /// How far behind a surface the ray can be and still count as a hit
+    pub thickness: f32,
+}
+
+impl Default for ScreenSpaceReflections {
+    fn default() -> Self {
+        ScreenSpaceReflections {
+            enabled: true,
+            max_steps: 64,
+            step_size: 1.0,
+            thickness: 4.0,
+        }
+    }
+}